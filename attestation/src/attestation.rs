@@ -18,6 +18,8 @@
 //! This module provide attestation public APIs in server side.
 
 use crate::key;
+use crate::key::CertKeyUsage;
+use crate::payload::PayloadProtection;
 use crate::AttestationConfig;
 use crate::AttestedTlsConfig;
 use crate::EndorsedAttestationReport;
@@ -38,6 +40,8 @@ const CERT_SUBJECT: &str = "CN=Teaclave";
 pub struct RemoteAttestation {
     attestation_config: Arc<AttestationConfig>,
     attested_tls_config: Option<Arc<RwLock<AttestedTlsConfig>>>,
+    key_usage: CertKeyUsage,
+    payload_protection: PayloadProtection,
 }
 
 impl RemoteAttestation {
@@ -46,22 +50,49 @@ impl RemoteAttestation {
         Self {
             attestation_config,
             attested_tls_config: None,
+            key_usage: CertKeyUsage::default(),
+            payload_protection: PayloadProtection::default(),
         }
     }
 
+    /// Include keyUsage/extendedKeyUsage extensions in the attested cert.
+    pub fn with_key_usage(mut self, key_usage: CertKeyUsage) -> Self {
+        self.key_usage = key_usage;
+        self
+    }
+
+    /// Seal the attestation report in the attested cert to a verifier instead of embedding it in
+    /// plaintext.
+    pub fn with_payload_protection(mut self, payload_protection: PayloadProtection) -> Self {
+        self.payload_protection = payload_protection;
+        self
+    }
+
     /// Generate a endorsed attestation report.
     pub fn generate_and_endorse(self) -> Result<Self> {
         let attested_tls_config = Arc::new(RwLock::new(AttestedTlsConfig::new(
             &self.attestation_config,
+            &self.key_usage,
+            &self.payload_protection,
         )?));
         let attestation_config_ref = self.attestation_config.clone();
         let attested_tls_config_ref = attested_tls_config.clone();
+        let key_usage = self.key_usage.clone();
+        let payload_protection = self.payload_protection.clone();
         thread::spawn(move || {
-            AttestationFreshnessKeeper::new(attestation_config_ref, attested_tls_config_ref).start()
+            AttestationFreshnessKeeper::new(
+                attestation_config_ref,
+                attested_tls_config_ref,
+                key_usage,
+                payload_protection,
+            )
+            .start()
         });
         Ok(Self {
             attestation_config: self.attestation_config,
             attested_tls_config: Some(attested_tls_config),
+            key_usage: self.key_usage,
+            payload_protection: self.payload_protection,
         })
     }
 
@@ -72,7 +103,11 @@ impl RemoteAttestation {
 }
 
 impl AttestedTlsConfig {
-    fn new(attestation_config: &AttestationConfig) -> Result<AttestedTlsConfig> {
+    fn new(
+        attestation_config: &AttestationConfig,
+        key_usage: &CertKeyUsage,
+        payload_protection: &PayloadProtection,
+    ) -> Result<AttestedTlsConfig> {
         let key_pair = key::NistP256KeyPair::new()?;
         let report = match attestation_config {
             AttestationConfig::NoAttestation => EndorsedAttestationReport::default(),
@@ -82,7 +117,13 @@ impl AttestedTlsConfig {
         };
 
         let extension = serde_json::to_vec(&report)?;
-        let cert = key_pair.create_cert_with_extension(CERT_ISSUER, CERT_SUBJECT, &extension);
+        let cert = key_pair.create_cert_with_extension(
+            CERT_ISSUER,
+            CERT_SUBJECT,
+            &extension,
+            key_usage,
+            payload_protection,
+        )?;
         let private_key = key_pair.private_key_into_der();
         let time = SystemTime::now();
         let validity = Duration::from_secs(ATTESTATION_VALIDITY_SECS);
@@ -104,16 +145,22 @@ impl AttestedTlsConfig {
 struct AttestationFreshnessKeeper {
     attestation_config: Arc<AttestationConfig>,
     attested_tls_config: Arc<RwLock<AttestedTlsConfig>>,
+    key_usage: CertKeyUsage,
+    payload_protection: PayloadProtection,
 }
 
 impl AttestationFreshnessKeeper {
     pub(crate) fn new(
         attestation_config: Arc<AttestationConfig>,
         attested_tls_config: Arc<RwLock<AttestedTlsConfig>>,
+        key_usage: CertKeyUsage,
+        payload_protection: PayloadProtection,
     ) -> Self {
         Self {
             attestation_config,
             attested_tls_config,
+            key_usage,
+            payload_protection,
         }
     }
 
@@ -134,7 +181,11 @@ impl AttestationFreshnessKeeper {
     /// attested TLS config.
     fn refresh(&self) -> Result<()> {
         debug!("begin refresh");
-        let updated_attested_tls_config = AttestedTlsConfig::new(&self.attestation_config)?;
+        let updated_attested_tls_config = AttestedTlsConfig::new(
+            &self.attestation_config,
+            &self.key_usage,
+            &self.payload_protection,
+        )?;
         let lock = self.attested_tls_config.clone();
         let mut config = lock
             .write()
@@ -108,6 +108,8 @@ mod no_instance {
     pub(crate) struct UtcTime;
     pub(crate) struct BitVec;
     pub(crate) struct Bytes;
+    pub(crate) struct Extension;
+    pub(crate) struct Extensions;
     pub(crate) struct Tagged<T: Asn1Tag, S: Asn1Ty> {
         t: PhantomData<T>,
         s: PhantomData<S>,
@@ -328,6 +330,54 @@ impl Asn1Ty for Bytes {
     }
 }
 
+impl Asn1Ty for Extension {
+    /// (extnID, critical, extnValue)
+    type ValueTy = (yasna::models::ObjectIdentifier, bool, Vec<u8>);
+    const TAG: yasna::Tag = yasna::tags::TAG_SEQUENCE;
+
+    fn dump(writer: Writer<'_>, value: Self::ValueTy) {
+        writer.write_sequence(|writer| {
+            writer.next().write_oid(&value.0);
+            // DER leaves out the critical flag if it has its default value FALSE.
+            if value.1 {
+                writer.next().write_bool(true);
+            }
+            writer.next().write_bytes(value.2.as_slice());
+        });
+    }
+
+    fn load<'a>(reader: Reader<'a, '_>) -> ASN1Result<Self::ValueTy> {
+        reader.read_sequence(|reader| {
+            let oid = reader.next().read_oid()?;
+            let critical = reader.read_default(false, |reader| reader.read_bool())?;
+            let value = reader.next().read_bytes()?;
+            Ok((oid, critical, value))
+        })
+    }
+}
+
+impl Asn1Ty for Extensions {
+    type ValueTy = Vec<<Extension as Asn1Ty>::ValueTy>;
+    const TAG: yasna::Tag = yasna::tags::TAG_SEQUENCE;
+
+    fn dump(writer: Writer<'_>, value: Self::ValueTy) {
+        writer.write_sequence(|writer| {
+            for extension in value {
+                Extension::dump(writer.next(), extension);
+            }
+        });
+    }
+
+    fn load<'a>(reader: Reader<'a, '_>) -> ASN1Result<Self::ValueTy> {
+        let mut extensions = Vec::new();
+        reader.read_sequence_of(|reader| {
+            extensions.push(Extension::load(reader)?);
+            Ok(())
+        })?;
+        Ok(extensions)
+    }
+}
+
 macro_rules! cons {
     () => { Nil };
     ($t: ty) => { Cons<$t, Nil> };
@@ -369,7 +419,7 @@ pub(crate) type Issuer = asn1_seq_ty!(asn1_set_ty!(asn1_seq_ty!(Oid, Utf8Str)));
 pub(crate) type Subject = Issuer;
 pub(crate) type PubKeyAlgo = asn1_seq_ty!(Oid, Oid);
 pub(crate) type PubKey = asn1_seq_ty!(PubKeyAlgo, BitVec);
-pub(crate) type SgxRaCertExt = Tagged<CtxT3, asn1_seq_ty!(asn1_seq_ty!(Oid, Bytes))>;
+pub(crate) type SgxRaCertExt = Tagged<CtxT3, Extensions>;
 pub(crate) type TbsCert = asn1_seq_ty!(
     Version,
     Serial,
@@ -19,12 +19,101 @@
 //! can export private key to a DER format or create a certificate with
 //! extension for TLS-based remote attestation.
 
-use anyhow::Result;
+use crate::payload::PayloadProtection;
+use crate::public_key::{oid_string, EcCurve, EC_PUBLIC_KEY_OID};
+use crate::report::ATTESTATION_REPORT_OID;
+use anyhow::{Context, Result};
+use bit_vec::BitVec;
 use sgx_crypto::ecc::{EcKeyPair, EcPublicKey};
+use std::time::SystemTime;
+use yasna::models::ObjectIdentifier;
+use yasna::Tag;
 
 /// Validation days of cert for TLS connection.
 const CERT_VALID_DAYS: i64 = 90i64;
 
+/// Bits of the keyUsage extension (RFC 5280, section 4.2.1.3).
+pub const KEY_USAGE_DIGITAL_SIGNATURE: usize = 0;
+pub const KEY_USAGE_NON_REPUDIATION: usize = 1;
+pub const KEY_USAGE_KEY_ENCIPHERMENT: usize = 2;
+pub const KEY_USAGE_DATA_ENCIPHERMENT: usize = 3;
+pub const KEY_USAGE_KEY_AGREEMENT: usize = 4;
+pub const KEY_USAGE_KEY_CERT_SIGN: usize = 5;
+pub const KEY_USAGE_CRL_SIGN: usize = 6;
+pub const KEY_USAGE_ENCIPHER_ONLY: usize = 7;
+pub const KEY_USAGE_DECIPHER_ONLY: usize = 8;
+
+/// Purposes of the extendedKeyUsage extension (RFC 5280, section 4.2.1.12).
+pub const EXTENDED_KEY_USAGE_SERVER_AUTH: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 1];
+pub const EXTENDED_KEY_USAGE_CLIENT_AUTH: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 2];
+
+/// Key usage constraints of the attestation cert, for TLS stacks which reject certs without
+/// them. An extension without any bits or purposes is left out, so the default cert has none.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CertKeyUsage {
+    key_usage: Vec<usize>,
+    extended_key_usage: Vec<Vec<u64>>,
+}
+
+impl CertKeyUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets one of the `KEY_USAGE_*` bits.
+    pub fn key_usage(mut self, bit: usize) -> Self {
+        self.key_usage.push(bit);
+        self
+    }
+
+    /// Adds a purpose such as `EXTENDED_KEY_USAGE_SERVER_AUTH`, given as OID arcs.
+    pub fn extended_key_usage(mut self, purpose: &[u64]) -> Self {
+        self.extended_key_usage.push(purpose.to_vec());
+        self
+    }
+
+    /// Value of the keyUsage extension: a BIT STRING without trailing zero bits.
+    fn key_usage_der(&self) -> Option<Vec<u8>> {
+        let len = self.key_usage.iter().max()? + 1;
+        let mut bits = BitVec::from_elem(len, false);
+        for &bit in &self.key_usage {
+            bits.set(bit, true);
+        }
+        Some(yasna::construct_der(|writer| writer.write_bitvec(&bits)))
+    }
+
+    /// Value of the extendedKeyUsage extension: a SEQUENCE OF purpose OIDs.
+    fn extended_key_usage_der(&self) -> Option<Vec<u8>> {
+        if self.extended_key_usage.is_empty() {
+            return None;
+        }
+        Some(yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                for purpose in &self.extended_key_usage {
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(purpose));
+                }
+            });
+        }))
+    }
+
+    /// Cert extensions as (extnID, critical, extnValue). keyUsage is marked critical as
+    /// recommended by RFC 5280.
+    fn extensions(&self) -> Vec<(ObjectIdentifier, bool, Vec<u8>)> {
+        let mut extensions = Vec::new();
+        if let Some(value) = self.key_usage_der() {
+            let key_usage_oid = ObjectIdentifier::from_slice(&[2, 5, 29, 15]);
+            extensions.push((key_usage_oid, true, value));
+        }
+        if let Some(value) = self.extended_key_usage_der() {
+            let extended_key_usage_oid = ObjectIdentifier::from_slice(&[2, 5, 29, 37]);
+            extensions.push((extended_key_usage_oid, false, value));
+        }
+        extensions
+    }
+}
+
 /// NistP256KeyPair stores a pair of ECDSA (private, public) key based on the
 /// NIST P-256 curve (a.k.a secp256r1).
 pub struct NistP256KeyPair {
@@ -43,10 +132,7 @@ impl NistP256KeyPair {
     }
 
     pub(crate) fn private_key_into_der(&self) -> Vec<u8> {
-        use bit_vec::BitVec;
         use yasna::construct_der;
-        use yasna::models::ObjectIdentifier;
-        use yasna::Tag;
 
         // Construct useful OIDs.
         let ec_public_key_oid = ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 2, 1]);
@@ -77,56 +163,273 @@ impl NistP256KeyPair {
         })
     }
 
+    /// into_pkcs12 bundles `cert_der` and the private key into a PKCS#12 file
+    /// protected by `password`, for TLS terminators which import keys only in
+    /// this format. See `pkcs12::build_pfx` for the algorithms.
+    pub fn into_pkcs12(&self, cert_der: &[u8], password: &str) -> Result<Vec<u8>> {
+        crate::pkcs12::build_pfx(cert_der, &self.private_key_into_der(), password)
+    }
+
+    /// into_pkcs12_with_mac is `into_pkcs12` with the digest of the MAC chosen by
+    /// `mac`, e.g. SHA-1 for older importers.
+    pub fn into_pkcs12_with_mac(
+        &self,
+        cert_der: &[u8],
+        password: &str,
+        mac: crate::pkcs12::PfxMac,
+    ) -> Result<Vec<u8>> {
+        crate::pkcs12::build_pfx_with_mac(cert_der, &self.private_key_into_der(), password, mac)
+    }
+
     /// create_cert_with_extension makes a self-signed x509-v3 cert with SGX
-    /// attestation report as extensions.
-    /// @reference [Internet X.509 Public Key Infrastructure Certificate and
-    /// Certificate Revocation List (CRL) Profile][1]
-    ///
-    /// [1]: https://tools.ietf.org/pdf/rfc5280.pdf
+    /// attestation report as extensions, followed by the extensions for
+    /// `key_usage`, if any. The report is embedded as `protection` says. See
+    /// `CertBuilder` for the other options of the cert.
     pub(crate) fn create_cert_with_extension(
         &self,
         issuer: &str,
         subject: &str,
         payload: &[u8],
-    ) -> Vec<u8> {
+        key_usage: &CertKeyUsage,
+        protection: &PayloadProtection,
+    ) -> Result<Vec<u8>> {
+        self.create_cert_with_extensions(
+            issuer,
+            subject,
+            &[(ATTESTATION_REPORT_OID, payload)],
+            key_usage,
+            protection,
+        )
+    }
+
+    /// Like `create_cert_with_extension`, with each of the (OID, payload) pairs of `payloads`
+    /// in an extension of its own, e.g. an EPID and a DCAP report during a migration.
+    pub(crate) fn create_cert_with_extensions(
+        &self,
+        issuer: &str,
+        subject: &str,
+        payloads: &[(&[u64], &[u8])],
+        key_usage: &CertKeyUsage,
+        protection: &PayloadProtection,
+    ) -> Result<Vec<u8>> {
+        let mut builder = CertBuilder::new(self)
+            .issuer(issuer)
+            .subject_dn(subject)
+            .key_usage(key_usage.clone())
+            .payload_protection(protection.clone());
+        for (oid, payload) in payloads {
+            builder = builder.attestation_extension(oid, payload);
+        }
+        builder.build()
+    }
+
+    fn public_key_into_bytes(&self) -> Vec<u8> {
+        // The first byte must be 4, which indicates the uncompressed encoding.
+        let mut pub_key_bytes: Vec<u8> = vec![4];
+        let public_key = self.pub_k().public_key();
+        pub_key_bytes.extend(public_key.gx.iter().rev());
+        pub_key_bytes.extend(public_key.gy.iter().rev());
+        pub_key_bytes
+    }
+
+    fn private_key_into_bytes(&self) -> Vec<u8> {
+        let mut prv_key_bytes: Vec<u8> = vec![];
+        let private_key = self.inner.private_key().private_key();
+        prv_key_bytes.extend(private_key.r.iter().rev());
+        prv_key_bytes
+    }
+}
+
+/// CertBuilder makes the self-signed attestation cert of a key pair. Options which are not
+/// set keep the defaults of the TLS cert: a 90 days validity from now, serial number 1, no
+/// subjectAltName or key usage, and a plaintext payload.
+/// @reference [Internet X.509 Public Key Infrastructure Certificate and
+/// Certificate Revocation List (CRL) Profile][1]
+///
+/// [1]: https://tools.ietf.org/pdf/rfc5280.pdf
+pub struct CertBuilder<'a> {
+    key_pair: &'a NistP256KeyPair,
+    issuer: String,
+    subject: String,
+    validity_days: i64,
+    san: Vec<String>,
+    serial: u8,
+    payloads: Vec<(ObjectIdentifier, Vec<u8>)>,
+    key_usage: CertKeyUsage,
+    protection: PayloadProtection,
+    issued_at: Option<SystemTime>,
+}
+
+impl<'a> CertBuilder<'a> {
+    pub fn new(key_pair: &'a NistP256KeyPair) -> Self {
+        Self {
+            key_pair,
+            issuer: String::new(),
+            subject: String::new(),
+            validity_days: CERT_VALID_DAYS,
+            san: Vec::new(),
+            serial: 1,
+            payloads: Vec::new(),
+            key_usage: CertKeyUsage::default(),
+            protection: PayloadProtection::Plaintext,
+            issued_at: None,
+        }
+    }
+
+    /// Common name of the issuer.
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = issuer.to_owned();
+        self
+    }
+
+    /// Subject name, written as the common name of the subject.
+    pub fn subject_dn(mut self, subject: &str) -> Self {
+        self.subject = subject.to_owned();
+        self
+    }
+
+    pub fn validity_days(mut self, days: i64) -> Self {
+        self.validity_days = days;
+        self
+    }
+
+    /// Adds a DNS name to the subjectAltName extension.
+    pub fn san(mut self, dns_name: &str) -> Self {
+        self.san.push(dns_name.to_owned());
+        self
+    }
+
+    /// Serial number of the cert. The cert parser of the verifier reads it as a single byte.
+    pub fn serial(mut self, serial: u8) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// Endorsed attestation report embedded in the first extension of the cert.
+    pub fn attestation_payload(mut self, payload: &[u8]) -> Self {
+        let oid = ObjectIdentifier::from_slice(ATTESTATION_REPORT_OID);
+        self.payloads.retain(|(other, _)| other != &oid);
+        self.payloads.insert(0, (oid, payload.to_vec()));
+        self
+    }
+
+    /// Embeds `payload` in an extension with OID `oid`, replacing an earlier payload with the
+    /// same OID. All payloads are protected alike and marked non-critical, so that verifiers
+    /// skip the ones they do not understand. Without any payload, the cert carries an empty
+    /// attestation report.
+    pub fn attestation_extension(mut self, oid: &[u64], payload: &[u8]) -> Self {
+        let oid = ObjectIdentifier::from_slice(oid);
+        match self.payloads.iter_mut().find(|(other, _)| other == &oid) {
+            Some(existing) => existing.1 = payload.to_vec(),
+            None => self.payloads.push((oid, payload.to_vec())),
+        }
+        self
+    }
+
+    pub fn key_usage(mut self, key_usage: CertKeyUsage) -> Self {
+        self.key_usage = key_usage;
+        self
+    }
+
+    pub fn payload_protection(mut self, protection: PayloadProtection) -> Self {
+        self.protection = protection;
+        self
+    }
+
+    /// Start of the validity of the cert, now by default.
+    pub fn issued_at(mut self, time: SystemTime) -> Self {
+        self.issued_at = Some(time);
+        self
+    }
+
+    /// Value of the subjectAltName extension: a SEQUENCE OF dNSName, which are IA5Strings
+    /// implicitly tagged [2].
+    fn san_der(&self) -> Option<Vec<u8>> {
+        if self.san.is_empty() {
+            return None;
+        }
+        Some(yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                for name in &self.san {
+                    writer
+                        .next()
+                        .write_tagged_implicit(yasna::Tag::context(2), |writer| {
+                            writer.write_bytes(name.as_bytes())
+                        });
+                }
+            });
+        }))
+    }
+
+    /// Builds and signs the cert.
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let tbs_cert_der = self.build_tbs()?;
+        Ok(self.sign_tbs(&tbs_cert_der))
+    }
+
+    /// Builds the tbsCertificate, i.e. the DER of all fields of the cert which the signature
+    /// covers.
+    pub fn build_tbs(&self) -> Result<Vec<u8>> {
         use crate::cert::*;
-        use bit_vec::BitVec;
         use chrono::TimeZone;
-        use num_bigint::BigUint;
-        use std::time::SystemTime;
         use std::time::UNIX_EPOCH;
         #[allow(unused_imports)]
         use std::untrusted::time::SystemTimeEx;
         use yasna::construct_der;
-        use yasna::models::{ObjectIdentifier, UTCTime};
+        use yasna::models::UTCTime;
 
         // Construct useful OIDs.
-        let ecdsa_with_sha256_oid = ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 4, 3, 2]);
         let common_name_oid = ObjectIdentifier::from_slice(&[2, 5, 4, 3]);
-        let ec_public_key_oid = ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 2, 1]);
-        let prime256v1_oid = ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 3, 1, 7]);
-        let comment_oid = ObjectIdentifier::from_slice(&[2, 16, 840, 1, 113_730, 1, 13]);
 
-        let pub_key_bytes = self.public_key_into_bytes();
+        let mut sgx_ra_cert_ext = Vec::new();
+        if self.payloads.is_empty() {
+            let comment_oid = ObjectIdentifier::from_slice(ATTESTATION_REPORT_OID);
+            sgx_ra_cert_ext.push((comment_oid, false, self.protection.protect(&[])?));
+        }
+        for (oid, payload) in &self.payloads {
+            sgx_ra_cert_ext.push((oid.clone(), false, self.protection.protect(payload)?));
+        }
+        sgx_ra_cert_ext.extend(self.key_usage.extensions());
+        if let Some(value) = self.san_der() {
+            let subject_alt_name_oid = ObjectIdentifier::from_slice(&[2, 5, 29, 17]);
+            sgx_ra_cert_ext.push((subject_alt_name_oid, false, value));
+        }
+        // RFC 5280 allows each extension only once per cert.
+        for (i, (oid, _, _)) in sgx_ra_cert_ext.iter().enumerate() {
+            if sgx_ra_cert_ext[..i]
+                .iter()
+                .any(|(other, _, _)| other == oid)
+            {
+                anyhow::bail!("duplicate cert extension: {}", oid);
+            }
+        }
 
         // UNIX_EPOCH is the earliest time stamp. This unwrap should constantly succeed.
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let now = self
+            .issued_at
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
         let issue_ts = chrono::Utc.timestamp_opt(now.as_secs() as i64, 0).unwrap();
 
-        // This is guaranteed to be a valid duration.
-        let expire = now + chrono::Duration::days(CERT_VALID_DAYS).to_std().unwrap();
+        let expire = now
+            + chrono::Duration::days(self.validity_days)
+                .to_std()
+                .map_err(|_| {
+                    anyhow::anyhow!("invalid cert validity: {} days", self.validity_days)
+                })?;
         let expire_ts = chrono::Utc
             .timestamp_opt(expire.as_secs() as i64, 0)
             .unwrap();
 
         // Construct certificate with payload in extension in DER.
-        let tbs_cert_der = construct_der(|writer| {
+        Ok(construct_der(|writer| {
             let version = 2i8;
-            let serial = 1u8;
-            let cert_sign_algo = asn1_seq!(ecdsa_with_sha256_oid.clone());
+            let serial = self.serial;
+            let cert_sign_algo = asn1_seq!(ecdsa_with_sha256_oid());
             let issuer = asn1_seq!(asn1_seq!(asn1_seq!(
                 common_name_oid.clone(),
-                issuer.to_owned()
+                self.issuer.clone()
             )));
             let valid_range = asn1_seq!(
                 UTCTime::from_datetime(&issue_ts),
@@ -134,13 +437,9 @@ impl NistP256KeyPair {
             );
             let subject = asn1_seq!(asn1_seq!(asn1_seq!(
                 common_name_oid.clone(),
-                subject.to_string(),
+                self.subject.clone(),
             )));
-            let pub_key = asn1_seq!(
-                asn1_seq!(ec_public_key_oid, prime256v1_oid,),
-                BitVec::from_bytes(&pub_key_bytes),
-            );
-            let sgx_ra_cert_ext = asn1_seq!(asn1_seq!(comment_oid, payload.to_owned()));
+            let pub_key = self.public_key_info();
             let tbs_cert = asn1_seq!(
                 version,
                 serial,
@@ -152,14 +451,61 @@ impl NistP256KeyPair {
                 sgx_ra_cert_ext,
             );
             TbsCert::dump(writer, tbs_cert);
-        });
+        }))
+    }
+
+    /// Signs `tbs_der`, a tbsCertificate built earlier by `build_tbs`, with the key pair of
+    /// this builder instead of building a new one, e.g. to rotate the signing key of a cert
+    /// which peers pin by its common name. The subjectPublicKeyInfo is replaced by the new
+    /// public key, and all other fields stay byte-identical; the options of this builder are
+    /// not applied. The attestation payload still binds the previous key, so the result does
+    /// not pass verifiers which check the report data against the key of the cert.
+    pub fn resign_tbs(&self, tbs_der: &[u8]) -> Result<Vec<u8>> {
+        use crate::cert::*;
+
+        let tbs_cert = yasna::parse_der(tbs_der, TbsCert::load)
+            .map_err(|e| anyhow::anyhow!("invalid tbsCertificate: {}", e))?;
+        let (version, (serial, (algo, (issuer, (valid_range, (subject, (_, (ext, ())))))))) =
+            tbs_cert;
+        if algo.0 != ecdsa_with_sha256_oid() {
+            anyhow::bail!("tbsCertificate is not signed with ecdsa-with-SHA256");
+        }
+        let tbs_cert = asn1_seq!(
+            version,
+            serial,
+            algo,
+            issuer,
+            valid_range,
+            subject,
+            self.public_key_info(),
+            ext,
+        );
+        let tbs_cert_der = yasna::construct_der(|writer| TbsCert::dump(writer, tbs_cert));
+        Ok(self.sign_tbs(&tbs_cert_der))
+    }
+
+    /// subjectPublicKeyInfo of the key pair.
+    fn public_key_info(&self) -> <crate::cert::PubKey as crate::cert::Asn1Ty>::ValueTy {
+        let ec_public_key_oid = ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 2, 1]);
+        let prime256v1_oid = ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 3, 1, 7]);
+        asn1_seq!(
+            asn1_seq!(ec_public_key_oid, prime256v1_oid,),
+            BitVec::from_bytes(&self.key_pair.public_key_into_bytes()),
+        )
+    }
+
+    /// Wraps `tbs_cert_der` into a cert signed with the key pair.
+    fn sign_tbs(&self, tbs_cert_der: &[u8]) -> Vec<u8> {
+        use crate::cert::*;
+        use num_bigint::BigUint;
 
         // There will be serious problems if this call fails. We might as well
         // panic in this case, thus unwrap()
         let sig = self
+            .key_pair
             .inner
             .private_key()
-            .sign(tbs_cert_der.as_slice())
+            .sign(tbs_cert_der)
             .unwrap();
 
         let sig_der = yasna::construct_der(|writer| {
@@ -176,28 +522,525 @@ impl NistP256KeyPair {
 
         yasna::construct_der(|writer| {
             writer.write_sequence(|writer| {
-                writer.next().write_der(tbs_cert_der.as_slice());
-                CertSignAlgo::dump(writer.next(), asn1_seq!(ecdsa_with_sha256_oid.clone()));
+                writer.next().write_der(tbs_cert_der);
+                CertSignAlgo::dump(writer.next(), asn1_seq!(ecdsa_with_sha256_oid()));
                 writer
                     .next()
                     .write_bitvec(&BitVec::from_bytes(sig_der.as_slice()));
             });
         })
     }
+}
 
-    fn public_key_into_bytes(&self) -> Vec<u8> {
-        // The first byte must be 4, which indicates the uncompressed encoding.
-        let mut pub_key_bytes: Vec<u8> = vec![4];
-        let public_key = self.pub_k().public_key();
-        pub_key_bytes.extend(public_key.gx.iter().rev());
-        pub_key_bytes.extend(public_key.gy.iter().rev());
-        pub_key_bytes
+/// Order n of the base point of NIST P-256, big-endian. Private scalars lie in [1, n-1].
+const P256_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9, 0xca, 0xc2, 0xfc, 0x63, 0x25, 0x51,
+];
+
+/// validate_private_key_der checks that `der` is a well-formed NIST P-256 private key, either
+/// PKCS#8 as exported by `private_key_into_der` or a bare SEC1 ECPrivateKey, without
+/// constructing a key from it. The structure, the versions and the curve are checked, and the
+/// private scalar must be in [1, n-1]. An embedded public key is not checked against the
+/// scalar.
+pub fn validate_private_key_der(der: &[u8]) -> Result<()> {
+    let fields = der_sequence(der).context("Malformed private key")?;
+    match der_version(&fields)? {
+        0 => validate_pkcs8(&fields[1..]),
+        1 => validate_sec1(&fields[1..]),
+        version => anyhow::bail!("Unsupported private key version {}", version),
     }
+}
 
-    fn private_key_into_bytes(&self) -> Vec<u8> {
-        let mut prv_key_bytes: Vec<u8> = vec![];
-        let private_key = self.inner.private_key().private_key();
-        prv_key_bytes.extend(private_key.r.iter().rev());
-        prv_key_bytes
+// PrivateKeyInfo (RFC 5208) without the version.
+fn validate_pkcs8(fields: &[Vec<u8>]) -> Result<()> {
+    anyhow::ensure!(
+        fields.len() >= 2,
+        "Malformed PKCS#8 private key: missing fields"
+    );
+    let (algorithm, curve) = yasna::parse_der(&fields[0], |reader| {
+        reader.read_sequence(|reader| Ok((reader.next().read_oid()?, reader.next().read_oid()?)))
+    })
+    .map_err(|e| anyhow::anyhow!("Malformed PKCS#8 algorithm identifier: {}", e))?;
+    anyhow::ensure!(
+        algorithm.components().as_slice() == EC_PUBLIC_KEY_OID,
+        "Unsupported private key algorithm {}",
+        oid_string(&algorithm)
+    );
+    check_p256(&curve)?;
+
+    let inner = yasna::parse_der(&fields[1], |reader| reader.read_bytes())
+        .map_err(|e| anyhow::anyhow!("Malformed PKCS#8 private key: {}", e))?;
+    let inner = der_sequence(&inner).context("Malformed EC private key")?;
+    match der_version(&inner)? {
+        1 => validate_sec1(&inner[1..]),
+        version => anyhow::bail!("Unsupported EC private key version {}", version),
+    }
+}
+
+// ECPrivateKey (RFC 5915) without the version.
+fn validate_sec1(fields: &[Vec<u8>]) -> Result<()> {
+    let scalar = fields
+        .first()
+        .map(|field| yasna::parse_der(field, |reader| reader.read_bytes()))
+        .ok_or_else(|| anyhow::anyhow!("Malformed EC private key: missing private key"))?
+        .map_err(|e| anyhow::anyhow!("Malformed EC private key: {}", e))?;
+    anyhow::ensure!(
+        scalar.len() == P256_ORDER.len(),
+        "Private key of {} bytes does not match curve P256",
+        scalar.len()
+    );
+    // Both are big-endian of the same length, so they compare like the numbers.
+    anyhow::ensure!(
+        scalar.iter().any(|&b| b != 0) && scalar.as_slice() < &P256_ORDER[..],
+        "Private key scalar is not in [1, n-1]"
+    );
+    for field in &fields[1..] {
+        let parameters = yasna::parse_der(field, |reader| {
+            reader.read_tagged(Tag::context(0), |r| r.read_oid())
+        });
+        if let Ok(curve) = parameters {
+            check_p256(&curve)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_p256(curve: &ObjectIdentifier) -> Result<()> {
+    let curve = EcCurve::from_oid(curve)?;
+    anyhow::ensure!(
+        curve == EcCurve::P256,
+        "Private key is on curve {:?}, expected P256",
+        curve
+    );
+    Ok(())
+}
+
+// The raw DER of each element of a sequence.
+fn der_sequence(der: &[u8]) -> Result<Vec<Vec<u8>>> {
+    yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let mut fields = Vec::new();
+            while let Some(field) = reader.read_optional(|reader| reader.read_der())? {
+                fields.push(field);
+            }
+            Ok(fields)
+        })
+    })
+    .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+fn der_version(fields: &[Vec<u8>]) -> Result<u64> {
+    let version = fields
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("Malformed private key: empty sequence"))?;
+    yasna::parse_der(version, |reader| reader.read_u64())
+        .map_err(|e| anyhow::anyhow!("Malformed private key version: {}", e))
+}
+
+fn ecdsa_with_sha256_oid() -> ObjectIdentifier {
+    ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 4, 3, 2])
+}
+
+#[cfg(all(feature = "enclave_unit_test", feature = "mesalock_sgx"))]
+pub mod tests {
+    use super::*;
+
+    pub fn test_key_usage_encoding() {
+        assert!(CertKeyUsage::default().extensions().is_empty());
+
+        let usage = CertKeyUsage::new().key_usage(KEY_USAGE_DIGITAL_SIGNATURE);
+        assert_eq!(usage.key_usage_der().unwrap(), [0x03, 0x02, 0x07, 0x80]);
+
+        let usage = CertKeyUsage::new()
+            .key_usage(KEY_USAGE_DIGITAL_SIGNATURE)
+            .key_usage(KEY_USAGE_KEY_ENCIPHERMENT);
+        assert_eq!(usage.key_usage_der().unwrap(), [0x03, 0x02, 0x05, 0xa0]);
+
+        let usage = CertKeyUsage::new()
+            .key_usage(KEY_USAGE_KEY_CERT_SIGN)
+            .key_usage(KEY_USAGE_CRL_SIGN);
+        assert_eq!(usage.key_usage_der().unwrap(), [0x03, 0x02, 0x01, 0x06]);
+
+        // Bits past the first byte need a second one.
+        let usage = CertKeyUsage::new().key_usage(KEY_USAGE_DECIPHER_ONLY);
+        assert_eq!(
+            usage.key_usage_der().unwrap(),
+            [0x03, 0x03, 0x07, 0x00, 0x80]
+        );
+
+        let usage = CertKeyUsage::new().extended_key_usage(EXTENDED_KEY_USAGE_SERVER_AUTH);
+        assert_eq!(
+            usage.extended_key_usage_der().unwrap(),
+            [0x30, 0x0a, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01]
+        );
+    }
+
+    pub fn test_create_cert_with_key_usage() {
+        use crate::cert::*;
+
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let usage = CertKeyUsage::new()
+            .key_usage(KEY_USAGE_DIGITAL_SIGNATURE)
+            .extended_key_usage(EXTENDED_KEY_USAGE_SERVER_AUTH);
+        let cert = key_pair
+            .create_cert_with_extension(
+                "Teaclave",
+                "CN=Teaclave",
+                b"{}",
+                &usage,
+                &PayloadProtection::Plaintext,
+            )
+            .unwrap();
+
+        let x509 = yasna::parse_der(&cert, X509::load).unwrap();
+        let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+        let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
+        let oids: Vec<(Vec<u64>, bool)> = cert_ext
+            .iter()
+            .map(|(oid, critical, _)| (oid.components().clone(), *critical))
+            .collect();
+        assert_eq!(
+            oids,
+            vec![
+                (vec![2, 16, 840, 1, 113_730, 1, 13], false),
+                (vec![2, 5, 29, 15], true),
+                (vec![2, 5, 29, 37], false),
+            ]
+        );
+        assert_eq!(cert_ext[0].2, b"{}");
+        assert_eq!(cert_ext[1].2, usage.key_usage_der().unwrap());
+    }
+
+    pub fn test_create_cert_with_encrypted_payload() {
+        use crate::cert::*;
+        use crate::payload::*;
+        use ring::agreement;
+        use teaclave_crypto::RecipientPrivateKey;
+
+        let verifier_key = RecipientPrivateKey::generate(&agreement::ECDH_P256).unwrap();
+        let verifier_public_key = verifier_key.public_key().unwrap();
+
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let payload = br#"{"report":[],"signature":[],"certs":[]}"#;
+        let cert = key_pair
+            .create_cert_with_extension(
+                "Teaclave",
+                "CN=Teaclave",
+                payload,
+                &CertKeyUsage::default(),
+                &PayloadProtection::Verifier(verifier_public_key),
+            )
+            .unwrap();
+
+        let x509 = yasna::parse_der(&cert, X509::load).unwrap();
+        let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+        let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
+        let extension = &cert_ext[0].2;
+        assert!(is_encrypted_payload(extension));
+        assert_eq!(
+            open_payload(extension, Some(&verifier_key)).unwrap(),
+            payload
+        );
+        // The verifier may store its key and open the payload later.
+        let stored_key =
+            RecipientPrivateKey::from_bytes(&agreement::ECDH_P256, verifier_key.as_bytes())
+                .unwrap();
+        assert_eq!(open_payload(extension, Some(&stored_key)).unwrap(), payload);
+    }
+
+    pub fn test_cert_builder_matches_legacy() {
+        use crate::cert::*;
+
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let usage = CertKeyUsage::new().key_usage(KEY_USAGE_DIGITAL_SIGNATURE);
+        let legacy = key_pair
+            .create_cert_with_extension(
+                "Teaclave",
+                "CN=Teaclave",
+                b"{}",
+                &usage,
+                &PayloadProtection::Plaintext,
+            )
+            .unwrap();
+        let built = CertBuilder::new(&key_pair)
+            .issuer("Teaclave")
+            .subject_dn("CN=Teaclave")
+            .attestation_payload(b"{}")
+            .key_usage(usage)
+            .build()
+            .unwrap();
+
+        // Signatures are randomized and the certs may be issued a second apart, so compare all
+        // fields of the tbsCertificate but the validity.
+        let load = |cert: &[u8]| {
+            let x509 = yasna::parse_der(cert, X509::load).unwrap();
+            let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+            let (version, (serial, (algo, (issuer, (valid_range, (subject, (pub_key, ext))))))) =
+                tbs_cert;
+            let days = (valid_range.1.datetime().timestamp()
+                - valid_range.0.datetime().timestamp())
+                / (24 * 3600);
+            (version, serial, algo, issuer, days, subject, pub_key, ext)
+        };
+        assert_eq!(load(&legacy), load(&built));
+        assert_eq!(load(&built).4, CERT_VALID_DAYS);
+    }
+
+    pub fn test_cert_builder_options() {
+        use crate::cert::*;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let issued_at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let cert = CertBuilder::new(&key_pair)
+            .issuer("Teaclave")
+            .subject_dn("CN=Teaclave")
+            .validity_days(7)
+            .serial(42)
+            .san("teaclave.example")
+            .san("localhost")
+            .issued_at(issued_at)
+            .build()
+            .unwrap();
+
+        let x509 = yasna::parse_der(&cert, X509::load).unwrap();
+        let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+        let serial = (tbs_cert.1).0;
+        let valid_range = ((((tbs_cert.1).1).1).1).0;
+        let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
+        assert_eq!(serial, 42);
+        assert_eq!(valid_range.0.datetime().timestamp(), 1_600_000_000);
+        assert_eq!(
+            valid_range.1.datetime().timestamp(),
+            1_600_000_000 + 7 * 24 * 3600
+        );
+        assert_eq!(cert_ext.len(), 2);
+        assert_eq!(cert_ext[1].0.components(), &vec![2, 5, 29, 17]);
+        let names = yasna::parse_der(&cert_ext[1].2, |reader| {
+            reader.collect_sequence_of(|reader| {
+                reader.read_tagged_implicit(yasna::Tag::context(2), |reader| reader.read_bytes())
+            })
+        })
+        .unwrap();
+        assert_eq!(
+            names,
+            vec![b"teaclave.example".to_vec(), b"localhost".to_vec()]
+        );
+
+        assert!(CertBuilder::new(&key_pair)
+            .validity_days(-1)
+            .build()
+            .is_err());
+    }
+
+    pub fn test_resign_tbs() {
+        use crate::cert::*;
+        use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+
+        let contains = |haystack: &[u8], needle: &[u8]| {
+            haystack
+                .windows(needle.len())
+                .any(|window| window == needle)
+        };
+        let signature = |cert: &[u8]| {
+            let x509 = yasna::parse_der(cert, X509::load).unwrap();
+            ((x509.1).1).0.to_bytes()
+        };
+
+        let old_key = NistP256KeyPair::new().unwrap();
+        let new_key = NistP256KeyPair::new().unwrap();
+        let tbs = CertBuilder::new(&old_key)
+            .issuer("Teaclave")
+            .subject_dn("CN=Teaclave")
+            .attestation_payload(b"{}")
+            .san("teaclave.example")
+            .build_tbs()
+            .unwrap();
+
+        // Both points are 65 bytes, so the new tbsCertificate only differs in the point.
+        let old_point = old_key.public_key_into_bytes();
+        let new_point = new_key.public_key_into_bytes();
+        let offset = tbs
+            .windows(old_point.len())
+            .position(|window| window == old_point.as_slice())
+            .unwrap();
+        let mut expected = tbs.clone();
+        expected[offset..offset + new_point.len()].copy_from_slice(&new_point);
+
+        // Options of the re-signing builder do not matter.
+        let cert = CertBuilder::new(&new_key)
+            .subject_dn("CN=Other")
+            .resign_tbs(&tbs)
+            .unwrap();
+        assert!(contains(&cert, &expected));
+        let verify = |key: &NistP256KeyPair| {
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, key.public_key_into_bytes())
+                .verify(&expected, &signature(&cert))
+                .is_ok()
+        };
+        assert!(verify(&new_key));
+        assert!(!verify(&old_key));
+
+        // The same key reproduces the original tbsCertificate byte for byte.
+        let cert = CertBuilder::new(&old_key).resign_tbs(&tbs).unwrap();
+        assert!(contains(&cert, &tbs));
+
+        assert!(CertBuilder::new(&new_key)
+            .resign_tbs(&tbs[..tbs.len() - 1])
+            .is_err());
+    }
+
+    pub fn test_create_cert_with_extensions() {
+        use crate::cert::*;
+        use crate::report::*;
+
+        // OID of the SGX extensions of DCAP PCK certs, standing in for a DCAP quote.
+        const DCAP_OID: &[u64] = &[1, 2, 840, 113_741, 1, 13, 1];
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let cert = key_pair
+            .create_cert_with_extensions(
+                "Teaclave",
+                "CN=Teaclave",
+                &[(ATTESTATION_REPORT_OID, b"epid"), (DCAP_OID, b"dcap")],
+                &CertKeyUsage::new().key_usage(KEY_USAGE_DIGITAL_SIGNATURE),
+                &PayloadProtection::Plaintext,
+            )
+            .unwrap();
+
+        let extensions =
+            attestation_extensions(&cert, &[ATTESTATION_REPORT_OID, DCAP_OID]).unwrap();
+        assert_eq!(
+            extensions,
+            vec![
+                (ATTESTATION_REPORT_OID.to_vec(), b"epid".to_vec()),
+                (DCAP_OID.to_vec(), b"dcap".to_vec()),
+            ]
+        );
+        // Verifiers which only know one of the payloads find just that one.
+        assert_eq!(attestation_extensions(&cert, &[DCAP_OID]).unwrap().len(), 1);
+
+        // Payload extensions are non-critical, so verifiers may skip unknown ones.
+        let x509 = yasna::parse_der(&cert, X509::load).unwrap();
+        let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+        let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
+        assert_eq!(cert_ext.len(), 3);
+        assert!(!cert_ext[0].1 && !cert_ext[1].1);
+
+        // The attestation report stays first, and each OID appears once.
+        let cert = CertBuilder::new(&key_pair)
+            .attestation_extension(DCAP_OID, b"dcap")
+            .attestation_payload(b"old")
+            .attestation_payload(b"epid")
+            .build()
+            .unwrap();
+        assert_eq!(
+            attestation_extensions(&cert, &[ATTESTATION_REPORT_OID, DCAP_OID]).unwrap(),
+            extensions
+        );
+        assert!(CertBuilder::new(&key_pair)
+            .attestation_extension(&[2, 5, 29, 15], b"{}")
+            .key_usage(CertKeyUsage::new().key_usage(KEY_USAGE_DIGITAL_SIGNATURE))
+            .build()
+            .is_err());
+    }
+
+    // SEC1 ECPrivateKey with the curve in its parameters, wrapped in PKCS#8 if `pkcs8`.
+    fn ec_private_key_der(scalar: &[u8], curve: &[u64], pkcs8: bool) -> Vec<u8> {
+        let sec1 = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_u8(1);
+                writer.next().write_bytes(scalar);
+                writer.next().write_tagged(Tag::context(0), |writer| {
+                    writer.write_oid(&ObjectIdentifier::from_slice(curve))
+                });
+            })
+        });
+        if !pkcs8 {
+            return sec1;
+        }
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_u8(0);
+                writer.next().write_sequence(|writer| {
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(EC_PUBLIC_KEY_OID));
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(curve));
+                });
+                writer.next().write_bytes(&sec1);
+            })
+        })
+    }
+
+    pub fn test_validate_private_key_der() {
+        use crate::public_key::PRIME256V1_OID;
+        use ring::rand::SystemRandom;
+        use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+        let key_pair = NistP256KeyPair::new().unwrap();
+        validate_private_key_der(&key_pair.private_key_into_der()).unwrap();
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+                .unwrap();
+        validate_private_key_der(pkcs8.as_ref()).unwrap();
+
+        let scalar = key_pair.private_key_into_bytes();
+        validate_private_key_der(&ec_private_key_der(&scalar, PRIME256V1_OID, false)).unwrap();
+        validate_private_key_der(&ec_private_key_der(&scalar, PRIME256V1_OID, true)).unwrap();
+
+        let der = key_pair.private_key_into_der();
+        assert!(validate_private_key_der(&der[..der.len() - 1]).is_err());
+        assert!(validate_private_key_der(b"").is_err());
+    }
+
+    pub fn test_validate_private_key_der_wrong_curve() {
+        use crate::public_key::SECP384R1_OID;
+        use ring::rand::SystemRandom;
+        use ring::signature::{EcdsaKeyPair, ECDSA_P384_SHA384_ASN1_SIGNING};
+
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, &SystemRandom::new())
+                .unwrap();
+        let error = validate_private_key_der(pkcs8.as_ref()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Private key is on curve P384, expected P256"
+        );
+
+        // The curve of a bare SEC1 key is taken from its parameters.
+        let der = ec_private_key_der(&[1; 32], SECP384R1_OID, false);
+        let error = validate_private_key_der(&der).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Private key is on curve P384, expected P256"
+        );
+    }
+
+    pub fn test_validate_private_key_der_scalar_range() {
+        use crate::public_key::PRIME256V1_OID;
+
+        let mut n_minus_1 = P256_ORDER;
+        n_minus_1[31] -= 1;
+        let mut one = [0; 32];
+        one[31] = 1;
+        for scalar in &[n_minus_1, one] {
+            validate_private_key_der(&ec_private_key_der(scalar, PRIME256V1_OID, true)).unwrap();
+        }
+
+        for scalar in &[P256_ORDER, [0; 32], [0xff; 32]] {
+            let error = validate_private_key_der(&ec_private_key_der(scalar, PRIME256V1_OID, true))
+                .unwrap_err();
+            assert_eq!(error.to_string(), "Private key scalar is not in [1, n-1]");
+        }
+
+        let error = validate_private_key_der(&ec_private_key_der(&[1; 31], PRIME256V1_OID, true))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Private key of 31 bytes does not match curve P256"
+        );
     }
 }
@@ -154,11 +154,45 @@ pub struct AttestedTlsConfig {
     pub validity: std::time::Duration,
 }
 
+impl AttestedTlsConfig {
+    /// Signs `message` with the private key of the attested cert, e.g. so that a record produced
+    /// by the enclave can be tied to its attestation. The signature is DER encoded ECDSA over
+    /// SHA-256 and verifies with `public_key::parse_cert_public_key(&self.cert)?.verify(..)`.
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &self.private_key)
+            .map_err(|_| anyhow::anyhow!("Invalid attestation private key"))?;
+        let signature = key_pair
+            .sign(&ring::rand::SystemRandom::new(), message)
+            .map_err(|_| anyhow::anyhow!("Cannot sign with the attestation key"))?;
+        Ok(signature.as_ref().to_vec())
+    }
+}
+
 #[macro_use]
 mod cert;
+pub mod payload;
+pub mod pkcs12;
+pub mod public_key;
 pub mod report;
 pub mod verifier;
 
+/// Compares two byte strings in constant time with respect to their contents, so that checking
+/// a MAC, digest or shared secret does not leak how many leading bytes matched. Only the lengths
+/// are compared in variable time.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    // Keep the compiler from turning the fold into an early-exit comparison.
+    unsafe { std::ptr::read_volatile(&diff) == 0 }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "mesalock_sgx")]  {
         mod service;
@@ -166,6 +200,7 @@ cfg_if::cfg_if! {
         mod platform;
         mod attestation;
         pub use attestation::RemoteAttestation;
+        pub use platform::enclave_debug_mode;
     }
 }
 
@@ -181,7 +216,44 @@ pub mod tests {
             platform::tests::test_get_sgx_quote,
             report::tests::test_sgx_quote_parse_from,
             report::tests::test_attestation_report_from_cert,
-            report::tests::test_attestation_report_from_cert_api_version_not_compatible
+            report::tests::test_attestation_report_from_cert_api_version_not_compatible,
+            key::tests::test_key_usage_encoding,
+            key::tests::test_create_cert_with_key_usage,
+            key::tests::test_create_cert_with_encrypted_payload,
+            key::tests::test_cert_builder_matches_legacy,
+            key::tests::test_cert_builder_options,
+            key::tests::test_resign_tbs,
+            key::tests::test_create_cert_with_extensions,
+            key::tests::test_validate_private_key_der,
+            key::tests::test_validate_private_key_der_wrong_curve,
+            key::tests::test_validate_private_key_der_scalar_range,
+            payload::tests::test_payload_round_trip,
+            payload::tests::test_chunked_payload,
+            payload::tests::test_chunked_payload_in_cert,
+            pkcs12::tests::test_pkcs12_kdf,
+            pkcs12::tests::test_pkcs12_known_answer,
+            pkcs12::tests::test_pkcs12_round_trip,
+            pkcs12::tests::test_pkcs12_openssl,
+            public_key::tests::test_parse_p256_spki,
+            public_key::tests::test_parse_p384_spki,
+            public_key::tests::test_parse_spki_errors,
+            public_key::tests::test_verify_signature,
+            test_ct_eq,
         )
     }
+
+    fn test_ct_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"teaclave", b"teaclave"));
+        assert!(!ct_eq(b"teaclave", b"teaclavE"));
+        assert!(!ct_eq(b"teaclave", b"Teaclave"));
+        assert!(!ct_eq(b"teaclave", b"teaclave!"));
+        assert!(!ct_eq(b"", b"\0"));
+
+        let digest = [0xa5u8; 32];
+        let mut other = digest;
+        assert!(ct_eq(&digest, &other));
+        other[31] ^= 1;
+        assert!(!ct_eq(&digest, &other));
+    }
 }
@@ -0,0 +1,310 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! This module protects the attestation payload embedded in the attested cert. By default the
+//! payload is embedded in plaintext, readable by anyone observing the TLS handshake. It can
+//! instead be sealed to the public key of an authorized verifier, marked by a header so that
+//! the verifier knows to decrypt it. Payloads too large for one extension may be split into
+//! chunks, each in its own extension under the same OID.
+
+use anyhow::{anyhow, bail, ensure, Result};
+use std::convert::TryInto;
+use teaclave_crypto::{RecipientPrivateKey, SealedOutput};
+
+/// Prefix of payloads sealed to a verifier. A plaintext payload is a JSON document and can't
+/// start with it.
+pub const ENCRYPTED_PAYLOAD_HEADER: &[u8] = b"TEACLAVE-SEALED-PAYLOAD-V1\n";
+
+/// Prefix of the extensions carrying one chunk of a payload. It is followed by the index of the
+/// chunk and the number of chunks, both as big-endian u32, and then by the chunk.
+pub const CHUNKED_PAYLOAD_HEADER: &[u8] = b"TEACLAVE-PAYLOAD-CHUNK-V1\n";
+
+/// How the attestation payload is placed in the cert extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayloadProtection {
+    /// Embed the payload as is.
+    Plaintext,
+    /// Seal the payload to the verifier's P-256 public key, an uncompressed SEC1 encoded point,
+    /// with ECDH, HKDF-SHA256 and AES-256-GCM.
+    Verifier(Vec<u8>),
+}
+
+impl Default for PayloadProtection {
+    fn default() -> Self {
+        PayloadProtection::Plaintext
+    }
+}
+
+impl PayloadProtection {
+    /// Returns the cert extension value carrying `payload`.
+    pub fn protect(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            PayloadProtection::Plaintext => Ok(payload.to_vec()),
+            PayloadProtection::Verifier(public_key) => {
+                let sealed = SealedOutput::seal(public_key, payload)?;
+                let mut extension = ENCRYPTED_PAYLOAD_HEADER.to_vec();
+                extension.extend(serde_json::to_vec(&sealed)?);
+                Ok(extension)
+            }
+        }
+    }
+}
+
+pub fn is_encrypted_payload(extension: &[u8]) -> bool {
+    extension.starts_with(ENCRYPTED_PAYLOAD_HEADER)
+}
+
+/// Splits an extension value into the values of chunk extensions, each carrying up to
+/// `chunk_size` bytes of it. An empty value still makes one chunk, as a cert without any
+/// extension under the attestation OID is rejected.
+pub fn chunk_payload(extension: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    let mut chunks: Vec<&[u8]> = extension.chunks(chunk_size.max(1)).collect();
+    if chunks.is_empty() {
+        chunks.push(extension);
+    }
+    let count = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut value = CHUNKED_PAYLOAD_HEADER.to_vec();
+            value.extend_from_slice(&(index as u32).to_be_bytes());
+            value.extend_from_slice(&count.to_be_bytes());
+            value.extend_from_slice(chunk);
+            value
+        })
+        .collect()
+}
+
+/// Parses a chunk extension into its index, the number of chunks and the chunk.
+fn parse_chunk(value: &[u8]) -> Result<(u32, u32, &[u8])> {
+    let value = &value[CHUNKED_PAYLOAD_HEADER.len()..];
+    ensure!(value.len() >= 8, "Truncated attestation payload chunk");
+    let index = u32::from_be_bytes(value[..4].try_into()?);
+    let count = u32::from_be_bytes(value[4..8].try_into()?);
+    Ok((index, count, &value[8..]))
+}
+
+/// Reassembles the extension value from the values of all extensions under the attestation
+/// OID, in the order of the cert. A single extension without a chunk header is the value
+/// itself; chunks are concatenated in the order of their index, which must run from 0 to the
+/// number of chunks without gaps or duplicates.
+pub fn reassemble_payload(extensions: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let is_chunk = |value: &Vec<u8>| value.starts_with(CHUNKED_PAYLOAD_HEADER);
+    match extensions {
+        [] => bail!("Missing attestation report extension"),
+        [value] if !is_chunk(value) => return Ok(value.clone()),
+        _ => ensure!(
+            extensions.iter().all(is_chunk),
+            "Several attestation report extensions without chunk index"
+        ),
+    }
+
+    let mut chunks = extensions
+        .iter()
+        .map(|value| parse_chunk(value))
+        .collect::<Result<Vec<_>>>()?;
+    chunks.sort_by_key(|(index, _, _)| *index);
+    let count = chunks[0].1;
+    let mut payload = Vec::new();
+    for (expected, (index, chunk_count, chunk)) in chunks.iter().enumerate() {
+        let expected = expected as u32;
+        ensure!(
+            *chunk_count == count,
+            "Attestation payload chunks disagree on their number"
+        );
+        ensure!(
+            *index < count,
+            "Attestation payload chunk {} beyond the {} chunks",
+            index,
+            count
+        );
+        if *index < expected {
+            bail!("Duplicate attestation payload chunk {}", index);
+        }
+        if *index > expected {
+            bail!(
+                "Missing attestation payload chunk {} of {}",
+                expected,
+                count
+            );
+        }
+        payload.extend_from_slice(chunk);
+    }
+    ensure!(
+        chunks.len() as u32 == count,
+        "Missing attestation payload chunk {} of {}",
+        chunks.len(),
+        count
+    );
+    Ok(payload)
+}
+
+/// Returns the payload carried in a cert extension. Sealed payloads are opened with
+/// `verifier_key`; without one they are rejected.
+pub fn open_payload(
+    extension: &[u8],
+    verifier_key: Option<&RecipientPrivateKey>,
+) -> Result<Vec<u8>> {
+    if !is_encrypted_payload(extension) {
+        return Ok(extension.to_vec());
+    }
+    let verifier_key = verifier_key.ok_or_else(|| {
+        anyhow!("Attestation payload is sealed to a verifier, but no verifier key is set")
+    })?;
+    let sealed: SealedOutput =
+        serde_json::from_slice(&extension[ENCRYPTED_PAYLOAD_HEADER.len()..])?;
+    let payload = sealed.open(verifier_key)?;
+    ensure!(
+        !is_encrypted_payload(&payload),
+        "Nested encrypted attestation payload"
+    );
+    Ok(payload)
+}
+
+#[cfg(all(feature = "enclave_unit_test", feature = "mesalock_sgx"))]
+pub mod tests {
+    use super::*;
+    use ring::agreement;
+
+    fn verifier_key() -> (RecipientPrivateKey, Vec<u8>) {
+        let key = RecipientPrivateKey::generate(&agreement::ECDH_P256).unwrap();
+        let public_key = key.public_key().unwrap();
+        (key, public_key)
+    }
+
+    pub fn test_payload_round_trip() {
+        let payload = br#"{"report":[],"signature":[],"certs":[]}"#;
+
+        let plaintext = PayloadProtection::default().protect(payload).unwrap();
+        assert_eq!(plaintext, payload);
+        assert_eq!(open_payload(&plaintext, None).unwrap(), payload);
+
+        let (key, public_key) = verifier_key();
+        let extension = PayloadProtection::Verifier(public_key.clone())
+            .protect(payload)
+            .unwrap();
+        assert!(is_encrypted_payload(&extension));
+        assert!(!extension
+            .windows(payload.len())
+            .any(|window| window == payload));
+        assert_eq!(open_payload(&extension, Some(&key)).unwrap(), payload);
+
+        // Without the key, or with another verifier's key, the payload stays sealed.
+        let err = open_payload(&extension, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Attestation payload is sealed to a verifier, but no verifier key is set"
+        );
+        let (other_key, _) = verifier_key();
+        assert!(open_payload(&extension, Some(&other_key)).is_err());
+    }
+
+    pub fn test_chunked_payload() {
+        let payload: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let chunks = chunk_payload(&payload, 400);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(reassemble_payload(&chunks).unwrap(), payload);
+        // The chunks may appear in any order in the cert.
+        let reversed: Vec<Vec<u8>> = chunks.iter().rev().cloned().collect();
+        assert_eq!(reassemble_payload(&reversed).unwrap(), payload);
+
+        // A single extension without chunk header is the payload itself.
+        assert_eq!(reassemble_payload(&[payload.clone()]).unwrap(), payload);
+        assert_eq!(
+            reassemble_payload(&chunk_payload(&payload, 1000)).unwrap(),
+            payload
+        );
+
+        let missing = vec![chunks[0].clone(), chunks[2].clone()];
+        assert_eq!(
+            reassemble_payload(&missing).unwrap_err().to_string(),
+            "Missing attestation payload chunk 1 of 3"
+        );
+        assert_eq!(
+            reassemble_payload(&chunks[..2]).unwrap_err().to_string(),
+            "Missing attestation payload chunk 2 of 3"
+        );
+        let duplicate = vec![chunks[0].clone(), chunks[1].clone(), chunks[1].clone()];
+        assert_eq!(
+            reassemble_payload(&duplicate).unwrap_err().to_string(),
+            "Duplicate attestation payload chunk 1"
+        );
+        let mixed = vec![payload.clone(), chunks[0].clone()];
+        assert!(reassemble_payload(&mixed).is_err());
+        assert!(reassemble_payload(&[]).is_err());
+
+        let empty = chunk_payload(&[], 400);
+        assert_eq!(empty.len(), 1);
+        assert_eq!(reassemble_payload(&empty).unwrap(), b"");
+    }
+
+    pub fn test_chunked_payload_in_cert() {
+        use crate::cert::*;
+        use crate::key::{CertBuilder, CertKeyUsage, NistP256KeyPair, KEY_USAGE_DIGITAL_SIGNATURE};
+        use crate::report::ATTESTATION_REPORT_OID;
+        use yasna::models::ObjectIdentifier;
+
+        // A signed cert with the payload in three extensions under the attestation OID, next
+        // to a key usage extension, read back the way the verifier does.
+        let payload: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let tbs_der = CertBuilder::new(&key_pair)
+            .key_usage(CertKeyUsage::new().key_usage(KEY_USAGE_DIGITAL_SIGNATURE))
+            .build_tbs()
+            .unwrap();
+        let tbs_cert = yasna::parse_der(&tbs_der, TbsCert::load).unwrap();
+        let (version, (serial, (algo, (issuer, (valid_range, (subject, (pub_key, (ext, ())))))))) =
+            tbs_cert;
+        let oid = ObjectIdentifier::from_slice(ATTESTATION_REPORT_OID);
+        let mut chunked_ext: Vec<_> = chunk_payload(&payload, 400)
+            .into_iter()
+            .map(|value| (oid.clone(), false, value))
+            .collect();
+        chunked_ext.extend(ext.into_iter().filter(|(other, _, _)| other != &oid));
+        let tbs_cert = asn1_seq!(
+            version,
+            serial,
+            algo,
+            issuer,
+            valid_range,
+            subject,
+            pub_key,
+            chunked_ext,
+        );
+        let tbs_der = yasna::construct_der(|writer| TbsCert::dump(writer, tbs_cert));
+        let cert = CertBuilder::new(&key_pair).resign_tbs(&tbs_der).unwrap();
+
+        let x509 = yasna::parse_der(&cert, X509::load).unwrap();
+        let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+        let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
+        assert_eq!(cert_ext.len(), 4);
+        let extensions: Vec<Vec<u8>> = cert_ext
+            .into_iter()
+            .filter(|(other, _, _)| other == &oid)
+            .map(|(_, _, value)| value)
+            .collect();
+        assert_eq!(extensions.len(), 3);
+        assert_eq!(reassemble_payload(&extensions).unwrap(), payload);
+        assert_eq!(
+            reassemble_payload(&extensions[1..])
+                .unwrap_err()
+                .to_string(),
+            "Missing attestation payload chunk 0 of 3"
+        );
+    }
+}
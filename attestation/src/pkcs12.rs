@@ -0,0 +1,594 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! This module assembles PKCS#12 (RFC 7292) bundles of a cert and its private
+//! key, for TLS terminators which only import keys in this format. The key is
+//! encrypted with PBES2 (PBKDF2 with HMAC-SHA256, AES-256-CBC) and the bundle
+//! is integrity protected with an HMAC-SHA256 MAC, the defaults of OpenSSL 3.
+//!
+//! Importers older than these defaults, such as Java before 8u301 and Windows
+//! before Server 2019, reject the SHA-256 MAC, usually claiming a wrong password.
+//! `build_pfx_with_mac` with `PfxMac::Sha1` makes bundles with the HMAC-SHA1 MAC
+//! of OpenSSL 1.1 for importers which only lack SHA-256 MACs. The key is still
+//! encrypted with PBES2, which the oldest of these importers cannot read either.
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockEncryptMut, KeyIvInit};
+use anyhow::Result;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+use std::num::NonZeroU32;
+use yasna::models::ObjectIdentifier;
+use yasna::Tag;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+/// Iterations of PBKDF2 and of the derivation of the MAC key.
+pub const PKCS12_ITERATIONS: u32 = 2048;
+
+const MAC_SALT_LEN: usize = 8;
+const ENCRYPTION_SALT_LEN: usize = 16;
+const AES_256_KEY_LEN: usize = 32;
+const AES_IV_LEN: usize = 16;
+
+const DATA_OID: &[u64] = &[1, 2, 840, 113549, 1, 7, 1];
+const PBES2_OID: &[u64] = &[1, 2, 840, 113549, 1, 5, 13];
+const PBKDF2_OID: &[u64] = &[1, 2, 840, 113549, 1, 5, 12];
+const HMAC_WITH_SHA256_OID: &[u64] = &[1, 2, 840, 113549, 2, 9];
+const AES_256_CBC_OID: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 1, 42];
+const SHA256_OID: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+const SHA1_OID: &[u64] = &[1, 3, 14, 3, 2, 26];
+const SHROUDED_KEY_BAG_OID: &[u64] = &[1, 2, 840, 113549, 1, 12, 10, 1, 2];
+const CERT_BAG_OID: &[u64] = &[1, 2, 840, 113549, 1, 12, 10, 1, 3];
+const X509_CERTIFICATE_OID: &[u64] = &[1, 2, 840, 113549, 1, 9, 22, 1];
+const LOCAL_KEY_ID_OID: &[u64] = &[1, 2, 840, 113549, 1, 9, 21];
+
+/// ID byte of the PKCS#12 key derivation for MAC keys (RFC 7292, appendix B.3).
+const KDF_MAC_ID: u8 = 3;
+
+/// Digest of the MAC of a bundle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PfxMac {
+    Sha256,
+    /// For importers which reject SHA-256 MACs, see the module docs.
+    Sha1,
+}
+
+impl Default for PfxMac {
+    fn default() -> Self {
+        PfxMac::Sha256
+    }
+}
+
+impl PfxMac {
+    fn digest(self) -> &'static digest::Algorithm {
+        match self {
+            PfxMac::Sha256 => &digest::SHA256,
+            PfxMac::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        }
+    }
+
+    fn output_len(self) -> usize {
+        match self {
+            PfxMac::Sha256 => digest::SHA256_OUTPUT_LEN,
+            PfxMac::Sha1 => digest::SHA1_OUTPUT_LEN,
+        }
+    }
+
+    fn hmac(self) -> hmac::Algorithm {
+        match self {
+            PfxMac::Sha256 => hmac::HMAC_SHA256,
+            PfxMac::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        }
+    }
+
+    fn oid(self) -> &'static [u64] {
+        match self {
+            PfxMac::Sha256 => SHA256_OID,
+            PfxMac::Sha1 => SHA1_OID,
+        }
+    }
+}
+
+/// Random inputs of a bundle.
+struct PfxSalts {
+    mac_salt: [u8; MAC_SALT_LEN],
+    encryption_salt: [u8; ENCRYPTION_SALT_LEN],
+    iv: [u8; AES_IV_LEN],
+}
+
+/// build_pfx bundles `cert_der` with the PKCS#8 private key `key_der` into a
+/// PKCS#12 file protected by `password`. The cert is stored in plain, as it is
+/// public anyway, and both bags carry the SHA-1 of the cert as localKeyId so
+/// that importers pair them.
+pub fn build_pfx(cert_der: &[u8], key_der: &[u8], password: &str) -> Result<Vec<u8>> {
+    build_pfx_with_mac(cert_der, key_der, password, PfxMac::default())
+}
+
+/// build_pfx_with_mac is `build_pfx` with the digest of the MAC chosen by `mac`.
+pub fn build_pfx_with_mac(
+    cert_der: &[u8],
+    key_der: &[u8],
+    password: &str,
+    mac: PfxMac,
+) -> Result<Vec<u8>> {
+    let rng = SystemRandom::new();
+    let mut salts = PfxSalts {
+        mac_salt: [0; MAC_SALT_LEN],
+        encryption_salt: [0; ENCRYPTION_SALT_LEN],
+        iv: [0; AES_IV_LEN],
+    };
+    for buf in [
+        &mut salts.mac_salt[..],
+        &mut salts.encryption_salt[..],
+        &mut salts.iv[..],
+    ] {
+        rng.fill(buf)
+            .map_err(|_| anyhow::anyhow!("Cannot generate PKCS#12 salts"))?;
+    }
+    Ok(assemble_pfx(cert_der, key_der, password, mac, &salts))
+}
+
+fn assemble_pfx(
+    cert_der: &[u8],
+    key_der: &[u8],
+    password: &str,
+    mac: PfxMac,
+    salts: &PfxSalts,
+) -> Vec<u8> {
+    let local_key_id = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, cert_der);
+
+    let cert_bag = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_oid(&oid(X509_CERTIFICATE_OID));
+            writer
+                .next()
+                .write_tagged(Tag::context(0), |writer| writer.write_bytes(cert_der));
+        });
+    });
+    let key_bag = encrypt_private_key(key_der, password, salts);
+    let safe_contents = |bag_id: &[u64], bag: &[u8]| {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_oid(&oid(bag_id));
+                    writer
+                        .next()
+                        .write_tagged(Tag::context(0), |writer| writer.write_der(bag));
+                    writer.next().write_set_of(|writer| {
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_oid(&oid(LOCAL_KEY_ID_OID));
+                            writer.next().write_set_of(|writer| {
+                                writer.next().write_bytes(local_key_id.as_ref())
+                            });
+                        });
+                    });
+                });
+            });
+        })
+    };
+    let auth_safe = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            for (bag_id, bag) in [(CERT_BAG_OID, &cert_bag), (SHROUDED_KEY_BAG_OID, &key_bag)] {
+                writer
+                    .next()
+                    .write_der(&data_content_info(&safe_contents(bag_id, bag)));
+            }
+        });
+    });
+
+    let mac_key = pkcs12_kdf(
+        mac.digest(),
+        password,
+        &salts.mac_salt,
+        KDF_MAC_ID,
+        PKCS12_ITERATIONS,
+        mac.output_len(),
+    );
+    let mac_algorithm = mac.oid();
+    let mac = hmac::sign(&hmac::Key::new(mac.hmac(), &mac_key), &auth_safe);
+
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_u8(3);
+            writer.next().write_der(&data_content_info(&auth_safe));
+            writer.next().write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_oid(&oid(mac_algorithm));
+                        writer.next().write_null();
+                    });
+                    writer.next().write_bytes(mac.as_ref());
+                });
+                writer.next().write_bytes(&salts.mac_salt);
+                writer.next().write_u32(PKCS12_ITERATIONS);
+            });
+        });
+    })
+}
+
+/// EncryptedPrivateKeyInfo of `key_der` with PBES2 (RFC 8018).
+fn encrypt_private_key(key_der: &[u8], password: &str, salts: &PfxSalts) -> Vec<u8> {
+    let mut key = [0u8; AES_256_KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PKCS12_ITERATIONS).unwrap(),
+        &salts.encryption_salt,
+        password.as_bytes(),
+        &mut key,
+    );
+    let encrypted =
+        Aes256CbcEnc::new(&key.into(), &salts.iv.into()).encrypt_padded_vec_mut::<Pkcs7>(key_der);
+
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_sequence(|writer| {
+                writer.next().write_oid(&oid(PBES2_OID));
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_oid(&oid(PBKDF2_OID));
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_bytes(&salts.encryption_salt);
+                            writer.next().write_u32(PKCS12_ITERATIONS);
+                            writer.next().write_sequence(|writer| {
+                                writer.next().write_oid(&oid(HMAC_WITH_SHA256_OID));
+                                writer.next().write_null();
+                            });
+                        });
+                    });
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_oid(&oid(AES_256_CBC_OID));
+                        writer.next().write_bytes(&salts.iv);
+                    });
+                });
+            });
+            writer.next().write_bytes(&encrypted);
+        });
+    })
+}
+
+/// ContentInfo of type data holding `content`.
+fn data_content_info(content: &[u8]) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_oid(&oid(DATA_OID));
+            writer
+                .next()
+                .write_tagged(Tag::context(0), |writer| writer.write_bytes(content));
+        });
+    })
+}
+
+fn oid(components: &[u64]) -> ObjectIdentifier {
+    ObjectIdentifier::from_slice(components)
+}
+
+/// Key derivation of PKCS#12 (RFC 7292, appendix B.2) with `algorithm`. The password is taken
+/// as a null-terminated BMPString.
+fn pkcs12_kdf(
+    algorithm: &'static digest::Algorithm,
+    password: &str,
+    salt: &[u8],
+    id: u8,
+    iterations: u32,
+    len: usize,
+) -> Vec<u8> {
+    // The block size of SHA-1 and SHA-256.
+    const V: usize = 64;
+
+    let mut bmp_password: Vec<u8> = password
+        .encode_utf16()
+        .flat_map(|c| c.to_be_bytes())
+        .collect();
+    bmp_password.extend_from_slice(&[0, 0]);
+    // Salt and password are each repeated to a multiple of the block size.
+    let fill = |data: &[u8]| -> Vec<u8> {
+        let len = V * ((data.len() + V - 1) / V);
+        data.iter().cycle().take(len).copied().collect()
+    };
+    let mut input = fill(salt);
+    input.extend(fill(&bmp_password));
+
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let mut context = digest::Context::new(algorithm);
+        context.update(&[id; V]);
+        context.update(&input);
+        let mut a = context.finish();
+        for _ in 1..iterations {
+            a = digest::digest(algorithm, a.as_ref());
+        }
+        output.extend_from_slice(a.as_ref());
+
+        // Each block of the input becomes (block + b + 1) mod 2^(8V) for the next round.
+        let b: Vec<u8> = a.as_ref().iter().cycle().take(V).copied().collect();
+        for block in input.chunks_mut(V) {
+            let mut carry = 1u16;
+            for (x, y) in block.iter_mut().zip(b.iter()).rev() {
+                let sum = *x as u16 + *y as u16 + carry;
+                *x = sum as u8;
+                carry = sum >> 8;
+            }
+        }
+    }
+    output.truncate(len);
+    output
+}
+
+#[cfg(all(feature = "enclave_unit_test", feature = "mesalock_sgx"))]
+pub mod tests {
+    use super::*;
+    use crate::key::{CertKeyUsage, NistP256KeyPair};
+    use crate::payload::PayloadProtection;
+    use aes::cipher::BlockDecryptMut;
+    use yasna::BERReader;
+
+    type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+    fn fixed_salts() -> PfxSalts {
+        let mut salts = PfxSalts {
+            mac_salt: [0; MAC_SALT_LEN],
+            encryption_salt: [0; ENCRYPTION_SALT_LEN],
+            iv: [0; AES_IV_LEN],
+        };
+        let bytes = salts
+            .mac_salt
+            .iter_mut()
+            .chain(salts.encryption_salt.iter_mut())
+            .chain(salts.iv.iter_mut());
+        for (i, byte) in bytes.enumerate() {
+            *byte = i as u8;
+        }
+        salts
+    }
+
+    fn read_algorithm(reader: BERReader) -> yasna::ASN1Result<ObjectIdentifier> {
+        reader.read_sequence(|reader| {
+            let algorithm = reader.next().read_oid()?;
+            reader.next().read_null()?;
+            Ok(algorithm)
+        })
+    }
+
+    /// Reads the bags of a bundle made by `build_pfx` back: checks the MAC with `password` and
+    /// decrypts the private key. Returns the cert and the key.
+    fn open_pfx(pfx: &[u8], password: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let parse_error = |e| anyhow::anyhow!("Malformed PKCS#12 bundle: {}", e);
+        let (auth_safe, algorithm, mac, mac_salt, iterations) = yasna::parse_der(pfx, |reader| {
+            reader.read_sequence(|reader| {
+                assert_eq!(reader.next().read_u8()?, 3);
+                let auth_safe = read_data_content_info(reader.next())?;
+                let (algorithm, mac, mac_salt, iterations) =
+                    reader.next().read_sequence(|reader| {
+                        let (algorithm, mac) = reader.next().read_sequence(|reader| {
+                            let algorithm = read_algorithm(reader.next())?;
+                            Ok((algorithm, reader.next().read_bytes()?))
+                        })?;
+                        let salt = reader.next().read_bytes()?;
+                        let iterations = reader.next().read_u32()?;
+                        Ok((algorithm, mac, salt, iterations))
+                    })?;
+                Ok((auth_safe, algorithm, mac, mac_salt, iterations))
+            })
+        })
+        .map_err(parse_error)?;
+
+        let mac_digest = [PfxMac::Sha256, PfxMac::Sha1]
+            .into_iter()
+            .find(|mac| oid(mac.oid()) == algorithm)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported MAC: {}", algorithm))?;
+        let mac_key = pkcs12_kdf(
+            mac_digest.digest(),
+            password,
+            &mac_salt,
+            KDF_MAC_ID,
+            iterations,
+            mac_digest.output_len(),
+        );
+        hmac::verify(
+            &hmac::Key::new(mac_digest.hmac(), &mac_key),
+            &auth_safe,
+            &mac,
+        )
+        .map_err(|_| anyhow::anyhow!("MAC verification failed"))?;
+
+        let contents = yasna::parse_der(&auth_safe, |reader| {
+            let mut contents = Vec::new();
+            reader.read_sequence_of(|reader| {
+                contents.push(read_data_content_info(reader)?);
+                Ok(())
+            })?;
+            Ok(contents)
+        })
+        .map_err(parse_error)?;
+        let mut cert = None;
+        let mut key = None;
+        for content in contents {
+            let (bag_id, bag) = yasna::parse_der(&content, |reader| {
+                reader.read_sequence(|reader| {
+                    reader.next().read_sequence(|reader| {
+                        let bag_id = reader.next().read_oid()?;
+                        let bag = reader
+                            .next()
+                            .read_tagged(Tag::context(0), |reader| reader.read_der())?;
+                        reader.next().read_der()?;
+                        Ok((bag_id, bag))
+                    })
+                })
+            })
+            .map_err(parse_error)?;
+            if bag_id == oid(CERT_BAG_OID) {
+                cert = Some(read_cert_bag(&bag).map_err(parse_error)?);
+            } else if bag_id == oid(SHROUDED_KEY_BAG_OID) {
+                key = Some(decrypt_key_bag(&bag, password)?);
+            }
+        }
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok((cert, key)),
+            _ => anyhow::bail!("Bundle lacks the cert or the key"),
+        }
+    }
+
+    fn read_data_content_info(reader: BERReader) -> yasna::ASN1Result<Vec<u8>> {
+        reader.read_sequence(|reader| {
+            assert_eq!(reader.next().read_oid()?, oid(DATA_OID));
+            reader
+                .next()
+                .read_tagged(Tag::context(0), |reader| reader.read_bytes())
+        })
+    }
+
+    fn read_cert_bag(bag: &[u8]) -> yasna::ASN1Result<Vec<u8>> {
+        yasna::parse_der(bag, |reader| {
+            reader.read_sequence(|reader| {
+                assert_eq!(reader.next().read_oid()?, oid(X509_CERTIFICATE_OID));
+                reader
+                    .next()
+                    .read_tagged(Tag::context(0), |reader| reader.read_bytes())
+            })
+        })
+    }
+
+    fn decrypt_key_bag(bag: &[u8], password: &str) -> Result<Vec<u8>> {
+        let (salt, iterations, iv, encrypted) = yasna::parse_der(bag, |reader| {
+            reader.read_sequence(|reader| {
+                let (salt, iterations, iv) = reader.next().read_sequence(|reader| {
+                    assert_eq!(reader.next().read_oid()?, oid(PBES2_OID));
+                    reader.next().read_sequence(|reader| {
+                        let (salt, iterations) = reader.next().read_sequence(|reader| {
+                            assert_eq!(reader.next().read_oid()?, oid(PBKDF2_OID));
+                            reader.next().read_sequence(|reader| {
+                                let salt = reader.next().read_bytes()?;
+                                let iterations = reader.next().read_u32()?;
+                                let prf = read_algorithm(reader.next())?;
+                                assert_eq!(prf, oid(HMAC_WITH_SHA256_OID));
+                                Ok((salt, iterations))
+                            })
+                        })?;
+                        let iv = reader.next().read_sequence(|reader| {
+                            assert_eq!(reader.next().read_oid()?, oid(AES_256_CBC_OID));
+                            reader.next().read_bytes()
+                        })?;
+                        Ok((salt, iterations, iv))
+                    })
+                })?;
+                Ok((salt, iterations, iv, reader.next().read_bytes()?))
+            })
+        })
+        .map_err(|e| anyhow::anyhow!("Malformed key bag: {}", e))?;
+
+        let mut key = [0u8; AES_256_KEY_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(iterations).unwrap(),
+            &salt,
+            password.as_bytes(),
+            &mut key,
+        );
+        Aes256CbcDec::new_from_slices(&key, &iv)
+            .map_err(|_| anyhow::anyhow!("Invalid IV"))?
+            .decrypt_padded_vec_mut::<Pkcs7>(&encrypted)
+            .map_err(|_| anyhow::anyhow!("Cannot decrypt the private key"))
+    }
+
+    pub fn test_pkcs12_kdf() {
+        // Generated with an independent implementation, checked by OpenSSL verifying MACs
+        // keyed with its output.
+        let salt = [0, 1, 2, 3, 4, 5, 6, 7];
+        let key = pkcs12_kdf(&digest::SHA256, "password", &salt, KDF_MAC_ID, 2048, 32);
+        assert_eq!(
+            hex::encode(key),
+            "e2b80cc8394bfab88d9a91767f2b8b148bd8db5152065e19c42c4037db2c34f5"
+        );
+        let key = pkcs12_kdf(&digest::SHA256, "", &salt, KDF_MAC_ID, 1, 32);
+        assert_eq!(
+            hex::encode(key),
+            "579feb5c17ff96a7025d45abe8a563be22351488bc218ccd53ddf3d60f4cd458"
+        );
+    }
+
+    pub fn test_pkcs12_known_answer() {
+        // The same bundle from the independent implementation imports into OpenSSL.
+        let pfx = assemble_pfx(
+            b"not a cert",
+            b"not a key",
+            "password",
+            PfxMac::Sha256,
+            &fixed_salts(),
+        );
+        assert_eq!(pfx.len(), 401);
+        assert_eq!(
+            hex::encode(digest::digest(&digest::SHA256, &pfx)),
+            "7cb819afd0848597a06f6ace453aa7798abac39b16c7050228d56cdf23a6c0d4"
+        );
+    }
+
+    pub fn test_pkcs12_round_trip() {
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let cert = key_pair
+            .create_cert_with_extension(
+                "Teaclave",
+                "CN=Teaclave",
+                b"payload",
+                &CertKeyUsage::default(),
+                &PayloadProtection::Plaintext,
+            )
+            .unwrap();
+        let pfx = key_pair.into_pkcs12(&cert, "pässwörd").unwrap();
+
+        let (opened_cert, opened_key) = open_pfx(&pfx, "pässwörd").unwrap();
+        assert_eq!(opened_cert, cert);
+        assert_eq!(opened_key, key_pair.private_key_into_der());
+
+        let error = open_pfx(&pfx, "password").unwrap_err();
+        assert_eq!(error.to_string(), "MAC verification failed");
+        // Salts and IV are fresh for every bundle.
+        assert_ne!(key_pair.into_pkcs12(&cert, "pässwörd").unwrap(), pfx);
+
+        let key_der = key_pair.private_key_into_der();
+        let pfx = build_pfx_with_mac(&cert, &key_der, "pässwörd", PfxMac::Sha1).unwrap();
+        assert_eq!(open_pfx(&pfx, "pässwörd").unwrap(), (cert, key_der));
+    }
+
+    pub fn test_pkcs12_openssl() {
+        // Bundles of a P-256 key and its self-signed cert made by OpenSSL 3.5:
+        //   openssl ecparam -name prime256v1 -genkey -noout -out key.pem
+        //   openssl req -new -x509 -key key.pem -subj "/CN=Teaclave" -days 3650 -out cert.pem
+        //   openssl pkcs12 -export -in cert.pem -inkey key.pem -passout pass:password \
+        //       -certpbe NONE -macalg sha256 -out openssl_sha256.p12
+        // and the same with `-macalg sha1` for openssl_sha1.p12. The digests are of
+        //   openssl x509 -in cert.pem -outform DER
+        //   openssl pkcs8 -topk8 -nocrypt -in key.pem -outform DER
+        let bundles: [&[u8]; 2] = [
+            include_bytes!("../../tests/fixtures/pkcs12/openssl_sha256.p12"),
+            include_bytes!("../../tests/fixtures/pkcs12/openssl_sha1.p12"),
+        ];
+        for pfx in bundles {
+            let (cert, key) = open_pfx(pfx, "password").unwrap();
+            assert_eq!(
+                hex::encode(digest::digest(&digest::SHA256, &cert)),
+                "a0e590404383ee8f4648eded52d7f346ba8cc0e9c6a61a91caccc6cc52ea94af"
+            );
+            assert_eq!(
+                hex::encode(digest::digest(&digest::SHA256, &key)),
+                "c2c5fb8160ab27b6484725fa27d1cb9ef8b2625bc1368d3c19246afa2380189e"
+            );
+            let error = open_pfx(pfx, "wrong password").unwrap_err();
+            assert_eq!(error.to_string(), "MAC verification failed");
+        }
+    }
+}
@@ -199,7 +199,7 @@ pub(crate) fn get_sgx_quote(ak_id: &AttKeyId, report: Report) -> Result<Vec<u8>>
     debug!("sgx sha256 slice");
     let rhs_hash = Sha256::digest(rhs_vec.as_slice()).map_err(PlatformError::Others)?;
     let lhs_hash = &qe_report.body.report_data.d[..32];
-    if rhs_hash.as_ref() != lhs_hash {
+    if !crate::ct_eq(rhs_hash.as_ref(), lhs_hash) {
         return Err(PlatformError::ReportReplay(
             rhs_hash.to_vec(),
             lhs_hash.to_vec(),
@@ -209,6 +209,17 @@ pub(crate) fn get_sgx_quote(ak_id: &AttKeyId, report: Report) -> Result<Vec<u8>>
     Ok(quote)
 }
 
+/// Whether this enclave was launched in debug mode, as set in the attributes of its report.
+/// The memory of a debug enclave can be read by the host.
+pub fn enclave_debug_mode() -> Result<bool> {
+    let report = Report::for_self().map_err(PlatformError::CreateReportError)?;
+    Ok(report
+        .body
+        .attributes
+        .flags
+        .contains(AttributesFlags::DEBUG))
+}
+
 #[cfg(all(feature = "enclave_unit_test", feature = "mesalock_sgx"))]
 pub mod tests {
     use super::*;
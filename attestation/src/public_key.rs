@@ -0,0 +1,292 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! This module reads the public key of an attested cert. Verifiers take the curve of the key
+//! from the algorithm parameters of its SubjectPublicKeyInfo rather than assuming P-256, so
+//! that certs with P-256 and P-384 enclave keys both verify.
+
+use crate::cert::{Asn1Ty, PubKey, TbsCert, X509};
+use anyhow::{anyhow, bail, ensure, Result};
+use ring::digest;
+use yasna::models::ObjectIdentifier;
+
+/// id-ecPublicKey, the algorithm of elliptic curve public keys (RFC 5480).
+pub const EC_PUBLIC_KEY_OID: &[u64] = &[1, 2, 840, 10045, 2, 1];
+/// prime256v1, a.k.a. NIST P-256.
+pub const PRIME256V1_OID: &[u64] = &[1, 2, 840, 10045, 3, 1, 7];
+/// secp384r1, a.k.a. NIST P-384.
+pub const SECP384R1_OID: &[u64] = &[1, 3, 132, 0, 34];
+
+/// Elliptic curve of an attested cert key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EcCurve {
+    P256,
+    P384,
+}
+
+impl EcCurve {
+    /// Identifies the curve named by the algorithm parameters of a public key.
+    pub fn from_oid(oid: &ObjectIdentifier) -> Result<Self> {
+        match oid.components().as_slice() {
+            PRIME256V1_OID => Ok(EcCurve::P256),
+            SECP384R1_OID => Ok(EcCurve::P384),
+            _ => bail!("Unsupported elliptic curve {}", oid_string(oid)),
+        }
+    }
+
+    pub fn oid(&self) -> ObjectIdentifier {
+        match self {
+            EcCurve::P256 => ObjectIdentifier::from_slice(PRIME256V1_OID),
+            EcCurve::P384 => ObjectIdentifier::from_slice(SECP384R1_OID),
+        }
+    }
+
+    /// Length of each coordinate of a point on the curve in bytes.
+    pub fn coordinate_len(&self) -> usize {
+        match self {
+            EcCurve::P256 => 32,
+            EcCurve::P384 => 48,
+        }
+    }
+}
+
+/// Public key of an attested cert: the coordinates x || y of the point, big-endian, without
+/// the 0x04 prefix of the uncompressed SEC1 encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CertPublicKey {
+    P256(Vec<u8>),
+    P384(Vec<u8>),
+}
+
+impl CertPublicKey {
+    /// Builds the key from the algorithm, the curve and the SEC1 encoded point of a
+    /// SubjectPublicKeyInfo. The length of the point must match the curve.
+    pub(crate) fn from_parts(
+        algorithm: &ObjectIdentifier,
+        curve: &ObjectIdentifier,
+        point: &[u8],
+    ) -> Result<Self> {
+        ensure!(
+            algorithm.components().as_slice() == EC_PUBLIC_KEY_OID,
+            "Unsupported public key algorithm {}",
+            oid_string(algorithm)
+        );
+        let curve = EcCurve::from_oid(curve)?;
+        // According to RFC 5480 `Elliptic Curve Cryptography Subject Public Key
+        // Information', SEC 2.2: ``The first octet of the OCTET STRING
+        // indicates whether the key is compressed or uncompressed. The
+        // uncompressed form is indicated by 0x04 and the compressed form is
+        // indicated by either 0x02 or 0x03 (see 2.3.3 in [SEC1]). The public
+        // key MUST be rejected if any other value is included in the first
+        // octet.''
+        //
+        // We only accept the uncompressed form here.
+        ensure!(
+            point.first() == Some(&4),
+            "Public key is not an uncompressed point"
+        );
+        let coordinates = &point[1..];
+        ensure!(
+            coordinates.len() == 2 * curve.coordinate_len(),
+            "Public key of {} bytes does not match curve {:?}",
+            coordinates.len(),
+            curve
+        );
+        Ok(match curve {
+            EcCurve::P256 => CertPublicKey::P256(coordinates.to_vec()),
+            EcCurve::P384 => CertPublicKey::P384(coordinates.to_vec()),
+        })
+    }
+
+    pub fn curve(&self) -> EcCurve {
+        match self {
+            CertPublicKey::P256(_) => EcCurve::P256,
+            CertPublicKey::P384(_) => EcCurve::P384,
+        }
+    }
+
+    pub fn coordinates(&self) -> &[u8] {
+        match self {
+            CertPublicKey::P256(coordinates) | CertPublicKey::P384(coordinates) => coordinates,
+        }
+    }
+
+    /// Checks a DER encoded ECDSA `signature` of `message` made with this key, over SHA-256
+    /// for P-256 and SHA-384 for P-384 keys.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ECDSA_P384_SHA384_ASN1};
+
+        let algorithm = match self {
+            CertPublicKey::P256(_) => &ECDSA_P256_SHA256_ASN1,
+            CertPublicKey::P384(_) => &ECDSA_P384_SHA384_ASN1,
+        };
+        let mut point = vec![4];
+        point.extend_from_slice(self.coordinates());
+        UnparsedPublicKey::new(algorithm, point)
+            .verify(message, signature)
+            .map_err(|_| anyhow!("Invalid signature"))
+    }
+
+    /// The 64 bytes of report data which bind the key to the enclave quote: the coordinates
+    /// of a P-256 key as they are, and the SHA-512 digest of the coordinates of a P-384 key,
+    /// which do not fit.
+    pub fn report_data(&self) -> Vec<u8> {
+        match self {
+            CertPublicKey::P256(coordinates) => coordinates.clone(),
+            CertPublicKey::P384(coordinates) => digest::digest(&digest::SHA512, coordinates)
+                .as_ref()
+                .to_vec(),
+        }
+    }
+}
+
+/// Parses a DER encoded SubjectPublicKeyInfo.
+pub fn parse_spki(der: &[u8]) -> Result<CertPublicKey> {
+    let spki = yasna::parse_der(der, PubKey::load)
+        .map_err(|e| anyhow!("Invalid SubjectPublicKeyInfo: {}", e))?;
+    from_spki(spki)
+}
+
+/// Parses the SubjectPublicKeyInfo of a DER encoded attested cert.
+pub fn parse_cert_public_key(cert: &[u8]) -> Result<CertPublicKey> {
+    let x509 =
+        yasna::parse_der(cert, X509::load).map_err(|e| anyhow!("Invalid attested cert: {}", e))?;
+    let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+    from_spki(((((((tbs_cert.1).1).1).1).1).1).0)
+}
+
+fn from_spki(spki: <PubKey as Asn1Ty>::ValueTy) -> Result<CertPublicKey> {
+    let ((algorithm, (curve, ())), (point, ())) = spki;
+    CertPublicKey::from_parts(&algorithm, &curve, &point.to_bytes())
+}
+
+pub(crate) fn oid_string(oid: &ObjectIdentifier) -> String {
+    oid.components()
+        .iter()
+        .map(|component| component.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(all(feature = "enclave_unit_test", feature = "mesalock_sgx"))]
+pub mod tests {
+    use super::*;
+    use crate::key::{CertBuilder, NistP256KeyPair};
+    use bit_vec::BitVec;
+    use ring::rand::SystemRandom;
+    use ring::signature::{self, EcdsaKeyPair, KeyPair};
+
+    fn spki_der(curve: &[u64], point: &[u8]) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(EC_PUBLIC_KEY_OID));
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(curve));
+                });
+                writer.next().write_bitvec(&BitVec::from_bytes(point));
+            })
+        })
+    }
+
+    fn generate_point(algorithm: &'static signature::EcdsaSigningAlgorithm) -> Vec<u8> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(algorithm, &rng).unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(algorithm, pkcs8.as_ref()).unwrap();
+        key_pair.public_key().as_ref().to_vec()
+    }
+
+    pub fn test_parse_p256_spki() {
+        let point = generate_point(&signature::ECDSA_P256_SHA256_ASN1_SIGNING);
+        let key = parse_spki(&spki_der(PRIME256V1_OID, &point)).unwrap();
+        assert_eq!(key.curve(), EcCurve::P256);
+        assert_eq!(key.coordinates(), &point[1..]);
+        assert_eq!(key.report_data(), &point[1..]);
+
+        // The key of an attested cert is read the same way.
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let cert = CertBuilder::new(&key_pair).build().unwrap();
+        let key = parse_cert_public_key(&cert).unwrap();
+        assert_eq!(key.curve(), EcCurve::P256);
+        assert_eq!(key.coordinates().len(), 64);
+    }
+
+    pub fn test_parse_p384_spki() {
+        let point = generate_point(&signature::ECDSA_P384_SHA384_ASN1_SIGNING);
+        assert_eq!(point.len(), 97);
+        let key = parse_spki(&spki_der(SECP384R1_OID, &point)).unwrap();
+        assert_eq!(key.curve(), EcCurve::P384);
+        assert_eq!(key.coordinates(), &point[1..]);
+        assert_eq!(key.report_data().len(), 64);
+    }
+
+    pub fn test_verify_signature() {
+        let key_pair = NistP256KeyPair::new().unwrap();
+        let cert = CertBuilder::new(&key_pair)
+            .attestation_payload(b"{}")
+            .build()
+            .unwrap();
+        let config = crate::AttestedTlsConfig {
+            cert,
+            private_key: key_pair.private_key_into_der(),
+            time: std::time::SystemTime::now(),
+            validity: std::time::Duration::from_secs(60),
+        };
+
+        let signature = config.sign(b"manifest").unwrap();
+        let public_key = parse_cert_public_key(&config.cert).unwrap();
+        assert!(public_key.verify(b"manifest", &signature).is_ok());
+        assert!(public_key.verify(b"manifesT", &signature).is_err());
+
+        let other = NistP256KeyPair::new().unwrap();
+        let other_cert = CertBuilder::new(&other).build().unwrap();
+        let err = parse_cert_public_key(&other_cert)
+            .unwrap()
+            .verify(b"manifest", &signature)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid signature");
+    }
+
+    pub fn test_parse_spki_errors() {
+        let p256_point = generate_point(&signature::ECDSA_P256_SHA256_ASN1_SIGNING);
+        let p384_point = generate_point(&signature::ECDSA_P384_SHA384_ASN1_SIGNING);
+
+        let secp521r1 = &[1, 3, 132, 0, 35];
+        let err = parse_spki(&spki_der(secp521r1, &p384_point)).unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported elliptic curve 1.3.132.0.35");
+
+        // Coordinates are never reinterpreted to fit another curve.
+        let err = parse_spki(&spki_der(SECP384R1_OID, &p256_point)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Public key of 64 bytes does not match curve P384"
+        );
+        let err = parse_spki(&spki_der(PRIME256V1_OID, &p384_point)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Public key of 96 bytes does not match curve P256"
+        );
+
+        let mut compressed = p256_point[..33].to_vec();
+        compressed[0] = 2;
+        let err = parse_spki(&spki_der(PRIME256V1_OID, &compressed)).unwrap_err();
+        assert_eq!(err.to_string(), "Public key is not an uncompressed point");
+    }
+}
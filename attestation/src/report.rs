@@ -19,6 +19,8 @@
 //! The implementation is based on Attestation Service API version 4.
 //! https://api.trustedservices.intel.com/documents/sgx-attestation-api-spec.pdf
 
+use crate::payload::{open_payload, reassemble_payload};
+use crate::public_key::CertPublicKey;
 use crate::AttestationError;
 use crate::EndorsedAttestationReport;
 
@@ -32,8 +34,13 @@ use std::untrusted::time::SystemTimeEx;
 use anyhow::{anyhow, bail, ensure, Error, Result};
 use chrono::DateTime;
 use serde_json::Value;
+use teaclave_crypto::RecipientPrivateKey;
 use uuid::Uuid;
 
+/// OID of the Netscape comment extension, which carries the endorsed attestation report in
+/// attested certs.
+pub const ATTESTATION_REPORT_OID: &[u64] = &[2, 16, 840, 1, 113_730, 1, 13];
+
 type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
     &webpki::ECDSA_P256_SHA256,
@@ -49,6 +56,25 @@ static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
     &webpki::RSA_PKCS1_3072_8192_SHA384,
 ];
 
+/// Returns the (OID, value) of all extensions of `cert_der` whose OID is one of `oids`, in
+/// the order of the cert, e.g. both the EPID and the DCAP payload of a cert made for
+/// verifiers of either kind. Extensions with other OIDs are skipped.
+pub fn attestation_extensions(
+    cert_der: &[u8],
+    oids: &[&[u64]],
+) -> Result<Vec<(Vec<u64>, Vec<u8>)>> {
+    use crate::cert::*;
+
+    let x509 = yasna::parse_der(cert_der, X509::load)?;
+    let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
+    let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
+    Ok(cert_ext
+        .into_iter()
+        .filter(|(oid, _, _)| oids.contains(&oid.components().as_slice()))
+        .map(|(oid, _, value)| (oid.components().clone(), value))
+        .collect())
+}
+
 /// A report generated by an enclave that contains measurement, identity and
 /// other data related to enclave.
 ///
@@ -504,18 +530,48 @@ impl fmt::Display for AttestationReport {
 impl AttestationReport {
     /// Construct a AttestationReport from a X509 certificate and verify
     /// attestation report with the report_ca_cert which is from the attestation
-    /// service provider.
+    /// service provider. Certs whose report is sealed to a verifier need
+    /// `from_cert_with_verifier_key`.
     pub fn from_cert(certs: &[rustls::Certificate], report_ca_cert: &[u8]) -> Result<Self> {
+        Self::from_cert_with(certs, report_ca_cert, None)
+    }
+
+    /// Like `from_cert`, for certs whose attestation report was sealed to the verifier holding
+    /// `verifier_key`.
+    pub fn from_cert_with_verifier_key(
+        certs: &[rustls::Certificate],
+        report_ca_cert: &[u8],
+        verifier_key: &RecipientPrivateKey,
+    ) -> Result<Self> {
+        Self::from_cert_with(certs, report_ca_cert, Some(verifier_key))
+    }
+
+    fn from_cert_with(
+        certs: &[rustls::Certificate],
+        report_ca_cert: &[u8],
+        verifier_key: Option<&RecipientPrivateKey>,
+    ) -> Result<Self> {
         // Before we reach here, Webpki already verifed the cert is properly signed.
         use crate::cert::*;
+        use yasna::models::ObjectIdentifier;
 
         // Extract information for attestation from TLS certification.
         let x509 = yasna::parse_der(&certs[0].0, X509::load)?;
         let tbs_cert: <TbsCert as Asn1Ty>::ValueTy = x509.0;
         let pub_key: <PubKey as Asn1Ty>::ValueTy = ((((((tbs_cert.1).1).1).1).1).1).0;
-        let pub_k = (pub_key.1).0;
+        let ((key_algorithm, (key_curve, ())), (key_point, ())) = pub_key;
         let cert_ext: <SgxRaCertExt as Asn1Ty>::ValueTy = (((((((tbs_cert.1).1).1).1).1).1).1).0;
-        let cert_ext_payload: Vec<u8> = ((cert_ext.0).1).0;
+        // The report is carried in the Netscape comment extension, or split across several of
+        // them, next to optional key usage extensions and attestation payloads for other
+        // verifiers, which are skipped.
+        let comment_oid = ObjectIdentifier::from_slice(ATTESTATION_REPORT_OID);
+        let cert_ext_payloads: Vec<Vec<u8>> = cert_ext
+            .into_iter()
+            .filter(|(oid, _, _)| oid == &comment_oid)
+            .map(|(_, _, payload)| payload)
+            .collect();
+        let cert_ext_payload = reassemble_payload(&cert_ext_payloads)?;
+        let cert_ext_payload = open_payload(&cert_ext_payload, verifier_key)?;
         log::debug!("cert_ext_payload: {:?}", &cert_ext_payload);
 
         // Convert to endorsed report
@@ -601,19 +657,13 @@ impl AttestationReport {
             SgxQuote::parse_from(quote_raw.as_slice())?
         };
 
-        // According to RFC 5480 `Elliptic Curve Cryptography Subject Public Key
-        // Information', SEC 2.2: ``The first octet of the OCTET STRING
-        // indicates whether the key is compressed or uncompressed. The
-        // uncompressed form is indicated by 0x04 and the compressed form is
-        // indicated by either 0x02 or 0x03 (see 2.3.3 in [SEC1]). The public
-        // key MUST be rejected if any other value is included in the first
-        // octet.''
-        //
-        // We only accept the uncompressed form here.
-        let raw_pub_k = pub_k.to_bytes();
-        let is_uncompressed = raw_pub_k[0] == 4;
-        let pub_k = &raw_pub_k.as_slice()[1..];
-        if !is_uncompressed || pub_k != &sgx_quote_body.isv_enclave_report.report_data[..] {
+        // The curve of the key is taken from its algorithm parameters, and the key must be bound
+        // to the quote through the report data.
+        let pub_k = CertPublicKey::from_parts(&key_algorithm, &key_curve, &key_point.to_bytes())?;
+        if !crate::ct_eq(
+            &pub_k.report_data(),
+            &sgx_quote_body.isv_enclave_report.report_data[..],
+        ) {
             bail!(AttestationError::ReportError);
         }
 
@@ -22,6 +22,7 @@ use crate::report::AttestationReport;
 use std::vec::Vec;
 
 use log::{debug, error};
+use teaclave_crypto::RecipientPrivateKey;
 use teaclave_types::EnclaveAttr;
 
 /// User defined verification function to further verify the attestation report.
@@ -38,6 +39,9 @@ pub struct AttestationReportVerifier {
     pub root_ca: Vec<u8>,
     /// User defined function to verify the attestation report.
     pub verifier: AttestationReportVerificationFn,
+    /// Key which opens attestation reports sealed to this verifier. Certs with sealed reports
+    /// are rejected without it.
+    pub verifier_key: Option<RecipientPrivateKey>,
 }
 
 /// Checks if he quote's status is not `UnknownBadStatus`
@@ -56,9 +60,17 @@ impl AttestationReportVerifier {
             accepted_enclave_attrs,
             root_ca: root_ca.to_vec(),
             verifier,
+            verifier_key: None,
         }
     }
 
+    /// Accepts certs whose attestation report is sealed to the public key of `verifier_key`,
+    /// see `PayloadProtection::Verifier`, next to certs with a plaintext report.
+    pub fn with_verifier_key(mut self, verifier_key: RecipientPrivateKey) -> Self {
+        self.verifier_key = Some(verifier_key);
+        self
+    }
+
     /// Verify whether the `MR_SIGNER` and `MR_ENCLAVE` in the attestation report is
     /// accepted by us, which are defined in `accepted_enclave_attrs`.
     fn verify_measures(&self, attestation_report: &AttestationReport) -> bool {
@@ -84,7 +96,11 @@ impl AttestationReportVerifier {
             return true;
         }
 
-        let report = match AttestationReport::from_cert(certs, &self.root_ca) {
+        let report = match &self.verifier_key {
+            Some(key) => AttestationReport::from_cert_with_verifier_key(certs, &self.root_ca, key),
+            None => AttestationReport::from_cert(certs, &self.root_ca),
+        };
+        let report = match report {
             Ok(report) => report,
             Err(e) => {
                 error!("cert verification error {:?}", e);
@@ -26,7 +26,8 @@ use std::sync::Arc;
 use structopt::StructOpt;
 use teaclave_attestation::report::AttestationReport;
 
-use teaclave_crypto::{AesGcm128Key, AesGcm256Key, TeaclaveFile128Key};
+use ring::agreement;
+use teaclave_crypto::{AesGcm128Key, AesGcm256Key, RecipientPrivateKey, TeaclaveFile128Key};
 
 const FILE_AUTH_TAG_LENGTH: usize = 16;
 type CMac = [u8; FILE_AUTH_TAG_LENGTH];
@@ -88,6 +89,18 @@ struct AttestOpt {
     /// CA cert of attestation service for verifying the attestation report
     #[structopt(short = "c", long)]
     as_ca_cert: PathBuf,
+
+    /// Path of the verifier key made with gen-verifier-key, to read attestation reports which
+    /// the service sealed to its public key
+    #[structopt(long = "verifier-key")]
+    verifier_key: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct GenerateVerifierKeyOpt {
+    /// Path to store the private key at, in the hex format
+    #[structopt(short, long = "output-file")]
+    output_file: PathBuf,
 }
 
 #[derive(Debug, StructOpt)]
@@ -107,6 +120,11 @@ enum Command {
     /// Display the attestation report of remote Teaclave services
     #[structopt(name = "attest")]
     Attest(AttestOpt),
+
+    /// Generate a P-256 key for services to seal their attestation reports to, and print its
+    /// public key
+    #[structopt(name = "gen-verifier-key")]
+    GenerateVerifierKey(GenerateVerifierKeyOpt),
 }
 
 #[derive(Debug, StructOpt)]
@@ -203,17 +221,23 @@ fn verify(opt: VerifyOpt) -> Result<bool> {
 
 struct TeaclaveServerCertVerifier {
     pub root_ca: Vec<u8>,
+    pub verifier_key: Option<RecipientPrivateKey>,
 }
 
 impl TeaclaveServerCertVerifier {
-    pub fn new(root_ca: &[u8]) -> Self {
+    pub fn new(root_ca: &[u8], verifier_key: Option<RecipientPrivateKey>) -> Self {
         Self {
             root_ca: root_ca.to_vec(),
+            verifier_key,
         }
     }
 
     fn display_attestation_report(&self, certs: &[rustls::Certificate]) -> bool {
-        match AttestationReport::from_cert(certs, &self.root_ca) {
+        let report = match &self.verifier_key {
+            Some(key) => AttestationReport::from_cert_with_verifier_key(certs, &self.root_ca, key),
+            None => AttestationReport::from_cert(certs, &self.root_ca),
+        };
+        match report {
             Ok(report) => println!("{}", report),
             Err(e) => println!("{:?}", e),
         }
@@ -250,7 +274,17 @@ fn attest(opt: AttestOpt) -> Result<()> {
     let hostname = webpki::DNSNameRef::try_from_ascii_str(hostname)?;
     let content = fs::read(opt.as_ca_cert)?;
     let pem = pem::parse(content)?;
-    let verifier = Arc::new(TeaclaveServerCertVerifier::new(&pem.contents));
+    let verifier_key = match opt.verifier_key {
+        Some(path) => {
+            let bytes = hex::decode(fs::read_to_string(path)?.trim())?;
+            Some(RecipientPrivateKey::from_bytes(
+                &agreement::ECDH_P256,
+                &bytes,
+            )?)
+        }
+        None => None,
+    };
+    let verifier = Arc::new(TeaclaveServerCertVerifier::new(&pem.contents, verifier_key));
     let mut config = rustls::ClientConfig::new();
     config.dangerous().set_certificate_verifier(verifier);
     config.versions.clear();
@@ -265,6 +299,13 @@ fn attest(opt: AttestOpt) -> Result<()> {
     Ok(())
 }
 
+fn generate_verifier_key(opt: GenerateVerifierKeyOpt) -> Result<()> {
+    let key = RecipientPrivateKey::generate(&agreement::ECDH_P256)?;
+    fs::write(opt.output_file, hex::encode(key.as_bytes()))?;
+    println!("{}", hex::encode(key.public_key()?));
+    Ok(())
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let args = Opt::from_args();
@@ -293,6 +334,7 @@ fn main() -> Result<()> {
             }
         },
         Command::Attest(opt) => attest(opt)?,
+        Command::GenerateVerifierKey(opt) => generate_verifier_key(opt)?,
     };
 
     Ok(())
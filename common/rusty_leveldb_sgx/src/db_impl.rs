@@ -165,6 +165,7 @@ impl DB {
 
         let _ = self.opt.env.mkdir(Path::new(&self.path));
         self.acquire_lock()?;
+        self.opt.env.replay_journal(Path::new(&self.path))?;
 
         if let Err(e) = read_current_file(&self.opt.env, &self.path) {
             if e.code == StatusCode::NotFound && self.opt.create_if_missing {
@@ -350,6 +351,12 @@ impl DB {
                     FileType::Current | FileType::DBLock | FileType::InfoLog => continue,
                 }
 
+                // A file still locked by someone else is left for a later cleanup.
+                if self.opt.env.is_locked(&self.path.join(&name)) {
+                    log!(self.opt.log, "Not deleting locked file num={}", num);
+                    continue;
+                }
+
                 // If we're here, delete this file.
                 if typ == FileType::Table {
                     let _ = self.cache.borrow_mut().evict(num);
@@ -1,28 +1,108 @@
-use crate::env::{path_to_str, Env, FileLock, Logger, RandomAccess};
-use crate::env_common::micros;
+use crate::env::{
+    path_to_str, path_to_string, Access, Env, FileLock, Logger, LoggerOptions, RandomAccess,
+};
+use crate::env_common::{Clock, SystemClock};
 use crate::error::{err, Result, Status, StatusCode};
+use crate::io_stats::{IoOp, IoRecorder, IoStats, TimedFile};
+use crate::io_timeout::{IoTimeout, TimeoutFile, IO_POOL_THREADS};
+use crate::read_cache::{CachedFile, ReadCache, ReadCacheStats, ReadaheadFile};
+use crate::types::{parse_file_name, FileType};
 
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::io::{Seek, SeekFrom};
 use std::iter::FromIterator;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 use std::untrusted::fs;
 use std::untrusted::path::PathEx;
 
+use sgx_tprotected_fs::SgxFile;
+
 pub type DBPersistKey = [u8; 16];
 
+/// Prefix of the HKDF info of tenant keys, so they never collide with keys derived from the same
+/// master key for other uses.
+const TENANT_KEY_INFO_PREFIX: &[u8] = b"teaclave-leveldb-tenant-key";
+
 const F_RDLCK: libc::c_short = 0;
 const F_WRLCK: libc::c_short = 1;
 const F_UNLCK: libc::c_short = 2;
 
+/// Held locks are marked on disk by writing this prefix followed by the owner into the lock file.
+const LOCK_SENTINEL_PREFIX: &[u8] = b"teaclave-lock:";
+
 type FileDescriptor = i32;
 
+/// TenantKeyInfo names the keyspace a key derived from the master key is used for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TenantKeyInfo {
+    pub tenant_id: String,
+    pub purpose: String,
+}
+
+impl TenantKeyInfo {
+    pub fn new<T: Into<String>, P: Into<String>>(tenant_id: T, purpose: P) -> TenantKeyInfo {
+        TenantKeyInfo {
+            tenant_id: tenant_id.into(),
+            purpose: purpose.into(),
+        }
+    }
+
+    /// encode returns the HKDF info. Every field is length-prefixed so that no two distinct infos
+    /// encode the same.
+    fn encode(&self) -> Vec<u8> {
+        let mut info = TENANT_KEY_INFO_PREFIX.to_vec();
+        for field in &[&self.tenant_id, &self.purpose] {
+            info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            info.extend_from_slice(field.as_bytes());
+        }
+        info
+    }
+}
+
+struct PersistKeyLen;
+
+impl ring::hkdf::KeyType for PersistKeyLen {
+    fn len(&self) -> usize {
+        std::mem::size_of::<DBPersistKey>()
+    }
+}
+
+/// derive_tenant_key derives the file key of a tenant from the master key with HKDF-SHA256. The
+/// salt is specific to a deployment, so deployments sharing a master key get disjoint keyspaces.
+pub fn derive_tenant_key(
+    master_key: &DBPersistKey,
+    salt: &[u8],
+    info: &TenantKeyInfo,
+) -> DBPersistKey {
+    let info = info.encode();
+    let info = [info.as_slice()];
+    let mut key = DBPersistKey::default();
+    ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, salt)
+        .extract(master_key)
+        .expand(&info, PersistKeyLen)
+        .and_then(|okm| okm.fill(&mut key))
+        .expect("HKDF output length is valid");
+    key
+}
+
+type OwnerPredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
 #[derive(Clone)]
 pub struct PosixDiskEnv {
     locks: Arc<Mutex<HashMap<String, sgx_tprotected_fs::SgxFile>>>,
     key: DBPersistKey,
+    owner: String,
+    live_owner: Option<OwnerPredicate>,
+    clock: Arc<dyn Clock>,
+    shut_down: Arc<AtomicBool>,
+    io_stats: Option<Arc<Mutex<IoStats>>>,
+    io_timeout: Option<IoTimeout>,
+    read_cache: Option<Arc<ReadCache>>,
+    root: Option<PathBuf>,
 }
 
 impl PosixDiskEnv {
@@ -30,10 +110,465 @@ impl PosixDiskEnv {
         PosixDiskEnv {
             locks: Arc::new(Mutex::new(HashMap::new())),
             key,
+            owner: format!("{:016x}", rand::random::<u64>()),
+            live_owner: None,
+            clock: Arc::new(SystemClock),
+            shut_down: Arc::new(AtomicBool::new(false)),
+            io_stats: None,
+            io_timeout: None,
+            read_cache: None,
+            root: None,
+        }
+    }
+
+    /// new_for_tenant creates an env whose files are encrypted with the key of the tenant described
+    /// by `info`, derived from `master_key` and the deployment's `salt`.
+    pub fn new_for_tenant(
+        master_key: &DBPersistKey,
+        salt: &[u8],
+        info: &TenantKeyInfo,
+    ) -> PosixDiskEnv {
+        PosixDiskEnv::new_with(derive_tenant_key(master_key, salt, info))
+    }
+
+    /// with_clock sets the clock used by `micros` and for file ages. By default the env uses the
+    /// real clock.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> PosixDiskEnv {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// with_owner sets the owner recorded in the sentinels of locks taken by this env. By default
+    /// every env gets a random owner.
+    pub fn with_owner<S: Into<String>>(mut self, owner: S) -> PosixDiskEnv {
+        self.owner = owner.into();
+        self
+    }
+
+    /// with_live_owner_predicate sets the predicate deciding whether the owner of an on-disk lock
+    /// is still alive. By default only locks owned by this env's owner are considered live.
+    pub fn with_live_owner_predicate<F>(mut self, f: F) -> PosixDiskEnv
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.live_owner = Some(Arc::new(f));
+        self
+    }
+
+    /// with_io_stats turns the latency histograms returned by `io_stats` on or off. Stats are off
+    /// by default; an env without stats doesn't time its operations at all. Turning stats on
+    /// starts from empty histograms.
+    pub fn with_io_stats(mut self, enabled: bool) -> PosixDiskEnv {
+        self.io_stats = if enabled {
+            Some(Arc::new(Mutex::new(IoStats::default())))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// io_stats returns the latency histograms of the opens, reads, writes and renames done
+    /// through this env and its clones so far, or None if stats are off.
+    pub fn io_stats(&self) -> Option<IoStats> {
+        self.io_stats.as_ref().map(|s| s.lock().unwrap().clone())
+    }
+
+    /// with_io_timeout abandons opens, reads and writes which take longer than `timeout` with
+    /// `StatusCode::TimedOut`, instead of letting a stalled protected FS block the calling thread
+    /// forever. By default operations have no timeout. The operations of this env and its clones
+    /// then run on a pool of `IO_POOL_THREADS` threads, and a file with an abandoned operation
+    /// fails all further ones; see `io_timeout` for what an abandoned write leaves behind.
+    pub fn with_io_timeout(mut self, timeout: Duration) -> PosixDiskEnv {
+        self.io_timeout = Some(IoTimeout::new(timeout, IO_POOL_THREADS));
+        self
+    }
+
+    /// with_read_cache keeps the most recently read blocks of the random access files of this env
+    /// and its clones, up to `capacity` bytes, so that reads of them skip the protected FS. Files
+    /// drop out of the cache when they are written, deleted or renamed. By default there is no
+    /// read cache.
+    pub fn with_read_cache(mut self, capacity: usize) -> PosixDiskEnv {
+        self.read_cache = Some(Arc::new(ReadCache::new(capacity)));
+        self
+    }
+
+    /// with_root confines the env to `root`: every path passed to it is resolved once, following
+    /// `..` and symlinks, operations on paths outside of `root` fail with
+    /// `StatusCode::InvalidArgument`, and the others operate on the resolved path, so that a
+    /// symlink swapped in after the check is not followed. Relative paths are still relative to
+    /// the working directory. By default paths are used as they are.
+    pub fn with_root<P: Into<PathBuf>>(mut self, root: P) -> PosixDiskEnv {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// read_cache_stats returns how many blocks the read cache served and missed so far, or None
+    /// if there is no read cache.
+    pub fn read_cache_stats(&self) -> Option<ReadCacheStats> {
+        self.read_cache.as_ref().map(|c| c.stats())
+    }
+
+    /// invalidate_cached drops the cached blocks of `p`, if any.
+    fn invalidate_cached(&self, p: &Path) {
+        if let Some(cache) = &self.read_cache {
+            cache.invalidate(p);
+        }
+    }
+
+    /// open_uncached opens the file at the confined path `p` for random access past the read
+    /// cache.
+    fn open_uncached(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        let f = self.timed(IoOp::Open, || {
+            self.open_with("open_sgx (randomaccess)", p, |p, key| {
+                sgx_tprotected_fs::OpenOptions::default()
+                    .read(true)
+                    .open_with_key(p, key)
+            })
+        })?;
+        Ok(self.timed_random_access(f))
+    }
+
+    /// open_with runs `open` under the io timeout, if any.
+    fn open_with<F>(&self, method: &'static str, p: &Path, open: F) -> Result<SgxFile>
+    where
+        F: FnOnce(&Path, DBPersistKey) -> io::Result<SgxFile> + Send + 'static,
+    {
+        let key = self.key;
+        let result = match &self.io_timeout {
+            Some(timeout) => {
+                let path = p.to_path_buf();
+                timeout
+                    .run(method, move || open(&path, key))
+                    .map_err(|s| s.annotate(path_to_str(p)))?
+            }
+            None => open(p, key),
+        };
+        result.map_err(|e| map_err_with_name(method, p, e))
+    }
+
+    /// check_parent_exists fails with NotFound naming the parent directory of `p` if it is
+    /// missing. The protected FS reports this case only as an opaque open error. Only the opens
+    /// which create files check this; reads of such paths fail or return as they always did.
+    fn check_parent_exists(&self, method: &'static str, p: &Path) -> Result<()> {
+        match p.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !parent.exists() => err(
+                StatusCode::NotFound,
+                &format!(
+                    "{}: parent directory does not exist: {}",
+                    method,
+                    path_to_str(parent)
+                ),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn io_recorder(&self) -> Option<IoRecorder> {
+        self.io_stats
+            .as_ref()
+            .map(|s| IoRecorder::new(s.clone(), self.clock.clone()))
+    }
+
+    /// timed runs `f`, recording its duration under `op` if stats are on.
+    fn timed<T, F: FnOnce() -> T>(&self, op: IoOp, f: F) -> T {
+        match self.io_recorder() {
+            Some(recorder) => recorder.time(op, f),
+            None => f(),
+        }
+    }
+
+    fn timed_reader<F: Read + Send + 'static>(&self, f: F) -> Box<dyn Read> {
+        if let Some(timeout) = &self.io_timeout {
+            let f = TimeoutFile::new(f, timeout.clone());
+            return match self.io_recorder() {
+                Some(recorder) => Box::new(TimedFile::new(f, recorder)),
+                None => Box::new(f),
+            };
+        }
+        match self.io_recorder() {
+            Some(recorder) => Box::new(TimedFile::new(f, recorder)),
+            None => Box::new(f),
+        }
+    }
+
+    fn timed_writer<F: Write + Send + 'static>(&self, f: F) -> Box<dyn Write> {
+        if let Some(timeout) = &self.io_timeout {
+            let f = TimeoutFile::new(f, timeout.clone());
+            return match self.io_recorder() {
+                Some(recorder) => Box::new(TimedFile::new(f, recorder)),
+                None => Box::new(f),
+            };
+        }
+        match self.io_recorder() {
+            Some(recorder) => Box::new(TimedFile::new(f, recorder)),
+            None => Box::new(f),
+        }
+    }
+
+    fn timed_random_access<F: RandomAccess + Send + 'static>(&self, f: F) -> Box<dyn RandomAccess> {
+        if let Some(timeout) = &self.io_timeout {
+            let f = TimeoutFile::new(f, timeout.clone());
+            return match self.io_recorder() {
+                Some(recorder) => Box::new(TimedFile::new(f, recorder)),
+                None => Box::new(f),
+            };
+        }
+        match self.io_recorder() {
+            Some(recorder) => Box::new(TimedFile::new(f, recorder)),
+            None => Box::new(f),
+        }
+    }
+
+    /// shutdown releases all locks held by this env and its clones, flushing their files and
+    /// clearing their sentinels, and drops the read cache. Every lock is released even if some
+    /// fail; the first error is returned. Afterwards all operations of the env fail.
+    pub fn shutdown(&self) -> Result<()> {
+        let held: Vec<(String, sgx_tprotected_fs::SgxFile)> = {
+            let mut locks = self.locks.lock().unwrap();
+            self.shut_down.store(true, Ordering::SeqCst);
+            locks.drain().collect()
+        };
+        if let Some(cache) = &self.read_cache {
+            cache.clear();
+        }
+
+        let mut first_error = None;
+        for (id, mut f) in held {
+            let p = Path::new(&id);
+            let flushed = f
+                .flush()
+                .map_err(|e| map_err_with_name("shutdown (flush)", p, e));
+            drop(f);
+            let result = flushed.and_then(|_| self.clear_lock_sentinel(p));
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
         }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return err(StatusCode::IOError, "env is shut down");
+        }
+        Ok(())
+    }
+
+    /// confine returns the path to operate on for `p`: `p` itself if the env has no root, and
+    /// otherwise `p` resolved, failing with InvalidArgument if it resolves to a path outside of
+    /// the root. Callers must use the returned path, and not resolve `p` again.
+    fn confine(&self, method: &'static str, p: &Path) -> Result<PathBuf> {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return Ok(p.to_path_buf()),
+        };
+        // Paths which can't be resolved are rejected, like escaping ones.
+        match (resolve_path(root), resolve_path(p)) {
+            (Some(root), Some(resolved)) if resolved.starts_with(root) => Ok(resolved),
+            _ => err(
+                StatusCode::InvalidArgument,
+                &format!(
+                    "{}: path escapes the root {}: {}",
+                    method,
+                    path_to_str(root),
+                    path_to_str(p)
+                ),
+            ),
+        }
+    }
+
+    /// clear_lock_sentinel truncates the lock file `p`, so that the lock doesn't look held after
+    /// a restart.
+    fn clear_lock_sentinel(&self, p: &Path) -> Result<()> {
+        sgx_tprotected_fs::OpenOptions::default()
+            .write(true)
+            .append(false)
+            .open_with_key(p, self.key)
+            .map_err(|e| map_err_with_name("clear_lock_sentinel", p, e))?;
+        Ok(())
+    }
+
+    /// reconcile_locks rebuilds the lock map from the lock sentinels found below `root`, e.g.
+    /// after a crash. Sentinels of dead owners are cleared; the ids of locks with live owners are
+    /// adopted into the lock map and returned.
+    pub fn reconcile_locks(&self, root: &Path) -> Result<Vec<String>> {
+        let mut locks = self.locks.lock().unwrap();
+        let mut adopted = vec![];
+
+        let root = &self.confine("reconcile_locks", root)?;
+        for child in self.children_recursive(root)? {
+            // Lock ids are confined paths, like those of `lock`.
+            let p = self.confine("reconcile_locks", &root.join(&child))?;
+            let id = path_to_string(&p);
+            if locks.contains_key(&id) {
+                continue;
+            }
+            let owner = match self.read_lock_sentinel(&p)? {
+                Some(owner) => owner,
+                None => continue,
+            };
+
+            let live = match &self.live_owner {
+                Some(f) => f(&owner),
+                None => owner == self.owner,
+            };
+            if live {
+                let f = sgx_tprotected_fs::OpenOptions::default()
+                    .append(true)
+                    .open_with_key(&p, self.key)
+                    .map_err(|e| map_err_with_name("reconcile_locks (adopt)", &p, e))?;
+                locks.insert(id.clone(), f);
+                adopted.push(id);
+            } else {
+                self.open_writable_file(&p)?;
+            }
+        }
+
+        adopted.sort();
+        Ok(adopted)
+    }
+
+    /// remove_stale_temp_files deletes the temp files (`*.dbtmp`) in `dir` which were last
+    /// modified more than `max_age` microseconds ago, e.g. left behind by a crash while writing
+    /// CURRENT. Returns the removed files, relative to `dir`, in sorted order.
+    pub fn remove_stale_temp_files(&self, dir: &Path, max_age: u64) -> Result<Vec<PathBuf>> {
+        let now = self.clock.micros();
+        let mut removed = vec![];
+        for child in self.children(dir)? {
+            match parse_file_name(&child) {
+                Ok((_, FileType::Temp)) => {}
+                _ => continue,
+            }
+            let p = dir.join(&child);
+            if now.saturating_sub(self.modified_micros(&p)?) > max_age {
+                self.delete(&p)?;
+                removed.push(child);
+            }
+        }
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// rmdir_report removes `p` like `rmdir`, but keeps going when an entry can't be removed and
+    /// returns the entries which remained with the reason, e.g. a lock held by this env. `p`
+    /// itself is only removed if everything below it was.
+    pub fn rmdir_report(&self, p: &Path) -> Result<Vec<(PathBuf, Status)>> {
+        let p = &self.confine("rmdir", p)?;
+        let mut failures = vec![];
+        for child in self.children(p)? {
+            self.remove_recursive(&p.join(child), &mut failures);
+        }
+        if failures.is_empty() {
+            if let Err(e) = fs::remove_dir(p) {
+                failures.push((p.to_owned(), map_err_with_name("rmdir", p, e)));
+            }
+        }
+        Ok(failures)
+    }
+
+    /// remove_recursive removes the file or directory `p`, recording every entry that can't be
+    /// removed in `failures`.
+    fn remove_recursive(&self, p: &Path, failures: &mut Vec<(PathBuf, Status)>) {
+        let p = &match self.confine("rmdir", p) {
+            Ok(p) => p,
+            Err(e) => {
+                failures.push((p.to_owned(), e));
+                return;
+            }
+        };
+        let failed_before = failures.len();
+        let result = if p.is_dir() {
+            match self.children(p) {
+                Ok(children) => {
+                    for child in children {
+                        self.remove_recursive(&p.join(child), failures);
+                    }
+                    if failures.len() > failed_before {
+                        return;
+                    }
+                    fs::remove_dir(p).map_err(|e| map_err_with_name("rmdir", p, e))
+                }
+                Err(e) => Err(e),
+            }
+        } else if self.is_locked(p) {
+            err(StatusCode::LockError, "Lock is held")
+        } else {
+            fs::remove_file(p).map_err(|e| map_err_with_name("rmdir", p, e))
+        };
+        if let Err(e) = result {
+            failures.push((p.to_owned(), e));
+        }
+    }
+
+    /// modified_micros returns the last modification time of `p` in microseconds since the epoch.
+    fn modified_micros(&self, p: &Path) -> Result<u64> {
+        let p = &self.confine("modified", p)?;
+        let modified = fs::metadata(p)
+            .and_then(|m| m.modified())
+            .map_err(|e| map_err_with_name("modified", p, e))?;
+        let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(since_epoch.as_micros() as u64)
+    }
+
+    /// read_lock_sentinel returns the owner recorded in `p`, or None if `p` isn't a held lock.
+    fn read_lock_sentinel(&self, p: &Path) -> Result<Option<String>> {
+        let mut f = self.open_sequential_file(p)?;
+        let mut prefix = vec![0u8; LOCK_SENTINEL_PREFIX.len()];
+        if f.read_exact(&mut prefix).is_err() || prefix != LOCK_SENTINEL_PREFIX {
+            return Ok(None);
+        }
+        let mut owner = vec![];
+        f.read_to_end(&mut owner)?;
+        Ok(String::from_utf8(owner).ok())
+    }
+
+    /// children_recursive lists all files below `p`, relative to `p`.
+    fn children_recursive(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for child in self.children(p)? {
+            let full = p.join(&child);
+            if full.is_dir() {
+                for grandchild in self.children_recursive(&full)? {
+                    files.push(child.join(grandchild));
+                }
+            } else {
+                files.push(child);
+            }
+        }
+        Ok(files)
     }
 }
 
+/// resolve_path returns the absolute form of `p` without `.`, `..` or symlinks, or None if not
+/// even the working directory can be canonicalized. The longest existing prefix of `p` is
+/// canonicalized; the rest, which doesn't exist yet, is resolved lexically.
+fn resolve_path(p: &Path) -> Option<PathBuf> {
+    let components: Vec<Component> = p.components().collect();
+    for existing in (0..=components.len()).rev() {
+        let prefix: PathBuf = components[..existing].iter().collect();
+        let prefix = if prefix.as_os_str().is_empty() {
+            Path::new(".").to_owned()
+        } else {
+            prefix
+        };
+        let mut resolved = match prefix.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(_) => continue,
+        };
+        for component in &components[existing..] {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => resolved.push(name),
+                _ => {}
+            }
+        }
+        return Some(resolved);
+    }
+    None
+}
+
 /// map_err_with_name annotates an io::Error with information about the operation and the file.
 fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
     let mut s = Status::from(e);
@@ -45,44 +580,92 @@ fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
 // error conversion using std::convert::From.
 impl Env for PosixDiskEnv {
     fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
-        Ok(Box::new(
-            sgx_tprotected_fs::OpenOptions::default()
-                .read(true)
-                .open_with_key(p, self.key)
-                .map_err(|e| map_err_with_name("open_sgx (seq)", p, e))?,
-        ))
+        self.check_open()?;
+        let p = &self.confine("open_sgx (seq)", p)?;
+        let f = self.timed(IoOp::Open, || {
+            self.open_with("open_sgx (seq)", p, |p, key| {
+                sgx_tprotected_fs::OpenOptions::default()
+                    .read(true)
+                    .open_with_key(p, key)
+            })
+        })?;
+        Ok(self.timed_reader(f))
     }
     fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
-        Ok(sgx_tprotected_fs::OpenOptions::default()
-            .read(true)
-            .open_with_key(p, self.key)
-            .map(|f| {
-                let b: Box<dyn RandomAccess> = Box::new(f);
-                b
-            })
-            .map_err(|e| map_err_with_name("open_sgx (randomaccess)", p, e))?)
+        self.check_open()?;
+        let p = &self.confine("open_sgx (randomaccess)", p)?;
+        let f = self.open_uncached(p)?;
+        match &self.read_cache {
+            Some(cache) => Ok(Box::new(CachedFile::new(f, p, cache.clone()))),
+            None => Ok(f),
+        }
+    }
+    /// Sequential files are read ahead in large windows past the read cache, so that compactions
+    /// don't evict the blocks of point lookups. WillNeed prefetches the whole file into the read
+    /// cache, and DontNeed drops the cached blocks of the file and reads past the cache.
+    fn open_random_access_file_with_advice(
+        &self,
+        p: &Path,
+        advice: Access,
+    ) -> Result<Box<dyn RandomAccess>> {
+        match advice {
+            Access::Random => self.open_random_access_file(p),
+            Access::WillNeed => {
+                if self.read_cache.is_some() {
+                    self.prefetch(&[p.to_path_buf()], None)?;
+                }
+                self.open_random_access_file(p)
+            }
+            Access::Sequential => {
+                self.check_open()?;
+                let p = &self.confine("open_sgx (randomaccess)", p)?;
+                Ok(Box::new(ReadaheadFile::new(self.open_uncached(p)?)))
+            }
+            Access::DontNeed => {
+                self.check_open()?;
+                let p = &self.confine("open_sgx (randomaccess)", p)?;
+                self.invalidate_cached(p);
+                self.open_uncached(p)
+            }
+        }
     }
     fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
-        Ok(Box::new(
-            sgx_tprotected_fs::OpenOptions::default()
-                .write(true)
-                .append(false)
-                .open_with_key(p, self.key)
-                .map_err(|e| map_err_with_name("open_sgx (write)", p, e))?,
-        ))
+        self.check_open()?;
+        let p = &self.confine("open_sgx (write)", p)?;
+        self.check_parent_exists("open_sgx (write)", p)?;
+        self.invalidate_cached(p);
+        let f = self.timed(IoOp::Open, || {
+            self.open_with("open_sgx (write)", p, |p, key| {
+                sgx_tprotected_fs::OpenOptions::default()
+                    .write(true)
+                    .append(false)
+                    .open_with_key(p, key)
+            })
+        })?;
+        Ok(self.timed_writer(f))
     }
     fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
-        Ok(Box::new(
-            sgx_tprotected_fs::OpenOptions::default()
-                .append(true)
-                .open_with_key(p, self.key)
-                .map_err(|e| map_err_with_name("open_sgx (append_sgx)", p, e))?,
-        ))
+        self.check_open()?;
+        let p = &self.confine("open_sgx (append_sgx)", p)?;
+        self.check_parent_exists("open_sgx (append_sgx)", p)?;
+        self.invalidate_cached(p);
+        let f = self.timed(IoOp::Open, || {
+            self.open_with("open_sgx (append_sgx)", p, |p, key| {
+                sgx_tprotected_fs::OpenOptions::default()
+                    .append(true)
+                    .open_with_key(p, key)
+            })
+        })?;
+        Ok(self.timed_writer(f))
     }
     fn exists(&self, p: &Path) -> Result<bool> {
+        self.check_open()?;
+        let p = &self.confine("exists", p)?;
         Ok(p.exists())
     }
     fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.check_open()?;
+        let p = &self.confine("children", p)?;
         let dir_reader = fs::read_dir(p).map_err(|e| map_err_with_name("children", p, e))?;
         let filenames = dir_reader
             .map(|r| {
@@ -98,6 +681,8 @@ impl Env for PosixDiskEnv {
     }
 
     fn size_of(&self, p: &Path) -> Result<usize> {
+        self.check_open()?;
+        let p = &self.confine("size_of", p)?;
         let mut f = sgx_tprotected_fs::OpenOptions::default()
             .read(true)
             .open_with_key(p, self.key)
@@ -105,17 +690,44 @@ impl Env for PosixDiskEnv {
         let size = f.seek(SeekFrom::End(0))?;
         Ok(size as usize)
     }
+    fn snapshot_sizes(&self, root: &Path) -> Result<HashMap<PathBuf, usize>> {
+        self.check_open()?;
+        let root = &self.confine("snapshot_sizes", root)?;
+        // Writers don't go through the lock map, so holding it wouldn't keep them out. Files
+        // written during the enumeration may be missing or have a stale size.
+        let mut sizes = HashMap::new();
+        for child in self.children_recursive(root)? {
+            let size = self.size_of(&root.join(&child))?;
+            sizes.insert(child, size);
+        }
+        Ok(sizes)
+    }
 
     fn delete(&self, p: &Path) -> Result<()> {
+        self.check_open()?;
+        let p = &self.confine("delete", p)?;
+        self.invalidate_cached(p);
         Ok(fs::remove_file(p).map_err(|e| map_err_with_name("delete", p, e))?)
     }
     fn mkdir(&self, p: &Path) -> Result<()> {
+        self.check_open()?;
+        let p = &self.confine("mkdir", p)?;
         Ok(fs::create_dir_all(p).map_err(|e| map_err_with_name("mkdir", p, e))?)
     }
     fn rmdir(&self, p: &Path) -> Result<()> {
+        self.check_open()?;
+        let p = &self.confine("rmdir", p)?;
+        if let Some(cache) = &self.read_cache {
+            cache.clear();
+        }
         Ok(fs::remove_dir_all(p).map_err(|e| map_err_with_name("rmdir", p, e))?)
     }
     fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.check_open()?;
+        let old = &self.confine("rename", old)?;
+        let new = &self.confine("rename", new)?;
+        self.invalidate_cached(old);
+        self.invalidate_cached(new);
         let old_name = old
             .file_name()
             .map(|f| f.to_str())
@@ -135,28 +747,37 @@ impl Env for PosixDiskEnv {
                 io::Error::from_raw_os_error(21),
             ))?;
 
-        {
-            let mut f = sgx_tprotected_fs::OpenOptions::default()
-                .append(true)
-                .open_with_key(old, self.key)
-                .map_err(|e| map_err_with_name("rename (open)", old, e))?;
-            f.rename(old_name, new_name)?;
-        }
+        self.timed(IoOp::Rename, || {
+            {
+                let mut f = sgx_tprotected_fs::OpenOptions::default()
+                    .append(true)
+                    .open_with_key(old, self.key)
+                    .map_err(|e| map_err_with_name("rename (open)", old, e))?;
+                f.rename(old_name, new_name)?;
+            }
 
-        Ok(fs::rename(old, new).map_err(|e| map_err_with_name("rename", old, e))?)
+            Ok(fs::rename(old, new).map_err(|e| map_err_with_name("rename", old, e))?)
+        })
     }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         let mut locks = self.locks.lock().unwrap();
+        // Checked under the lock map so that no lock can be taken while shutdown drains it.
+        self.check_open()?;
+        let p = &self.confine("lock_sgx", p)?;
 
         if locks.contains_key(&p.to_str().unwrap().to_string()) {
             Err(Status::new(StatusCode::AlreadyExists, "Lock is held"))
         } else {
-            let f = sgx_tprotected_fs::OpenOptions::default()
+            let mut f = sgx_tprotected_fs::OpenOptions::default()
                 .write(true)
                 .append(false)
                 .open_with_key(p, self.key)
                 .map_err(|e| map_err_with_name("lock_sgx: ", p, e))?;
+            f.write_all(LOCK_SENTINEL_PREFIX)
+                .and_then(|_| f.write_all(self.owner.as_bytes()))
+                .and_then(|_| f.flush())
+                .map_err(|e| map_err_with_name("lock_sgx (sentinel)", p, e))?;
 
             locks.insert(p.to_str().unwrap().to_string(), f);
             let lock = FileLock {
@@ -166,6 +787,9 @@ impl Env for PosixDiskEnv {
         }
     }
     fn unlock(&self, l: FileLock) -> Result<()> {
+        self.check_open()?;
+        // Ids of locks taken by `lock` are confined already, so this only rejects forged ones.
+        self.confine("unlock_sgx", Path::new(&l.id))?;
         let mut locks = self.locks.lock().unwrap();
         if !locks.contains_key(&l.id) {
             return err(
@@ -174,30 +798,63 @@ impl Env for PosixDiskEnv {
             );
         } else {
             locks.remove(&l.id).unwrap();
-            Ok(())
+            self.clear_lock_sentinel(Path::new(&l.id))
         }
     }
+    /// is_locked only reflects the locks held by this env and its clones within this process.
+    /// Locks of other processes, and sentinels left by a crash which `reconcile_locks` has not
+    /// adopted, are not seen.
+    fn is_locked(&self, p: &Path) -> bool {
+        let p = match self.confine("is_locked", p) {
+            Ok(p) => p,
+            Err(_) => return false,
+        };
+        let locks = self.locks.lock().unwrap();
+        p.to_str().map_or(false, |p| locks.contains_key(p))
+    }
 
-    fn new_logger(&self, p: &Path) -> Result<Logger> {
+    fn new_logger(&self, p: &Path, options: LoggerOptions) -> Result<Logger> {
         self.open_appendable_file(p)
-            .map(|dst| Logger::new(Box::new(dst)))
+            .map(|dst| Logger::with_options(Box::new(dst), options, self.clock.clone()))
     }
 
     fn micros(&self) -> u64 {
-        micros()
+        self.clock.micros()
     }
 }
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use crate::io_stats::LatencyHistogram;
+    use crate::read_cache::{READAHEAD_SIZE, READ_CACHE_BLOCK_SIZE};
     use std::convert::AsRef;
     use std::io::Write;
     use std::iter::FromIterator;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_files, test_locking, test_dirs,)
+        run_tests!(
+            test_files,
+            test_locking,
+            test_is_locked,
+            test_dirs,
+            test_missing_parent_dir,
+            test_snapshot_sizes,
+            test_health_check,
+            test_reconcile_locks,
+            test_remove_stale_temp_files,
+            test_rmdir_report,
+            test_tenant_key_salt,
+            test_shutdown,
+            test_io_stats,
+            test_io_timeout,
+            test_read_cache_prefetch,
+            test_open_with_advice,
+            test_append_batch,
+            test_logger_options,
+            test_root_confinement,
+        )
     }
 
     fn test_files() {
@@ -269,6 +926,22 @@ pub mod tests {
         assert!(env.delete(name).is_ok());
     }
 
+    fn test_is_locked() {
+        let env = PosixDiskEnv::new_with([0u8; 16]);
+        let locked = Path::new("is_locked.123");
+        let unlocked = Path::new("is_locked.456");
+
+        let lock = env.lock(locked).unwrap();
+        assert!(env.is_locked(locked));
+        assert!(!env.is_locked(unlocked));
+        // Clones share the lock map.
+        assert!(env.clone().is_locked(locked));
+
+        env.unlock(lock).unwrap();
+        assert!(!env.is_locked(locked));
+        assert!(env.delete(locked).is_ok());
+    }
+
     fn test_dirs() {
         let d = "subdir/";
         let dirname = d.as_ref();
@@ -283,4 +956,632 @@ pub mod tests {
         assert_eq!(env.children(dirname).unwrap().len(), 1);
         assert!(env.rmdir(dirname).is_ok());
     }
+
+    fn test_missing_parent_dir() {
+        let env = PosixDiskEnv::new_with([0u8; 16]);
+        let name = Path::new("no_such_dir/f1.txt");
+
+        for result in [env.open_writable_file(name), env.open_appendable_file(name)] {
+            let e = result.err().unwrap();
+            assert_eq!(e.code, StatusCode::NotFound);
+            assert!(e
+                .err
+                .ends_with("parent directory does not exist: no_such_dir"));
+        }
+        assert!(!env.exists(Path::new("no_such_dir")).unwrap());
+
+        // Read-only calls on such paths are unaffected.
+        assert!(!env.exists(name).unwrap());
+        let e = env.open_sequential_file(name).err().unwrap();
+        assert!(!e.err.contains("parent directory does not exist"));
+        assert!(env.size_of(name).is_err());
+    }
+
+    fn test_snapshot_sizes() {
+        let d = "snapshot_dir/";
+        let dirname: &Path = d.as_ref();
+        let env = PosixDiskEnv::new_with([0u8; 16]);
+
+        assert!(env.mkdir(&dirname.join("sub")).is_ok());
+        for (name, contents) in &[("a.ldb", "123"), ("b.log", "123456"), ("sub/c.ldb", "")] {
+            let mut f = env.open_writable_file(&dirname.join(name)).unwrap();
+            let _ = f.write(contents.as_bytes());
+        }
+
+        // Nothing is written meanwhile, so the best-effort sizes are exact here.
+        let sizes = env.snapshot_sizes(dirname).unwrap();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes.get(Path::new("a.ldb")), Some(&3));
+        assert_eq!(sizes.get(Path::new("b.log")), Some(&6));
+        assert_eq!(sizes.get(Path::new("sub/c.ldb")), Some(&0));
+        assert!(env.snapshot_sizes(Path::new("no_such_dir/")).is_err());
+
+        assert!(env.rmdir(dirname).is_ok());
+    }
+
+    fn test_health_check() {
+        let d = "health_check_dir/";
+        let dirname: &Path = d.as_ref();
+        let env = PosixDiskEnv::new_with([0u8; 16]);
+
+        assert!(env.mkdir(dirname).is_ok());
+        assert!(env.health_check(dirname).is_ok());
+        // The sentinel is gone again.
+        assert!(env.children(dirname).unwrap().is_empty());
+
+        // A location which cannot be written to fails the check.
+        let missing = Path::new("no_such_dir/");
+        assert_eq!(
+            env.health_check(missing).unwrap_err().code,
+            StatusCode::NotFound
+        );
+        let file = dirname.join("file");
+        env.open_writable_file(&file).unwrap();
+        assert!(env.health_check(&file).is_err());
+        assert!(env.delete(&file).is_ok());
+
+        // The database at the root is readable with the key of the env, but not with another.
+        let current = dirname.join("CURRENT");
+        let mut f = env.open_writable_file(&current).unwrap();
+        f.write_all(b"MANIFEST-000001\n").unwrap();
+        drop(f);
+        assert!(env.health_check(dirname).is_ok());
+        let wrong_key = PosixDiskEnv::new_with([1u8; 16]);
+        assert!(wrong_key.health_check(dirname).is_err());
+        assert_eq!(
+            env.children(dirname).unwrap(),
+            vec![PathBuf::from("CURRENT")]
+        );
+
+        assert!(env.delete(&current).is_ok());
+        assert!(env.rmdir(dirname).is_ok());
+    }
+
+    fn test_reconcile_locks() {
+        let d = "reconcile_dir/";
+        let dirname: &Path = d.as_ref();
+        let (stale, live) = (dirname.join("stale.LOCK"), dirname.join("live.LOCK"));
+        let env = PosixDiskEnv::new_with([0u8; 16])
+            .with_owner("restarted")
+            .with_live_owner_predicate(|owner| owner == "alive");
+        assert!(env.mkdir(dirname).is_ok());
+
+        {
+            // Take both locks and "crash" without unlocking.
+            let crashed = PosixDiskEnv::new_with([0u8; 16]).with_owner("crashed");
+            crashed.lock(&stale).unwrap();
+            let alive = PosixDiskEnv::new_with([0u8; 16]).with_owner("alive");
+            alive.lock(&live).unwrap();
+        }
+        {
+            let mut f = env.open_writable_file(&dirname.join("000001.ldb")).unwrap();
+            let _ = f.write("not a lock".as_bytes());
+        }
+
+        let adopted = env.reconcile_locks(dirname).unwrap();
+        assert_eq!(adopted, vec![path_to_string(&live)]);
+
+        // The stale sentinel is cleared and its lock can be taken again; the live one stays held.
+        assert_eq!(env.size_of(&stale).unwrap(), 0);
+        assert!(env.lock(&stale).is_ok());
+        assert!(env.lock(&live).is_err());
+        assert!(env.reconcile_locks(dirname).unwrap().is_empty());
+
+        assert!(env.rmdir(dirname).is_ok());
+    }
+
+    #[derive(Clone)]
+    struct FakeClock(Arc<std::sync::atomic::AtomicU64>);
+
+    impl FakeClock {
+        fn advance(&self, micros: u64) {
+            self.0
+                .fetch_add(micros, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn micros(&self) -> u64 {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    fn test_remove_stale_temp_files() {
+        const HOUR: u64 = 3_600_000_000;
+        let d = "temp_cleanup_dir/";
+        let dirname: &Path = d.as_ref();
+        let clock = FakeClock(Arc::new(Default::default()));
+        clock.advance(crate::env_common::micros());
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_clock(clock.clone());
+        assert!(env.mkdir(dirname).is_ok());
+
+        for name in &["000003.dbtmp", "000005.dbtmp", "000004.ldb", "CURRENT"] {
+            env.open_writable_file(&dirname.join(name)).unwrap();
+        }
+        assert_eq!(env.micros(), clock.micros());
+
+        // Nothing is old enough yet.
+        clock.advance(HOUR / 2);
+        assert!(env
+            .remove_stale_temp_files(dirname, HOUR)
+            .unwrap()
+            .is_empty());
+
+        // Two hours later only the temp files go; a fresh one stays.
+        clock.advance(2 * HOUR);
+        let removed = env.remove_stale_temp_files(dirname, HOUR).unwrap();
+        assert_eq!(
+            removed,
+            vec![PathBuf::from("000003.dbtmp"), PathBuf::from("000005.dbtmp")]
+        );
+        let mut children = env.children(dirname).unwrap();
+        children.sort();
+        assert_eq!(
+            children,
+            vec![PathBuf::from("000004.ldb"), PathBuf::from("CURRENT")]
+        );
+
+        assert!(env.rmdir(dirname).is_ok());
+    }
+
+    fn test_logger_options() {
+        let name: &Path = "logger_test.log".as_ref();
+        let clock = FakeClock(Arc::new(Default::default()));
+        clock.advance(1_700_000_000_000_042);
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_clock(clock.clone());
+        let read = || {
+            let mut content = String::new();
+            env.open_sequential_file(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+
+        {
+            let mut logger = env.new_logger(name, LoggerOptions::default()).unwrap();
+            logger.log("raw line");
+        }
+        assert_eq!(read(), "raw line\n");
+
+        {
+            let options = LoggerOptions {
+                timestamp: true,
+                level: true,
+            };
+            let mut logger = env.new_logger(name, options).unwrap();
+            logger.log("opened");
+            clock.advance(1_000_000);
+            logger.log_at(crate::env::LogLevel::Warn, "slow compaction");
+        }
+        assert_eq!(
+            read(),
+            "raw line\n\
+             1700000000.000042 [INFO] opened\n\
+             1700000001.000042 [WARN] slow compaction\n"
+        );
+
+        {
+            let options = LoggerOptions {
+                timestamp: true,
+                level: false,
+            };
+            env.new_logger(name, options).unwrap().log("no level");
+        }
+        assert!(read().ends_with("\n1700000001.000042 no level\n"));
+
+        assert!(env.delete(name).is_ok());
+    }
+
+    fn test_rmdir_report() {
+        let env = PosixDiskEnv::new_with([0u8; 16]);
+        let dirname: &Path = "rmdir_report_dir".as_ref();
+        let lockname = dirname.join("LOCK");
+        assert!(env.mkdir(&dirname.join("sub")).is_ok());
+        for name in &["000001.ldb", "LOCK", "sub/000002.ldb"] {
+            env.open_writable_file(&dirname.join(name)).unwrap();
+        }
+        let lock = env.lock(&lockname).unwrap();
+
+        let failures = env.rmdir_report(dirname).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, lockname);
+        assert_eq!(failures[0].1.code, StatusCode::LockError);
+        // Everything else was removed, only the locked file and its directory remain.
+        assert_eq!(env.children(dirname).unwrap(), vec![PathBuf::from("LOCK")]);
+
+        env.unlock(lock).unwrap();
+        assert!(env.rmdir_report(dirname).unwrap().is_empty());
+        assert!(!env.exists(dirname).unwrap());
+        assert!(env.rmdir_report(dirname).is_err());
+    }
+
+    fn test_tenant_key_salt() {
+        let master_key = [7u8; 16];
+        let info = TenantKeyInfo::new("tenant-a", "storage");
+        let name = Path::new("tenant_key_salt.xyz");
+        let cluster_a = PosixDiskEnv::new_for_tenant(&master_key, b"cluster-a", &info);
+        let cluster_a_again = PosixDiskEnv::new_for_tenant(&master_key, b"cluster-a", &info);
+        let cluster_b = PosixDiskEnv::new_for_tenant(&master_key, b"cluster-b", &info);
+
+        {
+            let mut f = cluster_a.open_writable_file(name).unwrap();
+            f.write_all(b"tenant data").unwrap();
+        }
+
+        let mut contents = vec![];
+        cluster_a_again
+            .open_sequential_file(name)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"tenant data");
+        assert!(cluster_b.open_sequential_file(name).is_err());
+
+        // Another purpose of the same tenant is a different keyspace as well.
+        assert_ne!(
+            derive_tenant_key(&master_key, b"cluster-a", &info),
+            derive_tenant_key(
+                &master_key,
+                b"cluster-a",
+                &TenantKeyInfo::new("tenant-a", "logs")
+            )
+        );
+        assert_ne!(
+            TenantKeyInfo::new("ab", "c").encode(),
+            TenantKeyInfo::new("a", "bc").encode()
+        );
+
+        cluster_a.delete(name).unwrap();
+    }
+
+    fn test_shutdown() {
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_owner("shutdown");
+        let clone = env.clone();
+        let names = [Path::new("shutdown_lock.1"), Path::new("shutdown_lock.2")];
+        env.lock(names[0]).unwrap();
+        clone.lock(names[1]).unwrap();
+
+        assert!(env.shutdown().is_ok());
+        assert!(env.locks.lock().unwrap().is_empty());
+
+        // Operations of the env and its clones fail from now on.
+        let e = clone.lock(Path::new("shutdown_lock.3")).err().unwrap();
+        assert_eq!(e.code, StatusCode::IOError);
+        assert_eq!(e.err, "env is shut down");
+        assert!(env.open_sequential_file(names[0]).is_err());
+        assert!(env.shutdown().is_ok());
+
+        // The locks were released: another env can take them and finds no sentinel.
+        let other = PosixDiskEnv::new_with([0u8; 16]);
+        for name in &names {
+            assert_eq!(other.read_lock_sentinel(name).unwrap(), None);
+            let lock = other.lock(name).unwrap();
+            other.unlock(lock).unwrap();
+            other.delete(name).unwrap();
+        }
+    }
+
+    fn test_read_cache_prefetch() {
+        let name = Path::new("read_cache.ldb");
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_read_cache(1 << 20);
+        let data: Vec<u8> = (0..3 * READ_CACHE_BLOCK_SIZE).map(|i| i as u8).collect();
+        {
+            let mut f = env.open_writable_file(name).unwrap();
+            f.write_all(&data).unwrap();
+            f.flush().unwrap();
+        }
+
+        let off = 2 * READ_CACHE_BLOCK_SIZE + 10;
+        env.prefetch(&[name.to_path_buf()], Some(vec![(off as u64, 100)]))
+            .unwrap();
+        let stats = ReadCacheStats { hits: 0, misses: 1 };
+        assert_eq!(env.read_cache_stats(), Some(stats));
+
+        // The prefetched range is served from the cache, by a clone as well.
+        let mut buf = [0u8; 50];
+        let f = env.clone().open_random_access_file(name).unwrap();
+        f.read_exact_at(off + 20, &mut buf).unwrap();
+        assert_eq!(&buf[..], &data[off + 20..off + 70]);
+        let stats = ReadCacheStats { hits: 1, misses: 1 };
+        assert_eq!(env.read_cache_stats(), Some(stats));
+
+        // Whole files are prefetched without ranges, and writes drop the cached blocks.
+        env.prefetch(&[name.to_path_buf()], None).unwrap();
+        let stats = ReadCacheStats { hits: 2, misses: 3 };
+        assert_eq!(env.read_cache_stats(), Some(stats));
+        f.read_exact_at(0, &mut buf).unwrap();
+        assert_eq!(env.read_cache_stats().unwrap().hits, 3);
+        env.open_appendable_file(name)
+            .unwrap()
+            .write_all(b"more")
+            .unwrap();
+        env.open_random_access_file(name)
+            .unwrap()
+            .read_exact_at(0, &mut buf)
+            .unwrap();
+        assert_eq!(env.read_cache_stats().unwrap().misses, 4);
+
+        assert!(env
+            .prefetch(&[PathBuf::from("read_cache_missing.ldb")], None)
+            .is_err());
+        assert_eq!(PosixDiskEnv::new_with([0u8; 16]).read_cache_stats(), None);
+        env.delete(name).unwrap();
+    }
+
+    fn test_open_with_advice() {
+        let name = Path::new("advice.ldb");
+        let data: Vec<u8> = (0..3 * READAHEAD_SIZE + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let cached = PosixDiskEnv::new_with([0u8; 16]).with_read_cache(1 << 20);
+        {
+            let mut f = cached.open_writable_file(name).unwrap();
+            f.write_all(&data).unwrap();
+            f.flush().unwrap();
+        }
+
+        let advices = [
+            Access::Sequential,
+            Access::Random,
+            Access::WillNeed,
+            Access::DontNeed,
+        ];
+        for env in &[cached.clone(), PosixDiskEnv::new_with([0u8; 16])] {
+            for &advice in &advices {
+                let f = env
+                    .open_random_access_file_with_advice(name, advice)
+                    .unwrap();
+                // A scan in small reads, followed by reads at scattered offsets and past the end.
+                let mut scanned = Vec::new();
+                let mut buf = vec![0u8; 1000];
+                loop {
+                    let n = f.read_at(scanned.len(), &mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    scanned.extend_from_slice(&buf[..n]);
+                }
+                assert_eq!(scanned, data, "{:?}", advice);
+                for &off in &[2 * READAHEAD_SIZE + 7, 5, READAHEAD_SIZE - 500] {
+                    f.read_exact_at(off, &mut buf).unwrap();
+                    assert_eq!(&buf[..], &data[off..off + 1000], "{:?}", advice);
+                }
+                assert_eq!(f.read_at(data.len() - 10, &mut buf).unwrap(), 10);
+                assert_eq!(f.read_at(data.len() + 10, &mut buf).unwrap(), 0);
+            }
+        }
+
+        // WillNeed fills the read cache, Sequential and DontNeed leave it alone.
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_read_cache(1 << 20);
+        let mut buf = [0u8; 100];
+        env.open_random_access_file_with_advice(name, Access::Sequential)
+            .unwrap()
+            .read_exact_at(0, &mut buf)
+            .unwrap();
+        assert_eq!(env.read_cache_stats(), Some(ReadCacheStats::default()));
+        env.open_random_access_file_with_advice(name, Access::WillNeed)
+            .unwrap()
+            .read_exact_at(0, &mut buf)
+            .unwrap();
+        let stats = env.read_cache_stats().unwrap();
+        assert_eq!(stats.hits, 1);
+        assert!(stats.misses > 0);
+        env.open_random_access_file_with_advice(name, Access::DontNeed)
+            .unwrap()
+            .read_exact_at(0, &mut buf)
+            .unwrap();
+        assert_eq!(env.read_cache_stats(), Some(stats));
+        env.open_random_access_file(name)
+            .unwrap()
+            .read_exact_at(0, &mut buf)
+            .unwrap();
+        assert_eq!(env.read_cache_stats().unwrap().misses, stats.misses + 1);
+
+        cached.delete(name).unwrap();
+    }
+
+    fn test_io_stats() {
+        let name = Path::new("io_stats.txt");
+        let renamed = Path::new("io_stats_renamed.txt");
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_io_stats(true);
+        let clone = env.clone();
+        assert_eq!(env.io_stats(), Some(IoStats::default()));
+
+        {
+            let mut f = env.open_writable_file(name).unwrap();
+            f.write_all(b"Hello").unwrap();
+            f.flush().unwrap();
+        }
+        clone.rename(name, renamed).unwrap();
+        let mut content = vec![];
+        env.open_sequential_file(renamed)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        let mut buf = [0u8; 3];
+        env.open_random_access_file(renamed)
+            .unwrap()
+            .read_at(1, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"ell");
+
+        // Clones share the stats.
+        let stats = clone.io_stats().unwrap();
+        assert_eq!(stats.histogram(IoOp::Open).count(), 3);
+        assert_eq!(stats.histogram(IoOp::Rename).count(), 1);
+        assert!(stats.write.count() >= 2);
+        assert!(stats.read.count() >= 2);
+        for h in &[&stats.open, &stats.read, &stats.write, &stats.rename] {
+            assert_eq!(h.buckets().iter().sum::<u64>(), h.count());
+            assert!(h.percentile(99.0) >= h.percentile(50.0));
+        }
+
+        let off = PosixDiskEnv::new_with([0u8; 16]);
+        assert!(off.open_sequential_file(renamed).is_ok());
+        assert_eq!(off.io_stats(), None);
+
+        env.delete(renamed).unwrap();
+
+        let mut h = LatencyHistogram::default();
+        for micros in &[0, 1, 3, 3, 100, 5000] {
+            h.record(*micros);
+        }
+        assert_eq!(h.count(), 6);
+        assert_eq!(h.max_micros(), 5000);
+        assert_eq!(h.buckets()[..3], [1, 1, 2]);
+        assert_eq!(h.percentile(50.0), 4);
+        assert_eq!(h.percentile(100.0), 5000);
+    }
+
+    fn test_io_timeout() {
+        let name = Path::new("io_timeout.txt");
+        let env = PosixDiskEnv::new_with([0u8; 16])
+            .with_io_timeout(Duration::from_secs(10))
+            .with_io_stats(true);
+
+        {
+            let mut f = env.open_writable_file(name).unwrap();
+            f.write_all(b"Hello").unwrap();
+            f.flush().unwrap();
+        }
+        {
+            let mut f = env.open_appendable_file(name).unwrap();
+            f.write_all(b", World").unwrap();
+        }
+        let mut content = vec![];
+        env.open_sequential_file(name)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"Hello, World");
+        let mut buf = [0u8; 5];
+        env.open_random_access_file(name)
+            .unwrap()
+            .read_exact_at(7, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"World");
+        assert_eq!(env.io_stats().unwrap().open.count(), 4);
+
+        // Errors of guarded opens keep their code and name the file.
+        let e = env
+            .open_sequential_file(Path::new("io_timeout_missing.txt"))
+            .err()
+            .unwrap();
+        assert_eq!(e.code, StatusCode::NotFound);
+        assert!(e.err.contains("io_timeout_missing.txt"));
+
+        env.delete(name).unwrap();
+    }
+
+    fn test_append_batch() {
+        let name = Path::new("append_batch.log");
+        let env = PosixDiskEnv::new_with([0u8; 16]);
+        env.open_writable_file(name).unwrap();
+
+        let records: Vec<Vec<u8>> = (0..100)
+            .map(|i| format!("record {};", i).into_bytes())
+            .collect();
+        let batch: Vec<&[u8]> = records.iter().map(|r| r.as_slice()).collect();
+        let expected = records.concat();
+        assert_eq!(env.append_batch(name, &batch).unwrap(), expected.len());
+        assert_eq!(env.append_batch(name, &[b"tail"]).unwrap(), 4);
+
+        let mut content = vec![];
+        env.open_sequential_file(name)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, [expected.as_slice(), b"tail"].concat());
+        env.delete(name).unwrap();
+    }
+
+    fn test_root_confinement() {
+        let root: &Path = "confined_root".as_ref();
+        let env = PosixDiskEnv::new_with([0u8; 16]).with_root(root);
+        env.mkdir(&root.join("sub")).unwrap();
+
+        // Subdirectories of the root, however they are spelled, are allowed.
+        let allowed = [
+            root.join("000001.ldb"),
+            root.join("sub/000002.ldb"),
+            root.join("sub/../000003.ldb"),
+            root.join("./sub/./000004.ldb"),
+        ];
+        for name in &allowed {
+            env.open_writable_file(name).unwrap();
+            assert!(env.exists(name).unwrap());
+        }
+        env.rename(&allowed[0], &root.join("sub/000005.ldb"))
+            .unwrap();
+        assert_eq!(env.children(&root.join("sub")).unwrap().len(), 3);
+
+        let escaping = [
+            root.join("../escaped.ldb"),
+            root.join("sub/../../escaped.ldb"),
+            root.join("missing/../../escaped.ldb"),
+            PathBuf::from("confined_root_sibling/escaped.ldb"),
+            PathBuf::from("/tmp/escaped.ldb"),
+        ];
+        for name in &escaping {
+            let e = env.open_writable_file(name).err().unwrap();
+            assert_eq!(e.code, StatusCode::InvalidArgument);
+            assert!(e.err.contains("path escapes the root"));
+            assert_eq!(
+                env.exists(name).unwrap_err().code,
+                StatusCode::InvalidArgument
+            );
+            assert_eq!(
+                env.delete(name).unwrap_err().code,
+                StatusCode::InvalidArgument
+            );
+        }
+        assert_eq!(
+            env.rename(&allowed[1], &escaping[0]).unwrap_err().code,
+            StatusCode::InvalidArgument
+        );
+
+        // Symlinks are followed: into the root they are allowed, out of it they are not.
+        let outside: &Path = "confined_root_outside".as_ref();
+        let unconfined = PosixDiskEnv::new_with([0u8; 16]);
+        unconfined.mkdir(outside).unwrap();
+        fs::soft_link("sub", root.join("inner")).unwrap();
+        fs::soft_link("../confined_root_outside", root.join("outer")).unwrap();
+        env.open_writable_file(&root.join("inner/000006.ldb"))
+            .unwrap();
+        assert!(env.exists(&root.join("sub/000006.ldb")).unwrap());
+        let escaping_link = root.join("outer/escaped.ldb");
+        let e = env.open_writable_file(&escaping_link).err().unwrap();
+        assert_eq!(e.code, StatusCode::InvalidArgument);
+        assert_eq!(
+            env.children(&root.join("outer")).unwrap_err().code,
+            StatusCode::InvalidArgument
+        );
+        assert_eq!(
+            env.lock(&escaping_link).err().unwrap().code,
+            StatusCode::InvalidArgument
+        );
+
+        // Locks are taken on the resolved path, and forged locks can't unlock outside of it.
+        let lock = env.lock(&root.join("inner/LOCK")).unwrap();
+        assert!(env.is_locked(&root.join("sub/LOCK")));
+        let forged = FileLock {
+            id: path_to_string(&outside.join("LOCK")),
+        };
+        assert_eq!(
+            env.unlock(forged).unwrap_err().code,
+            StatusCode::InvalidArgument
+        );
+        env.unlock(lock).unwrap();
+
+        // Nothing was written outside of the root.
+        assert!(!unconfined.exists(Path::new("escaped.ldb")).unwrap());
+        assert!(!unconfined.exists(Path::new("/tmp/escaped.ldb")).unwrap());
+        assert!(unconfined.children(outside).unwrap().is_empty());
+
+        fs::remove_file(root.join("inner")).unwrap();
+        fs::remove_file(root.join("outer")).unwrap();
+        assert!(env.rmdir(root).is_ok());
+        assert!(unconfined.rmdir(outside).is_ok());
+    }
 }
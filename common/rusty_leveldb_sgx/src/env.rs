@@ -1,16 +1,54 @@
 //! An `env` is an abstraction layer that allows the database to run both on different platforms as
 //! well as persisting data on disk or in memory.
 
-use crate::error::Result;
+use crate::env_common::{self, Clock, SystemClock};
+use crate::env_journal::{self, Txn};
+use crate::error::{err, Result, StatusCode};
 
+use std::collections::HashMap;
 use std::io::prelude::*;
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use sgx_tprotected_fs::SgxFile;
 
 pub trait RandomAccess {
+    /// read_at reads up to `dst.len()` bytes starting at `off` and returns how many were read.
+    /// Fewer bytes are read if the file ends within the range, and none at or after its end;
+    /// neither is an error.
     fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize>;
+
+    /// read_exact_at fills `dst` with the bytes starting at `off`, failing if the file ends
+    /// before `dst` is full.
+    fn read_exact_at(&self, mut off: usize, mut dst: &mut [u8]) -> Result<()> {
+        let len = dst.len();
+        while !dst.is_empty() {
+            match self.read_at(off, dst)? {
+                0 => {
+                    return err(
+                        StatusCode::IOError,
+                        &format!(
+                            "unexpected end of file: read {} of {} bytes",
+                            len - dst.len(),
+                            len
+                        ),
+                    )
+                }
+                n => {
+                    off += n;
+                    dst = &mut dst[n..];
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: RandomAccess + ?Sized> RandomAccess for Box<R> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        (**self).read_at(off, dst)
+    }
 }
 
 impl RandomAccess for SgxFile {
@@ -19,6 +57,56 @@ impl RandomAccess for SgxFile {
     }
 }
 
+/// Algorithm of `Env::checksum`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgo {
+    /// CRC-32C (Castagnoli), the checksum also used for log records and table blocks.
+    Crc32c,
+}
+
+/// TreeDiff is the result of `Env::diff_trees`. All paths are relative to the compared roots and
+/// sorted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeDiff {
+    /// Files which exist only below the source root.
+    pub only_in_source: Vec<PathBuf>,
+    /// Files which exist only below the destination root.
+    pub only_in_dest: Vec<PathBuf>,
+    /// Files which exist below both roots but differ in size or SHA-256 digest.
+    pub differing: Vec<PathBuf>,
+}
+
+impl TreeDiff {
+    /// Whether both trees hold the same files with the same contents.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_source.is_empty() && self.only_in_dest.is_empty() && self.differing.is_empty()
+    }
+}
+
+/// Order of `Env::children_sorted`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Byte-wise order of the names.
+    Lexical,
+    /// Order of the first number in each name, e.g. `9.log` before `10.log` and `000123.sst`.
+    /// Names without a number come last, in lexical order.
+    Numeric,
+}
+
+/// Access is the expected access pattern of a random access file, which lets an env tune its
+/// readahead and caching for it. See `Env::open_random_access_file_with_advice`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// The file is read once from start to end, e.g. by a compaction.
+    Sequential,
+    /// The file is read at scattered offsets, e.g. by point lookups.
+    Random,
+    /// The whole file will be read soon, so the env may read it ahead of time.
+    WillNeed,
+    /// The file will not be read again soon, so the env should not keep its data around.
+    DontNeed,
+}
+
 pub struct FileLock {
     pub id: String,
 }
@@ -26,12 +114,101 @@ pub struct FileLock {
 pub trait Env {
     fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>>;
     fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>>;
+    /// Like `open_random_access_file`, but tells the env how the file is going to be read. The
+    /// advice only affects performance, never what is read; envs without any tuning ignore it.
+    fn open_random_access_file_with_advice(
+        &self,
+        p: &Path,
+        _advice: Access,
+    ) -> Result<Box<dyn RandomAccess>> {
+        self.open_random_access_file(p)
+    }
     fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>>;
     fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>>;
 
     fn exists(&self, p: &Path) -> Result<bool>;
     fn children(&self, p: &Path) -> Result<Vec<PathBuf>>;
     fn size_of(&self, p: &Path) -> Result<usize>;
+    /// Returns the sizes of all files below `root`, keyed by their path relative to `root`. This
+    /// is best effort: the map is taken in one pass, but not atomically with respect to
+    /// concurrent writes, so files written meanwhile may be missing or have a stale size.
+    fn snapshot_sizes(&self, root: &Path) -> Result<HashMap<PathBuf, usize>>;
+    /// Sums the sizes of all files below `root` by file extension, e.g. to see how much space
+    /// goes to tables, logs and manifests. Files without an extension are counted under "".
+    fn usage_by_extension(&self, root: &Path) -> Result<HashMap<String, u64>> {
+        Ok(env_common::usage_by_extension(&self.snapshot_sizes(root)?))
+    }
+
+    /// Computes a checksum of the file at `p`, streaming its contents. The result is
+    /// deterministic, so it can tell whether a file changed between two backups, but it is not
+    /// cryptographic and must not be relied upon against deliberate tampering.
+    fn checksum(&self, p: &Path, algo: ChecksumAlgo) -> Result<u64> {
+        env_common::checksum(self.open_sequential_file(p)?, algo)
+    }
+
+    /// Streams the file at `p` through SHA-256 and tells whether the digest equals `expected`.
+    /// The comparison takes constant time, so it does not leak how much of a guess was right.
+    fn verify_digest(&self, p: &Path, expected: &[u8; 32]) -> Result<bool> {
+        env_common::verify_digest(self.open_sequential_file(p)?, expected)
+    }
+
+    /// Compares the files below `self_root` with those below `other_root` in `other`, e.g. to
+    /// verify a backup. Files present in both are compared by size first; only files of equal
+    /// size are streamed through SHA-256.
+    fn diff_trees(&self, other: &dyn Env, self_root: &Path, other_root: &Path) -> Result<TreeDiff> {
+        env_common::diff_trees(self, other, self_root, other_root)
+    }
+
+    /// Streams the file at `src` through `f` in blocks and writes what `f` appends to its output
+    /// buffer to `dst`, e.g. to compress or redact a file. `dst` is only replaced once the whole
+    /// file went through, so it never holds a partial result. Returns the number of bytes read
+    /// and written.
+    fn transform_file(
+        &self,
+        src: &Path,
+        dst: &Path,
+        f: &mut dyn FnMut(&[u8], &mut Vec<u8>),
+    ) -> Result<(usize, usize)> {
+        env_common::transform_file(self, src, dst, f)
+    }
+
+    /// Appends `records` to the file at `p` through a single handle in one write, followed by a
+    /// single flush, so a group of small records costs one open and one durability barrier.
+    /// Returns the number of bytes appended.
+    fn append_batch(&self, p: &Path, records: &[&[u8]]) -> Result<usize> {
+        env_common::append_batch(self.open_appendable_file(p)?, records)
+    }
+
+    /// Probes the storage below `root` by writing a small sentinel file, reading it back,
+    /// checking its size and deleting it again, e.g. before a worker accepts traffic. The
+    /// CURRENT file of a database at `root` is read back too. Fails if any step fails, such as
+    /// on a read-only or full file system or with the wrong key.
+    fn health_check(&self, root: &Path) -> Result<()> {
+        env_common::health_check(self, root)
+    }
+
+    /// Reads the given ranges of the files at `paths`, so that the reads which follow are served
+    /// from the read cache of the env, e.g. the index and filter blocks of all tables right
+    /// after opening a database. `ranges` are (offset, length) pairs applied to every file; with
+    /// None whole files are read, so only pass small ones. Ranges past the end of a file stop
+    /// there. Envs without a read cache only warm whatever caches lie below them.
+    fn prefetch(&self, paths: &[PathBuf], ranges: Option<Vec<(u64, u64)>>) -> Result<()> {
+        env_common::prefetch(self, paths, ranges.as_deref())
+    }
+
+    /// Like `children`, but sorted in the given order.
+    fn children_sorted(&self, p: &Path, order: SortOrder) -> Result<Vec<PathBuf>> {
+        let mut children = self.children(p)?;
+        env_common::sort_children(&mut children, order);
+        Ok(children)
+    }
+
+    /// Exchanges the contents of the existing files `a` and `b`, e.g. the live and the
+    /// candidate version of an index. Neither path is missing at any point, but for a moment
+    /// both hold the contents of `b`.
+    fn swap(&self, a: &Path, b: &Path) -> Result<()> {
+        env_common::swap(self, a, b)
+    }
 
     fn delete(&self, p: &Path) -> Result<()>;
     fn mkdir(&self, p: &Path) -> Result<()>;
@@ -40,24 +217,112 @@ pub trait Env {
 
     fn lock(&self, p: &Path) -> Result<FileLock>;
     fn unlock(&self, l: FileLock) -> Result<()>;
+    /// Whether `p` is locked through this env, e.g. to skip a file in use instead of racing a
+    /// rename or deletion against its owner. The answer is advisory: it is stale as soon as it
+    /// is returned, and envs which do not track their locks always report false.
+    fn is_locked(&self, _p: &Path) -> bool {
+        false
+    }
+
+    /// Starts a transaction of renames, deletions and file writes within the directory `dir`.
+    /// Nothing is changed until the transaction is passed to `commit`.
+    fn begin_txn(&self, dir: &Path) -> Txn {
+        Txn::new(dir)
+    }
+    /// Applies all operations of `txn` through a journal in its directory, so that a crash leaves
+    /// the directory either untouched or, after `replay_journal`, fully updated.
+    fn commit(&self, txn: Txn) -> Result<()> {
+        env_journal::commit(self, txn)
+    }
+    /// Finishes or discards a transaction that was interrupted in `dir`. Returns whether an
+    /// interrupted commit was completed.
+    fn replay_journal(&self, dir: &Path) -> Result<bool> {
+        env_journal::replay(self, dir)
+    }
 
-    fn new_logger(&self, p: &Path) -> Result<Logger>;
+    /// Opens a logger appending to the file at `p`, which prefixes its lines as `options` say.
+    fn new_logger(&self, p: &Path, options: LoggerOptions) -> Result<Logger>;
 
     fn micros(&self) -> u64;
 }
 
+/// LogLevel is the level tag of a line written by a `Logger`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
+/// LoggerOptions controls what a `Logger` prepends to each line. By default lines are written
+/// as given.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoggerOptions {
+    /// Prepends the time of the env clock as seconds and microseconds since the UNIX epoch,
+    /// e.g. `1700000000.000042`.
+    pub timestamp: bool,
+    /// Prepends the level of the line, e.g. `[INFO]`.
+    pub level: bool,
+}
+
 pub struct Logger {
     dst: Box<dyn Write>,
+    options: LoggerOptions,
+    clock: Arc<dyn Clock>,
 }
 
 impl Logger {
     pub fn new(w: Box<dyn Write>) -> Logger {
-        Logger { dst: w }
+        Logger::with_options(w, LoggerOptions::default(), Arc::new(SystemClock))
+    }
+
+    /// with_options makes a logger which prefixes its lines as `options` say, taking timestamps
+    /// from `clock`.
+    pub fn with_options(
+        w: Box<dyn Write>,
+        options: LoggerOptions,
+        clock: Arc<dyn Clock>,
+    ) -> Logger {
+        Logger {
+            dst: w,
+            options,
+            clock,
+        }
     }
 
     pub fn log(&mut self, message: &str) {
-        let _ = self.dst.write(message.as_bytes());
-        let _ = self.dst.write("\n".as_bytes());
+        self.log_at(LogLevel::Info, message)
+    }
+
+    /// log_at writes `message` as one line, so the prefix is written in the same call.
+    pub fn log_at(&mut self, level: LogLevel, message: &str) {
+        let mut line = String::with_capacity(message.len() + 32);
+        if self.options.timestamp {
+            let micros = self.clock.micros();
+            line.push_str(&format!(
+                "{}.{:06} ",
+                micros / 1_000_000,
+                micros % 1_000_000
+            ));
+        }
+        if self.options.level {
+            line.push_str(&format!("[{}] ", level.as_str()));
+        }
+        line.push_str(message);
+        line.push('\n');
+        let _ = self.dst.write(line.as_bytes());
     }
 }
 
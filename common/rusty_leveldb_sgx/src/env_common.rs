@@ -1,8 +1,32 @@
+use crate::env::{path_to_str, ChecksumAlgo, Env, SortOrder, TreeDiff};
+use crate::error::{err, Result, StatusCode};
+
+use crc::crc32::{self, Hasher32};
+use ring::{constant_time, digest};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::time;
 #[cfg(feature = "mesalock_sgx")]
 #[allow(unused_imports)]
 use std::untrusted::time::SystemTimeEx;
 
+/// Source of the current time for an env, in microseconds since the UNIX epoch. Tests can
+/// substitute their own to move time forward without sleeping.
+pub trait Clock: Send + Sync {
+    fn micros(&self) -> u64;
+}
+
+/// The real clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn micros(&self) -> u64 {
+        micros()
+    }
+}
+
 pub fn micros() -> u64 {
     loop {
         let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH);
@@ -13,3 +37,336 @@ pub fn micros() -> u64 {
         }
     }
 }
+
+const CHECKSUM_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn checksum(mut src: Box<dyn Read>, algo: ChecksumAlgo) -> Result<u64> {
+    let mut buf = vec![0; CHECKSUM_BUFFER_SIZE];
+    match algo {
+        ChecksumAlgo::Crc32c => {
+            let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
+            loop {
+                let n = src.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                digest.write(&buf[..n]);
+            }
+            Ok(u64::from(digest.sum32()))
+        }
+    }
+}
+
+fn sha256(mut src: Box<dyn Read>) -> Result<digest::Digest> {
+    let mut buf = vec![0; CHECKSUM_BUFFER_SIZE];
+    let mut context = digest::Context::new(&digest::SHA256);
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(context.finish())
+}
+
+pub fn verify_digest(src: Box<dyn Read>, expected: &[u8; 32]) -> Result<bool> {
+    let actual = sha256(src)?;
+    Ok(constant_time::verify_slices_are_equal(actual.as_ref(), expected).is_ok())
+}
+
+/// diff_trees compares the files below `src_root` in `src` with those below `dst_root` in
+/// `dst`. Digests are only computed for files whose sizes match.
+pub fn diff_trees<E: Env + ?Sized>(
+    src: &E,
+    dst: &dyn Env,
+    src_root: &Path,
+    dst_root: &Path,
+) -> Result<TreeDiff> {
+    let src_sizes = src.snapshot_sizes(src_root)?;
+    let dst_sizes = dst.snapshot_sizes(dst_root)?;
+
+    let mut diff = TreeDiff::default();
+    for (p, size) in &src_sizes {
+        match dst_sizes.get(p) {
+            None => diff.only_in_source.push(p.clone()),
+            Some(dst_size) if dst_size != size => diff.differing.push(p.clone()),
+            Some(_) => {
+                let src_digest = sha256(src.open_sequential_file(&src_root.join(p))?)?;
+                let dst_digest = sha256(dst.open_sequential_file(&dst_root.join(p))?)?;
+                if src_digest.as_ref() != dst_digest.as_ref() {
+                    diff.differing.push(p.clone());
+                }
+            }
+        }
+    }
+    diff.only_in_dest = dst_sizes
+        .keys()
+        .filter(|p| !src_sizes.contains_key(*p))
+        .cloned()
+        .collect();
+
+    diff.only_in_source.sort();
+    diff.only_in_dest.sort();
+    diff.differing.sort();
+    Ok(diff)
+}
+
+pub fn append_batch(mut dst: Box<dyn Write>, records: &[&[u8]]) -> Result<usize> {
+    let batch = records.concat();
+    dst.write_all(&batch)?;
+    dst.flush()?;
+    Ok(batch.len())
+}
+
+/// Name of the sentinel file written by `health_check`.
+pub const HEALTH_CHECK_FILE_NAME: &str = "HEALTH_CHECK";
+
+/// health_check writes a sentinel file below `root`, reads it back and checks its size. The
+/// sentinel is deleted again even if one of the checks fails. A sentinel written and read with
+/// the same key cannot tell whether the key is the one the store was written with, so the
+/// CURRENT file of a database at `root`, if there is one, is read back as well.
+pub fn health_check<E: Env + ?Sized>(env: &E, root: &Path) -> Result<()> {
+    let p = root.join(HEALTH_CHECK_FILE_NAME);
+    let contents = format!("health check at {}", env.micros()).into_bytes();
+    let result = check_sentinel(env, &p, &contents);
+    let deleted = match env.exists(&p) {
+        Ok(true) => env.delete(&p),
+        Ok(false) => Ok(()),
+        Err(e) => Err(e),
+    };
+    result.and(deleted)?;
+
+    let current = root.join("CURRENT");
+    if env.exists(&current)? {
+        check_readable(env, &current)?;
+    }
+    Ok(())
+}
+
+/// check_readable reads the existing file at `p` to its end and checks that it is as long as
+/// `size_of` claims.
+fn check_readable<E: Env + ?Sized>(env: &E, p: &Path) -> Result<()> {
+    let mut read = Vec::new();
+    env.open_sequential_file(p)?.read_to_end(&mut read)?;
+    let size = env.size_of(p)?;
+    if read.len() != size {
+        return err(
+            StatusCode::Corruption,
+            &format!(
+                "health_check: read {} bytes instead of {}: {}",
+                read.len(),
+                size,
+                path_to_str(p)
+            ),
+        );
+    }
+    Ok(())
+}
+
+fn check_sentinel<E: Env + ?Sized>(env: &E, p: &Path, contents: &[u8]) -> Result<()> {
+    let mut f = env.open_writable_file(p)?;
+    f.write_all(contents)?;
+    f.flush()?;
+    drop(f);
+
+    let mut read = Vec::new();
+    env.open_sequential_file(p)?.read_to_end(&mut read)?;
+    if read != contents {
+        return err(
+            StatusCode::Corruption,
+            &format!(
+                "health_check: sentinel reads back differently: {}",
+                path_to_str(p)
+            ),
+        );
+    }
+    let size = env.size_of(p)?;
+    if size != contents.len() {
+        return err(
+            StatusCode::Corruption,
+            &format!(
+                "health_check: sentinel has size {} instead of {}: {}",
+                size,
+                contents.len(),
+                path_to_str(p)
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// swap_temp_file_name returns the name of the copy of `p` taken by `swap`.
+fn swap_temp_file_name(p: &Path) -> PathBuf {
+    let mut name = p.file_name().unwrap_or_default().to_os_string();
+    name.push(".swap");
+    p.with_file_name(name)
+}
+
+fn copy_file<E: Env + ?Sized>(env: &E, from: &Path, to: &Path) -> Result<()> {
+    let mut src = env.open_sequential_file(from)?;
+    let mut dst = env.open_writable_file(to)?;
+    let mut buf = vec![0; CHECKSUM_BUFFER_SIZE];
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n])?;
+    }
+    dst.flush()?;
+    Ok(())
+}
+
+/// swap exchanges the contents of `a` and `b`. Both are copied to temporary files first, and
+/// each copy is then renamed over the other path, so both paths exist at every point.
+pub fn swap<E: Env + ?Sized>(env: &E, a: &Path, b: &Path) -> Result<()> {
+    for p in [a, b] {
+        if !env.exists(p)? {
+            return err(
+                StatusCode::NotFound,
+                &format!("swap: file not found: {}", path_to_str(p)),
+            );
+        }
+    }
+    if a == b {
+        return Ok(());
+    }
+    let (a_copy, b_copy) = (swap_temp_file_name(a), swap_temp_file_name(b));
+    copy_file(env, a, &a_copy)?;
+    copy_file(env, b, &b_copy)?;
+    env.rename(&b_copy, a)?;
+    env.rename(&a_copy, b)
+}
+
+/// transform_temp_file_name returns the name of the file `transform_file` writes before it is
+/// renamed to `dst`. Its `.dbtmp` extension marks it as a temp file which is safe to delete.
+fn transform_temp_file_name(dst: &Path) -> PathBuf {
+    let mut name = dst.file_name().unwrap_or_default().to_os_string();
+    name.push(".dbtmp");
+    dst.with_file_name(name)
+}
+
+/// transform_file streams `src` through `f` into a temp file, which is then renamed to `dst`.
+/// The temp file is deleted again if any step fails.
+pub fn transform_file<E: Env + ?Sized>(
+    env: &E,
+    src: &Path,
+    dst: &Path,
+    f: &mut dyn FnMut(&[u8], &mut Vec<u8>),
+) -> Result<(usize, usize)> {
+    let tmp = transform_temp_file_name(dst);
+    match transform_into(env, src, &tmp, f).and_then(|counts| {
+        env.rename(&tmp, dst)?;
+        Ok(counts)
+    }) {
+        Ok(counts) => Ok(counts),
+        Err(e) => {
+            if env.exists(&tmp).unwrap_or(false) {
+                let _ = env.delete(&tmp);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn transform_into<E: Env + ?Sized>(
+    env: &E,
+    src: &Path,
+    tmp: &Path,
+    f: &mut dyn FnMut(&[u8], &mut Vec<u8>),
+) -> Result<(usize, usize)> {
+    let mut src = env.open_sequential_file(src)?;
+    let mut dst = env.open_writable_file(tmp)?;
+    let mut buf = vec![0; CHECKSUM_BUFFER_SIZE];
+    let mut out = Vec::new();
+    let (mut bytes_in, mut bytes_out) = (0, 0);
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        out.clear();
+        f(&buf[..n], &mut out);
+        dst.write_all(&out)?;
+        bytes_in += n;
+        bytes_out += out.len();
+    }
+    dst.flush()?;
+    Ok((bytes_in, bytes_out))
+}
+
+/// Size of the reads issued by `prefetch`.
+const PREFETCH_CHUNK_SIZE: usize = 64 * 1024;
+
+/// prefetch reads `ranges` of each of `paths`, or all of them, through random access files of
+/// `env` and discards the data.
+pub fn prefetch<E: Env + ?Sized>(
+    env: &E,
+    paths: &[PathBuf],
+    ranges: Option<&[(u64, u64)]>,
+) -> Result<()> {
+    let mut buf = vec![0; PREFETCH_CHUNK_SIZE];
+    for p in paths {
+        let f = env.open_random_access_file(p)?;
+        let whole;
+        let ranges = match ranges {
+            Some(ranges) => ranges,
+            None => {
+                whole = [(0, env.size_of(p)? as u64)];
+                &whole[..]
+            }
+        };
+        for &(off, len) in ranges {
+            let (mut off, end) = (off, off.saturating_add(len));
+            while off < end {
+                let n = buf.len().min((end - off) as usize);
+                match f.read_at(off as usize, &mut buf[..n])? {
+                    0 => break,
+                    read => off += read as u64,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// usage_by_extension sums `sizes` by the extension of their paths.
+pub fn usage_by_extension(sizes: &HashMap<PathBuf, usize>) -> HashMap<String, u64> {
+    let mut usage = HashMap::new();
+    for (p, size) in sizes {
+        let extension = p
+            .extension()
+            .map(|e| e.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        *usage.entry(extension).or_insert(0) += *size as u64;
+    }
+    usage
+}
+
+pub fn sort_children(children: &mut [PathBuf], order: SortOrder) {
+    match order {
+        SortOrder::Lexical => children.sort(),
+        SortOrder::Numeric => {
+            children.sort_by(|a, b| match (numeric_component(a), numeric_component(b)) {
+                (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => a.cmp(b),
+            })
+        }
+    }
+}
+
+/// numeric_component returns the first run of digits in the file name of `p`.
+fn numeric_component(p: &Path) -> Option<u64> {
+    let name = p.file_name()?.to_str()?;
+    let digits: String = name
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
@@ -0,0 +1,386 @@
+//! A small write-ahead journal that makes a set of renames, deletions and file writes within one
+//! directory crash-atomic on top of any `Env`.
+//!
+//! Committing a transaction first writes the contents of staged files to temporary files next to
+//! their targets. It then writes the list of remaining renames and deletions to `JOURNAL.tmp` and
+//! renames it to `JOURNAL`, which is the commit point. After that each operation is applied and
+//! its index appended to the journal, and finally the journal is deleted. `replay` rolls a
+//! published journal forward and throws away the leftovers of one that was never published, so
+//! after a crash the directory is either in its pre- or its post-transaction state.
+
+use crate::env::{path_to_str, Env};
+use crate::error::{err, Result, StatusCode};
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "JOURNAL";
+const JOURNAL_TEMP_FILE: &str = "JOURNAL.tmp";
+const STAGED_FILE_INFIX: &str = ".txntmp.";
+
+/// A set of directory mutations which `Env::commit` applies all-or-nothing.
+pub struct Txn {
+    dir: PathBuf,
+    ops: Vec<TxnOp>,
+}
+
+enum TxnOp {
+    Rename(PathBuf, PathBuf),
+    Delete(PathBuf),
+    WriteFile(PathBuf, Vec<u8>),
+}
+
+/// An operation as recorded in the journal. Staged file writes have been turned into renames by
+/// the time the journal is written.
+#[derive(Clone, Debug, PartialEq)]
+enum JournalOp {
+    Rename(PathBuf, PathBuf),
+    Delete(PathBuf),
+}
+
+impl Txn {
+    pub fn new(dir: &Path) -> Txn {
+        Txn {
+            dir: dir.to_owned(),
+            ops: vec![],
+        }
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        self.ops.push(TxnOp::Rename(from.to_owned(), to.to_owned()));
+    }
+
+    pub fn delete(&mut self, p: &Path) {
+        self.ops.push(TxnOp::Delete(p.to_owned()));
+    }
+
+    /// Replaces the contents of `p` with `contents` when the transaction is committed.
+    pub fn write_file_atomic(&mut self, p: &Path, contents: &[u8]) {
+        self.ops
+            .push(TxnOp::WriteFile(p.to_owned(), contents.to_vec()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+fn journal_file_name(dir: &Path) -> PathBuf {
+    dir.join(JOURNAL_FILE)
+}
+
+fn journal_temp_file_name(dir: &Path) -> PathBuf {
+    dir.join(JOURNAL_TEMP_FILE)
+}
+
+fn staged_file_name(p: &Path, ix: usize) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", path_to_str(p), STAGED_FILE_INFIX, ix))
+}
+
+fn check_path(p: &Path) -> Result<()> {
+    match p.to_str() {
+        Some(s) if !s.contains('\t') && !s.contains('\n') => Ok(()),
+        _ => err(
+            StatusCode::InvalidArgument,
+            "journal paths must be valid UTF-8 without tabs or newlines",
+        ),
+    }
+}
+
+pub fn commit<E: Env + ?Sized>(env: &E, txn: Txn) -> Result<()> {
+    if txn.is_empty() {
+        return Ok(());
+    }
+    let ops = stage(env, &txn)?;
+    publish(env, &txn.dir, &ops)?;
+    apply(env, &txn.dir, &ops, 0, ops.len())?;
+    env.delete(&journal_file_name(&txn.dir))
+}
+
+/// Writes the contents of staged files to temporary files and returns the operations to journal.
+fn stage<E: Env + ?Sized>(env: &E, txn: &Txn) -> Result<Vec<JournalOp>> {
+    for op in txn.ops.iter() {
+        match op {
+            TxnOp::Rename(from, to) => {
+                check_path(from)?;
+                check_path(to)?;
+            }
+            TxnOp::Delete(p) | TxnOp::WriteFile(p, _) => check_path(p)?,
+        }
+    }
+
+    let mut ops = Vec::with_capacity(txn.ops.len());
+    for (ix, op) in txn.ops.iter().enumerate() {
+        let op = match op {
+            TxnOp::Rename(from, to) => JournalOp::Rename(from.clone(), to.clone()),
+            TxnOp::Delete(p) => JournalOp::Delete(p.clone()),
+            TxnOp::WriteFile(p, contents) => {
+                let staged = staged_file_name(p, ix);
+                let mut f = env.open_writable_file(&staged)?;
+                f.write_all(contents)?;
+                f.flush()?;
+                JournalOp::Rename(staged, p.clone())
+            }
+        };
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+/// Writes the journal and atomically moves it into place. Once this returns, the transaction
+/// will be completed even if the process crashes.
+fn publish<E: Env + ?Sized>(env: &E, dir: &Path, ops: &[JournalOp]) -> Result<()> {
+    let temp = journal_temp_file_name(dir);
+    {
+        let mut f = env.open_writable_file(&temp)?;
+        for op in ops {
+            f.write_all(encode_op(op).as_bytes())?;
+        }
+        f.flush()?;
+    }
+    env.rename(&temp, &journal_file_name(dir))
+}
+
+/// Applies `ops[from..to]`, recording the index of every applied operation in the journal.
+fn apply<E: Env + ?Sized>(
+    env: &E,
+    dir: &Path,
+    ops: &[JournalOp],
+    from: usize,
+    to: usize,
+) -> Result<()> {
+    let journal = journal_file_name(dir);
+    for ix in from..to {
+        apply_op(env, &ops[ix])?;
+        let mut f = env.open_appendable_file(&journal)?;
+        f.write_all(format!("A\t{}\n", ix).as_bytes())?;
+        f.flush()?;
+    }
+    Ok(())
+}
+
+/// Applies a single operation. An operation may have already been applied before a crash
+/// without its index making it into the journal, so both operations tolerate that.
+fn apply_op<E: Env + ?Sized>(env: &E, op: &JournalOp) -> Result<()> {
+    match op {
+        JournalOp::Rename(from, to) => {
+            if env.exists(from)? {
+                env.rename(from, to)
+            } else if env.exists(to)? {
+                Ok(())
+            } else {
+                err(
+                    StatusCode::Corruption,
+                    &format!("journal: neither {:?} nor {:?} exists", from, to),
+                )
+            }
+        }
+        JournalOp::Delete(p) => {
+            if env.exists(p)? {
+                env.delete(p)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Completes a transaction that was interrupted after its journal had been published, and
+/// removes the leftovers of one that was interrupted before. Returns whether a journal was
+/// replayed.
+pub fn replay<E: Env + ?Sized>(env: &E, dir: &Path) -> Result<bool> {
+    let journal = journal_file_name(dir);
+    let replayed = if env.exists(&journal)? {
+        let (ops, applied) = read_journal(env, &journal)?;
+        apply(env, dir, &ops, applied, ops.len())?;
+        env.delete(&journal)?;
+        true
+    } else {
+        false
+    };
+
+    let temp = journal_temp_file_name(dir);
+    if env.exists(&temp)? {
+        env.delete(&temp)?;
+    }
+    for child in env.children(dir)? {
+        if path_to_str(&child).contains(STAGED_FILE_INFIX) {
+            env.delete(&dir.join(child))?;
+        }
+    }
+    Ok(replayed)
+}
+
+fn encode_op(op: &JournalOp) -> String {
+    match op {
+        JournalOp::Rename(from, to) => {
+            format!("R\t{}\t{}\n", path_to_str(from), path_to_str(to))
+        }
+        JournalOp::Delete(p) => format!("D\t{}\n", path_to_str(p)),
+    }
+}
+
+/// Returns the journaled operations and the number of them which have been applied.
+fn read_journal<E: Env + ?Sized>(env: &E, journal: &Path) -> Result<(Vec<JournalOp>, usize)> {
+    let mut contents = String::new();
+    env.open_sequential_file(journal)?
+        .read_to_string(&mut contents)?;
+
+    let mut ops = vec![];
+    let mut applied = 0;
+    // A trailing line without a newline is a torn progress record; the operation it refers to
+    // is idempotent and simply gets applied again.
+    let complete = match contents.rfind('\n') {
+        Some(ix) => &contents[..ix],
+        None => "",
+    };
+    for line in complete.split('\n').filter(|l| !l.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["R", from, to] => ops.push(JournalOp::Rename(PathBuf::from(from), PathBuf::from(to))),
+            ["D", p] => ops.push(JournalOp::Delete(PathBuf::from(p))),
+            ["A", ix] if ix.parse::<usize>().ok() == Some(applied) => applied += 1,
+            _ => {
+                return err(
+                    StatusCode::Corruption,
+                    &format!("journal: malformed record {:?}", line),
+                )
+            }
+        }
+    }
+    if applied > ops.len() {
+        return err(StatusCode::Corruption, "journal: too many progress records");
+    }
+    Ok((ops, applied))
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::mem_env::MemEnv;
+    use std::collections::BTreeMap;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_journal_commit,
+            test_journal_crash_before_publish,
+            test_journal_crash_mid_commit,
+            test_journal_encoding,
+        )
+    }
+
+    fn write(env: &MemEnv, p: &str, contents: &str) {
+        let mut f = env.open_writable_file(Path::new(p)).unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+    }
+
+    fn dir_contents(env: &MemEnv, dir: &str) -> BTreeMap<String, String> {
+        let mut contents = BTreeMap::new();
+        for child in env.children(Path::new(dir)).unwrap() {
+            let mut s = String::new();
+            env.open_sequential_file(&Path::new(dir).join(&child))
+                .unwrap()
+                .read_to_string(&mut s)
+                .unwrap();
+            contents.insert(path_to_str(&child).to_string(), s);
+        }
+        contents
+    }
+
+    fn setup() -> MemEnv {
+        let env = MemEnv::new();
+        write(&env, "db/CURRENT", "MANIFEST-000001\n");
+        write(&env, "db/000003.ldb", "old table");
+        write(&env, "db/000005.ldb.new", "new table");
+        env
+    }
+
+    fn version_edit_txn(env: &MemEnv) -> Txn {
+        let mut txn = env.begin_txn(Path::new("db"));
+        txn.rename(Path::new("db/000005.ldb.new"), Path::new("db/000005.ldb"));
+        txn.delete(Path::new("db/000003.ldb"));
+        txn.write_file_atomic(Path::new("db/MANIFEST-000006"), b"edit");
+        txn.write_file_atomic(Path::new("db/CURRENT"), b"MANIFEST-000006\n");
+        txn
+    }
+
+    fn post_state() -> BTreeMap<String, String> {
+        let mut m = BTreeMap::new();
+        m.insert("000005.ldb".to_string(), "new table".to_string());
+        m.insert("CURRENT".to_string(), "MANIFEST-000006\n".to_string());
+        m.insert("MANIFEST-000006".to_string(), "edit".to_string());
+        m
+    }
+
+    fn test_journal_commit() {
+        let env = setup();
+        let txn = version_edit_txn(&env);
+        env.commit(txn).unwrap();
+        assert_eq!(dir_contents(&env, "db"), post_state());
+
+        // Nothing is left to replay.
+        assert!(!env.replay_journal(Path::new("db")).unwrap());
+        assert_eq!(dir_contents(&env, "db"), post_state());
+    }
+
+    fn test_journal_crash_before_publish() {
+        let env = setup();
+        let pre_state = dir_contents(&env, "db");
+
+        let txn = version_edit_txn(&env);
+        let ops = stage(&env, &txn).unwrap();
+        // Crash while the journal is still being written.
+        write(&env, "db/JOURNAL.tmp", &encode_op(&ops[0]));
+
+        assert!(!env.replay_journal(Path::new("db")).unwrap());
+        assert_eq!(dir_contents(&env, "db"), pre_state);
+    }
+
+    fn test_journal_crash_mid_commit() {
+        for crash_after in 0..=4 {
+            let env = setup();
+            let txn = version_edit_txn(&env);
+            let dir = txn.dir.clone();
+            let ops = stage(&env, &txn).unwrap();
+            publish(&env, &dir, &ops).unwrap();
+            apply(&env, &dir, &ops, 0, crash_after).unwrap();
+
+            assert!(env.replay_journal(Path::new("db")).unwrap());
+            assert_eq!(dir_contents(&env, "db"), post_state());
+        }
+
+        // Crash after applying an operation but before recording it.
+        let env = setup();
+        let txn = version_edit_txn(&env);
+        let dir = txn.dir.clone();
+        let ops = stage(&env, &txn).unwrap();
+        publish(&env, &dir, &ops).unwrap();
+        apply(&env, &dir, &ops, 0, 1).unwrap();
+        apply_op(&env, &ops[1]).unwrap();
+        apply_op(&env, &ops[2]).unwrap();
+
+        assert!(env.replay_journal(Path::new("db")).unwrap());
+        assert_eq!(dir_contents(&env, "db"), post_state());
+    }
+
+    fn test_journal_encoding() {
+        let env = MemEnv::new();
+        let ops = vec![
+            JournalOp::Rename(PathBuf::from("db/a"), PathBuf::from("db/b")),
+            JournalOp::Delete(PathBuf::from("db/c")),
+        ];
+        publish(&env, Path::new("db"), &ops).unwrap();
+        let journal = journal_file_name(Path::new("db"));
+        assert_eq!(read_journal(&env, &journal).unwrap(), (ops.clone(), 0));
+
+        // A torn progress record is ignored.
+        let mut f = env.open_appendable_file(&journal).unwrap();
+        f.write_all(b"A\t0\nA\t").unwrap();
+        assert_eq!(read_journal(&env, &journal).unwrap(), (ops, 1));
+
+        let mut txn = Txn::new(Path::new("db"));
+        txn.delete(Path::new("db/with\ttab"));
+        assert!(stage(&env, &txn).is_err());
+    }
+}
@@ -24,6 +24,7 @@ pub enum StatusCode {
     NotFound,
     NotSupported,
     PermissionDenied,
+    TimedOut,
     Unknown,
     Errno(c_int),
 }
@@ -91,6 +92,7 @@ impl From<io::Error> for Status {
             io::ErrorKind::InvalidData => StatusCode::Corruption,
             io::ErrorKind::InvalidInput => StatusCode::InvalidArgument,
             io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+            io::ErrorKind::TimedOut => StatusCode::TimedOut,
             _ => StatusCode::IOError,
         };
 
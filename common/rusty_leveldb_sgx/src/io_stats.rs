@@ -0,0 +1,171 @@
+//! Latency histograms of the file operations of an env, for finding tail latency, e.g. caused by
+//! EPC paging inside the protected FS.
+
+use crate::env::RandomAccess;
+use crate::env_common::Clock;
+use crate::error::Result;
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Number of buckets of a `LatencyHistogram`. The last bucket collects everything from about 17
+/// minutes upwards.
+const NUM_BUCKETS: usize = 32;
+
+/// IoOp is a kind of file operation with its own latency histogram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoOp {
+    Open,
+    Read,
+    Write,
+    Rename,
+}
+
+/// LatencyHistogram counts operation durations in buckets of powers of two microseconds: bucket
+/// 0 holds durations of 0us, bucket i > 0 durations in [2^(i-1), 2^i) us.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; NUM_BUCKETS],
+    count: u64,
+    total_micros: u64,
+    max_micros: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, micros: u64) {
+        let bucket = (64 - micros.leading_zeros() as usize).min(NUM_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_micros = self.total_micros.saturating_add(micros);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn total_micros(&self) -> u64 {
+        self.total_micros
+    }
+
+    pub fn max_micros(&self) -> u64 {
+        self.max_micros
+    }
+
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// upper_bound returns the smallest duration in microseconds which no longer falls into
+    /// `bucket`.
+    pub fn upper_bound(bucket: usize) -> u64 {
+        1u64 << bucket.min(63)
+    }
+
+    /// percentile returns an upper bound of the `p`th percentile (0-100) of the recorded
+    /// durations, or 0 if nothing was recorded.
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank.max(1) {
+                return Self::upper_bound(bucket).min(self.max_micros.max(1));
+            }
+        }
+        self.max_micros
+    }
+}
+
+/// IoStats holds the latency histograms of the file operations of an env.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub open: LatencyHistogram,
+    pub read: LatencyHistogram,
+    pub write: LatencyHistogram,
+    pub rename: LatencyHistogram,
+}
+
+impl IoStats {
+    pub fn histogram(&self, op: IoOp) -> &LatencyHistogram {
+        match op {
+            IoOp::Open => &self.open,
+            IoOp::Read => &self.read,
+            IoOp::Write => &self.write,
+            IoOp::Rename => &self.rename,
+        }
+    }
+
+    fn histogram_mut(&mut self, op: IoOp) -> &mut LatencyHistogram {
+        match op {
+            IoOp::Open => &mut self.open,
+            IoOp::Read => &mut self.read,
+            IoOp::Write => &mut self.write,
+            IoOp::Rename => &mut self.rename,
+        }
+    }
+}
+
+/// IoRecorder times operations against the clock of an env and records them in shared stats.
+#[derive(Clone)]
+pub struct IoRecorder {
+    stats: Arc<Mutex<IoStats>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl IoRecorder {
+    pub fn new(stats: Arc<Mutex<IoStats>>, clock: Arc<dyn Clock>) -> IoRecorder {
+        IoRecorder { stats, clock }
+    }
+
+    /// time runs `f` and records its duration under `op`. The clock may step backwards, in which
+    /// case the duration is recorded as 0.
+    pub fn time<T, F: FnOnce() -> T>(&self, op: IoOp, f: F) -> T {
+        let start = self.clock.micros();
+        let result = f();
+        let elapsed = self.clock.micros().saturating_sub(start);
+        self.stats.lock().unwrap().histogram_mut(op).record(elapsed);
+        result
+    }
+}
+
+/// TimedFile records the duration of every read and write of the wrapped file.
+pub struct TimedFile<F> {
+    inner: F,
+    recorder: IoRecorder,
+}
+
+impl<F> TimedFile<F> {
+    pub fn new(inner: F, recorder: IoRecorder) -> TimedFile<F> {
+        TimedFile { inner, recorder }
+    }
+}
+
+impl<F: Read> Read for TimedFile<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.recorder.time(IoOp::Read, || inner.read(buf))
+    }
+}
+
+impl<F: Write> Write for TimedFile<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.recorder.time(IoOp::Write, || inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.recorder.time(IoOp::Write, || inner.flush())
+    }
+}
+
+impl<F: RandomAccess> RandomAccess for TimedFile<F> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        self.recorder
+            .time(IoOp::Read, || self.inner.read_at(off, dst))
+    }
+}
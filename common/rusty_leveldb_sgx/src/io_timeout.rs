@@ -0,0 +1,434 @@
+//! Per-operation timeouts of the file operations of an env, so that a stalled protected FS fails
+//! the operation instead of blocking its thread forever.
+//!
+//! An operation which runs into its timeout is abandoned, not cancelled: it goes on running on
+//! its pool thread, and may still complete, complete partially, or never return. Since the
+//! position and contents of such a file are unknown, it refuses all further operations with
+//! `TimedOut`. After an abandoned write the file may hold none, some or all of the bytes, like a
+//! file torn by a crash: log recovery drops a torn tail record, and a table whose write timed out
+//! never makes it into the version set, but the data of the failed write is not guaranteed to
+//! be either absent or present.
+
+use crate::env::RandomAccess;
+use crate::error::{err, Result, StatusCode};
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// IO_POOL_THREADS is the number of threads an env with timeouts and its clones run file
+/// operations on. The enclaves have 22 TCSs (`TCSNum` in Enclave.config.xml) for all of their
+/// threads, the RPC threads of the service and the function threads of the executor included, and
+/// an operation stalled in the protected FS holds the TCS of its thread until it returns. The pool
+/// therefore keeps to a few of them.
+pub const IO_POOL_THREADS: usize = 4;
+
+/// Time an idle pool thread waits for work before it exits and frees its TCS.
+const IDLE_THREAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct PoolState {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+}
+
+/// IoPool runs jobs on at most `max_threads` threads, started when there is work and no idle
+/// thread. Jobs wait in a queue while all threads are busy, stalled ones included.
+struct IoPool {
+    max_threads: usize,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+impl IoPool {
+    fn submit(self: &Arc<Self>, op: &str, job: Job) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back(job);
+        if state.idle > 0 || state.threads >= self.max_threads {
+            self.available.notify_one();
+            return Ok(());
+        }
+        let pool = self.clone();
+        match thread::Builder::new().spawn(move || pool.work()) {
+            Ok(_) => {
+                state.threads += 1;
+                Ok(())
+            }
+            // The job still runs once one of the running threads is free.
+            Err(_) if state.threads > 0 => Ok(()),
+            Err(e) => {
+                state.jobs.pop_back();
+                err(
+                    StatusCode::IOError,
+                    &format!("{}: cannot start io thread: {}", op, e),
+                )
+            }
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state.idle += 1;
+            let (next, wait) = self
+                .available
+                .wait_timeout(state, IDLE_THREAD_TIMEOUT)
+                .unwrap();
+            state = next;
+            state.idle -= 1;
+            if wait.timed_out() && state.jobs.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+/// IoTimeout runs file operations on a bounded pool of threads and waits up to its timeout for
+/// each of them. Clones share the pool.
+#[derive(Clone)]
+pub struct IoTimeout {
+    timeout: Duration,
+    pool: Arc<IoPool>,
+}
+
+impl IoTimeout {
+    /// new creates a timeout of `timeout` for operations run on at most `threads` threads.
+    pub fn new(timeout: Duration, threads: usize) -> IoTimeout {
+        IoTimeout {
+            timeout,
+            pool: Arc::new(IoPool {
+                max_threads: threads.max(1),
+                state: Mutex::new(PoolState {
+                    jobs: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                }),
+                available: Condvar::new(),
+            }),
+        }
+    }
+
+    /// run runs `f` on a thread of the pool and waits up to the timeout for its result. If the
+    /// timeout passes first, `f` is abandoned and a `TimedOut` status naming `op` is returned.
+    ///
+    /// An abandoned `f` keeps its thread until it returns. While every thread of the pool is
+    /// held that way, further operations wait in the queue and time out in turn; those never
+    /// run.
+    pub fn run<T, F>(&self, op: &str, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let skip = abandoned.clone();
+        self.pool.submit(
+            op,
+            Box::new(move || {
+                if skip.load(Ordering::SeqCst) {
+                    return;
+                }
+                // A panic drops the sender, which the caller reports.
+                if let Ok(result) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                    let _ = sender.send(result);
+                }
+            }),
+        )?;
+        match receiver.recv_timeout(self.timeout) {
+            Ok(result) => Ok(result),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                abandoned.store(true, Ordering::SeqCst);
+                err(
+                    StatusCode::TimedOut,
+                    &format!("{} did not complete within {:?}", op, self.timeout),
+                )
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                err(StatusCode::IOError, &format!("{} panicked", op))
+            }
+        }
+    }
+}
+
+/// TimeoutFile abandons reads and writes of the wrapped file which take longer than its timeout.
+pub struct TimeoutFile<F> {
+    inner: Arc<Mutex<F>>,
+    timeout: IoTimeout,
+    abandoned: AtomicBool,
+}
+
+impl<F: Send + 'static> TimeoutFile<F> {
+    pub fn new(inner: F, timeout: IoTimeout) -> TimeoutFile<F> {
+        TimeoutFile {
+            inner: Arc::new(Mutex::new(inner)),
+            timeout,
+            abandoned: AtomicBool::new(false),
+        }
+    }
+
+    fn run<T, O>(&self, op: &str, f: O) -> io::Result<T>
+    where
+        T: Send + 'static,
+        O: FnOnce(&mut F) -> io::Result<T> + Send + 'static,
+    {
+        if self.abandoned.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} on a file with an abandoned operation", op),
+            ));
+        }
+        let inner = self.inner.clone();
+        let result = self.timeout.run(op, move || match inner.lock() {
+            Ok(mut file) => f(&mut file),
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "file lock poisoned")),
+        });
+        match result {
+            Ok(result) => result,
+            Err(status) => {
+                if status.code == StatusCode::TimedOut {
+                    self.abandoned.store(true, Ordering::SeqCst);
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("{} did not complete within {:?}", op, self.timeout.timeout),
+                    ));
+                }
+                Err(io::Error::new(io::ErrorKind::Other, status.err))
+            }
+        }
+    }
+}
+
+impl<F: Read + Send + 'static> Read for TimeoutFile<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len();
+        let data = self.run("read", move |file| {
+            let mut data = vec![0; len];
+            let n = file.read(&mut data)?;
+            data.truncate(n);
+            Ok(data)
+        })?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl<F: Write + Send + 'static> Write for TimeoutFile<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let data = buf.to_vec();
+        self.run("write", move |file| file.write(&data))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.run("flush", |file| file.flush())
+    }
+}
+
+impl<F: RandomAccess + Send + 'static> RandomAccess for TimeoutFile<F> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let len = dst.len();
+        let data = self.run("read_at", move |file| {
+            let mut data = vec![0; len];
+            let n = file
+                .read_at(off, &mut data)
+                .map_err(|s| io::Error::new(io::ErrorKind::Other, s.err))?;
+            data.truncate(n);
+            Ok(data)
+        })?;
+        dst[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::error::Status;
+    use std::time::Instant;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_with_timeout,
+            test_pool_bound,
+            test_timeout_file_fast_backend,
+            test_timeout_file_slow_backend,
+            test_abandoned_write,
+        )
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+    const STALL: Duration = Duration::from_millis(500);
+
+    /// SlowFile stands in for a protected FS whose every operation stalls for `delay`.
+    struct SlowFile {
+        delay: Duration,
+        data: Vec<u8>,
+        pos: usize,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl SlowFile {
+        fn new(delay: Duration, data: &[u8]) -> SlowFile {
+            SlowFile {
+                delay,
+                data: data.to_vec(),
+                pos: 0,
+                written: Arc::new(Mutex::new(vec![])),
+            }
+        }
+    }
+
+    impl Read for SlowFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            let n = buf.len().min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for SlowFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RandomAccess for SlowFile {
+        fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+            thread::sleep(self.delay);
+            let data = self.data.get(off..).unwrap_or_default();
+            let n = dst.len().min(data.len());
+            dst[..n].copy_from_slice(&data[..n]);
+            Ok(n)
+        }
+    }
+
+    fn timeout(duration: Duration) -> IoTimeout {
+        IoTimeout::new(duration, IO_POOL_THREADS)
+    }
+
+    fn test_with_timeout() {
+        let t = timeout(TIMEOUT);
+        assert_eq!(t.run("open", || 42).unwrap(), 42);
+
+        let start = Instant::now();
+        let status = t.run("open", || thread::sleep(STALL)).unwrap_err();
+        assert!(start.elapsed() < STALL);
+        assert_eq!(status.code, StatusCode::TimedOut);
+        assert_eq!(
+            status.err,
+            format!("TimedOut: open did not complete within {:?}", TIMEOUT)
+        );
+
+        let status = t.run("open", || -> u8 { panic!("stall") }).unwrap_err();
+        assert_eq!(status.code, StatusCode::IOError);
+    }
+
+    fn threads(t: &IoTimeout) -> usize {
+        t.pool.state.lock().unwrap().threads
+    }
+
+    fn test_pool_bound() {
+        let t = IoTimeout::new(TIMEOUT, 1);
+        assert_eq!(t.run("read", || 1).unwrap(), 1);
+        assert_eq!(threads(&t), 1);
+
+        // The only thread stalls, so the next operation waits in the queue, times out and is
+        // dropped without running, and no thread is added.
+        assert_eq!(
+            t.run("write", || thread::sleep(STALL)).unwrap_err().code,
+            StatusCode::TimedOut
+        );
+        let ran = Arc::new(AtomicBool::new(false));
+        let r = ran.clone();
+        assert_eq!(
+            t.run("read", move || r.store(true, Ordering::SeqCst))
+                .unwrap_err()
+                .code,
+            StatusCode::TimedOut
+        );
+        assert_eq!(threads(&t), 1);
+
+        thread::sleep(STALL);
+        assert_eq!(t.run("read", || 2).unwrap(), 2);
+        assert!(!ran.load(Ordering::SeqCst));
+        assert_eq!(threads(&t), 1);
+    }
+
+    fn test_timeout_file_fast_backend() {
+        let mut f = TimeoutFile::new(
+            SlowFile::new(Duration::from_millis(0), b"Hello"),
+            timeout(STALL),
+        );
+        let mut content = vec![];
+        f.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"Hello");
+        let mut buf = [0u8; 3];
+        assert_eq!(f.read_at(1, &mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"ell");
+        f.write_all(b"World").unwrap();
+        f.flush().unwrap();
+    }
+
+    fn test_timeout_file_slow_backend() {
+        let mut f = TimeoutFile::new(SlowFile::new(STALL, b"Hello"), timeout(TIMEOUT));
+        let start = Instant::now();
+        let mut buf = [0u8; 5];
+        let e = f.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(Status::from(e).code, StatusCode::TimedOut);
+
+        // Once an operation is abandoned, the file refuses the following ones right away.
+        assert_eq!(
+            f.write(b"World").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(
+            f.read_at(0, &mut buf).unwrap_err().code,
+            StatusCode::TimedOut
+        );
+        assert!(start.elapsed() < STALL);
+
+        let f = TimeoutFile::new(SlowFile::new(STALL, b"Hello"), timeout(TIMEOUT));
+        assert_eq!(
+            f.read_at(0, &mut buf).unwrap_err().code,
+            StatusCode::TimedOut
+        );
+    }
+
+    fn test_abandoned_write() {
+        let slow = SlowFile::new(Duration::from_millis(200), b"");
+        let written = slow.written.clone();
+        let mut f = TimeoutFile::new(slow, timeout(TIMEOUT));
+        assert_eq!(
+            f.write(b"late").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(written.lock().unwrap().is_empty());
+
+        // The abandoned write still lands once the backend recovers.
+        thread::sleep(STALL);
+        assert_eq!(*written.lock().unwrap(), b"late");
+    }
+}
@@ -41,17 +41,21 @@ mod cmp;
 mod disk_env;
 mod env;
 mod env_common;
+mod env_journal;
 mod error;
 mod filter;
 mod filter_block;
 #[macro_use]
 mod infolog;
+mod io_stats;
+mod io_timeout;
 mod key_types;
 mod log;
 mod mem_env;
 mod memtable;
 mod merging_iter;
 mod options;
+mod read_cache;
 mod skipmap;
 mod snapshot;
 mod table_block;
@@ -70,16 +74,21 @@ mod db_iter;
 
 pub use crate::cmp::{Cmp, DefaultCmp};
 pub use crate::db_iter::DBIterator;
-pub use crate::env::Env;
+pub use crate::env::{Access, ChecksumAlgo, Env, LogLevel, LoggerOptions, SortOrder, TreeDiff};
+pub use crate::env_common::{Clock, SystemClock};
+pub use crate::env_journal::Txn;
 pub use crate::error::{Result, Status, StatusCode};
 pub use crate::filter::{BloomPolicy, FilterPolicy};
+pub use crate::io_stats::{IoOp, IoStats, LatencyHistogram};
+pub use crate::io_timeout::IO_POOL_THREADS;
 pub use crate::mem_env::MemEnv;
 pub use crate::options::{in_memory, CompressionType, Options};
+pub use crate::read_cache::{ReadCacheStats, READ_CACHE_BLOCK_SIZE};
 pub use crate::skipmap::SkipMap;
 pub use crate::types::LdbIterator;
 pub use crate::write_batch::WriteBatch;
 pub use db_impl::DB;
-pub use disk_env::PosixDiskEnv;
+pub use disk_env::{derive_tenant_key, PosixDiskEnv, TenantKeyInfo};
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
@@ -96,14 +105,17 @@ pub mod tests {
             db_impl::tests::run_tests(),
             db_iter::tests::run_tests(),
             disk_env::tests::run_tests(),
+            env_journal::tests::run_tests(),
             error::tests::run_tests(),
             filter::tests::run_tests(),
             filter_block::tests::run_tests(),
+            io_timeout::tests::run_tests(),
             key_types::tests::run_tests(),
             log::tests::run_tests(),
             mem_env::tests::run_tests(),
             memtable::tests::run_tests(),
             merging_iter::tests::run_tests(),
+            read_cache::tests::run_tests(),
             skipmap::tests::run_tests(),
             snapshot::tests::run_tests(),
             table_builder::tests::run_tests(),
@@ -1,7 +1,7 @@
 //! An in-memory implementation of Env.
 
-use crate::env::{path_to_str, path_to_string, Env, FileLock, Logger, RandomAccess};
-use crate::env_common::micros;
+use crate::env::{path_to_str, path_to_string, Env, FileLock, Logger, LoggerOptions, RandomAccess};
+use crate::env_common::{micros, SystemClock};
 use crate::error::{err, Result, StatusCode};
 
 use std::collections::hash_map::Entry;
@@ -186,6 +186,22 @@ impl MemFS {
         }
         Ok(children)
     }
+    fn snapshot_sizes_(&self, p: &Path) -> Result<HashMap<PathBuf, usize>> {
+        let fs = self.store.lock()?;
+        let mut prefix = path_to_string(p);
+        let main_separator_str = std::path::MAIN_SEPARATOR.to_string();
+        if !prefix.ends_with(&main_separator_str) {
+            prefix.push(std::path::MAIN_SEPARATOR);
+        }
+
+        let mut sizes = HashMap::new();
+        for (k, e) in fs.iter() {
+            if let Some(child) = k.strip_prefix(&prefix) {
+                sizes.insert(Path::new(child).to_owned(), e.f.0.lock()?.len());
+            }
+        }
+        Ok(sizes)
+    }
     fn size_of_(&self, p: &Path) -> Result<usize> {
         let mut fs = self.store.lock()?;
         match fs.entry(path_to_string(p)) {
@@ -250,6 +266,10 @@ impl MemFS {
             }
         }
     }
+    fn is_locked_(&self, p: &Path) -> bool {
+        let fs = self.store.lock().unwrap();
+        fs.get(path_to_str(p)).map_or(false, |e| e.locked)
+    }
     fn unlock_(&self, l: FileLock) -> Result<()> {
         let mut fs = self.store.lock()?;
         let id = l.id.clone();
@@ -309,6 +329,9 @@ impl Env for MemEnv {
     fn size_of(&self, p: &Path) -> Result<usize> {
         self.0.size_of_(p)
     }
+    fn snapshot_sizes(&self, root: &Path) -> Result<HashMap<PathBuf, usize>> {
+        self.0.snapshot_sizes_(root)
+    }
 
     fn delete(&self, p: &Path) -> Result<()> {
         self.0.delete_(p)
@@ -337,14 +360,17 @@ impl Env for MemEnv {
     fn unlock(&self, p: FileLock) -> Result<()> {
         self.0.unlock_(p)
     }
+    fn is_locked(&self, p: &Path) -> bool {
+        self.0.is_locked_(p)
+    }
 
     fn micros(&self) -> u64 {
         micros()
     }
 
-    fn new_logger(&self, p: &Path) -> Result<Logger> {
+    fn new_logger(&self, p: &Path, options: LoggerOptions) -> Result<Logger> {
         self.open_appendable_file(p)
-            .map(|dst| Logger::new(Box::new(dst)))
+            .map(|dst| Logger::with_options(Box::new(dst), options, Arc::new(SystemClock)))
     }
 }
 
@@ -359,12 +385,23 @@ pub mod tests {
             test_mem_fs_memfile_read,
             test_mem_fs_memfile_write,
             test_mem_fs_memfile_readat,
+            test_mem_fs_memfile_read_across_eof,
             test_mem_fs_open_read_write,
             test_mem_fs_open_read_write_append_truncate,
             test_mem_fs_metadata_operations,
             test_mem_fs_children,
+            test_mem_fs_snapshot_sizes,
             test_mem_fs_lock,
             test_memenv_all,
+            test_memenv_checksum,
+            test_memenv_children_sorted,
+            test_memenv_append_batch,
+            test_memenv_verify_digest,
+            test_memenv_swap,
+            test_memenv_usage_by_extension,
+            test_memenv_health_check,
+            test_memenv_diff_trees,
+            test_memenv_transform_file,
         )
     }
 
@@ -418,6 +455,25 @@ pub mod tests {
         assert_eq!(buf2, [1, 2, 3, 4, 5, 0]);
     }
 
+    fn test_mem_fs_memfile_read_across_eof() {
+        let f = new_memfile(vec![1, 2, 3, 4, 5]);
+
+        // A read across the end returns the bytes up to it.
+        let mut buf = [0; 4];
+        assert_eq!(f.read_at(3, &mut buf).unwrap(), 2);
+        assert_eq!(buf, [4, 5, 0, 0]);
+        assert_eq!(f.read_at(7, &mut buf).unwrap(), 0);
+
+        let e = f.read_exact_at(3, &mut buf).unwrap_err();
+        assert_eq!(e.code, StatusCode::IOError);
+        assert!(e.err.contains("read 2 of 4 bytes"));
+
+        let mut buf = [0; 3];
+        assert!(f.read_exact_at(2, &mut buf).is_ok());
+        assert_eq!(buf, [3, 4, 5]);
+        assert!(f.read_exact_at(5, &mut []).is_ok());
+    }
+
     fn test_mem_fs_open_read_write() {
         let fs = MemFS::new();
         let path = Path::new("/a/b/hello.txt");
@@ -538,6 +594,19 @@ pub mod tests {
         );
     }
 
+    fn test_mem_fs_snapshot_sizes() {
+        let fs = MemFS::new();
+        for (p, contents) in &[("/a/1.txt", "x"), ("/a/b/2.txt", "xyz"), ("/c/3.txt", "xy")] {
+            let mut w = fs.open_w(Path::new(p), false, false).unwrap();
+            write!(w, "{}", contents).unwrap();
+        }
+
+        let sizes = fs.snapshot_sizes_(&Path::new("/a")).unwrap();
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes.get(&s2p("1.txt")), Some(&1));
+        assert_eq!(sizes.get(&s2p("b/2.txt")), Some(&3));
+    }
+
     fn test_mem_fs_lock() {
         let fs = MemFS::new();
         let p = Path::new("/a/lock");
@@ -550,9 +619,12 @@ pub mod tests {
         // Locking on new file.
         let lock = fs.lock_(p).unwrap();
         assert!(fs.lock_(p).is_err());
+        assert!(fs.is_locked_(p));
+        assert!(!fs.is_locked_(Path::new("/a/other")));
 
         // Unlock of locked file is ok.
         assert!(fs.unlock_(lock).is_ok());
+        assert!(!fs.is_locked_(p));
 
         // Lock of unlocked file is ok.
         let lock = fs.lock_(p).unwrap();
@@ -603,7 +675,316 @@ pub mod tests {
         me.unlock(me.lock(p3).unwrap()).unwrap();
         assert!(me.lock(nonexist).is_ok());
 
-        me.new_logger(p1).unwrap();
+        me.new_logger(p1, LoggerOptions::default()).unwrap();
         assert!(me.micros() > 0);
     }
+
+    fn test_memenv_checksum() {
+        let me = MemEnv::new();
+        let (p1, p2) = (Path::new("/a/1.ldb"), Path::new("/a/2.ldb"));
+        for p in &[p1, p2] {
+            let mut f = me.open_writable_file(p).unwrap();
+            f.write_all(b"123456789").unwrap();
+        }
+
+        // Standard CRC-32C check value.
+        let sum = me.checksum(p1, env::ChecksumAlgo::Crc32c).unwrap();
+        assert_eq!(sum, 0xe306_9283);
+        assert_eq!(me.checksum(p2, env::ChecksumAlgo::Crc32c).unwrap(), sum);
+
+        {
+            let mut f = me.open_writable_file(p2).unwrap();
+            f.write_all(b"123456780").unwrap();
+        }
+        assert_ne!(me.checksum(p2, env::ChecksumAlgo::Crc32c).unwrap(), sum);
+
+        // Files larger than the read buffer are streamed in several reads.
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        me.open_writable_file(p1).unwrap().write_all(&data).unwrap();
+        let streamed = me.checksum(p1, env::ChecksumAlgo::Crc32c).unwrap();
+        let mut digest = crc::crc32::Digest::new(crc::crc32::CASTAGNOLI);
+        crc::Hasher32::write(&mut digest, &data);
+        assert_eq!(streamed, u64::from(digest.sum32()));
+
+        assert!(me
+            .checksum(Path::new("/x/y"), env::ChecksumAlgo::Crc32c)
+            .is_err());
+    }
+
+    fn test_memenv_children_sorted() {
+        let me = MemEnv::new();
+        for name in &[
+            "10.log",
+            "9.log",
+            "000123.sst",
+            "CURRENT",
+            "LOCK",
+            "000011.ldb",
+        ] {
+            me.open_writable_file(&Path::new("/db").join(name)).unwrap();
+        }
+        let names = |order| {
+            me.children_sorted(Path::new("/db"), order)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(env::SortOrder::Numeric),
+            vec![
+                "9.log",
+                "10.log",
+                "000011.ldb",
+                "000123.sst",
+                "CURRENT",
+                "LOCK"
+            ]
+        );
+        assert_eq!(
+            names(env::SortOrder::Lexical),
+            vec![
+                "000011.ldb",
+                "000123.sst",
+                "10.log",
+                "9.log",
+                "CURRENT",
+                "LOCK"
+            ]
+        );
+    }
+
+    fn test_memenv_append_batch() {
+        let me = MemEnv::new();
+        let p = Path::new("/db/000003.log");
+        me.open_writable_file(p)
+            .unwrap()
+            .write_all(b"head;")
+            .unwrap();
+
+        let records: [&[u8]; 3] = [b"first;", b"", b"second;"];
+        assert_eq!(me.append_batch(p, &records).unwrap(), 13);
+        assert_eq!(me.append_batch(p, &[b"third"]).unwrap(), 5);
+        assert_eq!(me.append_batch(p, &[]).unwrap(), 0);
+
+        let mut content = vec![];
+        me.open_sequential_file(p)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"head;first;second;third");
+    }
+
+    fn test_memenv_verify_digest() {
+        let me = MemEnv::new();
+        let p = Path::new("/a/000005.ldb");
+        // Larger than the read buffer, so the file is streamed in several reads.
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        me.open_writable_file(p).unwrap().write_all(&data).unwrap();
+
+        let mut expected = [0; 32];
+        expected.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, &data).as_ref());
+        assert!(me.verify_digest(p, &expected).unwrap());
+
+        let mut mismatching = expected;
+        mismatching[31] ^= 1;
+        assert!(!me.verify_digest(p, &mismatching).unwrap());
+
+        // SHA-256 of the empty string.
+        let empty = Path::new("/a/000006.ldb");
+        me.open_writable_file(empty).unwrap();
+        let mut expected = [0; 32];
+        hex_decode(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            &mut expected,
+        );
+        assert!(me.verify_digest(empty, &expected).unwrap());
+
+        assert!(me
+            .verify_digest(Path::new("/a/missing"), &expected)
+            .is_err());
+    }
+
+    fn hex_decode(s: &str, out: &mut [u8]) {
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+    }
+
+    fn test_memenv_swap() {
+        let me = MemEnv::new();
+        let live = Path::new("/index/live");
+        let candidate = Path::new("/index/candidate");
+        me.open_writable_file(live)
+            .unwrap()
+            .write_all(b"version 1")
+            .unwrap();
+        me.open_writable_file(candidate)
+            .unwrap()
+            .write_all(b"version 2 is longer")
+            .unwrap();
+
+        let read = |p: &Path| {
+            let mut content = vec![];
+            me.open_sequential_file(p)
+                .unwrap()
+                .read_to_end(&mut content)
+                .unwrap();
+            content
+        };
+
+        me.swap(live, candidate).unwrap();
+        assert_eq!(read(live), b"version 2 is longer");
+        assert_eq!(read(candidate), b"version 1");
+        // The temporary copies are renamed over the two files, so none is left behind.
+        assert_eq!(me.children(Path::new("/index")).unwrap().len(), 2);
+
+        me.swap(candidate, live).unwrap();
+        assert_eq!(read(live), b"version 1");
+        assert_eq!(read(candidate), b"version 2 is longer");
+
+        me.swap(live, live).unwrap();
+        assert_eq!(read(live), b"version 1");
+
+        let missing = Path::new("/index/missing");
+        assert_eq!(
+            me.swap(live, missing).unwrap_err().code,
+            StatusCode::NotFound
+        );
+        assert_eq!(read(live), b"version 1");
+        assert!(!me.exists(missing).unwrap());
+    }
+
+    fn test_memenv_usage_by_extension() {
+        let me = MemEnv::new();
+        for (p, len) in &[
+            ("/db/000005.ldb", 100),
+            ("/db/000007.ldb", 50),
+            ("/db/000006.log", 30),
+            ("/db/MANIFEST-000004", 20),
+            ("/db/CURRENT", 16),
+            ("/db/backup/000001.ldb", 8),
+            ("/other/000002.ldb", 1000),
+        ] {
+            me.open_writable_file(Path::new(p))
+                .unwrap()
+                .write_all(&vec![0; *len])
+                .unwrap();
+        }
+
+        let usage = me.usage_by_extension(Path::new("/db")).unwrap();
+        assert_eq!(usage.len(), 3);
+        assert_eq!(usage.get("ldb"), Some(&158));
+        assert_eq!(usage.get("log"), Some(&30));
+        assert_eq!(usage.get(""), Some(&36));
+    }
+
+    fn test_memenv_health_check() {
+        let me = MemEnv::new();
+        let root = Path::new("/db");
+        me.open_writable_file(&root.join("CURRENT")).unwrap();
+
+        assert!(me.health_check(root).is_ok());
+        assert!(me.health_check(root).is_ok());
+        assert_eq!(me.children(root).unwrap(), vec![PathBuf::from("CURRENT")]);
+    }
+
+    fn test_memenv_diff_trees() {
+        let (src, dst) = (MemEnv::new(), MemEnv::new());
+        let write = |env: &MemEnv, p: &str, contents: &[u8]| {
+            env.open_writable_file(Path::new(p))
+                .unwrap()
+                .write_all(contents)
+                .unwrap();
+        };
+        for (p, contents) in &[
+            ("CURRENT", &b"MANIFEST-000004"[..]),
+            ("sub/000005.ldb", b"same table"),
+        ] {
+            write(&src, &format!("/db/{}", p), contents);
+            write(&dst, &format!("/backup/{}", p), contents);
+        }
+        // Same size, different contents: only the digest tells them apart.
+        write(&src, "/db/000007.ldb", b"same size a");
+        write(&dst, "/backup/000007.ldb", b"same size b");
+        write(&src, "/db/000006.log", b"shorter");
+        write(&dst, "/backup/000006.log", b"longer log");
+        write(&src, "/db/LOCK", b"");
+        write(&dst, "/backup/LOG.old", b"old log");
+
+        let diff = src
+            .diff_trees(&dst, Path::new("/db"), Path::new("/backup"))
+            .unwrap();
+        assert_eq!(diff.only_in_source, vec![PathBuf::from("LOCK")]);
+        assert_eq!(diff.only_in_dest, vec![PathBuf::from("LOG.old")]);
+        assert_eq!(
+            diff.differing,
+            vec![PathBuf::from("000006.log"), PathBuf::from("000007.ldb")]
+        );
+        assert!(!diff.is_empty());
+
+        assert!(src
+            .diff_trees(&src, Path::new("/db"), Path::new("/db"))
+            .unwrap()
+            .is_empty());
+    }
+
+    fn test_memenv_transform_file() {
+        let me = MemEnv::new();
+        let (src, dst) = (Path::new("/data/in.txt"), Path::new("/data/out.txt"));
+        let contents: Vec<u8> = b"hello teaclave\n".repeat(10_000);
+        me.open_writable_file(src)
+            .unwrap()
+            .write_all(&contents)
+            .unwrap();
+        let read = |p: &Path| {
+            let mut content = Vec::new();
+            me.open_sequential_file(p)
+                .unwrap()
+                .read_to_end(&mut content)
+                .unwrap();
+            content
+        };
+
+        let mut blocks = 0;
+        let counts = me
+            .transform_file(src, dst, &mut |block, out| {
+                blocks += 1;
+                out.extend_from_slice(block)
+            })
+            .unwrap();
+        assert_eq!(counts, (contents.len(), contents.len()));
+        assert_eq!(read(dst), contents);
+        assert!(blocks > 1);
+
+        let counts = me
+            .transform_file(src, dst, &mut |block, out| {
+                out.extend(block.iter().map(u8::to_ascii_uppercase))
+            })
+            .unwrap();
+        assert_eq!(counts, (contents.len(), contents.len()));
+        assert_eq!(read(dst), contents.to_ascii_uppercase());
+
+        // Dropping the letter l makes the output smaller than the input.
+        let counts = me
+            .transform_file(src, dst, &mut |block, out| {
+                out.extend(block.iter().filter(|b| **b != b'l'))
+            })
+            .unwrap();
+        assert_eq!(counts, (contents.len(), 130_000));
+        // The temp file was renamed to the destination.
+        assert_eq!(me.children(Path::new("/data")).unwrap().len(), 2);
+
+        // A missing source leaves the destination alone.
+        let missing = Path::new("/data/missing.txt");
+        assert_eq!(
+            me.transform_file(missing, dst, &mut |_, _| {})
+                .unwrap_err()
+                .code,
+            StatusCode::NotFound
+        );
+        assert_eq!(read(dst).len(), 130_000);
+        assert_eq!(me.children(Path::new("/data")).unwrap().len(), 2);
+    }
 }
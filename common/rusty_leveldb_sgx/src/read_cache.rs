@@ -0,0 +1,543 @@
+//! Block cache of the files read at random through an env, so that repeated reads of the same
+//! ranges, e.g. of the index and filter blocks of a table, skip the protected FS and its
+//! decryption, and readahead of the files read sequentially.
+
+use crate::env::RandomAccess;
+use crate::error::Result;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Granularity of a `ReadCache`: reads are served from, and fill, aligned blocks of this size.
+pub const READ_CACHE_BLOCK_SIZE: usize = 4096;
+
+/// Size of the reads a `ReadaheadFile` issues to the wrapped file.
+pub const READAHEAD_SIZE: usize = 64 * 1024;
+
+/// ReadCacheStats counts the blocks a `ReadCache` served (hits) and had to read (misses).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Block of a file: the id the cache gave the file, and the index of the block within it.
+type BlockKey = (u64, u64);
+
+/// A file known to the cache: its id, the handles open on it and the number of its cached blocks.
+/// Files with neither handles nor blocks are dropped.
+struct CachedPath {
+    id: u64,
+    handles: usize,
+    blocks: usize,
+}
+
+#[derive(Default)]
+struct CacheState {
+    files: HashMap<PathBuf, CachedPath>,
+    /// The path of every live file id, i.e. of every id in `files`.
+    paths: HashMap<u64, PathBuf>,
+    next_file_id: u64,
+    /// Cached blocks with the tick of their last use.
+    blocks: HashMap<BlockKey, (Arc<Vec<u8>>, u64)>,
+    /// The keys of `blocks` by the tick of their last use, least recently used first.
+    lru: BTreeMap<u64, BlockKey>,
+    tick: u64,
+    stats: ReadCacheStats,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: BlockKey) -> Option<Arc<Vec<u8>>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (block, last_used) = self.blocks.get_mut(&key)?;
+        self.lru.remove(last_used);
+        *last_used = tick;
+        self.lru.insert(tick, key);
+        Some(block.clone())
+    }
+
+    fn file_mut(&mut self, id: u64) -> Option<&mut CachedPath> {
+        let path = self.paths.get(&id)?;
+        self.files.get_mut(path)
+    }
+
+    /// prune drops the file `id` if it has neither open handles nor cached blocks.
+    fn prune(&mut self, id: u64) {
+        if let Some(file) = self.file_mut(id) {
+            if file.handles == 0 && file.blocks == 0 {
+                let path = self.paths.remove(&id).unwrap();
+                self.files.remove(&path);
+            }
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        let evicted = match self.lru.values().next() {
+            Some(&key) => key,
+            None => return,
+        };
+        let (_, last_used) = self.blocks.remove(&evicted).unwrap();
+        self.lru.remove(&last_used);
+        if let Some(file) = self.file_mut(evicted.0) {
+            file.blocks -= 1;
+        }
+        self.prune(evicted.0);
+    }
+
+    fn remove_file(&mut self, id: u64) {
+        let lru = &mut self.lru;
+        self.blocks.retain(|&(file, _), (_, last_used)| {
+            if file == id {
+                lru.remove(last_used);
+            }
+            file != id
+        });
+    }
+}
+
+/// ReadCache holds the most recently read blocks of all files of an env, up to a capacity in
+/// bytes. Files are told apart by path, so the env must invalidate a path whenever the file
+/// behind it is written, deleted or replaced. Files may still grow by appends, so only whole
+/// blocks are cached: the short block at the end of a file is read again every time.
+pub struct ReadCache {
+    capacity_blocks: usize,
+    state: Mutex<CacheState>,
+}
+
+impl ReadCache {
+    /// new makes a cache of at most `capacity` bytes, rounded down to whole blocks but holding at
+    /// least one.
+    pub fn new(capacity: usize) -> ReadCache {
+        ReadCache {
+            capacity_blocks: (capacity / READ_CACHE_BLOCK_SIZE).max(1),
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn stats(&self) -> ReadCacheStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// invalidate drops the cached blocks of `p`.
+    pub fn invalidate(&self, p: &Path) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(file) = state.files.remove(p) {
+            state.paths.remove(&file.id);
+            state.remove_file(file.id);
+        }
+    }
+
+    /// clear drops all cached blocks, e.g. after removing a directory or shutting down the env.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        // Ids are not reused, so open files of the dropped paths can't see blocks of new ones.
+        state.files.clear();
+        state.paths.clear();
+        state.blocks.clear();
+        state.lru.clear();
+    }
+
+    /// open_file returns the id of the file at `p` for a new handle of it.
+    fn open_file(&self, p: &Path) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        if let Some(file) = state.files.get_mut(p) {
+            file.handles += 1;
+            return file.id;
+        }
+        let id = state.next_file_id;
+        state.next_file_id += 1;
+        state.files.insert(
+            p.to_path_buf(),
+            CachedPath {
+                id,
+                handles: 1,
+                blocks: 0,
+            },
+        );
+        state.paths.insert(id, p.to_path_buf());
+        id
+    }
+
+    /// close_file releases a handle of the file `id`, which `open_file` returned.
+    fn close_file(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(file) = state.file_mut(id) {
+            file.handles -= 1;
+        }
+        state.prune(id);
+    }
+
+    /// block returns the block `key`, reading it with `read` on a miss. Reads run without
+    /// holding the cache, so two readers may read the same block at once.
+    fn block<F>(&self, key: BlockKey, read: F) -> Result<Arc<Vec<u8>>>
+    where
+        F: FnOnce() -> Result<Vec<u8>>,
+    {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(block) = state.touch(key) {
+                state.stats.hits += 1;
+                return Ok(block);
+            }
+            state.stats.misses += 1;
+        }
+
+        let block = Arc::new(read()?);
+        // A short block is the end of a file which a writer may still append to.
+        if block.len() < READ_CACHE_BLOCK_SIZE {
+            return Ok(block);
+        }
+        let mut state = self.state.lock().unwrap();
+        // Skip blocks of files which were invalidated meanwhile.
+        if state.paths.contains_key(&key.0) && state.touch(key).is_none() {
+            while !state.blocks.is_empty() && state.blocks.len() >= self.capacity_blocks {
+                state.evict_lru();
+            }
+            let tick = state.tick;
+            state.blocks.insert(key, (block.clone(), tick));
+            state.lru.insert(tick, key);
+            if let Some(file) = state.file_mut(key.0) {
+                file.blocks += 1;
+            }
+        }
+        Ok(block)
+    }
+}
+
+/// CachedFile serves the reads of the wrapped file from a `ReadCache`, reading whole blocks on
+/// misses.
+pub struct CachedFile<F> {
+    inner: F,
+    id: u64,
+    cache: Arc<ReadCache>,
+}
+
+impl<F: RandomAccess> CachedFile<F> {
+    /// new wraps `inner`, the file at `p`.
+    pub fn new(inner: F, p: &Path, cache: Arc<ReadCache>) -> CachedFile<F> {
+        let id = cache.open_file(p);
+        CachedFile { inner, id, cache }
+    }
+
+    fn read_block(&self, index: u64) -> Result<Vec<u8>> {
+        let mut block = vec![0; READ_CACHE_BLOCK_SIZE];
+        let off = index as usize * READ_CACHE_BLOCK_SIZE;
+        let mut filled = 0;
+        while filled < block.len() {
+            match self.inner.read_at(off + filled, &mut block[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        block.truncate(filled);
+        Ok(block)
+    }
+}
+
+impl<F> Drop for CachedFile<F> {
+    fn drop(&mut self) {
+        self.cache.close_file(self.id);
+    }
+}
+
+impl<F: RandomAccess> RandomAccess for CachedFile<F> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < dst.len() {
+            let pos = off + read;
+            let index = (pos / READ_CACHE_BLOCK_SIZE) as u64;
+            let block = self
+                .cache
+                .block((self.id, index), || self.read_block(index))?;
+            let start = pos % READ_CACHE_BLOCK_SIZE;
+            if start >= block.len() {
+                break;
+            }
+            let n = (block.len() - start).min(dst.len() - read);
+            dst[read..read + n].copy_from_slice(&block[start..start + n]);
+            read += n;
+            if block.len() < READ_CACHE_BLOCK_SIZE {
+                break;
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Window of a file read ahead, starting at offset `start`.
+#[derive(Default)]
+struct Window {
+    start: usize,
+    data: Vec<u8>,
+}
+
+impl Window {
+    fn contains(&self, pos: usize) -> bool {
+        pos >= self.start && pos < self.start + self.data.len()
+    }
+}
+
+/// ReadaheadFile reads the wrapped file in windows of `READAHEAD_SIZE` bytes and serves smaller
+/// reads from the last window, so that a scan through a file in small reads, e.g. of the blocks
+/// of a table, costs one read of the wrapped file per window. Unlike a `CachedFile` it keeps
+/// only one window per handle, so scans don't evict the blocks cached for point lookups.
+pub struct ReadaheadFile<F> {
+    inner: F,
+    window: Mutex<Window>,
+}
+
+impl<F: RandomAccess> ReadaheadFile<F> {
+    pub fn new(inner: F) -> ReadaheadFile<F> {
+        ReadaheadFile {
+            inner,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    /// fill reads the window starting at `pos`, which is shorter than `READAHEAD_SIZE` only at
+    /// the end of the file.
+    fn fill(&self, window: &mut Window, pos: usize) -> Result<()> {
+        window.start = pos;
+        window.data.resize(READAHEAD_SIZE, 0);
+        let mut filled = 0;
+        while filled < READAHEAD_SIZE {
+            match self.inner.read_at(pos + filled, &mut window.data[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) => {
+                    window.data.clear();
+                    return Err(e);
+                }
+            }
+        }
+        window.data.truncate(filled);
+        Ok(())
+    }
+}
+
+impl<F: RandomAccess> RandomAccess for ReadaheadFile<F> {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        // Reads of a whole window or more gain nothing from going through one.
+        if dst.len() >= READAHEAD_SIZE {
+            return self.inner.read_at(off, dst);
+        }
+        let mut window = self.window.lock().unwrap();
+        let mut read = 0;
+        while read < dst.len() {
+            let pos = off + read;
+            if !window.contains(pos) {
+                self.fill(&mut window, pos)?;
+                if window.data.is_empty() {
+                    break;
+                }
+            }
+            let start = pos - window.start;
+            let n = (window.data.len() - start).min(dst.len() - read);
+            dst[read..read + n].copy_from_slice(&window.data[start..start + n]);
+            read += n;
+            if window.data.len() < READAHEAD_SIZE && start + n == window.data.len() {
+                break;
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::mem_env::BufferBackedFile;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_cached_reads,
+            test_eviction_and_invalidation,
+            test_growing_file,
+            test_pruned_files,
+            test_readahead
+        )
+    }
+
+    fn contents(len: usize) -> BufferBackedFile {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn test_cached_reads() {
+        let cache = Arc::new(ReadCache::new(16 * READ_CACHE_BLOCK_SIZE));
+        let data = contents(3 * READ_CACHE_BLOCK_SIZE + 100);
+        let f = CachedFile::new(data.clone(), Path::new("a.ldb"), cache.clone());
+
+        // A read across a block boundary fills both blocks.
+        let mut buf = vec![0; 200];
+        let off = READ_CACHE_BLOCK_SIZE - 100;
+        assert_eq!(f.read_at(off, &mut buf).unwrap(), 200);
+        assert_eq!(&buf[..], &data[off..off + 200]);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 0, misses: 2 });
+
+        assert_eq!(f.read_at(off + 50, &mut buf[..100]).unwrap(), 100);
+        assert_eq!(&buf[..100], &data[off + 50..off + 150]);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 2, misses: 2 });
+
+        // Reads stop at the end of the file.
+        let off = 3 * READ_CACHE_BLOCK_SIZE + 50;
+        assert_eq!(f.read_at(off, &mut buf).unwrap(), 50);
+        assert_eq!(&buf[..50], &data[off..]);
+        assert_eq!(f.read_at(data.len() + 10, &mut buf).unwrap(), 0);
+
+        // Other handles of the same path share the blocks.
+        let g = CachedFile::new(data, Path::new("a.ldb"), cache.clone());
+        let hits = cache.stats().hits;
+        g.read_at(READ_CACHE_BLOCK_SIZE, &mut buf).unwrap();
+        assert_eq!(cache.stats().hits, hits + 1);
+    }
+
+    fn test_eviction_and_invalidation() {
+        let cache = Arc::new(ReadCache::new(2 * READ_CACHE_BLOCK_SIZE));
+        let f = CachedFile::new(
+            contents(4 * READ_CACHE_BLOCK_SIZE),
+            Path::new("b.ldb"),
+            cache.clone(),
+        );
+        let mut buf = [0u8; 1];
+        let read_block = |index: usize, buf: &mut [u8]| {
+            f.read_at(index * READ_CACHE_BLOCK_SIZE, buf).unwrap();
+        };
+
+        read_block(0, &mut buf);
+        read_block(1, &mut buf);
+        read_block(0, &mut buf);
+        // Evicts block 1, the least recently used one.
+        read_block(2, &mut buf);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 1, misses: 3 });
+        read_block(0, &mut buf);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 2, misses: 3 });
+        read_block(1, &mut buf);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 2, misses: 4 });
+
+        // A new file at the same path must not see the old blocks.
+        cache.invalidate(Path::new("b.ldb"));
+        let f = CachedFile::new(vec![7u8; 10], Path::new("b.ldb"), cache.clone());
+        assert_eq!(f.read_at(0, &mut buf).unwrap(), 1);
+        assert_eq!(buf, [7]);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 2, misses: 5 });
+    }
+
+    fn test_growing_file() {
+        let cache = Arc::new(ReadCache::new(16 * READ_CACHE_BLOCK_SIZE));
+        let data = contents(2 * READ_CACHE_BLOCK_SIZE);
+        let mut buf = vec![0; 2 * READ_CACHE_BLOCK_SIZE];
+
+        // A reader opened while the file is written sees a short last block.
+        let f = CachedFile::new(
+            data[..READ_CACHE_BLOCK_SIZE + 10].to_vec(),
+            Path::new("c.log"),
+            cache.clone(),
+        );
+        assert_eq!(f.read_at(0, &mut buf).unwrap(), READ_CACHE_BLOCK_SIZE + 10);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 0, misses: 2 });
+
+        // Once more was appended, the whole block is read instead of the cached short one.
+        let g = CachedFile::new(data.clone(), Path::new("c.log"), cache.clone());
+        assert_eq!(g.read_at(0, &mut buf).unwrap(), data.len());
+        assert_eq!(buf, data);
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 1, misses: 3 });
+    }
+
+    fn test_pruned_files() {
+        let cache = Arc::new(ReadCache::new(READ_CACHE_BLOCK_SIZE));
+        let files = || cache.state.lock().unwrap().files.len();
+        let mut buf = [0u8; 1];
+
+        let f = CachedFile::new(
+            contents(READ_CACHE_BLOCK_SIZE),
+            Path::new("d.ldb"),
+            cache.clone(),
+        );
+        f.read_at(0, &mut buf).unwrap();
+        // Closed files stay known while they have cached blocks.
+        drop(f);
+        assert_eq!(files(), 1);
+        let f = CachedFile::new(
+            contents(READ_CACHE_BLOCK_SIZE),
+            Path::new("d.ldb"),
+            cache.clone(),
+        );
+        f.read_at(0, &mut buf).unwrap();
+        assert_eq!(cache.stats(), ReadCacheStats { hits: 1, misses: 1 });
+        drop(f);
+
+        // Evicting the last block of a closed file drops it, while open files are kept.
+        let g = CachedFile::new(
+            contents(READ_CACHE_BLOCK_SIZE),
+            Path::new("e.ldb"),
+            cache.clone(),
+        );
+        g.read_at(0, &mut buf).unwrap();
+        assert_eq!(files(), 1);
+        let h = CachedFile::new(
+            contents(READ_CACHE_BLOCK_SIZE),
+            Path::new("f.ldb"),
+            cache.clone(),
+        );
+        h.read_at(0, &mut buf).unwrap();
+        assert_eq!(files(), 2);
+        drop((g, h));
+        assert_eq!(files(), 1);
+
+        cache.clear();
+        assert_eq!(files(), 0);
+    }
+
+    /// CountingFile counts the reads of the wrapped file.
+    struct CountingFile {
+        inner: BufferBackedFile,
+        reads: Mutex<usize>,
+    }
+
+    impl RandomAccess for CountingFile {
+        fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+            *self.reads.lock().unwrap() += 1;
+            self.inner.read_at(off, dst)
+        }
+    }
+
+    fn test_readahead() {
+        let data = contents(2 * READAHEAD_SIZE + 1000);
+        let f = ReadaheadFile::new(CountingFile {
+            inner: data.clone(),
+            reads: Mutex::new(0),
+        });
+        let reads = || *f.inner.reads.lock().unwrap();
+
+        // A scan in small reads reads each window once, plus the reads which find the end of
+        // the file.
+        let mut scanned = Vec::new();
+        let mut buf = [0u8; 3000];
+        loop {
+            let n = f.read_at(scanned.len(), &mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            scanned.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(scanned, data);
+        assert_eq!(reads(), 5);
+
+        // Reads going back or across windows are served correctly as well.
+        let off = READAHEAD_SIZE - 100;
+        assert_eq!(f.read_at(off, &mut buf[..200]).unwrap(), 200);
+        assert_eq!(&buf[..200], &data[off..off + 200]);
+        assert_eq!(f.read_at(10, &mut buf[..10]).unwrap(), 10);
+        assert_eq!(&buf[..10], &data[10..20]);
+
+        // Large reads go to the file directly.
+        let mut large = vec![0; READAHEAD_SIZE];
+        let reads_before = reads();
+        assert_eq!(f.read_at(5, &mut large).unwrap(), READAHEAD_SIZE);
+        assert_eq!(&large[..], &data[5..5 + READAHEAD_SIZE]);
+        assert_eq!(reads(), reads_before + 1);
+    }
+}
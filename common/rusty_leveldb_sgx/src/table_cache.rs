@@ -3,6 +3,7 @@
 //! returned.
 
 use crate::cache::{self, Cache};
+use crate::env::Access;
 use crate::error::{err, Result, StatusCode};
 use crate::key_types::InternalKey;
 use crate::options::Options;
@@ -55,26 +56,39 @@ impl TableCache {
 
     /// Return a table from cache, or open the backing file, then cache and return it.
     pub fn get_table(&mut self, file_num: FileNum) -> Result<Table> {
+        self.get_table_with_advice(file_num, Access::Random)
+    }
+
+    /// Like get_table, but a table which is not cached yet is opened with `advice`. Tables opened
+    /// for a `Sequential` scan, e.g. the inputs of a compaction, are read once and then deleted,
+    /// so they are not cached in place of the tables of lookups.
+    pub fn get_table_with_advice(&mut self, file_num: FileNum, advice: Access) -> Result<Table> {
         let key = filenum_to_key(file_num);
         if let Some(t) = self.cache.get(&key) {
             return Ok(t.clone());
         }
-        self.open_table(file_num)
+        let table = self.open_table(file_num, advice)?;
+        if advice != Access::Sequential {
+            self.cache.insert(&key, table.clone());
+        }
+        Ok(table)
     }
 
     /// Open a table on the file system and read it.
-    fn open_table(&mut self, file_num: FileNum) -> Result<Table> {
+    fn open_table(&mut self, file_num: FileNum, advice: Access) -> Result<Table> {
         let name = table_file_name(&self.dbname, file_num);
         let path = Path::new(&name);
         let file_size = self.opts.env.size_of(&path)?;
         if file_size == 0 {
             return err(StatusCode::InvalidData, "file is empty");
         }
-        let file = Rc::new(self.opts.env.open_random_access_file(&path)?);
+        let file = Rc::new(
+            self.opts
+                .env
+                .open_random_access_file_with_advice(&path, advice)?,
+        );
         // No SSTable file name compatibility.
-        let table = Table::new(self.opts.clone(), file, file_size)?;
-        self.cache.insert(&filenum_to_key(file_num), table.clone());
-        Ok(table)
+        Table::new(self.opts.clone(), file, file_size)
     }
 
     pub fn evict(&mut self, file_num: FileNum) -> Result<()> {
@@ -165,5 +179,12 @@ pub mod tests {
         assert!(cache.evict(123).is_ok());
         assert!(cache.evict(123).is_err());
         assert!(cache.cache.get(&filenum_to_key(123)).is_none());
+
+        // Tables opened for a scan are not cached.
+        let tbl = cache
+            .get_table_with_advice(123, Access::Sequential)
+            .unwrap();
+        assert_eq!(LdbIteratorIter::wrap(&mut tbl.iter()).count(), 4);
+        assert!(cache.cache.get(&filenum_to_key(123)).is_none());
     }
 }
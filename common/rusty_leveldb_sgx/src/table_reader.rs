@@ -20,7 +20,7 @@ use integer_encoding::FixedIntWriter;
 /// Reads the table footer.
 fn read_footer(f: &dyn RandomAccess, size: usize) -> Result<Footer> {
     let mut buf = vec![0; table_builder::FULL_FOOTER_LENGTH];
-    f.read_at(size - table_builder::FULL_FOOTER_LENGTH, &mut buf)?;
+    f.read_exact_at(size - table_builder::FULL_FOOTER_LENGTH, &mut buf)?;
     Ok(Footer::decode(&buf))
 }
 
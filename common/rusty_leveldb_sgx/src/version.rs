@@ -1,4 +1,5 @@
 use crate::cmp::{Cmp, InternalKeyCmp};
+use crate::env::Access;
 use crate::error::Result;
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, UserKey, ValueType};
 use crate::table_cache::TableCache;
@@ -394,6 +395,7 @@ pub fn new_version_iter(
         cmp: InternalKeyCmp(ucmp),
         current: None,
         current_ix: 0,
+        advice: Access::Random,
     }
 }
 
@@ -410,6 +412,16 @@ pub struct VersionIter {
 
     current: Option<TableIterator>,
     current_ix: usize,
+    advice: Access,
+}
+
+impl VersionIter {
+    /// with_advice opens the tables which are not cached yet with `advice`, e.g. `Sequential`
+    /// for the inputs of a compaction. By default they are opened for random access.
+    pub fn with_advice(mut self, advice: Access) -> VersionIter {
+        self.advice = advice;
+        self
+    }
 }
 
 impl LdbIterator for VersionIter {
@@ -432,7 +444,7 @@ impl LdbIterator for VersionIter {
         if let Ok(tbl) = self
             .cache
             .borrow_mut()
-            .get_table(self.files[self.current_ix].borrow().num)
+            .get_table_with_advice(self.files[self.current_ix].borrow().num, self.advice)
         {
             self.current = Some(tbl.iter());
         } else {
@@ -452,7 +464,7 @@ impl LdbIterator for VersionIter {
             if let Ok(tbl) = self
                 .cache
                 .borrow_mut()
-                .get_table(self.files[ix].borrow().num)
+                .get_table_with_advice(self.files[ix].borrow().num, self.advice)
             {
                 let mut iter = tbl.iter();
                 iter.seek(key);
@@ -479,7 +491,11 @@ impl LdbIterator for VersionIter {
             } else if self.current_ix > 0 {
                 let f = &self.files[self.current_ix - 1];
                 // Find previous table, seek to last entry.
-                if let Ok(tbl) = self.cache.borrow_mut().get_table(f.borrow().num) {
+                if let Ok(tbl) = self
+                    .cache
+                    .borrow_mut()
+                    .get_table_with_advice(f.borrow().num, self.advice)
+                {
                     let mut iter = tbl.iter();
                     iter.seek(&f.borrow().largest);
                     // The saved largest key must be in the table.
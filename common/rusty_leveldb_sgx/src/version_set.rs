@@ -1,5 +1,5 @@
 use crate::cmp::{Cmp, InternalKeyCmp};
-use crate::env::Env;
+use crate::env::{Access, Env};
 use crate::error::{err, Result, Status, StatusCode};
 use crate::key_types::{parse_internal_key, InternalKey, UserKey};
 use crate::log::{LogReader, LogWriter};
@@ -704,7 +704,8 @@ impl VersionSet {
         false
     }
 
-    /// make_input_iterator returns an iterator over the inputs of a compaction.
+    /// make_input_iterator returns an iterator over the inputs of a compaction. The compaction
+    /// reads them once from start to end, so their tables are opened as `Sequential`.
     pub fn make_input_iterator(&self, c: &Compaction) -> Box<dyn LdbIterator> {
         let cap = if c.level == 0 { c.num_inputs(0) + 1 } else { 2 };
         let mut iters: Vec<Box<dyn LdbIterator>> = Vec::with_capacity(cap);
@@ -716,7 +717,10 @@ impl VersionSet {
                 // Add individual iterators for L0 tables.
                 for fi in 0..c.num_inputs(i) {
                     let f = &c.inputs[i][fi];
-                    let s = self.cache.borrow_mut().get_table(f.borrow().num);
+                    let s = self
+                        .cache
+                        .borrow_mut()
+                        .get_table_with_advice(f.borrow().num, Access::Sequential);
                     if let Ok(tbl) = s {
                         iters.push(Box::new(tbl.iter()));
                     } else {
@@ -730,11 +734,14 @@ impl VersionSet {
                 }
             } else {
                 // Create concatenating iterator higher levels.
-                iters.push(Box::new(new_version_iter(
-                    c.inputs[i].clone(),
-                    self.cache.clone(),
-                    self.opt.cmp.clone(),
-                )));
+                iters.push(Box::new(
+                    new_version_iter(
+                        c.inputs[i].clone(),
+                        self.cache.clone(),
+                        self.opt.cmp.clone(),
+                    )
+                    .with_advice(Access::Sequential),
+                ));
             }
         }
         assert!(iters.len() <= cap);
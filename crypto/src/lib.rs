@@ -17,7 +17,7 @@
 
 use anyhow::{anyhow, ensure, Context, Result};
 use rand::prelude::RngCore;
-use ring::aead;
+use ring::{aead, agreement, hkdf};
 use serde::{Deserialize, Serialize};
 use sgx_tprotected_fs::SgxFile;
 use std::io::{Read, Write};
@@ -31,6 +31,8 @@ const AES_GCM_256_IV_LENGTH: usize = 12;
 const TEACLAVE_FILE_128_ROOT_KEY_LENGTH: usize = 16;
 const CMAC_LENGTH: usize = 16;
 const FILE_CHUNK_SIZE: usize = 1024 * 1024;
+const SEALED_OUTPUT_HKDF_INFO: &[u8] = b"teaclave-sealed-output";
+const RECIPIENT_PRIVATE_KEY_LENGTH: usize = 32;
 
 type CMac = [u8; CMAC_LENGTH];
 
@@ -238,6 +240,167 @@ impl Default for TeaclaveFile128Key {
     }
 }
 
+/// Private key of the recipient of sealed data: a P-256 scalar, big-endian, or an X25519
+/// scalar. Unlike ring's `EphemeralPrivateKey`, it can be stored as its 32 raw bytes and loaded
+/// again, so that data sealed to a long-lived public key can be opened later.
+#[derive(Clone)]
+pub struct RecipientPrivateKey {
+    algorithm: &'static agreement::Algorithm,
+    bytes: [u8; RECIPIENT_PRIVATE_KEY_LENGTH],
+}
+
+impl RecipientPrivateKey {
+    /// Generates a key for `algorithm`, `agreement::ECDH_P256` or `agreement::X25519`.
+    pub fn generate(algorithm: &'static agreement::Algorithm) -> Result<Self> {
+        loop {
+            let mut bytes = [0u8; RECIPIENT_PRIVATE_KEY_LENGTH];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            // Few random strings are not a P-256 scalar, they are drawn again.
+            if let Ok(key) = Self::from_bytes(algorithm, &bytes) {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Loads a key stored with `as_bytes`.
+    pub fn from_bytes(algorithm: &'static agreement::Algorithm, bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() == RECIPIENT_PRIVATE_KEY_LENGTH,
+            "Invalid recipient private key length: {}",
+            bytes.len()
+        );
+        let mut key = Self {
+            algorithm,
+            bytes: [0u8; RECIPIENT_PRIVATE_KEY_LENGTH],
+        };
+        key.bytes.copy_from_slice(bytes);
+        key.agreement_key()
+            .map_err(|_| anyhow!("Invalid recipient private key"))?;
+        Ok(key)
+    }
+
+    pub fn algorithm(&self) -> &'static agreement::Algorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Public key to seal data to: an uncompressed SEC1 encoded P-256 point, or 32 bytes for
+    /// X25519.
+    pub fn public_key(&self) -> Result<Vec<u8>> {
+        Ok(self
+            .agreement_key()?
+            .compute_public_key()
+            .map_err(|_| anyhow!("Ecdh public key error"))?
+            .as_ref()
+            .to_vec())
+    }
+
+    /// The key as a ring agreement key, which is consumed by a single agreement.
+    pub fn agreement_key(&self) -> Result<agreement::EphemeralPrivateKey> {
+        // ring only creates agreement keys from the output of a random source, so a source
+        // whose output is the stored scalar recreates the same key every time.
+        let rng = ring::test::rand::FixedSliceRandom { bytes: &self.bytes };
+        agreement::EphemeralPrivateKey::generate(self.algorithm, &rng)
+            .map_err(|_| anyhow!("Invalid recipient private key"))
+    }
+}
+
+impl std::fmt::Debug for RecipientPrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RecipientPrivateKey")
+            .field("algorithm", self.algorithm)
+            .finish()
+    }
+}
+
+impl Drop for RecipientPrivateKey {
+    fn drop(&mut self) {
+        for byte in self.bytes.iter_mut() {
+            // Keep the compiler from dropping the writes to memory which is freed next.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// SealedOutput is a result encrypted to a recipient's P-256 public key, so that only the
+/// holder of the matching private key can read it. The AES-256-GCM key is derived with HKDF-SHA256
+/// from an ECDH agreement between a fresh ephemeral key and the recipient's key.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SealedOutput {
+    pub ephemeral_public_key: Vec<u8>,
+    pub nonce: [u8; AES_GCM_256_IV_LENGTH],
+    pub ciphertext: Vec<u8>,
+    pub tag: [u8; CMAC_LENGTH],
+}
+
+impl SealedOutput {
+    /// Seals `plaintext` to `recipient_public_key`, an uncompressed SEC1 encoded P-256 point.
+    pub fn seal(recipient_public_key: &[u8], plaintext: &[u8]) -> Result<Self> {
+        let rng = ring::rand::SystemRandom::new();
+        let ephemeral_key = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
+            .map_err(|_| anyhow!("Ecdh key generation error"))?;
+        let ephemeral_public_key = ephemeral_key
+            .compute_public_key()
+            .map_err(|_| anyhow!("Ecdh public key error"))?
+            .as_ref()
+            .to_vec();
+        let key = derive_sealing_key(ephemeral_key, recipient_public_key)?;
+
+        let mut nonce = [0u8; AES_GCM_256_IV_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut in_out = plaintext.to_vec();
+        aead_encrypt(&aead::AES_256_GCM, &mut in_out, &key, &nonce)?;
+
+        let ciphertext_len = in_out.len() - CMAC_LENGTH;
+        let mut tag = [0u8; CMAC_LENGTH];
+        tag.copy_from_slice(&in_out[ciphertext_len..]);
+        in_out.truncate(ciphertext_len);
+
+        Ok(Self {
+            ephemeral_public_key,
+            nonce,
+            ciphertext: in_out,
+            tag,
+        })
+    }
+
+    pub fn open(&self, recipient_private_key: &RecipientPrivateKey) -> Result<Vec<u8>> {
+        let key = derive_sealing_key(
+            recipient_private_key.agreement_key()?,
+            &self.ephemeral_public_key,
+        )?;
+        let mut in_out = self.ciphertext.clone();
+        in_out.extend_from_slice(&self.tag);
+        let plaintext_len = aead_decrypt(&aead::AES_256_GCM, &mut in_out, &key, &self.nonce)?.len();
+        in_out.truncate(plaintext_len);
+        Ok(in_out)
+    }
+}
+
+fn derive_sealing_key(
+    private_key: agreement::EphemeralPrivateKey,
+    peer_public_key: &[u8],
+) -> Result<[u8; AES_GCM_256_KEY_LENGTH]> {
+    let peer_public_key = agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, peer_public_key);
+    agreement::agree_ephemeral(
+        private_key,
+        &peer_public_key,
+        anyhow!("Ecdh key agreement error"),
+        |shared_secret| {
+            let mut key = [0u8; AES_GCM_256_KEY_LENGTH];
+            hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+                .extract(shared_secret)
+                .expand(&[SEALED_OUTPUT_HKDF_INFO], &aead::AES_256_GCM)
+                .and_then(|okm| okm.fill(&mut key))
+                .map_err(|_| anyhow!("Hkdf expand error"))?;
+            Ok(key)
+        },
+    )
+}
+
 pub fn aead_decrypt<'a>(
     alg: &'static aead::Algorithm,
     in_out: &'a mut [u8],
@@ -279,10 +442,16 @@ pub fn aead_encrypt(
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use std::format;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_aead_enc_then_dec, test_crypto_info,)
+        run_tests!(
+            test_aead_enc_then_dec,
+            test_crypto_info,
+            test_sealed_output,
+            test_recipient_private_key,
+        )
     }
 
     fn test_aead_enc_then_dec() {
@@ -310,4 +479,37 @@ pub mod tests {
         crypto_info.decrypt(&mut buf).unwrap();
         assert_eq!(&buf[..], &plain_text[..]);
     }
+
+    fn test_sealed_output() {
+        let recipient_key = RecipientPrivateKey::generate(&agreement::ECDH_P256).unwrap();
+        let recipient_public_key = recipient_key.public_key().unwrap();
+        let other_key = RecipientPrivateKey::generate(&agreement::ECDH_P256).unwrap();
+
+        let plain_text: [u8; 5] = [0xde, 0xff, 0xab, 0xcd, 0x90];
+        let sealed = SealedOutput::seal(&recipient_public_key, &plain_text).unwrap();
+        assert_ne!(&sealed.ciphertext[..], &plain_text[..]);
+        assert!(sealed.open(&other_key).is_err());
+        assert_eq!(sealed.open(&recipient_key).unwrap(), plain_text);
+        // The key opens any number of outputs.
+        assert_eq!(sealed.open(&recipient_key).unwrap(), plain_text);
+
+        assert!(SealedOutput::seal(&[4u8; 65], &plain_text).is_err());
+    }
+
+    fn test_recipient_private_key() {
+        for algorithm in &[&agreement::ECDH_P256, &agreement::X25519] {
+            let key = RecipientPrivateKey::generate(algorithm).unwrap();
+            let stored = key.as_bytes().to_vec();
+            let loaded = RecipientPrivateKey::from_bytes(algorithm, &stored).unwrap();
+            assert_eq!(loaded.public_key().unwrap(), key.public_key().unwrap());
+            assert!(!format!("{:?}", key).contains(&hex::encode(&stored)));
+        }
+
+        // Zero and the group order are not P-256 scalars.
+        let order = hex::decode("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551")
+            .unwrap();
+        assert!(RecipientPrivateKey::from_bytes(&agreement::ECDH_P256, &order).is_err());
+        assert!(RecipientPrivateKey::from_bytes(&agreement::ECDH_P256, &[0u8; 32]).is_err());
+        assert!(RecipientPrivateKey::from_bytes(&agreement::ECDH_P256, &[1u8; 31]).is_err());
+    }
 }
@@ -41,7 +41,7 @@ class BuiltinOnlineDecryptExample:
 
         print("[+] registering function")
         function_id = client.register_function(
-            name="builtin_online_decrypt",
+            name="builtin-online-decrypt",
             description="Native Online Decrypt",
             executor_type="builtin",
             arguments=[
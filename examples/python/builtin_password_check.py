@@ -32,6 +32,7 @@ class UserData:
                  user_id,
                  password,
                  input_url="",
+                 output_url="",
                  encryption_algorithm="teaclave-file-128",
                  input_cmac=[],
                  iv=[],
@@ -39,6 +40,7 @@ class UserData:
         self.user_id = user_id
         self.password = password
         self.input_url = input_url
+        self.output_url = output_url
         self.encryption_algorithm = encryption_algorithm
         self.input_cmac = input_cmac
         self.iv = iv
@@ -46,12 +48,14 @@ class UserData:
 
 
 INPUT_FILE_URL_PREFIX = "http://localhost:6789/fixtures/functions/password_check/"
+OUTPUT_FILE_URL_PREFIX = "http://localhost:6789/fixtures/functions/password_check/"
 
 # Client
 USER_DATA_0 = UserData(
     "user0",
     "password",
     "data:text/plain;base64,c+mpvRfZ0fboR0j3rTgOGDBiubSzlCt9",  # base64 of encrypted string "password"
+    OUTPUT_FILE_URL_PREFIX + "result.enc",
     "aes-gcm-128",
     [
         0xe8, 0x47, 0x48, 0xf7, 0xad, 0x38, 0x0e, 0x18, 0x30, 0x62, 0xb9, 0xb4,
@@ -60,13 +64,14 @@ USER_DATA_0 = UserData(
     [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
     [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
 
-# Data provider
-USER_DATA_1 = UserData("user1", "password",
-                       INPUT_FILE_URL_PREFIX + "exposed_passwords.txt.enc",
-                       "teaclave-file-128", [
-                           0xa3, 0x1d, 0xd0, 0xb7, 0xde, 0x0b, 0x6c, 0xc7,
-                           0xe0, 0xde, 0xc1, 0xdc, 0xf7, 0xa4, 0x64, 0x79
-                       ], [], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+# Data provider: sorted SHA-1 hashes of the exposed passwords
+USER_DATA_1 = UserData(
+    "user1", "password", INPUT_FILE_URL_PREFIX + "corpus_sha1.txt.aes_gcm_128",
+    "", "aes-gcm-128", [
+        0x83, 0x8b, 0xf3, 0x37, 0x21, 0x7c, 0x51, 0xbd, 0xed, 0xf1, 0xbb, 0x68,
+        0x2c, 0xd0, 0x30, 0xbd
+    ], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
 
 
 class DataList:
@@ -99,10 +104,10 @@ class Client:
             executor_type="builtin",
             arguments=[],
             inputs=[
-                FunctionInput("password", "Client 0 data."),
-                FunctionInput("exposed_passwords", "Client 1 data.")
+                FunctionInput("candidates", "Client 0 data."),
+                FunctionInput("corpus", "Client 1 data.")
             ],
-            outputs=[])
+            outputs=[FunctionOutput("result", "Per-candidate result.")])
 
         print(f"[+] {self.user_id} creating task")
         task_id = client.create_task(
@@ -110,10 +115,10 @@ class Client:
             function_arguments={},
             executor="builtin",
             inputs_ownership=[
-                OwnerList("password", [USER_DATA_0.user_id]),
-                OwnerList("exposed_passwords", [USER_DATA_1.user_id])
+                OwnerList("candidates", [USER_DATA_0.user_id]),
+                OwnerList("corpus", [USER_DATA_1.user_id])
             ],
-        )
+            outputs_ownership=[OwnerList("result", [USER_DATA_0.user_id])])
 
         return task_id
 
@@ -122,8 +127,16 @@ class Client:
         print(f"[+] {self.user_id} invoking task")
         client.invoke_task(task_id)
 
-    def register_data(self, task_id, input_url, algorithm, input_cmac,
-                      file_key, iv, input_label):
+    def register_data(self,
+                      task_id,
+                      input_url,
+                      algorithm,
+                      input_cmac,
+                      file_key,
+                      iv,
+                      input_label,
+                      output_url="",
+                      output_label=""):
         client = self.client
 
         print(f"[+] {self.user_id} registering input file")
@@ -133,9 +146,17 @@ class Client:
         key = file_key
         input_id = client.register_input_file(url, schema, key, iv, cmac)
 
+        outputs = []
+        if output_url:
+            print(f"[+] {self.user_id} registering output file")
+            output_id = client.register_output_file(output_url,
+                                                    "teaclave-file-128",
+                                                    file_key, [])
+            outputs.append(DataList(output_label, output_id))
+
         print(f"[+] {self.user_id} assigning data to task")
         client.assign_data_to_task(task_id, [DataList(input_label, input_id)],
-                                   [])
+                                   outputs)
 
     def approve_task(self, task_id):
         client = self.client
@@ -168,7 +189,9 @@ def main():
         USER_DATA_0.input_cmac,
         USER_DATA_0.key,
         USER_DATA_0.iv,
-        "password",
+        "candidates",
+        USER_DATA_0.output_url,
+        "result",
     )
 
     user1.register_data(
@@ -178,7 +201,7 @@ def main():
         USER_DATA_1.input_cmac,
         USER_DATA_1.key,
         USER_DATA_1.iv,
-        "exposed_passwords",
+        "corpus",
     )
 
     user0.approve_task(task_id)
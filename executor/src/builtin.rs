@@ -16,13 +16,19 @@
 // under the License.
 
 use teaclave_function::{
-    Echo, FaceDetection, GbdtPredict, GbdtTrain, LogisticRegressionPredict,
-    LogisticRegressionTrain, OnlineDecrypt, OrderedSetIntersect, PasswordCheck,
-    PrincipalComponentsAnalysis, PrivateJoinAndCompute, RsaSign,
+    registry, BuiltinRegistry, FunctionDisabled, NoMatchingVersion, NotCompiled, NotFound,
 };
-use teaclave_types::{FunctionArguments, FunctionRuntime, TeaclaveExecutor};
+use teaclave_types::{FunctionArguments, FunctionError, FunctionRuntime, TeaclaveExecutor};
 
-use anyhow::{bail, Result};
+use crate::staging::{check_staging, StagedKeys};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Shared by all executions of the worker, so that prepared builtins are reused.
+    static ref REGISTRY: BuiltinRegistry = registry();
+}
 
 #[derive(Default)]
 pub struct BuiltinFunctionExecutor;
@@ -35,43 +41,239 @@ impl TeaclaveExecutor for BuiltinFunctionExecutor {
         _payload: Vec<u8>,
         runtime: FunctionRuntime,
     ) -> Result<String> {
-        match name.as_str() {
-            #[cfg(feature = "builtin_echo")]
-            Echo::NAME => Echo::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_gbdt_predict")]
-            GbdtPredict::NAME => GbdtPredict::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_gbdt_train")]
-            GbdtTrain::NAME => GbdtTrain::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_logistic_regression_train")]
-            LogisticRegressionTrain::NAME => LogisticRegressionTrain::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_logistic_regression_predict")]
-            LogisticRegressionPredict::NAME => {
-                LogisticRegressionPredict::new().run(arguments, runtime)
+        let metrics = runtime.metrics();
+        let policy = runtime.execution_policy();
+        // Classify the failure here, so that the task result can tell user errors apart from
+        // platform errors. Unknown, disabled and unmatched builtins fail before the staged
+        // files are looked at.
+        let builtin = REGISTRY.lookup(&name, &policy).map_err(|e| {
+            if let Some(not_found) = e.downcast_ref::<NotFound>() {
+                FunctionError::invalid_arguments(not_found)
+            } else if let Some(not_compiled) = e.downcast_ref::<NotCompiled>() {
+                FunctionError::invalid_arguments(not_compiled)
+            } else if let Some(disabled) = e.downcast_ref::<FunctionDisabled>() {
+                FunctionError::invalid_arguments(disabled)
+            } else if let Some(no_match) = e.downcast_ref::<NoMatchingVersion>() {
+                FunctionError::invalid_arguments(no_match)
+            } else {
+                FunctionError::from(e)
             }
-            #[cfg(feature = "builtin_online_decrypt")]
-            OnlineDecrypt::NAME => OnlineDecrypt::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_private_join_and_compute")]
-            PrivateJoinAndCompute::NAME => PrivateJoinAndCompute::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_ordered_set_intersect")]
-            OrderedSetIntersect::NAME => OrderedSetIntersect::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_rsa_sign")]
-            RsaSign::NAME => RsaSign::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_principal_components_analysis")]
-            PrincipalComponentsAnalysis::NAME => {
-                PrincipalComponentsAnalysis::new().run(arguments, runtime)
-            }
-            #[cfg(feature = "builtin_face_detection")]
-            FaceDetection::NAME => FaceDetection::new().run(arguments, runtime),
-            #[cfg(feature = "builtin_password_check")]
-            PasswordCheck::NAME => PasswordCheck::new().run(arguments, runtime),
-            _ => bail!("Function not found."),
-        }
+        })?;
+        // Missing files would otherwise surface as IO errors in the middle of the run.
+        let declared = builtin.descriptor().staging.as_ref();
+        let warnings = match (declared, StagedKeys::of(runtime.as_ref())) {
+            (Some(declared), Some(staged)) => check_staging(&declared.resolve(&arguments), &staged)
+                .map_err(FunctionError::invalid_arguments)?,
+            _ => Vec::new(),
+        };
+        // A failed run has no summary. The caller, who shares the counters of the runtime,
+        // attaches them to the failure of the task instead.
+        let mut summary = builtin
+            .run(arguments, runtime)
+            .map_err(FunctionError::from)?;
+        summary.metrics.extend(metrics.to_summary_metrics());
+        summary.warnings.extend(warnings);
+        summary.to_json()
     }
 }
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
     pub fn run_tests() -> bool {
-        true
+        run_tests!(
+            test_failure_categories,
+            test_runtime_metrics,
+            test_staging_preflight
+        )
+    }
+
+    fn execute(name: &str, arguments: serde_json::Value) -> TaskFailure {
+        let arguments = FunctionArguments::from_json(arguments).unwrap();
+        let runtime = RawIoRuntime::new(StagedFiles::default(), StagedFiles::default());
+        let error = BuiltinFunctionExecutor
+            .execute(name.to_string(), arguments, vec![], Box::new(runtime))
+            .unwrap_err();
+        TaskFailure::from_error(error)
+    }
+
+    fn test_failure_categories() {
+        let failure = execute("builtin-echo", json!({}));
+        assert_eq!(failure.category, TaskFailureCategory::InvalidArguments);
+        assert!(failure.reason.contains("missing field `message`"));
+
+        let failure = execute("builtin-nonexistent", json!({}));
+        assert_eq!(failure.category, TaskFailureCategory::InvalidArguments);
+        assert_eq!(
+            failure.reason,
+            "Invalid arguments: Function not found: builtin-nonexistent"
+        );
+
+        // Experimental builtins only run for tasks whose policy allows them.
+        let failure = execute("builtin-fuzzy-intersect", json!({}));
+        assert_eq!(failure.category, TaskFailureCategory::InvalidArguments);
+        assert_eq!(
+            failure.reason,
+            "Invalid arguments: Function disabled by the execution policy: builtin-fuzzy-intersect"
+        );
+
+        // Failures the function itself cannot explain are internal.
+        let arguments = json!({"message": "", "fail": true, "fail_message": "secret detail"});
+        let failure = execute("builtin-echo", arguments);
+        assert_eq!(failure.category, TaskFailureCategory::Internal);
+        assert_eq!(failure.reason, "Internal error");
+    }
+
+    fn test_runtime_metrics() {
+        let input = vec![7; DEFAULT_CHUNK_SIZE + 5];
+        let runtime = RawIoRuntime::new(
+            StagedFiles::from_memory(hashmap!("data" => input)),
+            StagedFiles::from_memory(hashmap!("data" => Vec::new())),
+        );
+        let summary = BuiltinFunctionExecutor
+            .execute(
+                "builtin-passthrough".to_string(),
+                FunctionArguments::default(),
+                vec![],
+                Box::new(runtime),
+            )
+            .unwrap();
+        let summary: FunctionSummary = serde_json::from_str(&summary).unwrap();
+
+        let size = (DEFAULT_CHUNK_SIZE + 5) as f64;
+        assert_eq!(summary.metrics["bytes"], size);
+        assert_eq!(summary.metrics["runtime.input_bytes.data"], size);
+        assert_eq!(summary.metrics["runtime.output_bytes.data"], size);
+        // Passthrough checks for cancellation once per chunk.
+        assert_eq!(summary.metrics["runtime.checkpoints"], 2.0);
+        assert!(summary.metrics.contains_key("runtime.wall_time_ms"));
+
+        // A copy failing partway through has counted what it copied until then.
+        let input = vec![7; 3 * DEFAULT_CHUNK_SIZE];
+        let limits =
+            ExecutionLimits::unlimited().max_single_output_bytes(DEFAULT_CHUNK_SIZE as u64);
+        let runtime = RawIoRuntime::new(
+            StagedFiles::from_memory(hashmap!("data" => input)),
+            StagedFiles::from_memory(hashmap!("data" => Vec::new())),
+        )
+        .with_limits(limits);
+        let metrics = runtime.metrics();
+        let error = BuiltinFunctionExecutor
+            .execute(
+                "builtin-passthrough".to_string(),
+                FunctionArguments::default(),
+                vec![],
+                Box::new(runtime),
+            )
+            .unwrap_err();
+        let failure = TaskFailure::from_error(error).with_metrics(metrics.to_summary_metrics());
+        assert_eq!(failure.category, TaskFailureCategory::ResourceLimit);
+
+        let chunk = DEFAULT_CHUNK_SIZE as f64;
+        assert_eq!(failure.metrics["runtime.input_bytes.data"], 2.0 * chunk);
+        assert_eq!(failure.metrics["runtime.output_bytes.data"], chunk);
+        assert_eq!(failure.metrics["runtime.checkpoints"], 2.0);
+    }
+
+    fn test_staging_preflight() {
+        let execute = |name: &str,
+                       arguments: serde_json::Value,
+                       inputs: HashMap<String, Vec<u8>>,
+                       outputs: HashMap<String, Vec<u8>>| {
+            let runtime = RawIoRuntime::new(
+                StagedFiles::from_memory(inputs),
+                StagedFiles::from_memory(outputs),
+            );
+            BuiltinFunctionExecutor.execute(
+                name.to_string(),
+                FunctionArguments::from_json(arguments).unwrap(),
+                vec![],
+                Box::new(runtime),
+            )
+        };
+
+        // Required files are checked before the builtin runs, which has not counted anything.
+        let runtime = RawIoRuntime::new(
+            StagedFiles::from_memory(hashmap!("data0" => b"a : 1".to_vec())),
+            StagedFiles::default(),
+        );
+        let metrics = runtime.metrics();
+        let error = BuiltinFunctionExecutor
+            .execute(
+                "builtin-private-join-and-compute".to_string(),
+                FunctionArguments::from_json(json!({"num_user": 2})).unwrap(),
+                vec![],
+                Box::new(runtime),
+            )
+            .unwrap_err();
+        let failure = TaskFailure::from_error(error);
+        assert_eq!(failure.category, TaskFailureCategory::InvalidArguments);
+        assert_eq!(
+            failure.reason,
+            "Invalid arguments: Missing staged files: input input_data*, output output_data*"
+        );
+        assert!(metrics.input_bytes().is_empty() && metrics.output_bytes().is_empty());
+        assert_eq!(metrics.checkpoints(), 0);
+
+        // The policy and the version are checked before the files, so that the task fails
+        // because of the builtin it asks for rather than because of its files.
+        let error =
+            execute("builtin-fuzzy-intersect", json!({}), hashmap!(), hashmap!()).unwrap_err();
+        assert_eq!(
+            TaskFailure::from_error(error).reason,
+            "Invalid arguments: Function disabled by the execution policy: builtin-fuzzy-intersect"
+        );
+        let error = execute(
+            "builtin-private-join-and-compute@2",
+            json!({"num_user": 2}),
+            hashmap!(),
+            hashmap!(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            TaskFailure::from_error(error).reason,
+            "Invalid arguments: No version of builtin-private-join-and-compute satisfies 2"
+        );
+
+        // Identifiers given by arguments are checked as given.
+        let error = execute(
+            "builtin-histogram",
+            json!({"input": "table", "column": 0}),
+            hashmap!("input" => b"1\n2\n".to_vec()),
+            hashmap!("output" => Vec::new()),
+        )
+        .unwrap_err();
+        assert_eq!(
+            TaskFailure::from_error(error).reason,
+            "Invalid arguments: Missing staged files: input table"
+        );
+
+        // Unused files only cause warnings, which come with the summary.
+        let summary = execute(
+            "builtin-private-join-and-compute",
+            json!({"num_user": 2}),
+            hashmap!(
+                "input_data0" => b"a : 1".to_vec(),
+                "input_data1" => b"a : 2".to_vec(),
+                "notes" => b"a : 3".to_vec(),
+            ),
+            hashmap!(
+                "output_data0" => Vec::new(),
+                "output_data1" => Vec::new(),
+            ),
+        )
+        .unwrap();
+        let summary: FunctionSummary = serde_json::from_str(&summary).unwrap();
+        assert_eq!(summary.message, "2 users join the task in total.");
+        assert_eq!(
+            summary.warnings,
+            vec!["Staged input notes is not used by the function"]
+        );
     }
 }
@@ -0,0 +1,498 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+#![allow(clippy::nonstandard_macro_braces)]
+
+use std::format;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+#[cfg(feature = "mesalock_sgx")]
+use std::untrusted::time::InstantEx;
+
+use teaclave_types::{
+    CancellationToken, ExecutionLog, ExecutionMetrics, FunctionArguments, FunctionRuntime,
+    TeaclaveExecutor,
+};
+
+use anyhow::Result;
+use thiserror::Error;
+
+/// Error returned when a task is refused before it runs.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AdmissionError {
+    #[error("Memory budget of {requested} bytes exceeds the {available} bytes left of {ceiling}")]
+    MemoryBudget {
+        requested: u64,
+        available: u64,
+        ceiling: u64,
+    },
+}
+
+/// A function execution to run on a `ConcurrentExecutor`, with the runtime it runs on.
+pub struct ConcurrentTask {
+    pub name: String,
+    pub arguments: FunctionArguments,
+    pub payload: Vec<u8>,
+    pub runtime: FunctionRuntime,
+    /// Memory the task declares it needs, counted against the ceiling of the executor.
+    pub memory_budget: u64,
+}
+
+/// Result of one execution together with its own metrics and log.
+pub struct TaskOutcome {
+    pub result: Result<String>,
+    pub metrics: ExecutionMetrics,
+    pub log: Vec<String>,
+}
+
+/// Executor which runs independent functions side by side, at most `max_concurrent` at a time.
+/// Most builtins spend their time on reads of staged files, so a worker gets more done by
+/// overlapping them than by running them one after the other.
+///
+/// Every execution brings its own runtime, and gets its own thread, cancellation token, metrics
+/// and log, so one failing or cancelled execution does not affect the others. Memory is not
+/// metered inside the enclave; instead, each task declares a budget, and tasks whose budget does
+/// not fit under the ceiling next to the admitted ones are refused with `AdmissionError`.
+///
+/// As a `TeaclaveExecutor`, it runs the function on the calling thread instead, with the memory
+/// budget of its `ExecutionEnvironment`, so that a worker can put the executions of all of its
+/// executors under the same `ConcurrencyLimits`.
+pub struct ConcurrentExecutor {
+    inner: Arc<dyn TeaclaveExecutor + Send + Sync>,
+    limits: ConcurrencyLimits,
+}
+
+/// Execution slots and memory ceiling shared by the `ConcurrentExecutor`s created with them,
+/// and by their clones.
+#[derive(Clone)]
+pub struct ConcurrencyLimits {
+    max_concurrent: usize,
+    memory_ceiling: u64,
+    state: Arc<(Mutex<PoolState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    running: usize,
+    reserved_memory: u64,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(max_concurrent: usize, memory_ceiling: u64) -> Self {
+        Self {
+            max_concurrent: max_concurrent.max(1),
+            memory_ceiling,
+            state: Arc::new((Mutex::new(PoolState::default()), Condvar::new())),
+        }
+    }
+
+    /// Memory reserved by the admitted executions which have not released it yet.
+    pub fn reserved_memory(&self) -> u64 {
+        self.state.0.lock().unwrap().reserved_memory
+    }
+
+    /// Memory left under the ceiling for the next execution.
+    pub fn available_memory(&self) -> u64 {
+        self.memory_ceiling.saturating_sub(self.reserved_memory())
+    }
+
+    fn reserve(&self, memory: u64) -> Result<Reservation, AdmissionError> {
+        let mut state = self.state.0.lock().unwrap();
+        let available = self.memory_ceiling.saturating_sub(state.reserved_memory);
+        if memory > available {
+            return Err(AdmissionError::MemoryBudget {
+                requested: memory,
+                available,
+                ceiling: self.memory_ceiling,
+            });
+        }
+        state.reserved_memory += memory;
+        Ok(Reservation {
+            limits: self.clone(),
+            memory,
+        })
+    }
+}
+
+impl ConcurrentExecutor {
+    pub fn new(
+        inner: Box<dyn TeaclaveExecutor + Send + Sync>,
+        max_concurrent: usize,
+        memory_ceiling: u64,
+    ) -> Self {
+        Self::with_limits(
+            inner,
+            ConcurrencyLimits::new(max_concurrent, memory_ceiling),
+        )
+    }
+
+    /// Executor which runs on `inner` and counts its executions against `limits`, next to
+    /// those of the other executors sharing them.
+    pub fn with_limits(
+        inner: Box<dyn TeaclaveExecutor + Send + Sync>,
+        limits: ConcurrencyLimits,
+    ) -> Self {
+        Self {
+            inner: Arc::from(inner),
+            limits,
+        }
+    }
+
+    /// Memory reserved by the admitted tasks whose outcome has not been taken yet.
+    pub fn reserved_memory(&self) -> u64 {
+        self.limits.reserved_memory()
+    }
+
+    /// Admits `task` and starts it as soon as one of the `max_concurrent` slots is free. The
+    /// memory budget of the task stays reserved until its outcome is taken from the handle,
+    /// since the outcome is held in memory until then.
+    pub fn submit(&self, task: ConcurrentTask) -> Result<ExecutionHandle, AdmissionError> {
+        let reservation = self.limits.reserve(task.memory_budget)?;
+
+        let cancellation = task.runtime.cancellation();
+        let metrics = task.runtime.metrics();
+        let log = ExecutionLog::default();
+        let inner = self.inner.clone();
+        let limits = self.limits.clone();
+        let thread_log = log.clone();
+        let (sender, receiver) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name(format!("function {}", task.name))
+            .spawn(move || {
+                let _slot = Slot::acquire(limits);
+                let result = thread_log.scope(|| run(inner.as_ref(), task, &thread_log));
+                let _ = sender.send(result);
+            });
+        // The handle reports the failure to spawn as the result of the execution, so that the
+        // caller handles it like any other failed execution.
+        let receiver = match spawned {
+            Ok(_) => Some(receiver),
+            Err(e) => {
+                log.log(log::Level::Error, &format!("Cannot start function: {}", e));
+                None
+            }
+        };
+        Ok(ExecutionHandle {
+            receiver,
+            cancellation,
+            metrics,
+            log,
+            _reservation: reservation,
+        })
+    }
+
+    /// Submits all `tasks` and waits for those admitted. Outcomes are in the order of `tasks`.
+    pub fn execute_all(
+        &self,
+        tasks: Vec<ConcurrentTask>,
+    ) -> Vec<Result<TaskOutcome, AdmissionError>> {
+        let handles: Vec<_> = tasks.into_iter().map(|task| self.submit(task)).collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.map(ExecutionHandle::join))
+            .collect()
+    }
+}
+
+impl TeaclaveExecutor for ConcurrentExecutor {
+    fn execute(
+        &self,
+        name: String,
+        arguments: FunctionArguments,
+        payload: Vec<u8>,
+        runtime: FunctionRuntime,
+    ) -> Result<String> {
+        let memory_budget = runtime.environment().memory_budget_bytes.unwrap_or(0);
+        // Held until the function returns, even if a `TimeoutExecutor` around this one has
+        // abandoned it, since the function keeps its slot and buffers until then.
+        let _reservation = self.limits.reserve(memory_budget)?;
+        let _slot = Slot::acquire(self.limits.clone());
+        self.inner.execute(name, arguments, payload, runtime)
+    }
+}
+
+fn run(
+    executor: &(dyn TeaclaveExecutor + Send + Sync),
+    task: ConcurrentTask,
+    log: &ExecutionLog,
+) -> Result<String> {
+    let name = task.name.clone();
+    let started = Instant::now();
+    log.log(log::Level::Info, &format!("Started {}", name));
+    let result = executor.execute(task.name, task.arguments, task.payload, task.runtime);
+    let elapsed = started.elapsed();
+    match &result {
+        Ok(_) => log.log(
+            log::Level::Info,
+            &format!("Finished {} in {:?}", name, elapsed),
+        ),
+        Err(e) => log.log(
+            log::Level::Warn,
+            &format!("Failed {} after {:?}: {}", name, elapsed, e),
+        ),
+    }
+    result
+}
+
+/// Handle of an admitted execution.
+pub struct ExecutionHandle {
+    receiver: Option<Receiver<Result<String>>>,
+    cancellation: CancellationToken,
+    metrics: ExecutionMetrics,
+    log: ExecutionLog,
+    _reservation: Reservation,
+}
+
+impl ExecutionHandle {
+    /// Cancels this execution only; the other executions keep running.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Waits for the execution to finish and releases its memory budget.
+    pub fn join(self) -> TaskOutcome {
+        let result = match &self.receiver {
+            Some(receiver) => receiver
+                .recv()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("Function thread panicked"))),
+            None => Err(anyhow::anyhow!("Function thread could not be started")),
+        };
+        TaskOutcome {
+            result,
+            metrics: self.metrics.clone(),
+            log: self.log.drain(),
+        }
+    }
+}
+
+/// Memory budget of an admitted task, released on drop.
+struct Reservation {
+    limits: ConcurrencyLimits,
+    memory: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.limits.state.0.lock().unwrap().reserved_memory -= self.memory;
+    }
+}
+
+/// One of the `max_concurrent` execution slots, released on drop, so that a panicking function
+/// gives its slot back as well.
+struct Slot {
+    limits: ConcurrencyLimits,
+}
+
+impl Slot {
+    fn acquire(limits: ConcurrencyLimits) -> Self {
+        {
+            let (lock, available) = &*limits.state;
+            let mut pool = lock.lock().unwrap();
+            while pool.running >= limits.max_concurrent {
+                pool = available.wait(pool).unwrap();
+            }
+            pool.running += 1;
+        }
+        Self { limits }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let (lock, available) = &*self.limits.state;
+        lock.lock().unwrap().running -= 1;
+        available.notify_one();
+    }
+}
+
+#[cfg(all(feature = "enclave_unit_test", executor_builtin))]
+pub mod tests {
+    use super::*;
+    use crate::BuiltinFunctionExecutor;
+    use serde_json::json;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_concurrent_passthrough,
+            test_memory_admission,
+            test_failure_isolation,
+            test_shared_limits,
+        )
+    }
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn passthrough(input: Vec<u8>, arguments: serde_json::Value) -> ConcurrentTask {
+        let runtime = RawIoRuntime::new(
+            StagedFiles::from_memory(hashmap!("data" => input)),
+            StagedFiles::from_memory(hashmap!("data" => Vec::new())),
+        );
+        ConcurrentTask {
+            name: "builtin-passthrough".to_string(),
+            arguments: FunctionArguments::from_json(arguments).unwrap(),
+            payload: vec![],
+            runtime: Box::new(runtime),
+            memory_budget: MIB,
+        }
+    }
+
+    fn executor(max_concurrent: usize, memory_ceiling: u64) -> ConcurrentExecutor {
+        ConcurrentExecutor::new(
+            Box::new(BuiltinFunctionExecutor),
+            max_concurrent,
+            memory_ceiling,
+        )
+    }
+
+    fn test_concurrent_passthrough() {
+        let executor = executor(3, 3 * MIB + MIB / 2);
+        let sizes = [10, DEFAULT_CHUNK_SIZE + 1, 3 * DEFAULT_CHUNK_SIZE];
+        let handles: Vec<_> = sizes
+            .iter()
+            .map(|&size| {
+                let task = passthrough(vec![1; size], json!({"delay_ms_per_chunk": 20}));
+                executor.submit(task).unwrap()
+            })
+            .collect();
+        assert_eq!(executor.reserved_memory(), 3 * MIB);
+
+        // The three admitted tasks leave half a budget, so a fourth is refused while they hold
+        // their reservations.
+        let error = executor
+            .submit(passthrough(vec![1; 10], json!({})))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            AdmissionError::MemoryBudget {
+                requested: MIB,
+                available: MIB / 2,
+                ceiling: 3 * MIB + MIB / 2,
+            }
+        );
+
+        for (handle, &size) in handles.into_iter().zip(sizes.iter()) {
+            let outcome = handle.join();
+            let summary: FunctionSummary = serde_json::from_str(&outcome.result.unwrap()).unwrap();
+            assert_eq!(summary.metrics["bytes"], size as f64);
+            assert_eq!(outcome.metrics.input_bytes()["data"], size as u64);
+            assert_eq!(outcome.metrics.output_bytes()["data"], size as u64);
+            // Passthrough checks for cancellation once per chunk.
+            let chunks = (size + DEFAULT_CHUNK_SIZE - 1) / DEFAULT_CHUNK_SIZE;
+            assert_eq!(outcome.metrics.checkpoints(), chunks as u64);
+            assert_eq!(outcome.log.len(), 2);
+            assert_eq!(outcome.log[0], "[INFO] Started builtin-passthrough");
+            assert!(outcome.log[1].starts_with("[INFO] Finished builtin-passthrough in "));
+        }
+        assert_eq!(executor.reserved_memory(), 0);
+        assert!(executor
+            .submit(passthrough(vec![1; 10], json!({})))
+            .unwrap()
+            .join()
+            .result
+            .is_ok());
+    }
+
+    fn test_memory_admission() {
+        let executor = executor(1, MIB);
+        let mut task = passthrough(vec![1; 10], json!({}));
+        task.memory_budget = MIB + 1;
+        let outcomes = executor.execute_all(vec![task, passthrough(vec![1; 10], json!({}))]);
+        assert_eq!(
+            outcomes[0].as_ref().err(),
+            Some(&AdmissionError::MemoryBudget {
+                requested: MIB + 1,
+                available: MIB,
+                ceiling: MIB,
+            })
+        );
+        assert!(outcomes[1].as_ref().unwrap().result.is_ok());
+    }
+
+    fn test_failure_isolation() {
+        let executor = executor(2, 3 * MIB);
+        let cancelled = executor
+            .submit(passthrough(
+                vec![1; 100 * DEFAULT_CHUNK_SIZE],
+                json!({"delay_ms_per_chunk": 10}),
+            ))
+            .unwrap();
+        let failing = passthrough(vec![1; 10], json!({"flip_byte_at": "first"}));
+        let mut outcomes = executor
+            .execute_all(vec![failing, passthrough(vec![2; 10], json!({}))])
+            .into_iter()
+            .map(|outcome| outcome.unwrap());
+        cancelled.cancel();
+
+        let failure = TaskFailure::from_error(outcomes.next().unwrap().result.unwrap_err());
+        assert_eq!(failure.category, TaskFailureCategory::InvalidArguments);
+        assert!(outcomes.next().unwrap().result.is_ok());
+
+        // Cancelling one execution leaves the others alone.
+        let outcome = cancelled.join();
+        let failure = TaskFailure::from_error(outcome.result.unwrap_err());
+        assert_eq!(failure.category, TaskFailureCategory::Cancelled);
+        assert!(outcome.log[1].starts_with("[WARN] Failed builtin-passthrough after "));
+    }
+
+    fn test_shared_limits() {
+        let limits = ConcurrencyLimits::new(2, MIB + MIB / 2);
+        let submitting =
+            ConcurrentExecutor::with_limits(Box::new(BuiltinFunctionExecutor), limits.clone());
+        let executing =
+            ConcurrentExecutor::with_limits(Box::new(BuiltinFunctionExecutor), limits.clone());
+        let execute = |memory_budget: u64| {
+            let runtime = RawIoRuntime::new(
+                StagedFiles::from_memory(hashmap!("data" => vec![1; 10])),
+                StagedFiles::from_memory(hashmap!("data" => Vec::new())),
+            )
+            .with_environment(ExecutionEnvironment::new().memory_budget_bytes(Some(memory_budget)));
+            executing.execute(
+                "builtin-passthrough".to_string(),
+                FunctionArguments::from_json(json!({})).unwrap(),
+                vec![],
+                Box::new(runtime),
+            )
+        };
+
+        let handle = submitting
+            .submit(passthrough(vec![1; 10], json!({"delay_ms_per_chunk": 20})))
+            .unwrap();
+        assert_eq!(limits.available_memory(), MIB / 2);
+
+        // An execution is admitted with the memory budget of its environment, against the
+        // reservations of the other executor.
+        let error = execute(MIB).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AdmissionError>(),
+            Some(&AdmissionError::MemoryBudget {
+                requested: MIB,
+                available: MIB / 2,
+                ceiling: MIB + MIB / 2,
+            })
+        );
+        assert!(execute(MIB / 2).is_ok());
+        assert_eq!(limits.reserved_memory(), MIB);
+
+        assert!(handle.join().result.is_ok());
+        assert_eq!(limits.reserved_memory(), 0);
+        assert!(execute(MIB).is_ok());
+    }
+}
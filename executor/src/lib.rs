@@ -20,15 +20,28 @@ extern crate sgx_types;
 
 #[cfg(executor_builtin)]
 mod builtin;
+mod concurrent;
 #[cfg(executor_mesapy)]
 mod mesapy;
+mod postprocess;
+mod staging;
+mod timeout;
 #[cfg(executor_wamr)]
 mod wamr;
 
 #[cfg(executor_builtin)]
 pub use builtin::BuiltinFunctionExecutor;
+pub use concurrent::{
+    AdmissionError, ConcurrencyLimits, ConcurrentExecutor, ConcurrentTask, ExecutionHandle,
+    TaskOutcome,
+};
 #[cfg(executor_mesapy)]
 pub use mesapy::MesaPy;
+pub use postprocess::{
+    StringRedactor, SummaryPostProcessor, SummaryRedactor, REDACTION_MARKER, TRUNCATION_MARKER,
+};
+pub use staging::{check_staging, MissingStagedFiles, StagedKeys};
+pub use timeout::TimeoutExecutor;
 #[cfg(executor_wamr)]
 pub use wamr::WAMicroRuntime;
 
@@ -43,8 +56,13 @@ pub mod tests {
         v.push(mesapy::tests::run_tests());
         #[cfg(executor_builtin)]
         v.push(builtin::tests::run_tests());
+        #[cfg(executor_builtin)]
+        v.push(concurrent::tests::run_tests());
         #[cfg(executor_wamr)]
         v.push(wamr::tests::run_tests());
+        v.push(postprocess::tests::run_tests());
+        v.push(staging::tests::run_tests());
+        v.push(timeout::tests::run_tests());
         v.iter().all(|&x| x)
     }
 }
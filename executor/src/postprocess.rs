@@ -0,0 +1,432 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::BTreeMap;
+use std::format;
+use std::sync::Arc;
+
+use teaclave_types::{ExecutionPolicy, FunctionError, FunctionSummary, StagedFiles};
+
+/// Replaces the parts of a summary removed by a `StringRedactor`.
+pub const REDACTION_MARKER: &str = "[REDACTED]";
+
+/// Ends a message shortened to the size limit of the summary.
+pub const TRUNCATION_MARKER: &str = "...[truncated]";
+
+/// Step of the summary post-processing which removes sensitive content from the strings a
+/// function put into its summary: the message, the warnings and the names of the metrics.
+/// Closures from `&str` to `Option<String>` are redactors, too.
+pub trait SummaryRedactor: Send + Sync {
+    /// Returns `text` with its sensitive parts replaced, or `None` if it has none.
+    fn redact(&self, text: &str) -> Option<String>;
+}
+
+impl<F> SummaryRedactor for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn redact(&self, text: &str) -> Option<String> {
+        self(text)
+    }
+}
+
+/// Replaces every occurrence of a set of strings with `REDACTION_MARKER`.
+pub struct StringRedactor {
+    strings: Vec<String>,
+}
+
+impl StringRedactor {
+    pub fn new<S: AsRef<str>>(strings: &[S]) -> Self {
+        let mut strings: Vec<String> = strings
+            .iter()
+            .map(|s| s.as_ref().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        // Longer strings first, so that a string containing another is redacted as a whole.
+        strings.sort_by(|a, b| b.len().cmp(&a.len()));
+        Self { strings }
+    }
+}
+
+impl SummaryRedactor for StringRedactor {
+    fn redact(&self, text: &str) -> Option<String> {
+        let mut redacted: Option<String> = None;
+        for string in &self.strings {
+            let current = redacted.as_deref().unwrap_or(text);
+            if current.contains(string.as_str()) {
+                redacted = Some(current.replace(string.as_str(), REDACTION_MARKER));
+            }
+        }
+        redacted
+    }
+}
+
+/// Post-processing of the summary of a function before it becomes the task result, so that a
+/// function can't pass its inputs out through the task status: redacts the summary with the
+/// configured redactors, and then shortens it to a maximum size of its JSON. Whether either
+/// happened is recorded in the summary.
+#[derive(Clone, Default)]
+pub struct SummaryPostProcessor {
+    max_bytes: Option<usize>,
+    redactors: Vec<Arc<dyn SummaryRedactor>>,
+}
+
+impl SummaryPostProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the `max_summary_bytes` and `redacted_strings` of `policy`.
+    pub fn from_policy(policy: &ExecutionPolicy) -> Self {
+        let mut processor = Self::new();
+        processor.max_bytes = policy.max_summary_bytes;
+        if !policy.redacted_strings.is_empty() {
+            let strings: Vec<&String> = policy.redacted_strings.iter().collect();
+            processor = processor.redactor(Arc::new(StringRedactor::new(&strings)));
+        }
+        processor
+    }
+
+    /// Limits the JSON of summaries to `max_bytes`. Larger summaries lose their warnings, then
+    /// their metrics and outputs until the rest fits, and the end of their message. A limit
+    /// below the size of an empty summary can't be met.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Lowers the size limit to `max_bytes`, or sets it if there is none.
+    pub fn max_bytes_at_most(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(self.max_bytes.map_or(max_bytes, |m| m.min(max_bytes)));
+        self
+    }
+
+    pub fn redactor(mut self, redactor: Arc<dyn SummaryRedactor>) -> Self {
+        self.redactors.push(redactor);
+        self
+    }
+
+    /// Redacts the authentication tags and the declared digests of the files in `inputs`, which
+    /// identify the inputs of the task to whoever can see its result.
+    pub fn redact_input_digests(self, inputs: &StagedFiles) -> Self {
+        let mut digests = Vec::new();
+        for key in inputs.keys() {
+            if let Some(info) = inputs.get(key) {
+                digests.push(info.cmac.to_hex());
+                if let Some(digest) = info.expected_digest {
+                    digests.push(digest.to_string());
+                }
+            }
+        }
+        // Hex digests may be printed in either case.
+        let upper: Vec<String> = digests.iter().map(|d| d.to_uppercase()).collect();
+        digests.extend(upper);
+        if digests.is_empty() {
+            return self;
+        }
+        self.redactor(Arc::new(StringRedactor::new(&digests)))
+    }
+
+    /// Post-processes the result of an executor. Results which are `FunctionSummary` JSON go
+    /// through `process`; other results, such as the return values of Python functions, are
+    /// redacted and shortened as plain text.
+    pub fn process_output(&self, output: String) -> String {
+        match serde_json::from_str::<FunctionSummary>(&output) {
+            Ok(mut summary) => {
+                self.process(&mut summary);
+                summary.to_json().unwrap_or(output)
+            }
+            Err(_) => {
+                let mut text = output;
+                self.process_text(&mut text);
+                text
+            }
+        }
+    }
+
+    /// Post-processes the error of a failed execution. The reasons of user errors are shown to
+    /// the user as the task result, so they are redacted and shortened like summaries; other
+    /// errors don't show their details and are left alone.
+    pub fn process_error(&self, error: anyhow::Error) -> anyhow::Error {
+        let mut error = FunctionError::from(error);
+        match &mut error {
+            FunctionError::InvalidArguments(reason)
+            | FunctionError::InvalidInputData { reason, .. } => {
+                self.process_text(reason);
+            }
+            _ => (),
+        }
+        error.into()
+    }
+
+    fn process_text(&self, text: &mut String) {
+        for redactor in &self.redactors {
+            if let Some(replacement) = redactor.redact(text) {
+                *text = replacement;
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if text.len() > max_bytes {
+                let mut kept = max_bytes.saturating_sub(TRUNCATION_MARKER.len());
+                while !text.is_char_boundary(kept) {
+                    kept -= 1;
+                }
+                text.truncate(kept);
+                text.push_str(TRUNCATION_MARKER);
+            }
+        }
+    }
+
+    pub fn process(&self, summary: &mut FunctionSummary) {
+        // Redacting first keeps truncation from cutting a sensitive string in half, which
+        // would hide it from the redactors.
+        for redactor in &self.redactors {
+            if redact_summary(redactor.as_ref(), summary) {
+                summary.redacted = true;
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if json_len(summary) > max_bytes {
+                summary.truncated = true;
+                truncate_summary(summary, max_bytes);
+            }
+        }
+    }
+}
+
+fn redact_summary(redactor: &dyn SummaryRedactor, summary: &mut FunctionSummary) -> bool {
+    let mut redacted = false;
+    let mut redact = |text: &mut String| {
+        if let Some(replacement) = redactor.redact(text) {
+            *text = replacement;
+            redacted = true;
+        }
+    };
+    redact(&mut summary.message);
+    summary.warnings.iter_mut().for_each(&mut redact);
+    let metrics = std::mem::take(&mut summary.metrics);
+    summary.metrics = metrics
+        .into_iter()
+        .map(|(mut name, value)| {
+            redact(&mut name);
+            (name, value)
+        })
+        .collect::<BTreeMap<_, _>>();
+    redacted
+}
+
+fn json_len(summary: &FunctionSummary) -> usize {
+    summary.to_json().map_or(usize::MAX, |json| json.len())
+}
+
+fn truncate_summary(summary: &mut FunctionSummary, max_bytes: usize) {
+    let message = std::mem::take(&mut summary.message);
+
+    // Make room for at least the marker, dropping the warnings first, then the metrics and
+    // the outputs, the last ones first.
+    summary.message = TRUNCATION_MARKER.to_string();
+    while json_len(summary) > max_bytes && summary.warnings.pop().is_some() {}
+    while json_len(summary) > max_bytes {
+        match summary.metrics.keys().next_back().cloned() {
+            Some(name) => summary.metrics.remove(&name),
+            None => break,
+        };
+    }
+    while json_len(summary) > max_bytes {
+        match summary.outputs.keys().next_back().cloned() {
+            Some(identifier) => summary.outputs.remove(&identifier),
+            None => break,
+        };
+    }
+
+    // Then keep as much of the start of the message as fits. JSON escapes make its encoded
+    // size differ from its length, so the cut is refined until the summary fits.
+    let mut kept = message.len();
+    loop {
+        summary.message = format!("{}{}", &message[..kept], TRUNCATION_MARKER);
+        let len = json_len(summary);
+        if len <= max_bytes || kept == 0 {
+            break;
+        }
+        let mut next = kept.saturating_sub(len - max_bytes).min(kept - 1);
+        while !message.is_char_boundary(next) {
+            next -= 1;
+        }
+        kept = next;
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::{hashmap, Digest, FileAuthTag, OutputInfo, StagedFileInfo, TaskFailure};
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_truncate_summary,
+            test_redact_summary,
+            test_compliant_summary,
+            test_redact_input_digests,
+            test_process_output_and_error
+        )
+    }
+
+    fn test_truncate_summary() {
+        let processor = SummaryPostProcessor::new().max_bytes(200);
+        let mut summary = FunctionSummary::new("ä".repeat(10_000))
+            .metric("rows", 3.0)
+            .warning("w".repeat(1000));
+        processor.process(&mut summary);
+
+        let json = summary.to_json().unwrap();
+        assert!(json.len() <= 200);
+        assert!(summary.truncated);
+        assert!(!summary.redacted);
+        assert!(summary.message.starts_with("ää"));
+        assert!(summary.message.ends_with(TRUNCATION_MARKER));
+        // The warning alone is larger than the limit, so it goes; the metrics still fit.
+        assert!(summary.warnings.is_empty());
+        assert_eq!(summary.metrics["rows"], 3.0);
+        assert!(json.contains("\"truncated\":true"));
+
+        // Even an oversized empty summary is brought down to the limit, down to its skeleton.
+        let mut summary = FunctionSummary::new("")
+            .metric("m".repeat(300), 1.0)
+            .output("o".repeat(300), OutputInfo::new(1));
+        SummaryPostProcessor::new()
+            .max_bytes(120)
+            .process(&mut summary);
+        assert!(summary.to_json().unwrap().len() <= 120);
+        assert!(summary.metrics.is_empty() && summary.outputs.is_empty());
+    }
+
+    fn test_redact_summary() {
+        let policy = ExecutionPolicy::new()
+            .redact_string("4111-1111")
+            .redact_string("4111-1111-1111-1111");
+        let mut summary = FunctionSummary::new("found 4111-1111-1111-1111 and 4111-1111")
+            .metric("card.4111-1111-1111-1111", 1.0)
+            .warning("first row 4111-1111-1111-1111");
+        SummaryPostProcessor::from_policy(&policy).process(&mut summary);
+
+        assert!(summary.redacted);
+        assert!(!summary.truncated);
+        assert_eq!(summary.message, "found [REDACTED] and [REDACTED]");
+        assert_eq!(summary.warnings, vec!["first row [REDACTED]"]);
+        assert_eq!(summary.metrics["card.[REDACTED]"], 1.0);
+
+        // Callbacks plug in next to the configured strings.
+        let digits = |text: &str| -> Option<String> {
+            if text.chars().any(|c| c.is_ascii_digit()) {
+                Some(text.chars().filter(|c| !c.is_ascii_digit()).collect())
+            } else {
+                None
+            }
+        };
+        let mut summary = FunctionSummary::new("pin 1234");
+        SummaryPostProcessor::new()
+            .redactor(Arc::new(digits))
+            .process(&mut summary);
+        assert_eq!(summary.message, "pin ");
+        assert!(summary.redacted);
+    }
+
+    fn test_compliant_summary() {
+        let policy = ExecutionPolicy::new()
+            .max_summary_bytes(1024)
+            .redact_string("secret");
+        let original = FunctionSummary::new("3 rows")
+            .metric("rows", 3.0)
+            .output("output", OutputInfo::new(12))
+            .warning("column 2 is empty");
+        let mut summary = original.clone();
+        SummaryPostProcessor::from_policy(&policy).process(&mut summary);
+        assert_eq!(summary, original);
+        // The flags are left out of the JSON of untouched summaries.
+        assert_eq!(
+            summary.to_json().unwrap(),
+            r#"{"message":"3 rows","metrics":{"rows":3.0},"outputs":{"output":{"size":12}},"warnings":["column 2 is empty"]}"#
+        );
+    }
+
+    fn test_redact_input_digests() {
+        let cmac = FileAuthTag::from_hex("00112233445566778899aabbccddeeff").unwrap();
+        let digest = Digest::compute(b"records");
+        let info = StagedFileInfo {
+            cmac,
+            expected_digest: Some(digest),
+            ..Default::default()
+        };
+        let inputs = StagedFiles::new(hashmap!("records" => info));
+        let processor = SummaryPostProcessor::new().redact_input_digests(&inputs);
+
+        let mut summary =
+            FunctionSummary::new(format!("read {} with tag {}", digest, cmac.to_hex()))
+                .warning(format!("digest {}", digest.to_string().to_uppercase()));
+        processor.process(&mut summary);
+        assert!(summary.redacted);
+        assert_eq!(summary.message, "read [REDACTED] with tag [REDACTED]");
+        assert_eq!(summary.warnings, vec!["digest [REDACTED]"]);
+
+        // Inputs staged in memory have no digest to redact.
+        let inputs = StagedFiles::from_memory(hashmap!("records" => b"records".to_vec()));
+        let mut summary = FunctionSummary::new(digest.to_string());
+        SummaryPostProcessor::new()
+            .redact_input_digests(&inputs)
+            .process(&mut summary);
+        assert!(!summary.redacted);
+    }
+
+    fn test_process_output_and_error() {
+        let processor = SummaryPostProcessor::from_policy(
+            &ExecutionPolicy::new()
+                .max_summary_bytes(64)
+                .redact_string("patient-0042"),
+        );
+
+        // Summaries are processed as summaries.
+        let output = FunctionSummary::new("record patient-0042")
+            .to_json()
+            .unwrap();
+        let summary: FunctionSummary =
+            serde_json::from_str(&processor.process_output(output)).unwrap();
+        assert!(summary.redacted);
+        assert_eq!(summary.message, "record [REDACTED]");
+
+        // Other results, e.g. of Python functions, as plain text.
+        let output = processor.process_output(format!("patient-0042 {}", "x".repeat(100)));
+        assert!(output.len() <= 64);
+        assert!(output.starts_with("[REDACTED] xx"));
+        assert!(output.ends_with(TRUNCATION_MARKER));
+
+        // The reasons of user errors end up in the task result, too.
+        let error = FunctionError::invalid_input_data("input", "bad record patient-0042").into();
+        let failure = TaskFailure::from_error(processor.process_error(error));
+        assert_eq!(
+            failure.reason,
+            "Invalid data in input input: bad record [REDACTED]"
+        );
+        let error = FunctionError::invalid_arguments("y".repeat(100)).into();
+        let failure = TaskFailure::from_error(processor.process_error(error));
+        assert!(failure.reason.ends_with(TRUNCATION_MARKER));
+        assert_eq!(
+            failure.category,
+            teaclave_types::TaskFailureCategory::InvalidArguments
+        );
+    }
+}
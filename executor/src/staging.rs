@@ -0,0 +1,181 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::format;
+
+use teaclave_types::{FileSpec, StagingDeclaration, TeaclaveRuntime};
+
+use thiserror::Error;
+
+/// Error returned before a function runs when required files of it are not staged. Lists them
+/// in the descriptor notation, e.g. `input input_data*`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Missing staged files: {}", .0.join(", "))]
+pub struct MissingStagedFiles(pub Vec<String>);
+
+/// Identifiers of the files staged for a task.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StagedKeys {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+impl StagedKeys {
+    /// The files staged in the `runtime`, or `None` if the runtime cannot list them, in which
+    /// case an empty list would report every required file as missing.
+    pub fn of(runtime: &dyn TeaclaveRuntime) -> Option<Self> {
+        if !runtime.lists_staged_files() {
+            return None;
+        }
+        Some(Self {
+            inputs: runtime.input_keys(),
+            outputs: runtime.output_keys(),
+        })
+    }
+}
+
+/// Preflight of an execution: checks the `staged` files against the files the function
+/// `declared`, resolved for the arguments of the execution. Fails with the required files which
+/// are not staged, and otherwise returns warnings about staged files the function does not use.
+pub fn check_staging(
+    declared: &StagingDeclaration,
+    staged: &StagedKeys,
+) -> Result<Vec<String>, MissingStagedFiles> {
+    let mut missing = missing_files("input", &declared.inputs, &staged.inputs);
+    missing.extend(missing_files("output", &declared.outputs, &staged.outputs));
+    if !missing.is_empty() {
+        return Err(MissingStagedFiles(missing));
+    }
+
+    let mut warnings = Vec::new();
+    for (direction, specs, keys) in [
+        ("input", &declared.inputs, &staged.inputs),
+        ("output", &declared.outputs, &staged.outputs),
+    ] {
+        for key in keys {
+            if !specs.iter().any(|spec| spec.matches(key)) {
+                warnings.push(format!(
+                    "Staged {} {} is not used by the function",
+                    direction, key
+                ));
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+fn missing_files(direction: &str, specs: &[FileSpec], keys: &[String]) -> Vec<String> {
+    specs
+        .iter()
+        .filter(|spec| spec.required && !keys.iter().any(|key| spec.matches(key)))
+        .map(|spec| format!("{} {}", direction, spec.key))
+        .collect()
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_missing_required_input,
+            test_unused_staged_files,
+            test_prefix_pattern,
+            test_unlisted_files,
+        )
+    }
+
+    fn staged(inputs: &[&str], outputs: &[&str]) -> StagedKeys {
+        StagedKeys {
+            inputs: inputs.iter().map(|s| s.to_string()).collect(),
+            outputs: outputs.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn test_missing_required_input() {
+        let declared = StagingDeclaration::new()
+            .input(FileSpec::fixed("model_file").required())
+            .input(FileSpec::fixed("data_file").required())
+            .input(FileSpec::fixed("weights"))
+            .output(FileSpec::fixed("result_file").required());
+
+        let error = check_staging(&declared, &staged(&["data_file"], &[])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Missing staged files: input model_file, output result_file"
+        );
+        // Optional files may be left out.
+        let staged = staged(&["model_file", "data_file"], &["result_file"]);
+        assert_eq!(check_staging(&declared, &staged), Ok(vec![]));
+    }
+
+    fn test_unused_staged_files() {
+        let declared = StagingDeclaration::new()
+            .input(FileSpec::fixed("dataset").required())
+            .output(FileSpec::fixed("top_values"));
+
+        let staged = staged(&["dataset", "notes"], &["top_values", "report"]);
+        assert_eq!(
+            check_staging(&declared, &staged).unwrap(),
+            vec![
+                "Staged input notes is not used by the function",
+                "Staged output report is not used by the function",
+            ]
+        );
+    }
+
+    fn test_prefix_pattern() {
+        // The declaration of builtin-private-join-and-compute, which aggregates any number of
+        // inputs.
+        let declared = StagingDeclaration::new()
+            .input(FileSpec::prefix("input_data").required())
+            .output(FileSpec::prefix("output_data").required());
+
+        let staged_files = staged(
+            &["input_data0", "input_data1", "input_data2"],
+            &["output_data0", "output_data1", "output_data2"],
+        );
+        assert_eq!(check_staging(&declared, &staged_files), Ok(vec![]));
+
+        let staged_files = staged(&["data0", "input_data1"], &["output_data1"]);
+        assert_eq!(
+            check_staging(&declared, &staged_files).unwrap(),
+            vec!["Staged input data0 is not used by the function"]
+        );
+
+        let error = check_staging(&declared, &staged(&["data0"], &["output_data1"]));
+        assert_eq!(
+            error,
+            Err(MissingStagedFiles(vec!["input input_data*".to_string()]))
+        );
+    }
+
+    fn test_unlisted_files() {
+        // A runtime which cannot list its files is not taken to have none staged.
+        struct Unlisted;
+        impl TeaclaveRuntime for Unlisted {
+            fn open_input(&self, _: &str) -> anyhow::Result<Box<dyn std::io::Read>> {
+                unimplemented!()
+            }
+            fn create_output(&self, _: &str) -> anyhow::Result<Box<dyn std::io::Write>> {
+                unimplemented!()
+            }
+        }
+        assert_eq!(StagedKeys::of(&Unlisted), None);
+    }
+}
@@ -0,0 +1,248 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::format;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use teaclave_types::{
+    ExecutionLog, FunctionArguments, FunctionError, FunctionRuntime, ResourceLimitExceeded,
+    TeaclaveExecutor, WorkerHealth,
+};
+
+use anyhow::Result;
+
+/// Executor which runs another executor on a dedicated thread and gives up waiting for it at a
+/// deadline, so that functions which never reach a cancellation checkpoint cannot hang the
+/// worker.
+///
+/// At the soft timeout, the cancellation token of the execution is tripped, and a function
+/// stopping because of it fails with `ResourceLimitExceeded::WallTime`. At the hard timeout, the
+/// execution is abandoned and fails with `FunctionError::Timeout`. An abandoned thread cannot be
+/// killed inside the enclave: it keeps running, and holds its TCS until the enclave restarts.
+/// Such leaks are counted in the `WorkerHealth` of the worker.
+pub struct TimeoutExecutor {
+    inner: Arc<dyn TeaclaveExecutor + Send + Sync>,
+    soft_timeout: Option<Duration>,
+    hard_timeout: Duration,
+    health: WorkerHealth,
+}
+
+impl TimeoutExecutor {
+    pub fn new(
+        inner: Box<dyn TeaclaveExecutor + Send + Sync>,
+        hard_timeout: Duration,
+        health: WorkerHealth,
+    ) -> Self {
+        Self {
+            inner: Arc::from(inner),
+            soft_timeout: None,
+            hard_timeout,
+            health,
+        }
+    }
+
+    /// Trips the cancellation token after `timeout`, which is capped at the hard timeout.
+    pub fn soft_timeout(mut self, timeout: Duration) -> Self {
+        self.soft_timeout = Some(timeout);
+        self
+    }
+}
+
+impl TeaclaveExecutor for TimeoutExecutor {
+    fn execute(
+        &self,
+        name: String,
+        arguments: FunctionArguments,
+        payload: Vec<u8>,
+        runtime: FunctionRuntime,
+    ) -> Result<String> {
+        let cancellation = runtime.cancellation();
+        // Log records are captured per thread, so the log moves along with the function.
+        let log = ExecutionLog::current();
+        let inner = self.inner.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name(format!("function {}", name))
+            .spawn(move || {
+                let run = move || inner.execute(name, arguments, payload, runtime);
+                let result = match log {
+                    Some(log) => log.scope(run),
+                    None => run(),
+                };
+                // Nobody is waiting any more if the execution was abandoned.
+                let _ = sender.send(result);
+            })?;
+
+        let soft_timeout = self
+            .soft_timeout
+            .unwrap_or(self.hard_timeout)
+            .min(self.hard_timeout);
+        match receiver.recv_timeout(soft_timeout) {
+            Ok(result) => return result,
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Function thread panicked"),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        cancellation.cancel();
+        match receiver.recv_timeout(self.hard_timeout - soft_timeout) {
+            // The function may have finished just before it noticed the cancellation.
+            Ok(Ok(summary)) => Ok(summary),
+            Ok(Err(error)) => match FunctionError::from(error) {
+                FunctionError::Cancelled => {
+                    Err(ResourceLimitExceeded::WallTime(soft_timeout).into())
+                }
+                error => Err(error.into()),
+            },
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Function thread panicked"),
+            Err(RecvTimeoutError::Timeout) => {
+                self.health.record_leaked_thread();
+                log::error!(
+                    "Abandoned a function after {:?}, {} threads leaked so far",
+                    self.hard_timeout,
+                    self.health.leaked_threads()
+                );
+                Err(FunctionError::Timeout(self.hard_timeout).into())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_finishes_in_time,
+            test_soft_timeout_cancels_cooperative_function,
+            test_hard_timeout_abandons_spinning_function,
+        )
+    }
+
+    /// Stands in for a builtin which checks for cancellation in its loop.
+    struct CooperativeExecutor {
+        iterations: usize,
+    }
+
+    impl TeaclaveExecutor for CooperativeExecutor {
+        fn execute(
+            &self,
+            _name: String,
+            _arguments: FunctionArguments,
+            _payload: Vec<u8>,
+            runtime: FunctionRuntime,
+        ) -> Result<String> {
+            let cancellation = runtime.cancellation();
+            for _ in 0..self.iterations {
+                cancellation.checkpoint()?;
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok("done".to_string())
+        }
+    }
+
+    /// Stands in for a builtin stuck in a loop without checkpoints. It only stops once the
+    /// test releases it, so that the test does not leave a thread spinning behind.
+    struct SpinningExecutor(Arc<AtomicBool>);
+
+    impl TeaclaveExecutor for SpinningExecutor {
+        fn execute(
+            &self,
+            _name: String,
+            _arguments: FunctionArguments,
+            _payload: Vec<u8>,
+            _runtime: FunctionRuntime,
+        ) -> Result<String> {
+            while !self.0.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok("released".to_string())
+        }
+    }
+
+    fn execute(executor: &TimeoutExecutor) -> Result<String> {
+        let runtime = RawIoRuntime::new(StagedFiles::default(), StagedFiles::default());
+        executor.execute(
+            "test".to_string(),
+            FunctionArguments::default(),
+            vec![],
+            Box::new(runtime),
+        )
+    }
+
+    fn test_finishes_in_time() {
+        let health = WorkerHealth::new();
+        let executor = TimeoutExecutor::new(
+            Box::new(CooperativeExecutor { iterations: 3 }),
+            Duration::from_secs(10),
+            health,
+        )
+        .soft_timeout(Duration::from_secs(5));
+        assert_eq!(execute(&executor).unwrap(), "done");
+    }
+
+    fn test_soft_timeout_cancels_cooperative_function() {
+        let health = WorkerHealth::new();
+        let executor = TimeoutExecutor::new(
+            Box::new(CooperativeExecutor {
+                iterations: usize::MAX,
+            }),
+            Duration::from_secs(10),
+            health.clone(),
+        )
+        .soft_timeout(Duration::from_millis(20));
+
+        let error = execute(&executor).unwrap_err();
+        let failure = TaskFailure::from_error(error);
+        assert_eq!(failure.category, TaskFailureCategory::ResourceLimit);
+        assert_eq!(
+            failure.reason,
+            ResourceLimitExceeded::WallTime(Duration::from_millis(20)).to_string()
+        );
+        // The function stopped by itself, so its thread is not leaked.
+        assert_eq!(health.leaked_threads(), 0);
+    }
+
+    fn test_hard_timeout_abandons_spinning_function() {
+        let health = WorkerHealth::new();
+        let released = Arc::new(AtomicBool::new(false));
+        let executor = TimeoutExecutor::new(
+            Box::new(SpinningExecutor(released.clone())),
+            Duration::from_millis(50),
+            health.clone(),
+        )
+        .soft_timeout(Duration::from_millis(20));
+
+        let error = execute(&executor).unwrap_err();
+        let failure = TaskFailure::from_error(error);
+        assert_eq!(failure.category, TaskFailureCategory::Timeout);
+        assert_eq!(
+            failure.reason,
+            "Execution did not stop within the hard timeout of 50ms"
+        );
+        assert_eq!(health.leaked_threads(), 1);
+
+        released.store(true, Ordering::SeqCst);
+    }
+}
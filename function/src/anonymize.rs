@@ -0,0 +1,446 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{
+    check_format, open_sniffed, split_fields, DetectedFormat, ExpectedFormat,
+};
+use ring::digest;
+use std::collections::BTreeMap;
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+    CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_DATASET: &str = "dataset";
+const OUT_ANONYMIZED: &str = "anonymized";
+
+#[derive(Default)]
+pub struct Anonymize;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnonymizeArguments {
+    /// Rule applied to each listed column, by column name. Other columns pass through.
+    rules: BTreeMap<String, Rule>,
+    /// Salt of the `hash` rule. Required if any column is hashed.
+    #[serde(default)]
+    salt: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "rule", rename_all = "snake_case", deny_unknown_fields)]
+enum Rule {
+    /// Removes the column.
+    Drop,
+    /// Replaces values by the hex encoded SHA-256 digest of the salt followed by the value.
+    Hash,
+    /// Replaces all but the first `keep_first` and last `keep_last` characters by '*'. Values
+    /// too short to hide anything are masked completely.
+    Mask {
+        #[serde(default)]
+        keep_first: usize,
+        #[serde(default)]
+        keep_last: usize,
+    },
+    /// Replaces numbers by the bucket `lo..hi` of width `bucket_width` they fall into, with
+    /// `lo` a multiple of the width and `hi` excluded.
+    GeneralizeNumeric { bucket_width: f64 },
+}
+
+impl Rule {
+    fn name(&self) -> &'static str {
+        match self {
+            Rule::Drop => "drop",
+            Rule::Hash => "hash",
+            Rule::Mask { .. } => "mask",
+            Rule::GeneralizeNumeric { .. } => "generalize_numeric",
+        }
+    }
+}
+
+/// Rules by column index, checked against the header.
+struct ColumnRules {
+    rules: Vec<Option<Rule>>,
+    salt: String,
+    delimiter: char,
+}
+
+impl ColumnRules {
+    fn new(
+        args: AnonymizeArguments,
+        header: &[String],
+        delimiter: char,
+    ) -> Result<Self, FunctionError> {
+        let mut rules = vec![None; header.len()];
+        for (column, rule) in args.rules {
+            let i = header
+                .iter()
+                .position(|name| name == &column)
+                .ok_or_else(|| {
+                    FunctionError::invalid_arguments(format!(
+                        "rules name unknown column '{}'",
+                        column
+                    ))
+                })?;
+            rules[i] = Some(rule);
+        }
+        Ok(Self {
+            rules,
+            salt: args.salt.unwrap_or_default(),
+            delimiter,
+        })
+    }
+
+    /// Applies the rules to the fields of a row, leaving out dropped columns.
+    fn apply(&self, fields: &[&str], row: usize) -> Result<Vec<String>, FunctionError> {
+        let mut output = Vec::with_capacity(fields.len());
+        for (field, rule) in fields.iter().zip(&self.rules) {
+            let rule = match rule {
+                None => {
+                    output.push(field.to_string());
+                    continue;
+                }
+                Some(Rule::Drop) => continue,
+                Some(rule) => rule,
+            };
+            let value = unquote(field);
+            let anonymized = match rule {
+                Rule::Hash => salted_hash(&self.salt, &value),
+                Rule::Mask {
+                    keep_first,
+                    keep_last,
+                } => mask(&value, *keep_first, *keep_last),
+                Rule::GeneralizeNumeric { bucket_width } if !value.trim().is_empty() => {
+                    let number: f64 = value.trim().parse().map_err(|_| {
+                        FunctionError::invalid_input_data(
+                            IN_DATASET,
+                            format!("row {}: '{}' is not a number", row, value),
+                        )
+                    })?;
+                    let lo = (number / bucket_width).floor() * bucket_width;
+                    format!("{}..{}", lo, lo + bucket_width)
+                }
+                _ => value,
+            };
+            output.push(quote(&anonymized, self.delimiter));
+        }
+        Ok(output)
+    }
+}
+
+fn salted_hash(salt: &str, value: &str) -> String {
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(salt.as_bytes());
+    context.update(value.as_bytes());
+    hex::encode(context.finish().as_ref())
+}
+
+fn mask(value: &str, keep_first: usize, keep_last: usize) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() <= keep_first + keep_last {
+        return "*".repeat(chars.len());
+    }
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            if i < keep_first || i >= chars.len() - keep_last {
+                c
+            } else {
+                '*'
+            }
+        })
+        .collect()
+}
+
+/// Removes the quotes around a CSV field and unescapes doubled quotes inside.
+fn unquote(field: &str) -> String {
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(inner) => inner.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+/// Quotes a CSV field if it contains the delimiter or quotes.
+fn quote(value: &str, delimiter: char) -> String {
+    if value.contains(delimiter) || value.contains('"') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl Anonymize {
+    pub const NAME: &'static str = "builtin-anonymize";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Anonymizes the columns of a CSV dataset with a header row, one row at a time, by
+    /// dropping, hashing, masking or generalizing the columns named in the rules.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: AnonymizeArguments = arguments.into_typed()?;
+        for (column, rule) in &args.rules {
+            if let Rule::GeneralizeNumeric { bucket_width } = rule {
+                if !(*bucket_width > 0.0 && bucket_width.is_finite()) {
+                    return Err(FunctionError::invalid_arguments(format!(
+                        "bucket_width of column '{}' must be a positive number",
+                        column
+                    )));
+                }
+            }
+        }
+        // Unsalted hashes of names and the like are easily reversed with a dictionary.
+        let hashes = args.rules.values().any(|rule| rule == &Rule::Hash);
+        if hashes && args.salt.as_deref().unwrap_or_default().is_empty() {
+            return Err(FunctionError::invalid_arguments(
+                "A salt is required to hash columns",
+            ));
+        }
+        let applied: Vec<String> = args
+            .rules
+            .iter()
+            .map(|(column, rule)| format!("{}={}", column, rule.name()))
+            .collect();
+
+        let (detected, input) = open_sniffed(runtime.as_ref(), IN_DATASET)?;
+        check_format(Self::NAME, IN_DATASET, detected, ExpectedFormat::Csv)?;
+        let delimiter = match detected {
+            DetectedFormat::Csv { delimiter, .. } => delimiter,
+            _ => ',',
+        };
+
+        let mut lines = BufReader::new(input).lines();
+        let header_line = lines.next().transpose()?.ok_or_else(|| {
+            FunctionError::invalid_input_data(IN_DATASET, "dataset has no header row")
+        })?;
+        let header_line = header_line.trim_end_matches('\r');
+        let header_fields = split_fields(header_line, delimiter);
+        let header: Vec<String> = header_fields
+            .iter()
+            .map(|name| unquote(name.trim()))
+            .collect();
+        let rules = ColumnRules::new(args, &header, delimiter)?;
+
+        let cancellation = runtime.cancellation();
+        let mut output = runtime.create_output(OUT_ANONYMIZED)?;
+        let mut bytes = 0;
+        let header_output: Vec<String> = header_fields
+            .iter()
+            .zip(&rules.rules)
+            .filter(|(_, rule)| !matches!(rule, Some(Rule::Drop)))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        let line = header_output.join(&delimiter.to_string());
+        writeln!(output, "{}", line)?;
+        bytes += line.len() + 1;
+
+        let mut rows = 0;
+        for (i, line) in lines.enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows += 1;
+            let fields = split_fields(line, delimiter);
+            if fields.len() != header.len() {
+                return Err(FunctionError::invalid_input_data(
+                    IN_DATASET,
+                    format!(
+                        "row {} has {} fields but the header has {}",
+                        rows,
+                        fields.len(),
+                        header.len()
+                    ),
+                ));
+            }
+            let line = rules.apply(&fields, rows)?.join(&delimiter.to_string());
+            writeln!(output, "{}", line)?;
+            bytes += line.len() + 1;
+        }
+        output.flush()?;
+
+        let summary = FunctionSummary::new(format!(
+            "Anonymized {} rows with rules {}",
+            rows,
+            applied.join(", ")
+        ))
+        .metric("rows", rows as f64)
+        .metric("rules", applied.len() as f64)
+        .output(OUT_ANONYMIZED, OutputInfo::new(bytes as u64));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_anonymize_drop,
+            test_anonymize_hash,
+            test_anonymize_hash_requires_salt,
+            test_anonymize_mask,
+            test_anonymize_generalize_numeric,
+            test_anonymize_unknown_column,
+        )
+    }
+
+    const DATASET: &str = "name,email,age,city\n\
+                           Alice,alice@example.com,34,Berlin\n\
+                           Bob,bob@example.com,27,\"Paris, FR\"\n\
+                           Eve,eve@example.com,45,Rome\n";
+
+    fn anonymize(arguments: serde_json::Value) -> Result<(FunctionSummary, String), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            Anonymize::new(),
+            arguments,
+            hashmap!(IN_DATASET => DATASET),
+            [OUT_ANONYMIZED]
+        );
+        let output = String::from_utf8(outputs.remove(OUT_ANONYMIZED).unwrap()).unwrap();
+        Ok((summary?, output))
+    }
+
+    fn test_anonymize_drop() {
+        let (summary, output) =
+            anonymize(json!({ "rules": { "email": { "rule": "drop" } } })).unwrap();
+        assert_eq!(
+            output,
+            "name,age,city\n\
+             Alice,34,Berlin\n\
+             Bob,27,\"Paris, FR\"\n\
+             Eve,45,Rome\n"
+        );
+        assert_eq!(summary.message, "Anonymized 3 rows with rules email=drop");
+        assert_eq!(summary.metrics["rows"], 3.0);
+        assert_eq!(summary.outputs[OUT_ANONYMIZED].size, output.len() as u64);
+    }
+
+    fn test_anonymize_hash() {
+        let arguments = json!({ "rules": { "email": { "rule": "hash" } }, "salt": "pepper" });
+        let (_, output) = anonymize(arguments.clone()).unwrap();
+        let rows: Vec<Vec<&str>> = output.lines().map(|l| split_fields(l, ',')).collect();
+        assert_eq!(rows[0], vec!["name", "email", "age", "city"]);
+        assert_eq!(rows[1][1], salted_hash("pepper", "alice@example.com"));
+        assert_eq!(rows[1][1].len(), 64);
+        assert_ne!(rows[1][1], rows[2][1]);
+        assert!(!output.contains("example.com"));
+
+        // The same salt gives the same hashes, so hashed columns can still be joined.
+        let (_, again) = anonymize(arguments).unwrap();
+        assert_eq!(output, again);
+        let (_, other_salt) =
+            anonymize(json!({ "rules": { "email": { "rule": "hash" } }, "salt": "salt" })).unwrap();
+        assert_ne!(output, other_salt);
+    }
+
+    fn test_anonymize_hash_requires_salt() {
+        for arguments in [
+            json!({ "rules": { "email": { "rule": "hash" } } }),
+            json!({ "rules": { "email": { "rule": "hash" } }, "salt": "" }),
+        ] {
+            let err = anonymize(arguments).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Invalid arguments: A salt is required to hash columns"
+            );
+        }
+    }
+
+    fn test_anonymize_mask() {
+        let (_, output) = anonymize(json!({ "rules": {
+            "name": { "rule": "mask", "keep_first": 1 },
+            "email": { "rule": "mask", "keep_first": 2, "keep_last": 4 },
+        } }))
+        .unwrap();
+        assert_eq!(
+            output,
+            "name,email,age,city\n\
+             A****,al***********.com,34,Berlin\n\
+             B**,bo*********.com,27,\"Paris, FR\"\n\
+             E**,ev*********.com,45,Rome\n"
+        );
+        assert_eq!(mask("abc", 2, 2), "***");
+    }
+
+    fn test_anonymize_generalize_numeric() {
+        let (summary, output) = anonymize(json!({ "rules": {
+            "age": { "rule": "generalize_numeric", "bucket_width": 10 },
+            "name": { "rule": "drop" },
+        } }))
+        .unwrap();
+        assert_eq!(
+            output,
+            "email,age,city\n\
+             alice@example.com,30..40,Berlin\n\
+             bob@example.com,20..30,\"Paris, FR\"\n\
+             eve@example.com,40..50,Rome\n"
+        );
+        assert_eq!(
+            summary.message,
+            "Anonymized 3 rows with rules age=generalize_numeric, name=drop"
+        );
+
+        let err = anonymize(json!({ "rules": {
+            "city": { "rule": "generalize_numeric", "bucket_width": 10 },
+        } }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input dataset: row 1: 'Berlin' is not a number"
+        );
+        let err = anonymize(json!({ "rules": {
+            "age": { "rule": "generalize_numeric", "bucket_width": 0 },
+        } }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: bucket_width of column 'age' must be a positive number"
+        );
+    }
+
+    fn test_anonymize_unknown_column() {
+        let err = anonymize(json!({ "rules": {
+            "email": { "rule": "drop" },
+            "phone": { "rule": "mask" },
+        } }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: rules name unknown column 'phone'"
+        );
+
+        let err = anonymize(json!({ "rules": { "email": { "rule": "encrypt" } } })).unwrap_err();
+        assert!(matches!(err, FunctionError::InvalidArguments(_)));
+    }
+}
@@ -0,0 +1,350 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{
+    check_format, open_sniffed, split_fields, unquote, DetectedFormat, ExpectedFormat,
+};
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_DATASET: &str = "dataset";
+const OUT_ANALYSIS: &str = "analysis";
+
+#[derive(Default)]
+pub struct BenfordCheck;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BenfordArguments {
+    /// Name of the column to check, as in the header row.
+    column: String,
+    /// Fewest usable values the test is run on. First digits of small samples say little.
+    #[serde(default = "default_min_rows")]
+    min_rows: u64,
+    /// Significance level below which the p-value fails the check.
+    #[serde(default = "default_alpha")]
+    alpha: f64,
+}
+
+fn default_min_rows() -> u64 {
+    100
+}
+
+fn default_alpha() -> f64 {
+    0.05
+}
+
+/// Share of values with first digit d + 1 under Benford's law, log10(1 + 1/d).
+fn expected_fraction(digit: usize) -> f64 {
+    (1.0 + 1.0 / digit as f64).log10()
+}
+
+/// Upper tail probability of the chi-squared distribution with 8 degrees of freedom, the
+/// regularized gamma function Q(4, x/2), which has a closed form for integer shapes.
+fn chi_squared_8_p_value(statistic: f64) -> f64 {
+    let y = statistic / 2.0;
+    (-y).exp() * (1.0 + y + y * y / 2.0 + y * y * y / 6.0)
+}
+
+/// First significant digit of a positive decimal number as written, e.g. 3 for `0.031` or
+/// `3.1e-2`.
+fn first_digit(field: &str) -> Option<usize> {
+    field
+        .chars()
+        .find(|c| ('1'..='9').contains(c))
+        .map(|c| c as usize - '0' as usize)
+}
+
+impl BenfordCheck {
+    pub const NAME: &'static str = "builtin-benford";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("column", ArgumentType::String)
+                    .required()
+                    .description("Name of the numeric column to check"),
+            )
+            .argument(
+                ArgumentSpec::new("min_rows", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .description("Fewest usable values to run the test on, 100 by default"),
+            )
+            .argument(
+                ArgumentSpec::new("alpha", ArgumentType::Number)
+                    .minimum(0.0)
+                    .maximum(1.0)
+                    .description("Significance level of the test, 0.05 by default"),
+            )
+    }
+
+    /// Tests whether the first digits of a column of a CSV dataset with a header row follow
+    /// Benford's law, e.g. to flag fabricated amounts in financial records. The observed digit
+    /// counts are compared to the expected ones with a chi-squared test, which fails if its
+    /// p-value is below `alpha`. Non-numeric and non-positive values have no first digit in
+    /// the sense of the law, and are counted and left out.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: BenfordArguments = arguments.into_typed()?;
+        if !(args.alpha > 0.0 && args.alpha < 1.0) {
+            return Err(FunctionError::invalid_arguments(
+                "alpha must be between 0 and 1",
+            ));
+        }
+
+        let (detected, input) = open_sniffed(runtime.as_ref(), IN_DATASET)?;
+        check_format(Self::NAME, IN_DATASET, detected, ExpectedFormat::Csv)?;
+        let delimiter = match detected {
+            DetectedFormat::Csv { delimiter, .. } => delimiter,
+            _ => ',',
+        };
+        let mut lines = BufReader::new(input).lines();
+        let header = lines.next().transpose()?.ok_or_else(|| {
+            FunctionError::invalid_input_data(IN_DATASET, "dataset has no header row")
+        })?;
+        let header = split_fields(header.trim_end_matches('\r'), delimiter)
+            .into_iter()
+            .map(|name| unquote(name.trim()).to_string())
+            .collect::<Vec<_>>();
+        let column = header
+            .iter()
+            .position(|name| name == &args.column)
+            .ok_or_else(|| {
+                FunctionError::invalid_arguments(format!("unknown column '{}'", args.column))
+            })?;
+
+        let mut counts = [0u64; 9];
+        let mut non_positive = 0u64;
+        let mut non_numeric = 0u64;
+        let mut rows = 0;
+        for (i, line) in lines.enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                runtime.cancellation().checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows += 1;
+            let fields = split_fields(line, delimiter);
+            if fields.len() != header.len() {
+                return Err(FunctionError::invalid_input_data(
+                    IN_DATASET,
+                    format!(
+                        "row {} has {} fields but the header has {}",
+                        rows,
+                        fields.len(),
+                        header.len()
+                    ),
+                ));
+            }
+            let field = unquote(fields[column].trim()).trim();
+            match field.parse::<f64>() {
+                Ok(x) if !x.is_finite() => non_numeric += 1,
+                Ok(x) if x <= 0.0 => non_positive += 1,
+                Ok(_) => match first_digit(field) {
+                    Some(digit) => counts[digit - 1] += 1,
+                    None => non_numeric += 1,
+                },
+                Err(_) => non_numeric += 1,
+            }
+        }
+
+        let values: u64 = counts.iter().sum();
+        if values < args.min_rows {
+            return Err(FunctionError::invalid_input_data(
+                IN_DATASET,
+                format!(
+                    "column '{}' has {} usable values but at least {} are required",
+                    args.column, values, args.min_rows
+                ),
+            ));
+        }
+
+        let mut output = runtime.create_output(OUT_ANALYSIS)?;
+        let mut bytes = 0;
+        let mut statistic = 0.0;
+        let header = "digit,count,observed,expected,chi_squared";
+        writeln!(output, "{}", header)?;
+        bytes += header.len() + 1;
+        for (i, &count) in counts.iter().enumerate() {
+            let digit = i + 1;
+            let expected = expected_fraction(digit);
+            let expected_count = expected * values as f64;
+            let contribution = (count as f64 - expected_count).powi(2) / expected_count;
+            statistic += contribution;
+            let line = format!(
+                "{},{},{},{},{}",
+                digit,
+                count,
+                count as f64 / values as f64,
+                expected,
+                contribution
+            );
+            writeln!(output, "{}", line)?;
+            bytes += line.len() + 1;
+        }
+        output.flush()?;
+
+        let p_value = chi_squared_8_p_value(statistic);
+        let passed = p_value >= args.alpha;
+        let summary = FunctionSummary::new(format!(
+            "Benford check of column '{}' {}: chi-squared {:.4} with 8 degrees of freedom, \
+             p-value {:.4} at alpha {} over {} values ({} non-positive, {} non-numeric excluded)",
+            args.column,
+            if passed { "passed" } else { "failed" },
+            statistic,
+            p_value,
+            args.alpha,
+            values,
+            non_positive,
+            non_numeric
+        ))
+        .metric("values", values as f64)
+        .metric("non_positive", non_positive as f64)
+        .metric("non_numeric", non_numeric as f64)
+        .metric("chi_squared", statistic)
+        .metric("p_value", p_value)
+        .metric("passed", if passed { 1.0 } else { 0.0 })
+        .output(OUT_ANALYSIS, OutputInfo::new(bytes as u64));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_benford_conforming,
+            test_benford_uniform,
+            test_benford_invalid
+        )
+    }
+
+    fn benford_check(
+        arguments: serde_json::Value,
+        input: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            BenfordCheck::new(),
+            arguments,
+            hashmap!(IN_DATASET => input),
+            [OUT_ANALYSIS]
+        );
+        let output = String::from_utf8(outputs.remove(OUT_ANALYSIS).unwrap()).unwrap();
+        Ok((summary?, output))
+    }
+
+    fn dataset(amounts: impl Iterator<Item = String>) -> String {
+        let mut dataset = "id,amount\n".to_string();
+        for (i, amount) in amounts.enumerate() {
+            dataset.push_str(&format!("{},{}\n", i, amount));
+        }
+        dataset
+    }
+
+    fn test_benford_conforming() {
+        // 10^u for u evenly spread over three decades follows Benford's law.
+        let amounts = (0..1000)
+            .map(|i| format!("{:.2}", 10f64.powf(3.0 * (i as f64 + 0.5) / 1000.0)))
+            .chain(
+                vec!["0", "-12.5", "n/a", "", "\"inf\""]
+                    .into_iter()
+                    .map(String::from),
+            );
+        let (summary, output) =
+            benford_check(json!({"column": "amount"}), &dataset(amounts)).unwrap();
+
+        assert!(summary
+            .message
+            .starts_with("Benford check of column 'amount' passed"));
+        assert!(summary
+            .message
+            .ends_with("over 1000 values (2 non-positive, 3 non-numeric excluded)"));
+        assert_eq!(summary.metrics["passed"], 1.0);
+        assert_eq!(summary.metrics["values"], 1000.0);
+        assert_eq!(summary.metrics["non_positive"], 2.0);
+        assert_eq!(summary.metrics["non_numeric"], 3.0);
+        assert!(summary.metrics["p_value"] > 0.99);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], "digit,count,observed,expected,chi_squared");
+        assert!(lines[1].starts_with("1,301,0.301,0.30102999566398"));
+        assert!(lines[9].starts_with("9,46,0.046,0.04575749056067"));
+    }
+
+    fn test_benford_uniform() {
+        // Every first digit equally often, as in made-up amounts.
+        let amounts = (0..900)
+            .map(|i| format!("{}{:02}", i % 9 + 1, i % 100))
+            .chain(vec!["-1", "x"].into_iter().map(String::from));
+        let (summary, output) = benford_check(
+            json!({"column": "amount", "alpha": 0.01}),
+            &dataset(amounts),
+        )
+        .unwrap();
+
+        assert!(summary
+            .message
+            .starts_with("Benford check of column 'amount' failed"));
+        assert_eq!(summary.metrics["passed"], 0.0);
+        assert_eq!(summary.metrics["values"], 900.0);
+        assert_eq!(summary.metrics["non_positive"], 1.0);
+        assert_eq!(summary.metrics["non_numeric"], 1.0);
+        assert!(summary.metrics["p_value"] < 1e-10);
+        assert!(output.lines().skip(1).all(|line| line.contains(",100,")));
+    }
+
+    fn test_benford_invalid() {
+        let amounts = (1..=50).map(|i| i.to_string());
+        let input = dataset(amounts);
+
+        let err = benford_check(json!({"column": "amount"}), &input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input dataset: \
+             column 'amount' has 50 usable values but at least 100 are required"
+        );
+        assert!(benford_check(json!({"column": "amount", "min_rows": 50}), &input).is_ok());
+
+        let err = benford_check(json!({"column": "total", "min_rows": 1}), &input).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid arguments: unknown column 'total'");
+        let err = benford_check(json!({"column": "amount", "alpha": 1.5}), &input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: alpha must be between 0 and 1"
+        );
+    }
+}
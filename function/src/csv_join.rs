@@ -0,0 +1,818 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{
+    check_format, open_sniffed, split_fields, unquote, DetectedFormat, ExpectedFormat,
+};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::format;
+use std::io::{self, BufRead, BufReader, Lines, Read, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, CancellationToken, FunctionArguments,
+    FunctionError, FunctionRuntime, FunctionSummary, OutputInfo, TeaclaveRuntime,
+    CANCELLATION_CHECK_INTERVAL, DEFAULT_CHUNK_SIZE,
+};
+
+const IN_LEFT: &str = "left";
+const IN_RIGHT: &str = "right";
+const OUT_RESULT: &str = "result";
+
+/// Sort buffer of each input if the execution environment has no memory budget.
+const DEFAULT_SORT_BUFFER_BYTES: u64 = 64 * 1024 * 1024;
+/// Memory a buffered row is assumed to take besides its key and line.
+const ROW_OVERHEAD_BYTES: u64 = 64;
+
+#[derive(Default)]
+pub struct CsvJoin;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CsvJoinArguments {
+    /// Key column of each input: a name as in the header row, or an index if the input has
+    /// none.
+    left_key: String,
+    right_key: String,
+    #[serde(default)]
+    join_type: JoinType,
+    /// Columns of each input to write, in order. By default all columns of the left input and
+    /// all but the key of the right input.
+    #[serde(default)]
+    columns_left: Option<Vec<String>>,
+    #[serde(default)]
+    columns_right: Option<Vec<String>>,
+    /// Largest number of rows of either input which may share a key. The rows of a key are
+    /// joined with their cross product, which the guard keeps from blowing up.
+    #[serde(default = "default_max_multiplicity")]
+    max_multiplicity: usize,
+}
+
+fn default_max_multiplicity() -> usize {
+    1000
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum JoinType {
+    /// Only writes the joined rows.
+    Inner,
+    /// Also writes the left rows without a match, with empty right columns.
+    Left,
+}
+
+impl Default for JoinType {
+    fn default() -> Self {
+        JoinType::Inner
+    }
+}
+
+/// Where the key of the rows of an input is.
+#[derive(Clone, Copy)]
+struct KeyColumn {
+    delimiter: char,
+    index: usize,
+}
+
+impl KeyColumn {
+    /// The key of `line`, unquoted, or None if the row is too short to have one.
+    fn key_of(self, line: &str) -> Option<String> {
+        split_fields(line, self.delimiter)
+            .get(self.index)
+            .map(|key| unquote(key.trim()).to_string())
+    }
+}
+
+/// One input of the join, with its header row, if any, and its rows sorted by key.
+struct Side {
+    key: KeyColumn,
+    header: Option<String>,
+    columns: Vec<usize>,
+    rows: SortedRows,
+    num_rows: u64,
+    runs: usize,
+}
+
+impl Side {
+    /// Names of the written columns: as in the header row, or their index without one.
+    fn column_names(&self) -> Vec<String> {
+        match &self.header {
+            Some(header) => project(header, self.key.delimiter, &self.columns)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            None => self
+                .columns
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Finds `column` in the header, or parses it as an index without one.
+fn resolve_column(
+    input: &str,
+    column: &str,
+    header: Option<&[&str]>,
+) -> Result<usize, FunctionError> {
+    let index = match header {
+        Some(names) => names.iter().position(|name| unquote(name.trim()) == column),
+        None => column.parse().ok(),
+    };
+    index.ok_or_else(|| match header {
+        Some(_) => FunctionError::invalid_arguments(format!(
+            "column '{}' is not a column of input {}",
+            column, input
+        )),
+        None => FunctionError::invalid_arguments(format!(
+            "column '{}' must be a column index, as input {} has no header",
+            column, input
+        )),
+    })
+}
+
+impl CsvJoin {
+    pub const NAME: &'static str = "builtin-csv-join";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("left_key", ArgumentType::String)
+                    .required()
+                    .description("Key column of the left input, by name or by index"),
+            )
+            .argument(
+                ArgumentSpec::new("right_key", ArgumentType::String)
+                    .required()
+                    .description("Key column of the right input, by name or by index"),
+            )
+            .argument(
+                ArgumentSpec::new("join_type", ArgumentType::String)
+                    .one_of(&["inner", "left"])
+                    .description("\"inner\" by default, or \"left\" to keep unmatched left rows"),
+            )
+            .argument(
+                ArgumentSpec::new("columns_left", ArgumentType::Array)
+                    .description("Columns of the left input to write, all by default"),
+            )
+            .argument(
+                ArgumentSpec::new("columns_right", ArgumentType::Array)
+                    .description("Columns of the right input to write, all but the key by default"),
+            )
+            .argument(
+                ArgumentSpec::new("max_multiplicity", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .description(
+                        "Largest number of rows of an input sharing a key, 1000 by default",
+                    ),
+            )
+    }
+
+    /// Sort-merge join of two CSV inputs on a key column. Both inputs are sorted by key first:
+    /// in memory if they fit into half of the memory budget of the execution environment each,
+    /// and otherwise in sorted runs spilled to scratch files and merged back. The joined rows
+    /// are written in the order of their keys, and the rows of one key in input order.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: CsvJoinArguments = arguments.into_typed()?;
+        if args.max_multiplicity == 0 {
+            return Err(FunctionError::invalid_arguments(
+                "max_multiplicity must be at least 1",
+            ));
+        }
+        let buffer_bytes = match runtime.environment().memory_budget_bytes {
+            Some(budget) => budget / 2,
+            None => DEFAULT_SORT_BUFFER_BYTES,
+        };
+        let cancellation = runtime.cancellation();
+
+        let mut left = sort_side(
+            runtime.as_ref(),
+            IN_LEFT,
+            &args.left_key,
+            args.columns_left.as_deref(),
+            buffer_bytes,
+            &cancellation,
+        )?;
+        runtime.report_progress(0.25, "sorted the left input");
+        let mut right = sort_side(
+            runtime.as_ref(),
+            IN_RIGHT,
+            &args.right_key,
+            args.columns_right.as_deref(),
+            buffer_bytes,
+            &cancellation,
+        )?;
+        runtime.report_progress(0.5, "sorted the right input");
+
+        let delimiter = left.key.delimiter;
+        let separator = delimiter.to_string();
+        let mut output = runtime.create_output_chunked(OUT_RESULT, DEFAULT_CHUNK_SIZE)?;
+        let mut written = 0u64;
+        let mut write_row = |left_fields: &[&str], right_fields: &[&str]| -> io::Result<()> {
+            let line = left_fields
+                .iter()
+                .chain(right_fields)
+                .map(|field| quote_field(field, delimiter))
+                .collect::<Vec<_>>()
+                .join(separator.as_str());
+            output.write_all(line.as_bytes())?;
+            output.write_all(b"\n")?;
+            written += line.len() as u64 + 1;
+            Ok(())
+        };
+        // If only one input has a header, the columns of the other are named by their index.
+        if left.header.is_some() || right.header.is_some() {
+            let (left_names, right_names) = (left.column_names(), right.column_names());
+            write_row(
+                &left_names.iter().map(String::as_str).collect::<Vec<_>>(),
+                &right_names.iter().map(String::as_str).collect::<Vec<_>>(),
+            )?;
+        }
+
+        let too_many_rows = |input: &str, key: &str| {
+            FunctionError::invalid_input_data(
+                input,
+                format!(
+                    "key '{}' is shared by more than max_multiplicity = {} rows",
+                    key, args.max_multiplicity
+                ),
+            )
+        };
+        let padding = vec![""; right.columns.len()];
+        let mut matched = 0u64;
+        let mut unmatched = 0u64;
+        let mut unmatched_right = 0u64;
+        // The right rows of the key of the latest left row, and how many left rows had it.
+        let mut group_key: Option<String> = None;
+        let mut group: Vec<String> = Vec::new();
+        let mut left_multiplicity = 0;
+        let mut next_right = right.rows.next_row()?;
+        let mut steps = 0u64;
+        while let Some((key, line)) = left.rows.next_row()? {
+            if steps % CANCELLATION_CHECK_INTERVAL as u64 == 0 {
+                cancellation.checkpoint()?;
+            }
+            steps += 1;
+            if group_key.as_deref() == Some(key.as_str()) {
+                left_multiplicity += 1;
+                // Without right rows there is no cross product to guard against.
+                if left_multiplicity > args.max_multiplicity && !group.is_empty() {
+                    return Err(too_many_rows(IN_LEFT, &key));
+                }
+            } else {
+                left_multiplicity = 1;
+                group.clear();
+                while let Some((right_key, right_line)) = next_right.take() {
+                    if steps % CANCELLATION_CHECK_INTERVAL as u64 == 0 {
+                        cancellation.checkpoint()?;
+                    }
+                    steps += 1;
+                    if right_key > key {
+                        next_right = Some((right_key, right_line));
+                        break;
+                    }
+                    if right_key == key {
+                        if group.len() == args.max_multiplicity {
+                            return Err(too_many_rows(IN_RIGHT, &key));
+                        }
+                        group.push(right_line);
+                    } else {
+                        unmatched_right += 1;
+                    }
+                    next_right = right.rows.next_row()?;
+                }
+                group_key = Some(key);
+            }
+
+            let left_fields = project(&line, left.key.delimiter, &left.columns);
+            if group.is_empty() {
+                unmatched += 1;
+                if args.join_type == JoinType::Left {
+                    write_row(&left_fields, &padding)?;
+                }
+            }
+            for right_line in &group {
+                write_row(
+                    &left_fields,
+                    &project(right_line, right.key.delimiter, &right.columns),
+                )?;
+                matched += 1;
+            }
+        }
+        while next_right.is_some() {
+            if steps % CANCELLATION_CHECK_INTERVAL as u64 == 0 {
+                cancellation.checkpoint()?;
+            }
+            steps += 1;
+            unmatched_right += 1;
+            next_right = right.rows.next_row()?;
+        }
+        output.flush()?;
+        runtime.report_progress(1.0, "joined the inputs");
+
+        let summary = FunctionSummary::new(format!(
+            "{} matched rows, {} unmatched rows",
+            matched, unmatched
+        ))
+        .metric("matched_rows", matched as f64)
+        .metric("unmatched_rows", unmatched as f64)
+        .metric("unmatched_right_rows", unmatched_right as f64)
+        .metric("left_rows", left.num_rows as f64)
+        .metric("right_rows", right.num_rows as f64)
+        .metric("spilled_runs", (left.runs + right.runs) as f64)
+        .output(OUT_RESULT, OutputInfo::new(written));
+        Ok(summary)
+    }
+}
+
+/// `field` as written with `delimiter`: quoted if it holds the delimiter and is not quoted yet,
+/// e.g. a field of a right input with another delimiter than the left one.
+fn quote_field(field: &str, delimiter: char) -> Cow<'_, str> {
+    let quoted = field.len() >= 2 && field.starts_with('"') && field.ends_with('"');
+    if quoted || !field.contains(delimiter) {
+        Cow::Borrowed(field)
+    } else {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    }
+}
+
+/// The fields of `line` in `columns`, empty for columns the row lacks.
+fn project<'a>(line: &'a str, delimiter: char, columns: &[usize]) -> Vec<&'a str> {
+    let fields = split_fields(line, delimiter);
+    columns
+        .iter()
+        .map(|&column| fields.get(column).copied().unwrap_or_default())
+        .collect()
+}
+
+/// Opens input `input`, resolves its key and columns and sorts its rows by key.
+fn sort_side(
+    runtime: &dyn TeaclaveRuntime,
+    input: &'static str,
+    key: &str,
+    columns: Option<&[String]>,
+    buffer_bytes: u64,
+    cancellation: &CancellationToken,
+) -> Result<Side, FunctionError> {
+    let (detected, reader) = open_sniffed(runtime, input)?;
+    check_format(CsvJoin::NAME, input, detected, ExpectedFormat::Csv)?;
+    let (delimiter, has_header) = match detected {
+        DetectedFormat::Csv { delimiter, header } => (delimiter, header),
+        _ => (',', false),
+    };
+
+    let mut lines = BufReader::new(reader).lines();
+    let header = if has_header {
+        lines
+            .next()
+            .transpose()?
+            .map(|line| line.trim_end_matches('\r').to_string())
+    } else {
+        None
+    };
+    let header_fields = header.as_deref().map(|line| split_fields(line, delimiter));
+    let key = KeyColumn {
+        delimiter,
+        index: resolve_column(input, key, header_fields.as_deref())?,
+    };
+    let columns = match columns {
+        Some(columns) => Some(
+            columns
+                .iter()
+                .map(|column| resolve_column(input, column, header_fields.as_deref()))
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        None => None,
+    };
+
+    let mut sorter = RunSorter::new(runtime, input, key, buffer_bytes);
+    let mut width = header_fields.as_ref().map(Vec::len);
+    for (i, line) in lines.enumerate() {
+        if i % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row_key = key.key_of(line).ok_or_else(|| {
+            FunctionError::invalid_input_data(
+                input,
+                format!("row {} has no key column {}", sorter.rows + 1, key.index),
+            )
+        })?;
+        width.get_or_insert_with(|| split_fields(line, delimiter).len());
+        sorter.push(row_key, line.to_string())?;
+    }
+
+    // Without a projection, the left input keeps all of its columns and the right input all
+    // but its key, which the left one already has.
+    let columns = columns.unwrap_or_else(|| {
+        (0..width.unwrap_or_default())
+            .filter(|&column| input == IN_LEFT || column != key.index)
+            .collect()
+    });
+    let (num_rows, runs) = (sorter.rows, sorter.runs);
+    Ok(Side {
+        key,
+        header,
+        columns,
+        rows: sorter.finish()?,
+        num_rows,
+        runs,
+    })
+}
+
+/// External sort of the rows of an input by key. Rows are buffered until they take more than
+/// `buffer_bytes`; the buffer is then sorted and spilled to a scratch file as one run. The
+/// sort is stable, so rows with equal keys stay in input order.
+struct RunSorter<'a> {
+    runtime: &'a dyn TeaclaveRuntime,
+    input: &'static str,
+    key: KeyColumn,
+    buffer_bytes: u64,
+    buffer: Vec<(String, String)>,
+    buffered_bytes: u64,
+    rows: u64,
+    runs: usize,
+}
+
+impl<'a> RunSorter<'a> {
+    fn new(
+        runtime: &'a dyn TeaclaveRuntime,
+        input: &'static str,
+        key: KeyColumn,
+        buffer_bytes: u64,
+    ) -> Self {
+        Self {
+            runtime,
+            input,
+            key,
+            buffer_bytes,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            rows: 0,
+            runs: 0,
+        }
+    }
+
+    fn push(&mut self, key: String, line: String) -> Result<(), FunctionError> {
+        self.buffered_bytes += (key.len() + line.len()) as u64 + ROW_OVERHEAD_BYTES;
+        self.buffer.push((key, line));
+        self.rows += 1;
+        if self.buffered_bytes > self.buffer_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<(), FunctionError> {
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut run = self
+            .runtime
+            .create_scratch(&run_name(self.input, self.runs))?;
+        for (_, line) in self.buffer.drain(..) {
+            run.write_all(line.as_bytes())?;
+            run.write_all(b"\n")?;
+        }
+        run.flush()?;
+        self.buffered_bytes = 0;
+        self.runs += 1;
+        Ok(())
+    }
+
+    /// The sorted rows: the buffer itself if nothing was spilled, and the merge of the runs
+    /// otherwise.
+    fn finish(mut self) -> Result<SortedRows, FunctionError> {
+        if self.runs == 0 {
+            self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+            return Ok(SortedRows::Memory(self.buffer.into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut runs = Vec::with_capacity(self.runs);
+        let mut heap = BinaryHeap::with_capacity(self.runs);
+        for index in 0..self.runs {
+            let scratch = self.runtime.open_scratch(&run_name(self.input, index))?;
+            let mut run = BufReader::new(scratch).lines();
+            if let Some(line) = run.next().transpose()? {
+                heap.push(Reverse((
+                    self.key.key_of(&line).unwrap_or_default(),
+                    index,
+                    line,
+                )));
+            }
+            runs.push(run);
+        }
+        Ok(SortedRows::Merged {
+            key: self.key,
+            runs,
+            heap,
+        })
+    }
+}
+
+fn run_name(input: &str, index: usize) -> String {
+    format!("{}-run-{}", input, index)
+}
+
+/// Rows of an input sorted by key, as pairs of key and line.
+enum SortedRows {
+    Memory(std::vec::IntoIter<(String, String)>),
+    /// K-way merge of sorted runs. The heap holds the next row of every run which has one;
+    /// ties between runs go to the earlier run, which keeps equal keys in input order.
+    Merged {
+        key: KeyColumn,
+        runs: Vec<Lines<BufReader<Box<dyn Read>>>>,
+        heap: BinaryHeap<Reverse<(String, usize, String)>>,
+    },
+}
+
+impl SortedRows {
+    fn next_row(&mut self) -> Result<Option<(String, String)>, FunctionError> {
+        match self {
+            SortedRows::Memory(rows) => Ok(rows.next()),
+            SortedRows::Merged { key, runs, heap } => {
+                let Reverse((row_key, index, line)) = match heap.pop() {
+                    Some(next) => next,
+                    None => return Ok(None),
+                };
+                if let Some(next) = runs[index].next().transpose()? {
+                    heap.push(Reverse((
+                        key.key_of(&next).unwrap_or_default(),
+                        index,
+                        next,
+                    )));
+                }
+                Ok(Some((row_key, line)))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_csv_join_inner,
+            test_csv_join_left,
+            test_csv_join_projection,
+            test_csv_join_duplicate_keys,
+            test_csv_join_max_multiplicity,
+            test_csv_join_one_header,
+            test_csv_join_quoting,
+            test_csv_join_progress,
+            test_csv_join_external_sort,
+            test_csv_join_arguments,
+        )
+    }
+
+    fn csv_join_in(
+        environment: ExecutionEnvironment,
+        arguments: serde_json::Value,
+        left: &str,
+        right: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            CsvJoin::new(),
+            arguments,
+            hashmap!(IN_LEFT => left, IN_RIGHT => right),
+            [OUT_RESULT],
+            with_environment(environment)
+        );
+        let result = String::from_utf8(outputs.remove(OUT_RESULT).unwrap()).unwrap();
+        Ok((summary?, result))
+    }
+
+    fn csv_join(
+        arguments: serde_json::Value,
+        left: &str,
+        right: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        csv_join_in(ExecutionEnvironment::new(), arguments, left, right)
+    }
+
+    const PEOPLE: &str = "id,name,age\n3,carol,41\n1,alice,30\n2,bob,25\n4,dave,52\n";
+    const CITIES: &str = "id,city,zip\n2,Paris,75001\n1,Rome,100\n5,Oslo,150\n";
+
+    fn test_csv_join_inner() {
+        let arguments = json!({"left_key": "id", "right_key": "id"});
+        let (summary, result) = csv_join(arguments, PEOPLE, CITIES).unwrap();
+        assert_eq!(
+            result,
+            "id,name,age,city,zip\n1,alice,30,Rome,100\n2,bob,25,Paris,75001\n"
+        );
+        assert_eq!(summary.message, "2 matched rows, 2 unmatched rows");
+        assert_eq!(summary.metrics["matched_rows"], 2.0);
+        assert_eq!(summary.metrics["unmatched_rows"], 2.0);
+        assert_eq!(summary.metrics["unmatched_right_rows"], 1.0);
+        assert_eq!(summary.metrics["left_rows"], 4.0);
+        assert_eq!(summary.metrics["right_rows"], 3.0);
+        assert_eq!(summary.metrics["spilled_runs"], 0.0);
+        assert_eq!(summary.outputs[OUT_RESULT].size, result.len() as u64);
+    }
+
+    fn test_csv_join_left() {
+        let arguments = json!({"left_key": "id", "right_key": "id", "join_type": "left"});
+        let (summary, result) = csv_join(arguments, PEOPLE, CITIES).unwrap();
+        // Unmatched left rows are kept, with empty right columns.
+        assert_eq!(
+            result,
+            "id,name,age,city,zip\n\
+             1,alice,30,Rome,100\n\
+             2,bob,25,Paris,75001\n\
+             3,carol,41,,\n\
+             4,dave,52,,\n"
+        );
+        assert_eq!(summary.metrics["matched_rows"], 2.0);
+        assert_eq!(summary.metrics["unmatched_rows"], 2.0);
+    }
+
+    fn test_csv_join_projection() {
+        let arguments = json!({
+            "left_key": "id",
+            "right_key": "id",
+            "join_type": "left",
+            "columns_left": ["name"],
+            "columns_right": ["zip", "city"],
+        });
+        let (_, result) = csv_join(arguments, PEOPLE, CITIES).unwrap();
+        assert_eq!(
+            result,
+            "name,zip,city\nalice,100,Rome\nbob,75001,Paris\ncarol,,\ndave,,\n"
+        );
+
+        // Without headers, keys and columns are given by index.
+        let left = "x;1\ny;2\n";
+        let right = "2,b\n1,a\n";
+        let arguments = json!({"left_key": "1", "right_key": "0", "columns_left": ["0"]});
+        let (_, result) = csv_join(arguments, left, right).unwrap();
+        assert_eq!(result, "x;a\ny;b\n");
+    }
+
+    fn test_csv_join_duplicate_keys() {
+        let left = "k,v\na,1\nb,2\na,3\n";
+        let right = "k,w\na,10\nc,20\na,30\na,40\n";
+        let arguments = json!({"left_key": "k", "right_key": "k", "join_type": "left"});
+        let (summary, result) = csv_join(arguments, left, right).unwrap();
+        // Each left row of a key is joined with each right row of the key, in input order.
+        assert_eq!(
+            result,
+            "k,v,w\na,1,10\na,1,30\na,1,40\na,3,10\na,3,30\na,3,40\nb,2,\n"
+        );
+        assert_eq!(summary.metrics["matched_rows"], 6.0);
+        assert_eq!(summary.metrics["unmatched_rows"], 1.0);
+        assert_eq!(summary.metrics["unmatched_right_rows"], 1.0);
+    }
+
+    fn test_csv_join_max_multiplicity() {
+        let left = "k,v\na,1\nb,2\na,3\n";
+        let right = "k,w\na,10\na,30\na,40\n";
+        let arguments = json!({"left_key": "k", "right_key": "k", "max_multiplicity": 3});
+        assert!(csv_join(arguments, left, right).is_ok());
+
+        let arguments = json!({"left_key": "k", "right_key": "k", "max_multiplicity": 2});
+        let err = csv_join(arguments, left, right).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input right: key 'a' is shared by more than max_multiplicity = 2 rows"
+        );
+
+        let arguments = json!({"left_key": "k", "right_key": "k", "max_multiplicity": 1});
+        let err = csv_join(arguments.clone(), left, "k,w\na,10\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input left: key 'a' is shared by more than max_multiplicity = 1 rows"
+        );
+
+        // Left rows without a right match are no cross product, however many share a key.
+        let mut arguments = arguments;
+        arguments["join_type"] = json!("left");
+        let (summary, _) = csv_join(arguments, left, "k,w\nb,20\n").unwrap();
+        assert_eq!(summary.metrics["unmatched_rows"], 2.0);
+    }
+
+    fn test_csv_join_one_header() {
+        // The input without a header names its columns by index.
+        let arguments = json!({"left_key": "id", "right_key": "0"});
+        let (_, result) = csv_join(arguments, PEOPLE, "2;Paris;2.5\n1;Rome;3.5\n").unwrap();
+        assert_eq!(
+            result,
+            "id,name,age,1,2\n1,alice,30,Rome,3.5\n2,bob,25,Paris,2.5\n"
+        );
+
+        let arguments = json!({"left_key": "0", "right_key": "id"});
+        let (_, result) = csv_join(arguments, "2;8.5\n1;9.5\n", CITIES).unwrap();
+        assert_eq!(result, "0;1;city;zip\n1;9.5;Rome;100\n2;8.5;Paris;75001\n");
+    }
+
+    fn test_csv_join_quoting() {
+        // Fields of the right input holding the delimiter of the left one are quoted, fields
+        // quoted already are kept.
+        let left = "id,name\n1,alice\n2,bob\n";
+        let right = "id;city;note\n1;Rome, Italy;\"x;y\"\n2;Paris;a \"b\", c\n";
+        let arguments = json!({"left_key": "id", "right_key": "id"});
+        let (_, result) = csv_join(arguments, left, right).unwrap();
+        assert_eq!(
+            result,
+            "id,name,city,note\n\
+             1,alice,\"Rome, Italy\",\"x;y\"\n\
+             2,bob,Paris,\"a \"\"b\"\", c\"\n"
+        );
+    }
+
+    fn test_csv_join_progress() {
+        let progress = ExecutionProgress::new();
+        let runtime = RawIoRuntime::new(
+            StagedFiles::from_memory(hashmap!(
+                IN_LEFT => PEOPLE.as_bytes().to_vec(),
+                IN_RIGHT => CITIES.as_bytes().to_vec()
+            )),
+            StagedFiles::from_memory(hashmap!(OUT_RESULT => Vec::new())),
+        )
+        .with_progress(progress.clone());
+        let arguments =
+            FunctionArguments::from_json(json!({"left_key": "id", "right_key": "id"})).unwrap();
+        CsvJoin::new().run(arguments, Box::new(runtime)).unwrap();
+        assert_eq!(
+            progress.latest(),
+            Some(TaskProgress::new(1.0, "joined the inputs"))
+        );
+    }
+
+    fn test_csv_join_external_sort() {
+        let mut left = String::from("id,value\n");
+        let mut right = String::from("id,score\n");
+        for i in (0..200).rev() {
+            left.push_str(&format!("k{:03},{}\n", i, i));
+            if i % 3 != 0 {
+                right.push_str(&format!("k{:03},{}\n", (i * 7) % 200, i));
+            }
+        }
+        let arguments = json!({"left_key": "id", "right_key": "id", "join_type": "left"});
+        let (in_memory, expected) = csv_join(arguments.clone(), &left, &right).unwrap();
+        assert_eq!(in_memory.metrics["spilled_runs"], 0.0);
+
+        // A budget of a few rows makes both inputs spill many runs, with the same result.
+        let environment = ExecutionEnvironment::new().memory_budget_bytes(Some(2048));
+        let (spilled, result) = csv_join_in(environment, arguments, &left, &right).unwrap();
+        assert!(spilled.metrics["spilled_runs"] > 10.0);
+        assert_eq!(result, expected);
+        assert_eq!(
+            spilled.metrics["matched_rows"],
+            in_memory.metrics["matched_rows"]
+        );
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!(lines.len(), 201);
+        assert!(lines[1..].windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    fn test_csv_join_arguments() {
+        let err = csv_join(
+            json!({"left_key": "id", "right_key": "zip_code"}),
+            PEOPLE,
+            CITIES,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: column 'zip_code' is not a column of input right"
+        );
+        let arguments = json!({"left_key": "id", "right_key": "id", "join_type": "outer"});
+        assert!(csv_join(arguments, PEOPLE, CITIES).is_err());
+        let arguments = json!({"left_key": "id", "right_key": "id", "max_multiplicity": 0});
+        assert!(csv_join(arguments, PEOPLE, CITIES).is_err());
+        assert!(csv_join(json!({"left_key": "id"}), PEOPLE, CITIES).is_err());
+    }
+}
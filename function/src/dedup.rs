@@ -0,0 +1,310 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::line_transform::{LinePipeline, LineTransform};
+use anyhow::bail;
+use ring::digest;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{BufReader, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo,
+};
+
+// Only a 128-bit digest of each seen line is kept in "first" mode.
+const SEEN_DIGEST_LENGTH: usize = 16;
+
+#[derive(Default)]
+pub struct Dedup;
+
+#[derive(serde::Deserialize)]
+struct DedupArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    /// "first" keeps the first occurrence of each line in input order; "sorted"
+    /// expects sorted input and only compares adjacent lines.
+    #[serde(default = "default_order")]
+    order: String,
+    #[serde(default)]
+    ignore_case: bool,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+fn default_order() -> String {
+    "first".to_string()
+}
+
+impl TryFrom<FunctionArguments> for DedupArguments {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        use anyhow::Context;
+        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    }
+}
+
+impl Dedup {
+    pub const NAME: &'static str = "builtin-dedup";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("input", ArgumentType::String)
+                    .description("Identifier of the input, \"input\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("output", ArgumentType::String)
+                    .description("Identifier of the output, \"output\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("order", ArgumentType::String)
+                    .one_of(&["first", "sorted"])
+                    .description(
+                        "\"first\" keeps first occurrences, \"sorted\" expects sorted input",
+                    ),
+            )
+            .argument(ArgumentSpec::new("ignore_case", ArgumentType::Boolean))
+    }
+
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let args = DedupArguments::try_from(arguments)?;
+        let sorted = match args.order.as_str() {
+            "first" => false,
+            "sorted" => true,
+            _ => bail!("Invalid order"),
+        };
+
+        let cancellation = runtime.cancellation();
+        let mut input = BufReader::new(runtime.open_input(&args.input)?);
+        let mut output = runtime.create_output(&args.output)?;
+
+        let transform = DedupTransform::new(sorted, args.ignore_case);
+        let stats =
+            LinePipeline::new()
+                .stage(&transform)
+                .run(&mut input, &mut output, &cancellation)?;
+
+        let summary = FunctionSummary::new(format!(
+            "{} unique lines, {} duplicate lines",
+            transform.unique(),
+            transform.duplicates()
+        ))
+        .metric("bytes_read", stats.bytes_read as f64)
+        .metric("unique_lines", transform.unique() as f64)
+        .metric("duplicate_lines", transform.duplicates() as f64)
+        .output(args.output, OutputInfo::new(stats.bytes_written as u64));
+        Ok(summary)
+    }
+}
+
+/// Line transform behind `Dedup`, for use as a stage of a `LinePipeline`.
+pub struct DedupTransform {
+    sorted: bool,
+    ignore_case: bool,
+    seen: RefCell<HashSet<[u8; SEEN_DIGEST_LENGTH]>>,
+    previous: RefCell<Option<Vec<u8>>>,
+    unique: Cell<usize>,
+    duplicates: Cell<usize>,
+}
+
+impl DedupTransform {
+    pub fn new(sorted: bool, ignore_case: bool) -> Self {
+        Self {
+            sorted,
+            ignore_case,
+            seen: RefCell::new(HashSet::new()),
+            previous: RefCell::new(None),
+            unique: Cell::new(0),
+            duplicates: Cell::new(0),
+        }
+    }
+
+    pub fn unique(&self) -> usize {
+        self.unique.get()
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.duplicates.get()
+    }
+}
+
+impl LineTransform for DedupTransform {
+    fn transform(&self, line: &[u8], out: &mut dyn Write) -> Result<(), FunctionError> {
+        let key = if self.ignore_case {
+            String::from_utf8_lossy(line).to_lowercase().into_bytes()
+        } else {
+            line.to_vec()
+        };
+
+        let is_new = if self.sorted {
+            let mut previous = self.previous.borrow_mut();
+            let is_new = previous.as_ref() != Some(&key);
+            *previous = Some(key);
+            is_new
+        } else {
+            self.seen.borrow_mut().insert(line_digest(&key))
+        };
+
+        if is_new {
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+            self.unique.set(self.unique.get() + 1);
+        } else {
+            self.duplicates.set(self.duplicates.get() + 1);
+        }
+        Ok(())
+    }
+}
+
+fn line_digest(line: &[u8]) -> [u8; SEEN_DIGEST_LENGTH] {
+    let mut d = [0u8; SEEN_DIGEST_LENGTH];
+    d.copy_from_slice(&digest::digest(&digest::SHA256, line).as_ref()[..SEEN_DIGEST_LENGTH]);
+    d
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_dedup_first,
+            test_dedup_first_ignore_case,
+            test_dedup_sorted,
+            test_dedup_sorted_ignore_case,
+            test_dedup_cancelled,
+            test_dedup_output_limit,
+        )
+    }
+
+    const INPUT: &str = "banana\napple\nBanana\ncherry\napple\nbanana";
+    const SORTED: &str = "apple\napple\nBanana\nbanana\ncherry\ncherry";
+
+    fn run_dedup(arguments: serde_json::Value, input: &str) -> (FunctionSummary, String) {
+        let (summary, output) =
+            run_dedup_with_limits(arguments, input, ExecutionLimits::unlimited());
+        (summary.unwrap(), output)
+    }
+
+    fn run_dedup_with_limits(
+        arguments: serde_json::Value,
+        input: &str,
+        limits: ExecutionLimits,
+    ) -> (anyhow::Result<FunctionSummary>, String) {
+        let (summary, mut outputs) = run_builtin!(
+            Dedup::new(),
+            arguments,
+            hashmap!("input" => input),
+            ["output"],
+            with_limits(limits)
+        );
+        let output = outputs.remove("output").unwrap_or_default();
+        (summary, String::from_utf8(output).unwrap())
+    }
+
+    fn test_dedup_first() {
+        let (summary, output) = run_dedup(json!({"order": "first"}), INPUT);
+        assert_eq!(summary.message, "4 unique lines, 2 duplicate lines");
+        assert_eq!(output, "banana\napple\nBanana\ncherry\n");
+        assert_eq!(summary.metrics["unique_lines"], 4.0);
+        assert_eq!(summary.metrics["duplicate_lines"], 2.0);
+        // The missing trailing newline of the last input line is counted as if present.
+        assert_eq!(summary.metrics["bytes_read"], 40.0);
+        assert_eq!(
+            summary.outputs["output"],
+            OutputInfo::new(output.len() as u64)
+        );
+    }
+
+    fn test_dedup_first_ignore_case() {
+        let (summary, output) = run_dedup(json!({"order": "first", "ignore_case": true}), INPUT);
+        assert_eq!(summary.message, "3 unique lines, 3 duplicate lines");
+        assert_eq!(output, "banana\napple\ncherry\n");
+    }
+
+    fn test_dedup_sorted() {
+        let (summary, output) = run_dedup(json!({"order": "sorted"}), SORTED);
+        assert_eq!(summary.message, "4 unique lines, 2 duplicate lines");
+        assert_eq!(output, "apple\nBanana\nbanana\ncherry\n");
+    }
+
+    fn test_dedup_sorted_ignore_case() {
+        let (summary, output) = run_dedup(json!({"order": "sorted", "ignore_case": true}), SORTED);
+        assert_eq!(summary.message, "3 unique lines, 3 duplicate lines");
+        assert_eq!(output, "apple\nBanana\ncherry\n");
+    }
+
+    fn test_dedup_cancelled() {
+        let arguments = FunctionArguments::from_json(json!({"order": "first"})).unwrap();
+        let input_files = StagedFiles::from_memory(hashmap!("input" => INPUT.as_bytes()));
+        let output_files = StagedFiles::from_memory(hashmap!("output" => Vec::<u8>::new()));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let runtime =
+            Box::new(RawIoRuntime::new(input_files, output_files).with_cancellation(token));
+        let err = Dedup::new().run(arguments, runtime).unwrap_err();
+        assert!(err.downcast_ref::<Cancelled>().is_some());
+    }
+
+    fn test_dedup_output_limit() {
+        let arguments = json!({"order": "first"});
+        let limits = ExecutionLimits::unlimited().max_single_output_bytes(10);
+        let (result, output) = run_dedup_with_limits(arguments.clone(), INPUT, limits);
+        let err = result.unwrap_err();
+        assert_eq!(
+            ResourceLimitExceeded::find(&err),
+            Some(&ResourceLimitExceeded::SingleOutputBytes(
+                "output".to_string(),
+                10
+            ))
+        );
+        // "banana\n" fits, "apple\n" would cross the limit and is not written.
+        assert_eq!(output, "banana\n");
+
+        let limits = ExecutionLimits::unlimited()
+            .max_single_output_bytes(27)
+            .max_total_output_bytes(27);
+        let (result, output) = run_dedup_with_limits(arguments, INPUT, limits);
+        assert_eq!(result.unwrap().message, "4 unique lines, 2 duplicate lines");
+        assert_eq!(output, "banana\napple\nBanana\ncherry\n");
+    }
+}
@@ -15,24 +15,50 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::convert::TryFrom;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_crypto::SealedOutput;
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary,
+};
 
 #[derive(Default)]
 pub struct Echo;
 
+/// Largest accepted `fixed_len`. The padded message is held in memory, and at up to four
+/// bytes per character this keeps it within 4 MiB.
+const MAX_FIXED_LEN: usize = 1 << 20;
+
 #[derive(serde::Deserialize)]
 struct EchoArguments {
     message: String,
+    /// Makes the function fail with `fail_message`, so that tests can exercise error handling.
+    #[serde(default)]
+    fail: bool,
+    #[serde(default = "default_fail_message")]
+    fail_message: String,
+    /// Pads the message with `pad` or truncates it to exactly this many characters, e.g. to
+    /// test fixed-size records.
+    #[serde(default)]
+    fixed_len: Option<usize>,
+    #[serde(default = "default_pad")]
+    pad: char,
 }
 
-impl TryFrom<FunctionArguments> for EchoArguments {
-    type Error = anyhow::Error;
+fn default_fail_message() -> String {
+    "Echo failed as requested".to_string()
+}
 
-    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
-        use anyhow::Context;
-        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
-    }
+fn default_pad() -> char {
+    ' '
+}
+
+/// Pads `message` with `pad` or truncates it to `len` Unicode scalar values. Truncation happens
+/// at character boundaries, so multi-byte characters are never split.
+fn fit_to_len(message: &str, len: usize, pad: char) -> String {
+    let mut fitted: String = message.chars().take(len).collect();
+    let missing = len - fitted.chars().count();
+    fitted.extend(std::iter::repeat(pad).take(missing));
+    fitted
 }
 
 impl Echo {
@@ -42,32 +68,88 @@ impl Echo {
         Default::default()
     }
 
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("message", ArgumentType::String)
+                    .required()
+                    .description("Message to return as the result"),
+            )
+            .argument(
+                ArgumentSpec::new("fail", ArgumentType::Boolean)
+                    .description("Fail with fail_message instead, to test error handling"),
+            )
+            .argument(ArgumentSpec::new("fail_message", ArgumentType::String))
+            .argument(
+                ArgumentSpec::new("fixed_len", ArgumentType::Integer)
+                    .minimum(0.0)
+                    .maximum(MAX_FIXED_LEN as f64)
+                    .description("Pad or truncate the message to this many characters"),
+            )
+            .argument(
+                ArgumentSpec::new("pad", ArgumentType::String)
+                    .description("Character to pad the message with, \" \" by default"),
+            )
+    }
+
     pub fn run(
         &self,
         arguments: FunctionArguments,
-        _runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
-        let message = EchoArguments::try_from(arguments)?.message;
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: EchoArguments = arguments.into_typed()?;
+        if args.fail {
+            return Err(FunctionError::Internal(anyhow::anyhow!(args.fail_message)));
+        }
+        let message = match args.fixed_len {
+            Some(len) if len > MAX_FIXED_LEN => {
+                return Err(FunctionError::invalid_arguments(format!(
+                    "fixed_len must be at most {}",
+                    MAX_FIXED_LEN
+                )));
+            }
+            Some(len) => fit_to_len(&args.message, len, args.pad),
+            None => args.message,
+        };
 
         #[cfg(test_mode)]
         log::info!("{}", message);
         #[cfg(test_mode)]
         log::debug!("{}", message);
 
-        Ok(message)
+        let message_bytes = message.len() as f64;
+        let message = match runtime.output_recipient_key() {
+            Some(key) => {
+                let sealed = SealedOutput::seal(key, message.as_bytes())?;
+                serde_json::to_string(&sealed).map_err(anyhow::Error::from)?
+            }
+            None => message,
+        };
+
+        Ok(FunctionSummary::new(message).metric("message_bytes", message_bytes))
     }
 }
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use crate::registry;
+    use ring::agreement;
     use serde_json::json;
+    use std::collections::HashMap;
     use teaclave_runtime::*;
     use teaclave_test_utils::*;
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_echo)
+        run_tests!(
+            test_echo,
+            test_echo_summary_json,
+            test_echo_sealed,
+            test_echo_fail,
+            test_echo_invalid_arguments,
+            test_echo_fixed_len,
+        )
     }
 
     fn test_echo() {
@@ -76,13 +158,158 @@ pub mod tests {
         }))
         .unwrap();
 
-        let input_files = StagedFiles::default();
-        let output_files = StagedFiles::default();
+        let input_files = StagedFiles::from_memory(HashMap::new());
+        let output_files = StagedFiles::from_memory(HashMap::new());
 
         let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
         let function = Echo;
 
         let summary = function.run(args, runtime).unwrap();
-        assert_eq!(summary, "Hello Teaclave!");
+        assert_eq!(summary.message, "Hello Teaclave!");
+        assert_eq!(summary.metrics["message_bytes"], 15.0);
+    }
+
+    fn test_echo_summary_json() {
+        let args = FunctionArguments::from_json(json!({
+            "message": "Hello Teaclave!"
+        }))
+        .unwrap();
+
+        let input_files = StagedFiles::default();
+        let output_files = StagedFiles::default();
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+
+        let summary = Echo.run(args, runtime).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&summary.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "message": "Hello Teaclave!",
+                "metrics": {"message_bytes": 15.0},
+                "outputs": {},
+                "warnings": [],
+            })
+        );
+    }
+
+    fn test_echo_sealed() {
+        let args = FunctionArguments::from_json(json!({
+            "message": "Hello Teaclave!"
+        }))
+        .unwrap();
+
+        let client_key =
+            teaclave_crypto::RecipientPrivateKey::generate(&agreement::ECDH_P256).unwrap();
+        let client_public_key = client_key.public_key().unwrap();
+
+        let input_files = StagedFiles::default();
+        let output_files = StagedFiles::default();
+        let runtime = Box::new(
+            RawIoRuntime::new(input_files, output_files)
+                .with_output_recipient_key(client_public_key),
+        );
+
+        let summary = Echo.run(args, runtime).unwrap();
+        assert!(!summary.message.contains("Hello Teaclave!"));
+        let sealed: teaclave_crypto::SealedOutput = serde_json::from_str(&summary.message).unwrap();
+        assert_eq!(sealed.open(&client_key).unwrap(), b"Hello Teaclave!");
+    }
+
+    fn test_echo_fail() {
+        let args = FunctionArguments::from_json(json!({
+            "message": "Hello Teaclave!",
+            "fail": true,
+            "fail_message": "injected failure",
+        }))
+        .unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        // The message of an internal error is only kept as its source.
+        let error = registry()
+            .run(Echo::NAME, &ExecutionPolicy::default(), args, runtime)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Internal error");
+        assert_eq!(format!("{:#}", error), "Internal error: injected failure");
+
+        let args = FunctionArguments::from_json(json!({
+            "message": "Hello Teaclave!",
+            "fail": true,
+        }))
+        .unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let error = Echo.run(args, runtime).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::Internal);
+        assert_eq!(
+            format!("{:#}", anyhow::Error::from(error)),
+            "Internal error: Echo failed as requested"
+        );
+    }
+
+    fn test_echo_invalid_arguments() {
+        let args = FunctionArguments::from_json(json!({"msg": "Hello Teaclave!"})).unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let error = Echo.run(args, runtime).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
+        assert_eq!(
+            error.to_string(),
+            "Invalid arguments: arguments: missing field `message`"
+        );
+    }
+
+    fn test_echo_fixed_len() {
+        let echo = |arguments: serde_json::Value| {
+            let args = FunctionArguments::from_json(arguments).unwrap();
+            let runtime = Box::new(RawIoRuntime::new(
+                StagedFiles::default(),
+                StagedFiles::default(),
+            ));
+            Echo.run(args, runtime).unwrap()
+        };
+
+        let summary = echo(json!({"message": "Hello", "fixed_len": 8}));
+        assert_eq!(summary.message, "Hello   ");
+        assert_eq!(summary.metrics["message_bytes"], 8.0);
+        let summary = echo(json!({"message": "Hello", "fixed_len": 8, "pad": "."}));
+        assert_eq!(summary.message, "Hello...");
+        assert_eq!(
+            echo(json!({"message": "Hello", "fixed_len": 3})).message,
+            "Hel"
+        );
+        assert_eq!(
+            echo(json!({"message": "Hello", "fixed_len": 0})).message,
+            ""
+        );
+
+        // Lengths count characters, not bytes: "é" takes two bytes and "€" three.
+        let summary = echo(json!({"message": "héllo €uro", "fixed_len": 7}));
+        assert_eq!(summary.message, "héllo €");
+        assert_eq!(summary.metrics["message_bytes"], 10.0);
+        let summary = echo(json!({"message": "é", "fixed_len": 3, "pad": "€"}));
+        assert_eq!(summary.message, "é€€");
+
+        assert_eq!(
+            echo(json!({"message": "Hello"})).message,
+            "Hello",
+            "unchanged without fixed_len"
+        );
+
+        let args = FunctionArguments::from_json(
+            json!({"message": "Hello", "fixed_len": MAX_FIXED_LEN + 1}),
+        )
+        .unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let error = Echo.run(args, runtime).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
     }
 }
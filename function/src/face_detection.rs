@@ -21,11 +21,20 @@ extern crate image;
 extern crate rustface;
 
 use std::convert::TryFrom;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use std::sync::Arc;
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
+
+use crate::PreparedFunction;
 
 #[derive(Default)]
 pub struct FaceDetection;
 
+/// Face detection with the default model, which is parsed once when the builtin is prepared
+/// and copied for every execution.
+struct PreparedFaceDetection {
+    model: rustface::Model,
+}
+
 #[derive(serde::Deserialize)]
 struct FaceDetectionArguments {
     image: Vec<u8>,
@@ -100,42 +109,68 @@ impl FaceDetection {
         Default::default()
     }
 
+    /// Parses the default model for the executions of a registry to share. The builtin has no
+    /// static arguments, so a registry prepares it once.
+    pub fn prepare(_config: &FunctionArguments) -> anyhow::Result<Arc<dyn PreparedFunction>> {
+        let model = rustface::model::default_model()?;
+        Ok(Arc::new(PreparedFaceDetection { model }))
+    }
+
     pub fn run(
         &self,
         arguments: FunctionArguments,
-        _runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
-        let arguments = FaceDetectionArguments::try_from(arguments)?;
-        let image = arguments.image;
-        let img = image::load_from_memory(&image)?;
-
-        let mut detector = rustface::create_default_detector()?;
-        if let Some(window_size) = arguments.window_size {
-            detector.set_window_size(window_size);
-        }
-        if let (Some(step_x), Some(step_y)) =
-            (arguments.slide_window_step_x, arguments.slide_window_step_y)
-        {
-            detector.set_slide_window_step(step_x, step_y);
-        }
-        if let Some(min_face_size) = arguments.min_face_size {
-            detector.set_min_face_size(min_face_size);
-        }
-        if let Some(max_face_size) = arguments.max_face_size {
-            detector.set_max_face_size(max_face_size);
-        }
-        if let Some(pyramid_scale_factor) = arguments.pyramid_scale_factor {
-            detector.set_pyramid_scale_factor(pyramid_scale_factor);
-        }
-        if let Some(score_thresh) = arguments.score_thresh {
-            detector.set_score_thresh(score_thresh);
-        }
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        detect(rustface::model::default_model()?, arguments, runtime)
+    }
+}
 
-        let faces = rustface::detect_faces(&mut *detector, img);
-        let result = serde_json::to_string(&faces)?;
+impl PreparedFunction for PreparedFaceDetection {
+    fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        detect(self.model.clone(), arguments, runtime)
+    }
+}
 
-        Ok(result)
+fn detect(
+    model: rustface::Model,
+    arguments: FunctionArguments,
+    runtime: FunctionRuntime,
+) -> anyhow::Result<FunctionSummary> {
+    // The image may be passed as a staged input with {"image": {"$file": "<input_key>"}}.
+    let arguments = FaceDetectionArguments::try_from(arguments.resolve_files(&*runtime)?)?;
+    let image = arguments.image;
+    let img = image::load_from_memory(&image)?;
+
+    let mut detector = rustface::create_detector_with_model(model);
+    if let Some(window_size) = arguments.window_size {
+        detector.set_window_size(window_size);
+    }
+    if let (Some(step_x), Some(step_y)) =
+        (arguments.slide_window_step_x, arguments.slide_window_step_y)
+    {
+        detector.set_slide_window_step(step_x, step_y);
+    }
+    if let Some(min_face_size) = arguments.min_face_size {
+        detector.set_min_face_size(min_face_size);
+    }
+    if let Some(max_face_size) = arguments.max_face_size {
+        detector.set_max_face_size(max_face_size);
     }
+    if let Some(pyramid_scale_factor) = arguments.pyramid_scale_factor {
+        detector.set_pyramid_scale_factor(pyramid_scale_factor);
+    }
+    if let Some(score_thresh) = arguments.score_thresh {
+        detector.set_score_thresh(score_thresh);
+    }
+
+    let faces = rustface::detect_faces(&mut *detector, img);
+    let result = serde_json::to_string(&faces)?;
+
+    Ok(result.into())
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -168,8 +203,29 @@ pub mod tests {
         let output_files = StagedFiles::new(hashmap!());
         let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
 
-        let result = FaceDetection::new().run(arguments, runtime).unwrap();
-        let json_result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        let result = FaceDetection::new()
+            .run(arguments.clone(), runtime)
+            .unwrap();
+        let json_result: serde_json::Value = serde_json::from_str(&result.message).unwrap();
         assert_eq!(json_result.as_array().unwrap().len(), 29);
+
+        // Executions through the registry share the model prepared for the first of them.
+        let registry = crate::registry();
+        for _ in 0..2 {
+            let runtime = Box::new(RawIoRuntime::new(
+                StagedFiles::default(),
+                StagedFiles::default(),
+            ));
+            let result = registry
+                .run(
+                    FaceDetection::NAME,
+                    &ExecutionPolicy::default(),
+                    arguments.clone(),
+                    runtime,
+                )
+                .unwrap();
+            let faces: serde_json::Value = serde_json::from_str(&result.message).unwrap();
+            assert_eq!(faces, json_result);
+        }
     }
 }
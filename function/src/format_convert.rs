@@ -0,0 +1,671 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{check_format, open_sniffed, DetectedFormat, ExpectedFormat};
+use serde_json::{Map, Number, Value};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{self, BufRead, BufReader, Write};
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+    CANCELLATION_CHECK_INTERVAL,
+};
+
+#[derive(Default)]
+pub struct FormatConvert;
+
+#[derive(serde::Deserialize)]
+struct FormatConvertArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    /// "csv_to_jsonl" or "jsonl_to_csv". May be left out if `auto_detect` is set.
+    direction: Option<String>,
+    #[serde(default = "default_delimiter")]
+    delimiter: String,
+    /// Names of the CSV columns in order. Required for "jsonl_to_csv". For "csv_to_jsonl" the
+    /// first row is the header unless the columns are given.
+    columns: Option<Vec<String>>,
+    /// Turn CSV fields which look like numbers or booleans into JSON numbers and booleans, and
+    /// empty fields into null. Otherwise every field becomes a string.
+    #[serde(default)]
+    type_inference: bool,
+    /// Number of malformed records which are skipped before the conversion fails.
+    #[serde(default)]
+    max_bad_records: usize,
+    /// Take the direction, and for CSV inputs the delimiter, from the detected format of the
+    /// input instead of the arguments. Inputs whose format is not recognized fall back to the
+    /// arguments.
+    #[serde(default)]
+    auto_detect: bool,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+fn default_delimiter() -> String {
+    ",".to_string()
+}
+
+impl TryFrom<FunctionArguments> for FormatConvertArguments {
+    type Error = FunctionError;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        serde_json::from_str(&arguments.into_string()).map_err(FunctionError::invalid_arguments)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    CsvToJsonl,
+    JsonlToCsv,
+}
+
+impl Direction {
+    fn input_format(self) -> ExpectedFormat {
+        match self {
+            Direction::CsvToJsonl => ExpectedFormat::Csv,
+            Direction::JsonlToCsv => ExpectedFormat::Jsonl,
+        }
+    }
+}
+
+/// Counts of converted and skipped records. Skipping fails once more than `max_bad_records`
+/// records of `input` were bad.
+struct Progress {
+    input: String,
+    records: usize,
+    bad_records: usize,
+    max_bad_records: usize,
+    bytes_written: usize,
+    warnings: Vec<String>,
+}
+
+impl Progress {
+    fn new(input: &str, max_bad_records: usize) -> Self {
+        Self {
+            input: input.to_string(),
+            records: 0,
+            bad_records: 0,
+            max_bad_records,
+            bytes_written: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn skip(&mut self, record: usize, reason: &str) -> Result<(), FunctionError> {
+        self.bad_records += 1;
+        if self.bad_records > self.max_bad_records {
+            return Err(FunctionError::invalid_input_data(
+                &self.input,
+                format!("Bad record {}: {}", record, reason),
+            ));
+        }
+        self.warnings
+            .push(format!("Skipped bad record {}: {}", record, reason));
+        Ok(())
+    }
+}
+
+impl FormatConvert {
+    pub const NAME: &'static str = "builtin-format-convert";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args = FormatConvertArguments::try_from(arguments)?;
+        let direction = match args.direction.as_deref() {
+            Some("csv_to_jsonl") => Some(Direction::CsvToJsonl),
+            Some("jsonl_to_csv") => Some(Direction::JsonlToCsv),
+            Some(_) => return Err(FunctionError::invalid_arguments("Invalid direction")),
+            None => None,
+        };
+        let delimiter = parse_delimiter(&args.delimiter)?;
+        if let Some(columns) = &args.columns {
+            check_columns(columns).map_err(FunctionError::invalid_arguments)?;
+        }
+
+        let (detected, input) = open_sniffed(&*runtime, &args.input)?;
+        let (direction, delimiter) = match (args.auto_detect, detected) {
+            (true, DetectedFormat::Csv { delimiter, .. }) => (Direction::CsvToJsonl, delimiter),
+            (true, DetectedFormat::Jsonl) => (Direction::JsonlToCsv, delimiter),
+            _ => match direction {
+                Some(direction) => (direction, delimiter),
+                None if args.auto_detect => {
+                    return Err(FunctionError::invalid_input_data(
+                        &args.input,
+                        format!(
+                            "input '{}' looks like {}, set the direction to convert it",
+                            args.input,
+                            detected.name()
+                        ),
+                    ))
+                }
+                None => return Err(FunctionError::invalid_arguments("Missing direction")),
+            },
+        };
+        check_format(Self::NAME, &args.input, detected, direction.input_format())?;
+
+        let input = BufReader::new(input);
+        let mut output = runtime.create_output(&args.output)?;
+        let mut progress = Progress::new(&args.input, args.max_bad_records);
+        let cancellation = runtime.cancellation();
+        let checkpoint = |record: usize| {
+            if record % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()
+            } else {
+                Ok(())
+            }
+        };
+
+        match direction {
+            Direction::CsvToJsonl => {
+                let mut reader = CsvReader::new(input, delimiter);
+                let columns = match args.columns {
+                    Some(columns) => columns,
+                    None => {
+                        let header = reader
+                            .read_record()?
+                            .ok_or_else(|| {
+                                FunctionError::invalid_input_data(&args.input, "Missing CSV header")
+                            })?
+                            .map_err(|reason| {
+                                FunctionError::invalid_input_data(
+                                    &args.input,
+                                    format!("Invalid CSV header: {}", reason),
+                                )
+                            })?;
+                        check_columns(&header).map_err(|reason| {
+                            FunctionError::invalid_input_data(&args.input, reason)
+                        })?;
+                        header
+                    }
+                };
+                let mut index = 0;
+                while let Some(record) = reader.read_record()? {
+                    checkpoint(index)?;
+                    index += 1;
+                    let fields = match record {
+                        Ok(fields) if fields.len() == columns.len() => fields,
+                        Ok(fields) => {
+                            let reason = format!(
+                                "expected {} fields, found {}",
+                                columns.len(),
+                                fields.len()
+                            );
+                            progress.skip(index, &reason)?;
+                            continue;
+                        }
+                        Err(reason) => {
+                            progress.skip(index, &reason)?;
+                            continue;
+                        }
+                    };
+                    let object: Map<String, Value> = columns
+                        .iter()
+                        .cloned()
+                        .zip(fields.into_iter().map(|field| {
+                            if args.type_inference {
+                                infer_type(field)
+                            } else {
+                                Value::String(field)
+                            }
+                        }))
+                        .collect();
+                    let line = serde_json::to_string(&object).map_err(anyhow::Error::from)?;
+                    writeln!(&mut output, "{}", line)?;
+                    progress.bytes_written += line.len() + 1;
+                    progress.records += 1;
+                }
+            }
+            Direction::JsonlToCsv => {
+                let columns = args.columns.ok_or_else(|| {
+                    FunctionError::invalid_arguments("Columns are required for jsonl_to_csv")
+                })?;
+                progress.bytes_written += write_csv_record(&mut output, &columns, delimiter)?;
+                for (index, line) in input.lines().enumerate() {
+                    checkpoint(index)?;
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match to_csv_fields(&line, &columns) {
+                        Ok(fields) => {
+                            progress.bytes_written +=
+                                write_csv_record(&mut output, &fields, delimiter)?;
+                            progress.records += 1;
+                        }
+                        Err(reason) => progress.skip(index + 1, &reason)?,
+                    }
+                }
+            }
+        }
+
+        let mut summary = FunctionSummary::new(format!(
+            "{} records converted, {} bad records skipped",
+            progress.records, progress.bad_records
+        ))
+        .metric("records", progress.records as f64)
+        .metric("bad_records", progress.bad_records as f64)
+        .output(args.output, OutputInfo::new(progress.bytes_written as u64));
+        for warning in progress.warnings {
+            summary = summary.warning(warning);
+        }
+        Ok(summary)
+    }
+}
+
+fn parse_delimiter(delimiter: &str) -> Result<char, FunctionError> {
+    let mut chars = delimiter.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !matches!(c, '"' | '\r' | '\n') => Ok(c),
+        _ => Err(FunctionError::invalid_arguments("Invalid delimiter")),
+    }
+}
+
+/// Checks that there is at least one column and no column appears twice.
+fn check_columns(columns: &[String]) -> Result<(), String> {
+    if columns.is_empty() {
+        return Err("No columns".to_string());
+    }
+    let mut seen = HashSet::new();
+    for column in columns {
+        if !seen.insert(column) {
+            return Err(format!("Duplicate column: {:?}", column));
+        }
+    }
+    Ok(())
+}
+
+fn infer_type(field: String) -> Value {
+    match field.as_str() {
+        "" => Value::Null,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => {
+            if let Ok(n) = field.parse::<i64>() {
+                return Value::from(n);
+            }
+            match field.parse::<f64>().ok().and_then(Number::from_f64) {
+                Some(n) => Value::Number(n),
+                None => Value::String(field),
+            }
+        }
+    }
+}
+
+/// The fields of a JSON record in the order of `columns`. Missing fields and nulls become
+/// empty fields; nested objects and arrays cannot be represented and make the record bad.
+fn to_csv_fields(line: &str, columns: &[String]) -> Result<Vec<String>, String> {
+    let record: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let object = record
+        .as_object()
+        .ok_or_else(|| "record is not an object".to_string())?;
+    columns
+        .iter()
+        .map(|column| match object.get(column) {
+            None | Some(Value::Null) => Ok(String::new()),
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(Value::Bool(b)) => Ok(b.to_string()),
+            Some(Value::Number(n)) => Ok(n.to_string()),
+            Some(_) => Err(format!("field {:?} is not a scalar", column)),
+        })
+        .collect()
+}
+
+/// Writes one CSV record terminated by a line feed and returns its length. Fields containing
+/// the delimiter, quotes or line breaks are quoted, with quotes doubled, as in RFC 4180.
+fn write_csv_record(
+    output: &mut dyn Write,
+    fields: &[String],
+    delimiter: char,
+) -> io::Result<usize> {
+    let mut record = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            record.push(delimiter);
+        }
+        if field.contains(|c: char| c == delimiter || c == '"' || c == '\r' || c == '\n') {
+            record.push('"');
+            record.push_str(&field.replace('"', "\"\""));
+            record.push('"');
+        } else {
+            record.push_str(field);
+        }
+    }
+    record.push('\n');
+    output.write_all(record.as_bytes())?;
+    Ok(record.len())
+}
+
+/// Reader of RFC 4180 CSV records, which may span several lines when quoted fields contain
+/// line breaks. Records end with CRLF or a bare LF.
+struct CsvReader<R> {
+    inner: R,
+    delimiter: char,
+    line: String,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn new(inner: R, delimiter: char) -> Self {
+        Self {
+            inner,
+            delimiter,
+            line: String::new(),
+        }
+    }
+
+    /// Returns the next record, `Err` with a reason for a malformed one, or `None` at the end of
+    /// the input. Empty lines are skipped.
+    fn read_record(&mut self) -> io::Result<Option<Result<Vec<String>, String>>> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut quoted = false;
+        let mut error = None;
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                if in_quotes {
+                    return Ok(Some(Err("unterminated quoted field".to_string())));
+                }
+                if fields.is_empty() && field.is_empty() && !quoted {
+                    return Ok(None);
+                }
+                fields.push(field);
+                return Ok(Some(error.map_or(Ok(fields), Err)));
+            }
+
+            let mut chars = self.line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c != '"' {
+                        field.push(c);
+                    } else if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+                    }
+                } else if c == self.delimiter {
+                    fields.push(std::mem::take(&mut field));
+                    quoted = false;
+                } else if c == '\n' || (c == '\r' && chars.peek() == Some(&'\n')) {
+                    break;
+                } else if c == '"' && field.is_empty() && !quoted {
+                    in_quotes = true;
+                    quoted = true;
+                } else if quoted {
+                    error.get_or_insert_with(|| "characters after closing quote".to_string());
+                } else {
+                    field.push(c);
+                }
+            }
+            if in_quotes {
+                continue;
+            }
+            if fields.is_empty() && field.is_empty() && !quoted {
+                continue;
+            }
+            fields.push(field);
+            return Ok(Some(error.map_or(Ok(fields), Err)));
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_round_trip,
+            test_csv_quoting,
+            test_csv_to_jsonl_strings,
+            test_csv_to_jsonl_delimiter,
+            test_bad_records,
+            test_jsonl_to_csv_requires_columns,
+            test_input_format_mismatch,
+            test_auto_detect,
+        )
+    }
+
+    fn convert(
+        arguments: serde_json::Value,
+        input: &str,
+    ) -> anyhow::Result<(FunctionSummary, String)> {
+        let (summary, mut outputs) = run_builtin!(
+            FormatConvert::new(),
+            arguments,
+            hashmap!("input" => input),
+            ["output"]
+        );
+        let output = String::from_utf8(outputs.remove("output").unwrap_or_default()).unwrap();
+        Ok((summary?, output))
+    }
+
+    fn parse_jsonl(jsonl: &str) -> Vec<Value> {
+        jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn test_round_trip() {
+        let records = vec![
+            json!({"id": 1, "name": "alice", "score": 9.5, "active": true, "note": "plain"}),
+            json!({"id": 2, "name": "bob, jr.", "score": -3, "active": false, "note": "says \"hi\""}),
+            json!({"id": 3, "name": "carol", "score": 0.25, "active": true, "note": "two\nlines"}),
+            json!({"id": 4, "name": "dan", "score": 1e21, "active": false, "note": null}),
+        ];
+        let jsonl: String = records.iter().map(|r| format!("{}\n", r)).collect();
+        let columns = json!(["id", "name", "score", "active", "note"]);
+
+        let (summary, csv) = convert(
+            json!({"direction": "jsonl_to_csv", "columns": columns}),
+            &jsonl,
+        )
+        .unwrap();
+        assert_eq!(
+            summary.message,
+            "4 records converted, 0 bad records skipped"
+        );
+
+        let (summary, converted) = convert(
+            json!({"direction": "csv_to_jsonl", "type_inference": true}),
+            &csv,
+        )
+        .unwrap();
+        assert_eq!(summary.metrics["records"], 4.0);
+        assert_eq!(parse_jsonl(&converted), records);
+
+        let (_, csv_again) = convert(
+            json!({"direction": "jsonl_to_csv", "columns": columns}),
+            &converted,
+        )
+        .unwrap();
+        assert_eq!(csv_again, csv);
+    }
+
+    fn test_csv_quoting() {
+        let jsonl = concat!(
+            r#"{"a": "x,y", "b": "say \"hi\""}"#,
+            "\n",
+            r#"{"a": "line\nbreak", "b": ""}"#,
+            "\n",
+            r#"{"b": 7}"#,
+            "\n",
+        );
+        let (_, csv) = convert(
+            json!({"direction": "jsonl_to_csv", "columns": ["a", "b"]}),
+            jsonl,
+        )
+        .unwrap();
+        assert_eq!(
+            csv,
+            "a,b\n\"x,y\",\"say \"\"hi\"\"\"\n\"line\nbreak\",\n,7\n"
+        );
+    }
+
+    fn test_csv_to_jsonl_strings() {
+        let csv = "id,name,comment\r\n1,\"Smith, J\",\"He said \"\"no\"\"\r\nthen left\"\r\n\r\n2,,true\r\n";
+        let (summary, jsonl) = convert(json!({"direction": "csv_to_jsonl"}), csv).unwrap();
+        assert_eq!(summary.metrics["records"], 2.0);
+        assert_eq!(
+            parse_jsonl(&jsonl),
+            vec![
+                json!({"id": "1", "name": "Smith, J", "comment": "He said \"no\"\r\nthen left"}),
+                json!({"id": "2", "name": "", "comment": "true"}),
+            ]
+        );
+    }
+
+    fn test_csv_to_jsonl_delimiter() {
+        let csv = "1;a;b\n2;\"c;d\";e\n";
+        let arguments = json!({
+            "direction": "csv_to_jsonl",
+            "delimiter": ";",
+            "columns": ["n", "x", "y"],
+            "type_inference": true,
+        });
+        let (_, jsonl) = convert(arguments, csv).unwrap();
+        assert_eq!(
+            parse_jsonl(&jsonl),
+            vec![
+                json!({"n": 1, "x": "a", "y": "b"}),
+                json!({"n": 2, "x": "c;d", "y": "e"}),
+            ]
+        );
+    }
+
+    fn test_bad_records() {
+        let csv = "a,b\n1,2\n3\n\"4\"x,5\n6,7\n";
+        let err = convert(json!({"direction": "csv_to_jsonl"}), csv).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: Bad record 2: expected 2 fields, found 1"
+        );
+        assert_eq!(
+            FunctionError::from(err).category(),
+            TaskFailureCategory::InvalidInputData
+        );
+
+        let arguments = json!({"direction": "csv_to_jsonl", "max_bad_records": 2});
+        let (summary, jsonl) = convert(arguments, csv).unwrap();
+        assert_eq!(
+            summary.message,
+            "2 records converted, 2 bad records skipped"
+        );
+        assert_eq!(
+            summary.warnings,
+            vec![
+                "Skipped bad record 2: expected 2 fields, found 1",
+                "Skipped bad record 3: characters after closing quote",
+            ]
+        );
+        assert_eq!(
+            parse_jsonl(&jsonl),
+            vec![json!({"a": "1", "b": "2"}), json!({"a": "6", "b": "7"})]
+        );
+
+        let jsonl = "{\"a\": 1}\n[1]\n{\"a\": {\"b\": 2}}\nnot json\n";
+        let arguments =
+            json!({"direction": "jsonl_to_csv", "columns": ["a"], "max_bad_records": 3});
+        let (summary, csv) = convert(arguments, jsonl).unwrap();
+        assert_eq!(summary.metrics["bad_records"], 3.0);
+        assert_eq!(csv, "a\n1\n");
+
+        let err = convert(json!({"direction": "csv_to_jsonl"}), "a,b\n\"1,2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: Bad record 1: unterminated quoted field"
+        );
+    }
+
+    fn test_jsonl_to_csv_requires_columns() {
+        let err = convert(json!({"direction": "jsonl_to_csv"}), "{}\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: Columns are required for jsonl_to_csv"
+        );
+        let err = convert(json!({"direction": "sideways"}), "").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid arguments: Invalid direction");
+        assert_eq!(
+            FunctionError::from(err).category(),
+            TaskFailureCategory::InvalidArguments
+        );
+    }
+
+    fn test_input_format_mismatch() {
+        let jsonl = "{\"a\": 1, \"b\": 2}\n{\"a\": 3, \"b\": 4}\n";
+        let err = convert(json!({"direction": "csv_to_jsonl"}), jsonl).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: \
+             input 'input' looks like JSONL but builtin-format-convert expects CSV"
+        );
+
+        let arguments = json!({"direction": "jsonl_to_csv", "columns": ["a", "b"]});
+        let err = convert(arguments, "a,b\n1,2\n3,4\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: \
+             input 'input' looks like CSV but builtin-format-convert expects JSONL"
+        );
+        assert_eq!(
+            FunctionError::from(err).category(),
+            TaskFailureCategory::InvalidInputData
+        );
+    }
+
+    fn test_auto_detect() {
+        // The detected format overrides the direction and the delimiter.
+        let arguments = json!({"direction": "jsonl_to_csv", "auto_detect": true});
+        let (_, jsonl) = convert(arguments, "n;x\n1;a\n2;b\n").unwrap();
+        assert_eq!(
+            parse_jsonl(&jsonl),
+            vec![json!({"n": "1", "x": "a"}), json!({"n": "2", "x": "b"})]
+        );
+
+        let arguments = json!({"auto_detect": true, "columns": ["n", "x"]});
+        let (_, csv) = convert(arguments, "{\"n\": 1, \"x\": \"a\"}\n").unwrap();
+        assert_eq!(csv, "n,x\n1,a\n");
+
+        let err = convert(json!({"auto_detect": true}), "just some text\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: \
+             input 'input' looks like plain text, set the direction to convert it"
+        );
+    }
+}
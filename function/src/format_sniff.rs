@@ -0,0 +1,407 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::format;
+use std::io::{self, Read};
+use std::str;
+use teaclave_types::{FunctionError, TeaclaveRuntime};
+
+/// Number of bytes at the start of an input which builtins examine to check its format.
+pub const SNIFF_BYTES: usize = 8 * 1024;
+
+/// Delimiters tried when sniffing CSV, in order of preference on ties.
+const CSV_DELIMITERS: [char; 4] = [',', '\t', ';', '|'];
+
+/// Format of an input as guessed from its first bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DetectedFormat {
+    Empty,
+    /// Lines with the same number of at least two fields. `header` tells whether the first row
+    /// looks like column names: distinct, non-numeric names above a column holding numbers.
+    Csv {
+        delimiter: char,
+        header: bool,
+    },
+    /// Lines which all hold a JSON object or array.
+    Jsonl,
+    /// Text which is neither of the above.
+    Text,
+    Binary,
+}
+
+impl DetectedFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            DetectedFormat::Empty => "an empty file",
+            DetectedFormat::Csv { .. } => "CSV",
+            DetectedFormat::Jsonl => "JSONL",
+            DetectedFormat::Text => "plain text",
+            DetectedFormat::Binary => "binary data",
+        }
+    }
+}
+
+/// Format which a builtin expects of an input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpectedFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExpectedFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ExpectedFormat::Csv => "CSV",
+            ExpectedFormat::Jsonl => "JSONL",
+        }
+    }
+
+    /// Whether an input detected as `detected` is clearly not in this format. Plain text may
+    /// still be a single-column CSV or JSONL with malformed lines, so only the other structured
+    /// format and binary data are mismatches.
+    fn rejects(self, detected: DetectedFormat) -> bool {
+        matches!(
+            (self, detected),
+            (_, DetectedFormat::Binary)
+                | (ExpectedFormat::Csv, DetectedFormat::Jsonl)
+                | (ExpectedFormat::Jsonl, DetectedFormat::Csv { .. })
+        )
+    }
+}
+
+/// Guesses the format of `reader` from at most its first `sample_bytes` bytes.
+pub fn format_sniff(reader: &mut dyn Read, sample_bytes: usize) -> io::Result<DetectedFormat> {
+    let mut sample = Vec::with_capacity(sample_bytes);
+    reader.take(sample_bytes as u64).read_to_end(&mut sample)?;
+    Ok(detect(&sample, sample.len() == sample_bytes))
+}
+
+/// Opens input `identifier` and sniffs its format. The returned reader yields the whole input,
+/// including the sniffed bytes, so the input is read only once.
+pub(crate) fn open_sniffed(
+    runtime: &dyn TeaclaveRuntime,
+    identifier: &str,
+) -> Result<(DetectedFormat, Box<dyn Read>), FunctionError> {
+    let mut input = runtime.open_input(identifier)?;
+    let mut sample = Vec::with_capacity(SNIFF_BYTES);
+    (&mut input)
+        .take(SNIFF_BYTES as u64)
+        .read_to_end(&mut sample)?;
+    let format = detect(&sample, sample.len() == SNIFF_BYTES);
+    Ok((format, Box::new(io::Cursor::new(sample).chain(input))))
+}
+
+/// Fails with an error naming both formats if input `identifier` of `builtin` was detected as
+/// something other than `expected`.
+pub(crate) fn check_format(
+    builtin: &str,
+    identifier: &str,
+    detected: DetectedFormat,
+    expected: ExpectedFormat,
+) -> Result<(), FunctionError> {
+    if expected.rejects(detected) {
+        return Err(FunctionError::invalid_input_data(
+            identifier,
+            format!(
+                "input '{}' looks like {} but {} expects {}",
+                identifier,
+                detected.name(),
+                builtin,
+                expected.name()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Opens input `identifier` of `builtin` after checking that it looks like `expected`.
+pub(crate) fn open_expecting(
+    runtime: &dyn TeaclaveRuntime,
+    builtin: &str,
+    identifier: &str,
+    expected: ExpectedFormat,
+) -> Result<Box<dyn Read>, FunctionError> {
+    let (detected, input) = open_sniffed(runtime, identifier)?;
+    check_format(builtin, identifier, detected, expected)?;
+    Ok(input)
+}
+
+/// Guesses the format of `sample`. If it is `truncated`, its last line may be incomplete and is
+/// ignored.
+fn detect(sample: &[u8], truncated: bool) -> DetectedFormat {
+    if sample.is_empty() {
+        return DetectedFormat::Empty;
+    }
+    if sample.contains(&0) {
+        return DetectedFormat::Binary;
+    }
+    let text = match str::from_utf8(sample) {
+        Ok(text) => text,
+        // The sample may end in the middle of a character.
+        Err(e) if truncated && e.error_len().is_none() => {
+            str::from_utf8(&sample[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return DetectedFormat::Binary,
+    };
+    let chars = text.chars().count();
+    let controls = text
+        .chars()
+        .filter(|&c| c.is_control() && !matches!(c, '\t' | '\r' | '\n'))
+        .count();
+    if controls * 10 > chars {
+        return DetectedFormat::Binary;
+    }
+
+    let complete = match (truncated, text.rfind('\n')) {
+        (true, Some(end)) => &text[..end],
+        // Not even one line fits into the sample.
+        (true, None) => return DetectedFormat::Text,
+        (false, _) => text,
+    };
+    let lines: Vec<&str> = complete
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return DetectedFormat::Text;
+    }
+
+    let is_json_record = |line: &str| {
+        serde_json::from_str::<Value>(line)
+            .map(|value| value.is_object() || value.is_array())
+            .unwrap_or(false)
+    };
+    if lines.iter().all(|line| is_json_record(line)) {
+        return DetectedFormat::Jsonl;
+    }
+    // Lines of JSON with a few bad records are no CSV, even if their commas line up.
+    if lines
+        .iter()
+        .any(|line| line.starts_with('{') || line.starts_with('['))
+    {
+        return DetectedFormat::Text;
+    }
+
+    let mut best: Option<(char, Vec<Vec<&str>>)> = None;
+    for &delimiter in CSV_DELIMITERS.iter() {
+        let rows: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|line| split_fields(line, delimiter))
+            .collect();
+        let width = rows[0].len();
+        if width < 2 || rows.iter().any(|row| row.len() != width) {
+            continue;
+        }
+        if best
+            .as_ref()
+            .map_or(true, |(_, best)| width > best[0].len())
+        {
+            best = Some((delimiter, rows));
+        }
+    }
+    match best {
+        Some((delimiter, rows)) => DetectedFormat::Csv {
+            delimiter,
+            header: looks_like_header(&rows),
+        },
+        None => DetectedFormat::Text,
+    }
+}
+
+/// Splits a CSV line at `delimiter` outside of quotes. Fields keep their quotes.
+pub(crate) fn split_fields(line: &str, delimiter: char) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == delimiter && !quoted {
+            fields.push(&line[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
+/// Removes the quotes around a CSV field.
+pub(crate) fn unquote(field: &str) -> &str {
+    field
+        .strip_prefix('"')
+        .and_then(|f| f.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+fn looks_like_header(rows: &[Vec<&str>]) -> bool {
+    let unquote = |field: &str| field.trim().trim_matches('"').to_string();
+    let is_number = |field: &str| unquote(field).parse::<f64>().is_ok();
+    let header = &rows[0];
+    let names: HashSet<String> = header.iter().map(|field| unquote(field)).collect();
+    if names.len() != header.len()
+        || header
+            .iter()
+            .any(|field| unquote(field).is_empty() || is_number(field))
+    {
+        return false;
+    }
+    // Without numbers below it, a row of names is as likely a record as a header.
+    rows[1..]
+        .iter()
+        .any(|row| row.iter().any(|field| is_number(field)))
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_sniff_csv,
+            test_sniff_jsonl,
+            test_sniff_text_and_binary,
+            test_sniff_reads_bounded_prefix,
+            test_open_expecting,
+        )
+    }
+
+    fn sniff(data: &[u8]) -> DetectedFormat {
+        format_sniff(&mut io::Cursor::new(data), SNIFF_BYTES).unwrap()
+    }
+
+    fn test_sniff_csv() {
+        assert_eq!(
+            sniff(b"name,age\nalice,30\nbob,25\n"),
+            DetectedFormat::Csv {
+                delimiter: ',',
+                header: true
+            }
+        );
+        assert_eq!(
+            sniff(b"alice;30\n\"b;ob\";25\n"),
+            DetectedFormat::Csv {
+                delimiter: ';',
+                header: false
+            }
+        );
+        assert_eq!(
+            sniff(b"id\tname\tcity\n1\tx, y\tz\n"),
+            DetectedFormat::Csv {
+                delimiter: '\t',
+                header: true
+            }
+        );
+        // Only text below the names, so they may as well be a record.
+        assert_eq!(
+            sniff(b"a,b\nc,d\n"),
+            DetectedFormat::Csv {
+                delimiter: ',',
+                header: false
+            }
+        );
+    }
+
+    fn test_sniff_jsonl() {
+        assert_eq!(
+            sniff(b"{\"a\": 1, \"b\": 2}\n\n{\"a\": 3, \"b\": 4}\n"),
+            DetectedFormat::Jsonl
+        );
+        assert_eq!(sniff(b"[1,2]\n[3,4]"), DetectedFormat::Jsonl);
+        // A bad record does not make JSONL look like CSV.
+        assert_eq!(
+            sniff(b"{\"a\": 1, \"b\": 2}\n{\"a\": 3, \"b\": \n"),
+            DetectedFormat::Text
+        );
+    }
+
+    fn test_sniff_text_and_binary() {
+        assert_eq!(sniff(b""), DetectedFormat::Empty);
+        assert_eq!(sniff(b"hello world\nsecond line\n"), DetectedFormat::Text);
+        assert_eq!(sniff(b"one,two\nthree\n"), DetectedFormat::Text);
+        assert_eq!(
+            sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            DetectedFormat::Binary
+        );
+        assert_eq!(sniff(&[0xff, 0xfe, 0x41, 0x42]), DetectedFormat::Binary);
+        assert_eq!(
+            sniff(&[0x01, 0x02, 0x03, b'a', b'\n']),
+            DetectedFormat::Binary
+        );
+    }
+
+    fn test_sniff_reads_bounded_prefix() {
+        // The last line of the sample is cut off; the lines before it decide.
+        let mut data = b"x,1\n".repeat(10);
+        data.extend_from_slice(b"{\"cut\": \"off");
+        let mut reader = io::Cursor::new(&data);
+        assert_eq!(
+            format_sniff(&mut reader, 44).unwrap(),
+            DetectedFormat::Csv {
+                delimiter: ',',
+                header: false
+            }
+        );
+        assert_eq!(reader.position(), 44);
+
+        // A sample without a complete line tells nothing.
+        let mut reader = io::Cursor::new(b"{\"a\": 1, \"b\": 2}\n");
+        assert_eq!(format_sniff(&mut reader, 8).unwrap(), DetectedFormat::Text);
+
+        // A multi-byte character may be cut in two.
+        let data = "é,1\né,2\n".as_bytes();
+        assert_eq!(
+            format_sniff(&mut io::Cursor::new(data), 6).unwrap(),
+            DetectedFormat::Csv {
+                delimiter: ',',
+                header: false
+            }
+        );
+    }
+
+    fn test_open_expecting() {
+        let jsonl = b"{\"a\": 1}\n{\"a\": 2}\n".to_vec();
+        let input_files = StagedFiles::from_memory(hashmap!("training_data" => jsonl.clone()));
+        let runtime = RawIoRuntime::new(input_files, StagedFiles::default());
+
+        let mut input = open_expecting(
+            &runtime,
+            "builtin-x",
+            "training_data",
+            ExpectedFormat::Jsonl,
+        )
+        .unwrap();
+        let mut read = Vec::new();
+        input.read_to_end(&mut read).unwrap();
+        assert_eq!(read, jsonl);
+
+        let error = open_expecting(&runtime, "builtin-x", "training_data", ExpectedFormat::Csv)
+            .err()
+            .unwrap();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidInputData);
+        assert_eq!(
+            error.to_string(),
+            "Invalid data in input training_data: \
+             input 'training_data' looks like JSONL but builtin-x expects CSV"
+        );
+    }
+}
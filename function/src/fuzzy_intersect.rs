@@ -0,0 +1,341 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use anyhow::bail;
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{self, BufRead, BufReader, Write};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
+
+const IN_SET_A: &str = "set_a";
+const IN_SET_B: &str = "set_b";
+const OUT_MATCHED_PAIRS: &str = "matched_pairs";
+
+const DEFAULT_MAX_FUZZY_LENGTH: usize = 64;
+
+#[derive(Default)]
+pub struct FuzzyIntersect;
+
+#[derive(serde::Deserialize)]
+struct FuzzyIntersectArguments {
+    #[serde(default)]
+    normalize: Vec<String>,
+    #[serde(default)]
+    max_edit_distance: usize,
+    // Strings longer than this (in chars, after normalization) are only matched exactly.
+    #[serde(default = "default_max_fuzzy_length")]
+    max_fuzzy_length: usize,
+}
+
+fn default_max_fuzzy_length() -> usize {
+    DEFAULT_MAX_FUZZY_LENGTH
+}
+
+impl TryFrom<FunctionArguments> for FuzzyIntersectArguments {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        use anyhow::Context;
+        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Normalization {
+    Lowercase,
+    Trim,
+    StripPunctuation,
+}
+
+impl TryFrom<&str> for Normalization {
+    type Error = anyhow::Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let n = match s {
+            "lowercase" => Normalization::Lowercase,
+            "trim" => Normalization::Trim,
+            "strip_punctuation" => Normalization::StripPunctuation,
+            _ => bail!("Invalid normalization: {}", s),
+        };
+        Ok(n)
+    }
+}
+
+struct Record {
+    original: String,
+    normalized: Vec<char>,
+}
+
+impl FuzzyIntersect {
+    pub const NAME: &'static str = "builtin-fuzzy-intersect";
+    pub const TAGS: &'static [&'static str] = &["experimental"];
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let args = FuzzyIntersectArguments::try_from(arguments)?;
+        let normalizations = args
+            .normalize
+            .iter()
+            .map(|n| Normalization::try_from(n.as_str()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let set_a = parse_input_data(runtime.open_input(IN_SET_A)?, &normalizations)?;
+        let set_b = parse_input_data(runtime.open_input(IN_SET_B)?, &normalizations)?;
+        let mut output = runtime.create_output(OUT_MATCHED_PAIRS)?;
+
+        // Exact matches on the normalized form are found through an index; only the
+        // remaining short records go through the quadratic fuzzy comparison.
+        let mut index: HashMap<&[char], Vec<usize>> = HashMap::new();
+        for (j, b) in set_b.iter().enumerate() {
+            index.entry(&b.normalized).or_default().push(j);
+        }
+
+        let mut matched_pairs = 0;
+        for a in &set_a {
+            if let Some(js) = index.get(a.normalized.as_slice()) {
+                for &j in js {
+                    writeln!(&mut output, "{}\t{}\t0", a.original, set_b[j].original)?;
+                    matched_pairs += 1;
+                }
+            }
+
+            if args.max_edit_distance == 0 || a.normalized.len() > args.max_fuzzy_length {
+                continue;
+            }
+            for b in &set_b {
+                if b.normalized.len() > args.max_fuzzy_length || a.normalized == b.normalized {
+                    continue;
+                }
+                if let Some(distance) =
+                    bounded_levenshtein(&a.normalized, &b.normalized, args.max_edit_distance)
+                {
+                    writeln!(&mut output, "{}\t{}\t{}", a.original, b.original, distance)?;
+                    matched_pairs += 1;
+                }
+            }
+        }
+
+        Ok(format!(
+            "{} records in set_a, {} records in set_b, {} matched pairs",
+            set_a.len(),
+            set_b.len(),
+            matched_pairs
+        )
+        .into())
+    }
+}
+
+fn parse_input_data(
+    input: impl io::Read,
+    normalizations: &[Normalization],
+) -> anyhow::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for line in BufReader::new(input).lines() {
+        let original = line?;
+        if original.trim().is_empty() {
+            continue;
+        }
+        let normalized = normalize(&original, normalizations);
+        records.push(Record {
+            original,
+            normalized,
+        });
+    }
+    Ok(records)
+}
+
+fn normalize(s: &str, normalizations: &[Normalization]) -> Vec<char> {
+    let mut s = s.to_owned();
+    for n in normalizations {
+        s = match n {
+            Normalization::Lowercase => s.to_lowercase(),
+            Normalization::Trim => s.trim().to_owned(),
+            Normalization::StripPunctuation => {
+                s.chars().filter(|c| !c.is_ascii_punctuation()).collect()
+            }
+        };
+    }
+    s.chars().collect()
+}
+
+// Levenshtein distance restricted to a diagonal band of width 2 * max_distance + 1. Returns
+// None as soon as the distance is known to exceed max_distance.
+fn bounded_levenshtein(a: &[char], b: &[char], max_distance: usize) -> Option<usize> {
+    let len_diff = if a.len() > b.len() {
+        a.len() - b.len()
+    } else {
+        b.len() - a.len()
+    };
+    if len_diff > max_distance {
+        return None;
+    }
+
+    let infinity = max_distance + 1;
+    let mut prev: Vec<usize> = (0..=b.len())
+        .map(|j| if j <= max_distance { j } else { infinity })
+        .collect();
+
+    for i in 1..=a.len() {
+        let mut cur = vec![infinity; b.len() + 1];
+        if i <= max_distance {
+            cur[0] = i;
+        }
+        let lo = cmp::max(1, i.saturating_sub(max_distance));
+        let hi = cmp::min(b.len(), i + max_distance);
+        let mut row_min = cur[0];
+        for j in lo..=hi {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let d = cmp::min(prev[j - 1] + cost, cmp::min(prev[j], cur[j - 1]) + 1);
+            cur[j] = cmp::min(d, infinity);
+            row_min = cmp::min(row_min, cur[j]);
+        }
+        if row_min > max_distance {
+            return None;
+        }
+        prev = cur;
+    }
+
+    if prev[b.len()] <= max_distance {
+        Some(prev[b.len()])
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::Path;
+    use std::untrusted::fs;
+    use teaclave_crypto::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_fuzzy_intersect_normalized,
+            test_fuzzy_intersect_edit_distance,
+            test_fuzzy_intersect_length_cutoff,
+            test_bounded_levenshtein,
+        )
+    }
+
+    fn run_fuzzy_intersect(arguments: FunctionArguments) -> (String, String) {
+        let base = Path::new("fixtures/functions/fuzzy_intersect");
+        let output = base.join("output_pairs.txt");
+
+        let input_files = StagedFiles::new(hashmap!(
+            IN_SET_A =>
+            StagedFileInfo::new(base.join("set_a.txt"), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+            IN_SET_B =>
+            StagedFileInfo::new(base.join("set_b.txt"), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+        let output_files = StagedFiles::new(hashmap!(
+            OUT_MATCHED_PAIRS =>
+            StagedFileInfo::new(&output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+        let summary = FuzzyIntersect::new().run(arguments, runtime).unwrap();
+        let pairs = fs::read_to_string(&output).unwrap();
+        (summary.message, pairs)
+    }
+
+    fn test_fuzzy_intersect_normalized() {
+        let arguments = FunctionArguments::from_json(json!({
+            "normalize": ["lowercase", "trim", "strip_punctuation"],
+            "max_edit_distance": 0,
+        }))
+        .unwrap();
+
+        let (summary, pairs) = run_fuzzy_intersect(arguments);
+        assert_eq!(
+            summary,
+            "4 records in set_a, 4 records in set_b, 2 matched pairs"
+        );
+        assert!(pairs.contains("Alice Smith\t  alice smith.\t0\n"));
+        assert!(pairs.contains("O'Brien\tobrien\t0\n"));
+    }
+
+    fn test_fuzzy_intersect_edit_distance() {
+        let arguments = FunctionArguments::from_json(json!({
+            "normalize": ["lowercase", "trim", "strip_punctuation"],
+            "max_edit_distance": 1,
+        }))
+        .unwrap();
+
+        let (summary, pairs) = run_fuzzy_intersect(arguments);
+        assert_eq!(
+            summary,
+            "4 records in set_a, 4 records in set_b, 4 matched pairs"
+        );
+        assert!(pairs.contains("Jon Doe\tJohn Doe\t1\n"));
+        assert!(
+            pairs.contains("International Business Machines\tInternational Busines Machines\t1\n")
+        );
+    }
+
+    fn test_fuzzy_intersect_length_cutoff() {
+        let arguments = FunctionArguments::from_json(json!({
+            "normalize": ["lowercase", "trim", "strip_punctuation"],
+            "max_edit_distance": 1,
+            "max_fuzzy_length": 16,
+        }))
+        .unwrap();
+
+        let (summary, pairs) = run_fuzzy_intersect(arguments);
+        assert_eq!(
+            summary,
+            "4 records in set_a, 4 records in set_b, 3 matched pairs"
+        );
+        assert!(pairs.contains("Jon Doe\tJohn Doe\t1\n"));
+        assert!(!pairs.contains("International"));
+    }
+
+    fn test_bounded_levenshtein() {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+        assert_eq!(
+            bounded_levenshtein(&chars("kitten"), &chars("kitten"), 0),
+            Some(0)
+        );
+        assert_eq!(
+            bounded_levenshtein(&chars("kitten"), &chars("sitten"), 1),
+            Some(1)
+        );
+        assert_eq!(
+            bounded_levenshtein(&chars("kitten"), &chars("sitting"), 3),
+            Some(3)
+        );
+        assert_eq!(
+            bounded_levenshtein(&chars("kitten"), &chars("sitting"), 2),
+            None
+        );
+        assert_eq!(bounded_levenshtein(&chars("a"), &chars("abcd"), 2), None);
+        assert_eq!(bounded_levenshtein(&chars(""), &chars("ab"), 2), Some(2));
+    }
+}
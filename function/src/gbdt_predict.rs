@@ -15,10 +15,13 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use std::convert::TryFrom;
 use std::format;
 use std::io::{self, BufRead, BufReader, Write};
+use std::sync::Arc;
 
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use crate::parallel::{map_chunks, resolve_num_threads};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
 
 use gbdt::decision_tree::Data;
 use gbdt::gradient_boost::GBDT;
@@ -30,6 +33,22 @@ const OUT_RESULT: &str = "result_file";
 #[derive(Default)]
 pub struct GbdtPredict;
 
+#[derive(serde::Deserialize)]
+struct GbdtPredictArguments {
+    /// Threads scoring the rows, at most the runtime's `max_parallelism`. Defaults to one.
+    #[serde(default)]
+    num_threads: Option<usize>,
+}
+
+impl TryFrom<FunctionArguments> for GbdtPredictArguments {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        use anyhow::Context;
+        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    }
+}
+
 impl GbdtPredict {
     pub const NAME: &'static str = "builtin-gbdt-predict";
 
@@ -39,26 +58,34 @@ impl GbdtPredict {
 
     pub fn run(
         &self,
-        _arguments: FunctionArguments,
+        arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<FunctionSummary> {
+        let args = GbdtPredictArguments::try_from(arguments)?;
+        let num_threads = resolve_num_threads(args.num_threads, &runtime)?;
+
         let mut json_model = String::new();
         let mut f = runtime.open_input(IN_MODEL)?;
         f.read_to_string(&mut json_model)?;
 
-        let model: GBDT = serde_json::from_str(&json_model)?;
+        let model: Arc<GBDT> = Arc::new(serde_json::from_str(&json_model)?);
 
         let in_data = runtime.open_input(IN_DATA)?;
         let test_data = parse_test_data(in_data)?;
 
-        let predict_set = model.predict(&test_data);
+        // Rows are scored independently, so the result doesn't depend on the thread count.
+        let predict_set = map_chunks(test_data, num_threads, move |rows| Ok(model.predict(&rows)))?;
 
         let mut of_result = runtime.create_output(OUT_RESULT)?;
         for predict_value in predict_set.iter() {
             writeln!(&mut of_result, "{:.10}", predict_value)?
         }
 
-        let summary = format!("Predict result has {} lines of data.", predict_set.len());
+        let summary = FunctionSummary::new(format!(
+            "Predict result has {} lines of data.",
+            predict_set.len()
+        ))
+        .metric("num_threads", num_threads as f64);
         Ok(summary)
     }
 }
@@ -101,7 +128,66 @@ pub mod tests {
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_gbdt_prediction)
+        run_tests!(
+            test_gbdt_prediction,
+            test_gbdt_prediction_threads,
+            test_gbdt_prediction_environment
+        )
+    }
+
+    fn test_gbdt_prediction_threads() {
+        let model = fs::read_to_string("fixtures/functions/gbdt_prediction/model.txt").unwrap();
+        let model: Arc<GBDT> = Arc::new(serde_json::from_str(&model).unwrap());
+        let data = fs::read("fixtures/functions/gbdt_prediction/test_data.txt").unwrap();
+        let test_data = parse_test_data(&data[..]).unwrap();
+
+        let expected: Vec<u32> = model
+            .predict(&test_data)
+            .iter()
+            .map(|v| v.to_bits())
+            .collect();
+        for num_threads in 1..=4 {
+            let model = model.clone();
+            let result = map_chunks(test_data.clone(), num_threads, move |rows| {
+                Ok(model.predict(&rows))
+            })
+            .unwrap();
+            let result: Vec<u32> = result.iter().map(|v| v.to_bits()).collect();
+            assert_eq!(result, expected);
+        }
+
+        let arguments =
+            FunctionArguments::from_json(serde_json::json!({"num_threads": 2})).unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let err = GbdtPredict::new().run(arguments, runtime).unwrap_err();
+        assert_eq!(err.to_string(), "num_threads must be between 1 and 1");
+    }
+
+    fn test_gbdt_prediction_environment() {
+        let model = fs::read("fixtures/functions/gbdt_prediction/model.txt").unwrap();
+        let data = fs::read("fixtures/functions/gbdt_prediction/test_data.txt").unwrap();
+        let expected = fs::read("fixtures/functions/gbdt_prediction/expected_result.txt").unwrap();
+        let predict = |arguments| {
+            let (summary, outputs) = run_builtin!(
+                GbdtPredict::new(),
+                arguments,
+                hashmap!(IN_MODEL => model.clone(), IN_DATA => data.clone()),
+                [OUT_RESULT],
+                with_environment(ExecutionEnvironment::new().max_parallelism(3))
+            );
+            assert_eq!(outputs[OUT_RESULT], expected);
+            summary.unwrap()
+        };
+
+        // Without an argument a single thread scores the rows, as before the argument existed.
+        let summary = predict(serde_json::json!({}));
+        assert_eq!(summary.metrics["num_threads"], 1.0);
+        // The environment bounds the threads a task may ask for.
+        let summary = predict(serde_json::json!({"num_threads": 3}));
+        assert_eq!(summary.metrics["num_threads"], 3.0);
     }
 
     fn test_gbdt_prediction() {
@@ -127,7 +213,7 @@ pub mod tests {
         let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
 
         let summary = GbdtPredict::new().run(arguments, runtime).unwrap();
-        assert_eq!(summary, "Predict result has 30 lines of data.");
+        assert_eq!(summary.message, "Predict result has 30 lines of data.");
 
         let result = fs::read_to_string(plain_output).unwrap();
         let expected = fs::read_to_string(expected_output).unwrap();
@@ -17,9 +17,19 @@
 
 use std::format;
 use std::io::{self, BufRead, BufReader, Write};
-
+use std::sync::Arc;
+use std::time::Instant;
+use std::untrusted::time::InstantEx;
+
+use crate::parallel::{map_chunks, resolve_num_threads};
+use crate::training_report::{
+    report_requested, write_report, DataStatistics, Timing, TrainingReport,
+};
 use std::convert::TryFrom;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, CancellationToken, FunctionArguments,
+    FunctionRuntime, FunctionSummary, CANCELLATION_CHECK_INTERVAL,
+};
 
 use gbdt::config::Config;
 use gbdt::decision_tree::Data;
@@ -31,7 +41,7 @@ const OUT_MODEL: &str = "trained_model";
 #[derive(Default)]
 pub struct GbdtTrain;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 struct GbdtTrainArguments {
     feature_size: usize,
     max_depth: u32,
@@ -42,6 +52,10 @@ struct GbdtTrainArguments {
     min_leaf_size: usize,
     loss: String,
     training_optimization_level: u8,
+    /// Threads computing the loss curve of the report, at most the runtime's
+    /// `max_parallelism`. Defaults to one.
+    #[serde(default, skip_serializing)]
+    num_threads: Option<usize>,
 }
 
 impl TryFrom<FunctionArguments> for GbdtTrainArguments {
@@ -60,19 +74,90 @@ impl GbdtTrain {
         Default::default()
     }
 
+    pub fn argument_schema() -> ArgumentSchema {
+        let count = |name: &str, description: &str| {
+            ArgumentSpec::new(name, ArgumentType::Integer)
+                .required()
+                .minimum(1.0)
+                .description(description)
+        };
+        let ratio = |name: &str, description: &str| {
+            ArgumentSpec::new(name, ArgumentType::Number)
+                .required()
+                .minimum(0.0)
+                .maximum(1.0)
+                .description(description)
+        };
+        ArgumentSchema::new()
+            .argument(count("feature_size", "Number of features of each sample"))
+            .argument(count("max_depth", "Maximum depth of the trees"))
+            .argument(count("iterations", "Number of trees to train"))
+            .argument(
+                ArgumentSpec::new("shrinkage", ArgumentType::Number)
+                    .required()
+                    .minimum(0.0)
+                    .description("Learning rate"),
+            )
+            .argument(ratio(
+                "feature_sample_ratio",
+                "Fraction of the features each tree sees",
+            ))
+            .argument(ratio(
+                "data_sample_ratio",
+                "Fraction of the samples each tree sees",
+            ))
+            .argument(count(
+                "min_leaf_size",
+                "Minimum number of samples of a leaf",
+            ))
+            .argument(
+                ArgumentSpec::new("loss", ArgumentType::String)
+                    .required()
+                    .one_of(&[
+                        "SquaredError",
+                        "LogLikelyhood",
+                        "LAD",
+                        "reg:linear",
+                        "binary:logistic",
+                        "reg:logistic",
+                        "binary:logitraw",
+                        "multi:softprob",
+                        "multi:softmax",
+                        "rank:pairwise",
+                    ])
+                    .description("Loss function"),
+            )
+            .argument(
+                ArgumentSpec::new("training_optimization_level", ArgumentType::Integer)
+                    .required()
+                    .minimum(0.0)
+                    .maximum(2.0),
+            )
+            .argument(
+                ArgumentSpec::new("num_threads", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .description("Threads computing the loss curve of the training report"),
+            )
+    }
+
     pub fn run(
         &self,
         arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<FunctionSummary> {
         log::debug!("start traning...");
         let args = GbdtTrainArguments::try_from(arguments)?;
+        let num_threads = resolve_num_threads(args.num_threads, &runtime)?;
 
         log::debug!("open input...");
         // read input
+        let cancellation = runtime.cancellation();
+        let parse_start = Instant::now();
         let training_file = runtime.open_input(IN_DATA)?;
-        let mut train_dv = parse_training_data(training_file, args.feature_size)?;
+        let mut train_dv = parse_training_data(training_file, args.feature_size, &cancellation)?;
         let data_size = train_dv.len();
+        let parse_time = parse_start.elapsed();
+        runtime.report_progress(0.1, "parsed training data");
 
         // init gbdt config
         let mut cfg = Config::new();
@@ -88,19 +173,104 @@ impl GbdtTrain {
         cfg.set_training_optimization_level(args.training_optimization_level);
 
         // start training
+        let training_start = Instant::now();
         let mut gbdt_train_mod = GBDT::new(&cfg);
         gbdt_train_mod.fit(&mut train_dv);
+        cancellation.checkpoint()?;
+        let training_time = training_start.elapsed();
+        runtime.report_progress(0.8, "trained model");
         let model_json = serde_json::to_string(&gbdt_train_mod)?;
 
         // save the model to output
         let mut model_file = runtime.create_output(OUT_MODEL)?;
         model_file.write_all(model_json.as_bytes())?;
-
+        runtime.report_progress(0.9, "saved model");
+
+        let report = if report_requested(&runtime) {
+            Some(TrainingReport {
+                parameters: &args,
+                data: data_statistics(&train_dv),
+                loss_curve: loss_curve(
+                    Arc::new(gbdt_train_mod),
+                    Arc::new(train_dv),
+                    &args.loss,
+                    args.iterations,
+                    num_threads,
+                    &cancellation,
+                )?,
+                timing: Timing::new(parse_time, training_time),
+            })
+        } else {
+            None
+        };
+
+        runtime.report_progress(1.0, "done");
         let summary = format!("Trained {} lines of data.", data_size);
-        Ok(summary)
+        write_report(&runtime, report.as_ref(), summary.into())
     }
 }
 
+fn data_statistics(samples: &[Data]) -> DataStatistics {
+    let features: Vec<f64> = samples
+        .iter()
+        .flat_map(|data| data.feature.iter().map(|&x| x as f64))
+        .collect();
+    let labels: Vec<f64> = samples.iter().map(|data| data.label as f64).collect();
+    DataStatistics::new(&features, &labels)
+}
+
+/// Mean loss of the model on its training data after each iteration. The loss is computed from
+/// the raw scores of the first trees, with the function matching the configured loss. The
+/// iterations are spread over `num_threads` threads; each loss only depends on its own number of
+/// trees, so the curve is the same for any thread count.
+fn loss_curve(
+    model: Arc<GBDT>,
+    samples: Arc<Vec<Data>>,
+    loss: &str,
+    iterations: usize,
+    num_threads: usize,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Vec<f64>> {
+    let labels: Vec<f64> = samples.iter().map(|data| data.label as f64).collect();
+    let loss = loss.to_string();
+    let cancellation = cancellation.clone();
+    map_chunks(
+        (1..=iterations).collect(),
+        num_threads,
+        move |iterations: Vec<usize>| {
+            let mut curve = Vec::with_capacity(iterations.len());
+            for n in iterations {
+                cancellation.checkpoint()?;
+                let scores = model.predict_n(&samples, n);
+                curve.push(mean_loss(&loss, &labels, &scores));
+            }
+            Ok(curve)
+        },
+    )
+}
+
+fn mean_loss(loss: &str, labels: &[f64], scores: &[f32]) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    let total: f64 = labels
+        .iter()
+        .zip(scores)
+        .map(|(&y, &score)| {
+            let f = score as f64;
+            match loss {
+                "LAD" => (y - f).abs(),
+                // Labels are -1 or 1.
+                "LogLikelyhood" => (1.0 + (-2.0 * y * f).exp()).ln(),
+                "reg:logistic" | "binary:logistic" | "binary:logitraw" => {
+                    let p = (1.0 / (1.0 + (-f).exp())).clamp(EPSILON, 1.0 - EPSILON);
+                    -(y * p.ln() + (1.0 - y) * (1.0 - p).ln())
+                }
+                _ => (y - f).powi(2),
+            }
+        })
+        .sum();
+    total / labels.len().max(1) as f64
+}
+
 fn parse_data_line(line: &str, feature_size: usize) -> anyhow::Result<Data> {
     let trimed_line = line.trim();
     anyhow::ensure!(!trimed_line.is_empty(), "Empty line");
@@ -128,10 +298,17 @@ fn parse_data_line(line: &str, feature_size: usize) -> anyhow::Result<Data> {
     })
 }
 
-fn parse_training_data(input: impl io::Read, feature_size: usize) -> anyhow::Result<Vec<Data>> {
+fn parse_training_data(
+    input: impl io::Read,
+    feature_size: usize,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Vec<Data>> {
     let mut samples: Vec<Data> = Vec::new();
     let reader = BufReader::new(input);
-    for line_result in reader.lines() {
+    for (index, line_result) in reader.lines().enumerate() {
+        if index % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
         let line = line_result?;
         let data = parse_data_line(&line, feature_size)?;
         samples.push(data);
@@ -143,6 +320,7 @@ fn parse_training_data(input: impl io::Read, feature_size: usize) -> anyhow::Res
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use crate::training_report::OUT_REPORT;
     use serde_json::json;
     use std::untrusted::fs;
     use teaclave_crypto::*;
@@ -151,11 +329,16 @@ pub mod tests {
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_gbdt_train, test_gbdt_parse_training_data,)
+        run_tests!(
+            test_gbdt_train,
+            test_gbdt_train_report,
+            test_gbdt_train_threads,
+            test_gbdt_parse_training_data,
+        )
     }
 
-    fn test_gbdt_train() {
-        let arguments = FunctionArguments::from_json(json!({
+    fn training_arguments() -> FunctionArguments {
+        FunctionArguments::from_json(json!({
             "feature_size": 4,
             "max_depth": 4,
             "iterations": 100,
@@ -166,7 +349,11 @@ pub mod tests {
             "loss": "LAD",
             "training_optimization_level": 2
         }))
-        .unwrap();
+        .unwrap()
+    }
+
+    fn test_gbdt_train() {
+        let arguments = training_arguments();
 
         let plain_input = "fixtures/functions/gbdt_training/train.txt";
         let plain_output = "fixtures/functions/gbdt_training/training_model.txt.out";
@@ -185,13 +372,99 @@ pub mod tests {
         let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
 
         let summary = GbdtTrain::new().run(arguments, runtime).unwrap();
-        assert_eq!(summary, "Trained 120 lines of data.");
+        assert_eq!(summary.message, "Trained 120 lines of data.");
+        assert_eq!(summary.metrics["report_emitted"], 0.0);
+        assert!(!summary.outputs.contains_key(OUT_REPORT));
 
         let result = fs::read_to_string(plain_output).unwrap();
         let expected = fs::read_to_string(expected_output).unwrap();
         assert_eq!(&result[..], &expected[..]);
     }
 
+    fn test_gbdt_train_report() {
+        let arguments = training_arguments();
+
+        let plain_input = "fixtures/functions/gbdt_training/train.txt";
+        let plain_output = "fixtures/functions/gbdt_training/training_model_with_report.txt.out";
+        let report_output = "fixtures/functions/gbdt_training/report.json.out";
+        let expected_output = "fixtures/functions/gbdt_training/expected_model.txt";
+
+        let input_files = StagedFiles::new(hashmap!(
+            IN_DATA =>
+            StagedFileInfo::new(plain_input, TeaclaveFile128Key::random(), FileAuthTag::mock())
+        ));
+
+        let output_files = StagedFiles::new(hashmap!(
+            OUT_MODEL =>
+            StagedFileInfo::new(plain_output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+            OUT_REPORT =>
+            StagedFileInfo::new(report_output, TeaclaveFile128Key::random(), FileAuthTag::mock())
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+
+        let summary = GbdtTrain::new().run(arguments, runtime).unwrap();
+        assert_eq!(
+            summary.message,
+            "Trained 120 lines of data. Wrote a training report."
+        );
+        assert_eq!(summary.metrics["report_emitted"], 1.0);
+
+        // The report does not change the model.
+        let result = fs::read_to_string(plain_output).unwrap();
+        let expected = fs::read_to_string(expected_output).unwrap();
+        assert_eq!(&result[..], &expected[..]);
+
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(report_output).unwrap()).unwrap();
+        assert_eq!(report["parameters"]["iterations"], 100);
+        assert_eq!(report["parameters"]["loss"], "LAD");
+        assert_eq!(report["data"]["rows"], 120);
+        assert_eq!(report["data"]["features"].as_array().unwrap().len(), 4);
+        assert!(report["data"]["label"]["mean"].is_number());
+        assert!(report["timing"]["parse_ms"].is_number());
+        assert!(report["timing"]["training_ms"].is_number());
+
+        let curve: Vec<f64> = serde_json::from_value(report["loss_curve"].clone()).unwrap();
+        assert_eq!(curve.len(), 100);
+        assert!(curve[99] < curve[0]);
+    }
+
+    fn test_gbdt_train_threads() {
+        let training_data = fs::read("fixtures/functions/gbdt_training/train.txt").unwrap();
+        let train = |num_threads: usize| {
+            let mut arguments: serde_json::Value =
+                serde_json::from_str(&training_arguments().into_string()).unwrap();
+            arguments["num_threads"] = json!(num_threads);
+            let arguments = FunctionArguments::from_json(arguments).unwrap();
+            let runtime = RawIoRuntime::new(
+                StagedFiles::from_memory(hashmap!(IN_DATA => training_data.clone())),
+                StagedFiles::from_memory(hashmap!(
+                    OUT_MODEL => Vec::new(),
+                    OUT_REPORT => Vec::new(),
+                )),
+            )
+            .with_environment(ExecutionEnvironment::new().max_parallelism(4));
+            let outputs = runtime.output_buffers();
+            GbdtTrain::new().run(arguments, Box::new(runtime)).unwrap();
+            let report: serde_json::Value =
+                serde_json::from_slice(&outputs.get(OUT_REPORT).unwrap()).unwrap();
+            let curve: Vec<u64> = report["loss_curve"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|loss| loss.as_f64().unwrap().to_bits())
+                .collect();
+            (outputs.get(OUT_MODEL).unwrap(), curve)
+        };
+
+        let (model, curve) = train(1);
+        assert_eq!(curve.len(), 100);
+        for num_threads in 2..=4 {
+            assert_eq!(train(num_threads), (model.clone(), curve.clone()));
+        }
+    }
+
     fn test_gbdt_parse_training_data() {
         let line = "4.8,3.0,1.4,0.3,3.0";
         let result = parse_data_line(line, 4);
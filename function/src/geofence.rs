@@ -0,0 +1,595 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{
+    check_format, open_sniffed, split_fields, unquote, DetectedFormat, ExpectedFormat,
+};
+use serde_json::{json, Value};
+use std::format;
+use std::io::{BufRead, BufReader, Read, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_POINTS: &str = "points";
+const IN_FENCES: &str = "fences";
+const OUT_COUNTS: &str = "counts";
+
+#[derive(Default)]
+pub struct GeoFence;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeoFenceArguments {
+    /// Whether to list the ids of the points inside each fence. Points without an id column are
+    /// listed by their row number.
+    #[serde(default)]
+    include_ids: bool,
+    /// Most ids listed over all fences, so that the listed ids stay in memory. Further ids are
+    /// left out and the fences they belong to are marked as truncated.
+    #[serde(default = "default_max_ids")]
+    max_ids: u64,
+    /// Whether an edge spanning more than 180 degrees of longitude crosses the antimeridian.
+    /// RFC 7946 asks for such polygons to be split there instead, so edges are taken as
+    /// written unless this is set.
+    #[serde(default)]
+    unwrap_antimeridian: bool,
+    /// Number of rows with malformed coordinates which are skipped before the input is
+    /// rejected.
+    #[serde(default = "default_max_malformed")]
+    max_malformed: u64,
+    /// Whether the first row of the points holds column names. Detected if left out.
+    #[serde(default)]
+    header: Option<bool>,
+}
+
+fn default_max_malformed() -> u64 {
+    100
+}
+
+fn default_max_ids() -> u64 {
+    10_000
+}
+
+/// A polygon with its rings as (longitude, latitude) pairs: the outer ring, then the holes. A
+/// polygon crossing the antimeridian has longitudes beyond 180 or -180.
+struct Polygon {
+    rings: Vec<Vec<(f64, f64)>>,
+}
+
+impl Polygon {
+    /// Takes the rings as parsed, the outer ring first. With `unwrap`, the longitudes of each
+    /// ring are unwrapped, so that no edge spans more than 180 degrees. The holes are then
+    /// shifted by full turns towards the outer ring, so that a hole written within -180 to 180
+    /// still cuts into an outer ring beyond 180.
+    fn new(mut rings: Vec<Vec<(f64, f64)>>, unwrap: bool) -> Self {
+        if unwrap {
+            rings = rings.into_iter().map(unwrap_longitudes).collect();
+        }
+        let (west, east) = rings[0].iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(west, east), &(lon, _)| (west.min(lon), east.max(lon)),
+        );
+        let reference = (west + east) / 2.0;
+        for hole in &mut rings[1..] {
+            let shift = 360.0 * ((reference - hole[0].0) / 360.0).round();
+            for (lon, _) in hole.iter_mut() {
+                *lon += shift;
+            }
+        }
+        Self { rings }
+    }
+
+    /// Whether the point is inside the outer ring and outside all holes. The point is tried at
+    /// its longitude shifted by a full turn either way too, to meet unwrapped polygons.
+    fn contains(&self, lon: f64, lat: f64) -> bool {
+        [lon, lon + 360.0, lon - 360.0].iter().any(|&lon| {
+            ring_contains(&self.rings[0], lon, lat)
+                && !self.rings[1..]
+                    .iter()
+                    .any(|hole| ring_contains(hole, lon, lat))
+        })
+    }
+}
+
+/// Shifts the longitudes of a ring by full turns, so that consecutive vertices are at most 180
+/// degrees apart.
+fn unwrap_longitudes(ring: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    let mut unwrapped: Vec<(f64, f64)> = Vec::with_capacity(ring.len());
+    for (lon, lat) in ring {
+        let lon = match unwrapped.last() {
+            Some(&(previous, _)) => lon + 360.0 * ((previous - lon) / 360.0).round(),
+            None => lon,
+        };
+        unwrapped.push((lon, lat));
+    }
+    unwrapped
+}
+
+/// Even-odd test of a point against a ring, casting a ray towards increasing longitudes.
+fn ring_contains(ring: &[(f64, f64)], lon: f64, lat: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (lon_i, lat_i) = ring[i];
+        let (lon_j, lat_j) = ring[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// A named geofence: one polygon, or several for a MultiPolygon.
+struct Fence {
+    name: String,
+    polygons: Vec<Polygon>,
+    count: u64,
+    ids: Vec<String>,
+    ids_truncated: bool,
+}
+
+impl Fence {
+    fn contains(&self, lon: f64, lat: f64) -> bool {
+        self.polygons
+            .iter()
+            .any(|polygon| polygon.contains(lon, lat))
+    }
+}
+
+fn invalid_fences(reason: impl ToString) -> FunctionError {
+    FunctionError::invalid_input_data(IN_FENCES, reason)
+}
+
+/// Reads the fences of a GeoJSON FeatureCollection, Feature or bare geometry. Features are named
+/// by their "name" property or their id, and otherwise by their position.
+fn parse_fences(geojson: &Value, unwrap: bool) -> Result<Vec<Fence>, FunctionError> {
+    let features = match geojson["type"].as_str() {
+        Some("FeatureCollection") => geojson["features"]
+            .as_array()
+            .ok_or_else(|| invalid_fences("the FeatureCollection has no features array"))?
+            .iter()
+            .collect(),
+        _ => vec![geojson],
+    };
+    let mut fences = Vec::with_capacity(features.len());
+    for (i, feature) in features.into_iter().enumerate() {
+        let (geometry, name) = match feature["type"].as_str() {
+            Some("Feature") => {
+                let name = match (&feature["properties"]["name"], &feature["id"]) {
+                    (Value::String(name), _) | (_, Value::String(name)) => name.clone(),
+                    (_, Value::Number(id)) => id.to_string(),
+                    _ => format!("fence {}", i),
+                };
+                (&feature["geometry"], name)
+            }
+            _ => (feature, format!("fence {}", i)),
+        };
+        let polygons = match geometry["type"].as_str() {
+            Some("Polygon") => vec![parse_polygon(&geometry["coordinates"], &name, unwrap)?],
+            Some("MultiPolygon") => geometry["coordinates"]
+                .as_array()
+                .ok_or_else(|| invalid_fences(format!("{} has no coordinates", name)))?
+                .iter()
+                .map(|polygon| parse_polygon(polygon, &name, unwrap))
+                .collect::<Result<_, _>>()?,
+            other => {
+                return Err(invalid_fences(format!(
+                    "{} is a {}, not a Polygon or MultiPolygon",
+                    name,
+                    other.unwrap_or("geometry without a type")
+                )))
+            }
+        };
+        fences.push(Fence {
+            name,
+            polygons,
+            count: 0,
+            ids: Vec::new(),
+            ids_truncated: false,
+        });
+    }
+    Ok(fences)
+}
+
+fn parse_polygon(coordinates: &Value, name: &str, unwrap: bool) -> Result<Polygon, FunctionError> {
+    let invalid = || invalid_fences(format!("{} has malformed coordinates", name));
+    let rings = coordinates.as_array().ok_or_else(invalid)?;
+    if rings.is_empty() {
+        return Err(invalid());
+    }
+    let mut parsed = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let mut positions = Vec::new();
+        for position in ring.as_array().ok_or_else(invalid)? {
+            match position.as_array().map(Vec::as_slice) {
+                Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+                    (Some(lon), Some(lat)) => positions.push((lon, lat)),
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        // GeoJSON rings repeat their first position at the end, which the test does not need.
+        if positions.len() > 1 && positions.first() == positions.last() {
+            positions.pop();
+        }
+        if positions.len() < 3 {
+            return Err(invalid_fences(format!(
+                "{} has a ring with less than three positions",
+                name
+            )));
+        }
+        parsed.push(positions);
+    }
+    Ok(Polygon::new(parsed, unwrap))
+}
+
+/// Parses a row of the points as (latitude, longitude, id). Returns None for rows without valid
+/// coordinates.
+fn parse_point(fields: &[&str]) -> Option<(f64, f64, Option<String>)> {
+    let coordinate = |i: usize| -> Option<f64> {
+        let value = unquote(fields.get(i)?.trim()).trim().parse::<f64>().ok()?;
+        Some(value).filter(|value| value.is_finite())
+    };
+    let lat = coordinate(0).filter(|lat| (-90.0..=90.0).contains(lat))?;
+    let lon = coordinate(1).filter(|lon| (-180.0..=180.0).contains(lon))?;
+    let id = fields.get(2).map(|id| unquote(id.trim()).to_string());
+    Some((lat, lon, id))
+}
+
+impl GeoFence {
+    pub const NAME: &'static str = "builtin-geofence";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("include_ids", ArgumentType::Boolean)
+                    .description("Whether to list the ids of the points inside each fence"),
+            )
+            .argument(
+                ArgumentSpec::new("max_ids", ArgumentType::Integer)
+                    .minimum(0.0)
+                    .description("Most ids listed over all fences, 10000 by default"),
+            )
+            .argument(
+                ArgumentSpec::new("unwrap_antimeridian", ArgumentType::Boolean).description(
+                    "Whether edges spanning more than 180 degrees cross the antimeridian",
+                ),
+            )
+            .argument(
+                ArgumentSpec::new("max_malformed", ArgumentType::Integer)
+                    .minimum(0.0)
+                    .description("Rows with malformed coordinates to skip, 100 by default"),
+            )
+            .argument(
+                ArgumentSpec::new("header", ArgumentType::Boolean)
+                    .description("Whether the first row holds column names, detected by default"),
+            )
+    }
+
+    /// Counts the points of a CSV input of latitude, longitude and an optional id inside each
+    /// polygon of a GeoJSON input, and writes the counts as JSON. Polygons may have holes and
+    /// may cross the antimeridian, either split there as RFC 7946 asks, with longitudes beyond
+    /// 180, or with an edge jumping from one side to the other if `unwrap_antimeridian` is set.
+    /// The fences are held in memory, the points are streamed.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: GeoFenceArguments = arguments.into_typed()?;
+
+        let mut geojson = Vec::new();
+        runtime.open_input(IN_FENCES)?.read_to_end(&mut geojson)?;
+        let geojson: Value = serde_json::from_slice(&geojson)
+            .map_err(|e| invalid_fences(format!("not valid JSON: {}", e)))?;
+        let mut fences = parse_fences(&geojson, args.unwrap_antimeridian)?;
+
+        let (detected, points) = open_sniffed(runtime.as_ref(), IN_POINTS)?;
+        check_format(Self::NAME, IN_POINTS, detected, ExpectedFormat::Csv)?;
+        let (delimiter, sniffed_header) = match detected {
+            DetectedFormat::Csv { delimiter, header } => (delimiter, header),
+            _ => (',', false),
+        };
+        let header = args.header.unwrap_or(sniffed_header);
+
+        let cancellation = runtime.cancellation();
+        let mut total = 0u64;
+        let mut inside_any = 0u64;
+        let mut malformed = 0u64;
+        let mut listed_ids = 0u64;
+        let mut lines = BufReader::new(points).lines();
+        if header {
+            lines.next().transpose()?;
+        }
+        let first_row = if header { 2 } else { 1 };
+        for (i, line) in lines.enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row = i + first_row;
+            let (lat, lon, id) = match parse_point(&split_fields(line, delimiter)) {
+                Some(point) => point,
+                None => {
+                    malformed += 1;
+                    if malformed > args.max_malformed {
+                        return Err(FunctionError::invalid_input_data(
+                            IN_POINTS,
+                            format!(
+                                "more than {} rows with malformed coordinates, the last in row {}",
+                                args.max_malformed, row
+                            ),
+                        ));
+                    }
+                    continue;
+                }
+            };
+            total += 1;
+            let mut inside = false;
+            for fence in fences.iter_mut().filter(|fence| fence.contains(lon, lat)) {
+                inside = true;
+                fence.count += 1;
+                if !args.include_ids {
+                    continue;
+                }
+                if listed_ids < args.max_ids {
+                    listed_ids += 1;
+                    fence
+                        .ids
+                        .push(id.clone().unwrap_or_else(|| row.to_string()));
+                } else {
+                    fence.ids_truncated = true;
+                }
+            }
+            if inside {
+                inside_any += 1;
+            }
+        }
+
+        let counts: Vec<Value> = fences
+            .iter()
+            .map(|fence| {
+                let mut counts = json!({"name": fence.name, "count": fence.count});
+                if args.include_ids {
+                    counts["ids"] = json!(fence.ids);
+                    counts["ids_truncated"] = json!(fence.ids_truncated);
+                }
+                counts
+            })
+            .collect();
+        let counts = json!({
+            "fences": counts,
+            "points": total,
+            "inside_any": inside_any,
+            "malformed": malformed,
+        });
+        let counts = serde_json::to_vec(&counts).map_err(anyhow::Error::from)?;
+        let mut output = runtime.create_output(OUT_COUNTS)?;
+        output.write_all(&counts)?;
+        output.flush()?;
+
+        let mut summary = FunctionSummary::new(format!(
+            "{} of {} points inside {} fences ({} malformed rows skipped)",
+            inside_any,
+            total,
+            fences.len(),
+            malformed
+        ))
+        .metric("points", total as f64)
+        .metric("inside_any", inside_any as f64)
+        .metric("malformed", malformed as f64)
+        .output(OUT_COUNTS, OutputInfo::new(counts.len() as u64));
+        for fence in &fences {
+            summary = summary.metric(format!("fence.{}", fence.name), fence.count as f64);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::untrusted::fs;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_geofence_counts_with_hole,
+            test_geofence_antimeridian,
+            test_geofence_unwrap_antimeridian,
+            test_geofence_hole_across_antimeridian,
+            test_geofence_include_ids,
+            test_geofence_max_ids,
+            test_geofence_malformed_rows,
+        )
+    }
+
+    fn geofence(arguments: Value, points: &str) -> Result<(FunctionSummary, Value), FunctionError> {
+        let fences = fs::read("fixtures/functions/geofence/fences.geojson").unwrap();
+        geofence_with(fences, arguments, points)
+    }
+
+    fn geofence_with(
+        fences: Vec<u8>,
+        arguments: Value,
+        points: &str,
+    ) -> Result<(FunctionSummary, Value), FunctionError> {
+        let (summary, outputs) = run_builtin!(
+            GeoFence::new(),
+            arguments,
+            hashmap!(IN_POINTS => points, IN_FENCES => fences),
+            [OUT_COUNTS]
+        );
+        let output = serde_json::from_slice(&outputs[OUT_COUNTS]).unwrap();
+        Ok((summary?, output))
+    }
+
+    fn count(counts: &Value, name: &str) -> u64 {
+        counts["fences"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|fence| fence["name"] == name)
+            .unwrap()["count"]
+            .as_u64()
+            .unwrap()
+    }
+
+    fn test_geofence_counts_with_hole() {
+        // The depot spans 0 to 10 degrees with a hole from 4 to 6, the yard is a triangle
+        // overlapping its corner.
+        let points = "lat,lon,id\n\
+                      1,1,a\n\
+                      5,5,in-hole\n\
+                      9,9,b\n\
+                      5,2,c\n\
+                      4.5,5.5,in-hole\n\
+                      -1,-1,yard\n\
+                      20,20,nowhere\n";
+        let (summary, counts) = geofence(json!({}), points).unwrap();
+
+        assert_eq!(
+            summary.message,
+            "4 of 7 points inside 4 fences (0 malformed rows skipped)"
+        );
+        assert_eq!(count(&counts, "depot"), 3);
+        assert_eq!(count(&counts, "yard"), 2);
+        assert_eq!(summary.metrics["fence.depot"], 3.0);
+        assert_eq!(counts["points"], 7);
+        assert_eq!(counts["inside_any"], 4);
+        // Without the flag no ids are listed.
+        assert!(counts["fences"][0].get("ids").is_none());
+    }
+
+    fn test_geofence_antimeridian() {
+        // "pacific" is split at the antimeridian, "date-line" uses longitudes up to 190.
+        let points = "0,175\n0,-175\n0,180\n0,-180\n5,165\n-5,-165\n1,0.5\n";
+        let (_, counts) = geofence(json!({}), points).unwrap();
+        assert_eq!(count(&counts, "pacific"), 4);
+        assert_eq!(count(&counts, "date-line"), 4);
+        assert_eq!(count(&counts, "depot"), 1);
+        assert_eq!(counts["inside_any"], 5);
+    }
+
+    fn test_geofence_unwrap_antimeridian() {
+        // The edges jump from 170 to -170 degrees.
+        let fences = json!({
+            "type": "Polygon",
+            "coordinates": [[[170, -10], [-170, -10], [-170, 10], [170, 10], [170, -10]]]
+        });
+        let fences = serde_json::to_vec(&fences).unwrap();
+        let points = "0,175
+0,-175
+0,0
+";
+
+        // As RFC 7946 reads them, the edges span the prime meridian.
+        let (_, counts) = geofence_with(fences.clone(), json!({}), points).unwrap();
+        assert_eq!(counts["inside_any"], 1);
+
+        let (_, counts) =
+            geofence_with(fences, json!({"unwrap_antimeridian": true}), points).unwrap();
+        assert_eq!(counts["inside_any"], 2);
+    }
+
+    fn test_geofence_hole_across_antimeridian() {
+        // The outer rings are beyond 180 degrees, the holes are written within -180 to 180.
+        let hole = vec![(-178.0, -2.0), (-176.0, -2.0), (-176.0, 2.0), (-178.0, 2.0)];
+        let beyond = vec![(172.0, -8.0), (190.0, -8.0), (190.0, 8.0), (172.0, 8.0)];
+        let polygon = Polygon::new(vec![beyond, hole.clone()], false);
+        assert!(!polygon.contains(-177.0, 0.0));
+        assert!(polygon.contains(-172.0, 0.0));
+        assert!(polygon.contains(175.0, 0.0));
+
+        let jumping = vec![
+            (170.0, -10.0),
+            (-170.0, -10.0),
+            (-170.0, 10.0),
+            (170.0, 10.0),
+        ];
+        let polygon = Polygon::new(vec![jumping, hole], true);
+        assert!(!polygon.contains(-177.0, 0.0));
+        assert!(polygon.contains(-172.0, 0.0));
+        assert!(polygon.contains(175.0, 0.0));
+    }
+
+    fn test_geofence_include_ids() {
+        let points = "lat,lon,id\n1,1,a\n5,5,b\n-1,-1,c\n";
+        let (_, counts) = geofence(json!({"include_ids": true}), points).unwrap();
+        let ids = |name: &str| {
+            counts["fences"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|fence| fence["name"] == name)
+                .unwrap()["ids"]
+                .clone()
+        };
+        assert_eq!(ids("depot"), json!(["a"]));
+        assert_eq!(ids("yard"), json!(["a", "c"]));
+        assert_eq!(ids("pacific"), json!([]));
+
+        // Points without ids are listed by row.
+        let (_, counts) = geofence(json!({"include_ids": true}), "0,175\n1,1\n").unwrap();
+        assert_eq!(counts["fences"][0]["ids"], json!(["2"]));
+        assert_eq!(counts["fences"][0]["ids_truncated"], false);
+    }
+
+    fn test_geofence_max_ids() {
+        // The first point is inside the depot and the yard, so only the depot lists it.
+        let points = "lat,lon,id
+1,1,a
+-1,-1,c
+";
+        let (_, counts) = geofence(json!({"include_ids": true, "max_ids": 1}), points).unwrap();
+        assert_eq!(counts["fences"][0]["ids"], json!(["a"]));
+        assert_eq!(counts["fences"][0]["ids_truncated"], false);
+        assert_eq!(counts["fences"][1]["ids"], json!([]));
+        assert_eq!(counts["fences"][1]["ids_truncated"], true);
+        assert_eq!(count(&counts, "yard"), 2);
+    }
+
+    fn test_geofence_malformed_rows() {
+        let points = "1,1\nnorth,1\n95,0\n1\n1,200\n2,2\n";
+        let (summary, counts) = geofence(json!({"header": false}), points).unwrap();
+        assert_eq!(counts["malformed"], 4);
+        assert_eq!(counts["points"], 2);
+        assert_eq!(summary.metrics["malformed"], 4.0);
+
+        let err = geofence(json!({"header": false, "max_malformed": 3}), points).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input points: more than 3 rows with malformed coordinates, the \
+             last in row 5"
+        );
+    }
+}
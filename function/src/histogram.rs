@@ -0,0 +1,503 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{
+    check_format, open_sniffed, split_fields, unquote, DetectedFormat, ExpectedFormat,
+};
+use serde_json::json;
+use std::format;
+use std::io::{BufRead, BufReader, Read, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, TeaclaveRuntime, CANCELLATION_CHECK_INTERVAL,
+};
+
+#[derive(Default)]
+pub struct Histogram;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HistogramArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    /// Zero-based index of the column to bucket.
+    column: usize,
+    #[serde(default)]
+    bins: Bins,
+    /// Range of equal-width bins. Left out or null, it is found in a first pass over the input.
+    #[serde(default)]
+    min: Option<f64>,
+    #[serde(default)]
+    max: Option<f64>,
+    /// Whether the first row holds column names. Detected from the input if left out.
+    #[serde(default)]
+    header: Option<bool>,
+    /// "skip" counts and leaves out cells which are not numbers, "error" fails on them.
+    #[serde(default = "default_non_numeric")]
+    non_numeric: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Bins {
+    /// Number of equal-width bins.
+    Count(usize),
+    /// Edges of the bins in increasing order; n edges make n - 1 bins.
+    Edges(Vec<f64>),
+}
+
+impl Default for Bins {
+    fn default() -> Self {
+        Bins::Count(20)
+    }
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+fn default_non_numeric() -> String {
+    "skip".to_string()
+}
+
+/// Reads the values of one column of a CSV input.
+struct ColumnReader<'a> {
+    runtime: &'a dyn TeaclaveRuntime,
+    input: &'a str,
+    column: usize,
+    delimiter: char,
+    header: bool,
+    skip_non_numeric: bool,
+}
+
+impl ColumnReader<'_> {
+    /// Streams the input once and calls `f` on every numeric value of the column. Returns the
+    /// number of non-numeric cells, which are empty, unparsable or not finite.
+    fn for_each(&self, input: Box<dyn Read>, mut f: impl FnMut(f64)) -> Result<u64, FunctionError> {
+        let cancellation = self.runtime.cancellation();
+        let mut non_numeric = 0;
+        let mut lines = BufReader::new(input).lines();
+        if self.header {
+            lines.next().transpose()?;
+        }
+        let first_row = if self.header { 2 } else { 1 };
+        for (i, line) in lines.enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row = i + first_row;
+            let fields = split_fields(line, self.delimiter);
+            let field = fields.get(self.column).ok_or_else(|| {
+                FunctionError::invalid_input_data(
+                    self.input,
+                    format!(
+                        "row {} has {} fields, no column {}",
+                        row,
+                        fields.len(),
+                        self.column
+                    ),
+                )
+            })?;
+            let field = unquote(field.trim()).trim();
+            match field.parse::<f64>() {
+                Ok(value) if value.is_finite() => f(value),
+                _ if self.skip_non_numeric => non_numeric += 1,
+                _ => {
+                    return Err(FunctionError::invalid_input_data(
+                        self.input,
+                        format!("row {} has the non-numeric value '{}'", row, field),
+                    ))
+                }
+            }
+        }
+        Ok(non_numeric)
+    }
+}
+
+/// Index of the bin of `value` among the bins between `edges`, or `None` outside of them. Bins
+/// include their lower edge, the last one also its upper edge.
+fn bin_index(edges: &[f64], value: f64) -> Option<usize> {
+    let last = edges.len() - 1;
+    if value < edges[0] || value > edges[last] {
+        return None;
+    }
+    let upper = edges.partition_point(|&edge| edge <= value);
+    Some(upper.min(last) - 1)
+}
+
+impl Histogram {
+    pub const NAME: &'static str = "builtin-histogram";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("input", ArgumentType::String)
+                    .description("Identifier of the input, \"input\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("output", ArgumentType::String)
+                    .description("Identifier of the output, \"output\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("column", ArgumentType::Integer)
+                    .required()
+                    .minimum(0.0)
+                    .description("Zero-based index of the numeric column"),
+            )
+            .argument(ArgumentSpec::any("bins").description(
+                "Number of equal-width bins, 20 by default, or an array of increasing bin edges",
+            ))
+            .argument(
+                ArgumentSpec::any("min")
+                    .description("Lower edge of equal-width bins, or null for the smallest value"),
+            )
+            .argument(
+                ArgumentSpec::any("max")
+                    .description("Upper edge of equal-width bins, or null for the largest value"),
+            )
+            .argument(
+                ArgumentSpec::new("header", ArgumentType::Boolean)
+                    .description("Whether the first row holds column names, detected by default"),
+            )
+            .argument(
+                ArgumentSpec::new("non_numeric", ArgumentType::String)
+                    .one_of(&["skip", "error"])
+                    .description("\"skip\" leaves out non-numeric cells, \"error\" fails on them"),
+            )
+    }
+
+    /// Counts the values of a column of a CSV input in bins, and writes the bins and their
+    /// counts as JSON. Equal-width bins span `min` to `max`; a missing bound is found in a first
+    /// pass over the input, so the input is read twice but never held in memory. If all values
+    /// are equal, the histogram has a single bin holding all of them. Values outside explicit
+    /// bounds or edges are counted as below or above the histogram.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: HistogramArguments = arguments.into_typed()?;
+        let skip_non_numeric = match args.non_numeric.as_str() {
+            "skip" => true,
+            "error" => false,
+            _ => {
+                return Err(FunctionError::invalid_arguments(
+                    "non_numeric must be \"skip\" or \"error\"",
+                ))
+            }
+        };
+        match &args.bins {
+            Bins::Count(0) => {
+                return Err(FunctionError::invalid_arguments("bins must be at least 1"))
+            }
+            Bins::Edges(edges) => {
+                if edges.len() < 2 {
+                    return Err(FunctionError::invalid_arguments(
+                        "bins needs at least two edges",
+                    ));
+                }
+                if edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(FunctionError::invalid_arguments(
+                        "bin edges must be strictly increasing",
+                    ));
+                }
+                if args.min.is_some() || args.max.is_some() {
+                    return Err(FunctionError::invalid_arguments(
+                        "min and max cannot be combined with bin edges",
+                    ));
+                }
+            }
+            Bins::Count(_) => {}
+        }
+        if let (Some(min), Some(max)) = (args.min, args.max) {
+            if min >= max {
+                return Err(FunctionError::invalid_arguments(
+                    "min must be less than max",
+                ));
+            }
+        }
+
+        let (detected, input) = open_sniffed(runtime.as_ref(), &args.input)?;
+        check_format(Self::NAME, &args.input, detected, ExpectedFormat::Csv)?;
+        let (delimiter, sniffed_header) = match detected {
+            DetectedFormat::Csv { delimiter, header } => (delimiter, header),
+            _ => (',', false),
+        };
+        let reader = ColumnReader {
+            runtime: runtime.as_ref(),
+            input: &args.input,
+            column: args.column,
+            delimiter,
+            header: args.header.unwrap_or(sniffed_header),
+            skip_non_numeric,
+        };
+
+        let edges = match args.bins {
+            Bins::Edges(edges) => Some((edges, input)),
+            Bins::Count(count) => match (args.min, args.max) {
+                (Some(min), Some(max)) => Some((equal_width_edges(min, max, count), input)),
+                (min, max) => {
+                    let mut range: Option<(f64, f64)> = None;
+                    reader.for_each(input, |value| {
+                        range = Some(match range {
+                            Some((min, max)) => (min.min(value), max.max(value)),
+                            None => (value, value),
+                        })
+                    })?;
+                    let min = min.or_else(|| range.map(|(min, _)| min));
+                    let max = max.or_else(|| range.map(|(_, max)| max));
+                    match (min, max) {
+                        (Some(min), Some(max)) if min < max => Some((
+                            equal_width_edges(min, max, count),
+                            runtime.open_input(&args.input)?,
+                        )),
+                        // All values are equal, so they share a single bin.
+                        (Some(min), Some(max)) if min == max => {
+                            Some((vec![min, max], runtime.open_input(&args.input)?))
+                        }
+                        (Some(min), Some(max)) => {
+                            return Err(FunctionError::invalid_arguments(format!(
+                                "the range from {} to {} is empty",
+                                min, max
+                            )))
+                        }
+                        // The column holds no values.
+                        _ => None,
+                    }
+                }
+            },
+        };
+
+        let mut counts = Vec::new();
+        let mut below = 0u64;
+        let mut above = 0u64;
+        let mut non_numeric = 0;
+        let edges = match edges {
+            Some((edges, input)) => {
+                counts = vec![0u64; edges.len() - 1];
+                non_numeric = reader.for_each(input, |value| match bin_index(&edges, value) {
+                    Some(bin) => counts[bin] += 1,
+                    None if value < edges[0] => below += 1,
+                    None => above += 1,
+                })?;
+                edges
+            }
+            None => Vec::new(),
+        };
+
+        let values: u64 = counts.iter().sum();
+        let bins: Vec<_> = counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| json!({"lower": edges[i], "upper": edges[i + 1], "count": count}))
+            .collect();
+        let histogram = json!({
+            "column": args.column,
+            "bins": bins,
+            "count": values,
+            "below": below,
+            "above": above,
+            "non_numeric": non_numeric,
+        });
+        let histogram = serde_json::to_vec(&histogram).map_err(anyhow::Error::from)?;
+        let mut output = runtime.create_output(&args.output)?;
+        output.write_all(&histogram)?;
+        output.flush()?;
+
+        let summary = FunctionSummary::new(format!(
+            "Histogram of column {} with {} bins over {} values ({} below, {} above, {} \
+             non-numeric)",
+            args.column,
+            bins.len(),
+            values,
+            below,
+            above,
+            non_numeric
+        ))
+        .metric("values", values as f64)
+        .metric("bins", bins.len() as f64)
+        .metric("below", below as f64)
+        .metric("above", above as f64)
+        .metric("non_numeric", non_numeric as f64)
+        .output(args.output, OutputInfo::new(histogram.len() as u64));
+        Ok(summary)
+    }
+}
+
+/// Edges of `count` bins of equal width from `min` to `max`. The last edge is `max` itself, so
+/// that rounding cannot leave the largest value outside of the last bin.
+fn equal_width_edges(min: f64, max: f64, count: usize) -> Vec<f64> {
+    let width = (max - min) / count as f64;
+    let mut edges: Vec<f64> = (0..count).map(|i| min + width * i as f64).collect();
+    edges.push(max);
+    edges
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::Value;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_histogram_known_distribution,
+            test_histogram_edges_and_bounds,
+            test_histogram_equal_values,
+            test_histogram_non_numeric,
+        )
+    }
+
+    fn histogram(arguments: Value, input: &str) -> Result<(FunctionSummary, Value), FunctionError> {
+        let (summary, outputs) = run_builtin!(
+            Histogram::new(),
+            arguments,
+            hashmap!("input" => input),
+            ["output"]
+        );
+        let output = serde_json::from_slice(&outputs["output"]).unwrap();
+        Ok((summary?, output))
+    }
+
+    fn counts(histogram: &Value) -> Vec<u64> {
+        histogram["bins"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bin| bin["count"].as_u64().unwrap())
+            .collect()
+    }
+
+    fn test_histogram_known_distribution() {
+        // Value k appears k times for k = 1..=10, so every bin of width 1 holds its own value.
+        let mut input = "id,name,score\n".to_string();
+        for k in 1..=10 {
+            for _ in 0..k {
+                input.push_str(&format!("{},row{},{}\n", k, k, k));
+            }
+        }
+        let (summary, histogram) = histogram(
+            json!({"column": 2, "bins": 9, "min": null, "max": null}),
+            &input,
+        )
+        .unwrap();
+
+        assert_eq!(
+            summary.message,
+            "Histogram of column 2 with 9 bins over 55 values (0 below, 0 above, 0 non-numeric)"
+        );
+        assert_eq!(summary.metrics["values"], 55.0);
+        assert_eq!(histogram["count"], 55);
+        assert_eq!(counts(&histogram), vec![1, 2, 3, 4, 5, 6, 7, 8, 9 + 10]);
+        let bins = histogram["bins"].as_array().unwrap();
+        assert_eq!(bins[0]["lower"], 1.0);
+        assert_eq!(bins[0]["upper"], 2.0);
+        // The largest value falls into the last bin, which includes its upper edge.
+        assert_eq!(bins[8]["lower"], 9.0);
+        assert_eq!(bins[8]["upper"], 10.0);
+    }
+
+    fn test_histogram_edges_and_bounds() {
+        let input = "x\n-5\n0\n0.5\n1\n2.5\n7\n10\n12\n";
+        let (_, edges) = histogram(json!({"column": 0, "bins": [0, 1, 5, 10]}), input).unwrap();
+        assert_eq!(counts(&edges), vec![2, 2, 2]);
+        assert_eq!(edges["below"], 1);
+        assert_eq!(edges["above"], 1);
+        assert_eq!(edges["bins"][1]["lower"], 1.0);
+        assert_eq!(edges["bins"][1]["upper"], 5.0);
+
+        let arguments = json!({"column": 0, "bins": 2, "min": 0, "max": 10});
+        let (summary, bounded) = histogram(arguments, input).unwrap();
+        assert_eq!(counts(&bounded), vec![4, 2]);
+        assert_eq!(summary.metrics["below"], 1.0);
+        assert_eq!(summary.metrics["above"], 1.0);
+
+        // Only the missing bound comes from the data.
+        let (_, half) = histogram(json!({"column": 0, "bins": 2, "min": 0}), input).unwrap();
+        assert_eq!(counts(&half), vec![4, 3]);
+        assert_eq!(half["below"], 1);
+        assert_eq!(half["bins"][1]["upper"], 12.0);
+
+        let err = histogram(json!({"column": 0, "bins": [1, 1]}), input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: bin edges must be strictly increasing"
+        );
+        let err = histogram(json!({"column": 0, "min": 3, "max": 3}), input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: min must be less than max"
+        );
+        let err = histogram(json!({"column": 0, "bins": 0}), input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: bins must be at least 1"
+        );
+    }
+
+    fn test_histogram_equal_values() {
+        let input = "a,b\n1,4.5\n2,4.5\n3,4.5\n";
+        let (summary, histogram) = histogram(json!({"column": 1}), input).unwrap();
+        assert_eq!(summary.metrics["bins"], 1.0);
+        assert_eq!(counts(&histogram), vec![3]);
+        assert_eq!(histogram["bins"][0]["lower"], 4.5);
+        assert_eq!(histogram["bins"][0]["upper"], 4.5);
+
+        // A column without any values has no bins.
+        let input = "a,b\n1,\n2,n/a\n";
+        let arguments = json!({"column": 1, "header": true});
+        let (summary, histogram) = histogram(arguments, input).unwrap();
+        assert_eq!(counts(&histogram), Vec::<u64>::new());
+        assert_eq!(summary.metrics["non_numeric"], 2.0);
+    }
+
+    fn test_histogram_non_numeric() {
+        let input = "id,value\n1,3\n2,n/a\n3,\"4\"\n4,inf\n5,1\n";
+        let (summary, histogram) = histogram(json!({"column": 1, "bins": 3}), input).unwrap();
+        assert_eq!(counts(&histogram), vec![1, 0, 2]);
+        assert_eq!(summary.metrics["non_numeric"], 2.0);
+        assert_eq!(histogram["non_numeric"], 2);
+
+        let arguments = json!({"column": 1, "non_numeric": "error"});
+        let err = histogram(arguments, input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: row 3 has the non-numeric value 'n/a'"
+        );
+        let err = histogram(json!({"column": 5}), input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: row 2 has 2 fields, no column 5"
+        );
+    }
+}
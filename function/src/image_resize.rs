@@ -0,0 +1,282 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+extern crate image;
+
+use image::imageops::FilterType;
+use image::ImageOutputFormat;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{Cursor, Read, Write};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary, OutputInfo};
+
+const IN_IMAGE: &str = "image";
+const OUT_THUMBNAIL: &str = "thumbnail";
+
+#[derive(Default)]
+pub struct ImageResize;
+
+#[derive(serde::Deserialize)]
+struct ImageResizeArguments {
+    max_width: u32,
+    max_height: u32,
+    /// Output format, either "png" or "jpeg".
+    #[serde(default = "default_format")]
+    format: String,
+    /// JPEG quality in 1..=100, ignored for PNG.
+    #[serde(default = "default_quality")]
+    quality: u8,
+    /// Images with more pixels than this are rejected before being decoded.
+    #[serde(default = "default_max_input_pixels")]
+    max_input_pixels: u64,
+}
+
+fn default_format() -> String {
+    "png".to_string()
+}
+
+fn default_quality() -> u8 {
+    80
+}
+
+fn default_max_input_pixels() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl TryFrom<FunctionArguments> for ImageResizeArguments {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        use anyhow::Context;
+        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImageResizeError {
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
+    #[error("Corrupt or unsupported image: {0}")]
+    Decode(String),
+    #[error("Image of {width}x{height} exceeds the limit of {max_pixels} pixels")]
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+    #[error("Cannot encode thumbnail: {0}")]
+    Encode(String),
+}
+
+impl ImageResize {
+    pub const NAME: &'static str = "builtin-image-resize";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let args = ImageResizeArguments::try_from(arguments)?;
+        if args.max_width == 0 || args.max_height == 0 {
+            return Err(ImageResizeError::InvalidArguments(
+                "max_width and max_height must be positive".to_string(),
+            )
+            .into());
+        }
+        let output_format = match args.format.as_str() {
+            "png" => ImageOutputFormat::Png,
+            "jpeg" if (1..=100).contains(&args.quality) => ImageOutputFormat::Jpeg(args.quality),
+            "jpeg" => {
+                return Err(ImageResizeError::InvalidArguments(format!(
+                    "quality {} is not in 1..=100",
+                    args.quality
+                ))
+                .into())
+            }
+            f => {
+                return Err(
+                    ImageResizeError::InvalidArguments(format!("unknown format {}", f)).into(),
+                )
+            }
+        };
+
+        let mut input = Vec::new();
+        runtime.open_input(IN_IMAGE)?.read_to_end(&mut input)?;
+
+        // Only the header is parsed here, so oversized images are rejected before allocating
+        // their pixel buffers.
+        let (width, height) = image::io::Reader::new(Cursor::new(&input))
+            .with_guessed_format()?
+            .into_dimensions()
+            .map_err(|e| ImageResizeError::Decode(e.to_string()))?;
+        if u64::from(width) * u64::from(height) > args.max_input_pixels {
+            return Err(ImageResizeError::TooLarge {
+                width,
+                height,
+                max_pixels: args.max_input_pixels,
+            }
+            .into());
+        }
+
+        let img = image::io::Reader::new(Cursor::new(&input))
+            .with_guessed_format()?
+            .decode()
+            .map_err(|e| ImageResizeError::Decode(e.to_string()))?;
+        let thumbnail = if width <= args.max_width && height <= args.max_height {
+            img
+        } else {
+            img.resize(args.max_width, args.max_height, FilterType::Triangle)
+        };
+
+        let mut encoded = Vec::new();
+        thumbnail
+            .write_to(&mut encoded, output_format)
+            .map_err(|e| ImageResizeError::Encode(e.to_string()))?;
+        runtime.create_output(OUT_THUMBNAIL)?.write_all(&encoded)?;
+
+        let summary = FunctionSummary::new(format!(
+            "Resized from {}x{} to {}x{}",
+            width,
+            height,
+            thumbnail.width(),
+            thumbnail.height()
+        ))
+        .metric("bytes_read", input.len() as f64)
+        .metric(
+            "input_pixels",
+            (u64::from(width) * u64::from(height)) as f64,
+        )
+        .metric(
+            "output_pixels",
+            (u64::from(thumbnail.width()) * u64::from(thumbnail.height())) as f64,
+        )
+        .output(OUT_THUMBNAIL, OutputInfo::new(encoded.len() as u64));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::Path;
+    use std::untrusted::fs;
+    use teaclave_crypto::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_image_resize_png,
+            test_image_resize_jpeg,
+            test_image_resize_decompression_bomb,
+            test_image_resize_corrupt_image,
+        )
+    }
+
+    fn run_image_resize(
+        arguments: serde_json::Value,
+        input: &Path,
+    ) -> anyhow::Result<FunctionSummary> {
+        let arguments = FunctionArguments::from_json(arguments).unwrap();
+        let output = Path::new("fixtures/functions/image_resize/thumbnail");
+
+        let input_files = StagedFiles::new(hashmap!(
+            IN_IMAGE =>
+            StagedFileInfo::new(input, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+        let output_files = StagedFiles::new(hashmap!(
+            OUT_THUMBNAIL =>
+            StagedFileInfo::new(output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+        ImageResize::new().run(arguments, runtime)
+    }
+
+    fn read_thumbnail() -> image::DynamicImage {
+        let bytes = fs::read("fixtures/functions/image_resize/thumbnail").unwrap();
+        image::load_from_memory(&bytes).unwrap()
+    }
+
+    fn test_image_resize_png() {
+        let input = Path::new("fixtures/functions/image_resize/input.png");
+        let summary = run_image_resize(
+            json!({"max_width": 32, "max_height": 32, "format": "png"}),
+            input,
+        )
+        .unwrap();
+        assert_eq!(summary.message, "Resized from 64x48 to 32x24");
+        assert_eq!(summary.metrics["input_pixels"], 3072.0);
+        assert_eq!(summary.metrics["output_pixels"], 768.0);
+        assert_eq!(
+            summary.metrics["bytes_read"],
+            fs::metadata(input).unwrap().len() as f64
+        );
+
+        let thumbnail = read_thumbnail();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (32, 24));
+        let size = fs::metadata("fixtures/functions/image_resize/thumbnail")
+            .unwrap()
+            .len();
+        assert_eq!(summary.outputs[OUT_THUMBNAIL], OutputInfo::new(size));
+    }
+
+    fn test_image_resize_jpeg() {
+        let input = Path::new("fixtures/functions/face_detection/input.jpg");
+        let summary = run_image_resize(
+            json!({"max_width": 200, "max_height": 200, "format": "jpeg", "quality": 70}),
+            input,
+        )
+        .unwrap();
+        assert_eq!(summary.message, "Resized from 1666x1136 to 200x136");
+
+        let thumbnail = read_thumbnail();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (200, 136));
+    }
+
+    fn test_image_resize_decompression_bomb() {
+        let input = Path::new("fixtures/functions/image_resize/input.png");
+        let err = run_image_resize(
+            json!({"max_width": 32, "max_height": 32, "max_input_pixels": 1000}),
+            input,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ImageResizeError>(),
+            Some(ImageResizeError::TooLarge {
+                width: 64,
+                height: 48,
+                ..
+            })
+        ));
+    }
+
+    fn test_image_resize_corrupt_image() {
+        let input = Path::new("fixtures/functions/image_resize/corrupt.png");
+        let err = run_image_resize(json!({"max_width": 32, "max_height": 32}), input).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ImageResizeError>(),
+            Some(ImageResizeError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,418 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{open_expecting, ExpectedFormat};
+use anyhow::bail;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+use teaclave_types::{
+    CancellationToken, FunctionArguments, FunctionRuntime, FunctionSummary, OutputInfo,
+    TeaclaveRuntime, CANCELLATION_CHECK_INTERVAL,
+};
+
+#[derive(Default)]
+pub struct Join;
+
+#[derive(serde::Deserialize)]
+struct JoinArguments {
+    #[serde(default = "default_left")]
+    left: String,
+    #[serde(default = "default_right")]
+    right: String,
+    #[serde(default)]
+    left_key: usize,
+    #[serde(default)]
+    right_key: usize,
+    /// "inner" only writes matched rows; "left" also writes unmatched left rows with empty
+    /// right columns.
+    #[serde(rename = "type", default = "default_join_type")]
+    join_type: String,
+    /// "string" compares keys as trimmed text; "integer" compares them as numbers and skips
+    /// rows whose key is not one.
+    #[serde(default = "default_key_type")]
+    key_type: String,
+    #[serde(default = "default_output")]
+    output: String,
+}
+
+fn default_left() -> String {
+    "left".to_string()
+}
+
+fn default_right() -> String {
+    "right".to_string()
+}
+
+fn default_join_type() -> String {
+    "inner".to_string()
+}
+
+fn default_key_type() -> String {
+    "string".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+impl TryFrom<FunctionArguments> for JoinArguments {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        use anyhow::Context;
+        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum KeyType {
+    String,
+    Integer,
+}
+
+/// One of the two inputs of the join.
+struct Side<'a> {
+    input: &'a str,
+    key: usize,
+    is_left: bool,
+}
+
+struct BufferedRow {
+    fields: Vec<String>,
+    matched: bool,
+}
+
+#[derive(Default)]
+struct JoinCounts {
+    matched: usize,
+    unmatched: usize,
+    skipped: usize,
+    buffered: usize,
+    bytes_written: usize,
+}
+
+impl Join {
+    pub const NAME: &'static str = "builtin-join";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Hash join of two comma-separated inputs. One input is held in memory in full, which
+    /// costs roughly its size plus a small overhead per row: the right input for left joins,
+    /// and for inner joins the smaller input if the runtime knows both sizes.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let args = JoinArguments::try_from(arguments)?;
+        let left_join = match args.join_type.as_str() {
+            "inner" => false,
+            "left" => true,
+            _ => bail!("Invalid join type"),
+        };
+        let key_type = match args.key_type.as_str() {
+            "string" => KeyType::String,
+            "integer" => KeyType::Integer,
+            _ => bail!("Invalid key type"),
+        };
+
+        let left = Side {
+            input: &args.left,
+            key: args.left_key,
+            is_left: true,
+        };
+        let right = Side {
+            input: &args.right,
+            key: args.right_key,
+            is_left: false,
+        };
+        let buffer_left = !left_join
+            && match (
+                input_size(&*runtime, &args.left)?,
+                input_size(&*runtime, &args.right)?,
+            ) {
+                (Some(left_size), Some(right_size)) => left_size < right_size,
+                _ => false,
+            };
+        let (buffered, streamed) = if buffer_left {
+            (left, right)
+        } else {
+            (right, left)
+        };
+
+        let cancellation = runtime.cancellation();
+        let mut counts = JoinCounts::default();
+        let mut table: HashMap<String, Vec<BufferedRow>> = HashMap::new();
+        let mut buffered_width = 0;
+        for_each_row(
+            &*runtime,
+            &buffered,
+            key_type,
+            &cancellation,
+            |key, fields| {
+                match key {
+                    Some(key) => {
+                        buffered_width = buffered_width.max(fields.len());
+                        counts.buffered += 1;
+                        table.entry(key).or_default().push(BufferedRow {
+                            fields,
+                            matched: false,
+                        });
+                    }
+                    None => counts.skipped += 1,
+                }
+                Ok(())
+            },
+        )?;
+
+        let mut output = runtime.create_output(&args.output)?;
+        // Unmatched left rows get empty fields in place of the right row without its key.
+        let padding = vec![String::new(); buffered_width.saturating_sub(1)];
+        for_each_row(
+            &*runtime,
+            &streamed,
+            key_type,
+            &cancellation,
+            |key, fields| {
+                let key = match key {
+                    Some(key) => key,
+                    None => {
+                        counts.skipped += 1;
+                        return Ok(());
+                    }
+                };
+                match table.get_mut(&key) {
+                    Some(matches) => {
+                        for row in matches.iter_mut() {
+                            row.matched = true;
+                            counts.bytes_written += if buffered.is_left {
+                                write_joined(&mut output, &row.fields, &fields, streamed.key)?
+                            } else {
+                                write_joined(&mut output, &fields, &row.fields, buffered.key)?
+                            };
+                            counts.matched += 1;
+                        }
+                    }
+                    None if streamed.is_left => {
+                        counts.unmatched += 1;
+                        if left_join {
+                            counts.bytes_written += write_row(&mut output, &fields, &padding)?;
+                        }
+                    }
+                    None => {}
+                }
+                Ok(())
+            },
+        )?;
+        if buffered.is_left {
+            counts.unmatched = table.values().flatten().filter(|row| !row.matched).count();
+        }
+        output.flush()?;
+
+        let summary = FunctionSummary::new(format!(
+            "{} matched rows, {} unmatched rows",
+            counts.matched, counts.unmatched
+        ))
+        .metric("matched_rows", counts.matched as f64)
+        .metric("unmatched_rows", counts.unmatched as f64)
+        .metric("skipped_rows", counts.skipped as f64)
+        .metric("buffered_rows", counts.buffered as f64)
+        .output(args.output, OutputInfo::new(counts.bytes_written as u64));
+        Ok(summary)
+    }
+}
+
+fn input_size(runtime: &dyn TeaclaveRuntime, identifier: &str) -> anyhow::Result<Option<u64>> {
+    match runtime.open_input_random_access(identifier)? {
+        Some(mut file) => Ok(Some(file.size_of()?)),
+        None => Ok(None),
+    }
+}
+
+/// Calls `f` with the key and fields of every non-empty row of the input. The key is `None` for
+/// rows which lack the key column or whose key does not match `key_type`.
+fn for_each_row(
+    runtime: &dyn TeaclaveRuntime,
+    side: &Side,
+    key_type: KeyType,
+    cancellation: &CancellationToken,
+    mut f: impl FnMut(Option<String>, Vec<String>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let input = BufReader::new(open_expecting(
+        runtime,
+        Join::NAME,
+        side.input,
+        ExpectedFormat::Csv,
+    )?);
+    for (index, line) in input.lines().enumerate() {
+        if index % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<String> = line.split(',').map(|f| f.trim().to_string()).collect();
+        let key = fields
+            .get(side.key)
+            .and_then(|key| normalize_key(key, key_type));
+        f(key, fields)?;
+    }
+    Ok(())
+}
+
+fn normalize_key(key: &str, key_type: KeyType) -> Option<String> {
+    match key_type {
+        KeyType::String => Some(key.to_string()),
+        KeyType::Integer => key.parse::<i64>().ok().map(|key| key.to_string()),
+    }
+}
+
+/// Writes the left row followed by the right row without its key column.
+fn write_joined(
+    output: &mut impl Write,
+    left: &[String],
+    right: &[String],
+    right_key: usize,
+) -> anyhow::Result<usize> {
+    let right: Vec<String> = right
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != right_key)
+        .map(|(_, field)| field.clone())
+        .collect();
+    write_row(output, left, &right)
+}
+
+fn write_row(output: &mut impl Write, left: &[String], right: &[String]) -> anyhow::Result<usize> {
+    let line = left
+        .iter()
+        .chain(right.iter())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(output, "{}", line)?;
+    Ok(line.len() + 1)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_inner_join,
+            test_inner_join_buffers_smaller_input,
+            test_left_join,
+            test_join_integer_keys,
+            test_join_skips_invalid_rows,
+            test_join_invalid_type,
+            test_join_jsonl_input,
+        )
+    }
+
+    const LEFT: &str = "1,alice\n2,bob\n3,carol\n02,dan\n";
+    const RIGHT: &str = "1,paris\n2,rome\n2,oslo\n4,lima\n";
+
+    fn run_join(
+        arguments: serde_json::Value,
+        left: &str,
+        right: &str,
+    ) -> anyhow::Result<(FunctionSummary, String)> {
+        let (summary, mut outputs) = run_builtin!(
+            Join::new(),
+            arguments,
+            hashmap!("left" => left, "right" => right),
+            ["output"]
+        );
+        let output = outputs.remove("output").unwrap_or_default();
+        Ok((summary?, String::from_utf8(output).unwrap()))
+    }
+
+    fn test_inner_join() {
+        let (summary, output) = run_join(json!({"type": "inner"}), LEFT, RIGHT).unwrap();
+        assert_eq!(summary.message, "3 matched rows, 2 unmatched rows");
+        assert_eq!(output, "1,alice,paris\n2,bob,rome\n2,bob,oslo\n");
+        assert_eq!(summary.metrics["buffered_rows"], 4.0);
+        assert_eq!(summary.metrics["skipped_rows"], 0.0);
+        assert_eq!(
+            summary.outputs["output"],
+            OutputInfo::new(output.len() as u64)
+        );
+    }
+
+    fn test_inner_join_buffers_smaller_input() {
+        let right = format!("{}5,kyiv\n6,bern\n", RIGHT);
+        let (summary, output) = run_join(json!({}), LEFT, &right).unwrap();
+        assert_eq!(summary.message, "3 matched rows, 2 unmatched rows");
+        assert_eq!(summary.metrics["buffered_rows"], 4.0);
+        // Rows come in the order of the streamed right input, columns stay left first.
+        assert_eq!(output, "1,alice,paris\n2,bob,rome\n2,bob,oslo\n");
+    }
+
+    fn test_left_join() {
+        let (summary, output) = run_join(json!({"type": "left"}), LEFT, RIGHT).unwrap();
+        assert_eq!(summary.message, "3 matched rows, 2 unmatched rows");
+        assert_eq!(
+            output,
+            "1,alice,paris\n2,bob,rome\n2,bob,oslo\n3,carol,\n02,dan,\n"
+        );
+    }
+
+    fn test_join_integer_keys() {
+        let (summary, output) =
+            run_join(json!({"type": "left", "key_type": "integer"}), LEFT, RIGHT).unwrap();
+        assert_eq!(summary.message, "5 matched rows, 1 unmatched rows");
+        assert_eq!(
+            output,
+            "1,alice,paris\n2,bob,rome\n2,bob,oslo\n3,carol,\n02,dan,rome\n02,dan,oslo\n"
+        );
+    }
+
+    fn test_join_skips_invalid_rows() {
+        let left = "alice,1\nbob\n\ncarol,x\n";
+        let right = "1,paris\nlima\n";
+        let arguments = json!({"left_key": 1, "right_key": 0, "key_type": "integer"});
+        let (summary, output) = run_join(arguments, left, right).unwrap();
+        assert_eq!(summary.message, "1 matched rows, 0 unmatched rows");
+        // The row without a key column, the key which is no integer and the right row "lima".
+        assert_eq!(summary.metrics["skipped_rows"], 3.0);
+        assert_eq!(output, "alice,1,paris\n");
+    }
+
+    fn test_join_invalid_type() {
+        let error = run_join(json!({"type": "outer"}), LEFT, RIGHT).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid join type");
+    }
+
+    fn test_join_jsonl_input() {
+        let right = "{\"id\": 1, \"city\": \"paris\"}\n";
+        let error = run_join(json!({}), LEFT, right).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid data in input right: \
+             input 'right' looks like JSONL but builtin-join expects CSV"
+        );
+    }
+}
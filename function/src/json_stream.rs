@@ -0,0 +1,481 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use serde::Serialize;
+use std::format;
+use std::io::{self, Write};
+
+/// Writer of a JSON document which emits every token as soon as it is given, so builtins with
+/// large results do not build the document in memory before writing it. Strings are escaped
+/// and numbers formatted by serde_json, so the output matches what `serde_json::to_writer`
+/// writes for the same value, except that non-finite floats are rejected instead of being
+/// written as null. A writer made with `pretty` matches `serde_json::to_writer_pretty` instead.
+///
+/// Tokens out of place, such as a key inside an array or a second top-level value, fail with
+/// `io::ErrorKind::InvalidInput`.
+pub struct JsonStreamWriter<W: Write> {
+    inner: CountingWriter<W>,
+    stack: Vec<Frame>,
+    complete: bool,
+    pretty: bool,
+}
+
+enum Frame {
+    /// `key` tells whether a key was written whose value is still missing.
+    Object {
+        first: bool,
+        key: bool,
+    },
+    Array {
+        first: bool,
+    },
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn misuse(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl<W: Write> JsonStreamWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: CountingWriter { inner, bytes: 0 },
+            stack: Vec::new(),
+            complete: false,
+            pretty: false,
+        }
+    }
+
+    /// Writer which indents the document by two spaces, like `serde_json::to_writer_pretty`.
+    pub fn pretty(inner: W) -> Self {
+        Self {
+            pretty: true,
+            ..Self::new(inner)
+        }
+    }
+
+    /// Bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.bytes
+    }
+
+    pub fn begin_object(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.inner.write_all(b"{")?;
+        self.stack.push(Frame::Object {
+            first: true,
+            key: false,
+        });
+        Ok(())
+    }
+
+    pub fn end_object(&mut self) -> io::Result<()> {
+        match self.stack.last() {
+            Some(Frame::Object { key: false, .. }) => {}
+            Some(Frame::Object { key: true, .. }) => return Err(misuse("key without a value")),
+            _ => return Err(misuse("no object to end")),
+        }
+        if let Some(Frame::Object { first: false, .. }) = self.stack.pop() {
+            self.newline()?;
+        }
+        self.inner.write_all(b"}")?;
+        self.after_value();
+        Ok(())
+    }
+
+    pub fn begin_array(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.inner.write_all(b"[")?;
+        self.stack.push(Frame::Array { first: true });
+        Ok(())
+    }
+
+    pub fn end_array(&mut self) -> io::Result<()> {
+        match self.stack.last() {
+            Some(Frame::Array { .. }) => {}
+            _ => return Err(misuse("no array to end")),
+        }
+        if let Some(Frame::Array { first: false }) = self.stack.pop() {
+            self.newline()?;
+        }
+        self.inner.write_all(b"]")?;
+        self.after_value();
+        Ok(())
+    }
+
+    /// Writes the key of the next member of the current object.
+    pub fn key(&mut self, key: &str) -> io::Result<()> {
+        let first = match self.stack.last_mut() {
+            Some(Frame::Object { first, key: false }) => std::mem::replace(first, false),
+            Some(Frame::Object { key: true, .. }) => {
+                return Err(misuse("key where a value is expected"))
+            }
+            _ => return Err(misuse("key outside of an object")),
+        };
+        if !first {
+            self.inner.write_all(b",")?;
+        }
+        self.newline()?;
+        serde_json::to_writer(&mut self.inner, key)?;
+        self.inner
+            .write_all(if self.pretty { b": " } else { b":" })?;
+        if let Some(Frame::Object { key, .. }) = self.stack.last_mut() {
+            *key = true;
+        }
+        Ok(())
+    }
+
+    /// Writes any serializable value in one piece, e.g. a record of a larger document.
+    pub fn value<T: Serialize + ?Sized>(&mut self, value: &T) -> io::Result<()> {
+        self.before_value()?;
+        if self.pretty {
+            // Strings are escaped, so every newline of the value is one of its line breaks and
+            // is followed by the indentation of the current depth.
+            let json = serde_json::to_vec_pretty(value)?;
+            for (i, line) in json.split(|&b| b == b'\n').enumerate() {
+                if i > 0 {
+                    self.newline()?;
+                }
+                self.inner.write_all(line)?;
+            }
+        } else {
+            serde_json::to_writer(&mut self.inner, value)?;
+        }
+        self.after_value();
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> io::Result<()> {
+        self.value(value)
+    }
+
+    /// Writes a float, failing with `io::ErrorKind::InvalidData` if it is NaN or infinite,
+    /// which JSON cannot represent.
+    pub fn f64(&mut self, value: f64) -> io::Result<()> {
+        if !value.is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} cannot be written as a JSON number", value),
+            ));
+        }
+        self.value(&value)
+    }
+
+    pub fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.value(&value)
+    }
+
+    pub fn i64(&mut self, value: i64) -> io::Result<()> {
+        self.value(&value)
+    }
+
+    pub fn bool(&mut self, value: bool) -> io::Result<()> {
+        self.value(&value)
+    }
+
+    pub fn null(&mut self) -> io::Result<()> {
+        self.value(&())
+    }
+
+    /// Writes one JSON value per line for each of `records`, as JSONL. Only allowed before any
+    /// other token. Returns the number of records written.
+    pub fn write_jsonl<T: Serialize>(
+        &mut self,
+        records: impl IntoIterator<Item = T>,
+    ) -> io::Result<u64> {
+        if self.complete || !self.stack.is_empty() {
+            return Err(misuse("JSONL records inside of a document"));
+        }
+        let mut count = 0;
+        for record in records {
+            serde_json::to_writer(&mut self.inner, &record)?;
+            self.inner.write_all(b"\n")?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Checks that the document is complete and flushes it. Returns the bytes written.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if !self.stack.is_empty() {
+            return Err(misuse("unterminated object or array"));
+        }
+        self.inner.flush()?;
+        Ok(self.inner.bytes)
+    }
+
+    fn before_value(&mut self) -> io::Result<()> {
+        match self.stack.last_mut() {
+            None if self.complete => Err(misuse("second top-level value")),
+            None => Ok(()),
+            Some(Frame::Object { key, .. }) => {
+                if !*key {
+                    return Err(misuse("value where a key is expected"));
+                }
+                *key = false;
+                Ok(())
+            }
+            Some(Frame::Array { first }) => {
+                if !std::mem::replace(first, false) {
+                    self.inner.write_all(b",")?;
+                }
+                self.newline()
+            }
+        }
+    }
+
+    /// Starts a line indented to the current depth, if the writer is pretty.
+    fn newline(&mut self) -> io::Result<()> {
+        if self.pretty {
+            self.inner.write_all(b"\n")?;
+            for _ in 0..self.stack.len() {
+                self.inner.write_all(b"  ")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn after_value(&mut self) {
+        if self.stack.is_empty() {
+            self.complete = true;
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_nested_document,
+            test_pretty_document,
+            test_non_finite_floats,
+            test_misuse,
+            test_write_jsonl,
+        )
+    }
+
+    #[derive(Serialize)]
+    struct Column {
+        name: String,
+        values: Vec<f64>,
+        labels: BTreeMap<String, i64>,
+        valid: bool,
+        note: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct Document {
+        title: String,
+        rows: u64,
+        columns: Vec<Column>,
+        empty: Vec<u64>,
+    }
+
+    fn escaped() -> String {
+        "quote \" backslash \\ newline \n tab \t control \u{1} unicode \u{e9}\u{1f600}".to_string()
+    }
+
+    fn document() -> Document {
+        Document {
+            title: escaped(),
+            rows: u64::MAX,
+            columns: vec![
+                Column {
+                    name: "a\"b".to_string(),
+                    values: vec![0.0, -1.5, 1e-7, 123456789.125, f64::MAX],
+                    labels: vec![("x".to_string(), -3), ("y\n".to_string(), 7)]
+                        .into_iter()
+                        .collect(),
+                    valid: true,
+                    note: None,
+                },
+                Column {
+                    name: String::new(),
+                    values: Vec::new(),
+                    labels: BTreeMap::new(),
+                    valid: false,
+                    note: Some(escaped()),
+                },
+            ],
+            empty: Vec::new(),
+        }
+    }
+
+    /// Streams `document` token by token, down to single numbers.
+    fn stream(document: &Document, writer: &mut JsonStreamWriter<&mut Vec<u8>>) -> io::Result<()> {
+        writer.begin_object()?;
+        writer.key("title")?;
+        writer.string(&document.title)?;
+        writer.key("rows")?;
+        writer.u64(document.rows)?;
+        writer.key("columns")?;
+        writer.begin_array()?;
+        for column in &document.columns {
+            writer.begin_object()?;
+            writer.key("name")?;
+            writer.string(&column.name)?;
+            writer.key("values")?;
+            writer.begin_array()?;
+            for &value in &column.values {
+                writer.f64(value)?;
+            }
+            writer.end_array()?;
+            writer.key("labels")?;
+            writer.begin_object()?;
+            for (label, &value) in &column.labels {
+                writer.key(label)?;
+                writer.i64(value)?;
+            }
+            writer.end_object()?;
+            writer.key("valid")?;
+            writer.bool(column.valid)?;
+            writer.key("note")?;
+            match &column.note {
+                Some(note) => writer.string(note)?,
+                None => writer.null()?,
+            }
+            writer.end_object()?;
+        }
+        writer.end_array()?;
+        writer.key("empty")?;
+        writer.value(&document.empty)?;
+        writer.end_object()
+    }
+
+    fn test_nested_document() {
+        let document = document();
+        let mut output = Vec::new();
+        let mut writer = JsonStreamWriter::new(&mut output);
+        stream(&document, &mut writer).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let reference = serde_json::to_vec(&document).unwrap();
+        assert_eq!(output, reference);
+        assert_eq!(bytes, reference.len() as u64);
+    }
+
+    fn test_pretty_document() {
+        let document = document();
+        let mut output = Vec::new();
+        let mut writer = JsonStreamWriter::pretty(&mut output);
+        stream(&document, &mut writer).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let reference = serde_json::to_vec_pretty(&document).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(reference.clone()).unwrap()
+        );
+        assert_eq!(bytes, reference.len() as u64);
+
+        // Values written in one piece are indented to their depth.
+        let mut output = Vec::new();
+        let mut writer = JsonStreamWriter::pretty(&mut output);
+        writer.begin_object().unwrap();
+        writer.key("title").unwrap();
+        writer.string(&document.title).unwrap();
+        writer.key("rows").unwrap();
+        writer.u64(document.rows).unwrap();
+        writer.key("columns").unwrap();
+        writer.begin_array().unwrap();
+        for column in &document.columns {
+            writer.value(column).unwrap();
+        }
+        writer.end_array().unwrap();
+        writer.key("empty").unwrap();
+        writer.begin_array().unwrap();
+        writer.end_array().unwrap();
+        writer.end_object().unwrap();
+        writer.finish().unwrap();
+        assert_eq!(output, reference);
+    }
+
+    fn test_non_finite_floats() {
+        for value in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let mut output = Vec::new();
+            let mut writer = JsonStreamWriter::new(&mut output);
+            writer.begin_array().unwrap();
+            writer.f64(1.0).unwrap();
+            let error = writer.f64(*value).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            // Nothing of the rejected number reaches the output.
+            assert_eq!(output, b"[1.0");
+        }
+    }
+
+    fn test_misuse() {
+        let mut output = Vec::new();
+        let mut writer = JsonStreamWriter::new(&mut output);
+        writer.begin_object().unwrap();
+        assert_eq!(
+            writer.u64(1).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        writer.key("a").unwrap();
+        assert!(writer.key("b").is_err());
+        assert!(writer.end_object().is_err());
+        writer.begin_array().unwrap();
+        assert!(writer.key("c").is_err());
+        assert!(writer.end_object().is_err());
+        writer.end_array().unwrap();
+        writer.end_object().unwrap();
+        assert!(writer.u64(2).is_err());
+        assert!(writer.write_jsonl(vec![1]).is_err());
+        writer.finish().unwrap();
+        assert_eq!(output, b"{\"a\":[]}");
+
+        let mut output = Vec::new();
+        let mut writer = JsonStreamWriter::new(&mut output);
+        writer.begin_array().unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    fn test_write_jsonl() {
+        let records = document().columns;
+        let mut output = Vec::new();
+        let mut writer = JsonStreamWriter::new(&mut output);
+        assert_eq!(writer.write_jsonl(&records).unwrap(), 2);
+        writer.finish().unwrap();
+
+        let mut reference = Vec::new();
+        for record in &records {
+            serde_json::to_writer(&mut reference, record).unwrap();
+            reference.push(b'\n');
+        }
+        assert_eq!(output, reference);
+    }
+}
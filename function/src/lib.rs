@@ -17,51 +17,255 @@
 
 extern crate sgx_types;
 
+#[cfg(feature = "builtin_anonymize")]
+mod anonymize;
+#[cfg(feature = "builtin_benford")]
+mod benford;
+#[cfg(feature = "builtin_csv_join")]
+mod csv_join;
+#[cfg(feature = "builtin_dedup")]
+mod dedup;
+#[cfg(feature = "builtin_echo")]
 mod echo;
+#[cfg(feature = "builtin_face_detection")]
 mod face_detection;
+#[cfg(feature = "builtin_format_convert")]
+mod format_convert;
+#[cfg(feature = "builtin_fuzzy_intersect")]
+mod fuzzy_intersect;
+#[cfg(feature = "builtin_gbdt_predict")]
 mod gbdt_predict;
+#[cfg(feature = "builtin_gbdt_train")]
 mod gbdt_train;
+#[cfg(feature = "builtin_geofence")]
+mod geofence;
+#[cfg(feature = "builtin_histogram")]
+mod histogram;
+#[cfg(feature = "builtin_image_resize")]
+mod image_resize;
+#[cfg(feature = "builtin_join")]
+mod join;
+#[cfg(feature = "builtin_logistic_regression_predict")]
 mod logistic_regression_predict;
+#[cfg(feature = "builtin_logistic_regression_train")]
 mod logistic_regression_train;
+#[cfg(feature = "builtin_manifest")]
+mod manifest;
+#[cfg(feature = "builtin_normalize_eol")]
+mod normalize_eol;
+#[cfg(feature = "builtin_online_decrypt")]
 mod online_decrypt;
+#[cfg(feature = "builtin_ordered_set_intersect")]
 mod ordered_set_intersect;
+#[cfg(feature = "builtin_outlier_filter")]
+mod outlier_filter;
+#[cfg(feature = "builtin_passthrough")]
+mod passthrough;
+#[cfg(feature = "builtin_password_check")]
 mod password_check;
+#[cfg(feature = "builtin_principal_components_analysis")]
 mod principal_components_analysis;
+#[cfg(feature = "builtin_private_join_and_compute")]
 mod private_join_and_compute;
+#[cfg(feature = "builtin_private_join_compute")]
+mod private_join_compute;
+#[cfg(feature = "builtin_profile")]
+mod profile;
+#[cfg(feature = "builtin_redact")]
+mod redact;
+mod registry;
+#[cfg(feature = "builtin_resample")]
+mod resample;
+#[cfg(feature = "builtin_rsa_sign")]
 mod rsa_sign;
+#[cfg(feature = "builtin_sample")]
+mod sample;
+#[cfg(feature = "builtin_tail")]
+mod tail;
+#[cfg(feature = "builtin_topk")]
+mod topk;
+#[cfg(feature = "builtin_train_test_split")]
+mod train_test_split;
+#[cfg(feature = "builtin_transpose")]
+mod transpose;
 
+// Helpers shared by builtins, some of which may be left out of the build.
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod format_sniff;
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod json_stream;
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod line_transform;
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod merge_join;
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod parallel;
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod training_report;
+
+#[cfg(feature = "builtin_anonymize")]
+pub use anonymize::Anonymize;
+#[cfg(feature = "builtin_benford")]
+pub use benford::BenfordCheck;
+#[cfg(feature = "builtin_csv_join")]
+pub use csv_join::CsvJoin;
+#[cfg(feature = "builtin_dedup")]
+pub use dedup::{Dedup, DedupTransform};
+#[cfg(feature = "builtin_echo")]
 pub use echo::Echo;
+#[cfg(feature = "builtin_face_detection")]
 pub use face_detection::FaceDetection;
+#[cfg(feature = "builtin_format_convert")]
+pub use format_convert::FormatConvert;
+pub use format_sniff::{format_sniff, DetectedFormat, SNIFF_BYTES};
+#[cfg(feature = "builtin_fuzzy_intersect")]
+pub use fuzzy_intersect::FuzzyIntersect;
+#[cfg(feature = "builtin_gbdt_predict")]
 pub use gbdt_predict::GbdtPredict;
+#[cfg(feature = "builtin_gbdt_train")]
 pub use gbdt_train::GbdtTrain;
+#[cfg(feature = "builtin_geofence")]
+pub use geofence::GeoFence;
+#[cfg(feature = "builtin_histogram")]
+pub use histogram::Histogram;
+#[cfg(feature = "builtin_image_resize")]
+pub use image_resize::ImageResize;
+#[cfg(feature = "builtin_join")]
+pub use join::Join;
+pub use json_stream::JsonStreamWriter;
+pub use line_transform::{LinePipeline, LinePipelineStats, LineTransform};
+#[cfg(feature = "builtin_logistic_regression_predict")]
 pub use logistic_regression_predict::LogisticRegressionPredict;
+#[cfg(feature = "builtin_logistic_regression_train")]
 pub use logistic_regression_train::LogisticRegressionTrain;
+#[cfg(feature = "builtin_manifest")]
+pub use manifest::Manifest;
+#[cfg(feature = "builtin_normalize_eol")]
+pub use normalize_eol::NormalizeEol;
+#[cfg(feature = "builtin_online_decrypt")]
 pub use online_decrypt::OnlineDecrypt;
+#[cfg(feature = "builtin_ordered_set_intersect")]
 pub use ordered_set_intersect::OrderedSetIntersect;
+#[cfg(feature = "builtin_outlier_filter")]
+pub use outlier_filter::OutlierFilter;
+#[cfg(feature = "builtin_passthrough")]
+pub use passthrough::Passthrough;
+#[cfg(feature = "builtin_password_check")]
 pub use password_check::PasswordCheck;
+#[cfg(feature = "builtin_principal_components_analysis")]
 pub use principal_components_analysis::PrincipalComponentsAnalysis;
+#[cfg(feature = "builtin_private_join_and_compute")]
 pub use private_join_and_compute::PrivateJoinAndCompute;
+#[cfg(feature = "builtin_private_join_compute")]
+pub use private_join_compute::PrivateJoinCompute;
+#[cfg(feature = "builtin_profile")]
+pub use profile::Profile;
+#[cfg(feature = "builtin_redact")]
+pub use redact::{Redact, RedactTransform};
+pub use registry::{
+    registry, BuiltinFn, BuiltinRegistry, FunctionDescriptor, FunctionDisabled, FunctionManifest,
+    KnownBuiltin, NoMatchingVersion, NotCompiled, NotFound, PrepareFn, PreparedFunction,
+    ResolvedBuiltin, VersionConstraint, BUILTINS, DEFAULT_PREPARED_CAPACITY, KNOWN_BUILTINS,
+};
+#[cfg(feature = "builtin_resample")]
+pub use resample::Resample;
+#[cfg(feature = "builtin_rsa_sign")]
 pub use rsa_sign::RsaSign;
+#[cfg(feature = "builtin_sample")]
+pub use sample::Sample;
+#[cfg(feature = "builtin_tail")]
+pub use tail::Tail;
+#[cfg(feature = "builtin_topk")]
+pub use topk::TopK;
+#[cfg(feature = "builtin_train_test_split")]
+pub use train_test_split::TrainTestSplit;
+#[cfg(feature = "builtin_transpose")]
+pub use transpose::Transpose;
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
-    use teaclave_test_utils::check_all_passed;
 
+    #[allow(clippy::vec_init_then_push)]
     pub fn run_tests() -> bool {
-        check_all_passed!(
-            echo::tests::run_tests(),
-            face_detection::tests::run_tests(),
-            gbdt_predict::tests::run_tests(),
-            gbdt_train::tests::run_tests(),
-            logistic_regression_predict::tests::run_tests(),
-            logistic_regression_train::tests::run_tests(),
-            password_check::tests::run_tests(),
-            online_decrypt::tests::run_tests(),
-            ordered_set_intersect::tests::run_tests(),
-            principal_components_analysis::tests::run_tests(),
-            private_join_and_compute::tests::run_tests(),
-            rsa_sign::tests::run_tests(),
-        )
+        let mut v: Vec<bool> = Vec::new();
+        #[cfg(feature = "builtin_echo")]
+        v.push(echo::tests::run_tests());
+        #[cfg(feature = "builtin_face_detection")]
+        v.push(face_detection::tests::run_tests());
+        #[cfg(feature = "builtin_gbdt_predict")]
+        v.push(gbdt_predict::tests::run_tests());
+        #[cfg(feature = "builtin_gbdt_train")]
+        v.push(gbdt_train::tests::run_tests());
+        #[cfg(feature = "builtin_logistic_regression_predict")]
+        v.push(logistic_regression_predict::tests::run_tests());
+        #[cfg(feature = "builtin_logistic_regression_train")]
+        v.push(logistic_regression_train::tests::run_tests());
+        #[cfg(feature = "builtin_password_check")]
+        v.push(password_check::tests::run_tests());
+        #[cfg(feature = "builtin_online_decrypt")]
+        v.push(online_decrypt::tests::run_tests());
+        #[cfg(feature = "builtin_ordered_set_intersect")]
+        v.push(ordered_set_intersect::tests::run_tests());
+        #[cfg(feature = "builtin_principal_components_analysis")]
+        v.push(principal_components_analysis::tests::run_tests());
+        #[cfg(feature = "builtin_private_join_and_compute")]
+        v.push(private_join_and_compute::tests::run_tests());
+        #[cfg(feature = "builtin_rsa_sign")]
+        v.push(rsa_sign::tests::run_tests());
+        #[cfg(feature = "builtin_fuzzy_intersect")]
+        v.push(fuzzy_intersect::tests::run_tests());
+        #[cfg(feature = "builtin_image_resize")]
+        v.push(image_resize::tests::run_tests());
+        #[cfg(feature = "builtin_dedup")]
+        v.push(dedup::tests::run_tests());
+        #[cfg(feature = "builtin_tail")]
+        v.push(tail::tests::run_tests());
+        #[cfg(feature = "builtin_join")]
+        v.push(join::tests::run_tests());
+        v.push(registry::tests::run_tests());
+        #[cfg(feature = "builtin_redact")]
+        v.push(redact::tests::run_tests());
+        #[cfg(feature = "builtin_format_convert")]
+        v.push(format_convert::tests::run_tests());
+        v.push(parallel::tests::run_tests());
+        #[cfg(feature = "builtin_passthrough")]
+        v.push(passthrough::tests::run_tests());
+        #[cfg(all(feature = "builtin_dedup", feature = "builtin_redact"))]
+        v.push(line_transform::tests::run_tests());
+        #[cfg(feature = "builtin_private_join_compute")]
+        v.push(private_join_compute::tests::run_tests());
+        #[cfg(feature = "builtin_sample")]
+        v.push(sample::tests::run_tests());
+        v.push(format_sniff::tests::run_tests());
+        #[cfg(feature = "builtin_train_test_split")]
+        v.push(train_test_split::tests::run_tests());
+        #[cfg(feature = "builtin_profile")]
+        v.push(profile::tests::run_tests());
+        #[cfg(feature = "builtin_anonymize")]
+        v.push(anonymize::tests::run_tests());
+        v.push(merge_join::tests::run_tests());
+        #[cfg(feature = "builtin_transpose")]
+        v.push(transpose::tests::run_tests());
+        #[cfg(feature = "builtin_resample")]
+        v.push(resample::tests::run_tests());
+        #[cfg(feature = "builtin_benford")]
+        v.push(benford::tests::run_tests());
+        #[cfg(feature = "builtin_histogram")]
+        v.push(histogram::tests::run_tests());
+        v.push(json_stream::tests::run_tests());
+        #[cfg(feature = "builtin_topk")]
+        v.push(topk::tests::run_tests());
+        #[cfg(feature = "builtin_geofence")]
+        v.push(geofence::tests::run_tests());
+        #[cfg(feature = "builtin_normalize_eol")]
+        v.push(normalize_eol::tests::run_tests());
+        #[cfg(feature = "builtin_outlier_filter")]
+        v.push(outlier_filter::tests::run_tests());
+        #[cfg(feature = "builtin_manifest")]
+        v.push(manifest::tests::run_tests());
+        #[cfg(feature = "builtin_csv_join")]
+        v.push(csv_join::tests::run_tests());
+        v.iter().all(|&x| x)
     }
 }
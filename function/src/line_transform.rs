@@ -0,0 +1,179 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io::{BufRead, Write};
+use teaclave_types::{CancellationToken, FunctionError, CANCELLATION_CHECK_INTERVAL};
+
+/// Per-line stage of a line-based builtin. Builtins implementing it can be chained by a
+/// `LinePipeline`, so that several of them run over one input without intermediate files.
+pub trait LineTransform {
+    /// Processes one line, given without its terminator, and writes zero or more lines to
+    /// `out`, each followed by '\n'.
+    fn transform(&self, line: &[u8], out: &mut dyn Write) -> Result<(), FunctionError>;
+}
+
+/// Counters of a `LinePipeline` run. Missing trailing newlines are counted as if present.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinePipelineStats {
+    pub lines_read: usize,
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+}
+
+/// Chain of line transforms over a single input-to-output stream. Every input line passes
+/// through all stages before the next one is read, so only the lines derived from one input
+/// line are held in memory.
+#[derive(Default)]
+pub struct LinePipeline<'a> {
+    stages: Vec<&'a dyn LineTransform>,
+}
+
+impl<'a> LinePipeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stage(mut self, transform: &'a dyn LineTransform) -> Self {
+        self.stages.push(transform);
+        self
+    }
+
+    /// Feeds every line of `input`, with "\n" or "\r\n" terminators removed, through the stages
+    /// and writes what the last stage emits to `output`. Errors of the stages are passed on
+    /// as they are, so they keep their classification.
+    pub fn run(
+        &self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<LinePipelineStats> {
+        let mut stats = LinePipelineStats::default();
+        let mut buffer = Vec::new();
+        loop {
+            if stats.lines_read % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            buffer.clear();
+            if input.read_until(b'\n', &mut buffer)? == 0 {
+                break;
+            }
+            let line = strip_terminator(&buffer);
+            stats.lines_read += 1;
+            stats.bytes_read += line.len() + 1;
+
+            let mut lines = line.to_vec();
+            lines.push(b'\n');
+            for stage in &self.stages {
+                if lines.is_empty() {
+                    break;
+                }
+                let mut next = Vec::new();
+                let emitted = lines.strip_suffix(b"\n").unwrap_or(&lines);
+                for line in emitted.split(|b| *b == b'\n') {
+                    stage.transform(line, &mut next)?;
+                }
+                lines = next;
+            }
+            output.write_all(&lines)?;
+            stats.bytes_written += lines.len();
+        }
+        output.flush()?;
+        Ok(stats)
+    }
+}
+
+fn strip_terminator(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::dedup::DedupTransform;
+    use crate::redact::RedactTransform;
+    use std::io;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_pipeline_single_stage, test_pipeline_dedup_then_redact)
+    }
+
+    /// Emits every line twice, to check that stages see each emitted line separately.
+    struct Repeat;
+
+    impl LineTransform for Repeat {
+        fn transform(&self, line: &[u8], out: &mut dyn Write) -> Result<(), FunctionError> {
+            for _ in 0..2 {
+                out.write_all(line)?;
+                out.write_all(b"\n")?;
+            }
+            Ok(())
+        }
+    }
+
+    fn run_pipeline(
+        pipeline: &LinePipeline,
+        input: &str,
+    ) -> anyhow::Result<(LinePipelineStats, String)> {
+        let mut output = Vec::new();
+        let stats = pipeline.run(
+            &mut io::Cursor::new(input.as_bytes()),
+            &mut output,
+            &CancellationToken::new(),
+        )?;
+        Ok((stats, String::from_utf8(output).unwrap()))
+    }
+
+    fn test_pipeline_single_stage() {
+        let pipeline = LinePipeline::new().stage(&Repeat);
+        let (stats, output) = run_pipeline(&pipeline, "a\r\nb").unwrap();
+        assert_eq!(output, "a\na\nb\nb\n");
+        assert_eq!(
+            stats,
+            LinePipelineStats {
+                lines_read: 2,
+                bytes_read: 4,
+                bytes_written: 8,
+            }
+        );
+    }
+
+    fn test_pipeline_dedup_then_redact() {
+        let input = concat!(
+            r#"{"name":"alice","ssn":"1"}"#,
+            "\n",
+            r#"{"name":"bob","ssn":"2"}"#,
+            "\n",
+            r#"{"name":"alice","ssn":"1"}"#,
+            "\n",
+        );
+        let dedup = DedupTransform::new(false, false);
+        let redact = RedactTransform::new("input", &["ssn".to_string()], &[], "").unwrap();
+        let pipeline = LinePipeline::new()
+            .stage(&Repeat)
+            .stage(&dedup)
+            .stage(&redact);
+
+        let (stats, output) = run_pipeline(&pipeline, input).unwrap();
+        assert_eq!(output, "{\"name\":\"alice\"}\n{\"name\":\"bob\"}\n");
+        assert_eq!(stats.lines_read, 3);
+        assert_eq!(stats.bytes_written, output.len());
+        assert_eq!((dedup.unique(), dedup.duplicates()), (2, 4));
+        assert_eq!((redact.records(), redact.dropped()), (2, 2));
+    }
+}
@@ -20,7 +20,7 @@ use super::logistic_regression_train::Model;
 use std::format;
 use std::io::{self, BufRead, BufReader, Write};
 
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
 
 use rusty_machine::learning::logistic_reg::LogisticRegressor;
 use rusty_machine::learning::SupModel;
@@ -44,7 +44,7 @@ impl LogisticRegressionPredict {
         &self,
         _arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<FunctionSummary> {
         let mut model_json = String::new();
         let mut f = runtime.open_input(MODEL_FILE)?;
         f.read_to_string(&mut model_json)?;
@@ -71,7 +71,7 @@ impl LogisticRegressionPredict {
         for c in result.data().iter() {
             writeln!(&mut output, "{:.4}", c)?;
         }
-        Ok(format!("Predicted {} lines of data.", result_cnt))
+        Ok(format!("Predicted {} lines of data.", result_cnt).into())
     }
 }
 
@@ -147,7 +147,7 @@ pub mod tests {
         let summary = LogisticRegressionPredict::new()
             .run(arguments, runtime)
             .unwrap();
-        assert_eq!(summary, "Predicted 5 lines of data.");
+        assert_eq!(summary.message, "Predicted 5 lines of data.");
 
         let result = fs::read_to_string(&plain_output).unwrap();
         let expected = fs::read_to_string(&expected_output).unwrap();
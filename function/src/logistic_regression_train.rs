@@ -18,8 +18,16 @@
 use std::convert::TryFrom;
 use std::format;
 use std::io::{self, BufRead, BufReader, Write};
+use std::time::Instant;
+use std::untrusted::time::InstantEx;
 
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use crate::training_report::{
+    report_requested, write_report, DataStatistics, Timing, TrainingReport,
+};
+use teaclave_types::{
+    CancellationToken, FunctionArguments, FunctionRuntime, FunctionSummary,
+    CANCELLATION_CHECK_INTERVAL,
+};
 
 use rusty_machine::learning::logistic_reg::LogisticRegressor;
 use rusty_machine::learning::optim::grad_desc::GradientDesc;
@@ -32,7 +40,7 @@ const OUT_MODEL_FILE: &str = "model_file";
 #[derive(Default)]
 pub struct LogisticRegressionTrain;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 struct LogisticRegressionTrainArguments {
     alg_alpha: f64,
     alg_iters: usize,
@@ -59,18 +67,35 @@ impl LogisticRegressionTrain {
         &self,
         arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<FunctionSummary> {
         let args = LogisticRegressionTrainArguments::try_from(arguments)?;
 
+        let cancellation = runtime.cancellation();
+        let parse_start = Instant::now();
         let input = runtime.open_input(TRAINING_DATA)?;
-        let (flattend_features, targets) = parse_training_data(input, args.feature_size)?;
+        let (flattend_features, targets) =
+            parse_training_data(input, args.feature_size, &cancellation)?;
         let data_size = targets.len();
+        let parse_time = parse_start.elapsed();
+        runtime.report_progress(0.1, "parsed training data");
+
+        // The report needs the data after the matrix has taken it over.
+        let report_data = if report_requested(&runtime) {
+            Some((flattend_features.clone(), targets.clone()))
+        } else {
+            None
+        };
+
+        let training_start = Instant::now();
         let data_matrix = linalg::Matrix::new(data_size, args.feature_size, flattend_features);
         let targets = linalg::Vector::new(targets);
 
         let gd = GradientDesc::new(args.alg_alpha, args.alg_iters);
         let mut lr = LogisticRegressor::new(gd);
         lr.train(&data_matrix, &targets)?;
+        cancellation.checkpoint()?;
+        let training_time = training_start.elapsed();
+        runtime.report_progress(0.8, "trained model");
         let model = Model::new(
             args.alg_alpha,
             args.alg_iters,
@@ -80,9 +105,78 @@ impl LogisticRegressionTrain {
         let model_json = serde_json::to_string(&model)?;
         let mut model_file = runtime.create_output(OUT_MODEL_FILE)?;
         model_file.write_all(model_json.as_bytes())?;
+        runtime.report_progress(0.9, "saved model");
+
+        let report = match report_data {
+            Some((features, labels)) => Some(TrainingReport {
+                parameters: &args,
+                data: DataStatistics::new(&features, &labels),
+                loss_curve: loss_curve(
+                    &features,
+                    &labels,
+                    args.alg_alpha,
+                    args.alg_iters,
+                    &cancellation,
+                )?,
+                timing: Timing::new(parse_time, training_time),
+            }),
+            None => None,
+        };
+
+        runtime.report_progress(1.0, "done");
+        let summary = format!("Trained {} lines of data.", data_size);
+        write_report(&runtime, report.as_ref(), summary.into())
+    }
+}
 
-        Ok(format!("Trained {} lines of data.", data_size))
+/// Cross-entropy loss on the training data after each iteration. LogisticRegressor does not
+/// expose its intermediate parameters, so this replays its gradient descent: an intercept is
+/// prepended to the features and all parameters start at 0.5.
+fn loss_curve(
+    features: &[f64],
+    labels: &[f64],
+    alpha: f64,
+    iterations: usize,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Vec<f64>> {
+    let feature_size = features.len() / labels.len().max(1);
+    let mut parameters = vec![0.5; feature_size + 1];
+    let mut curve = Vec::with_capacity(iterations);
+    let (_, mut gradient) = cost_and_gradient(&parameters, features, labels);
+    for _ in 0..iterations {
+        cancellation.checkpoint()?;
+        for (parameter, g) in parameters.iter_mut().zip(&gradient) {
+            *parameter -= alpha * g;
+        }
+        let (cost, next_gradient) = cost_and_gradient(&parameters, features, labels);
+        curve.push(cost);
+        gradient = next_gradient;
     }
+    Ok(curve)
+}
+
+fn cost_and_gradient(parameters: &[f64], features: &[f64], labels: &[f64]) -> (f64, Vec<f64>) {
+    const EPSILON: f64 = 1e-15;
+    let feature_size = parameters.len() - 1;
+    let rows = labels.len().max(1) as f64;
+    let mut cost = 0.0;
+    let mut gradient = vec![0.0; parameters.len()];
+    for (row, &y) in features.chunks(feature_size.max(1)).zip(labels) {
+        let z = parameters[0]
+            + row
+                .iter()
+                .zip(&parameters[1..])
+                .map(|(x, w)| x * w)
+                .sum::<f64>();
+        let h = (1.0 / (1.0 + (-z).exp())).clamp(EPSILON, 1.0 - EPSILON);
+        cost -= y * h.ln() + (1.0 - y) * (1.0 - h).ln();
+        gradient[0] += h - y;
+        for (g, x) in gradient[1..].iter_mut().zip(row) {
+            *g += (h - y) * x;
+        }
+    }
+    gradient.iter_mut().for_each(|g| *g /= rows);
+    (cost / rows, gradient)
 }
 
 // LogisticRegressor does not support serde, so we use this intermedia structure
@@ -116,12 +210,16 @@ impl Model {
 fn parse_training_data(
     input: impl io::Read,
     feature_size: usize,
+    cancellation: &CancellationToken,
 ) -> anyhow::Result<(Vec<f64>, Vec<f64>)> {
     let reader = BufReader::new(input);
     let mut targets = Vec::<f64>::new();
     let mut features = Vec::new();
 
-    for line_result in reader.lines() {
+    for (index, line_result) in reader.lines().enumerate() {
+        if index % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
         let line = line_result?;
         let trimed_line = line.trim();
         anyhow::ensure!(!trimed_line.is_empty(), "Empty line");
@@ -149,6 +247,7 @@ fn parse_training_data(
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use crate::training_report::OUT_REPORT;
     use serde_json::json;
     use std::path::Path;
     use std::untrusted::fs;
@@ -158,16 +257,23 @@ pub mod tests {
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_logistic_regression_train)
+        run_tests!(
+            test_logistic_regression_train,
+            test_logistic_regression_train_report
+        )
     }
 
-    fn test_logistic_regression_train() {
-        let arguments = FunctionArguments::from_json(json!({
+    fn training_arguments() -> FunctionArguments {
+        FunctionArguments::from_json(json!({
             "alg_alpha": 0.3,
             "alg_iters": 100,
             "feature_size": 30
         }))
-        .unwrap();
+        .unwrap()
+    }
+
+    fn test_logistic_regression_train() {
+        let arguments = training_arguments();
 
         let base = Path::new("fixtures/functions/logistic_regression_training");
         let training_data = base.join("train.txt");
@@ -189,10 +295,64 @@ pub mod tests {
         let summary = LogisticRegressionTrain::new()
             .run(arguments, runtime)
             .unwrap();
-        assert_eq!(summary, "Trained 100 lines of data.");
+        assert_eq!(summary.message, "Trained 100 lines of data.");
+        assert_eq!(summary.metrics["report_emitted"], 0.0);
+        assert!(!summary.outputs.contains_key(OUT_REPORT));
+
+        let result = fs::read_to_string(&plain_output).unwrap();
+        let expected = fs::read_to_string(&expected_output).unwrap();
+        assert_eq!(&result[..], &expected[..]);
+    }
+
+    fn test_logistic_regression_train_report() {
+        let arguments = training_arguments();
+
+        let base = Path::new("fixtures/functions/logistic_regression_training");
+        let training_data = base.join("train.txt");
+        let plain_output = base.join("model_with_report.txt.out");
+        let report_output = base.join("report.json.out");
+        let expected_output = base.join("expected_model.txt");
+
+        let input_files = StagedFiles::new(hashmap!(
+            TRAINING_DATA =>
+            StagedFileInfo::new(&training_data, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+
+        let output_files = StagedFiles::new(hashmap!(
+            OUT_MODEL_FILE =>
+            StagedFileInfo::new(&plain_output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+            OUT_REPORT =>
+            StagedFileInfo::new(&report_output, TeaclaveFile128Key::random(), FileAuthTag::mock())
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+
+        let summary = LogisticRegressionTrain::new()
+            .run(arguments, runtime)
+            .unwrap();
+        assert_eq!(
+            summary.message,
+            "Trained 100 lines of data. Wrote a training report."
+        );
+        assert_eq!(summary.metrics["report_emitted"], 1.0);
 
+        // The report does not change the model.
         let result = fs::read_to_string(&plain_output).unwrap();
         let expected = fs::read_to_string(&expected_output).unwrap();
         assert_eq!(&result[..], &expected[..]);
+
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&report_output).unwrap()).unwrap();
+        assert_eq!(report["parameters"]["alg_iters"], 100);
+        assert_eq!(report["parameters"]["alg_alpha"], 0.3);
+        assert_eq!(report["data"]["rows"], 100);
+        assert_eq!(report["data"]["features"].as_array().unwrap().len(), 30);
+        assert!(report["data"]["label"]["max"].as_f64().unwrap() <= 1.0);
+        assert!(report["timing"]["parse_ms"].is_number());
+        assert!(report["timing"]["training_ms"].is_number());
+
+        let curve: Vec<f64> = serde_json::from_value(report["loss_curve"].clone()).unwrap();
+        assert_eq!(curve.len(), 100);
+        assert!(curve.iter().all(|loss| loss.is_finite()));
     }
 }
@@ -0,0 +1,198 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use ring::digest;
+use serde::Serialize;
+use std::format;
+use std::io::Write;
+use teaclave_types::{
+    ArgumentSchema, FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+    DEFAULT_CHUNK_SIZE,
+};
+
+const IN_PREFIX: &str = "input";
+const OUT_MANIFEST: &str = "manifest";
+
+#[derive(Default)]
+pub struct Manifest;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestArguments {}
+
+/// Identifier, size and hex encoded SHA-256 digest of one file.
+#[derive(Serialize)]
+struct FileEntry {
+    identifier: String,
+    size: u64,
+    sha256: String,
+}
+
+/// The manifest written by the function. Files are sorted by identifier, so that the same
+/// files always give the same bytes.
+#[derive(Serialize)]
+struct ManifestDocument {
+    inputs: Vec<FileEntry>,
+}
+
+impl Manifest {
+    pub const NAME: &'static str = "builtin-manifest";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+    }
+
+    /// Streams every input of the task whose identifier starts with `input` through SHA-256 and
+    /// writes the identifier, size and digest of each to the manifest output as JSON. The
+    /// function cannot sign the manifest itself, but its digest lands in the I/O manifest of the
+    /// execution, which the executor signs with the attestation key of the enclave, and in the
+    /// summary.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let _: ManifestArguments = arguments.into_typed()?;
+        let cancellation = runtime.cancellation();
+
+        let mut identifiers: Vec<String> = runtime
+            .input_keys()
+            .into_iter()
+            .filter(|identifier| identifier.starts_with(IN_PREFIX))
+            .collect();
+        identifiers.sort();
+        let mut inputs = Vec::with_capacity(identifiers.len());
+        for identifier in identifiers {
+            let mut context = digest::Context::new(&digest::SHA256);
+            let mut size = 0;
+            for chunk in runtime.open_input_chunked(&identifier, DEFAULT_CHUNK_SIZE)? {
+                cancellation.checkpoint()?;
+                let chunk = chunk?;
+                context.update(&chunk);
+                size += chunk.len() as u64;
+            }
+            inputs.push(FileEntry {
+                identifier,
+                size,
+                sha256: hex::encode(context.finish()),
+            });
+        }
+
+        let document = ManifestDocument { inputs };
+        let bytes = serde_json::to_vec(&document).map_err(anyhow::Error::from)?;
+        let mut output = runtime.create_output(OUT_MANIFEST)?;
+        output.write_all(&bytes)?;
+        output.flush()?;
+
+        let input_bytes: u64 = document.inputs.iter().map(|input| input.size).sum();
+        Ok(FunctionSummary::new(format!(
+            "Manifest of {} inputs ({} bytes), SHA-256 {}",
+            document.inputs.len(),
+            input_bytes,
+            hex::encode(digest::digest(&digest::SHA256, &bytes))
+        ))
+        .metric("inputs", document.inputs.len() as f64)
+        .metric("input_bytes", input_bytes as f64)
+        .output(OUT_MANIFEST, OutputInfo::new(bytes.len() as u64)))
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_manifest_lists_inputs, test_manifest_without_inputs)
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(digest::digest(&digest::SHA256, data))
+    }
+
+    fn manifest(
+        inputs: HashMap<String, Vec<u8>>,
+        arguments: Value,
+    ) -> Result<(FunctionSummary, Vec<u8>, IoManifestRecorder), FunctionError> {
+        let recorder = IoManifestRecorder::new();
+        let (summary, mut outputs) = run_builtin!(
+            Manifest::new(),
+            arguments,
+            inputs,
+            [OUT_MANIFEST],
+            with_io_recorder(recorder.clone())
+        );
+        Ok((summary?, outputs.remove(OUT_MANIFEST).unwrap(), recorder))
+    }
+
+    fn test_manifest_lists_inputs() {
+        let large: Vec<u8> = (0..DEFAULT_CHUNK_SIZE * 2 + 7).map(|i| i as u8).collect();
+        let inputs = hashmap!(
+            "input_model" => large.clone(),
+            "input_dataset" => b"a,b\n1,2\n".to_vec(),
+            "input_empty" => Vec::new(),
+            // Not covered by the manifest, and reported as unused by the staging check.
+            "notes" => b"draft".to_vec(),
+        );
+        let (summary, written, recorder) = manifest(inputs, json!({})).unwrap();
+        let document: Value = serde_json::from_slice(&written).unwrap();
+
+        assert_eq!(
+            document,
+            json!({
+                "inputs": [
+                    {"identifier": "input_dataset", "size": 8, "sha256": sha256(b"a,b\n1,2\n")},
+                    {"identifier": "input_empty", "size": 0, "sha256": sha256(b"")},
+                    {"identifier": "input_model", "size": large.len(), "sha256": sha256(&large)},
+                ],
+            })
+        );
+        assert_eq!(summary.metrics["inputs"], 3.0);
+        assert_eq!(summary.metrics["input_bytes"], (large.len() + 8) as f64);
+
+        // The manifest is covered by the I/O manifest of the execution, which gets signed.
+        let io_manifest = recorder.manifest();
+        assert_eq!(
+            io_manifest.outputs[OUT_MANIFEST].sha256,
+            Some(sha256(&written))
+        );
+        assert!(summary.message.ends_with(&sha256(&written)));
+        assert!(!io_manifest.inputs["input_model"].partial);
+        assert!(!io_manifest
+            .inputs
+            .get("notes")
+            .map_or(false, |record| record.opened));
+    }
+
+    fn test_manifest_without_inputs() {
+        let (summary, written, _) = manifest(HashMap::new(), json!({})).unwrap();
+        assert_eq!(written, br#"{"inputs":[]}"#);
+        assert!(summary
+            .message
+            .starts_with("Manifest of 0 inputs (0 bytes),"));
+
+        let arguments = json!({"sign": true});
+        assert!(manifest(HashMap::new(), arguments).is_err());
+    }
+}
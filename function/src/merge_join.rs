@@ -0,0 +1,266 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use anyhow::{bail, Context};
+use std::cmp::Ordering;
+use std::format;
+use std::io::BufRead;
+use teaclave_types::{CancellationToken, CANCELLATION_CHECK_INTERVAL};
+
+/// Reads one line at a time into a reused buffer, without its line terminator.
+struct LineReader<R> {
+    input: R,
+    line: String,
+    number: usize,
+}
+
+impl<R: BufRead> LineReader<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            number: 0,
+        }
+    }
+
+    /// Advances to the next line. Returns false at the end of the input.
+    fn advance(&mut self) -> anyhow::Result<bool> {
+        self.line.clear();
+        if self.input.read_line(&mut self.line)? == 0 {
+            return Ok(false);
+        }
+        if self.line.ends_with('\n') {
+            self.line.pop();
+            if self.line.ends_with('\r') {
+                self.line.pop();
+            }
+        }
+        self.number += 1;
+        Ok(true)
+    }
+}
+
+/// Reads `input` through to check that its lines are sorted by `cmp`, passing every line to
+/// `validate` on the way. Fails with the number of the first line which is out of order, and
+/// returns the number of lines otherwise. Only two lines are held in memory at a time.
+pub(crate) fn check_sorted_lines(
+    input: impl BufRead,
+    cancellation: &CancellationToken,
+    mut cmp: impl FnMut(&str, &str) -> Ordering,
+    mut validate: impl FnMut(&str) -> anyhow::Result<()>,
+) -> anyhow::Result<usize> {
+    let mut lines = LineReader::new(input);
+    let mut previous = String::new();
+    while lines.advance()? {
+        if lines.number % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        validate(&lines.line).with_context(|| format!("line {}", lines.number))?;
+        if lines.number > 1 && cmp(&previous, &lines.line) == Ordering::Greater {
+            bail!("line {} is out of order", lines.number);
+        }
+        std::mem::swap(&mut previous, &mut lines.line);
+    }
+    Ok(lines.number)
+}
+
+/// Joins two inputs whose lines are sorted by `cmp`, stepping through both in lockstep so that
+/// neither is held in memory. `on_match` is called with the zero-based line indices and the
+/// contents of every pair of equal lines; both sides advance past a match, so duplicates pair
+/// up in order. Returns the number of matches. Unsorted inputs give incomplete results, so
+/// callers check them with `check_sorted_lines` first.
+pub(crate) fn merge_join_lines(
+    a: impl BufRead,
+    b: impl BufRead,
+    cancellation: &CancellationToken,
+    mut cmp: impl FnMut(&str, &str) -> Ordering,
+    mut on_match: impl FnMut(usize, usize, &str, &str) -> anyhow::Result<()>,
+) -> anyhow::Result<usize> {
+    let mut a = LineReader::new(a);
+    let mut b = LineReader::new(b);
+    let mut matches = 0;
+    let mut steps = 0;
+    let (mut more_a, mut more_b) = (a.advance()?, b.advance()?);
+    while more_a && more_b {
+        if steps % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        steps += 1;
+        match cmp(&a.line, &b.line) {
+            Ordering::Less => more_a = a.advance()?,
+            Ordering::Greater => more_b = b.advance()?,
+            Ordering::Equal => {
+                on_match(a.number - 1, b.number - 1, &a.line, &b.line)?;
+                matches += 1;
+                more_a = a.advance()?;
+                more_b = b.advance()?;
+            }
+        }
+    }
+    Ok(matches)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::io::{self, BufReader, Read};
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::sync::Arc;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_merge_join_lines,
+            test_merge_join_bounded_buffers,
+            test_check_sorted_lines,
+        )
+    }
+
+    const BUFFER_BUDGET: usize = 4096;
+
+    // Generates the input line by line and records the largest read, i.e. the size of the
+    // buffer in front of it.
+    struct GeneratedInput {
+        lines: Box<dyn Iterator<Item = String>>,
+        pending: Vec<u8>,
+        largest_read: Arc<AtomicUsize>,
+    }
+
+    impl GeneratedInput {
+        fn new(
+            lines: impl Iterator<Item = String> + 'static,
+            largest_read: Arc<AtomicUsize>,
+        ) -> Self {
+            Self {
+                lines: Box::new(lines),
+                pending: Vec::new(),
+                largest_read,
+            }
+        }
+    }
+
+    impl Read for GeneratedInput {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.largest_read
+                .fetch_max(buf.len(), AtomicOrdering::SeqCst);
+            while self.pending.len() < buf.len() {
+                match self.lines.next() {
+                    Some(line) => self.pending.extend(format!("{}\n", line).bytes()),
+                    None => break,
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    fn join(a: &str, b: &str) -> Vec<(usize, usize, String)> {
+        let mut matched = Vec::new();
+        let count = merge_join_lines(
+            a.as_bytes(),
+            b.as_bytes(),
+            &CancellationToken::new(),
+            |x, y| x.cmp(y),
+            |i, j, x, y| {
+                assert_eq!(x, y);
+                matched.push((i, j, x.to_string()));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(count, matched.len());
+        matched
+    }
+
+    fn test_merge_join_lines() {
+        assert_eq!(
+            join("a\nc\nd\nf\n", "b\nc\nf\ng\n"),
+            vec![(1, 1, "c".to_string()), (3, 2, "f".to_string())]
+        );
+        // Line terminators do not take part in the comparison.
+        assert_eq!(join("x\r\ny", "x\ny\n").len(), 2);
+        assert_eq!(
+            join("a\na\nb\n", "a\nb\nb\n"),
+            vec![(0, 0, "a".to_string()), (2, 1, "b".to_string())]
+        );
+        assert!(join("", "a\n").is_empty());
+        assert!(join("a\nb\n", "c\nd\n").is_empty());
+    }
+
+    fn test_merge_join_bounded_buffers() {
+        // Multiples of 2 against multiples of 3, zero-padded so that they sort as strings. Each
+        // input is about 100 times larger than the buffer budget.
+        let largest_read = Arc::new(AtomicUsize::new(0));
+        let a = GeneratedInput::new(
+            (0..60_000).map(|i| format!("{:08}", i * 2)),
+            largest_read.clone(),
+        );
+        let b = GeneratedInput::new(
+            (0..40_000).map(|i| format!("{:08}", i * 3)),
+            largest_read.clone(),
+        );
+        let a = BufReader::with_capacity(BUFFER_BUDGET / 2, a);
+        let b = BufReader::with_capacity(BUFFER_BUDGET / 2, b);
+
+        let mut expected = (0..).map(|i| i * 6);
+        let count = merge_join_lines(
+            a,
+            b,
+            &CancellationToken::new(),
+            |x, y| x.cmp(y),
+            |i, j, x, _| {
+                let value = expected.next().unwrap();
+                assert_eq!(x, format!("{:08}", value));
+                assert_eq!((i, j), (value / 2, value / 3));
+                Ok(())
+            },
+        )
+        .unwrap();
+        // Multiples of 6 below 120000, where the shorter input ends.
+        assert_eq!(count, 20_000);
+        assert_eq!(largest_read.load(AtomicOrdering::SeqCst), BUFFER_BUDGET / 2);
+    }
+
+    fn test_check_sorted_lines() {
+        let cancellation = CancellationToken::new();
+        let check = |input: &str| {
+            check_sorted_lines(
+                input.as_bytes(),
+                &cancellation,
+                |x, y| x.cmp(y),
+                |line| {
+                    if line.contains(' ') {
+                        bail!("contains a space");
+                    }
+                    Ok(())
+                },
+            )
+        };
+        assert_eq!(check("a\nb\nb\nc\n").unwrap(), 4);
+        assert_eq!(check("").unwrap(), 0);
+        assert_eq!(
+            check("a\nc\nb\nd\na\n").unwrap_err().to_string(),
+            "line 3 is out of order"
+        );
+        assert_eq!(
+            format!("{:#}", check("a\nb c\n").unwrap_err()),
+            "line 2: contains a space"
+        );
+    }
+}
@@ -0,0 +1,354 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::format;
+use std::io::{self, Read, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, DEFAULT_CHUNK_SIZE,
+};
+
+#[derive(Default)]
+pub struct NormalizeEol;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NormalizeEolArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    #[serde(default)]
+    to: LineEnding,
+    /// Whether to end a last line without line ending with one.
+    #[serde(default)]
+    ensure_trailing: bool,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        LineEnding::Lf
+    }
+}
+
+impl LineEnding {
+    fn bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct EolStats {
+    /// Line endings written, the added trailing one included.
+    lines: u64,
+    /// Line endings which were rewritten, i.e. did not match the target already.
+    normalized: u64,
+    /// Line endings which were a CR without LF.
+    lone_cr: u64,
+    trailing_added: bool,
+}
+
+/// Rewrites the line endings of a stream fed in chunks of any size. A CR at the end of a chunk
+/// is held back until the next chunk tells whether it starts a CRLF.
+struct EolNormalizer {
+    to: LineEnding,
+    pending_cr: bool,
+    /// Whether the last byte written ended a line, or nothing was written yet.
+    at_line_start: bool,
+    stats: EolStats,
+}
+
+impl EolNormalizer {
+    fn new(to: LineEnding) -> Self {
+        Self {
+            to,
+            pending_cr: false,
+            at_line_start: true,
+            stats: EolStats::default(),
+        }
+    }
+
+    fn end_line(&mut self, found: LineEnding, lone_cr: bool, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.to.bytes());
+        self.stats.lines += 1;
+        if lone_cr {
+            self.stats.lone_cr += 1;
+        }
+        if lone_cr || found != self.to {
+            self.stats.normalized += 1;
+        }
+        self.at_line_start = true;
+    }
+
+    fn push(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        for &byte in chunk {
+            match byte {
+                b'\n' => {
+                    let found = if self.pending_cr {
+                        LineEnding::Crlf
+                    } else {
+                        LineEnding::Lf
+                    };
+                    self.pending_cr = false;
+                    self.end_line(found, false, out);
+                }
+                b'\r' => {
+                    if self.pending_cr {
+                        self.end_line(LineEnding::Lf, true, out);
+                    }
+                    self.pending_cr = true;
+                }
+                _ => {
+                    if self.pending_cr {
+                        self.pending_cr = false;
+                        self.end_line(LineEnding::Lf, true, out);
+                    }
+                    out.push(byte);
+                    self.at_line_start = false;
+                }
+            }
+        }
+    }
+
+    /// Flushes a held back CR and adds the trailing line ending if asked to. An empty input
+    /// stays empty.
+    fn finish(&mut self, ensure_trailing: bool, out: &mut Vec<u8>) -> EolStats {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.end_line(LineEnding::Lf, true, out);
+        }
+        if ensure_trailing && !self.at_line_start {
+            out.extend_from_slice(self.to.bytes());
+            self.stats.lines += 1;
+            self.stats.trailing_added = true;
+        }
+        self.stats
+    }
+}
+
+/// Reads the next chunk of `input` into `buffer`, retrying interrupted reads.
+fn read_chunk(input: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match input.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+impl NormalizeEol {
+    pub const NAME: &'static str = "builtin-normalize-eol";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("input", ArgumentType::String)
+                    .description("Identifier of the input, \"input\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("output", ArgumentType::String)
+                    .description("Identifier of the output, \"output\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("to", ArgumentType::String)
+                    .one_of(&["lf", "crlf"])
+                    .description("Line ending to write, \"lf\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("ensure_trailing", ArgumentType::Boolean)
+                    .description("Whether to add a line ending after a last line without one"),
+            )
+    }
+
+    /// Copies the input to the output with every line ending, CRLF, LF or a lone CR, replaced
+    /// by the one asked for. The input is streamed in chunks, so its size is not limited.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: NormalizeEolArguments = arguments.into_typed()?;
+        let cancellation = runtime.cancellation();
+
+        let mut input = runtime.open_input(&args.input)?;
+        let mut output = runtime.create_output(&args.output)?;
+        let mut normalizer = EolNormalizer::new(args.to);
+        let mut buffer = vec![0; DEFAULT_CHUNK_SIZE];
+        let mut normalized = Vec::with_capacity(DEFAULT_CHUNK_SIZE);
+        let mut bytes_read = 0u64;
+        let mut bytes_written = 0u64;
+        loop {
+            cancellation.checkpoint()?;
+            let n = read_chunk(input.as_mut(), &mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            bytes_read += n as u64;
+            normalized.clear();
+            normalizer.push(&buffer[..n], &mut normalized);
+            output.write_all(&normalized)?;
+            bytes_written += normalized.len() as u64;
+        }
+        normalized.clear();
+        let stats = normalizer.finish(args.ensure_trailing, &mut normalized);
+        output.write_all(&normalized)?;
+        output.flush()?;
+        bytes_written += normalized.len() as u64;
+
+        let mut message = format!(
+            "Normalized {} of {} line endings to {}",
+            stats.normalized,
+            stats.lines,
+            args.to.name()
+        );
+        if stats.trailing_added {
+            message.push_str(", added a trailing line ending");
+        }
+        Ok(FunctionSummary::new(message)
+            .metric("lines", stats.lines as f64)
+            .metric("normalized_lines", stats.normalized as f64)
+            .metric("lone_cr", stats.lone_cr as f64)
+            .metric("bytes_read", bytes_read as f64)
+            .output(&args.output, OutputInfo::new(bytes_written)))
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_normalize_mixed_endings,
+            test_normalize_chunk_boundaries,
+            test_normalize_trailing,
+        )
+    }
+
+    fn normalize(
+        arguments: serde_json::Value,
+        input: &[u8],
+    ) -> Result<(FunctionSummary, Vec<u8>), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            NormalizeEol::new(),
+            arguments,
+            hashmap!("input" => input),
+            ["output"]
+        );
+        Ok((summary?, outputs.remove("output").unwrap()))
+    }
+
+    fn test_normalize_mixed_endings() {
+        let input = b"unix\nwindows\r\nold mac\rblank next\r\n\r\nlast";
+
+        let (summary, output) = normalize(json!({}), input).unwrap();
+        assert_eq!(
+            output,
+            b"unix\nwindows\nold mac\nblank next\n\nlast".to_vec()
+        );
+        assert_eq!(summary.message, "Normalized 4 of 5 line endings to LF");
+        assert_eq!(summary.metrics["normalized_lines"], 4.0);
+        assert_eq!(summary.metrics["lone_cr"], 1.0);
+        assert_eq!(summary.outputs["output"].size, output.len() as u64);
+
+        let (summary, output) = normalize(json!({"to": "crlf"}), input).unwrap();
+        assert_eq!(
+            output,
+            b"unix\r\nwindows\r\nold mac\r\nblank next\r\n\r\nlast".to_vec()
+        );
+        assert_eq!(summary.message, "Normalized 2 of 5 line endings to CRLF");
+        // Every CR of the output starts a CRLF, every LF ends one.
+        assert!(output
+            .windows(2)
+            .all(|pair| (pair[0] == b'\r') == (pair[1] == b'\n')));
+
+        let error = normalize(json!({"to": "cr"}), input).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
+    }
+
+    fn test_normalize_chunk_boundaries() {
+        let input = b"a\r\nb\r\rc\r";
+        let expected = b"a\nb\n\nc\n".to_vec();
+        // Feeding the input byte by byte splits every CRLF across chunks.
+        let mut normalizer = EolNormalizer::new(LineEnding::Lf);
+        let mut output = Vec::new();
+        for byte in input.iter() {
+            normalizer.push(&[*byte], &mut output);
+        }
+        let stats = normalizer.finish(false, &mut output);
+        assert_eq!(output, expected);
+        assert_eq!(
+            stats,
+            EolStats {
+                lines: 4,
+                normalized: 4,
+                lone_cr: 3,
+                trailing_added: false,
+            }
+        );
+    }
+
+    fn test_normalize_trailing() {
+        let arguments = json!({"to": "crlf", "ensure_trailing": true});
+        let (summary, output) = normalize(arguments.clone(), b"a\nb").unwrap();
+        assert_eq!(output, b"a\r\nb\r\n".to_vec());
+        assert_eq!(
+            summary.message,
+            "Normalized 1 of 2 line endings to CRLF, added a trailing line ending"
+        );
+
+        // Inputs which already end with a line ending, or are empty, are left alone.
+        let (_, output) = normalize(arguments.clone(), b"a\r\n").unwrap();
+        assert_eq!(output, b"a\r\n".to_vec());
+        let (_, output) = normalize(arguments, b"").unwrap();
+        assert!(output.is_empty());
+        let (_, output) = normalize(json!({}), b"a\nb").unwrap();
+        assert_eq!(output, b"a\nb".to_vec());
+    }
+}
@@ -15,112 +15,297 @@
 // specific language governing permissions and limitations
 // under the License.
 
-extern crate base64;
-use anyhow::{anyhow, bail, Result};
-use ring::aead::*;
+use anyhow::{anyhow, bail, ensure, Result};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::digest;
 use std::convert::TryFrom;
+use std::io::{self, Write};
 use std::str;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_types::{
+    FunctionArguments, FunctionRuntime, FunctionSummary, OutputInfo, DEFAULT_CHUNK_SIZE,
+};
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+
+/// Longest key or nonce accepted as an argument.
+const MAX_KEY_LEN: usize = 32;
 
 #[derive(Default)]
 pub struct OnlineDecrypt;
 
-#[derive(serde::Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Algorithm {
+    Aes128Gcm,
+    Aes256Gcm,
+    /// AES-256 in counter mode with a 16-byte initial counter block as nonce. It does not
+    /// authenticate the ciphertext, see `expected_sha256`.
+    Aes256Ctr,
+    ChaCha20Poly1305,
+}
+
+impl TryFrom<&str> for Algorithm {
+    type Error = anyhow::Error;
+
+    fn try_from(algorithm: &str) -> Result<Self> {
+        let algorithm = match algorithm {
+            "aes128gcm" => Algorithm::Aes128Gcm,
+            "aes256gcm" => Algorithm::Aes256Gcm,
+            "aes256ctr" => Algorithm::Aes256Ctr,
+            "chacha20poly1305" => Algorithm::ChaCha20Poly1305,
+            _ => bail!("Invalid algorithm"),
+        };
+        Ok(algorithm)
+    }
+}
+
+impl Algorithm {
+    fn aead(self) -> Option<&'static aead::Algorithm> {
+        match self {
+            Algorithm::Aes128Gcm => Some(&aead::AES_128_GCM),
+            Algorithm::Aes256Gcm => Some(&aead::AES_256_GCM),
+            Algorithm::ChaCha20Poly1305 => Some(&aead::CHACHA20_POLY1305),
+            Algorithm::Aes256Ctr => None,
+        }
+    }
+}
+
 struct OnlineDecryptArguments {
-    key: String,
-    nonce: String,
-    encrypted_data: String,
-    algorithm: String,
+    key: Vec<u8>,
+    nonce: Vec<u8>,
+    algorithm: Algorithm,
+    /// Ciphertext given inline, decrypted into the summary message instead of an output.
+    encrypted_data: Option<Vec<u8>>,
+    /// SHA-256 digest the plaintext must have, for the unauthenticated CTR mode.
+    expected_sha256: Option<Vec<u8>>,
 }
 
 impl TryFrom<FunctionArguments> for OnlineDecryptArguments {
     type Error = anyhow::Error;
 
     fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
-        use anyhow::Context;
-        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+        let algorithm = arguments
+            .get("algorithm")?
+            .as_str()
+            .ok_or_else(|| anyhow!("Argument algorithm is not a string"))?;
+        let encrypted_data = match arguments.get("encrypted_data") {
+            Ok(_) => Some(arguments.get_bytes("encrypted_data", None)?),
+            Err(_) => None,
+        };
+        let expected_sha256 = match arguments.get("expected_sha256") {
+            Ok(value) => {
+                let digest = value
+                    .as_str()
+                    .and_then(|digest| hex::decode(digest).ok())
+                    .filter(|digest| digest.len() == digest::SHA256_OUTPUT_LEN)
+                    .ok_or_else(|| anyhow!("Argument expected_sha256 is not a hex SHA-256"))?;
+                Some(digest)
+            }
+            Err(_) => None,
+        };
+        Ok(Self {
+            key: arguments.get_bytes("key", Some(MAX_KEY_LEN))?,
+            nonce: arguments.get_bytes("nonce", Some(MAX_KEY_LEN))?,
+            algorithm: Algorithm::try_from(algorithm)?,
+            encrypted_data,
+            expected_sha256,
+        })
     }
 }
 
-fn decrypt(
+fn aead_decrypt(
     key: &[u8],
     nonce_data: &[u8],
     data: &mut Vec<u8>,
-    alg: &'static ring::aead::Algorithm,
+    alg: &'static aead::Algorithm,
 ) -> anyhow::Result<()> {
     let key = LessSafeKey::new(UnboundKey::new(alg, key).map_err(|_| anyhow!("decryption error"))?);
-    let nonce = Nonce::try_assume_unique_for_key(&nonce_data[0..12])
-        .map_err(|_| anyhow!("decryption error"))?;
+    let nonce = nonce_data
+        .get(..NONCE_LEN)
+        .and_then(|nonce| Nonce::try_assume_unique_for_key(nonce).ok())
+        .ok_or_else(|| anyhow!("decryption error"))?;
     key.open_in_place(nonce, Aad::empty(), data)
         .map_err(|_| anyhow!("decryption error"))?;
     data.truncate(data.len() - alg.tag_len());
     Ok(())
 }
 
-fn decrypt_string_base64(
-    key: &str,
-    nonce_str: &str,
-    encrypted: &str,
-    alg: &'static ring::aead::Algorithm,
-) -> anyhow::Result<String> {
-    let decoded_key = base64::decode(key)?;
-    let nonce = base64::decode(nonce_str)?;
-    let mut data_vec = base64::decode(encrypted)?;
-
-    decrypt(&decoded_key, &nonce, &mut data_vec, alg).map_err(|_| anyhow!("decryption error"))?;
+fn ctr_cipher(key: &[u8], nonce: &[u8]) -> anyhow::Result<Aes256Ctr> {
+    Aes256Ctr::new_from_slices(key, nonce).map_err(|_| anyhow!("decryption error"))
+}
+
+fn decrypt(args: &OnlineDecryptArguments, data: &mut Vec<u8>) -> anyhow::Result<()> {
+    match args.algorithm.aead() {
+        Some(alg) => aead_decrypt(&args.key, &args.nonce, data, alg),
+        None => {
+            ctr_cipher(&args.key, &args.nonce)?.apply_keystream(data);
+            Ok(())
+        }
+    }
+}
+
+fn check_digest(args: &OnlineDecryptArguments, digest: digest::Digest) -> anyhow::Result<()> {
+    if let Some(expected) = &args.expected_sha256 {
+        ensure!(
+            digest.as_ref() == &expected[..],
+            "Plaintext does not match expected_sha256"
+        );
+    }
+    Ok(())
+}
+
+fn decrypt_string(args: &OnlineDecryptArguments, mut data_vec: Vec<u8>) -> anyhow::Result<String> {
+    decrypt(args, &mut data_vec).map_err(|_| anyhow!("decryption error"))?;
+    check_digest(args, digest::digest(&digest::SHA256, &data_vec))?;
     let string = str::from_utf8(&data_vec).map_err(|_| anyhow!("base64 decoded error"))?;
 
     Ok(string.to_string())
 }
 
 impl OnlineDecrypt {
-    pub const NAME: &'static str = "builtin_online_decrypt";
+    pub const NAME: &'static str = "builtin-online-decrypt";
+    pub const INPUT: &'static str = "ciphertext";
+    pub const OUTPUT: &'static str = "plaintext";
 
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// Decrypts the `encrypted_data` argument into the summary message if it is given, and the
+    /// `ciphertext` input into the `plaintext` output otherwise.
     pub fn run(
         &self,
         arguments: FunctionArguments,
-        _runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
-        let args = OnlineDecryptArguments::try_from(arguments)?;
-        let key = args.key;
-        let nonce = args.nonce;
-        let encrypted_data = args.encrypted_data;
-        let algorithm = &args.algorithm[..];
-
-        let alg = match algorithm {
-            "aes128gcm" => &AES_128_GCM,
-            "aes256gcm" => &AES_256_GCM,
-            _ => bail!("Invalid algorithm"),
-        };
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let mut args = OnlineDecryptArguments::try_from(arguments)?;
+        match args.encrypted_data.take() {
+            Some(encrypted_data) => Ok(decrypt_string(&args, encrypted_data)?.into()),
+            None => {
+                let size = match args.algorithm.aead() {
+                    Some(_) => decrypt_aead_file(&args, &runtime)?,
+                    None => decrypt_ctr_file(&args, &runtime)?,
+                };
+                Ok(FunctionSummary::new(format!("{} bytes decrypted", size))
+                    .metric("bytes", size as f64)
+                    .output(Self::OUTPUT, OutputInfo::new(size)))
+            }
+        }
+    }
+}
 
-        let result = decrypt_string_base64(&key, &nonce, &encrypted_data, alg)?;
-        Ok(result)
+/// AEAD tags cover the whole ciphertext, so it is decrypted in memory and nothing is written
+/// unless it authenticates.
+fn decrypt_aead_file(args: &OnlineDecryptArguments, runtime: &FunctionRuntime) -> Result<u64> {
+    let cancellation = runtime.cancellation();
+    let mut data = Vec::new();
+    for chunk in runtime.open_input_chunked(OnlineDecrypt::INPUT, DEFAULT_CHUNK_SIZE)? {
+        cancellation.checkpoint()?;
+        data.extend_from_slice(&chunk?);
     }
+    decrypt(args, &mut data)?;
+    check_digest(args, digest::digest(&digest::SHA256, &data))?;
+
+    let mut writer = runtime.create_output_chunked(OnlineDecrypt::OUTPUT, DEFAULT_CHUNK_SIZE)?;
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(data.len() as u64)
+}
+
+/// Streams the ciphertext through the CTR keystream. With `expected_sha256`, the plaintext
+/// goes to an encrypted scratch file first and reaches the output only once its digest
+/// matches.
+fn decrypt_ctr_file(args: &OnlineDecryptArguments, runtime: &FunctionRuntime) -> Result<u64> {
+    let cancellation = runtime.cancellation();
+    let mut cipher = ctr_cipher(&args.key, &args.nonce)?;
+    let mut writer = match args.expected_sha256 {
+        Some(_) => runtime.create_scratch(OnlineDecrypt::OUTPUT)?,
+        None => runtime.create_output_chunked(OnlineDecrypt::OUTPUT, DEFAULT_CHUNK_SIZE)?,
+    };
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut size = 0;
+    for chunk in runtime.open_input_chunked(OnlineDecrypt::INPUT, DEFAULT_CHUNK_SIZE)? {
+        cancellation.checkpoint()?;
+        let mut chunk = chunk?;
+        cipher.apply_keystream(&mut chunk);
+        context.update(&chunk);
+        writer.write_all(&chunk)?;
+        size += chunk.len() as u64;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    if args.expected_sha256.is_some() {
+        check_digest(args, context.finish())?;
+        let mut scratch = runtime.open_scratch(OnlineDecrypt::OUTPUT)?;
+        let mut output =
+            runtime.create_output_chunked(OnlineDecrypt::OUTPUT, DEFAULT_CHUNK_SIZE)?;
+        io::copy(&mut scratch, &mut output)?;
+        output.flush()?;
+    }
+    Ok(size)
 }
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
     use serde_json::json;
+    use std::collections::HashMap;
     use teaclave_runtime::*;
     use teaclave_test_utils::*;
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_online_decrypt)
+        run_tests!(
+            test_online_decrypt,
+            test_online_decrypt_short_nonce,
+            test_online_decrypt_files,
+            test_online_decrypt_wrong_key,
+            test_online_decrypt_ctr_digest_mismatch,
+        )
     }
 
+    const KEY: [u8; 32] = [0x2a; 32];
+    const NONCE: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+    const CTR_NONCE: [u8; 16] = [0xf0; 16];
+
     fn test_subroutine(args: FunctionArguments, result: &str) {
         let input_files = StagedFiles::default();
         let output_files = StagedFiles::default();
         let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
         let function = OnlineDecrypt;
         let summary = function.run(args, runtime).unwrap();
-        assert_eq!(summary, result);
+        assert_eq!(summary.message, result);
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn encrypt(algorithm: &str, key: &[u8], nonce: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut data = plaintext.to_vec();
+        match Algorithm::try_from(algorithm).unwrap().aead() {
+            Some(alg) => {
+                let key = LessSafeKey::new(UnboundKey::new(alg, key).unwrap());
+                let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+                key.seal_in_place_append_tag(nonce, Aad::empty(), &mut data)
+                    .unwrap();
+            }
+            None => ctr_cipher(key, nonce).unwrap().apply_keystream(&mut data),
+        }
+        data
+    }
+
+    fn run_decrypt(
+        arguments: serde_json::Value,
+        ciphertext: Vec<u8>,
+    ) -> (anyhow::Result<FunctionSummary>, HashMap<String, Vec<u8>>) {
+        run_builtin!(
+            OnlineDecrypt::new(),
+            arguments,
+            hashmap!(OnlineDecrypt::INPUT => ciphertext),
+            [OnlineDecrypt::OUTPUT]
+        )
     }
 
     fn test_online_decrypt() {
@@ -141,5 +326,108 @@ pub mod tests {
         }))
         .unwrap();
         test_subroutine(args2, "Hello Teaclave!");
+
+        let encrypted = encrypt("chacha20poly1305", &KEY, &NONCE, b"Hello Teaclave!");
+        let args3 = FunctionArguments::from_json(json!({
+            "key": KEY.to_vec(),
+            "nonce": NONCE.to_vec(),
+            "encrypted_data": encrypted,
+            "algorithm": "chacha20poly1305"
+        }))
+        .unwrap();
+        test_subroutine(args3, "Hello Teaclave!");
+    }
+
+    fn test_online_decrypt_short_nonce() {
+        // Binary arguments may also be given as arrays of bytes.
+        let args = FunctionArguments::from_json(json!({
+            "key": "aqUdgZ0lJnuz9yiPkoDxMw==",
+            "nonce": [0, 1, 2, 3],
+            "encrypted_data": "OqMscYqxk1CshHQZulTrrDlJjS/v6BE/clWJyTerUw==",
+            "algorithm": "aes128gcm"
+        }))
+        .unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let error = OnlineDecrypt.run(args, runtime).unwrap_err();
+        assert_eq!(error.to_string(), "decryption error");
+    }
+
+    fn test_online_decrypt_files() {
+        let plaintext = test_data(DEFAULT_CHUNK_SIZE * 2 + 17);
+        for (algorithm, nonce) in [
+            ("aes256gcm", &NONCE[..]),
+            ("chacha20poly1305", &NONCE[..]),
+            ("aes256ctr", &CTR_NONCE[..]),
+        ] {
+            let ciphertext = encrypt(algorithm, &KEY, nonce, &plaintext);
+            let (summary, outputs) = run_decrypt(
+                json!({ "key": KEY.to_vec(), "nonce": nonce, "algorithm": algorithm }),
+                ciphertext,
+            );
+            let summary = summary.unwrap();
+            assert_eq!(outputs[OnlineDecrypt::OUTPUT], plaintext, "{}", algorithm);
+            assert_eq!(
+                summary.outputs[OnlineDecrypt::OUTPUT].size,
+                plaintext.len() as u64
+            );
+        }
+
+        // With a matching digest, the CTR plaintext is released from scratch to the output.
+        let digest = hex::encode(digest::digest(&digest::SHA256, &plaintext));
+        let (summary, outputs) = run_decrypt(
+            json!({
+                "key": KEY.to_vec(),
+                "nonce": CTR_NONCE.to_vec(),
+                "algorithm": "aes256ctr",
+                "expected_sha256": digest,
+            }),
+            encrypt("aes256ctr", &KEY, &CTR_NONCE, &plaintext),
+        );
+        assert!(summary.is_ok());
+        assert_eq!(outputs[OnlineDecrypt::OUTPUT], plaintext);
+    }
+
+    fn test_online_decrypt_wrong_key() {
+        let plaintext = test_data(DEFAULT_CHUNK_SIZE + 5);
+        for algorithm in ["aes256gcm", "chacha20poly1305"] {
+            let ciphertext = encrypt(algorithm, &KEY, &NONCE, &plaintext);
+            let (summary, outputs) = run_decrypt(
+                json!({
+                    "key": vec![0x2b_u8; 32],
+                    "nonce": NONCE.to_vec(),
+                    "algorithm": algorithm,
+                }),
+                ciphertext,
+            );
+            assert_eq!(summary.unwrap_err().to_string(), "decryption error");
+            assert!(
+                !outputs.contains_key(OnlineDecrypt::OUTPUT),
+                "{}",
+                algorithm
+            );
+        }
+    }
+
+    fn test_online_decrypt_ctr_digest_mismatch() {
+        let plaintext = test_data(DEFAULT_CHUNK_SIZE + 5);
+        let mut ciphertext = encrypt("aes256ctr", &KEY, &CTR_NONCE, &plaintext);
+        ciphertext[3] ^= 0x01;
+        let (summary, outputs) = run_decrypt(
+            json!({
+                "key": KEY.to_vec(),
+                "nonce": CTR_NONCE.to_vec(),
+                "algorithm": "aes256ctr",
+                "expected_sha256": hex::encode(digest::digest(&digest::SHA256, &plaintext)),
+            }),
+            ciphertext,
+        );
+        assert_eq!(
+            summary.unwrap_err().to_string(),
+            "Plaintext does not match expected_sha256"
+        );
+        assert!(!outputs.contains_key(OnlineDecrypt::OUTPUT));
     }
 }
@@ -15,12 +15,13 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use anyhow::bail;
-use std::cmp;
+use crate::merge_join::{check_sorted_lines, merge_join_lines};
+use anyhow::{anyhow, bail};
+use std::cmp::Ordering;
 use std::convert::TryFrom;
 use std::format;
-use std::io::{self, BufRead, BufReader, Write};
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use std::io::{self, BufWriter, Write};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
 
 extern crate hex;
 
@@ -55,15 +56,13 @@ impl OrderedSetIntersect {
         Default::default()
     }
 
+    /// Intersects two sorted lists of hashes with a merge join, so that neither input is held
+    /// in memory. Both inputs are checked to be sorted before any output is written.
     pub fn run(
         &self,
         arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
-        let input1 = runtime.open_input(IN_DATA1)?;
-        let input2 = runtime.open_input(IN_DATA2)?;
-        let mut output1 = runtime.create_output(OUT_RESULT1)?;
-        let mut output2 = runtime.create_output(OUT_RESULT2)?;
+    ) -> anyhow::Result<FunctionSummary> {
         let args = OrderedSetIntersectArguments::try_from(arguments)?;
         let order = &args.order[..];
         let ascending_order = match order {
@@ -71,90 +70,91 @@ impl OrderedSetIntersect {
             "desending" => false,
             _ => bail!("Invalid order"),
         };
-
-        let vec1 = parse_input_data(input1, ascending_order)?;
-        let vec2 = parse_input_data(input2, ascending_order)?;
-        let (result1, result2) = intersection_ordered_vec(&vec1, &vec2, ascending_order)?;
-
-        let mut common_sets = 0;
-
-        for item in result1 {
-            write!(&mut output1, "{}", item as u32)?;
-            if item {
-                common_sets += 1;
+        let cmp = move |a: &str, b: &str| {
+            let ordering = hex_cmp(a, b);
+            if ascending_order {
+                ordering
+            } else {
+                ordering.reverse()
             }
+        };
+
+        let cancellation = runtime.cancellation();
+        let identifiers = [IN_DATA1, IN_DATA2];
+        let mut lens = Vec::with_capacity(identifiers.len());
+        for (identifier, input) in identifiers.iter().zip(runtime.open_inputs(&identifiers)?) {
+            let validate = |line: &str| -> anyhow::Result<()> {
+                hex::decode(line)?;
+                Ok(())
+            };
+            let len = check_sorted_lines(input, &cancellation, cmp, validate)
+                .map_err(|e| anyhow!("Invalid {}: {:#}", identifier, e))?;
+            lens.push(len);
         }
-        for item in result2 {
-            write!(&mut output2, "{}", item as u32)?;
-        }
+
+        let mut inputs = runtime.open_inputs(&identifiers)?;
+        let input2 = inputs.pop().unwrap();
+        let input1 = inputs.pop().unwrap();
+        let mut output1 = MembershipWriter::new(runtime.create_output(OUT_RESULT1)?);
+        let mut output2 = MembershipWriter::new(runtime.create_output(OUT_RESULT2)?);
+        let common_sets = merge_join_lines(input1, input2, &cancellation, cmp, |i, j, _, _| {
+            output1.mark(i)?;
+            output2.mark(j)?;
+            Ok(())
+        })?;
+        output1.finish(lens[0])?;
+        output2.finish(lens[1])?;
 
         log::trace!("{}", common_sets);
 
-        Ok(format!("{} common items", common_sets))
+        Ok(format!("{} common items", common_sets).into())
     }
 }
 
-fn parse_input_data(input: impl io::Read, ascending_order: bool) -> anyhow::Result<Vec<Vec<u8>>> {
-    let mut samples: Vec<Vec<u8>> = Vec::new();
-    let reader = BufReader::new(input);
-    for (index, byte_result) in reader.lines().enumerate() {
-        let byte = byte_result?;
-        let result = hex::decode(byte)?;
-        if index > 0 {
-            // If vec has more than 2 elements, then verify the ordering
-            let last_element = &samples[index - 1];
-            if ascending_order && result < *last_element
-                || !ascending_order && result > *last_element
-            {
-                bail!("Invalid ordering");
-            }
+/// Orders hex strings like the bytes they encode. Both are compared digit by digit, which
+/// gives the same order for valid hex regardless of case.
+fn hex_cmp(a: &str, b: &str) -> Ordering {
+    let a = a.bytes().map(|c| c.to_ascii_lowercase());
+    let b = b.bytes().map(|c| c.to_ascii_lowercase());
+    a.cmp(b)
+}
+
+/// Writes for every line of an input whether it is in the intersection, as '1' or '0'.
+struct MembershipWriter {
+    output: BufWriter<Box<dyn Write>>,
+    written: usize,
+}
+
+impl MembershipWriter {
+    fn new(output: Box<dyn Write>) -> Self {
+        Self {
+            output: BufWriter::new(output),
+            written: 0,
         }
-        samples.push(result)
     }
 
-    Ok(samples)
-}
+    /// Marks the line at `index` as common, and the lines before it which were not marked yet
+    /// as not.
+    fn mark(&mut self, index: usize) -> io::Result<()> {
+        self.fill(index)?;
+        self.output.write_all(b"1")?;
+        self.written += 1;
+        Ok(())
+    }
 
-fn intersection_ordered_vec(
-    input1: &[Vec<u8>],
-    input2: &[Vec<u8>],
-    ascending_order: bool,
-) -> anyhow::Result<(Vec<bool>, Vec<bool>)> {
-    let v1_len = input1.len();
-    let v2_len = input2.len();
-
-    let mut res1 = std::vec![false; v1_len];
-    let mut res2 = std::vec![false; v2_len];
-
-    let mut i = 0;
-    let mut j = 0;
-
-    while i < v1_len && j < v2_len {
-        let order = &input1[i].cmp(&input2[j]);
-        match order {
-            cmp::Ordering::Equal => {
-                res1[i] = true;
-                res2[j] = true;
-                i += 1;
-                j += 1;
-            }
-            cmp::Ordering::Less => {
-                if ascending_order {
-                    i += 1;
-                } else {
-                    j += 1;
-                }
-            }
-            cmp::Ordering::Greater => {
-                if ascending_order {
-                    j += 1;
-                } else {
-                    i += 1;
-                }
-            }
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        while self.written < len {
+            self.output.write_all(b"0")?;
+            self.written += 1;
         }
+        Ok(())
+    }
+
+    /// Marks the remaining lines of an input of `len` lines as not common.
+    fn finish(mut self, len: usize) -> io::Result<()> {
+        self.fill(len)?;
+        self.output.flush()
     }
-    Ok((res1, res2))
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -169,7 +169,11 @@ pub mod tests {
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_ordered_set_intersect)
+        run_tests!(
+            test_ordered_set_intersect,
+            test_ordered_set_intersect_memory,
+            test_ordered_set_intersect_unsorted,
+        )
     }
 
     fn test_ordered_set_intersect() {
@@ -208,6 +212,58 @@ pub mod tests {
 
         assert_eq!(&user1_result[..], "0101010");
         assert_eq!(&user2_result[..], "01101");
-        assert_eq!(summary, "3 common items");
+        assert_eq!(summary.message, "3 common items");
+    }
+
+    fn intersect_memory(
+        order: &str,
+        input1: &str,
+        input2: &str,
+    ) -> anyhow::Result<(String, String, FunctionSummary)> {
+        let (summary, mut outputs) = run_builtin!(
+            OrderedSetIntersect::new(),
+            json!({ "order": order }),
+            hashmap!(IN_DATA1 => input1, IN_DATA2 => input2),
+            [OUT_RESULT1, OUT_RESULT2]
+        );
+        let result1 = String::from_utf8(outputs.remove(OUT_RESULT1).unwrap()).unwrap();
+        let result2 = String::from_utf8(outputs.remove(OUT_RESULT2).unwrap()).unwrap();
+        Ok((result1, result2, summary?))
+    }
+
+    fn test_ordered_set_intersect_memory() {
+        let (result1, result2, summary) =
+            intersect_memory("ascending", "01\n0a\n0B\nff\n", "0A\n0b\n10\n").unwrap();
+        assert_eq!(result1, "0110");
+        assert_eq!(result2, "110");
+        assert_eq!(summary.message, "2 common items");
+
+        let (result1, result2, _) =
+            intersect_memory("desending", "ff\n10\n01\n", "ff\n02\n01\n00\n").unwrap();
+        assert_eq!(result1, "101");
+        assert_eq!(result2, "1010");
+
+        let (result1, result2, _) = intersect_memory("ascending", "", "01\n").unwrap();
+        assert_eq!(result1, "");
+        assert_eq!(result2, "0");
+    }
+
+    fn test_ordered_set_intersect_unsorted() {
+        let err = intersect_memory("ascending", "01\n02\n", "01\n03\n02\n04\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid input_data2: line 3 is out of order"
+        );
+
+        let err = intersect_memory("desending", "01\n02\n", "02\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid input_data1: line 2 is out of order"
+        );
+
+        let err = intersect_memory("ascending", "01\nzz\n", "01\n").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Invalid input_data1: line 2: Invalid character"));
     }
 }
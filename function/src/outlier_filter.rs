@@ -0,0 +1,541 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{
+    check_format, open_sniffed, split_fields, unquote, DetectedFormat, ExpectedFormat,
+};
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, ResourceLimitExceeded, CANCELLATION_CHECK_INTERVAL,
+    DEFAULT_CHUNK_SIZE,
+};
+
+const IN_DATASET: &str = "dataset";
+const OUT_RESULT: &str = "result";
+/// Scratch file holding the data rows between the two passes.
+const SCRATCH_ROWS: &str = "rows";
+/// Name of the column added by the "flag" action to datasets with a header.
+const FLAG_COLUMN: &str = "outlier";
+/// Memory for the values kept for the IQR if the execution environment has no memory budget.
+const DEFAULT_VALUE_BUFFER_BYTES: u64 = 64 * 1024 * 1024;
+/// Bytes a value kept for the IQR may take: 8, and as much again while its vector grows.
+const VALUE_BYTES: u64 = 2 * std::mem::size_of::<f64>() as u64;
+
+#[derive(Default)]
+pub struct OutlierFilter;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutlierFilterArguments {
+    /// Columns to check: names as in the header row, or indices if the dataset has none.
+    columns: Vec<String>,
+    #[serde(default)]
+    method: Method,
+    /// Z-scores beyond which, or multiples of the IQR outside the quartiles beyond which, a
+    /// value is an outlier. 3 for z-scores and 1.5 for the IQR by default.
+    #[serde(default)]
+    threshold: Option<f64>,
+    #[serde(default)]
+    action: Action,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Method {
+    Zscore,
+    Iqr,
+}
+
+impl Default for Method {
+    fn default() -> Self {
+        Method::Zscore
+    }
+}
+
+impl Method {
+    fn default_threshold(self) -> f64 {
+        match self {
+            Method::Zscore => 3.0,
+            Method::Iqr => 1.5,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Method::Zscore => "z-score",
+            Method::Iqr => "IQR",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    /// Keeps all rows and adds a column telling whether each is an outlier.
+    Flag,
+    /// Leaves out the rows with an outlier in any of the columns.
+    Drop,
+}
+
+impl Default for Action {
+    fn default() -> Self {
+        Action::Flag
+    }
+}
+
+/// Statistics of the numeric values of a column from the first pass. The variance is
+/// accumulated with Welford's method; for the IQR the values themselves are kept, 8 bytes
+/// each, as the quartiles can't be computed from a stream.
+struct ColumnStats {
+    name: String,
+    index: usize,
+    count: u64,
+    mean: f64,
+    m2: f64,
+    values: Vec<f64>,
+    outliers: u64,
+}
+
+impl ColumnStats {
+    fn new(name: String, index: usize) -> Self {
+        Self {
+            name,
+            index,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            values: Vec::new(),
+            outliers: 0,
+        }
+    }
+
+    fn add(&mut self, x: f64, method: Method) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+        if method == Method::Iqr {
+            self.values.push(x);
+        }
+    }
+
+    /// The range of values which are not outliers. Columns without any spread, or without
+    /// values, have no outliers, rather than dividing by a zero deviation.
+    fn bounds(&mut self, method: Method, threshold: f64) -> (f64, f64) {
+        let stddev = (self.m2 / self.count.max(1) as f64).sqrt();
+        if self.count == 0 || stddev == 0.0 {
+            return (f64::NEG_INFINITY, f64::INFINITY);
+        }
+        match method {
+            Method::Zscore => (
+                self.mean - threshold * stddev,
+                self.mean + threshold * stddev,
+            ),
+            Method::Iqr => {
+                self.values
+                    .sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let q1 = quantile(&self.values, 0.25);
+                let q3 = quantile(&self.values, 0.75);
+                let iqr = q3 - q1;
+                // The values are not needed for the second pass.
+                self.values = Vec::new();
+                (q1 - threshold * iqr, q3 + threshold * iqr)
+            }
+        }
+    }
+}
+
+/// Quantile `p` of sorted `values`, interpolating linearly between the closest ranks.
+fn quantile(values: &[f64], p: f64) -> f64 {
+    let rank = p * (values.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    values[lower] + (values[upper] - values[lower]) * (rank - lower as f64)
+}
+
+/// Parses a field as a finite number. Empty and non-numeric fields are not checked.
+fn parse_value(field: Option<&str>) -> Option<f64> {
+    let value = unquote(field?.trim()).trim().parse::<f64>().ok()?;
+    Some(value).filter(|value| value.is_finite())
+}
+
+/// Finds the columns to check in the header, or parses them as indices without one.
+fn resolve_columns(
+    columns: &[String],
+    header: Option<&[&str]>,
+) -> Result<Vec<ColumnStats>, FunctionError> {
+    columns
+        .iter()
+        .map(|column| {
+            let index = match header {
+                Some(names) => names.iter().position(|name| unquote(name.trim()) == column),
+                None => column.parse().ok(),
+            };
+            index
+                .map(|index| ColumnStats::new(column.clone(), index))
+                .ok_or_else(|| match header {
+                    Some(_) => FunctionError::invalid_arguments(format!(
+                        "column '{}' is not a column of the dataset",
+                        column
+                    )),
+                    None => FunctionError::invalid_arguments(format!(
+                        "column '{}' must be a column index, as the dataset has no header",
+                        column
+                    )),
+                })
+        })
+        .collect()
+}
+
+impl OutlierFilter {
+    pub const NAME: &'static str = "builtin-outlier-filter";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("columns", ArgumentType::Array)
+                    .required()
+                    .description("Columns to check, by name or by index without a header"),
+            )
+            .argument(
+                ArgumentSpec::new("method", ArgumentType::String)
+                    .one_of(&["zscore", "iqr"])
+                    .description("How outliers are found, \"zscore\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("threshold", ArgumentType::Number).description(
+                    "Z-score, or multiple of the IQR, beyond which values are outliers",
+                ),
+            )
+            .argument(
+                ArgumentSpec::new("action", ArgumentType::String)
+                    .one_of(&["flag", "drop"])
+                    .description("Whether to flag or to drop outlier rows, \"flag\" by default"),
+            )
+    }
+
+    /// Finds the rows of a CSV dataset with an outlier in any of the given columns, and either
+    /// flags them in an added column or drops them. The first pass computes the statistics of
+    /// the columns and copies the rows to scratch, the second reads them back and filters them.
+    /// The values kept for the IQR must fit into the memory budget of the execution
+    /// environment, or the function fails with `ResourceLimitExceeded::MemoryBudget`.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: OutlierFilterArguments = arguments.into_typed()?;
+        if args.columns.is_empty() {
+            return Err(FunctionError::invalid_arguments(
+                "columns must not be empty",
+            ));
+        }
+        let threshold = args
+            .threshold
+            .unwrap_or_else(|| args.method.default_threshold());
+        if !(threshold.is_finite() && threshold > 0.0) {
+            return Err(FunctionError::invalid_arguments(
+                "threshold must be a positive number",
+            ));
+        }
+
+        let (detected, dataset) = open_sniffed(runtime.as_ref(), IN_DATASET)?;
+        check_format(Self::NAME, IN_DATASET, detected, ExpectedFormat::Csv)?;
+        let (delimiter, has_header) = match detected {
+            DetectedFormat::Csv { delimiter, header } => (delimiter, header),
+            _ => (',', false),
+        };
+
+        let memory_budget = runtime
+            .environment()
+            .memory_budget_bytes
+            .unwrap_or(DEFAULT_VALUE_BUFFER_BYTES);
+        let max_values = match args.method {
+            Method::Iqr => memory_budget / VALUE_BYTES,
+            Method::Zscore => u64::MAX,
+        };
+
+        // First pass: statistics of the columns, and a copy of the data rows.
+        let cancellation = runtime.cancellation();
+        let mut values = 0u64;
+        let mut lines = BufReader::new(dataset).lines();
+        let header = if has_header {
+            lines
+                .next()
+                .transpose()?
+                .map(|line| line.trim_end_matches('\r').to_string())
+        } else {
+            None
+        };
+        let header_fields = header.as_deref().map(|line| split_fields(line, delimiter));
+        let mut columns = resolve_columns(&args.columns, header_fields.as_deref())?;
+        let mut scratch = runtime.create_scratch(SCRATCH_ROWS)?;
+        let mut rows = 0u64;
+        for (i, line) in lines.enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_fields(line, delimiter);
+            for column in &mut columns {
+                if let Some(x) = parse_value(fields.get(column.index).copied()) {
+                    values += 1;
+                    if values > max_values {
+                        return Err(ResourceLimitExceeded::MemoryBudget(memory_budget).into());
+                    }
+                    column.add(x, args.method);
+                }
+            }
+            scratch.write_all(line.as_bytes())?;
+            scratch.write_all(b"\n")?;
+            rows += 1;
+        }
+        scratch.flush()?;
+        drop(scratch);
+        runtime.report_progress(0.5, "computed column statistics");
+
+        // Second pass: check the rows against the bounds of the columns.
+        let bounds: Vec<(f64, f64)> = columns
+            .iter_mut()
+            .map(|column| column.bounds(args.method, threshold))
+            .collect();
+        let mut output = runtime.create_output_chunked(OUT_RESULT, DEFAULT_CHUNK_SIZE)?;
+        let mut written = 0u64;
+        let mut write_line = |line: &str| -> std::io::Result<()> {
+            output.write_all(line.as_bytes())?;
+            output.write_all(b"\n")?;
+            written += line.len() as u64 + 1;
+            Ok(())
+        };
+        if let Some(header) = &header {
+            match args.action {
+                Action::Flag => write_line(&format!("{}{}{}", header, delimiter, FLAG_COLUMN))?,
+                Action::Drop => write_line(header)?,
+            }
+        }
+        let mut outlier_rows = 0u64;
+        let scratch = BufReader::new(runtime.open_scratch(SCRATCH_ROWS)?);
+        for (i, line) in scratch.lines().enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let fields = split_fields(&line, delimiter);
+            let mut outlier = false;
+            for (column, &(low, high)) in columns.iter_mut().zip(&bounds) {
+                if let Some(x) = parse_value(fields.get(column.index).copied()) {
+                    if x < low || x > high {
+                        column.outliers += 1;
+                        outlier = true;
+                    }
+                }
+            }
+            if outlier {
+                outlier_rows += 1;
+            }
+            match args.action {
+                Action::Flag => write_line(&format!("{}{}{}", line, delimiter, outlier))?,
+                Action::Drop if !outlier => write_line(&line)?,
+                Action::Drop => {}
+            }
+        }
+        output.flush()?;
+
+        let verb = match args.action {
+            Action::Flag => "Flagged",
+            Action::Drop => "Dropped",
+        };
+        let mut summary = FunctionSummary::new(format!(
+            "{} {} of {} rows with {} outliers in {} columns",
+            verb,
+            outlier_rows,
+            rows,
+            args.method.name(),
+            columns.len()
+        ))
+        .metric("rows", rows as f64)
+        .metric("outlier_rows", outlier_rows as f64)
+        .output(OUT_RESULT, OutputInfo::new(written));
+        for column in &columns {
+            summary = summary.metric(format!("outliers.{}", column.name), column.outliers as f64);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_outlier_filter_zscore,
+            test_outlier_filter_iqr,
+            test_outlier_filter_zero_variance,
+            test_outlier_filter_memory_budget,
+            test_outlier_filter_arguments,
+        )
+    }
+
+    fn outlier_filter(
+        arguments: serde_json::Value,
+        dataset: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        outlier_filter_in(ExecutionEnvironment::new(), arguments, dataset)
+    }
+
+    fn outlier_filter_in(
+        environment: ExecutionEnvironment,
+        arguments: serde_json::Value,
+        dataset: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            OutlierFilter::new(),
+            arguments,
+            hashmap!(IN_DATASET => dataset),
+            [OUT_RESULT],
+            with_environment(environment)
+        );
+        let result = String::from_utf8(outputs.remove(OUT_RESULT).unwrap()).unwrap();
+        Ok((summary?, result))
+    }
+
+    // The amounts have a mean of 22.375 and a standard deviation of 29.355, so 100 has a
+    // z-score of 2.64 and all others are within 0.43.
+    const AMOUNTS: &str = "id,amount,age\n\
+                           a,10,30\n\
+                           b,12,30\n\
+                           c,11,30\n\
+                           d,13,30\n\
+                           e,n/a,30\n\
+                           f,12,30\n\
+                           g,11,30\n\
+                           h,10,30\n\
+                           i,100,30\n";
+
+    fn test_outlier_filter_zscore() {
+        let arguments = json!({"columns": ["amount"], "threshold": 2.0});
+        let (summary, result) = outlier_filter(arguments, AMOUNTS).unwrap();
+        assert_eq!(
+            summary.message,
+            "Flagged 1 of 9 rows with z-score outliers in 1 columns"
+        );
+        assert_eq!(summary.metrics["outliers.amount"], 1.0);
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!(lines[0], "id,amount,age,outlier");
+        assert_eq!(lines[1], "a,10,30,false");
+        // Values which are not numbers are never outliers.
+        assert_eq!(lines[5], "e,n/a,30,false");
+        assert_eq!(lines[9], "i,100,30,true");
+        assert_eq!(summary.outputs[OUT_RESULT].size, result.len() as u64);
+
+        // 2.64 is within the default threshold of 3.
+        let (summary, _) = outlier_filter(json!({"columns": ["amount"]}), AMOUNTS).unwrap();
+        assert_eq!(summary.metrics["outlier_rows"], 0.0);
+
+        let arguments = json!({"columns": ["amount"], "threshold": 2.0, "action": "drop"});
+        let (summary, result) = outlier_filter(arguments, AMOUNTS).unwrap();
+        assert_eq!(
+            summary.message,
+            "Dropped 1 of 9 rows with z-score outliers in 1 columns"
+        );
+        assert_eq!(result, AMOUNTS.replace("i,100,30\n", ""));
+    }
+
+    fn test_outlier_filter_iqr() {
+        // Sorted, the values are -10, 2, ..., 9, 14.5, 50: the quartiles are 3.5 and 8.5 at
+        // ranks 2.5 and 7.5, the IQR 5 and the bounds -4 and 16.
+        let dataset = "7\t1\n-10\t1\n3\t1\n50\t1\n2\t1\n9\t1\n5\t1\n14.5\t1\n4\t1\n6\t1\n8\t1\n";
+        // Without a header, columns are given by index.
+        let arguments = json!({"columns": ["0"], "method": "iqr", "action": "drop"});
+        let (summary, result) = outlier_filter(arguments, dataset).unwrap();
+        assert_eq!(
+            summary.message,
+            "Dropped 2 of 11 rows with IQR outliers in 1 columns"
+        );
+        assert_eq!(summary.metrics["outliers.0"], 2.0);
+        assert_eq!(
+            result,
+            dataset.replace("-10\t1\n", "").replace("50\t1\n", "")
+        );
+
+        let arguments = json!({"columns": ["0"], "method": "iqr", "threshold": 3.0});
+        let (summary, result) = outlier_filter(arguments, dataset).unwrap();
+        // The bounds are -11.5 and 23.5 now.
+        assert_eq!(summary.metrics["outlier_rows"], 1.0);
+        assert!(result.starts_with("7\t1\tfalse\n-10\t1\tfalse\n3\t1\tfalse\n50\t1\ttrue\n"));
+    }
+
+    fn test_outlier_filter_zero_variance() {
+        for method in &["zscore", "iqr"] {
+            let arguments =
+                json!({"columns": ["age", "amount"], "method": method, "threshold": 0.5});
+            let (summary, _) = outlier_filter(arguments, AMOUNTS).unwrap();
+            assert_eq!(summary.metrics["outliers.age"], 0.0);
+            assert!(summary.metrics["outliers.amount"] > 0.0);
+        }
+    }
+
+    fn test_outlier_filter_memory_budget() {
+        // AMOUNTS has 8 numeric amounts and 9 ages, 17 values to keep for both columns.
+        let arguments = json!({"columns": ["amount", "age"], "method": "iqr"});
+        let environment = ExecutionEnvironment::new().memory_budget_bytes(Some(17 * VALUE_BYTES));
+        assert!(outlier_filter_in(environment, arguments.clone(), AMOUNTS).is_ok());
+
+        let budget = 16 * VALUE_BYTES;
+        let environment = ExecutionEnvironment::new().memory_budget_bytes(Some(budget));
+        let error = outlier_filter_in(environment.clone(), arguments, AMOUNTS).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::ResourceLimit);
+        assert_eq!(
+            error.to_string(),
+            ResourceLimitExceeded::MemoryBudget(budget).to_string()
+        );
+
+        // Z-scores keep no values, so any budget will do.
+        let arguments = json!({"columns": ["amount", "age"]});
+        assert!(outlier_filter_in(environment, arguments, AMOUNTS).is_ok());
+    }
+
+    fn test_outlier_filter_arguments() {
+        let error = outlier_filter(json!({"columns": ["price"]}), AMOUNTS).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid arguments: column 'price' is not a column of the dataset"
+        );
+        let error = outlier_filter(json!({"columns": []}), AMOUNTS).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid arguments: columns must not be empty"
+        );
+        let arguments = json!({"columns": ["amount"], "threshold": 0.0});
+        assert!(outlier_filter(arguments, AMOUNTS).is_err());
+        let arguments = json!({"columns": ["amount"], "method": "mad"});
+        assert!(outlier_filter(arguments, AMOUNTS).is_err());
+    }
+}
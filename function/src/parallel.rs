@@ -0,0 +1,204 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use anyhow::{bail, Context};
+use std::sync::Arc;
+use std::thread;
+use teaclave_types::{ExecutionLog, FunctionRuntime};
+
+/// Checks a requested thread count against the ceiling granted by the runtime.
+pub(crate) fn check_num_threads(
+    num_threads: usize,
+    runtime: &FunctionRuntime,
+) -> anyhow::Result<usize> {
+    let max = runtime.max_parallelism();
+    if num_threads == 0 || num_threads > max {
+        bail!("num_threads must be between 1 and {}", max);
+    }
+    Ok(num_threads)
+}
+
+/// Thread count of a function: `requested` if the task sets it, otherwise a single thread, as
+/// before functions could run on several. Either is checked with `check_num_threads`.
+pub(crate) fn resolve_num_threads(
+    requested: Option<usize>,
+    runtime: &FunctionRuntime,
+) -> anyhow::Result<usize> {
+    check_num_threads(requested.unwrap_or(1), runtime)
+}
+
+/// Splits `items` into `num_threads` contiguous chunks, maps every chunk on its own thread and
+/// concatenates the results in the order of the chunks. The result is therefore the same for
+/// any number of threads, provided that `f` maps each item independently. The threads log into
+/// the execution log of the calling thread.
+pub(crate) fn map_chunks<T, R, F>(items: Vec<T>, num_threads: usize, f: F) -> anyhow::Result<Vec<R>>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(Vec<T>) -> anyhow::Result<Vec<R>> + Send + Sync + 'static,
+{
+    if num_threads <= 1 || items.len() <= 1 {
+        return f(items);
+    }
+
+    let chunk_size = (items.len() + num_threads - 1) / num_threads;
+    let mut chunks = Vec::with_capacity(num_threads);
+    let mut rest = items;
+    while rest.len() > chunk_size {
+        let tail = rest.split_off(chunk_size);
+        chunks.push(rest);
+        rest = tail;
+    }
+    chunks.push(rest);
+
+    let f = Arc::new(f);
+    let log = ExecutionLog::current();
+    let handles = chunks
+        .into_iter()
+        .map(|chunk| {
+            let f = f.clone();
+            let log = log.clone();
+            thread::Builder::new()
+                .spawn(move || match log {
+                    Some(log) => log.scope(|| f(chunk)),
+                    None => f(chunk),
+                })
+                .context("Cannot spawn worker thread")
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut results = Vec::new();
+    for handle in handles {
+        match handle.join() {
+            Ok(result) => results.extend(result?),
+            Err(_) => bail!("Worker thread panicked"),
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_map_chunks_order,
+            test_map_chunks_error,
+            test_map_chunks_log_scope,
+            test_check_num_threads,
+            test_resolve_num_threads,
+        )
+    }
+
+    // Deliberately slow and order-sensitive within an item, so that results would differ if
+    // items were mixed up between threads.
+    fn score(x: u64, rounds: u64) -> u64 {
+        (0..rounds).fold(x, |acc, i| {
+            acc.wrapping_mul(6364136223846793005).wrapping_add(i ^ x)
+        })
+    }
+
+    fn test_map_chunks_order() {
+        let items: Vec<u64> = (0..1003).collect();
+        let expected: Vec<u64> = items.iter().map(|&x| score(x, 100)).collect();
+        for num_threads in 1..=8 {
+            let result = map_chunks(items.clone(), num_threads, |chunk| {
+                Ok(chunk.into_iter().map(|x| score(x, 100)).collect())
+            })
+            .unwrap();
+            assert_eq!(result, expected);
+        }
+        assert!(map_chunks(Vec::<u64>::new(), 4, |chunk| Ok(chunk))
+            .unwrap()
+            .is_empty());
+    }
+
+    fn test_map_chunks_error() {
+        let result: anyhow::Result<Vec<u64>> = map_chunks((0..100).collect(), 4, |chunk| {
+            if chunk.contains(&60) {
+                bail!("bad item");
+            }
+            Ok(chunk)
+        });
+        assert_eq!(result.unwrap_err().to_string(), "bad item");
+    }
+
+    fn test_map_chunks_log_scope() {
+        let log = ExecutionLog::default();
+        let result = log
+            .scope(|| {
+                map_chunks((0..4).collect(), 4, |chunk: Vec<u64>| {
+                    let log = ExecutionLog::current().expect("no execution log");
+                    for x in &chunk {
+                        log.log(log::Level::Info, &format!("item {}", x));
+                    }
+                    Ok(chunk)
+                })
+            })
+            .unwrap();
+        assert_eq!(result, vec![0, 1, 2, 3]);
+
+        let mut lines = log.drain();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "[INFO] item 0",
+                "[INFO] item 1",
+                "[INFO] item 2",
+                "[INFO] item 3"
+            ]
+        );
+        assert!(ExecutionLog::current().is_none());
+    }
+
+    fn test_check_num_threads() {
+        let runtime: FunctionRuntime = Box::new(
+            RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+                .with_max_parallelism(4),
+        );
+        assert_eq!(check_num_threads(4, &runtime).unwrap(), 4);
+        assert_eq!(
+            check_num_threads(5, &runtime).unwrap_err().to_string(),
+            "num_threads must be between 1 and 4"
+        );
+        assert!(check_num_threads(0, &runtime).is_err());
+    }
+
+    fn test_resolve_num_threads() {
+        let runtime: FunctionRuntime = Box::new(
+            RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+                .with_environment(ExecutionEnvironment::new().max_parallelism(3)),
+        );
+        // More threads are only used when asked for, up to the ceiling of the environment.
+        assert_eq!(resolve_num_threads(None, &runtime).unwrap(), 1);
+        assert_eq!(resolve_num_threads(Some(3), &runtime).unwrap(), 3);
+        assert!(resolve_num_threads(Some(4), &runtime).is_err());
+
+        // Runtimes without an environment of their own grant a single thread.
+        let runtime: FunctionRuntime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        assert_eq!(resolve_num_threads(None, &runtime).unwrap(), 1);
+        assert!(resolve_num_threads(Some(2), &runtime).is_err());
+    }
+}
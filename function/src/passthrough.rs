@@ -0,0 +1,217 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::HashSet;
+use std::format;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+    DEFAULT_CHUNK_SIZE,
+};
+
+#[derive(Default)]
+pub struct Passthrough;
+
+#[derive(serde::Deserialize)]
+struct PassthroughArguments {
+    /// Offset of a byte to invert in every copied file, so that tests can tamper with outputs.
+    flip_byte_at: Option<u64>,
+    /// Time to sleep after every chunk, so that tests can run into timeouts.
+    #[serde(default)]
+    delay_ms_per_chunk: u64,
+}
+
+impl Passthrough {
+    pub const NAME: &'static str = "builtin-passthrough";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Copies every input to the output of the same name.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: PassthroughArguments = arguments.into_typed()?;
+        let cancellation = runtime.cancellation();
+        let outputs: HashSet<String> = runtime.output_keys().into_iter().collect();
+
+        let mut summary_outputs = Vec::new();
+        let mut warnings = Vec::new();
+        let mut total_bytes = 0;
+        for key in runtime.input_keys() {
+            if !outputs.contains(&key) {
+                warnings.push(format!("No output for input {}", key));
+                continue;
+            }
+
+            let mut writer = runtime.create_output_chunked(&key, DEFAULT_CHUNK_SIZE)?;
+            let mut offset = 0;
+            for chunk in runtime.open_input_chunked(&key, DEFAULT_CHUNK_SIZE)? {
+                cancellation.checkpoint()?;
+                let mut chunk = chunk?;
+                if let Some(flip) = args.flip_byte_at {
+                    if (offset..offset + chunk.len() as u64).contains(&flip) {
+                        chunk[(flip - offset) as usize] ^= 0xff;
+                    }
+                }
+                writer.write_all(&chunk)?;
+                offset += chunk.len() as u64;
+                if args.delay_ms_per_chunk > 0 {
+                    thread::sleep(Duration::from_millis(args.delay_ms_per_chunk));
+                }
+            }
+            writer.flush()?;
+
+            if matches!(args.flip_byte_at, Some(flip) if flip >= offset) {
+                warnings.push(format!("Input {} has no byte to flip", key));
+            }
+            total_bytes += offset;
+            summary_outputs.push((key, offset));
+        }
+
+        let mut summary = FunctionSummary::new(format!(
+            "{} files copied, {} bytes",
+            summary_outputs.len(),
+            total_bytes
+        ))
+        .metric("files", summary_outputs.len() as f64)
+        .metric("bytes", total_bytes as f64);
+        for (key, bytes) in summary_outputs {
+            summary = summary.output(key, OutputInfo::new(bytes));
+        }
+        for warning in warnings {
+            summary = summary.warning(warning);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::Instant;
+    use std::untrusted::time::InstantEx;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_passthrough_copies_inputs,
+            test_passthrough_flip_byte,
+            test_passthrough_delay,
+        )
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn run_passthrough(
+        arguments: serde_json::Value,
+        inputs: HashMap<String, Vec<u8>>,
+        outputs: &[&str],
+        cancellation: CancellationToken,
+    ) -> Result<(FunctionSummary, HashMap<String, Vec<u8>>), FunctionError> {
+        let (summary, buffers) = run_builtin!(
+            Passthrough::new(),
+            arguments,
+            inputs,
+            outputs,
+            with_cancellation(cancellation)
+        );
+        Ok((summary?, buffers))
+    }
+
+    fn test_passthrough_copies_inputs() {
+        let large = test_data(DEFAULT_CHUNK_SIZE * 2 + 17);
+        let inputs = hashmap!(
+            "input_a" => large.clone(),
+            "input_b" => b"small".to_vec(),
+            "input_c" => Vec::new(),
+            "unmatched" => b"no slot".to_vec(),
+        );
+        let outputs = ["input_a", "input_b", "input_c"];
+        let (summary, copied) =
+            run_passthrough(json!({}), inputs, &outputs, CancellationToken::new()).unwrap();
+
+        assert_eq!(copied["input_a"], large);
+        assert_eq!(copied["input_b"], b"small");
+        assert!(copied["input_c"].is_empty());
+        assert!(!copied.contains_key("unmatched"));
+
+        assert_eq!(summary.outputs["input_a"].size, large.len() as u64);
+        assert_eq!(summary.outputs["input_b"].size, 5);
+        assert_eq!(summary.outputs["input_c"].size, 0);
+        assert_eq!(summary.metrics["files"], 3.0);
+        assert_eq!(summary.warnings, vec!["No output for input unmatched"]);
+    }
+
+    fn test_passthrough_flip_byte() {
+        let data = test_data(DEFAULT_CHUNK_SIZE + 10);
+        let flip = DEFAULT_CHUNK_SIZE as u64 + 3;
+        let inputs = hashmap!("input_a" => data.clone(), "input_b" => b"short".to_vec());
+        let (summary, copied) = run_passthrough(
+            json!({ "flip_byte_at": flip }),
+            inputs,
+            &["input_a", "input_b"],
+            CancellationToken::new(),
+        )
+        .unwrap();
+
+        let tampered = &copied["input_a"];
+        assert_eq!(tampered.len(), data.len());
+        let differing: Vec<usize> = (0..data.len())
+            .filter(|&i| tampered[i] != data[i])
+            .collect();
+        assert_eq!(differing, vec![flip as usize]);
+        assert_eq!(tampered[flip as usize], !data[flip as usize]);
+
+        assert_eq!(copied["input_b"], b"short");
+        assert_eq!(summary.warnings, vec!["Input input_b has no byte to flip"]);
+    }
+
+    fn test_passthrough_delay() {
+        let inputs = hashmap!("input" => test_data(DEFAULT_CHUNK_SIZE * 3));
+        let start = Instant::now();
+        run_passthrough(
+            json!({ "delay_ms_per_chunk": 20 }),
+            inputs.clone(),
+            &["input"],
+            CancellationToken::new(),
+        )
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        // A delay long enough to run past the deadline fails at the next chunk.
+        let token = CancellationToken::new().with_deadline(Duration::from_millis(10));
+        let error = run_passthrough(
+            json!({ "delay_ms_per_chunk": 20 }),
+            inputs,
+            &["input"],
+            token,
+        )
+        .unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::ResourceLimit);
+    }
+}
@@ -17,23 +17,63 @@
 
 use std::io::prelude::*;
 
-use std::collections::HashSet;
-use std::convert::TryFrom;
-use std::io::BufReader;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use anyhow::{bail, ensure};
+use ring::digest;
+use std::io::{BufReader, Lines};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
+
+// The corpus is a list of SHA-1 or SHA-256 hashes in lowercase hex, sorted in
+// ascending order, one per line. It is streamed and never fully loaded.
+const IN_CORPUS: &str = "corpus";
+const IN_CANDIDATES: &str = "candidates";
+const OUT_RESULT: &str = "result";
 
 #[derive(Default)]
 pub struct PasswordCheck;
 
 #[derive(serde::Deserialize)]
-struct PasswordCheckArguments;
+struct PasswordCheckArguments {
+    #[serde(default)]
+    candidates_are_hashed: bool,
+}
+
+#[derive(Clone, Copy)]
+enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
 
-impl TryFrom<FunctionArguments> for PasswordCheckArguments {
-    type Error = anyhow::Error;
+impl HashAlgorithm {
+    fn detect(entry: &str) -> anyhow::Result<Self> {
+        match entry.len() {
+            40 => Ok(HashAlgorithm::Sha1),
+            64 => Ok(HashAlgorithm::Sha256),
+            len => bail!("Unknown corpus hash format: {} hex digits", len),
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 40,
+            HashAlgorithm::Sha256 => 64,
+        }
+    }
+
+    fn hash(self, password: &str) -> String {
+        let algorithm = match self {
+            HashAlgorithm::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            HashAlgorithm::Sha256 => &digest::SHA256,
+        };
+        hex::encode(digest::digest(algorithm, password.as_bytes()))
+    }
 
-    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
-        use anyhow::Context;
-        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    fn validate(self, entry: &str) -> anyhow::Result<()> {
+        ensure!(
+            entry.len() == self.hex_len() && entry.bytes().all(|b| b.is_ascii_hexdigit()),
+            "Invalid hash: {}",
+            entry
+        );
+        Ok(())
     }
 }
 
@@ -44,59 +84,176 @@ impl PasswordCheck {
         Default::default()
     }
 
-    pub fn run(&self, _: FunctionArguments, runtime: FunctionRuntime) -> anyhow::Result<String> {
-        let password_file = runtime.open_input("password")?;
-        let password = BufReader::new(password_file)
-            .lines()
-            .next()
-            .unwrap()
-            .unwrap()
-            .trim()
-            .to_owned();
-        let exposed_passwords_file = runtime.open_input("exposed_passwords")?;
-        let exposed_passwords: HashSet<String> = BufReader::new(exposed_passwords_file)
-            .lines()
-            .map(|l| l.expect("Could not parse line").trim().to_string())
-            .collect::<Vec<String>>()
-            .iter()
-            .cloned()
-            .collect();
-        if exposed_passwords.contains(&password) {
-            Ok("true".to_string())
-        } else {
-            Ok("false".to_string())
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let args: PasswordCheckArguments = arguments.into_typed()?;
+
+        let mut corpus = BufReader::new(runtime.open_input(IN_CORPUS)?).lines();
+        let first = match next_corpus_entry(&mut corpus)? {
+            Some(entry) => entry,
+            None => bail!("Empty corpus"),
+        };
+        let algorithm = HashAlgorithm::detect(&first)?;
+
+        // Blank lines are skipped, but every candidate keeps the index of its line, so that the
+        // result can be matched up with the input.
+        let mut indices = Vec::new();
+        let mut hashes = Vec::new();
+        let candidates = BufReader::new(runtime.open_input(IN_CANDIDATES)?).lines();
+        for (index, line) in candidates.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let hash = if args.candidates_are_hashed {
+                let hash = line.trim().to_lowercase();
+                algorithm.validate(&hash)?;
+                hash
+            } else {
+                algorithm.hash(line.trim())
+            };
+            indices.push(index);
+            hashes.push(hash);
+        }
+
+        // Walk the sorted candidates and the sorted corpus side by side. The corpus is read to
+        // the end even after the last candidate, so that an unsorted or malformed tail fails the
+        // check. Nothing is written until the whole corpus has been validated.
+        let mut order: Vec<usize> = (0..hashes.len()).collect();
+        order.sort_by(|&i, &j| hashes[i].cmp(&hashes[j]));
+        let mut found = vec![false; hashes.len()];
+        let mut next = 0;
+        let mut previous: Option<String> = None;
+        let mut entry = Some(first);
+
+        while let Some(current) = entry {
+            algorithm.validate(&current)?;
+            if let Some(previous) = &previous {
+                ensure!(previous <= &current, "Corpus is not sorted");
+            }
+            while next < order.len() && hashes[order[next]] < current {
+                next += 1;
+            }
+            for &i in order[next..].iter() {
+                if hashes[i] != current {
+                    break;
+                }
+                found[i] = true;
+            }
+            previous = Some(current);
+            entry = next_corpus_entry(&mut corpus)?;
+        }
+
+        let mut output = runtime.create_output(OUT_RESULT)?;
+        for (index, &f) in indices.iter().zip(&found) {
+            writeln!(
+                &mut output,
+                "{},{}",
+                index,
+                if f { "found" } else { "not found" }
+            )?;
+        }
+
+        let exposed = found.iter().filter(|&&f| f).count();
+        Ok(format!("{} of {} candidates found in corpus", exposed, found.len()).into())
+    }
+}
+
+fn next_corpus_entry(corpus: &mut Lines<impl BufRead>) -> anyhow::Result<Option<String>> {
+    for line in corpus {
+        let line = line?;
+        let entry = line.trim();
+        if !entry.is_empty() {
+            return Ok(Some(entry.to_lowercase()));
         }
     }
+    Ok(None)
 }
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use serde_json::json;
     use std::path::Path;
+    use std::untrusted::fs;
     use teaclave_crypto::*;
     use teaclave_runtime::*;
     use teaclave_test_utils::*;
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_password_check)
+        run_tests!(
+            test_password_check,
+            test_password_check_hashed_candidates,
+            test_password_check_blank_lines,
+            test_password_check_unsorted_corpus,
+        )
     }
 
-    fn test_password_check() {
-        let password_input = Path::new("fixtures/functions/password_check/password.txt");
-        let exposed_passwords_input =
-            Path::new("fixtures/functions/password_check/exposed_passwords.txt");
-        let arguments = FunctionArguments::default();
+    fn run_password_check(
+        arguments: FunctionArguments,
+        corpus: &str,
+        candidates: &str,
+    ) -> anyhow::Result<(String, String)> {
+        let base = Path::new("fixtures/functions/password_check");
+        let output = base.join("result.txt");
 
         let input_files = StagedFiles::new(hashmap!(
-            "password" => StagedFileInfo::new(password_input, TeaclaveFile128Key::random(), FileAuthTag::mock()),
-            "exposed_passwords" => StagedFileInfo::new(exposed_passwords_input, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+            IN_CORPUS => StagedFileInfo::new(base.join(corpus), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+            IN_CANDIDATES => StagedFileInfo::new(base.join(candidates), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+        let output_files = StagedFiles::new(hashmap!(
+            OUT_RESULT => StagedFileInfo::new(&output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
         ));
-        let output_files = StagedFiles::new(hashmap!());
         let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
 
-        let result = PasswordCheck::new().run(arguments, runtime).unwrap();
+        let summary = PasswordCheck::new().run(arguments, runtime)?;
+        let result = fs::read_to_string(&output)?;
+        Ok((summary.message, result))
+    }
+
+    fn test_password_check() {
+        let arguments = FunctionArguments::default();
+        let (summary, result) =
+            run_password_check(arguments, "corpus_sha1.txt", "candidates.txt").unwrap();
+
+        assert_eq!(summary, "2 of 4 candidates found in corpus");
+        assert_eq!(result, "0,found\n1,not found\n2,found\n3,not found\n");
+    }
 
-        assert_eq!(result, "true");
+    fn test_password_check_hashed_candidates() {
+        let arguments = FunctionArguments::from_json(json!({
+            "candidates_are_hashed": true,
+        }))
+        .unwrap();
+        let (summary, result) =
+            run_password_check(arguments, "corpus_sha256.txt", "candidates_sha256.txt").unwrap();
+
+        assert_eq!(summary, "1 of 2 candidates found in corpus");
+        assert_eq!(result, "0,found\n1,not found\n");
+    }
+
+    fn test_password_check_blank_lines() {
+        let arguments = FunctionArguments::default();
+        let (summary, result) =
+            run_password_check(arguments, "corpus_sha1.txt", "candidates_blank_lines.txt").unwrap();
+
+        // Results refer to the lines of the candidates, blank lines included.
+        assert_eq!(summary, "2 of 3 candidates found in corpus");
+        assert_eq!(result, "0,found\n2,found\n4,not found\n");
+    }
+
+    fn test_password_check_unsorted_corpus() {
+        let arguments = FunctionArguments::default();
+        let result = run_password_check(arguments, "corpus_unsorted.txt", "candidates.txt");
+        assert!(result.is_err());
+
+        // Only entries past every candidate are out of order.
+        let arguments = FunctionArguments::default();
+        let result = run_password_check(arguments, "corpus_unsorted_tail.txt", "candidates.txt");
+        assert_eq!(result.unwrap_err().to_string(), "Corpus is not sorted");
     }
 }
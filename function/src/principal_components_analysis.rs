@@ -19,7 +19,7 @@ use std::convert::TryFrom;
 use std::format;
 use std::io::{self, BufRead, BufReader, Write};
 
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
 
 use rusty_machine::learning::pca::PCA;
 use rusty_machine::learning::UnSupModel;
@@ -58,7 +58,7 @@ impl PrincipalComponentsAnalysis {
         &self,
         arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<FunctionSummary> {
         let args = PrincipalComponentsAnalysisArguments::try_from(arguments)?;
         let input = runtime.open_input(IN_DATA)?;
         let (flattend_features, targets) = parse_input_data(input, args.feature_size)?;
@@ -87,7 +87,8 @@ impl PrincipalComponentsAnalysis {
             "transform {} rows * {} cols lines of data.",
             predict_result.rows(),
             predict_result.cols()
-        ))
+        )
+        .into())
     }
 }
 
@@ -167,7 +168,7 @@ pub mod tests {
         let summary = PrincipalComponentsAnalysis::new()
             .run(args, runtime)
             .unwrap();
-        assert_eq!(summary, "transform 90 rows * 2 cols lines of data.");
+        assert_eq!(summary.message, "transform 90 rows * 2 cols lines of data.");
 
         let result = fs::read_to_string(&output_data_file).unwrap();
         let expected = fs::read_to_string(&expected_output).unwrap();
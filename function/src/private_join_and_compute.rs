@@ -21,7 +21,7 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::format;
 use std::io::Write;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
 
 const IN_DATA: &str = "input_data";
 const OUT_RESULT: &str = "output_data";
@@ -54,20 +54,31 @@ impl PrivateJoinAndCompute {
         &self,
         arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<FunctionSummary> {
         let args = PrivateJoinAndComputeArguments::try_from(arguments)?;
         let num_user = args.num_user;
         if num_user < 2 {
             bail!("The demo requires at least two parties!");
         }
 
+        let inputs = keys_with_prefix(runtime.input_keys(), IN_DATA);
+        let outputs = keys_with_prefix(runtime.output_keys(), OUT_RESULT);
+        if inputs.len() != num_user || outputs.len() != num_user {
+            bail!(
+                "Expected {} inputs and outputs, found {} and {}",
+                num_user,
+                inputs.len(),
+                outputs.len()
+            );
+        }
+
         let mut output = String::new();
-        let data_0 = get_data(0, &runtime)?;
+        let data_0 = get_data(&inputs[0], &runtime)?;
         let input_map_0 = parse_input(data_0)?;
         let mut res_map: HashMap<String, u32> = input_map_0;
 
-        for i in 1..num_user {
-            let data = get_data(i, &runtime)?;
+        for input in &inputs[1..] {
+            let data = get_data(input, &runtime)?;
             let input_map = parse_input(data)?;
             res_map = get_intersection_sum(&input_map, &res_map);
         }
@@ -78,21 +89,25 @@ impl PrivateJoinAndCompute {
 
         let output_bytes = output.as_bytes();
 
-        for i in 0..num_user {
-            let output_file_name = format!("{}{}", OUT_RESULT, i);
-            let mut output = runtime.create_output(&output_file_name)?;
+        for output_file_name in &outputs {
+            let mut output = runtime.create_output(output_file_name)?;
             output.write_all(output_bytes)?;
         }
 
         let summary = format!("{} users join the task in total.", num_user);
-        Ok(summary)
+        Ok(summary.into())
     }
 }
 
-fn get_data(user_id: usize, runtime: &FunctionRuntime) -> anyhow::Result<Vec<u8>> {
+fn keys_with_prefix(keys: Vec<String>, prefix: &str) -> Vec<String> {
+    keys.into_iter()
+        .filter(|key| key.starts_with(prefix))
+        .collect()
+}
+
+fn get_data(input_file_name: &str, runtime: &FunctionRuntime) -> anyhow::Result<Vec<u8>> {
     let mut data: Vec<u8> = Vec::new();
-    let input_file_name = format!("{}{}", IN_DATA, user_id);
-    let mut input_io = runtime.open_input(&input_file_name)?;
+    let mut input_io = runtime.open_input(input_file_name)?;
     input_io.read_to_end(&mut data)?;
     Ok(data)
 }
@@ -144,7 +159,10 @@ pub mod tests {
     use teaclave_types::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_private_join_and_compute)
+        run_tests!(
+            test_private_join_and_compute,
+            test_private_join_and_compute_input_count
+        )
     }
 
     fn test_private_join_and_compute() {
@@ -194,6 +212,34 @@ pub mod tests {
         let user2 = fs::read_to_string(user2_output).unwrap();
         assert_eq!(&user0[..], &user1[..]);
         assert_eq!(&user1[..], &user2[..]);
-        assert_eq!(summary, "3 users join the task in total.")
+        assert_eq!(summary.message, "3 users join the task in total.")
+    }
+
+    fn test_private_join_and_compute_input_count() {
+        let arguments = FunctionArguments::from_json(json!({
+            "num_user": 3
+        }))
+        .unwrap();
+
+        let input_files = StagedFiles::from_memory(hashmap!(
+            "input_data0" => b"a : 1".to_vec(),
+            "input_data1" => b"a : 2".to_vec(),
+            "other" => b"a : 3".to_vec(),
+        ));
+        let output_files = StagedFiles::from_memory(hashmap!(
+            "output_data0" => Vec::<u8>::new(),
+            "output_data1" => Vec::<u8>::new(),
+            "output_data2" => Vec::<u8>::new(),
+        ));
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+
+        // Only keys with the input prefix count as parties.
+        let err = PrivateJoinAndCompute::new()
+            .run(arguments, runtime)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected 3 inputs and outputs, found 2 and 3"
+        );
     }
 }
@@ -0,0 +1,275 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::format;
+use std::io::{BufRead, BufReader};
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, CANCELLATION_CHECK_INTERVAL,
+};
+
+const IDS_A: &str = "ids_a";
+const DATA_B: &str = "data_b";
+
+#[derive(Default)]
+pub struct PrivateJoinCompute;
+
+#[derive(serde::Deserialize)]
+struct PrivateJoinComputeArguments {
+    operation: Operation,
+    /// Smallest intersection for which the aggregate is revealed. Aggregates over a handful of
+    /// ids give away the values of individual records.
+    min_intersection_size: usize,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Operation {
+    Sum,
+    Count,
+    Mean,
+    Min,
+    Max,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Sum => "sum",
+            Operation::Count => "count",
+            Operation::Mean => "mean",
+            Operation::Min => "min",
+            Operation::Max => "max",
+        }
+    }
+}
+
+impl TryFrom<FunctionArguments> for PrivateJoinComputeArguments {
+    type Error = FunctionError;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        serde_json::from_str(&arguments.into_string()).map_err(FunctionError::invalid_arguments)
+    }
+}
+
+impl PrivateJoinCompute {
+    pub const NAME: &'static str = "builtin-private-join-compute";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Aggregates the values of `data_b` over the ids also listed in `ids_a`. Only the aggregate
+    /// and the size of the intersection are revealed, and only if the intersection holds at
+    /// least `min_intersection_size` ids. An id listed several times in `ids_a` counts once; the
+    /// values of an id listed several times in `data_b` are summed before aggregating.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args = PrivateJoinComputeArguments::try_from(arguments)?;
+        let ids = read_ids(&runtime)?;
+        let values = read_values(&runtime, &ids)?;
+
+        // The error must not tell how small the intersection is.
+        if values.len() < args.min_intersection_size.max(1) {
+            return Err(FunctionError::invalid_arguments(
+                "The intersection is smaller than min_intersection_size",
+            ));
+        }
+
+        // Values are visited in id order, so the rounding of the result does not depend on the
+        // order of the inputs.
+        let count = values.len();
+        let values = values.values().copied();
+        let result = match args.operation {
+            Operation::Sum => values.sum(),
+            Operation::Count => count as f64,
+            Operation::Mean => values.sum::<f64>() / count as f64,
+            Operation::Min => values.fold(f64::INFINITY, f64::min),
+            Operation::Max => values.fold(f64::NEG_INFINITY, f64::max),
+        };
+
+        let summary = FunctionSummary::new(format!(
+            "{} over {} common ids: {}",
+            args.operation.name(),
+            count,
+            result
+        ))
+        .metric("intersection_size", count as f64)
+        .metric("result", result);
+        Ok(summary)
+    }
+}
+
+fn read_ids(runtime: &FunctionRuntime) -> Result<HashSet<String>, FunctionError> {
+    let cancellation = runtime.cancellation();
+    let input = BufReader::new(runtime.open_input(IDS_A)?);
+    let mut ids = HashSet::new();
+    for (index, line) in input.lines().enumerate() {
+        if index % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        let line = line?;
+        let id = line.trim();
+        if !id.is_empty() {
+            ids.insert(id.to_string());
+        }
+    }
+    Ok(ids)
+}
+
+/// Reads the `id,value` records of `data_b` whose id is in `ids`, summing the values of
+/// repeated ids.
+fn read_values(
+    runtime: &FunctionRuntime,
+    ids: &HashSet<String>,
+) -> Result<BTreeMap<String, f64>, FunctionError> {
+    let cancellation = runtime.cancellation();
+    let input = BufReader::new(runtime.open_input(DATA_B)?);
+    let mut values = BTreeMap::new();
+    for (index, line) in input.lines().enumerate() {
+        if index % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, value) = line
+            .split_once(',')
+            .and_then(|(id, value)| Some((id.trim(), value.trim().parse::<f64>().ok()?)))
+            .filter(|(_, value)| value.is_finite())
+            .ok_or_else(|| {
+                FunctionError::invalid_input_data(
+                    DATA_B,
+                    format!("Invalid record on line {}", index + 1),
+                )
+            })?;
+        if ids.contains(id) {
+            *values.entry(id.to_string()).or_insert(0.0) += value;
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_private_join_compute_operations,
+            test_private_join_compute_threshold,
+            test_private_join_compute_duplicates,
+            test_private_join_compute_invalid_record,
+        )
+    }
+
+    const IDS: &str = "alice\nbob\ncarol\ndave\n";
+    const DATA: &str = "bob,10\nalice,4.5\neve,100\ncarol,-2\n";
+
+    fn run_join_compute(
+        arguments: serde_json::Value,
+        ids: &str,
+        data: &str,
+    ) -> (Result<FunctionSummary, FunctionError>, usize) {
+        let (summary, outputs) = run_builtin!(
+            PrivateJoinCompute::new(),
+            arguments,
+            hashmap!(IDS_A => ids, DATA_B => data),
+            []
+        );
+        (summary, outputs.len())
+    }
+
+    fn test_private_join_compute_operations() {
+        for (operation, expected) in [
+            ("sum", 12.5),
+            ("count", 3.0),
+            ("mean", 12.5 / 3.0),
+            ("min", -2.0),
+            ("max", 10.0),
+        ] {
+            let arguments = json!({"operation": operation, "min_intersection_size": 3});
+            let (summary, outputs) = run_join_compute(arguments, IDS, DATA);
+            let summary = summary.unwrap();
+            assert_eq!(summary.metrics["result"], expected, "{}", operation);
+            assert_eq!(summary.metrics["intersection_size"], 3.0);
+            assert!(summary.message.starts_with(operation));
+            // Nothing but the aggregate and the cardinality leaves the function.
+            assert_eq!(summary.metrics.len(), 2);
+            assert!(summary.outputs.is_empty());
+            assert!(summary.warnings.is_empty());
+            assert_eq!(outputs, 0);
+        }
+    }
+
+    fn test_private_join_compute_threshold() {
+        let arguments = json!({"operation": "sum", "min_intersection_size": 4});
+        let (summary, outputs) = run_join_compute(arguments, IDS, DATA);
+        let error = summary.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid arguments: The intersection is smaller than min_intersection_size"
+        );
+        assert_eq!(outputs, 0);
+
+        // An empty intersection has no aggregate, whatever the threshold.
+        let arguments = json!({"operation": "min", "min_intersection_size": 0});
+        let (summary, _) = run_join_compute(arguments, "frank\n", DATA);
+        assert!(summary.is_err());
+    }
+
+    fn test_private_join_compute_duplicates() {
+        let ids = "alice\nbob\nalice\n";
+        let data = "alice,1\nbob,5\nalice,2\nalice,3\n";
+        let run = |operation: &str| {
+            let arguments = json!({"operation": operation, "min_intersection_size": 2});
+            run_join_compute(arguments, ids, data).0.unwrap()
+        };
+
+        // Duplicate ids in ids_a count once; alice's values in data_b are summed to 6 first.
+        assert_eq!(run("count").metrics["result"], 2.0);
+        assert_eq!(run("sum").metrics["result"], 11.0);
+        assert_eq!(run("mean").metrics["result"], 5.5);
+        assert_eq!(run("min").metrics["result"], 5.0);
+        assert_eq!(run("max").metrics["result"], 6.0);
+    }
+
+    fn test_private_join_compute_invalid_record() {
+        let arguments = json!({"operation": "sum", "min_intersection_size": 1});
+        let (summary, _) = run_join_compute(arguments, IDS, "alice,1\nbob;2\n");
+        let error = summary.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid data in input data_b: Invalid record on line 2"
+        );
+
+        let arguments = json!({"operation": "median", "min_intersection_size": 1});
+        let (summary, _) = run_join_compute(arguments, IDS, DATA);
+        assert_eq!(
+            summary.unwrap_err().category(),
+            TaskFailureCategory::InvalidArguments
+        );
+    }
+}
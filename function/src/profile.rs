@@ -0,0 +1,795 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{open_sniffed, DetectedFormat};
+use crate::json_stream::JsonStreamWriter;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::format;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+    CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_DATASET: &str = "dataset";
+const OUT_PROFILE: &str = "profile";
+
+/// Number of index bits of the HyperLogLog sketches, which have 2^12 one-byte registers.
+const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+#[derive(Default)]
+pub struct Profile;
+
+#[derive(serde::Deserialize)]
+struct ProfileArguments {
+    /// Number of distinct values per column which are counted exactly. Columns with more
+    /// distinct values are estimated with a HyperLogLog sketch instead.
+    #[serde(default = "default_max_distinct_tracked")]
+    max_distinct_tracked: usize,
+}
+
+fn default_max_distinct_tracked() -> usize {
+    1000
+}
+
+/// HyperLogLog sketch of the distinct values of a column. It takes 4 KiB regardless of the
+/// number of values, and its estimates have a standard error of 1.04 / sqrt(2^12), about 1.6%,
+/// so 99% of them are within 5% of the true count. Small counts are estimated by linear
+/// counting, which is more accurate there.
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; HLL_REGISTERS],
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        // The sentinel bit bounds the rank if all remaining bits are zero.
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// Counts distinct values exactly up to a limit, and estimates them beyond.
+enum DistinctCounter {
+    Exact(HashSet<String>, usize),
+    Estimated(HyperLogLog),
+}
+
+impl DistinctCounter {
+    fn new(max_tracked: usize) -> Self {
+        DistinctCounter::Exact(HashSet::new(), max_tracked)
+    }
+
+    fn add(&mut self, value: &str) {
+        match self {
+            DistinctCounter::Exact(values, max_tracked) => {
+                if values.contains(value) {
+                    return;
+                }
+                if values.len() < *max_tracked {
+                    values.insert(value.to_string());
+                    return;
+                }
+                let mut sketch = HyperLogLog::new();
+                values.iter().for_each(|v| sketch.add(v));
+                sketch.add(value);
+                *self = DistinctCounter::Estimated(sketch);
+            }
+            DistinctCounter::Estimated(sketch) => sketch.add(value),
+        }
+    }
+
+    /// The distinct count and whether it is exact.
+    fn count(&self) -> (u64, bool) {
+        match self {
+            DistinctCounter::Exact(values, _) => (values.len() as u64, true),
+            DistinctCounter::Estimated(sketch) => (sketch.estimate(), false),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Integer,
+    Number,
+    Boolean,
+    String,
+    Nested,
+}
+
+/// Number of values of each kind in a column.
+#[derive(Default)]
+struct Kinds {
+    integer: u64,
+    number: u64,
+    boolean: u64,
+    string: u64,
+    nested: u64,
+}
+
+impl Kinds {
+    fn add(&mut self, kind: Kind) {
+        match kind {
+            Kind::Integer => self.integer += 1,
+            Kind::Number => self.number += 1,
+            Kind::Boolean => self.boolean += 1,
+            Kind::String => self.string += 1,
+            Kind::Nested => self.nested += 1,
+        }
+    }
+
+    /// The inferred type of the column: the kind of all its values, "number" for integers
+    /// mixed with other numbers, "mixed" for anything else, and "null" without values.
+    fn type_name(&self) -> &'static str {
+        let kinds = [
+            (self.integer, "integer"),
+            (self.number, "number"),
+            (self.boolean, "boolean"),
+            (self.string, "string"),
+            (self.nested, "nested"),
+        ];
+        let present: Vec<&str> = kinds
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(_, name)| *name)
+            .collect();
+        match present.as_slice() {
+            [] => "null",
+            [name] => name,
+            ["integer", "number"] => "number",
+            _ => "mixed",
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self.type_name(), "integer" | "number")
+    }
+}
+
+/// Min, max, mean and standard deviation of the numeric values of a column. The mean is the
+/// plain sum over the count, the variance is accumulated with Welford's method to stay stable.
+struct NumericStats {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    welford_mean: f64,
+    m2: f64,
+}
+
+impl Default for NumericStats {
+    fn default() -> Self {
+        NumericStats {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            welford_mean: 0.0,
+            m2: 0.0,
+        }
+    }
+}
+
+impl NumericStats {
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        self.sum += x;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        let delta = x - self.welford_mean;
+        self.welford_mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.welford_mean);
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    /// Population standard deviation.
+    fn stddev(&self) -> f64 {
+        (self.m2 / self.count as f64).sqrt()
+    }
+}
+
+struct ColumnProfiler {
+    name: String,
+    values: u64,
+    kinds: Kinds,
+    numeric: NumericStats,
+    distinct: DistinctCounter,
+}
+
+impl ColumnProfiler {
+    fn new(name: String, max_distinct_tracked: usize) -> Self {
+        ColumnProfiler {
+            name,
+            values: 0,
+            kinds: Kinds::default(),
+            numeric: NumericStats::default(),
+            distinct: DistinctCounter::new(max_distinct_tracked),
+        }
+    }
+
+    fn add(&mut self, kind: Kind, number: Option<f64>, text: &str) {
+        self.values += 1;
+        self.kinds.add(kind);
+        if let Some(x) = number {
+            self.numeric.add(x);
+        }
+        self.distinct.add(text);
+    }
+
+    /// Adds a CSV field, inferring its kind from the text. Empty fields are nulls.
+    fn add_text(&mut self, field: &str) {
+        let field = field.trim();
+        if field.is_empty() {
+            return;
+        }
+        if let Ok(i) = field.parse::<i64>() {
+            self.add(Kind::Integer, Some(i as f64), field);
+        } else if let Some(x) = field.parse::<f64>().ok().filter(|x| x.is_finite()) {
+            self.add(Kind::Number, Some(x), field);
+        } else if field.eq_ignore_ascii_case("true") || field.eq_ignore_ascii_case("false") {
+            self.add(Kind::Boolean, None, field);
+        } else {
+            self.add(Kind::String, None, field);
+        }
+    }
+
+    fn add_json(&mut self, value: &Value) {
+        match value {
+            Value::Null => {}
+            Value::Bool(b) => self.add(Kind::Boolean, None, &b.to_string()),
+            Value::Number(n) if n.is_i64() || n.is_u64() => {
+                self.add(Kind::Integer, n.as_f64(), &n.to_string())
+            }
+            Value::Number(n) => self.add(Kind::Number, n.as_f64(), &n.to_string()),
+            Value::String(s) => self.add(Kind::String, None, s),
+            nested => self.add(Kind::Nested, None, &nested.to_string()),
+        }
+    }
+
+    fn finish(self, rows: u64) -> ColumnProfile {
+        let numeric = self.kinds.is_numeric();
+        let (distinct, distinct_exact) = self.distinct.count();
+        ColumnProfile {
+            column_type: self.kinds.type_name(),
+            values: self.values,
+            nulls: rows - self.values,
+            min: Some(self.numeric.min).filter(|_| numeric),
+            max: Some(self.numeric.max).filter(|_| numeric),
+            mean: Some(self.numeric.mean()).filter(|_| numeric),
+            stddev: Some(self.numeric.stddev()).filter(|_| numeric),
+            distinct,
+            distinct_exact,
+            name: self.name,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ColumnProfile {
+    name: String,
+    #[serde(rename = "type")]
+    column_type: &'static str,
+    values: u64,
+    nulls: u64,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_stat"
+    )]
+    min: Option<f64>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_stat"
+    )]
+    max: Option<f64>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_stat"
+    )]
+    mean: Option<f64>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_stat"
+    )]
+    stddev: Option<f64>,
+    distinct: u64,
+    distinct_exact: bool,
+}
+
+/// Writes a statistic as a number, or as "NaN", "Infinity" or "-Infinity" when it overflowed,
+/// which serde_json would otherwise write as null, the same as a missing value.
+fn serialize_stat<S: serde::Serializer>(
+    stat: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match stat {
+        Some(x) if x.is_nan() => serializer.serialize_str("NaN"),
+        Some(x) if x.is_infinite() && *x > 0.0 => serializer.serialize_str("Infinity"),
+        Some(x) if x.is_infinite() => serializer.serialize_str("-Infinity"),
+        Some(x) => serializer.serialize_f64(*x),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Overview of the profile, returned as the summary of the function.
+#[derive(Serialize)]
+struct ProfileOverview<'a> {
+    rows: u64,
+    columns: Vec<ColumnOverview<'a>>,
+}
+
+#[derive(Serialize)]
+struct ColumnOverview<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    column_type: &'static str,
+    nulls: u64,
+}
+
+/// The columns of a dataset, in order of first appearance.
+struct Columns {
+    profilers: Vec<ColumnProfiler>,
+    index: HashMap<String, usize>,
+    max_distinct_tracked: usize,
+}
+
+impl Columns {
+    fn new(max_distinct_tracked: usize) -> Self {
+        Columns {
+            profilers: Vec::new(),
+            index: HashMap::new(),
+            max_distinct_tracked,
+        }
+    }
+
+    fn get(&mut self, name: &str) -> &mut ColumnProfiler {
+        let profilers = &mut self.profilers;
+        let max_distinct_tracked = self.max_distinct_tracked;
+        let i = *self.index.entry(name.to_string()).or_insert_with(|| {
+            profilers.push(ColumnProfiler::new(name.to_string(), max_distinct_tracked));
+            profilers.len() - 1
+        });
+        &mut self.profilers[i]
+    }
+
+    fn nth(&mut self, i: usize) -> &mut ColumnProfiler {
+        while self.profilers.len() <= i {
+            let name = format!("column_{}", self.profilers.len());
+            self.get(&name);
+        }
+        &mut self.profilers[i]
+    }
+}
+
+/// A JSON object with its fields in document order, which `serde_json::Map` doesn't keep. A
+/// repeated field keeps its first position and its last value.
+struct OrderedObject(Vec<(String, Value)>);
+
+impl<'de> serde::Deserialize<'de> for OrderedObject {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ObjectVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ObjectVisitor {
+            type Value = OrderedObject;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<OrderedObject, A::Error> {
+                let mut fields: Vec<(String, Value)> = Vec::new();
+                let mut index: HashMap<String, usize> = HashMap::new();
+                while let Some((name, value)) = map.next_entry::<String, Value>()? {
+                    match index.get(&name) {
+                        Some(&i) => fields[i].1 = value,
+                        None => {
+                            index.insert(name.clone(), fields.len());
+                            fields.push((name, value));
+                        }
+                    }
+                }
+                Ok(OrderedObject(fields))
+            }
+        }
+
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+impl Profile {
+    pub const NAME: &'static str = "builtin-profile";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Profiles the columns of a CSV or JSONL dataset in one pass: their inferred type, null
+    /// count, numeric aggregates and distinct count. CSV columns are named by the header, or
+    /// `column_<i>` without one; JSONL columns are the fields of the objects. Memory grows with
+    /// the number of columns, and with at most `max_distinct_tracked` values per column.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: ProfileArguments = arguments.into_typed()?;
+        let (detected, input) = open_sniffed(runtime.as_ref(), IN_DATASET)?;
+        let (delimiter, header) = match detected {
+            DetectedFormat::Csv { delimiter, header } => (Some(delimiter), header),
+            DetectedFormat::Jsonl => (None, false),
+            DetectedFormat::Text | DetectedFormat::Empty => (Some(','), false),
+            DetectedFormat::Binary => {
+                return Err(FunctionError::invalid_input_data(
+                    IN_DATASET,
+                    "expected CSV or JSONL but found binary data",
+                ))
+            }
+        };
+
+        let cancellation = runtime.cancellation();
+        let mut columns = Columns::new(args.max_distinct_tracked);
+        let mut lines = BufReader::new(input).lines();
+        let mut header_width = None;
+        if let (Some(delimiter), true) = (delimiter, header) {
+            if let Some(line) = lines.next().transpose()? {
+                for name in line.trim_end_matches('\r').split(delimiter) {
+                    columns.get(name.trim());
+                }
+                header_width = Some(columns.profilers.len());
+            }
+        }
+
+        let rows = runtime.time_block("scan", || -> Result<u64, FunctionError> {
+            let mut rows: u64 = 0;
+            for (i, line) in lines.enumerate() {
+                if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                    cancellation.checkpoint()?;
+                }
+                let line = line?;
+                let line = line.trim_end_matches('\r');
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rows += 1;
+                match delimiter {
+                    Some(delimiter) => {
+                        let fields: Vec<&str> = line.split(delimiter).collect();
+                        if let Some(width) = header_width.filter(|&width| fields.len() > width) {
+                            return Err(FunctionError::invalid_input_data(
+                                IN_DATASET,
+                                format!(
+                                    "row {} has {} fields but the header has {}",
+                                    rows,
+                                    fields.len(),
+                                    width
+                                ),
+                            ));
+                        }
+                        for (i, field) in fields.iter().enumerate() {
+                            columns.nth(i).add_text(field);
+                        }
+                    }
+                    None => {
+                        let object = match serde_json::from_str::<OrderedObject>(line) {
+                            Ok(OrderedObject(object)) => object,
+                            _ => {
+                                return Err(FunctionError::invalid_input_data(
+                                    IN_DATASET,
+                                    format!("row {} is not a JSON object", rows),
+                                ))
+                            }
+                        };
+                        for (name, value) in &object {
+                            columns.get(name).add_json(value);
+                        }
+                    }
+                }
+            }
+            Ok(rows)
+        })?;
+
+        let columns: Vec<ColumnProfile> = runtime.time_block("summarize", || {
+            columns
+                .profilers
+                .into_iter()
+                .map(|column| column.finish(rows))
+                .collect()
+        });
+        // The profile is streamed column by column instead of being serialized as a whole, so
+        // that it is not held in memory twice.
+        let mut output = JsonStreamWriter::pretty(runtime.create_output(OUT_PROFILE)?);
+        output.begin_object()?;
+        output.key("format")?;
+        output.string(if delimiter.is_some() { "CSV" } else { "JSONL" })?;
+        output.key("rows")?;
+        output.u64(rows)?;
+        output.key("columns")?;
+        output.begin_array()?;
+        for column in &columns {
+            output.value(column)?;
+        }
+        output.end_array()?;
+        output.end_object()?;
+        let profile_bytes = output.finish()?;
+
+        let overview = ProfileOverview {
+            rows,
+            columns: columns
+                .iter()
+                .map(|column| ColumnOverview {
+                    name: &column.name,
+                    column_type: column.column_type,
+                    nulls: column.nulls,
+                })
+                .collect(),
+        };
+        let message = serde_json::to_string(&overview).map_err(anyhow::Error::from)?;
+        let summary = FunctionSummary::new(message)
+            .metric("rows", rows as f64)
+            .metric("columns", columns.len() as f64)
+            .output(OUT_PROFILE, OutputInfo::new(profile_bytes));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::format_sniff::SNIFF_BYTES;
+    use serde_json::json;
+    use std::path::Path;
+    use teaclave_crypto::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_profile_csv,
+            test_profile_jsonl,
+            test_profile_distinct_estimate,
+            test_profile_overflow,
+            test_profile_invalid_rows,
+        )
+    }
+
+    fn profile(
+        input_files: StagedFiles,
+        arguments: serde_json::Value,
+    ) -> Result<(FunctionSummary, Value), FunctionError> {
+        let arguments = FunctionArguments::from_json(arguments).unwrap();
+        let runtime = RawIoRuntime::new(
+            input_files,
+            StagedFiles::from_memory(hashmap!(OUT_PROFILE => Vec::new())),
+        );
+        let outputs = runtime.output_buffers();
+        let metrics = runtime.metrics();
+        let summary = Profile::new().run(arguments, Box::new(runtime))?;
+        let timings = metrics.timings();
+        assert!(timings.contains_key("scan") && timings.contains_key("summarize"));
+        let profile = serde_json::from_slice(&outputs.get(OUT_PROFILE).unwrap()).unwrap();
+        Ok((summary, profile))
+    }
+
+    fn profile_memory(
+        dataset: &str,
+        arguments: serde_json::Value,
+    ) -> Result<(FunctionSummary, Value), FunctionError> {
+        let input_files =
+            StagedFiles::from_memory(hashmap!(IN_DATASET => dataset.as_bytes().to_vec()));
+        profile(input_files, arguments)
+    }
+
+    fn test_profile_csv() {
+        let input_files = StagedFiles::new(hashmap!(
+            IN_DATASET =>
+            StagedFileInfo::new(
+                Path::new("fixtures/functions/profile/dataset.csv"),
+                TeaclaveFile128Key::random(),
+                FileAuthTag::mock(),
+            ),
+        ));
+        let (summary, profile) = profile(input_files, json!({})).unwrap();
+        assert_eq!(summary.metrics["rows"], 10.0);
+        assert_eq!(summary.metrics["columns"], 4.0);
+        let overview: Value = serde_json::from_str(&summary.message).unwrap();
+        assert_eq!(
+            overview,
+            json!({
+                "rows": 10,
+                "columns": [
+                    { "name": "id", "type": "integer", "nulls": 0 },
+                    { "name": "age", "type": "integer", "nulls": 2 },
+                    { "name": "city", "type": "string", "nulls": 1 },
+                    { "name": "score", "type": "number", "nulls": 1 },
+                ],
+            })
+        );
+
+        assert_eq!(profile["format"], "CSV");
+        assert_eq!(profile["rows"], 10);
+        let columns = &profile["columns"];
+        assert_eq!(
+            columns[1],
+            json!({
+                "name": "age",
+                "type": "integer",
+                "values": 8,
+                "nulls": 2,
+                "min": 2.0,
+                "max": 9.0,
+                "mean": 5.0,
+                "stddev": 2.0,
+                "distinct": 5,
+                "distinct_exact": true,
+            })
+        );
+        assert_eq!(columns[0]["mean"], 5.5);
+        assert_eq!(columns[0]["distinct"], 10);
+        assert_eq!(
+            columns[2],
+            json!({
+                "name": "city",
+                "type": "string",
+                "values": 9,
+                "nulls": 1,
+                "distinct": 3,
+                "distinct_exact": true,
+            })
+        );
+        // 0.5, 1.5 and 2.5 three times each.
+        assert_eq!(columns[3]["min"], 0.5);
+        assert_eq!(columns[3]["max"], 2.5);
+        assert_eq!(columns[3]["mean"], 1.5);
+        let stddev = columns[3]["stddev"].as_f64().unwrap();
+        assert!((stddev - (2.0f64 / 3.0).sqrt()).abs() < 1e-12);
+    }
+
+    fn test_profile_jsonl() {
+        let dataset = r#"{"name": "a", "tags": ["x"], "active": true, "weight": 1}
+{"weight": 2.5, "note": null, "name": "b", "active": false}
+{"name": "c", "active": "yes", "weight": 3}
+"#;
+        let (summary, profile) = profile_memory(dataset, json!({})).unwrap();
+        assert_eq!(summary.metrics["columns"], 5.0);
+        assert_eq!(profile["format"], "JSONL");
+        let columns = profile["columns"].as_array().unwrap();
+        // Columns keep the order in which their fields first appear, not the order of their
+        // names or of the fields in later rows.
+        let types: Vec<(&str, &str, u64)> = columns
+            .iter()
+            .map(|c| {
+                (
+                    c["name"].as_str().unwrap(),
+                    c["type"].as_str().unwrap(),
+                    c["nulls"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("name", "string", 0),
+                ("tags", "nested", 2),
+                ("active", "mixed", 0),
+                ("weight", "number", 0),
+                ("note", "null", 3),
+            ]
+        );
+        assert_eq!(columns[3]["mean"], 6.5 / 3.0);
+        assert!(columns[2].get("mean").is_none());
+    }
+
+    fn test_profile_distinct_estimate() {
+        let distinct = 20_000;
+        let dataset: String = (0..distinct * 2)
+            .map(|i| format!("{{\"key\": \"value {}\"}}\n", i % distinct))
+            .collect();
+        let (_, profile) =
+            profile_memory(&dataset, json!({ "max_distinct_tracked": 100 })).unwrap();
+        let column = &profile["columns"][0];
+        assert_eq!(column["values"], 2 * distinct);
+        assert_eq!(column["distinct_exact"], false);
+        // 99% of the estimates are within three standard errors, about 5%.
+        let estimate = column["distinct"].as_f64().unwrap();
+        let error = (estimate - distinct as f64).abs() / distinct as f64;
+        assert!(error < 0.05, "estimate {} is off by {}", estimate, error);
+
+        // Below the limit the count is exact.
+        let (_, profile) =
+            profile_memory(&dataset, json!({ "max_distinct_tracked": 100_000 })).unwrap();
+        assert_eq!(profile["columns"][0]["distinct"], distinct);
+        assert_eq!(profile["columns"][0]["distinct_exact"], true);
+
+        // Linear counting keeps small estimates close.
+        let mut sketch = HyperLogLog::new();
+        (0..50).for_each(|i| sketch.add(&i.to_string()));
+        assert!((48..=52).contains(&sketch.estimate()));
+    }
+
+    fn test_profile_overflow() {
+        let dataset = r#"{"a": 1e308, "b": 1e308}
+{"a": -1e308, "b": 1e308}
+"#;
+        let (_, profile) = profile_memory(dataset, json!({})).unwrap();
+        let columns = &profile["columns"];
+        // The variance of "a" and the sum of "b" overflow.
+        assert_eq!(columns[0]["mean"], 0.0);
+        assert_eq!(columns[0]["stddev"], "NaN");
+        assert_eq!(columns[1]["mean"], "Infinity");
+        assert_eq!(columns[1]["stddev"], 0.0);
+        assert_eq!(columns[1]["max"], 1e308);
+    }
+
+    fn test_profile_invalid_rows() {
+        // The bad row comes after the sniffed bytes, which look like CSV with a header.
+        let mut dataset = "a,b\n".to_string();
+        dataset.push_str(&"1,2\n".repeat(SNIFF_BYTES / 4));
+        dataset.push_str("1,2,3\n");
+        let error = profile_memory(&dataset, json!({})).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidInputData);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid data in input dataset: row {} has 3 fields but the header has 2",
+                SNIFF_BYTES / 4 + 1
+            )
+        );
+
+        let error = profile_memory("{\"a\": 1}\n[1, 2]\n", json!({})).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid data in input dataset: row 2 is not a JSON object"
+        );
+
+        let error = profile_memory("", json!({ "max_distinct_tracked": "many" })).unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
+    }
+}
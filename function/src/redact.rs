@@ -0,0 +1,373 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{open_expecting, ExpectedFormat};
+use crate::line_transform::{LinePipeline, LineTransform};
+use ring::digest;
+use serde_json::Value;
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{BufReader, Write};
+use std::str;
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+};
+
+#[derive(Default)]
+pub struct Redact;
+
+#[derive(serde::Deserialize)]
+struct RedactArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    /// Dot-separated paths of fields to remove, e.g. "address.street".
+    #[serde(default)]
+    drop: Vec<String>,
+    /// Dot-separated paths of fields whose values are replaced by their salted SHA-256 hash.
+    #[serde(default)]
+    hash: Vec<String>,
+    #[serde(default)]
+    salt: String,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+impl TryFrom<FunctionArguments> for RedactArguments {
+    type Error = FunctionError;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        serde_json::from_str(&arguments.into_string()).map_err(FunctionError::invalid_arguments)
+    }
+}
+
+impl Redact {
+    pub const NAME: &'static str = "builtin-redact";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args = RedactArguments::try_from(arguments)?;
+        let transform = RedactTransform::new(&args.input, &args.drop, &args.hash, &args.salt)?;
+
+        let cancellation = runtime.cancellation();
+        let mut input = BufReader::new(open_expecting(
+            &*runtime,
+            Self::NAME,
+            &args.input,
+            ExpectedFormat::Jsonl,
+        )?);
+        let mut output = runtime.create_output(&args.output)?;
+        let stats =
+            LinePipeline::new()
+                .stage(&transform)
+                .run(&mut input, &mut output, &cancellation)?;
+
+        let summary = FunctionSummary::new(format!(
+            "{} records, {} fields dropped, {} fields hashed",
+            transform.records(),
+            transform.dropped(),
+            transform.hashed()
+        ))
+        .metric("records", transform.records() as f64)
+        .metric("dropped_fields", transform.dropped() as f64)
+        .metric("hashed_fields", transform.hashed() as f64)
+        .output(args.output, OutputInfo::new(stats.bytes_written as u64));
+        Ok(summary)
+    }
+}
+
+/// Line transform behind `Redact`, for use as a stage of a `LinePipeline`. Data errors name
+/// `input` and count lines from the first one the transform sees.
+pub struct RedactTransform {
+    input: String,
+    drop: Vec<Vec<String>>,
+    hash: Vec<Vec<String>>,
+    salt: String,
+    lines: Cell<usize>,
+    records: Cell<usize>,
+    dropped: Cell<usize>,
+    hashed: Cell<usize>,
+}
+
+impl RedactTransform {
+    pub fn new(
+        input: &str,
+        drop: &[String],
+        hash: &[String],
+        salt: &str,
+    ) -> Result<Self, FunctionError> {
+        // Unsalted hashes of e-mail addresses and the like are easily reversed with a dictionary.
+        if !hash.is_empty() && salt.is_empty() {
+            return Err(FunctionError::invalid_arguments(
+                "A salt is required to hash fields",
+            ));
+        }
+        Ok(Self {
+            input: input.to_string(),
+            drop: parse_paths(drop)?,
+            hash: parse_paths(hash)?,
+            salt: salt.to_string(),
+            lines: Cell::new(0),
+            records: Cell::new(0),
+            dropped: Cell::new(0),
+            hashed: Cell::new(0),
+        })
+    }
+
+    pub fn records(&self) -> usize {
+        self.records.get()
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.get()
+    }
+
+    pub fn hashed(&self) -> usize {
+        self.hashed.get()
+    }
+}
+
+impl LineTransform for RedactTransform {
+    fn transform(&self, line: &[u8], out: &mut dyn Write) -> Result<(), FunctionError> {
+        let line_number = self.lines.get() + 1;
+        self.lines.set(line_number);
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let mut record: Value = str::from_utf8(line)
+            .ok()
+            .and_then(|line| serde_json::from_str(line).ok())
+            .ok_or_else(|| {
+                FunctionError::invalid_input_data(
+                    &self.input,
+                    format!("Invalid record on line {}", line_number),
+                )
+            })?;
+        if !record.is_object() {
+            return Err(FunctionError::invalid_input_data(
+                &self.input,
+                format!("Record on line {} is not an object", line_number),
+            ));
+        }
+
+        // Hashing first lets a field listed in both "hash" and "drop" be dropped.
+        for path in &self.hash {
+            if let Some(value) = lookup_mut(&mut record, path) {
+                *value = Value::String(salted_hash(&self.salt, value));
+                self.hashed.set(self.hashed.get() + 1);
+            }
+        }
+        for path in &self.drop {
+            if remove(&mut record, path) {
+                self.dropped.set(self.dropped.get() + 1);
+            }
+        }
+
+        let line = serde_json::to_string(&record).map_err(anyhow::Error::from)?;
+        writeln!(out, "{}", line)?;
+        self.records.set(self.records.get() + 1);
+        Ok(())
+    }
+}
+
+fn parse_paths(paths: &[String]) -> Result<Vec<Vec<String>>, FunctionError> {
+    paths
+        .iter()
+        .map(|path| {
+            let components: Vec<String> = path.split('.').map(String::from).collect();
+            if components.iter().any(|c| c.is_empty()) {
+                return Err(FunctionError::invalid_arguments(format!(
+                    "Invalid field path: {:?}",
+                    path
+                )));
+            }
+            Ok(components)
+        })
+        .collect()
+}
+
+/// Returns the value at `path`, or `None` if the record has no such field.
+fn lookup_mut<'a>(record: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    path.iter()
+        .try_fold(record, |value, component| value.get_mut(component.as_str()))
+}
+
+/// Removes the field at `path` and reports whether it was present.
+fn remove(record: &mut Value, path: &[String]) -> bool {
+    let (last, parent) = match path.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    lookup_mut(record, parent)
+        .and_then(Value::as_object_mut)
+        .and_then(|object| object.remove(last))
+        .is_some()
+}
+
+/// Hex-encoded SHA-256 of the salt followed by the value. Strings are hashed without their JSON
+/// quotes, so the hash of an e-mail address matches one computed outside of Teaclave.
+fn salted_hash(salt: &str, value: &Value) -> String {
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(salt.as_bytes());
+    match value {
+        Value::String(s) => context.update(s.as_bytes()),
+        other => context.update(other.to_string().as_bytes()),
+    }
+    hex::encode(context.finish().as_ref())
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_redact_drop_and_hash,
+            test_redact_missing_fields,
+            test_redact_requires_salt,
+            test_redact_invalid_record,
+            test_redact_csv_input,
+        )
+    }
+
+    const INPUT: &str = concat!(
+        r#"{"name":"alice","ssn":"123-45-6789","email":"alice@example.com","address":{"city":"Paris","street":"Rue 1"}}"#,
+        "\n",
+        r#"{"name":"bob","email":"bob@example.com","address":{"city":"Rome"}}"#,
+        "\n",
+    );
+
+    fn run_redact(
+        arguments: serde_json::Value,
+        input: &str,
+    ) -> anyhow::Result<(FunctionSummary, Vec<Value>)> {
+        let (summary, mut outputs) = run_builtin!(
+            Redact::new(),
+            arguments,
+            hashmap!("input" => input),
+            ["output"]
+        );
+        let summary = summary?;
+        let output = String::from_utf8(outputs.remove("output").unwrap_or_default()).unwrap();
+        let records = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        Ok((summary, records))
+    }
+
+    fn expected_hash(value: &str) -> String {
+        let digest = digest::digest(&digest::SHA256, format!("pepper{}", value).as_bytes());
+        hex::encode(digest.as_ref())
+    }
+
+    fn test_redact_drop_and_hash() {
+        let arguments = json!({
+            "drop": ["ssn", "address.street"],
+            "hash": ["email"],
+            "salt": "pepper",
+        });
+        let (summary, records) = run_redact(arguments, INPUT).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                json!({
+                    "name": "alice",
+                    "email": expected_hash("alice@example.com"),
+                    "address": {"city": "Paris"},
+                }),
+                json!({
+                    "name": "bob",
+                    "email": expected_hash("bob@example.com"),
+                    "address": {"city": "Rome"},
+                }),
+            ]
+        );
+        assert_eq!(
+            summary.message,
+            "2 records, 2 fields dropped, 2 fields hashed"
+        );
+        assert_eq!(summary.metrics["dropped_fields"], 2.0);
+        assert_eq!(summary.metrics["hashed_fields"], 2.0);
+    }
+
+    fn test_redact_missing_fields() {
+        let arguments = json!({
+            "drop": ["phone", "address.city.zip", "name.first"],
+            "hash": ["address.country"],
+            "salt": "pepper",
+        });
+        let (summary, records) = run_redact(arguments, INPUT).unwrap();
+        let expected: Vec<Value> = INPUT
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records, expected);
+        assert_eq!(
+            summary.message,
+            "2 records, 0 fields dropped, 0 fields hashed"
+        );
+    }
+
+    fn test_redact_requires_salt() {
+        let err = run_redact(json!({"hash": ["email"]}), INPUT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: A salt is required to hash fields"
+        );
+    }
+
+    fn test_redact_invalid_record() {
+        let input = "{\"name\":\"alice\"}\nnot json\n";
+        let err = run_redact(json!({"drop": ["name"]}), input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: Invalid record on line 2"
+        );
+        assert_eq!(
+            FunctionError::from(err).category(),
+            TaskFailureCategory::InvalidInputData
+        );
+    }
+
+    fn test_redact_csv_input() {
+        let err = run_redact(json!({"drop": ["name"]}), "name,ssn\nalice,1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: \
+             input 'input' looks like CSV but builtin-redact expects JSONL"
+        );
+    }
+}
@@ -0,0 +1,1620 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use teaclave_types::{
+    ArgumentSchema, Digest, ExecutionPolicy, FileSpec, FunctionArguments, FunctionError,
+    FunctionRuntime, FunctionSummary, StagingDeclaration,
+};
+use thiserror::Error;
+
+pub type BuiltinFn = fn(FunctionArguments, FunctionRuntime) -> anyhow::Result<FunctionSummary>;
+
+/// Builds the reusable state of a builtin, e.g. loads model weights, from its static config:
+/// the arguments its descriptor lists as `static_arguments`.
+pub type PrepareFn = fn(&FunctionArguments) -> anyhow::Result<Arc<dyn PreparedFunction>>;
+
+/// Number of prepared builtins a registry keeps by default.
+pub const DEFAULT_PREPARED_CAPACITY: usize = 8;
+
+/// A builtin prepared once for a static config and reused by the executions with that config.
+pub trait PreparedFunction: Send + Sync {
+    /// Runs one execution with all of its arguments, static ones included.
+    fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary>;
+
+    /// Called when the registry drops this instance, because it was evicted or its builtin
+    /// was invalidated. Executions still holding the instance may finish with it.
+    fn invalidate(&self) {}
+}
+
+enum Builtin {
+    Function(BuiltinFn),
+    Prepared(PrepareFn),
+}
+
+/// Error returned when running a function which is neither registered nor one of the
+/// `KNOWN_BUILTINS`, e.g. because of a typo in its name.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Function not found: {0}")]
+pub struct NotFound(pub String);
+
+/// Error returned when running a builtin of this crate which was left out of the build, e.g. by
+/// an enclave compiled with only some of the builtin groups.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Function not compiled into this enclave: {0}")]
+pub struct NotCompiled(pub String);
+
+/// Error returned when running a gated builtin which the execution policy of the task does not
+/// allow. It is returned before the arguments are parsed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Function disabled by the execution policy: {0}")]
+pub struct FunctionDisabled(pub String);
+
+/// Error returned when a builtin is registered, but none of its versions satisfies the requested
+/// version constraint.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("No version of {name} satisfies {constraint}")]
+pub struct NoMatchingVersion {
+    pub name: String,
+    pub constraint: VersionConstraint,
+}
+
+/// Versions of a builtin which a caller accepts, written after the name as in `builtin-echo@1`:
+/// `*` for any version, `N` for exactly N, `>=N` for N or later and `N-M` for N to M inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VersionConstraint {
+    Any,
+    Exact(u32),
+    AtLeast(u32),
+    Between(u32, u32),
+}
+
+impl VersionConstraint {
+    pub fn matches(&self, version: u32) -> bool {
+        match *self {
+            VersionConstraint::Any => true,
+            VersionConstraint::Exact(v) => version == v,
+            VersionConstraint::AtLeast(v) => version >= v,
+            VersionConstraint::Between(low, high) => (low..=high).contains(&version),
+        }
+    }
+}
+
+impl FromStr for VersionConstraint {
+    type Err = FunctionError;
+
+    fn from_str(constraint: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            FunctionError::invalid_arguments(format!(
+                "Invalid version constraint: {:?}",
+                constraint
+            ))
+        };
+        let version = |v: &str| v.trim().parse::<u32>().map_err(|_| invalid());
+        let constraint = constraint.trim();
+        if constraint == "*" {
+            Ok(VersionConstraint::Any)
+        } else if let Some(v) = constraint.strip_prefix(">=") {
+            Ok(VersionConstraint::AtLeast(version(v)?))
+        } else if let Some((low, high)) = constraint.split_once('-') {
+            let (low, high) = (version(low)?, version(high)?);
+            if low > high {
+                return Err(invalid());
+            }
+            Ok(VersionConstraint::Between(low, high))
+        } else {
+            Ok(VersionConstraint::Exact(version(constraint)?))
+        }
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionConstraint::Any => write!(f, "*"),
+            VersionConstraint::Exact(v) => write!(f, "{}", v),
+            VersionConstraint::AtLeast(v) => write!(f, ">={}", v),
+            VersionConstraint::Between(low, high) => write!(f, "{}-{}", low, high),
+        }
+    }
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/// What users need to know to call a builtin.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDescriptor {
+    pub name: String,
+    /// Bumped when the arguments or behavior change incompatibly. Versions of the same builtin
+    /// are registered side by side, so pinned callers keep getting the version they expect.
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Names of the accepted arguments.
+    pub arguments: Vec<String>,
+    /// Identifiers of the input and output files. For builtins taking any number of files, a
+    /// trailing `*` stands for numbered identifiers with that prefix. Builtins whose file
+    /// identifiers are arguments list the defaults.
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    /// Capability tags such as "experimental". Tagged builtins only run for tasks whose
+    /// execution policy allows their name or one of their tags.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Arguments which make up the static config of a prepared builtin. Executions with the
+    /// same values for them share one prepared instance.
+    #[serde(default)]
+    pub static_arguments: Vec<String>,
+    /// Types and constraints of the arguments, for builtins which describe them.
+    #[serde(default)]
+    pub schema: Option<ArgumentSchema>,
+    /// Which input and output files are required. Every builtin declares them, and the
+    /// executor checks the staged files of a task against it before running the builtin.
+    #[serde(default)]
+    pub staging: Option<StagingDeclaration>,
+}
+
+/// Machine-readable description of a builtin for clients: the descriptor with the arguments
+/// as a draft-07 JSON Schema.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FunctionManifest {
+    pub name: String,
+    pub version: u32,
+    pub schema_json: serde_json::Value,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl FunctionDescriptor {
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            version: default_version(),
+            ..Default::default()
+        }
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn arguments(mut self, arguments: &[&str]) -> Self {
+        self.arguments = arguments.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.tags = tags.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Describes the arguments by `schema`, which also names them.
+    pub fn schema(mut self, schema: ArgumentSchema) -> Self {
+        self.arguments = schema.names().into_iter().map(String::from).collect();
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Declares the input and output files by `staging`, which also lists them.
+    pub fn staging(mut self, staging: StagingDeclaration) -> Self {
+        let keys = |specs: &[FileSpec]| specs.iter().map(|s| s.key.to_string()).collect();
+        self.inputs = keys(&staging.inputs);
+        self.outputs = keys(&staging.outputs);
+        self.staging = Some(staging);
+        self
+    }
+
+    /// The schema of the arguments. Builtins without one get a schema which only checks
+    /// argument names.
+    pub fn argument_schema(&self) -> ArgumentSchema {
+        match &self.schema {
+            Some(schema) => schema.clone(),
+            None => ArgumentSchema::from_names(&self.arguments),
+        }
+    }
+
+    pub fn manifest(&self) -> FunctionManifest {
+        FunctionManifest {
+            name: self.name.clone(),
+            version: self.version,
+            schema_json: self.argument_schema().to_json_schema(),
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            tags: self.tags.clone(),
+        }
+    }
+
+    pub fn static_arguments(mut self, static_arguments: &[&str]) -> Self {
+        self.static_arguments = static_arguments.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// The static config among `arguments`, and its digest.
+    fn static_config(&self, arguments: &FunctionArguments) -> (FunctionArguments, Digest) {
+        let mut config = FunctionArguments::default();
+        for name in &self.static_arguments {
+            if let Ok(value) = arguments.get(name) {
+                config.insert(name.clone(), value.clone());
+            }
+        }
+        // Arguments are kept sorted by name, so equal configs serialize equally.
+        let digest = Digest::compute(config.clone().into_string().as_bytes());
+        (config, digest)
+    }
+
+    /// Whether the builtin may run under `policy`. Untagged builtins are always enabled.
+    pub fn enabled(&self, policy: &ExecutionPolicy) -> bool {
+        self.tags.is_empty() || policy.allows(&self.name, &self.tags)
+    }
+}
+
+/// Prepared instance of a builtin version for the static config with the given digest.
+struct PreparedEntry {
+    name: String,
+    version: u32,
+    config: Digest,
+    prepared: Arc<dyn PreparedFunction>,
+}
+
+/// Prepared builtins, least recently used first.
+#[derive(Default)]
+struct PreparedCache {
+    capacity: usize,
+    entries: Vec<PreparedEntry>,
+}
+
+impl PreparedCache {
+    fn get_or_prepare(
+        &mut self,
+        descriptor: &FunctionDescriptor,
+        arguments: &FunctionArguments,
+        prepare: PrepareFn,
+    ) -> anyhow::Result<Arc<dyn PreparedFunction>> {
+        let (config, digest) = descriptor.static_config(arguments);
+        let position = self.entries.iter().position(|entry| {
+            entry.name == descriptor.name
+                && entry.version == descriptor.version
+                && entry.config == digest
+        });
+        if let Some(position) = position {
+            let entry = self.entries.remove(position);
+            let prepared = entry.prepared.clone();
+            self.entries.push(entry);
+            return Ok(prepared);
+        }
+
+        let prepared = prepare(&config)?;
+        if self.capacity == 0 {
+            return Ok(prepared);
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.remove(0).prepared.invalidate();
+        }
+        self.entries.push(PreparedEntry {
+            name: descriptor.name.clone(),
+            version: descriptor.version,
+            config: digest,
+            prepared: prepared.clone(),
+        });
+        Ok(prepared)
+    }
+
+    fn invalidate(&mut self, name: &str) {
+        let (invalidated, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| entry.name == name);
+        self.entries = kept;
+        for entry in invalidated {
+            entry.prepared.invalidate();
+        }
+    }
+}
+
+/// A builtin of this crate, whether or not its feature is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KnownBuiltin {
+    pub name: &'static str,
+    /// The feature group, such as `dataproc`, which enables the feature.
+    pub group: &'static str,
+    /// Whether the `builtin_*` feature of the builtin is enabled in this build.
+    pub compiled: bool,
+}
+
+macro_rules! known_builtins {
+    ($($name:tt => $feature:tt in $group:ident,)*) => {
+        /// All builtins of this crate with their features and groups. The `[features]` of
+        /// Cargo.toml must agree with it, which the registry tests check for the enabled ones.
+        pub const BUILTINS: &[KnownBuiltin] = &[$(KnownBuiltin {
+            name: $name,
+            group: stringify!($group),
+            compiled: cfg!(feature = $feature),
+        },)*];
+
+        /// Names of all builtins of this crate, so that builtins missing from the build can be
+        /// told apart from unknown functions.
+        pub const KNOWN_BUILTINS: &[&str] = &[$($name,)*];
+    };
+}
+
+known_builtins! {
+    "builtin-anonymize" => "builtin_anonymize" in dataproc,
+    "builtin-benford" => "builtin_benford" in dataproc,
+    "builtin-csv-join" => "builtin_csv_join" in dataproc,
+    "builtin-dedup" => "builtin_dedup" in dataproc,
+    "builtin-echo" => "builtin_echo" in dataproc,
+    "builtin-face-detection" => "builtin_face_detection" in ml,
+    "builtin-format-convert" => "builtin_format_convert" in dataproc,
+    "builtin-fuzzy-intersect" => "builtin_fuzzy_intersect" in crypto,
+    "builtin-gbdt-predict" => "builtin_gbdt_predict" in ml,
+    "builtin-gbdt-train" => "builtin_gbdt_train" in ml,
+    "builtin-geofence" => "builtin_geofence" in dataproc,
+    "builtin-histogram" => "builtin_histogram" in dataproc,
+    "builtin-image-resize" => "builtin_image_resize" in ml,
+    "builtin-join" => "builtin_join" in dataproc,
+    "builtin-logistic-regression-predict" => "builtin_logistic_regression_predict" in ml,
+    "builtin-logistic-regression-train" => "builtin_logistic_regression_train" in ml,
+    "builtin-manifest" => "builtin_manifest" in crypto,
+    "builtin-normalize-eol" => "builtin_normalize_eol" in dataproc,
+    "builtin-online-decrypt" => "builtin_online_decrypt" in crypto,
+    "builtin-ordered-set-intersect" => "builtin_ordered_set_intersect" in crypto,
+    "builtin-outlier-filter" => "builtin_outlier_filter" in dataproc,
+    "builtin-passthrough" => "builtin_passthrough" in dataproc,
+    "builtin-password-check" => "builtin_password_check" in crypto,
+    "builtin_principal_components_analysis" => "builtin_principal_components_analysis" in ml,
+    "builtin-private-join-and-compute" => "builtin_private_join_and_compute" in crypto,
+    "builtin-private-join-compute" => "builtin_private_join_compute" in crypto,
+    "builtin-profile" => "builtin_profile" in dataproc,
+    "builtin-redact" => "builtin_redact" in dataproc,
+    "builtin-resample" => "builtin_resample" in dataproc,
+    "builtin-rsa-sign" => "builtin_rsa_sign" in crypto,
+    "builtin-sample" => "builtin_sample" in dataproc,
+    "builtin-tail" => "builtin_tail" in dataproc,
+    "builtin-topk" => "builtin_topk" in dataproc,
+    "builtin-train-test-split" => "builtin_train_test_split" in ml,
+    "builtin-transpose" => "builtin_transpose" in dataproc,
+}
+
+/// Builtin functions compiled into this build, looked up by name and version. Prepared
+/// builtins are prepared on their first execution with a static config and kept for the
+/// following ones, up to a capacity, so a registry should live as long as the worker.
+pub struct BuiltinRegistry {
+    builtins: Vec<(FunctionDescriptor, Builtin)>,
+    prepared: Mutex<PreparedCache>,
+}
+
+impl Default for BuiltinRegistry {
+    fn default() -> Self {
+        Self {
+            builtins: Vec::new(),
+            prepared: Mutex::new(PreparedCache {
+                capacity: DEFAULT_PREPARED_CAPACITY,
+                entries: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl BuiltinRegistry {
+    /// Keeps up to `capacity` prepared builtins, evicting the least recently used ones.
+    pub fn with_prepared_capacity(self, capacity: usize) -> Self {
+        self.prepared.lock().unwrap().capacity = capacity;
+        self
+    }
+
+    fn register(&mut self, descriptor: FunctionDescriptor, run: BuiltinFn) {
+        self.register_builtin(descriptor, Builtin::Function(run));
+    }
+
+    /// Registers a builtin which is prepared once per static config, see `PreparedFunction`.
+    fn register_prepared(&mut self, descriptor: FunctionDescriptor, prepare: PrepareFn) {
+        self.register_builtin(descriptor, Builtin::Prepared(prepare));
+    }
+
+    fn register_builtin(&mut self, descriptor: FunctionDescriptor, builtin: Builtin) {
+        debug_assert!(self
+            .find_versioned(
+                &descriptor.name,
+                VersionConstraint::Exact(descriptor.version)
+            )
+            .is_none());
+        self.builtins.push((descriptor, builtin));
+    }
+
+    /// Finds the latest version of the builtin called `name`.
+    fn find(&self, name: &str) -> Option<&(FunctionDescriptor, Builtin)> {
+        self.find_versioned(name, VersionConstraint::Any)
+    }
+
+    /// Finds the latest version of the builtin called `name` which satisfies `constraint`.
+    fn find_versioned(
+        &self,
+        name: &str,
+        constraint: VersionConstraint,
+    ) -> Option<&(FunctionDescriptor, Builtin)> {
+        self.builtins
+            .iter()
+            .filter(|(descriptor, _)| {
+                descriptor.name == name && constraint.matches(descriptor.version)
+            })
+            .max_by_key(|(descriptor, _)| descriptor.version)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    /// Lists the builtins enabled under `policy`.
+    pub fn list(&self, policy: &ExecutionPolicy) -> Vec<FunctionDescriptor> {
+        self.builtins
+            .iter()
+            .filter(|(descriptor, _)| descriptor.enabled(policy))
+            .map(|(descriptor, _)| descriptor.clone())
+            .collect()
+    }
+
+    /// Manifests of the builtins enabled under `policy`, for listing them to clients.
+    pub fn manifests(&self, policy: &ExecutionPolicy) -> Vec<FunctionManifest> {
+        self.list(policy)
+            .iter()
+            .map(FunctionDescriptor::manifest)
+            .collect()
+    }
+
+    /// Manifest of the latest version of the builtin called `name`.
+    pub fn describe(&self, name: &str) -> Option<FunctionManifest> {
+        self.find(name).map(|(descriptor, _)| descriptor.manifest())
+    }
+
+    /// Declared files of the builtin which `run` would run for `name`, if it declares them.
+    pub fn staging(&self, name: &str) -> Option<&StagingDeclaration> {
+        let (name, constraint) = parse_versioned_name(name).ok()?;
+        let (descriptor, _) = self.find_versioned(name, constraint)?;
+        descriptor.staging.as_ref()
+    }
+
+    /// Drops the prepared instances of the builtin called `name`, e.g. because the files its
+    /// static config refers to changed, so that its next execution prepares it again.
+    pub fn invalidate(&self, name: &str) {
+        self.prepared.lock().unwrap().invalidate(name);
+    }
+
+    /// Runs the builtin called `name`, failing with `NotFound` if there is none, with
+    /// `NotCompiled` if it was left out of the build and with `FunctionDisabled` if `policy`
+    /// does not allow it. The name may be followed by a version constraint, as in
+    /// `builtin-echo@1`; without one the latest version runs.
+    pub fn run(
+        &self,
+        name: &str,
+        policy: &ExecutionPolicy,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        self.lookup(name, policy)?.run(arguments, runtime)
+    }
+
+    /// Runs the latest version of the builtin called `name` which satisfies `constraint`,
+    /// failing with `NotFound` or `NotCompiled` if there is no such builtin, with
+    /// `NoMatchingVersion` if none of its versions qualifies and with `FunctionDisabled` if
+    /// `policy` does not allow it.
+    pub fn run_versioned(
+        &self,
+        name: &str,
+        constraint: VersionConstraint,
+        policy: &ExecutionPolicy,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        self.lookup_versioned(name, constraint, policy)?
+            .run(arguments, runtime)
+    }
+
+    /// The builtin `run` would run for `name` under `policy`, failing as `run` does before
+    /// the builtin starts. Lets callers check the builtin against the task first.
+    pub fn lookup(
+        &self,
+        name: &str,
+        policy: &ExecutionPolicy,
+    ) -> anyhow::Result<ResolvedBuiltin<'_>> {
+        let (name, constraint) = parse_versioned_name(name)?;
+        self.lookup_versioned(name, constraint, policy)
+    }
+
+    /// The builtin `run_versioned` would run, failing as `run_versioned` does before the
+    /// builtin starts.
+    pub fn lookup_versioned(
+        &self,
+        name: &str,
+        constraint: VersionConstraint,
+        policy: &ExecutionPolicy,
+    ) -> anyhow::Result<ResolvedBuiltin<'_>> {
+        if !self.contains(name) {
+            if KNOWN_BUILTINS.contains(&name) {
+                return Err(NotCompiled(name.to_string()).into());
+            }
+            return Err(NotFound(name.to_string()).into());
+        }
+        let (descriptor, builtin) =
+            self.find_versioned(name, constraint)
+                .ok_or_else(|| NoMatchingVersion {
+                    name: name.to_string(),
+                    constraint,
+                })?;
+        if !descriptor.enabled(policy) {
+            return Err(FunctionDisabled(name.to_string()).into());
+        }
+        Ok(ResolvedBuiltin {
+            registry: self,
+            descriptor,
+            builtin,
+        })
+    }
+}
+
+/// A builtin version which `BuiltinRegistry::lookup` found and the execution policy allows.
+pub struct ResolvedBuiltin<'a> {
+    registry: &'a BuiltinRegistry,
+    descriptor: &'a FunctionDescriptor,
+    builtin: &'a Builtin,
+}
+
+impl ResolvedBuiltin<'_> {
+    pub fn descriptor(&self) -> &FunctionDescriptor {
+        self.descriptor
+    }
+
+    /// Runs the builtin, preparing it first if it is a prepared builtin.
+    pub fn run(
+        self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        match self.builtin {
+            Builtin::Function(run) => run(arguments, runtime),
+            Builtin::Prepared(prepare) => {
+                // Preparing under the lock keeps concurrent executions from preparing the same
+                // instance twice. The execution itself runs without it.
+                let prepared = self.registry.prepared.lock().unwrap().get_or_prepare(
+                    self.descriptor,
+                    &arguments,
+                    *prepare,
+                )?;
+                prepared.run(arguments, runtime)
+            }
+        }
+    }
+}
+
+/// Splits a name like `builtin-echo@1` into the name and the version constraint.
+fn parse_versioned_name(name: &str) -> Result<(&str, VersionConstraint), FunctionError> {
+    match name.split_once('@') {
+        Some((name, constraint)) => Ok((name, constraint.parse()?)),
+        None => Ok((name, VersionConstraint::Any)),
+    }
+}
+
+/// The registry of the builtins enabled by the `builtin_*` features, or by the groups of them
+/// such as `dataproc`.
+pub fn registry() -> BuiltinRegistry {
+    #[allow(unused_mut)]
+    let mut registry = BuiltinRegistry::default();
+
+    #[cfg(feature = "builtin_echo")]
+    registry.register(
+        FunctionDescriptor::new(Echo::NAME)
+            .schema(Echo::argument_schema())
+            .staging(StagingDeclaration::new()),
+        |arguments, runtime| Ok(Echo::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_gbdt_predict")]
+    registry.register(
+        FunctionDescriptor::new(GbdtPredict::NAME)
+            .arguments(&["num_threads"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("model_file").required())
+                    .input(FileSpec::fixed("data_file").required())
+                    .output(FileSpec::fixed("result_file").required()),
+            ),
+        |arguments, runtime| GbdtPredict::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_gbdt_train")]
+    registry.register(
+        FunctionDescriptor::new(GbdtTrain::NAME)
+            .schema(GbdtTrain::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("training_data").required())
+                    .output(FileSpec::fixed("trained_model").required())
+                    .output(FileSpec::fixed("report")),
+            ),
+        |arguments, runtime| GbdtTrain::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_logistic_regression_train")]
+    registry.register(
+        FunctionDescriptor::new(LogisticRegressionTrain::NAME)
+            .arguments(&["alg_alpha", "alg_iters", "feature_size"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("training_data").required())
+                    .output(FileSpec::fixed("model_file").required())
+                    .output(FileSpec::fixed("report")),
+            ),
+        |arguments, runtime| LogisticRegressionTrain::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_logistic_regression_predict")]
+    registry.register(
+        FunctionDescriptor::new(LogisticRegressionPredict::NAME).staging(
+            StagingDeclaration::new()
+                .input(FileSpec::fixed("model_file").required())
+                .input(FileSpec::fixed("data_file").required())
+                .output(FileSpec::fixed("result_file").required()),
+        ),
+        |arguments, runtime| LogisticRegressionPredict::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_online_decrypt")]
+    registry.register(
+        FunctionDescriptor::new(OnlineDecrypt::NAME)
+            .arguments(&[
+                "key",
+                "nonce",
+                "encrypted_data",
+                "algorithm",
+                "expected_sha256",
+            ])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed(OnlineDecrypt::INPUT).required())
+                    .output(FileSpec::fixed(OnlineDecrypt::OUTPUT).required()),
+            ),
+        |arguments, runtime| OnlineDecrypt::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_private_join_and_compute")]
+    registry.register(
+        FunctionDescriptor::new(PrivateJoinAndCompute::NAME)
+            .arguments(&["num_user"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::prefix("input_data").required())
+                    .output(FileSpec::prefix("output_data").required()),
+            ),
+        |arguments, runtime| PrivateJoinAndCompute::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_private_join_compute")]
+    registry.register(
+        FunctionDescriptor::new(PrivateJoinCompute::NAME)
+            .arguments(&["operation", "min_intersection_size"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("ids_a").required())
+                    .input(FileSpec::fixed("data_b").required()),
+            ),
+        |arguments, runtime| Ok(PrivateJoinCompute::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_ordered_set_intersect")]
+    registry.register(
+        FunctionDescriptor::new(OrderedSetIntersect::NAME)
+            .arguments(&["order"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("input_data1").required())
+                    .input(FileSpec::fixed("input_data2").required())
+                    .output(FileSpec::fixed("output_result1").required())
+                    .output(FileSpec::fixed("output_result2").required()),
+            ),
+        |arguments, runtime| OrderedSetIntersect::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_rsa_sign")]
+    registry.register(
+        FunctionDescriptor::new(RsaSign::NAME)
+            .arguments(&["data"])
+            .staging(StagingDeclaration::new().input(FileSpec::fixed("rsa_key").required())),
+        |arguments, runtime| RsaSign::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_principal_components_analysis")]
+    registry.register(
+        FunctionDescriptor::new(PrincipalComponentsAnalysis::NAME)
+            .arguments(&["n", "center", "feature_size"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("input_data").required())
+                    .output(FileSpec::fixed("output_data").required()),
+            ),
+        |arguments, runtime| PrincipalComponentsAnalysis::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_face_detection")]
+    registry.register_prepared(
+        FunctionDescriptor::new(FaceDetection::NAME)
+            .arguments(&[
+                "image",
+                "window_size",
+                "slide_window_step_x",
+                "slide_window_step_y",
+                "min_face_size",
+                "max_face_size",
+                "pyramid_scale_factor",
+                "score_thresh",
+            ])
+            .staging(StagingDeclaration::new()),
+        FaceDetection::prepare,
+    );
+    #[cfg(feature = "builtin_password_check")]
+    registry.register(
+        FunctionDescriptor::new(PasswordCheck::NAME)
+            .arguments(&["candidates_are_hashed"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("corpus").required())
+                    .input(FileSpec::fixed("candidates").required())
+                    .output(FileSpec::fixed("result").required()),
+            ),
+        |arguments, runtime| PasswordCheck::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_fuzzy_intersect")]
+    registry.register(
+        FunctionDescriptor::new(FuzzyIntersect::NAME)
+            .arguments(&["normalize", "max_edit_distance", "max_fuzzy_length"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("set_a").required())
+                    .input(FileSpec::fixed("set_b").required())
+                    .output(FileSpec::fixed("matched_pairs").required()),
+            )
+            .tags(FuzzyIntersect::TAGS),
+        |arguments, runtime| FuzzyIntersect::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_image_resize")]
+    registry.register(
+        FunctionDescriptor::new(ImageResize::NAME)
+            .arguments(&[
+                "max_width",
+                "max_height",
+                "format",
+                "quality",
+                "max_input_pixels",
+            ])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("image").required())
+                    .output(FileSpec::fixed("thumbnail").required()),
+            ),
+        |arguments, runtime| ImageResize::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_dedup")]
+    registry.register(
+        FunctionDescriptor::new(Dedup::NAME)
+            .schema(Dedup::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Dedup::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_sample")]
+    registry.register(
+        FunctionDescriptor::new(Sample::NAME)
+            .arguments(&["input", "output", "k", "fraction", "seed"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Ok(Sample::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_profile")]
+    registry.register(
+        FunctionDescriptor::new(Profile::NAME)
+            .arguments(&["max_distinct_tracked"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("dataset").required())
+                    .output(FileSpec::fixed("profile").required()),
+            ),
+        |arguments, runtime| Ok(Profile::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_anonymize")]
+    registry.register(
+        FunctionDescriptor::new(Anonymize::NAME)
+            .arguments(&["rules", "salt"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("dataset").required())
+                    .output(FileSpec::fixed("anonymized").required()),
+            ),
+        |arguments, runtime| Ok(Anonymize::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_transpose")]
+    registry.register(
+        FunctionDescriptor::new(Transpose::NAME)
+            .arguments(&["input", "output", "max_cells", "pad_ragged"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Ok(Transpose::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_resample")]
+    registry.register(
+        FunctionDescriptor::new(Resample::NAME)
+            .schema(Resample::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("series").required())
+                    .output(FileSpec::fixed("resampled").required()),
+            ),
+        |arguments, runtime| Ok(Resample::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_benford")]
+    registry.register(
+        FunctionDescriptor::new(BenfordCheck::NAME)
+            .schema(BenfordCheck::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("dataset").required())
+                    .output(FileSpec::fixed("analysis").required()),
+            ),
+        |arguments, runtime| Ok(BenfordCheck::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_histogram")]
+    registry.register(
+        FunctionDescriptor::new(Histogram::NAME)
+            .schema(Histogram::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Ok(Histogram::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_topk")]
+    registry.register(
+        FunctionDescriptor::new(TopK::NAME)
+            .schema(TopK::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("dataset").required())
+                    .output(FileSpec::fixed("top_values").required()),
+            ),
+        |arguments, runtime| Ok(TopK::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_geofence")]
+    registry.register(
+        FunctionDescriptor::new(GeoFence::NAME)
+            .schema(GeoFence::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("points").required())
+                    .input(FileSpec::fixed("fences").required())
+                    .output(FileSpec::fixed("counts").required()),
+            ),
+        |arguments, runtime| Ok(GeoFence::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_normalize_eol")]
+    registry.register(
+        FunctionDescriptor::new(NormalizeEol::NAME)
+            .schema(NormalizeEol::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Ok(NormalizeEol::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_outlier_filter")]
+    registry.register(
+        FunctionDescriptor::new(OutlierFilter::NAME)
+            .schema(OutlierFilter::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("dataset").required())
+                    .output(FileSpec::fixed("result").required()),
+            ),
+        |arguments, runtime| Ok(OutlierFilter::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_manifest")]
+    registry.register(
+        FunctionDescriptor::new(Manifest::NAME)
+            .schema(Manifest::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::prefix("input"))
+                    .output(FileSpec::fixed("manifest").required()),
+            ),
+        |arguments, runtime| Ok(Manifest::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_csv_join")]
+    registry.register(
+        FunctionDescriptor::new(CsvJoin::NAME)
+            .schema(CsvJoin::argument_schema())
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("left").required())
+                    .input(FileSpec::fixed("right").required())
+                    .output(FileSpec::fixed("result").required()),
+            ),
+        |arguments, runtime| Ok(CsvJoin::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_train_test_split")]
+    registry.register(
+        FunctionDescriptor::new(TrainTestSplit::NAME)
+            .arguments(&["test_fraction", "shuffle", "seed", "stratify_column"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::fixed("dataset").required())
+                    .output(FileSpec::fixed("train").required())
+                    .output(FileSpec::fixed("test").required()),
+            ),
+        |arguments, runtime| Ok(TrainTestSplit::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_tail")]
+    registry.register(
+        FunctionDescriptor::new(Tail::NAME)
+            .arguments(&["input", "output", "lines"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Tail::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_join")]
+    registry.register(
+        FunctionDescriptor::new(Join::NAME)
+            .arguments(&[
+                "left",
+                "right",
+                "left_key",
+                "right_key",
+                "type",
+                "key_type",
+                "output",
+            ])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("left", "left").required())
+                    .input(FileSpec::argument("right", "right").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Join::new().run(arguments, runtime),
+    );
+    #[cfg(feature = "builtin_format_convert")]
+    registry.register(
+        FunctionDescriptor::new(FormatConvert::NAME)
+            .arguments(&[
+                "input",
+                "output",
+                "direction",
+                "delimiter",
+                "columns",
+                "type_inference",
+                "max_bad_records",
+                "auto_detect",
+            ])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Ok(FormatConvert::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_redact")]
+    registry.register(
+        FunctionDescriptor::new(Redact::NAME)
+            .arguments(&["input", "output", "drop", "hash", "salt"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::argument("input", "input").required())
+                    .output(FileSpec::argument("output", "output").required()),
+            ),
+        |arguments, runtime| Ok(Redact::new().run(arguments, runtime)?),
+    );
+    #[cfg(feature = "builtin_passthrough")]
+    registry.register(
+        FunctionDescriptor::new(Passthrough::NAME)
+            .arguments(&["flip_byte_at", "delay_ms_per_chunk"])
+            .staging(
+                StagingDeclaration::new()
+                    .input(FileSpec::prefix(""))
+                    .output(FileSpec::prefix("")),
+            ),
+        |arguments, runtime| Ok(Passthrough::new().run(arguments, runtime)?),
+    );
+
+    registry
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    #[allow(clippy::vec_init_then_push)]
+    pub fn run_tests() -> bool {
+        let mut v: Vec<bool> = Vec::new();
+        v.push(run_tests!(
+            test_registry_lists_builtins,
+            test_registry_not_found,
+            test_registry_versions,
+            test_registry_policy,
+            test_registry_prepared,
+            test_registry_prepared_capacity,
+        ));
+        #[cfg(feature = "builtin_echo")]
+        v.push(run_tests!(test_registry_run));
+        // These look at builtins of all groups.
+        #[cfg(feature = "full")]
+        v.push(run_tests!(test_registry_describe, test_argument_schemas));
+        v.iter().all(|&x| x)
+    }
+
+    fn test_registry_lists_builtins() {
+        let registry = registry();
+        let policy = ExecutionPolicy::new().allow_tag("experimental");
+        let listed: HashSet<String> = registry.list(&policy).into_iter().map(|d| d.name).collect();
+        let compiled: HashSet<String> = BUILTINS
+            .iter()
+            .filter(|builtin| builtin.compiled)
+            .map(|builtin| builtin.name.to_string())
+            .collect();
+        assert_eq!(listed, compiled);
+        // The executor checks the staged files of every builtin before running it.
+        assert!(listed.iter().all(|name| registry.staging(name).is_some()));
+
+        // Groups enable all of their builtins. Single builtins may be enabled on top of them.
+        let groups = [
+            ("ml", cfg!(feature = "ml")),
+            ("crypto", cfg!(feature = "crypto")),
+            ("dataproc", cfg!(feature = "dataproc")),
+        ];
+        for builtin in BUILTINS {
+            let (_, enabled) = groups
+                .iter()
+                .find(|(group, _)| *group == builtin.group)
+                .unwrap();
+            if *enabled {
+                assert!(
+                    builtin.compiled,
+                    "{} is not in {}",
+                    builtin.name, builtin.group
+                );
+            }
+        }
+        if cfg!(feature = "full") {
+            assert_eq!(listed.len(), KNOWN_BUILTINS.len());
+        }
+    }
+
+    #[cfg(feature = "builtin_echo")]
+    fn test_registry_run() {
+        let arguments =
+            FunctionArguments::from_json(json!({"message": "Hello Teaclave!"})).unwrap();
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let summary = registry()
+            .run(Echo::NAME, &ExecutionPolicy::default(), arguments, runtime)
+            .unwrap();
+        assert_eq!(summary.message, "Hello Teaclave!");
+    }
+
+    fn test_registry_not_found() {
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let registry = registry();
+        assert!(!registry.contains("builtin-unknown"));
+        let error = registry
+            .run(
+                "builtin-unknown",
+                &ExecutionPolicy::default(),
+                FunctionArguments::default(),
+                runtime,
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<NotFound>(),
+            Some(&NotFound("builtin-unknown".to_string()))
+        );
+
+        // Known builtins missing from the registry were left out of the build.
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let error = BuiltinRegistry::default()
+            .run(
+                "builtin-echo@1",
+                &ExecutionPolicy::default(),
+                FunctionArguments::default(),
+                runtime,
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<NotCompiled>(),
+            Some(&NotCompiled("builtin-echo".to_string()))
+        );
+        assert_eq!(
+            error.to_string(),
+            "Function not compiled into this enclave: builtin-echo"
+        );
+    }
+
+    fn test_registry_versions() {
+        let mut registry = BuiltinRegistry::default();
+        registry.register(FunctionDescriptor::new("builtin-versioned"), |_, _| {
+            Ok(FunctionSummary::new("v1"))
+        });
+        registry.register(
+            FunctionDescriptor::new("builtin-versioned").version(2),
+            |_, _| Ok(FunctionSummary::new("v2")),
+        );
+        let run = |name: &str| {
+            let runtime = Box::new(RawIoRuntime::new(
+                StagedFiles::default(),
+                StagedFiles::default(),
+            ));
+            registry
+                .run(
+                    name,
+                    &ExecutionPolicy::default(),
+                    FunctionArguments::default(),
+                    runtime,
+                )
+                .map(|summary| summary.message)
+        };
+
+        assert_eq!(run("builtin-versioned").unwrap(), "v2");
+        assert_eq!(run("builtin-versioned@1").unwrap(), "v1");
+        assert_eq!(run("builtin-versioned@2").unwrap(), "v2");
+        assert_eq!(run("builtin-versioned@*").unwrap(), "v2");
+        assert_eq!(run("builtin-versioned@>=1").unwrap(), "v2");
+        assert_eq!(run("builtin-versioned@0-1").unwrap(), "v1");
+        assert_eq!(registry.list(&ExecutionPolicy::default()).len(), 2);
+
+        // Lookups resolve the version which would run, without running it.
+        let policy = ExecutionPolicy::default();
+        let builtin = registry.lookup("builtin-versioned@0-1", &policy).unwrap();
+        assert_eq!(builtin.descriptor().version, 1);
+        assert_eq!(
+            registry
+                .lookup("builtin-versioned", &policy)
+                .unwrap()
+                .descriptor()
+                .version,
+            2
+        );
+
+        let error = run("builtin-versioned@3").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<NoMatchingVersion>(),
+            Some(&NoMatchingVersion {
+                name: "builtin-versioned".to_string(),
+                constraint: VersionConstraint::Exact(3),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "No version of builtin-versioned satisfies 3"
+        );
+        assert!(run("builtin-unknown@1")
+            .unwrap_err()
+            .downcast_ref::<NotFound>()
+            .is_some());
+        let error = FunctionError::from(run("builtin-versioned@two").unwrap_err());
+        assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
+    }
+
+    fn test_registry_policy() {
+        let mut registry = BuiltinRegistry::default();
+        registry.register(FunctionDescriptor::new("builtin-stable"), |_, _| {
+            Ok(FunctionSummary::new("stable"))
+        });
+        registry.register(
+            FunctionDescriptor::new("builtin-beta").tags(&["experimental"]),
+            |_, _| Ok(FunctionSummary::new("beta")),
+        );
+        registry.register(
+            FunctionDescriptor::new("builtin-dp").tags(&["experimental", "differential_privacy"]),
+            |_, _| Ok(FunctionSummary::new("dp")),
+        );
+        let run = |name: &str, policy: &ExecutionPolicy| {
+            let runtime = Box::new(RawIoRuntime::new(
+                StagedFiles::default(),
+                StagedFiles::default(),
+            ));
+            // Arguments the builtins would reject, to show that the policy is checked first.
+            let arguments = FunctionArguments::from_json(json!({"unexpected": true})).unwrap();
+            registry
+                .run(name, policy, arguments, runtime)
+                .map(|summary| summary.message)
+        };
+        let listed = |policy: &ExecutionPolicy| {
+            let mut names: Vec<String> =
+                registry.list(policy).into_iter().map(|d| d.name).collect();
+            names.sort();
+            names
+        };
+
+        let default = ExecutionPolicy::default();
+        assert_eq!(run("builtin-stable", &default).unwrap(), "stable");
+        let error = run("builtin-beta", &default).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<FunctionDisabled>(),
+            Some(&FunctionDisabled("builtin-beta".to_string()))
+        );
+        assert_eq!(
+            error.to_string(),
+            "Function disabled by the execution policy: builtin-beta"
+        );
+        assert!(run("builtin-unknown", &default)
+            .unwrap_err()
+            .downcast_ref::<NotFound>()
+            .is_some());
+        assert_eq!(listed(&default), vec!["builtin-stable"]);
+
+        let by_name = ExecutionPolicy::new().allow_function("builtin-beta");
+        assert_eq!(run("builtin-beta@1", &by_name).unwrap(), "beta");
+        assert!(run("builtin-dp", &by_name).is_err());
+        assert_eq!(listed(&by_name), vec!["builtin-beta", "builtin-stable"]);
+
+        let by_tag = ExecutionPolicy::new().allow_tag("differential_privacy");
+        assert_eq!(run("builtin-dp", &by_tag).unwrap(), "dp");
+        assert!(run("builtin-beta", &by_tag).is_err());
+        assert_eq!(run("builtin-stable", &by_tag).unwrap(), "stable");
+        assert_eq!(listed(&by_tag), vec!["builtin-dp", "builtin-stable"]);
+
+        let experimental = ExecutionPolicy::new().allow_tag("experimental");
+        assert_eq!(listed(&experimental).len(), 3);
+
+        // Gated builtins compiled into this build are disabled by default, too.
+        #[cfg(feature = "builtin_fuzzy_intersect")]
+        {
+            let registry = super::registry();
+            assert!(registry.contains(FuzzyIntersect::NAME));
+            assert!(!registry
+                .list(&default)
+                .iter()
+                .any(|d| d.name == FuzzyIntersect::NAME));
+        }
+    }
+
+    static PREPARED: AtomicUsize = AtomicUsize::new(0);
+    static INVALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    // Stands in for a builtin loading a model named by its static config.
+    struct CountingFunction {
+        model: String,
+    }
+
+    impl PreparedFunction for CountingFunction {
+        fn run(
+            &self,
+            arguments: FunctionArguments,
+            _runtime: FunctionRuntime,
+        ) -> anyhow::Result<FunctionSummary> {
+            let input = arguments.get("input")?.as_str().unwrap_or_default();
+            Ok(FunctionSummary::new(format!("{}({})", self.model, input)))
+        }
+
+        fn invalidate(&self) {
+            INVALIDATED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn counting_registry(capacity: usize) -> BuiltinRegistry {
+        PREPARED.store(0, Ordering::SeqCst);
+        INVALIDATED.store(0, Ordering::SeqCst);
+        let mut registry = BuiltinRegistry::default().with_prepared_capacity(capacity);
+        registry.register_prepared(
+            FunctionDescriptor::new("builtin-counting")
+                .arguments(&["model", "input"])
+                .static_arguments(&["model"]),
+            |config| {
+                PREPARED.fetch_add(1, Ordering::SeqCst);
+                let model = config.get("model")?.as_str().unwrap_or_default();
+                Ok(Arc::new(CountingFunction {
+                    model: model.to_string(),
+                }))
+            },
+        );
+        registry
+    }
+
+    fn run_counting(registry: &BuiltinRegistry, model: &str, input: &str) -> String {
+        let runtime = Box::new(RawIoRuntime::new(
+            StagedFiles::default(),
+            StagedFiles::default(),
+        ));
+        let arguments = FunctionArguments::from_json(json!({"model": model, "input": input}));
+        registry
+            .run(
+                "builtin-counting",
+                &ExecutionPolicy::default(),
+                arguments.unwrap(),
+                runtime,
+            )
+            .unwrap()
+            .message
+    }
+
+    fn test_registry_prepared() {
+        let registry = counting_registry(DEFAULT_PREPARED_CAPACITY);
+        assert_eq!(run_counting(&registry, "a", "1"), "a(1)");
+        assert_eq!(run_counting(&registry, "a", "2"), "a(2)");
+        assert_eq!(run_counting(&registry, "a", "3"), "a(3)");
+        assert_eq!(PREPARED.load(Ordering::SeqCst), 1);
+
+        // Another static config is prepared on its own; the first one stays cached.
+        assert_eq!(run_counting(&registry, "b", "4"), "b(4)");
+        assert_eq!(PREPARED.load(Ordering::SeqCst), 2);
+        assert_eq!(run_counting(&registry, "a", "5"), "a(5)");
+        assert_eq!(PREPARED.load(Ordering::SeqCst), 2);
+
+        registry.invalidate("builtin-counting");
+        assert_eq!(INVALIDATED.load(Ordering::SeqCst), 2);
+        assert_eq!(run_counting(&registry, "a", "6"), "a(6)");
+        assert_eq!(PREPARED.load(Ordering::SeqCst), 3);
+    }
+
+    fn test_registry_prepared_capacity() {
+        let registry = counting_registry(1);
+        run_counting(&registry, "a", "1");
+        run_counting(&registry, "b", "2");
+        assert_eq!(INVALIDATED.load(Ordering::SeqCst), 1);
+        run_counting(&registry, "a", "3");
+        assert_eq!(PREPARED.load(Ordering::SeqCst), 3);
+        assert_eq!(INVALIDATED.load(Ordering::SeqCst), 2);
+
+        // Without capacity every execution prepares anew.
+        let registry = counting_registry(0);
+        run_counting(&registry, "a", "1");
+        run_counting(&registry, "a", "2");
+        assert_eq!(PREPARED.load(Ordering::SeqCst), 2);
+        assert_eq!(INVALIDATED.load(Ordering::SeqCst), 0);
+    }
+
+    // Checks `arguments` against the subset of draft-07 JSON Schema which argument schemas
+    // export, returning the violations.
+    #[cfg(feature = "full")]
+    fn schema_violations(schema: &serde_json::Value, arguments: &serde_json::Value) -> Vec<String> {
+        let mut violations = Vec::new();
+        let properties = schema["properties"].as_object().unwrap();
+        let arguments = arguments.as_object().unwrap();
+        for required in schema["required"].as_array().unwrap() {
+            let name = required.as_str().unwrap();
+            if !arguments.contains_key(name) {
+                violations.push(format!("{}: required", name));
+            }
+        }
+        for (name, value) in arguments {
+            let property = match properties.get(name) {
+                Some(property) => property,
+                None => {
+                    if schema["additionalProperties"] == json!(false) {
+                        violations.push(format!("{}: not allowed", name));
+                    }
+                    continue;
+                }
+            };
+            // "type" is a single type or a list of types.
+            let types: Vec<&str> = match &property["type"] {
+                serde_json::Value::String(ty) => vec![ty.as_str()],
+                serde_json::Value::Array(types) => {
+                    types.iter().map(|ty| ty.as_str().unwrap()).collect()
+                }
+                _ => Vec::new(),
+            };
+            let type_matches = types.is_empty()
+                || types.iter().any(|&ty| match ty {
+                    "string" => value.is_string(),
+                    "integer" => value.is_i64() || value.is_u64(),
+                    "number" => value.is_number(),
+                    "boolean" => value.is_boolean(),
+                    "array" => value.is_array(),
+                    "object" => value.is_object(),
+                    ty => panic!("unexpected type {}", ty),
+                });
+            if !type_matches {
+                violations.push(format!("{}: type", name));
+            }
+            if let Some(allowed) = property["enum"].as_array() {
+                if !allowed.contains(value) {
+                    violations.push(format!("{}: enum", name));
+                }
+            }
+            if let Some(number) = value.as_f64() {
+                if property["minimum"]
+                    .as_f64()
+                    .map_or(false, |min| number < min)
+                    || property["maximum"]
+                        .as_f64()
+                        .map_or(false, |max| number > max)
+                {
+                    violations.push(format!("{}: range", name));
+                }
+            }
+        }
+        violations
+    }
+
+    #[cfg(feature = "full")]
+    fn test_registry_describe() {
+        let registry = registry();
+        let manifest = registry.describe(GbdtTrain::NAME).unwrap();
+        assert_eq!(manifest.name, GbdtTrain::NAME);
+        assert_eq!(manifest.inputs, vec!["training_data"]);
+        assert_eq!(manifest.outputs, vec!["trained_model", "report"]);
+        assert_eq!(
+            manifest.schema_json["$schema"],
+            "http://json-schema.org/draft-07/schema#"
+        );
+        assert_eq!(
+            manifest.schema_json["required"].as_array().unwrap().len(),
+            9
+        );
+        assert_eq!(
+            manifest.schema_json["properties"]["loss"]["description"],
+            "Loss function"
+        );
+        assert!(registry.describe("builtin-unknown").is_none());
+
+        // Declared files are listed in the descriptor notation.
+        let manifest = registry.describe(PrivateJoinAndCompute::NAME).unwrap();
+        assert_eq!(manifest.inputs, vec!["input_data*"]);
+        assert_eq!(manifest.outputs, vec!["output_data*"]);
+        let staging = registry
+            .staging("builtin-private-join-and-compute@1")
+            .unwrap();
+        assert!(staging.inputs[0].matches("input_data3"));
+        let staging = registry.staging(GbdtTrain::NAME).unwrap();
+        assert!(!staging.outputs[1].required);
+        assert!(registry
+            .staging("builtin-private-join-and-compute@x")
+            .is_none());
+
+        // Builtins without a schema describe their argument names.
+        let manifest = registry.describe(Tail::NAME).unwrap();
+        assert_eq!(
+            manifest.schema_json["properties"],
+            json!({"input": {}, "output": {}, "lines": {}})
+        );
+
+        let policy = ExecutionPolicy::default();
+        let manifests = registry.manifests(&policy);
+        assert_eq!(manifests.len(), registry.list(&policy).len());
+        assert!(manifests
+            .iter()
+            .any(|manifest| manifest == &registry.describe(Dedup::NAME).unwrap()));
+        let manifest = &manifests[0];
+        let json = serde_json::to_value(manifest).unwrap();
+        assert_eq!(
+            serde_json::from_value::<FunctionManifest>(json).unwrap(),
+            *manifest
+        );
+    }
+
+    #[cfg(feature = "full")]
+    fn test_argument_schemas() {
+        let registry = registry();
+        let schema = |name: &str| registry.describe(name).unwrap().schema_json;
+        let check = |name: &str, arguments: serde_json::Value, expected: &[&str]| {
+            assert_eq!(
+                schema_violations(&schema(name), &arguments),
+                expected,
+                "{} {}",
+                name,
+                arguments
+            );
+        };
+
+        check(Echo::NAME, json!({"message": "hi"}), &[]);
+        check(Echo::NAME, json!({"message": "hi", "fail": false}), &[]);
+        check(
+            Echo::NAME,
+            json!({"message": "hi", "fixed_len": 4, "pad": "-"}),
+            &[],
+        );
+        // Platform arguments are accepted whatever the builtin.
+        check(
+            Echo::NAME,
+            json!({"message": "hi", "save_log": "true"}),
+            &[],
+        );
+        check(Dedup::NAME, json!({"save_log": true}), &[]);
+        check(Dedup::NAME, json!({"save_log": 1}), &["save_log: type"]);
+        check(Echo::NAME, json!({}), &["message: required"]);
+        check(Echo::NAME, json!({"message": 1}), &["message: type"]);
+        check(
+            Echo::NAME,
+            json!({"message": "hi", "extra": 1}),
+            &["extra: not allowed"],
+        );
+
+        check(Dedup::NAME, json!({}), &[]);
+        check(
+            Dedup::NAME,
+            json!({"order": "sorted", "ignore_case": true}),
+            &[],
+        );
+        check(Dedup::NAME, json!({"order": "random"}), &["order: enum"]);
+        check(
+            Dedup::NAME,
+            json!({"ignore_case": "yes"}),
+            &["ignore_case: type"],
+        );
+
+        let training = json!({
+            "feature_size": 4,
+            "max_depth": 4,
+            "iterations": 100,
+            "shrinkage": 0.1,
+            "feature_sample_ratio": 1.0,
+            "data_sample_ratio": 1.0,
+            "min_leaf_size": 1,
+            "loss": "LAD",
+            "training_optimization_level": 2
+        });
+        check(GbdtTrain::NAME, training.clone(), &[]);
+        let with = |name: &str, value: serde_json::Value| {
+            let mut arguments = training.clone();
+            arguments[name] = value;
+            arguments
+        };
+        check(
+            GbdtTrain::NAME,
+            with("feature_sample_ratio", json!(1.5)),
+            &["feature_sample_ratio: range"],
+        );
+        check(
+            GbdtTrain::NAME,
+            with("max_depth", json!(0)),
+            &["max_depth: range"],
+        );
+        check(
+            GbdtTrain::NAME,
+            with("iterations", json!(2.5)),
+            &["iterations: type"],
+        );
+        check(
+            GbdtTrain::NAME,
+            with("loss", json!("Hinge")),
+            &["loss: enum"],
+        );
+        let mut missing = training;
+        missing.as_object_mut().unwrap().remove("loss");
+        check(GbdtTrain::NAME, missing, &["loss: required"]);
+    }
+}
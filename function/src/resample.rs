@@ -0,0 +1,586 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use chrono::{DateTime, TimeZone, Utc};
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_SERIES: &str = "series";
+const OUT_RESAMPLED: &str = "resampled";
+
+/// Default of `max_filled_intervals`. Every filled interval is an output row, so without a cap
+/// a single gap between two points far apart could write rows without end.
+const DEFAULT_MAX_FILLED_INTERVALS: u64 = 1 << 20;
+
+#[derive(Default)]
+pub struct Resample;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResampleArguments {
+    /// Width of the output intervals. Intervals are aligned to the UNIX epoch.
+    interval_seconds: i64,
+    #[serde(default)]
+    aggregation: Aggregation,
+    #[serde(default)]
+    fill: Fill,
+    #[serde(default)]
+    timestamp_format: TimestampFormat,
+    /// Longest run of empty intervals which `fill` fills. Longer gaps stay empty.
+    #[serde(default)]
+    max_gap_intervals: Option<u64>,
+    /// Most empty intervals which are filled in total. Filling more fails the task.
+    #[serde(default = "default_max_filled_intervals")]
+    max_filled_intervals: u64,
+}
+
+fn default_max_filled_intervals() -> u64 {
+    DEFAULT_MAX_FILLED_INTERVALS
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Aggregation {
+    Mean,
+    Sum,
+    Last,
+    Max,
+    Min,
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Aggregation::Mean
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Fill {
+    /// Leaves empty intervals out of the output.
+    None,
+    /// Repeats the value of the last interval before the gap.
+    Previous,
+    /// Interpolates linearly between the intervals around the gap.
+    Linear,
+    Zero,
+}
+
+impl Default for Fill {
+    fn default() -> Self {
+        Fill::None
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum TimestampFormat {
+    /// Whole seconds since the UNIX epoch, e.g. `1700000000`.
+    Unix,
+    /// E.g. `2023-11-14T22:13:20Z`. Output timestamps are written in UTC.
+    Rfc3339,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Unix
+    }
+}
+
+impl TimestampFormat {
+    fn parse(self, field: &str) -> Option<i64> {
+        match self {
+            TimestampFormat::Unix => field.parse().ok(),
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(field)
+                .ok()
+                .map(|t| t.timestamp()),
+        }
+    }
+
+    fn format(self, timestamp: i64) -> String {
+        match self {
+            TimestampFormat::Unix => timestamp.to_string(),
+            TimestampFormat::Rfc3339 => match Utc.timestamp_opt(timestamp, 0).single() {
+                Some(t) => t.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                None => timestamp.to_string(),
+            },
+        }
+    }
+}
+
+/// Aggregate of the points within one interval.
+struct Bucket {
+    index: i64,
+    count: usize,
+    sum: f64,
+    last: f64,
+    max: f64,
+    min: f64,
+}
+
+impl Bucket {
+    fn new(index: i64, value: f64) -> Self {
+        Self {
+            index,
+            count: 1,
+            sum: value,
+            last: value,
+            max: value,
+            min: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.last = value;
+        self.max = self.max.max(value);
+        self.min = self.min.min(value);
+    }
+
+    fn value(&self, aggregation: Aggregation) -> f64 {
+        match aggregation {
+            Aggregation::Mean => self.sum / self.count as f64,
+            Aggregation::Sum => self.sum,
+            Aggregation::Last => self.last,
+            Aggregation::Max => self.max,
+            Aggregation::Min => self.min,
+        }
+    }
+}
+
+/// Writes the resampled series, filling the gaps between consecutive non-empty intervals.
+struct ResampledWriter<'a> {
+    args: &'a ResampleArguments,
+    output: Box<dyn Write>,
+    previous: Option<(i64, f64)>,
+    intervals: u64,
+    filled: u64,
+    bytes: u64,
+}
+
+impl ResampledWriter<'_> {
+    fn write_header(&mut self, header: &str) -> anyhow::Result<()> {
+        let line = format!("{}\n", header);
+        self.output.write_all(line.as_bytes())?;
+        self.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    fn write_row(&mut self, index: i64, value: f64) -> anyhow::Result<()> {
+        let line = format!(
+            "{},{}\n",
+            self.args
+                .timestamp_format
+                .format(index * self.args.interval_seconds),
+            value
+        );
+        self.output.write_all(line.as_bytes())?;
+        self.bytes += line.len() as u64;
+        self.intervals += 1;
+        Ok(())
+    }
+
+    fn push(&mut self, runtime: &FunctionRuntime, index: i64, value: f64) -> anyhow::Result<()> {
+        if let Some((previous_index, previous_value)) = self.previous {
+            let gap = (index - previous_index - 1) as u64;
+            let fillable = self.args.max_gap_intervals.map_or(true, |max| gap <= max);
+            if gap > 0 && fillable && self.args.fill != Fill::None {
+                if self.filled + gap > self.args.max_filled_intervals {
+                    return Err(FunctionError::invalid_input_data(
+                        IN_SERIES,
+                        format!(
+                            "filling the {} empty intervals before {} exceeds \
+                             max_filled_intervals {}",
+                            gap,
+                            self.args
+                                .timestamp_format
+                                .format(index * self.args.interval_seconds),
+                            self.args.max_filled_intervals
+                        ),
+                    )
+                    .into());
+                }
+                for missing in previous_index + 1..index {
+                    if missing % CANCELLATION_CHECK_INTERVAL as i64 == 0 {
+                        runtime.cancellation().checkpoint()?;
+                    }
+                    let filled = match self.args.fill {
+                        Fill::Previous => previous_value,
+                        Fill::Zero => 0.0,
+                        Fill::Linear => {
+                            let position =
+                                (missing - previous_index) as f64 / (index - previous_index) as f64;
+                            previous_value + (value - previous_value) * position
+                        }
+                        Fill::None => unreachable!(),
+                    };
+                    self.write_row(missing, filled)?;
+                    self.filled += 1;
+                }
+            }
+        }
+        self.write_row(index, value)?;
+        self.previous = Some((index, value));
+        Ok(())
+    }
+}
+
+impl Resample {
+    pub const NAME: &'static str = "builtin-resample";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("interval_seconds", ArgumentType::Integer)
+                    .required()
+                    .minimum(1.0)
+                    .description("Width of the output intervals in seconds"),
+            )
+            .argument(
+                ArgumentSpec::new("aggregation", ArgumentType::String)
+                    .one_of(&["mean", "sum", "last", "max", "min"])
+                    .description("Aggregate of the points in an interval, \"mean\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("fill", ArgumentType::String)
+                    .one_of(&["none", "previous", "linear", "zero"])
+                    .description("How to fill empty intervals, \"none\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("timestamp_format", ArgumentType::String)
+                    .one_of(&["unix", "rfc3339"])
+                    .description("Format of the timestamps, \"unix\" by default"),
+            )
+            .argument(
+                ArgumentSpec::new("max_gap_intervals", ArgumentType::Integer)
+                    .minimum(0.0)
+                    .description("Longest run of empty intervals to fill"),
+            )
+            .argument(
+                ArgumentSpec::new("max_filled_intervals", ArgumentType::Integer)
+                    .minimum(0.0)
+                    .description("Most empty intervals to fill in total, 2^20 by default"),
+            )
+    }
+
+    /// Resamples a `timestamp,value` series to fixed intervals. The input is streamed and must
+    /// be in timestamp order; points with equal timestamps are allowed. Each non-empty interval
+    /// gives one output row with its start time and aggregate, and runs of empty intervals are
+    /// filled as `fill` says, up to `max_gap_intervals` intervals and `max_filled_intervals` in
+    /// total. A first line whose fields are neither a timestamp nor a number is a header, and is
+    /// copied to the output.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: ResampleArguments = arguments.into_typed()?;
+        if args.interval_seconds <= 0 {
+            return Err(FunctionError::invalid_arguments(
+                "interval_seconds must be positive",
+            ));
+        }
+        let input = BufReader::new(runtime.open_input(IN_SERIES)?);
+        let mut writer = ResampledWriter {
+            args: &args,
+            output: runtime.create_output(OUT_RESAMPLED)?,
+            previous: None,
+            intervals: 0,
+            filled: 0,
+            bytes: 0,
+        };
+
+        let invalid = |line: usize, reason: String| {
+            FunctionError::invalid_input_data(IN_SERIES, format!("line {}: {}", line, reason))
+        };
+        let mut bucket: Option<Bucket> = None;
+        let mut range: Option<(i64, i64)> = None;
+        let mut points = 0;
+        let mut first_line = true;
+        for (i, line) in input.lines().enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                runtime.cancellation().checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (timestamp, value) = match line.split_once(',') {
+                Some((timestamp, value)) => (timestamp.trim(), value.trim()),
+                None => return Err(invalid(i + 1, "expected timestamp,value".to_string())),
+            };
+            if std::mem::replace(&mut first_line, false)
+                && args.timestamp_format.parse(timestamp).is_none()
+                && value.parse::<f64>().is_err()
+            {
+                writer.write_header(line)?;
+                continue;
+            }
+            let timestamp = args
+                .timestamp_format
+                .parse(timestamp)
+                .ok_or_else(|| invalid(i + 1, format!("invalid timestamp {:?}", timestamp)))?;
+            let value: f64 = value
+                .parse()
+                .map_err(|_| invalid(i + 1, format!("invalid value {:?}", value)))?;
+            if let Some((_, last)) = range {
+                if timestamp < last {
+                    return Err(invalid(
+                        i + 1,
+                        format!(
+                            "timestamp {} is before the previous timestamp {}",
+                            args.timestamp_format.format(timestamp),
+                            args.timestamp_format.format(last)
+                        ),
+                    ));
+                }
+            }
+            range = Some((range.map_or(timestamp, |(first, _)| first), timestamp));
+            points += 1;
+
+            let index = timestamp.div_euclid(args.interval_seconds);
+            match &mut bucket {
+                Some(current) if current.index == index => current.add(value),
+                _ => {
+                    if let Some(done) = bucket.replace(Bucket::new(index, value)) {
+                        writer.push(&runtime, done.index, done.value(args.aggregation))?;
+                    }
+                }
+            }
+        }
+        if let Some(done) = bucket {
+            writer.push(&runtime, done.index, done.value(args.aggregation))?;
+        }
+        writer.output.flush()?;
+
+        let mut summary = FunctionSummary::new(match range {
+            Some((first, last)) => format!(
+                "Resampled {} points into {} intervals from {} to {}",
+                points,
+                writer.intervals,
+                args.timestamp_format.format(first),
+                args.timestamp_format.format(last)
+            ),
+            None => "Resampled 0 points".to_string(),
+        })
+        .metric("points", points as f64)
+        .metric("intervals", writer.intervals as f64)
+        .metric("filled_intervals", writer.filled as f64)
+        .output(OUT_RESAMPLED, OutputInfo::new(writer.bytes));
+        if let Some((first, last)) = range {
+            summary = summary
+                .metric("first_timestamp", first as f64)
+                .metric("last_timestamp", last as f64);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_resample_aggregations,
+            test_resample_fill,
+            test_resample_linear,
+            test_resample_rfc3339,
+            test_resample_header,
+            test_resample_max_filled_intervals,
+            test_resample_not_monotonic,
+        )
+    }
+
+    fn resample(
+        arguments: serde_json::Value,
+        input: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            Resample::new(),
+            arguments,
+            hashmap!(IN_SERIES => input),
+            [OUT_RESAMPLED]
+        );
+        let output = String::from_utf8(outputs.remove(OUT_RESAMPLED).unwrap()).unwrap();
+        Ok((summary?, output))
+    }
+
+    const SERIES: &str = "100,1\n105,4\n109,2\n110,10\n125,3\n";
+
+    fn test_resample_aggregations() {
+        for (aggregation, expected) in &[
+            ("mean", "100,2.3333333333333335\n110,10\n120,3\n"),
+            ("sum", "100,7\n110,10\n120,3\n"),
+            ("last", "100,2\n110,10\n120,3\n"),
+            ("max", "100,4\n110,10\n120,3\n"),
+            ("min", "100,1\n110,10\n120,3\n"),
+        ] {
+            let (summary, output) = resample(
+                json!({"interval_seconds": 10, "aggregation": aggregation}),
+                SERIES,
+            )
+            .unwrap();
+            assert_eq!(&output, expected, "{}", aggregation);
+            assert_eq!(
+                summary.message,
+                "Resampled 5 points into 3 intervals from 100 to 125"
+            );
+            assert_eq!(summary.metrics["points"], 5.0);
+            assert_eq!(summary.metrics["intervals"], 3.0);
+            assert_eq!(summary.metrics["first_timestamp"], 100.0);
+            assert_eq!(summary.metrics["last_timestamp"], 125.0);
+        }
+
+        let (summary, output) = resample(json!({"interval_seconds": 10}), "").unwrap();
+        assert_eq!(output, "");
+        assert_eq!(summary.message, "Resampled 0 points");
+    }
+
+    fn test_resample_fill() {
+        let series = "0,1\n30,4\n100,5\n";
+        let run = |fill: &str| {
+            resample(
+                json!({"interval_seconds": 10, "fill": fill, "max_gap_intervals": 2}),
+                series,
+            )
+            .unwrap()
+        };
+
+        let (summary, output) = run("none");
+        assert_eq!(output, "0,1\n30,4\n100,5\n");
+        assert_eq!(summary.metrics["filled_intervals"], 0.0);
+
+        // The gap of 6 intervals between 30 and 100 exceeds max_gap_intervals and stays empty.
+        let (summary, output) = run("previous");
+        assert_eq!(output, "0,1\n10,1\n20,1\n30,4\n100,5\n");
+        assert_eq!(summary.metrics["filled_intervals"], 2.0);
+        assert_eq!(summary.metrics["intervals"], 5.0);
+
+        let (_, output) = run("zero");
+        assert_eq!(output, "0,1\n10,0\n20,0\n30,4\n100,5\n");
+    }
+
+    fn test_resample_linear() {
+        let (summary, output) = resample(
+            json!({"interval_seconds": 60, "fill": "linear"}),
+            "0,10\n59,20\n300,0\n",
+        )
+        .unwrap();
+        // The first interval averages to 15; the 4 missing intervals lie on the line from 15
+        // at 0 to 0 at 300.
+        assert_eq!(output, "0,15\n60,12\n120,9\n180,6\n240,3\n300,0\n");
+        assert_eq!(summary.metrics["filled_intervals"], 4.0);
+    }
+
+    fn test_resample_rfc3339() {
+        let (summary, output) = resample(
+            json!({"interval_seconds": 3600, "timestamp_format": "rfc3339", "fill": "previous"}),
+            "2023-01-01T00:15:00Z,1\n2023-01-01T02:30:00+01:00,3\n2023-01-01T02:00:00Z,5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "2023-01-01T00:00:00Z,1\n2023-01-01T01:00:00Z,3\n2023-01-01T02:00:00Z,5\n"
+        );
+        assert_eq!(
+            summary.message,
+            "Resampled 3 points into 3 intervals \
+             from 2023-01-01T00:15:00Z to 2023-01-01T02:00:00Z"
+        );
+    }
+
+    fn test_resample_header() {
+        let (summary, output) = resample(
+            json!({"interval_seconds": 10}),
+            "\ntimestamp, value\r\n100,1\n125,3\n",
+        )
+        .unwrap();
+        assert_eq!(output, "timestamp, value\n100,1\n120,3\n");
+        assert_eq!(summary.metrics["points"], 2.0);
+        assert_eq!(summary.outputs[OUT_RESAMPLED].size, output.len() as u64);
+
+        // A malformed first point is not mistaken for a header.
+        let err = resample(json!({"interval_seconds": 10}), "noon,1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input series: line 1: invalid timestamp \"noon\""
+        );
+    }
+
+    fn test_resample_max_filled_intervals() {
+        let series = "0,1\n30,4\n100,5\n";
+        let (summary, _) = resample(
+            json!({"interval_seconds": 10, "fill": "zero", "max_filled_intervals": 8}),
+            series,
+        )
+        .unwrap();
+        assert_eq!(summary.metrics["filled_intervals"], 8.0);
+
+        let err = resample(
+            json!({"interval_seconds": 10, "fill": "zero", "max_filled_intervals": 7}),
+            series,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input series: \
+             filling the 6 empty intervals before 100 exceeds max_filled_intervals 7"
+        );
+
+        // Without a cap of its own, a gap of a trillion intervals hits the default one.
+        let err = resample(
+            json!({"interval_seconds": 1, "fill": "previous"}),
+            "0,1\n1000000000000,2\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("exceeds max_filled_intervals"));
+    }
+
+    fn test_resample_not_monotonic() {
+        let err = resample(
+            json!({"interval_seconds": 10}),
+            "100,1\n110,2\n\n105,3\n120,4\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input series: line 4: \
+             timestamp 105 is before the previous timestamp 110"
+        );
+
+        let err = resample(json!({"interval_seconds": 10}), "100,1\nnoon,2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input series: line 2: invalid timestamp \"noon\""
+        );
+        assert!(resample(json!({"interval_seconds": 0}), SERIES).is_err());
+    }
+}
@@ -19,8 +19,7 @@ use std::vec;
 
 use ring::{rand, signature};
 
-use std::convert::TryFrom;
-use teaclave_types::{FunctionArguments, FunctionRuntime};
+use teaclave_types::{FunctionArguments, FunctionRuntime, FunctionSummary};
 
 const IN_DATA: &str = "rsa_key";
 
@@ -29,15 +28,6 @@ struct RsaSignArguments {
     data: String,
 }
 
-impl TryFrom<FunctionArguments> for RsaSignArguments {
-    type Error = anyhow::Error;
-
-    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
-        use anyhow::Context;
-        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
-    }
-}
-
 #[derive(Default)]
 pub struct RsaSign;
 
@@ -52,8 +42,8 @@ impl RsaSign {
         &self,
         arguments: FunctionArguments,
         runtime: FunctionRuntime,
-    ) -> anyhow::Result<String> {
-        let args = RsaSignArguments::try_from(arguments)?;
+    ) -> anyhow::Result<FunctionSummary> {
+        let args: RsaSignArguments = arguments.into_typed()?;
 
         let mut key = Vec::new();
         let mut f = runtime.open_input(IN_DATA)?;
@@ -72,7 +62,7 @@ impl RsaSign {
             .map_err(|e| anyhow::anyhow!(e.to_string()))?;
 
         let output_base64 = base64::encode(&sig);
-        Ok(output_base64)
+        Ok(output_base64.into())
     }
 }
 
@@ -110,6 +100,6 @@ pub mod tests {
         let summary = RsaSign::new().run(arguments, runtime).unwrap();
         let mut expected_string = fs::read_to_string(expected_output).unwrap();
         expected_string = expected_string.replace('\n', "");
-        assert_eq!(summary, expected_string);
+        assert_eq!(summary.message, expected_string);
     }
 }
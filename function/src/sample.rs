@@ -0,0 +1,267 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::convert::TryFrom;
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+
+use rand::Rng;
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRng, FunctionRuntime, FunctionSummary, OutputInfo,
+    CANCELLATION_CHECK_INTERVAL,
+};
+
+#[derive(Default)]
+pub struct Sample;
+
+#[derive(serde::Deserialize)]
+struct SampleArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    /// Number of lines to select.
+    k: Option<usize>,
+    /// Probability with which each line is selected, as an alternative to `k`.
+    fraction: Option<f64>,
+    /// Seeds the selection instead of the runtime RNG.
+    seed: Option<u64>,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+impl TryFrom<FunctionArguments> for SampleArguments {
+    type Error = FunctionError;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        serde_json::from_str(&arguments.into_string()).map_err(FunctionError::invalid_arguments)
+    }
+}
+
+enum Selection {
+    Count(usize),
+    Fraction(f64),
+}
+
+impl SampleArguments {
+    fn selection(&self) -> Result<Selection, FunctionError> {
+        match (self.k, self.fraction) {
+            (Some(k), None) => Ok(Selection::Count(k)),
+            (None, Some(fraction)) if (0.0..=1.0).contains(&fraction) => {
+                Ok(Selection::Fraction(fraction))
+            }
+            (None, Some(_)) => Err(FunctionError::invalid_arguments(
+                "fraction must be between 0 and 1",
+            )),
+            _ => Err(FunctionError::invalid_arguments(
+                "Exactly one of k and fraction must be set",
+            )),
+        }
+    }
+}
+
+impl Sample {
+    pub const NAME: &'static str = "builtin-sample";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Selects `k` lines of the input uniformly at random with reservoir sampling, or each line
+    /// with probability `fraction`, in one pass. Selected lines keep their input order.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args = SampleArguments::try_from(arguments)?;
+        let selection = args.selection()?;
+        let mut rng = args
+            .seed
+            .map_or_else(|| runtime.rng(), FunctionRng::from_seed);
+
+        let cancellation = runtime.cancellation();
+        let mut input = BufReader::new(runtime.open_input(&args.input)?);
+        let mut output = runtime.create_output(&args.output)?;
+
+        // Holds the index and content of each line selected so far, for the count selection.
+        let mut reservoir: Vec<(u64, Vec<u8>)> = Vec::new();
+        let mut lines_read: u64 = 0;
+        let mut selected = 0;
+        let mut bytes_written = 0;
+        let mut line = Vec::new();
+        loop {
+            if lines_read % CANCELLATION_CHECK_INTERVAL as u64 == 0 {
+                cancellation.checkpoint()?;
+            }
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if line.last() != Some(&b'\n') {
+                line.push(b'\n');
+            }
+            match selection {
+                Selection::Count(k) => {
+                    if reservoir.len() < k {
+                        reservoir.push((lines_read, line.clone()));
+                    } else {
+                        let j = rng.gen_range(0..=lines_read);
+                        if j < k as u64 {
+                            reservoir[j as usize] = (lines_read, line.clone());
+                        }
+                    }
+                }
+                Selection::Fraction(fraction) => {
+                    if rng.gen_bool(fraction) {
+                        output.write_all(&line)?;
+                        selected += 1;
+                        bytes_written += line.len();
+                    }
+                }
+            }
+            lines_read += 1;
+        }
+
+        reservoir.sort_unstable_by_key(|(index, _)| *index);
+        for (_, line) in &reservoir {
+            output.write_all(line)?;
+            selected += 1;
+            bytes_written += line.len();
+        }
+        output.flush()?;
+
+        let summary = FunctionSummary::new(format!("Sampled {} of {} lines", selected, lines_read))
+            .metric("selected_lines", selected as f64)
+            .metric("lines_read", lines_read as f64)
+            .output(args.output, OutputInfo::new(bytes_written as u64));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::Path;
+    use std::untrusted::fs;
+    use teaclave_crypto::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_sample_seeded,
+            test_sample_small_input,
+            test_sample_empty_input,
+            test_sample_fraction,
+            test_sample_invalid_arguments,
+        )
+    }
+
+    fn run_sample(
+        input: &str,
+        arguments: serde_json::Value,
+    ) -> Result<(FunctionSummary, Vec<String>), FunctionError> {
+        let arguments = FunctionArguments::from_json(arguments).unwrap();
+        let base = Path::new("fixtures/functions/sample");
+        let output = base.join("output.txt");
+
+        let input_files = StagedFiles::new(hashmap!(
+            "input" =>
+            StagedFileInfo::new(base.join(input), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+        let output_files = StagedFiles::new(hashmap!(
+            "output" =>
+            StagedFileInfo::new(&output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+        let summary = Sample::new().run(arguments, runtime)?;
+        let lines = fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        Ok((summary, lines))
+    }
+
+    fn line_number(line: &str) -> usize {
+        line.trim_start_matches("line ").parse().unwrap()
+    }
+
+    fn test_sample_seeded() {
+        let (summary, lines) = run_sample("input.txt", json!({ "k": 10, "seed": 42 })).unwrap();
+        assert_eq!(summary.message, "Sampled 10 of 100 lines");
+        assert_eq!(lines.len(), 10);
+        // Selected lines keep their input order.
+        let numbers: Vec<usize> = lines.iter().map(|line| line_number(line)).collect();
+        assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // The same seed selects the same lines, another seed other lines.
+        let (_, again) = run_sample("input.txt", json!({ "k": 10, "seed": 42 })).unwrap();
+        assert_eq!(again, lines);
+        let (_, other) = run_sample("input.txt", json!({ "k": 10, "seed": 43 })).unwrap();
+        assert_ne!(other, lines);
+    }
+
+    fn test_sample_small_input() {
+        let (summary, lines) = run_sample("input.txt", json!({ "k": 1000, "seed": 42 })).unwrap();
+        assert_eq!(summary.message, "Sampled 100 of 100 lines");
+        let expected: Vec<String> = (1..=100).map(|i| format!("line {}", i)).collect();
+        assert_eq!(lines, expected);
+    }
+
+    fn test_sample_empty_input() {
+        let (summary, lines) = run_sample("empty.txt", json!({ "k": 10 })).unwrap();
+        assert_eq!(summary.message, "Sampled 0 of 0 lines");
+        assert!(lines.is_empty());
+        assert_eq!(summary.outputs["output"], OutputInfo::new(0));
+    }
+
+    fn test_sample_fraction() {
+        let (_, all) = run_sample("input.txt", json!({ "fraction": 1.0 })).unwrap();
+        assert_eq!(all.len(), 100);
+        let (_, none) = run_sample("input.txt", json!({ "fraction": 0.0 })).unwrap();
+        assert!(none.is_empty());
+
+        let (summary, lines) =
+            run_sample("input.txt", json!({ "fraction": 0.5, "seed": 7 })).unwrap();
+        assert_eq!(summary.metrics["selected_lines"], lines.len() as f64);
+        assert!(lines.len() > 20 && lines.len() < 80);
+        let (_, again) = run_sample("input.txt", json!({ "fraction": 0.5, "seed": 7 })).unwrap();
+        assert_eq!(again, lines);
+    }
+
+    fn test_sample_invalid_arguments() {
+        for arguments in [
+            json!({}),
+            json!({ "k": 10, "fraction": 0.5 }),
+            json!({ "fraction": 1.5 }),
+        ] {
+            let error = run_sample("input.txt", arguments).unwrap_err();
+            assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::format;
+use std::io::{self, BufRead, BufReader, Write};
+use teaclave_types::{
+    CancellationToken, FunctionArguments, FunctionRuntime, FunctionSummary, OutputInfo,
+    RandomAccess, CANCELLATION_CHECK_INTERVAL,
+};
+
+// Inputs opened for random access are read backward from the end in blocks of this size.
+const BLOCK_SIZE: usize = 8 * 1024;
+
+#[derive(Default)]
+pub struct Tail;
+
+#[derive(serde::Deserialize)]
+struct TailArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    lines: usize,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+impl TryFrom<FunctionArguments> for TailArguments {
+    type Error = anyhow::Error;
+
+    fn try_from(arguments: FunctionArguments) -> Result<Self, Self::Error> {
+        use anyhow::Context;
+        serde_json::from_str(&arguments.into_string()).context("Cannot deserialize arguments")
+    }
+}
+
+impl Tail {
+    pub const NAME: &'static str = "builtin-tail";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<FunctionSummary> {
+        let args = TailArguments::try_from(arguments)?;
+        let cancellation = runtime.cancellation();
+
+        let (tail, bytes_read) = match runtime.open_input_random_access(&args.input)? {
+            Some(mut file) => tail_backward(file.as_mut(), args.lines, BLOCK_SIZE, &cancellation)?,
+            None => tail_streaming(runtime.open_input(&args.input)?, args.lines, &cancellation)?,
+        };
+
+        let mut output = runtime.create_output(&args.output)?;
+        output.write_all(&tail)?;
+        output.flush()?;
+
+        let lines = tail.iter().filter(|&&b| b == b'\n').count();
+        let summary = FunctionSummary::new(format!("{} lines", lines))
+            .metric("lines", lines as f64)
+            .metric("bytes_read", bytes_read as f64)
+            .output(args.output, OutputInfo::new(tail.len() as u64));
+        Ok(summary)
+    }
+}
+
+/// Returns the last `lines` lines of `file`, each terminated by a newline, and the number of
+/// bytes read to find them. Blocks are read from the end until enough line breaks are seen.
+fn tail_backward(
+    file: &mut dyn RandomAccess,
+    lines: usize,
+    block_size: usize,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<(Vec<u8>, u64)> {
+    let size = file.size_of()?;
+    if lines == 0 || size == 0 {
+        return Ok((Vec::new(), 0));
+    }
+
+    // Blocks in the order they were read, i.e. the last block of the file first.
+    let mut blocks: Vec<Vec<u8>> = Vec::new();
+    let mut position = size;
+    let mut start = 0;
+    let mut breaks = 0;
+    'scan: while position > 0 {
+        cancellation.checkpoint()?;
+        let len = (block_size as u64).min(position);
+        position -= len;
+        let mut block = vec![0; len as usize];
+        file.read_exact_at(position, &mut block)?;
+        for (i, &b) in block.iter().enumerate().rev() {
+            let offset = position + i as u64;
+            // A newline at the very end terminates the last line instead of starting a new one.
+            if b == b'\n' && offset != size - 1 {
+                breaks += 1;
+                if breaks == lines {
+                    start = offset + 1;
+                    blocks.push(block);
+                    break 'scan;
+                }
+            }
+        }
+        blocks.push(block);
+    }
+
+    let mut tail: Vec<u8> = blocks.into_iter().rev().flatten().collect();
+    tail.drain(..(start - position) as usize);
+    if tail.last() != Some(&b'\n') {
+        tail.push(b'\n');
+    }
+    Ok((tail, size - position))
+}
+
+/// Fallback for inputs which can only be read sequentially: keeps a window of the last
+/// `lines` lines while scanning the whole input.
+fn tail_streaming(
+    input: Box<dyn io::Read>,
+    lines: usize,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<(Vec<u8>, u64)> {
+    if lines == 0 {
+        return Ok((Vec::new(), 0));
+    }
+
+    let mut window: VecDeque<Vec<u8>> = VecDeque::with_capacity(lines);
+    let mut bytes_read = 0;
+    for (index, line) in BufReader::new(input).split(b'\n').enumerate() {
+        if index % CANCELLATION_CHECK_INTERVAL == 0 {
+            cancellation.checkpoint()?;
+        }
+        let line = line?;
+        bytes_read += line.len() as u64 + 1;
+        if window.len() == lines {
+            window.pop_front();
+        }
+        window.push_back(line);
+    }
+
+    let mut tail = Vec::new();
+    for line in window {
+        tail.extend_from_slice(&line);
+        tail.push(b'\n');
+    }
+    Ok((tail, bytes_read))
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::Path;
+    use std::untrusted::fs;
+    use teaclave_crypto::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_tail,
+            test_tail_fewer_lines,
+            test_tail_zero_lines,
+            test_tail_reads_from_end,
+            test_tail_trailing_newline,
+        )
+    }
+
+    fn run_tail(lines: usize) -> (FunctionSummary, String) {
+        let arguments = FunctionArguments::from_json(json!({ "lines": lines })).unwrap();
+        let base = Path::new("fixtures/functions/tail");
+        let output = base.join("output.txt");
+
+        let input_files = StagedFiles::new(hashmap!(
+            "input" =>
+            StagedFileInfo::new(base.join("input.txt"), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+        let output_files = StagedFiles::new(hashmap!(
+            "output" =>
+            StagedFileInfo::new(&output, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+        let summary = Tail::new().run(arguments, runtime).unwrap();
+        (summary, fs::read_to_string(&output).unwrap())
+    }
+
+    fn test_tail() {
+        let (summary, output) = run_tail(3);
+        assert_eq!(summary.message, "3 lines");
+        // The input has no trailing newline; the output always ends with one.
+        assert_eq!(output, "line 8\nline 9\nline 10\n");
+        assert_eq!(
+            summary.outputs["output"],
+            OutputInfo::new(output.len() as u64)
+        );
+    }
+
+    fn test_tail_fewer_lines() {
+        let (summary, output) = run_tail(20);
+        assert_eq!(summary.message, "10 lines");
+        assert_eq!(output.lines().next(), Some("line 1"));
+        assert_eq!(output.lines().last(), Some("line 10"));
+    }
+
+    fn test_tail_zero_lines() {
+        let (summary, output) = run_tail(0);
+        assert_eq!(summary.message, "0 lines");
+        assert_eq!(output, "");
+    }
+
+    fn test_tail_reads_from_end() {
+        let data = fs::read("fixtures/functions/tail/input.txt").unwrap();
+        let token = CancellationToken::new();
+
+        let mut file = io::Cursor::new(data.clone());
+        let (tail, bytes_read) = tail_backward(&mut file, 2, 8, &token).unwrap();
+        assert_eq!(tail, b"line 9\nline 10\n");
+        // Only the blocks covering the last two lines are read.
+        assert_eq!(bytes_read, 16);
+
+        let (streamed, bytes_read) =
+            tail_streaming(Box::new(io::Cursor::new(data.clone())), 2, &token).unwrap();
+        assert_eq!(streamed, tail);
+        assert_eq!(bytes_read, data.len() as u64 + 1);
+    }
+
+    fn test_tail_trailing_newline() {
+        let token = CancellationToken::new();
+        for block_size in 1..8 {
+            let mut file = io::Cursor::new(b"a\nb\n\nc\n".to_vec());
+            let (tail, _) = tail_backward(&mut file, 2, block_size, &token).unwrap();
+            assert_eq!(tail, b"\nc\n");
+            let (tail, _) = tail_backward(&mut file, 4, block_size, &token).unwrap();
+            assert_eq!(tail, b"a\nb\n\nc\n");
+        }
+
+        let input = Box::new(io::Cursor::new(b"a\nb\n\nc\n".to_vec()));
+        let (tail, _) = tail_streaming(input, 2, &token).unwrap();
+        assert_eq!(tail, b"\nc\n");
+    }
+}
@@ -0,0 +1,546 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{open_sniffed, split_fields, unquote, DetectedFormat};
+use crate::json_stream::JsonStreamWriter;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::format;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+use teaclave_types::{
+    ArgumentSchema, ArgumentSpec, ArgumentType, FunctionArguments, FunctionError, FunctionRuntime,
+    FunctionSummary, OutputInfo, CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_DATASET: &str = "dataset";
+const OUT_TOP_VALUES: &str = "top_values";
+
+/// Values named in the summary of the function.
+const SUMMARY_VALUES: usize = 3;
+
+#[derive(Default)]
+pub struct TopK;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TopKArguments {
+    /// Name of the column, as in the CSV header or the fields of the JSONL objects.
+    column: String,
+    #[serde(default = "default_k")]
+    k: usize,
+    /// Counters per row of the count-min sketch. The error bound shrinks as it grows.
+    #[serde(default = "default_sketch_width")]
+    sketch_width: usize,
+    /// Rows of the count-min sketch. The error bound fails to hold less often as it grows.
+    #[serde(default = "default_sketch_depth")]
+    sketch_depth: usize,
+    /// Counts every distinct value exactly instead of sketching.
+    #[serde(default)]
+    exact: bool,
+    /// Most distinct values counted in exact mode.
+    #[serde(default = "default_max_distinct")]
+    max_distinct: usize,
+}
+
+fn default_k() -> usize {
+    10
+}
+
+fn default_sketch_width() -> usize {
+    2048
+}
+
+fn default_sketch_depth() -> usize {
+    4
+}
+
+fn default_max_distinct() -> usize {
+    100_000
+}
+
+/// Count-min sketch: `depth` rows of `width` counters, each row indexed by its own hash of the
+/// value. The estimate of a value is the smallest of its counters, which is never below its
+/// true count, and exceeds it by more than e / width times the total count with a probability
+/// of at most e^-depth.
+struct CountMinSketch {
+    width: usize,
+    counters: Vec<Vec<u64>>,
+    total: u64,
+}
+
+impl CountMinSketch {
+    fn new(width: usize, depth: usize) -> Self {
+        Self {
+            width,
+            counters: vec![vec![0; width]; depth],
+            total: 0,
+        }
+    }
+
+    fn column(&self, row: usize, value: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        value.hash(&mut hasher);
+        (hasher.finish() % self.width as u64) as usize
+    }
+
+    /// Counts one occurrence of `value` and returns its new estimate.
+    fn add(&mut self, value: &str) -> u64 {
+        self.total += 1;
+        let mut estimate = u64::MAX;
+        for row in 0..self.counters.len() {
+            let column = self.column(row, value);
+            let counter = &mut self.counters[row][column];
+            *counter += 1;
+            estimate = estimate.min(*counter);
+        }
+        estimate
+    }
+
+    /// Largest overestimate which holds with probability 1 - e^-depth.
+    fn error_bound(&self) -> u64 {
+        (std::f64::consts::E / self.width as f64 * self.total as f64).ceil() as u64
+    }
+}
+
+/// The `k` values with the largest estimates seen so far, ordered by estimate so that the
+/// smallest can be evicted.
+struct Candidates {
+    k: usize,
+    estimates: HashMap<String, u64>,
+    ordered: BTreeSet<(u64, String)>,
+}
+
+impl Candidates {
+    fn new(k: usize) -> Self {
+        Self {
+            k,
+            estimates: HashMap::new(),
+            ordered: BTreeSet::new(),
+        }
+    }
+
+    fn offer(&mut self, value: &str, estimate: u64) {
+        if let Some(previous) = self.estimates.get_mut(value) {
+            let previous = std::mem::replace(previous, estimate);
+            let entry = (previous, value.to_string());
+            self.ordered.remove(&entry);
+            self.ordered.insert((estimate, entry.1));
+            return;
+        }
+        if self.estimates.len() == self.k {
+            let smallest = match self.ordered.iter().next() {
+                Some(smallest) if smallest.0 < estimate => smallest.clone(),
+                _ => return,
+            };
+            self.ordered.remove(&smallest);
+            self.estimates.remove(&smallest.1);
+        }
+        self.estimates.insert(value.to_string(), estimate);
+        self.ordered.insert((estimate, value.to_string()));
+    }
+}
+
+/// Counts of the values of the column, sketched or exact.
+enum Counter {
+    Sketch(CountMinSketch, Candidates),
+    Exact(HashMap<String, u64>, usize),
+}
+
+impl Counter {
+    fn add(&mut self, column: &str, value: &str) -> Result<(), FunctionError> {
+        match self {
+            Counter::Sketch(sketch, candidates) => {
+                let estimate = sketch.add(value);
+                candidates.offer(value, estimate);
+            }
+            Counter::Exact(counts, max_distinct) => {
+                if let Some(count) = counts.get_mut(value) {
+                    *count += 1;
+                } else if counts.len() == *max_distinct {
+                    return Err(FunctionError::invalid_input_data(
+                        IN_DATASET,
+                        format!(
+                            "column '{}' has more than {} distinct values, too many to count \
+                             exactly",
+                            column, max_distinct
+                        ),
+                    ));
+                } else {
+                    counts.insert(value.to_string(), 1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The `k` most frequent values with their counts, most frequent first, and the error bound
+    /// of the counts.
+    fn top(self, k: usize) -> (Vec<(String, u64)>, u64) {
+        let (mut top, error_bound): (Vec<_>, _) = match self {
+            Counter::Sketch(sketch, candidates) => (
+                candidates.estimates.into_iter().collect(),
+                sketch.error_bound(),
+            ),
+            Counter::Exact(counts, _) => (counts.into_iter().collect(), 0),
+        };
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(k);
+        (top, error_bound)
+    }
+}
+
+/// Text of a JSON field as counted, or `None` for null.
+fn json_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+impl TopK {
+    pub const NAME: &'static str = "builtin-topk";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn argument_schema() -> ArgumentSchema {
+        ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("column", ArgumentType::String)
+                    .required()
+                    .description("Name of the column whose most frequent values are wanted"),
+            )
+            .argument(
+                ArgumentSpec::new("k", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .description("Number of values to report, 10 by default"),
+            )
+            .argument(
+                ArgumentSpec::new("sketch_width", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .description("Counters per row of the count-min sketch, 2048 by default"),
+            )
+            .argument(
+                ArgumentSpec::new("sketch_depth", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .maximum(16.0)
+                    .description("Rows of the count-min sketch, 4 by default"),
+            )
+            .argument(
+                ArgumentSpec::new("exact", ArgumentType::Boolean)
+                    .description("Count exactly instead of sketching, false by default"),
+            )
+            .argument(
+                ArgumentSpec::new("max_distinct", ArgumentType::Integer)
+                    .minimum(1.0)
+                    .description("Most distinct values counted in exact mode, 100000 by default"),
+            )
+    }
+
+    /// Finds the most frequent values of a column of a CSV dataset with a header row, or of a
+    /// field of a JSONL dataset, in one pass. By default the values are counted in a count-min
+    /// sketch, so memory stays bounded by the sketch and the `k` candidates no matter how many
+    /// distinct values there are. Estimated counts are never too low, and too high by at most
+    /// the reported error bound with a probability of 1 - e^-sketch_depth. Empty CSV fields and
+    /// missing or null JSON fields are not counted.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: TopKArguments = arguments.into_typed()?;
+        if args.k == 0 || args.sketch_width == 0 || args.max_distinct == 0 {
+            return Err(FunctionError::invalid_arguments(
+                "k, sketch_width and max_distinct must be at least 1",
+            ));
+        }
+        if !(1..=16).contains(&args.sketch_depth) {
+            return Err(FunctionError::invalid_arguments(
+                "sketch_depth must be between 1 and 16",
+            ));
+        }
+
+        let (detected, input) = open_sniffed(runtime.as_ref(), IN_DATASET)?;
+        let delimiter = match detected {
+            DetectedFormat::Csv { delimiter, .. } => Some(delimiter),
+            DetectedFormat::Jsonl => None,
+            DetectedFormat::Text | DetectedFormat::Empty => Some(','),
+            DetectedFormat::Binary => {
+                return Err(FunctionError::invalid_input_data(
+                    IN_DATASET,
+                    "expected CSV or JSONL but found binary data",
+                ))
+            }
+        };
+
+        let mut counter = if args.exact {
+            Counter::Exact(HashMap::new(), args.max_distinct)
+        } else {
+            Counter::Sketch(
+                CountMinSketch::new(args.sketch_width, args.sketch_depth),
+                Candidates::new(args.k),
+            )
+        };
+        let cancellation = runtime.cancellation();
+        let mut lines = BufReader::new(input).lines();
+        let mut csv_column = None;
+        if let Some(delimiter) = delimiter {
+            let header = lines.next().transpose()?.unwrap_or_default();
+            let position = split_fields(header.trim_end_matches('\r'), delimiter)
+                .into_iter()
+                .position(|name| unquote(name.trim()) == args.column)
+                .ok_or_else(|| {
+                    FunctionError::invalid_arguments(format!("unknown column '{}'", args.column))
+                })?;
+            csv_column = Some((delimiter, position));
+        }
+
+        let mut values = 0u64;
+        let mut missing = 0u64;
+        let mut rows = 0;
+        for (i, line) in lines.enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows += 1;
+            let value = match csv_column {
+                Some((delimiter, position)) => split_fields(line, delimiter)
+                    .get(position)
+                    .map(|field| unquote(field.trim()).to_string())
+                    .filter(|field| !field.is_empty()),
+                None => match serde_json::from_str(line) {
+                    Ok(Value::Object(object)) => object.get(&args.column).and_then(json_text),
+                    _ => {
+                        return Err(FunctionError::invalid_input_data(
+                            IN_DATASET,
+                            format!("row {} is not a JSON object", rows),
+                        ))
+                    }
+                },
+            };
+            match value {
+                Some(value) => {
+                    counter.add(&args.column, &value)?;
+                    values += 1;
+                }
+                None => missing += 1,
+            }
+        }
+
+        let (top, error_bound) = counter.top(args.k);
+        let mut output = JsonStreamWriter::new(runtime.create_output(OUT_TOP_VALUES)?);
+        output.write_jsonl(top.iter().map(|(value, count)| {
+            serde_json::json!({"value": value, "count": count, "error_bound": error_bound})
+        }))?;
+        let bytes = output.finish()?;
+
+        let named: Vec<String> = top
+            .iter()
+            .take(SUMMARY_VALUES)
+            .map(|(value, count)| format!("'{}' ({})", value, count))
+            .collect();
+        let summary = FunctionSummary::new(format!(
+            "Most frequent values of column '{}' over {} values{}: {}",
+            args.column,
+            values,
+            if args.exact {
+                String::new()
+            } else {
+                format!(", counts overestimated by at most {}", error_bound)
+            },
+            if named.is_empty() {
+                "none".to_string()
+            } else {
+                named.join(", ")
+            }
+        ))
+        .metric("values", values as f64)
+        .metric("missing", missing as f64)
+        .metric("reported", top.len() as f64)
+        .metric("error_bound", error_bound as f64)
+        .output(OUT_TOP_VALUES, OutputInfo::new(bytes));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_topk_sketch_matches_exact,
+            test_topk_error_bound_on_skewed_data,
+            test_topk_exact_cap,
+            test_topk_jsonl,
+        )
+    }
+
+    fn topk(
+        arguments: Value,
+        input: &str,
+    ) -> Result<(FunctionSummary, Vec<(String, u64, u64)>), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            TopK::new(),
+            arguments,
+            hashmap!(IN_DATASET => input),
+            [OUT_TOP_VALUES]
+        );
+        let summary = summary?;
+        let output = String::from_utf8(outputs.remove(OUT_TOP_VALUES).unwrap()).unwrap();
+        let top = output
+            .lines()
+            .map(|line| {
+                let record: Value = serde_json::from_str(line).unwrap();
+                (
+                    record["value"].as_str().unwrap().to_string(),
+                    record["count"].as_u64().unwrap(),
+                    record["error_bound"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        Ok((summary, top))
+    }
+
+    fn dataset(counts: &[(&str, usize)]) -> String {
+        let mut dataset = "id,color\n".to_string();
+        let mut id = 0;
+        // Interleave the values, so that the candidates change while the input is read.
+        let max = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+        for round in 0..max {
+            for (value, count) in counts {
+                if round < *count {
+                    dataset.push_str(&format!("{},{}\n", id, value));
+                    id += 1;
+                }
+            }
+        }
+        dataset
+    }
+
+    fn test_topk_sketch_matches_exact() {
+        let input = dataset(&[
+            ("red", 50),
+            ("green", 30),
+            ("blue", 20),
+            ("\"dark, grey\"", 10),
+            ("white", 5),
+            ("black", 1),
+        ]);
+        let (summary, sketched) = topk(json!({"column": "color", "k": 4}), &input).unwrap();
+        let (exact_summary, exact) =
+            topk(json!({"column": "color", "k": 4, "exact": true}), &input).unwrap();
+
+        let counts = |top: &[(String, u64, u64)]| -> Vec<(String, u64)> {
+            top.iter().map(|(v, c, _)| (v.clone(), *c)).collect()
+        };
+        assert_eq!(counts(&sketched), counts(&exact));
+        assert_eq!(
+            counts(&exact),
+            vec![
+                ("red".to_string(), 50),
+                ("green".to_string(), 30),
+                ("blue".to_string(), 20),
+                ("dark, grey".to_string(), 10),
+            ]
+        );
+        // e / 2048 * 116 rounded up.
+        assert!(sketched.iter().all(|(_, _, bound)| *bound == 1));
+        assert!(exact.iter().all(|(_, _, bound)| *bound == 0));
+        assert_eq!(
+            summary.message,
+            "Most frequent values of column 'color' over 116 values, counts overestimated by at \
+             most 1: 'red' (50), 'green' (30), 'blue' (20)"
+        );
+        assert_eq!(
+            exact_summary.message,
+            "Most frequent values of column 'color' over 116 values: \
+             'red' (50), 'green' (30), 'blue' (20)"
+        );
+        assert_eq!(summary.metrics["reported"], 4.0);
+    }
+
+    fn test_topk_error_bound_on_skewed_data() {
+        // Zipf-like: the value of rank r occurs 2000 / r times.
+        let counts: Vec<(String, usize)> = (1..=500)
+            .map(|rank| (format!("v{}", rank), 2000 / rank))
+            .collect();
+        let borrowed: Vec<(&str, usize)> = counts.iter().map(|(v, c)| (v.as_str(), *c)).collect();
+        let input = dataset(&borrowed);
+        let exact: HashMap<&str, usize> = borrowed.iter().cloned().collect();
+        let total: usize = exact.values().sum();
+
+        // A narrow sketch, so that many values share counters.
+        let arguments = json!({"column": "color", "k": 5, "sketch_width": 64});
+        let (summary, top) = topk(arguments, &input).unwrap();
+        let bound = (std::f64::consts::E / 64.0 * total as f64).ceil() as u64;
+        assert_eq!(summary.metrics["error_bound"], bound as f64);
+        assert_eq!(top.len(), 5);
+        for (value, estimate, error_bound) in &top {
+            let exact = exact[value.as_str()] as u64;
+            assert_eq!(*error_bound, bound);
+            assert!(*estimate >= exact);
+            assert!(*estimate <= exact + bound);
+        }
+        // The heaviest value stands out by more than the error bound.
+        assert_eq!(top[0].0, "v1");
+    }
+
+    fn test_topk_exact_cap() {
+        let input = dataset(&[("a", 3), ("b", 2), ("c", 1)]);
+        let arguments = json!({"column": "color", "exact": true, "max_distinct": 2});
+        let err = topk(arguments, &input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input dataset: column 'color' has more than 2 distinct values, \
+             too many to count exactly"
+        );
+
+        let arguments = json!({"column": "color", "exact": true, "max_distinct": 3, "k": 2});
+        let (_, top) = topk(arguments, &input).unwrap();
+        assert_eq!(top, vec![("a".to_string(), 3, 0), ("b".to_string(), 2, 0)]);
+
+        let err = topk(json!({"column": "shade"}), &input).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid arguments: unknown column 'shade'");
+        let err = topk(json!({"column": "color", "k": 0}), &input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments: k, sketch_width and max_distinct must be at least 1"
+        );
+    }
+
+    fn test_topk_jsonl() {
+        let input = "{\"tag\": \"x\"}\n{\"tag\": 1}\n{\"tag\": \"x\"}\n{\"tag\": null}\n{}\n";
+        let (summary, top) = topk(json!({"column": "tag", "k": 1}), input).unwrap();
+        assert_eq!(top, vec![("x".to_string(), 2, 1)]);
+        assert_eq!(summary.metrics["values"], 3.0);
+        assert_eq!(summary.metrics["missing"], 2.0);
+    }
+}
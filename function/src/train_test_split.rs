@@ -0,0 +1,576 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::{open_sniffed, DetectedFormat};
+use std::collections::HashMap;
+use std::format;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use rand::Rng;
+use teaclave_types::{
+    CancellationToken, FunctionArguments, FunctionError, FunctionRng, FunctionRuntime,
+    FunctionSummary, OutputInfo, TeaclaveRuntime, CANCELLATION_CHECK_INTERVAL,
+};
+
+const IN_DATASET: &str = "dataset";
+const OUT_TRAIN: &str = "train";
+const OUT_TEST: &str = "test";
+
+#[derive(Default)]
+pub struct TrainTestSplit;
+
+#[derive(serde::Deserialize)]
+struct TrainTestSplitArguments {
+    /// Share of the rows which go to the test split, between 0 and 1.
+    test_fraction: f64,
+    /// Picks the test rows at random. Otherwise the last rows of the dataset, or of each class,
+    /// are the test rows.
+    #[serde(default = "default_shuffle")]
+    shuffle: bool,
+    /// Seeds the shuffle instead of the runtime RNG.
+    seed: Option<u64>,
+    /// Column whose classes keep their proportions in both splits: a column name, or index if
+    /// the CSV has no header, or a field of the JSONL objects.
+    stratify_column: Option<String>,
+}
+
+fn default_shuffle() -> bool {
+    true
+}
+
+#[derive(Clone, Copy)]
+enum RowFormat {
+    Csv(char),
+    Jsonl,
+}
+
+/// How the rows of the dataset are read, as sniffed from its first bytes.
+struct Layout {
+    format: RowFormat,
+    header: bool,
+}
+
+impl Layout {
+    fn detect(detected: DetectedFormat) -> Result<Self, FunctionError> {
+        let (format, header) = match detected {
+            DetectedFormat::Csv { delimiter, header } => (RowFormat::Csv(delimiter), header),
+            DetectedFormat::Jsonl => (RowFormat::Jsonl, false),
+            // Single-column CSV, or nothing to split.
+            DetectedFormat::Text | DetectedFormat::Empty => (RowFormat::Csv(','), false),
+            DetectedFormat::Binary => {
+                return Err(FunctionError::invalid_input_data(
+                    IN_DATASET,
+                    "expected CSV or JSONL but found binary data",
+                ))
+            }
+        };
+        Ok(Layout { format, header })
+    }
+}
+
+/// Finds the class of a row by the stratify column.
+enum Stratifier {
+    CsvColumn(char, usize),
+    JsonlField(String),
+}
+
+impl Stratifier {
+    fn new(layout: &Layout, header: Option<&str>, column: &str) -> Result<Self, FunctionError> {
+        match (layout.format, header) {
+            (RowFormat::Jsonl, _) => Ok(Stratifier::JsonlField(column.to_string())),
+            (RowFormat::Csv(delimiter), Some(header)) => header
+                .split(delimiter)
+                .position(|name| name.trim() == column)
+                .map(|index| Stratifier::CsvColumn(delimiter, index))
+                .ok_or_else(|| {
+                    FunctionError::invalid_arguments(format!(
+                        "stratify_column '{}' is not a column of the dataset",
+                        column
+                    ))
+                }),
+            (RowFormat::Csv(delimiter), None) => column
+                .parse()
+                .map(|index| Stratifier::CsvColumn(delimiter, index))
+                .map_err(|_| {
+                    FunctionError::invalid_arguments(
+                        "stratify_column must be a column index, as the dataset has no header",
+                    )
+                }),
+        }
+    }
+
+    fn class_of(&self, row: &str, row_number: usize) -> Result<String, FunctionError> {
+        let class = match self {
+            Stratifier::CsvColumn(delimiter, index) => row
+                .split(*delimiter)
+                .nth(*index)
+                .map(|v| v.trim().to_string()),
+            Stratifier::JsonlField(field) => {
+                let value: serde_json::Value = serde_json::from_str(row).map_err(|e| {
+                    FunctionError::invalid_input_data(
+                        IN_DATASET,
+                        format!("row {} is not valid JSON: {}", row_number, e),
+                    )
+                })?;
+                value.get(field).map(|v| match v {
+                    serde_json::Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                })
+            }
+        };
+        class.ok_or_else(|| {
+            FunctionError::invalid_input_data(
+                IN_DATASET,
+                format!("row {} has no stratify column", row_number),
+            )
+        })
+    }
+}
+
+/// Reads the non-blank rows of the dataset, skipping the header, with a cancellation check
+/// every `CANCELLATION_CHECK_INTERVAL` rows.
+struct Rows {
+    input: BufReader<Box<dyn Read>>,
+    cancellation: CancellationToken,
+    lines_read: usize,
+}
+
+impl Rows {
+    fn open(runtime: &dyn TeaclaveRuntime, header: bool) -> Result<Self, FunctionError> {
+        let mut rows = Rows {
+            input: BufReader::new(runtime.open_input(IN_DATASET)?),
+            cancellation: runtime.cancellation(),
+            lines_read: 0,
+        };
+        if header {
+            rows.next_line()?;
+        }
+        Ok(rows)
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>, FunctionError> {
+        if self.lines_read % CANCELLATION_CHECK_INTERVAL == 0 {
+            self.cancellation.checkpoint()?;
+        }
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.lines_read += 1;
+        Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+    }
+
+    fn next_row(&mut self) -> Result<Option<String>, FunctionError> {
+        while let Some(line) = self.next_line()? {
+            if !line.trim().is_empty() {
+                return Ok(Some(line));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Number of test rows of a group of `rows` rows and how many of them are still to be
+/// picked.
+struct Quota {
+    rows: usize,
+    test: usize,
+    seen: usize,
+    picked: usize,
+}
+
+impl Quota {
+    fn new(rows: usize, test: usize) -> Self {
+        Quota {
+            rows,
+            test,
+            seen: 0,
+            picked: 0,
+        }
+    }
+
+    /// Tells whether the next row of the group is a test row. Shuffled, each row is picked
+    /// with probability (still needed) / (still left), which selects exactly `test` rows
+    /// uniformly at random in one pass.
+    fn pick(&mut self, rng: Option<&mut FunctionRng>) -> bool {
+        let left = self.rows - self.seen;
+        let needed = self.test - self.picked;
+        let test = match rng {
+            Some(rng) => rng.gen_range(0..left) < needed,
+            None => self.seen >= self.rows - self.test,
+        };
+        self.seen += 1;
+        if test {
+            self.picked += 1;
+        }
+        test
+    }
+}
+
+struct SplitOutput {
+    writer: Box<dyn Write>,
+    rows: usize,
+    bytes: usize,
+}
+
+impl SplitOutput {
+    fn create(runtime: &dyn TeaclaveRuntime, identifier: &str) -> Result<Self, FunctionError> {
+        Ok(SplitOutput {
+            writer: runtime.create_output(identifier)?,
+            rows: 0,
+            bytes: 0,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), FunctionError> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.bytes += line.len() + 1;
+        Ok(())
+    }
+}
+
+impl TrainTestSplit {
+    pub const NAME: &'static str = "builtin-train-test-split";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Splits the rows of a CSV or JSONL dataset into a train and a test output, copying the
+    /// CSV header to both. The dataset is read twice, once to count its rows, per class if
+    /// stratified, and once to assign them, so only the counts are kept in memory.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: TrainTestSplitArguments = arguments.into_typed()?;
+        if !(args.test_fraction > 0.0 && args.test_fraction < 1.0) {
+            return Err(FunctionError::invalid_arguments(
+                "test_fraction must be between 0 and 1",
+            ));
+        }
+        let mut rng = match (args.shuffle, args.seed) {
+            (false, _) => None,
+            (true, Some(seed)) => Some(FunctionRng::from_seed(seed)),
+            (true, None) => Some(runtime.rng()),
+        };
+
+        let (detected, input) = open_sniffed(runtime.as_ref(), IN_DATASET)?;
+        let layout = Layout::detect(detected)?;
+        let header = if layout.header {
+            BufReader::new(input)
+                .lines()
+                .next()
+                .transpose()?
+                .map(|line| line.trim_end_matches('\r').to_string())
+        } else {
+            None
+        };
+        let stratifier = args
+            .stratify_column
+            .as_deref()
+            .map(|column| Stratifier::new(&layout, header.as_deref(), column))
+            .transpose()?;
+
+        // First pass: count the rows of the dataset, or of each class.
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut total = 0;
+        let mut rows = Rows::open(runtime.as_ref(), layout.header)?;
+        while let Some(row) = rows.next_row()? {
+            total += 1;
+            let class = match &stratifier {
+                Some(stratifier) => stratifier.class_of(&row, total)?,
+                None => String::new(),
+            };
+            *counts.entry(class).or_default() += 1;
+        }
+
+        runtime.report_progress(0.5, "counted rows");
+
+        let mut quotas = HashMap::new();
+        for (class, rows) in counts {
+            let test = (rows as f64 * args.test_fraction).round() as usize;
+            let test = match stratifier {
+                // Every class needs a row in each split to keep its proportion.
+                Some(_) if rows < 2 => {
+                    return Err(FunctionError::invalid_input_data(
+                        IN_DATASET,
+                        format!(
+                        "class '{}' has only {} row, but stratifying needs at least 2 rows per class",
+                        class, rows
+                    ),
+                    ))
+                }
+                Some(_) => test.clamp(1, rows - 1),
+                None => test,
+            };
+            quotas.insert(class, Quota::new(rows, test));
+        }
+
+        // Second pass: assign each row to a split.
+        let mut train = SplitOutput::create(runtime.as_ref(), OUT_TRAIN)?;
+        let mut test = SplitOutput::create(runtime.as_ref(), OUT_TEST)?;
+        if let Some(header) = &header {
+            train.write_line(header)?;
+            test.write_line(header)?;
+        }
+        let mut rows = Rows::open(runtime.as_ref(), layout.header)?;
+        let mut row_number = 0;
+        while let Some(row) = rows.next_row()? {
+            row_number += 1;
+            if row_number % CANCELLATION_CHECK_INTERVAL == 0 {
+                let done = row_number as f32 / total as f32;
+                runtime.report_progress(0.5 + done / 2.0, "assigning rows");
+            }
+            let class = match &stratifier {
+                Some(stratifier) => stratifier.class_of(&row, row_number)?,
+                None => String::new(),
+            };
+            let quota = quotas.get_mut(&class).ok_or_else(|| {
+                FunctionError::invalid_input_data(IN_DATASET, "dataset changed while splitting")
+            })?;
+            let split = if quota.pick(rng.as_mut()) {
+                &mut test
+            } else {
+                &mut train
+            };
+            split.write_line(&row)?;
+            split.rows += 1;
+        }
+        train.writer.flush()?;
+        test.writer.flush()?;
+        runtime.report_progress(1.0, "done");
+
+        let summary = FunctionSummary::new(format!(
+            "Split {} rows into {} train and {} test rows",
+            total, train.rows, test.rows
+        ))
+        .metric("train_rows", train.rows as f64)
+        .metric("test_rows", test.rows as f64)
+        .output(OUT_TRAIN, OutputInfo::new(train.bytes as u64))
+        .output(OUT_TEST, OutputInfo::new(test.bytes as u64));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::Path;
+    use std::untrusted::fs;
+    use teaclave_crypto::*;
+    use teaclave_runtime::*;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_split_sizes,
+            test_split_seeded,
+            test_split_without_shuffle,
+            test_split_stratified,
+            test_split_stratified_jsonl,
+            test_split_rare_class,
+            test_split_invalid_arguments,
+            test_split_progress,
+        )
+    }
+
+    fn run_split(
+        dataset: &str,
+        arguments: serde_json::Value,
+    ) -> Result<(FunctionSummary, Vec<String>, Vec<String>), FunctionError> {
+        let arguments = FunctionArguments::from_json(arguments).unwrap();
+        let base = Path::new("fixtures/functions/train_test_split");
+        let train = base.join("train.txt");
+        let test = base.join("test.txt");
+
+        let input_files = StagedFiles::new(hashmap!(
+            IN_DATASET =>
+            StagedFileInfo::new(base.join(dataset), TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+        let output_files = StagedFiles::new(hashmap!(
+            OUT_TRAIN =>
+            StagedFileInfo::new(&train, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+            OUT_TEST =>
+            StagedFileInfo::new(&test, TeaclaveFile128Key::random(), FileAuthTag::mock()),
+        ));
+
+        let runtime = Box::new(RawIoRuntime::new(input_files, output_files));
+        let summary = TrainTestSplit::new().run(arguments, runtime)?;
+        let read_lines = |path: &Path| -> Vec<String> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| line.to_string())
+                .collect()
+        };
+        Ok((summary, read_lines(&train), read_lines(&test)))
+    }
+
+    fn count_class(rows: &[String], class: &str) -> usize {
+        rows.iter().filter(|row| row.ends_with(class)).count()
+    }
+
+    fn test_split_sizes() {
+        let (summary, train, test) =
+            run_split("dataset.csv", json!({ "test_fraction": 0.3 })).unwrap();
+        assert_eq!(
+            summary.message,
+            "Split 100 rows into 70 train and 30 test rows"
+        );
+        assert_eq!(summary.metrics["train_rows"], 70.0);
+        assert_eq!(summary.metrics["test_rows"], 30.0);
+        // The header goes to both splits.
+        assert_eq!(train.len(), 71);
+        assert_eq!(test.len(), 31);
+        assert_eq!(train[0], "feature,label");
+        assert_eq!(test[0], "feature,label");
+
+        // Every row ends up in exactly one split, in its original order.
+        let mut all: Vec<String> = train[1..].iter().chain(&test[1..]).cloned().collect();
+        all.sort_by_key(|row| row.split(',').next().unwrap().parse::<usize>().unwrap());
+        let expected: Vec<String> =
+            fs::read_to_string("fixtures/functions/train_test_split/dataset.csv")
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.to_string())
+                .collect();
+        assert_eq!(all, expected);
+        let numbers: Vec<usize> = test[1..]
+            .iter()
+            .map(|row| row.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    fn test_split_seeded() {
+        let arguments = json!({ "test_fraction": 0.25, "seed": 42 });
+        let (_, train, test) = run_split("dataset.csv", arguments.clone()).unwrap();
+        let (_, again_train, again_test) = run_split("dataset.csv", arguments).unwrap();
+        assert_eq!(again_train, train);
+        assert_eq!(again_test, test);
+
+        let (_, _, other) =
+            run_split("dataset.csv", json!({ "test_fraction": 0.25, "seed": 43 })).unwrap();
+        assert_eq!(other.len(), test.len());
+        assert_ne!(other, test);
+    }
+
+    fn test_split_without_shuffle() {
+        let (_, train, test) = run_split(
+            "dataset.csv",
+            json!({ "test_fraction": 0.1, "shuffle": false }),
+        )
+        .unwrap();
+        assert_eq!(train.len(), 91);
+        let expected: Vec<String> = std::iter::once("feature,label".to_string())
+            .chain((90..100).map(|i| format!("{},{}", i, if i % 10 < 7 { "a" } else { "b" })))
+            .collect();
+        assert_eq!(test, expected);
+    }
+
+    fn test_split_stratified() {
+        let (_, train, test) = run_split(
+            "dataset.csv",
+            json!({ "test_fraction": 0.2, "stratify_column": "label", "seed": 1 }),
+        )
+        .unwrap();
+        // 70 rows of class a and 30 of class b.
+        assert_eq!(count_class(&test, ",a"), 14);
+        assert_eq!(count_class(&test, ",b"), 6);
+        assert_eq!(count_class(&train, ",a"), 56);
+        assert_eq!(count_class(&train, ",b"), 24);
+
+        // Every seed keeps the proportions.
+        for seed in 0..10 {
+            let (_, _, test) = run_split(
+                "dataset.csv",
+                json!({ "test_fraction": 0.33, "stratify_column": "label", "seed": seed }),
+            )
+            .unwrap();
+            let test_a = count_class(&test, ",a") as f64;
+            let test_b = count_class(&test, ",b") as f64;
+            assert!((test_a / 70.0 - 0.33).abs() <= 1.0 / 70.0);
+            assert!((test_b / 30.0 - 0.33).abs() <= 1.0 / 30.0);
+        }
+    }
+
+    fn test_split_stratified_jsonl() {
+        let (summary, train, test) = run_split(
+            "dataset.jsonl",
+            json!({ "test_fraction": 0.5, "stratify_column": "label" }),
+        )
+        .unwrap();
+        assert_eq!(
+            summary.message,
+            "Split 40 rows into 20 train and 20 test rows"
+        );
+        // 30 rows of class x and 10 of class y, and no header.
+        assert_eq!(count_class(&test, "\"x\"}"), 15);
+        assert_eq!(count_class(&test, "\"y\"}"), 5);
+        assert_eq!(count_class(&train, "\"y\"}"), 5);
+    }
+
+    fn test_split_rare_class() {
+        let error = run_split(
+            "rare.csv",
+            json!({ "test_fraction": 0.2, "stratify_column": "label" }),
+        )
+        .unwrap_err();
+        assert_eq!(error.category(), TaskFailureCategory::InvalidInputData);
+        assert_eq!(
+            error.to_string(),
+            "Invalid data in input dataset: class 'b' has only 1 row, but stratifying needs at least 2 rows per class"
+        );
+
+        // Without stratification the rare class is no problem.
+        assert!(run_split("rare.csv", json!({ "test_fraction": 0.2 })).is_ok());
+    }
+
+    fn test_split_invalid_arguments() {
+        for arguments in [
+            json!({}),
+            json!({ "test_fraction": 0.0 }),
+            json!({ "test_fraction": 1.0 }),
+            json!({ "test_fraction": 0.2, "stratify_column": "missing" }),
+        ] {
+            let error = run_split("dataset.csv", arguments).unwrap_err();
+            assert_eq!(error.category(), TaskFailureCategory::InvalidArguments);
+        }
+    }
+
+    fn test_split_progress() {
+        let dataset = fs::read("fixtures/functions/train_test_split/dataset.csv").unwrap();
+        let input_files = StagedFiles::from_memory(hashmap!(IN_DATASET => dataset));
+        let output_files = StagedFiles::from_memory(hashmap!(
+            OUT_TRAIN => Vec::new(),
+            OUT_TEST => Vec::new(),
+        ));
+        let progress = ExecutionProgress::new();
+        let runtime =
+            Box::new(RawIoRuntime::new(input_files, output_files).with_progress(progress.clone()));
+
+        let arguments = FunctionArguments::from_json(json!({ "test_fraction": 0.3 })).unwrap();
+        TrainTestSplit::new().run(arguments, runtime).unwrap();
+        assert_eq!(progress.latest(), Some(TaskProgress::new(1.0, "done")));
+    }
+}
@@ -0,0 +1,137 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io::Write;
+use std::time::Duration;
+
+use serde::Serialize;
+use teaclave_types::{FunctionRuntime, FunctionSummary, OutputInfo};
+
+/// Identifier of the optional report output of the training builtins.
+pub(crate) const OUT_REPORT: &str = "report";
+
+/// Human-readable summary of a training run, written as JSON to the `report` output of the
+/// training builtins if that output is staged.
+#[derive(Debug, Serialize)]
+pub(crate) struct TrainingReport<P: Serialize> {
+    pub parameters: P,
+    pub data: DataStatistics,
+    /// Training loss after each iteration.
+    pub loss_curve: Vec<f64>,
+    pub timing: Timing,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Timing {
+    pub parse_ms: f64,
+    pub training_ms: f64,
+}
+
+impl Timing {
+    pub fn new(parse: Duration, training: Duration) -> Self {
+        Self {
+            parse_ms: parse.as_secs_f64() * 1000.0,
+            training_ms: training.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ColumnStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl ColumnStatistics {
+    fn new(values: impl Iterator<Item = f64>) -> Self {
+        let mut stats = Self {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.0,
+        };
+        let mut count = 0;
+        for value in values {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.mean += value;
+            count += 1;
+        }
+        if count == 0 {
+            return Self {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+            };
+        }
+        stats.mean /= count as f64;
+        stats
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DataStatistics {
+    pub rows: usize,
+    pub features: Vec<ColumnStatistics>,
+    pub label: ColumnStatistics,
+}
+
+impl DataStatistics {
+    /// Computes the statistics of the training data, given the features of all rows flattened
+    /// row by row, and their labels.
+    pub fn new(features: &[f64], labels: &[f64]) -> Self {
+        let feature_size = if labels.is_empty() {
+            0
+        } else {
+            features.len() / labels.len()
+        };
+        Self {
+            rows: labels.len(),
+            features: (0..feature_size)
+                .map(|i| {
+                    ColumnStatistics::new(features.iter().skip(i).step_by(feature_size).copied())
+                })
+                .collect(),
+            label: ColumnStatistics::new(labels.iter().copied()),
+        }
+    }
+}
+
+/// Whether the caller staged the `report` output, i.e., wants a report written.
+pub(crate) fn report_requested(runtime: &FunctionRuntime) -> bool {
+    runtime.output_keys().iter().any(|key| key == OUT_REPORT)
+}
+
+/// Writes `report` to the `report` output and records it in `summary`. Without a staged
+/// `report` output, only notes in `summary` that no report was emitted.
+pub(crate) fn write_report<P: Serialize>(
+    runtime: &FunctionRuntime,
+    report: Option<&TrainingReport<P>>,
+    summary: FunctionSummary,
+) -> anyhow::Result<FunctionSummary> {
+    let report = match report {
+        Some(report) => report,
+        None => return Ok(summary.metric("report_emitted", 0.0)),
+    };
+    let report_json = serde_json::to_vec(report)?;
+    let mut report_file = runtime.create_output(OUT_REPORT)?;
+    report_file.write_all(&report_json)?;
+    let message = format!("{} Wrote a training report.", summary.message);
+    Ok(FunctionSummary { message, ..summary }
+        .metric("report_emitted", 1.0)
+        .output(OUT_REPORT, OutputInfo::new(report_json.len() as u64)))
+}
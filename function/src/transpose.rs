@@ -0,0 +1,314 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::format_sniff::split_fields;
+use std::format;
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use teaclave_types::{
+    FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary, OutputInfo,
+    CANCELLATION_CHECK_INTERVAL,
+};
+
+#[derive(Default)]
+pub struct Transpose;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransposeArguments {
+    #[serde(default = "default_input")]
+    input: String,
+    #[serde(default = "default_output")]
+    output: String,
+    /// Largest number of cells, padding included, the table may have. Transposing holds the
+    /// whole table in memory, so this bounds the memory of the function. If it is not given,
+    /// the table may take up to the memory budget of the execution environment instead.
+    #[serde(default)]
+    max_cells: Option<usize>,
+    /// Pads rows shorter than the longest one with empty cells instead of failing.
+    #[serde(default)]
+    pad_ragged: bool,
+}
+
+fn default_input() -> String {
+    "input".to_string()
+}
+
+fn default_output() -> String {
+    "output".to_string()
+}
+
+/// Largest number of cells if neither the task nor the execution environment bounds them.
+const DEFAULT_MAX_CELLS: usize = 1_000_000;
+
+/// Bookkeeping the allocator is assumed to add to every heap allocation.
+const ALLOCATION_OVERHEAD: usize = 16;
+
+/// Bound on the buffered table: a number of cells, padding included, or a number of bytes.
+#[derive(Clone, Copy)]
+enum TableLimit {
+    Cells(usize),
+    Bytes(u64),
+}
+
+fn table_limit(max_cells: Option<usize>, runtime: &FunctionRuntime) -> TableLimit {
+    match (max_cells, runtime.environment().memory_budget_bytes) {
+        (Some(cells), _) => TableLimit::Cells(cells),
+        (None, Some(bytes)) => TableLimit::Bytes(bytes),
+        (None, None) => TableLimit::Cells(DEFAULT_MAX_CELLS),
+    }
+}
+
+/// Memory a buffered row takes: the text and allocation of each cell, the `String` of each cell
+/// in the row, the allocation of the row and its slot in the table, counted twice since the
+/// table grows by doubling.
+fn row_bytes(fields: &[String]) -> u64 {
+    let cells: usize = fields
+        .iter()
+        .map(|field| field.capacity() + ALLOCATION_OVERHEAD + mem::size_of::<String>())
+        .sum();
+    (cells + ALLOCATION_OVERHEAD + 2 * mem::size_of::<Vec<String>>()) as u64
+}
+
+impl Transpose {
+    pub const NAME: &'static str = "builtin-transpose";
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Transposes a comma separated table, so that row i of the output holds column i of the
+    /// input. Quoted fields are kept as they are, quotes included. Unlike most builtins this
+    /// cannot stream: the whole table is buffered, and inputs with more than `max_cells` cells,
+    /// or taking more than the memory budget, are rejected before they exhaust the memory of the
+    /// enclave.
+    pub fn run(
+        &self,
+        arguments: FunctionArguments,
+        runtime: FunctionRuntime,
+    ) -> Result<FunctionSummary, FunctionError> {
+        let args: TransposeArguments = arguments.into_typed()?;
+        let limit = table_limit(args.max_cells, &runtime);
+        let cancellation = runtime.cancellation();
+        let input = BufReader::new(runtime.open_input(&args.input)?);
+
+        let too_large = || {
+            let message = match limit {
+                TableLimit::Cells(max_cells) => format!(
+                    "table has more than max_cells = {} cells to transpose",
+                    max_cells
+                ),
+                TableLimit::Bytes(budget) => format!(
+                    "table takes more than the memory budget of {} bytes to transpose",
+                    budget
+                ),
+            };
+            FunctionError::invalid_input_data(&args.input, message)
+        };
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut cells = 0;
+        let mut table_bytes = 0;
+        let mut width = 0;
+        for (i, line) in input.lines().enumerate() {
+            if i % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<String> = split_fields(line, ',')
+                .into_iter()
+                .map(String::from)
+                .collect();
+            if !rows.is_empty() && fields.len() != width && !args.pad_ragged {
+                return Err(FunctionError::invalid_input_data(
+                    &args.input,
+                    format!(
+                        "row {} has {} fields but row 1 has {}; set pad_ragged to pad short rows",
+                        rows.len() + 1,
+                        fields.len(),
+                        width
+                    ),
+                ));
+            }
+            width = width.max(fields.len());
+            cells += fields.len();
+            table_bytes += row_bytes(&fields);
+            let too_large_table = match limit {
+                TableLimit::Cells(max_cells) => {
+                    cells > max_cells || (rows.len() + 1) * width > max_cells
+                }
+                // Padding is written on the fly and takes no memory.
+                TableLimit::Bytes(budget) => table_bytes > budget,
+            };
+            if too_large_table {
+                return Err(too_large());
+            }
+            rows.push(fields);
+        }
+
+        let mut output = runtime.create_output(&args.output)?;
+        let mut bytes = 0;
+        for column in 0..width {
+            if column % CANCELLATION_CHECK_INTERVAL == 0 {
+                cancellation.checkpoint()?;
+            }
+            let line = rows
+                .iter()
+                .map(|row| row.get(column).map(String::as_str).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(output, "{}", line)?;
+            bytes += line.len() + 1;
+        }
+        output.flush()?;
+
+        let summary = FunctionSummary::new(format!(
+            "Transposed {} rows and {} columns",
+            rows.len(),
+            width
+        ))
+        .metric("rows", rows.len() as f64)
+        .metric("columns", width as f64)
+        .output(args.output, OutputInfo::new(bytes as u64));
+        Ok(summary)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_transpose,
+            test_transpose_quoted_fields,
+            test_transpose_ragged_rows,
+            test_transpose_max_cells,
+            test_transpose_memory_budget,
+        )
+    }
+
+    fn transpose(
+        arguments: serde_json::Value,
+        input: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        transpose_in(ExecutionEnvironment::new(), arguments, input)
+    }
+
+    fn transpose_in(
+        environment: ExecutionEnvironment,
+        arguments: serde_json::Value,
+        input: &str,
+    ) -> Result<(FunctionSummary, String), FunctionError> {
+        let (summary, mut outputs) = run_builtin!(
+            Transpose::new(),
+            arguments,
+            hashmap!("input" => input),
+            ["output"],
+            with_environment(environment)
+        );
+        let output = String::from_utf8(outputs.remove("output").unwrap()).unwrap();
+        Ok((summary?, output))
+    }
+
+    fn test_transpose() {
+        let (summary, output) = transpose(json!({}), "a,1\nb,2\nc,3\n").unwrap();
+        assert_eq!(output, "a,b,c\n1,2,3\n");
+        assert_eq!(summary.message, "Transposed 3 rows and 2 columns");
+        assert_eq!(summary.metrics["rows"], 3.0);
+        assert_eq!(summary.metrics["columns"], 2.0);
+        assert_eq!(
+            summary.outputs["output"],
+            OutputInfo::new(output.len() as u64)
+        );
+
+        // Transposing twice gives the table back.
+        let (_, twice) = transpose(json!({}), &output).unwrap();
+        assert_eq!(twice, "a,1\nb,2\nc,3\n");
+    }
+
+    fn test_transpose_quoted_fields() {
+        let (_, output) = transpose(
+            json!({}),
+            "name,city\r\nBob,\"Paris, FR\"\r\n\"Eve \"\"E\"\"\",Rome\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "name,Bob,\"Eve \"\"E\"\"\"\ncity,\"Paris, FR\",Rome\n"
+        );
+    }
+
+    fn test_transpose_ragged_rows() {
+        let err = transpose(json!({}), "a,b,c\n1\n2,3\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: row 2 has 1 fields but row 1 has 3; \
+             set pad_ragged to pad short rows"
+        );
+
+        let (summary, output) = transpose(json!({"pad_ragged": true}), "a,b\n1\n2,3,4\n").unwrap();
+        assert_eq!(output, "a,1,2\nb,,3\n,,4\n");
+        assert_eq!(summary.metrics["columns"], 3.0);
+    }
+
+    fn test_transpose_max_cells() {
+        assert!(transpose(json!({"max_cells": 6}), "a,1\nb,2\nc,3\n").is_ok());
+        let err = transpose(json!({"max_cells": 5}), "a,1\nb,2\nc,3\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data in input input: table has more than max_cells = 5 cells to transpose"
+        );
+        // Padding counts: 3 rows padded to 3 columns are 9 cells.
+        let err =
+            transpose(json!({"max_cells": 8, "pad_ragged": true}), "a\nb\nc,1,2\n").unwrap_err();
+        assert!(err.to_string().contains("max_cells = 8"));
+    }
+
+    fn test_transpose_memory_budget() {
+        // The rows take the same memory, since their fields are as long.
+        let row = row_bytes(&["a".to_string(), "1".to_string()]);
+        let environment = ExecutionEnvironment::new().memory_budget_bytes(Some(3 * row));
+        let (_, output) = transpose_in(environment, json!({}), "a,1\nb,2\nc,3\n").unwrap();
+        assert_eq!(output, "a,b,c\n1,2,3\n");
+
+        let environment = ExecutionEnvironment::new().memory_budget_bytes(Some(3 * row - 1));
+        let err = transpose_in(environment.clone(), json!({}), "a,1\nb,2\nc,3\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Invalid data in input input: table takes more than the memory budget of {} bytes \
+                 to transpose",
+                3 * row - 1
+            )
+        );
+        // Long fields count with their text.
+        let long = format!("{},1\n", "x".repeat(3 * row as usize));
+        let budget = ExecutionEnvironment::new().memory_budget_bytes(Some(3 * row));
+        assert!(transpose_in(budget, json!({}), &long).is_err());
+        // An explicit max_cells still wins over the budget.
+        let (_, output) =
+            transpose_in(environment, json!({"max_cells": 6}), "a,1\nb,2\nc,3\n").unwrap();
+        assert_eq!(output, "a,b,c\n1,2,3\n");
+    }
+}
@@ -51,7 +51,9 @@ impl<T: Log> TeaclaveLogger<T> {
 
 impl<T: Log> Log for TeaclaveLogger<T> {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        if let Some(tl) = &*self.task_logger.read().unwrap() {
+        if teaclave_types::ExecutionLog::current().is_some() {
+            true
+        } else if let Some(tl) = &*self.task_logger.read().unwrap() {
             tl.enabled(metadata)
         } else if let Some(sl) = &self.secondary_logger {
             sl.enabled(metadata)
@@ -77,10 +79,19 @@ impl<T: Log> Log for TeaclaveLogger<T> {
             return;
         }
 
+        // Logs of a running function go to its execution log only.
+        if teaclave_types::capture_log_record(record) {
+            return;
+        }
+
+        // The platform's records made while a function runs must not reach the task's user,
+        // so they skip the task logger.
         let mut lock = self.task_logger.write().unwrap();
         if let Some(ref mut tl) = *lock {
-            tl.log(record);
-            return;
+            if teaclave_types::ExecutionLog::current().is_none() {
+                tl.log(record);
+                return;
+            }
         }
 
         if let Some(sl) = &self.secondary_logger {
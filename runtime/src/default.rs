@@ -17,21 +17,144 @@
 
 use std::io;
 
+use crate::KvScope;
+use teaclave_types::CancellationToken;
+use teaclave_types::RandomAccess;
+use teaclave_types::ScratchDir;
 use teaclave_types::StagedFiles;
 use teaclave_types::TeaclaveRuntime;
+use teaclave_types::{
+    ExecutionEnvironment, ExecutionLimits, ExecutionLog, ExecutionMetrics, ExecutionPolicy,
+    ExecutionProgress, FunctionRng, IoManifest, IoManifestRecorder, KvError, KvStore, OutputMeter,
+    DEFAULT_SCRATCH_BASE_DIR, KV_CAPABILITY,
+};
 
 pub struct DefaultRuntime {
     input_files: StagedFiles,
     output_files: StagedFiles,
+    output_recipient_key: Option<Vec<u8>>,
+    cancellation: CancellationToken,
+    output_meter: OutputMeter,
+    execution_log: ExecutionLog,
+    progress: ExecutionProgress,
+    policy: ExecutionPolicy,
+    rng: FunctionRng,
+    environment: ExecutionEnvironment,
+    metrics: ExecutionMetrics,
+    io_recorder: IoManifestRecorder,
+    kv: Option<KvScope>,
+    scratch: ScratchDir,
 }
 
 impl DefaultRuntime {
     pub fn new(input_files: StagedFiles, output_files: StagedFiles) -> DefaultRuntime {
+        let cancellation = CancellationToken::default();
+        let metrics = ExecutionMetrics::new();
+        metrics.track_cancellation(cancellation.clone());
         DefaultRuntime {
             input_files,
             output_files,
+            output_recipient_key: None,
+            cancellation,
+            output_meter: OutputMeter::default(),
+            execution_log: ExecutionLog::default(),
+            progress: ExecutionProgress::default(),
+            policy: ExecutionPolicy::default(),
+            rng: FunctionRng::default(),
+            environment: ExecutionEnvironment::default(),
+            metrics,
+            io_recorder: IoManifestRecorder::default(),
+            kv: None,
+            scratch: ScratchDir::new(DEFAULT_SCRATCH_BASE_DIR),
         }
     }
+
+    /// Seal function results to the given P-256 public key instead of returning them in clear.
+    pub fn with_output_recipient_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.output_recipient_key = Some(key.into());
+        self
+    }
+
+    /// Let the given token cancel functions running in this runtime.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.metrics.track_cancellation(token.clone());
+        self.cancellation = token;
+        self
+    }
+
+    /// Meter the outputs of functions running in this runtime against the output limits in
+    /// `limits`. The wall time limit is enforced by the cancellation token.
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.output_meter = OutputMeter::new(limits);
+        self
+    }
+
+    /// Collect the messages functions log through `TeaclaveRuntime::log` in `log`.
+    pub fn with_execution_log(mut self, log: ExecutionLog) -> Self {
+        self.execution_log = log;
+        self
+    }
+
+    /// Store the progress functions report through `TeaclaveRuntime::report_progress` in
+    /// `progress`.
+    pub fn with_progress(mut self, progress: ExecutionProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Let functions running in this runtime use the gated builtins `policy` allows.
+    pub fn with_policy(mut self, policy: ExecutionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Hand out `rng` as the randomness of functions running in this runtime.
+    pub fn with_rng(mut self, rng: FunctionRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Let functions running in this runtime use up to `threads` threads.
+    pub fn with_max_parallelism(mut self, threads: usize) -> Self {
+        self.environment.max_parallelism = threads.max(1);
+        self
+    }
+
+    /// Describe the execution to functions running in this runtime with `environment`,
+    /// including the number of threads they may use.
+    pub fn with_environment(mut self, environment: ExecutionEnvironment) -> Self {
+        let threads = environment.max_parallelism;
+        self.environment = environment;
+        self.with_max_parallelism(threads)
+    }
+
+    /// Record the digests of what functions running in this runtime read and write in
+    /// `recorder`.
+    pub fn with_io_recorder(mut self, recorder: IoManifestRecorder) -> Self {
+        self.io_recorder = recorder;
+        self
+    }
+
+    /// Keep the counters of functions running in this runtime in `metrics`, where the caller
+    /// can still read them after a failed execution.
+    pub fn with_metrics(mut self, metrics: ExecutionMetrics) -> Self {
+        metrics.track_cancellation(self.cancellation.clone());
+        self.metrics = metrics;
+        self
+    }
+
+    /// Let functions running in this runtime use the key-value store `scope`, if the policy
+    /// grants them `KV_CAPABILITY`. Without a scope, functions have no key-value store.
+    pub fn with_kv(mut self, scope: Option<KvScope>) -> Self {
+        self.kv = scope;
+        self
+    }
+
+    /// Keep the scratch files of functions running in this runtime under `dir`.
+    pub fn with_scratch_base_dir(mut self, dir: impl AsRef<std::path::Path>) -> Self {
+        self.scratch = ScratchDir::new(dir);
+        self
+    }
 }
 
 impl TeaclaveRuntime for DefaultRuntime {
@@ -43,7 +166,26 @@ impl TeaclaveRuntime for DefaultRuntime {
 
         log::debug!("open_input: {:?}", file_info.path);
         let readable = file_info.create_readable_io()?;
-        Ok(readable)
+        let recorded = self.io_recorder.record_input(identifier, readable);
+        Ok(self.metrics.count_input(identifier, recorded))
+    }
+
+    fn open_input_random_access(
+        &self,
+        identifier: &str,
+    ) -> anyhow::Result<Option<Box<dyn RandomAccess>>> {
+        let file_info = self
+            .input_files
+            .get(identifier)
+            .ok_or_else(|| anyhow::anyhow!("Invalid input file identifier."))?;
+        log::debug!("open_input_random_access: {:?}", file_info.path);
+        let file = file_info.create_random_access_io()?;
+        let recorded = self
+            .io_recorder
+            .record_input_random_access(identifier, file);
+        Ok(Some(
+            self.metrics.count_input_random_access(identifier, recorded),
+        ))
     }
 
     fn create_output(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Write>> {
@@ -54,6 +196,128 @@ impl TeaclaveRuntime for DefaultRuntime {
 
         log::debug!("create_output: {:?}", file_info.path);
         let writable = file_info.create_writable_io()?;
-        Ok(writable)
+        let recorded = self.io_recorder.record_output(identifier, writable);
+        let metered = self.output_meter.meter(identifier, recorded);
+        let counted = self.metrics.count_output(identifier, metered);
+        self.output_files.seal_output(identifier, counted)
+    }
+
+    fn create_scratch(&self, name: &str) -> anyhow::Result<Box<dyn io::Write>> {
+        let writable = self.scratch.create(name)?;
+        log::debug!("create_scratch: {:?}", name);
+        let metered = self
+            .output_meter
+            .meter(&format!("scratch:{}", name), writable);
+        Ok(self.metrics.count_scratch(name, metered))
+    }
+
+    fn open_scratch(&self, name: &str) -> anyhow::Result<Box<dyn io::Read>> {
+        self.scratch.open(name)
+    }
+
+    fn lists_staged_files(&self) -> bool {
+        true
+    }
+
+    fn input_keys(&self) -> Vec<String> {
+        self.input_files
+            .keys()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn output_keys(&self) -> Vec<String> {
+        self.output_files
+            .keys()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn verify_inputs(&self) -> anyhow::Result<()> {
+        self.input_files.verify_digests()
+    }
+
+    fn output_recipient_key(&self) -> Option<&[u8]> {
+        self.output_recipient_key.as_deref()
+    }
+
+    fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    fn log(&self, level: log::Level, message: &str) {
+        self.execution_log.log(level, message);
+    }
+
+    fn report_progress(&self, fraction: f32, note: &str) {
+        self.progress.report(fraction, note);
+    }
+
+    fn execution_policy(&self) -> ExecutionPolicy {
+        self.policy.clone()
+    }
+
+    fn kv(&self) -> anyhow::Result<Box<dyn KvStore>> {
+        if !self.policy.grants(KV_CAPABILITY) {
+            return Err(KvError::CapabilityDenied.into());
+        }
+        let scope = self
+            .kv
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No key-value store is configured for this runtime"))?;
+        Ok(Box::new(scope.open()?))
+    }
+
+    fn rng(&self) -> FunctionRng {
+        self.rng.clone()
+    }
+
+    fn max_parallelism(&self) -> usize {
+        self.environment.max_parallelism
+    }
+
+    fn environment(&self) -> ExecutionEnvironment {
+        self.environment.clone()
+    }
+
+    fn metrics(&self) -> ExecutionMetrics {
+        self.metrics.clone()
+    }
+
+    fn io_manifest(&self) -> Option<IoManifest> {
+        Some(self.io_recorder.manifest())
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::untrusted::fs;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_scratch_removed_after_execution)
+    }
+
+    // Stands in for a function which spills its intermediate state to scratch.
+    fn spill_and_reload(runtime: Box<dyn TeaclaveRuntime>) -> anyhow::Result<Vec<u8>> {
+        runtime
+            .create_scratch("spill")?
+            .write_all(b"partial sums")?;
+        let mut content = Vec::new();
+        runtime.open_scratch("spill")?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    fn test_scratch_removed_after_execution() {
+        let runtime = DefaultRuntime::new(StagedFiles::default(), StagedFiles::default());
+        let scratch_path = runtime.scratch.path().to_path_buf();
+
+        let content = spill_and_reload(Box::new(runtime)).unwrap();
+        assert_eq!(content, b"partial sums");
+        assert!(fs::metadata(&scratch_path).is_err());
     }
 }
@@ -0,0 +1,225 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::untrusted::fs;
+
+use anyhow::Context;
+use ring::rand::{SecureRandom, SystemRandom};
+use rusty_leveldb::{derive_tenant_key, Options, TenantKeyInfo, WriteBatch, DB};
+use sgx_tprotected_fs::SgxFile;
+use teaclave_types::{KvError, KvStore, DEFAULT_KV_MAX_VALUE_SIZE, DEFAULT_KV_QUOTA};
+
+/// Purpose under which the file keys of the stores are derived from the master key.
+const KV_KEY_PURPOSE: &str = "kv";
+
+/// File under the base directory which keeps the master key.
+const MASTER_KEY_FILE: &str = "master_key";
+
+/// Key under which a store records the bytes its tenant uses. Keys of functions start with
+/// `DATA_KEY_PREFIX` instead, so they never collide with it.
+const USAGE_KEY: &[u8] = b"u";
+const DATA_KEY_PREFIX: u8 = b'd';
+
+/// Key-value stores of all tenants. Every tenant gets a database of its own under `base_dir`,
+/// whose files are encrypted with a key derived from `master_key` for the tenant.
+#[derive(Clone)]
+pub struct KvSpace {
+    base_dir: PathBuf,
+    master_key: [u8; 16],
+    salt: Vec<u8>,
+    quota: u64,
+    max_value_size: usize,
+}
+
+impl KvSpace {
+    pub fn new(base_dir: impl AsRef<Path>, master_key: [u8; 16], salt: &[u8]) -> Self {
+        Self {
+            base_dir: base_dir.as_ref().to_path_buf(),
+            master_key,
+            salt: salt.to_vec(),
+            quota: DEFAULT_KV_QUOTA,
+            max_value_size: DEFAULT_KV_MAX_VALUE_SIZE,
+        }
+    }
+
+    /// The space under `base_dir` with the master key kept there. The key is generated on first
+    /// use and stored in a protected file sealed to the enclave, so the stores outlive it.
+    pub fn open_sealed(base_dir: impl AsRef<Path>, salt: &[u8]) -> anyhow::Result<Self> {
+        let base_dir = base_dir.as_ref();
+        fs::create_dir_all(base_dir).context("Failed to create key-value store directory")?;
+        let path = base_dir.join(MASTER_KEY_FILE);
+        let mut master_key = [0; 16];
+        if path.exists() {
+            SgxFile::open(&path)
+                .and_then(|mut file| file.read_exact(&mut master_key))
+                .context("Failed to read master key of key-value stores")?;
+        } else {
+            SystemRandom::new()
+                .fill(&mut master_key)
+                .map_err(|_| anyhow::anyhow!("Failed to generate master key"))?;
+            SgxFile::create(&path)
+                .and_then(|mut file| {
+                    file.write_all(&master_key)?;
+                    file.flush()
+                })
+                .context("Failed to store master key of key-value stores")?;
+        }
+        Ok(Self::new(base_dir, master_key, salt))
+    }
+
+    /// Let every tenant keep up to `bytes` bytes of keys and values.
+    pub fn with_quota(mut self, bytes: u64) -> Self {
+        self.quota = bytes;
+        self
+    }
+
+    /// Refuse values larger than `bytes` bytes.
+    pub fn with_max_value_size(mut self, bytes: usize) -> Self {
+        self.max_value_size = bytes;
+        self
+    }
+
+    /// The part of the space `function` of `tenant` sees.
+    pub fn scope(&self, tenant: &str, function: &str) -> KvScope {
+        KvScope {
+            space: self.clone(),
+            tenant: tenant.to_string(),
+            function: function.to_string(),
+        }
+    }
+}
+
+/// Keys of one function in the store of one tenant.
+#[derive(Clone)]
+pub struct KvScope {
+    space: KvSpace,
+    tenant: String,
+    function: String,
+}
+
+impl KvScope {
+    /// Opens the database of the tenant. The database stays locked while the store is alive,
+    /// so a concurrent execution of the same tenant fails to open it.
+    pub fn open(&self) -> anyhow::Result<LevelDbKv> {
+        let info = TenantKeyInfo::new(self.tenant.as_str(), KV_KEY_PURPOSE);
+        let key = derive_tenant_key(&self.space.master_key, &self.space.salt, &info);
+        fs::create_dir_all(&self.space.base_dir)
+            .context("Failed to create key-value store directory")?;
+        let path = self.space.base_dir.join(tenant_dir_name(&self.tenant));
+        let db = DB::open(&path, Options::new_disk_db_with(key))
+            .with_context(|| format!("Failed to open key-value store of {}", self.tenant))?;
+
+        let mut prefix = vec![DATA_KEY_PREFIX];
+        prefix.extend_from_slice(&(self.function.len() as u32).to_be_bytes());
+        prefix.extend_from_slice(self.function.as_bytes());
+        Ok(LevelDbKv {
+            db,
+            prefix,
+            quota: self.space.quota,
+            max_value_size: self.space.max_value_size,
+        })
+    }
+}
+
+// Tenant ids are chosen by users, so they are hashed instead of used as a path.
+fn tenant_dir_name(tenant: &str) -> String {
+    ring::digest::digest(&ring::digest::SHA256, tenant.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `KvStore` of a function backed by the LevelDB database of its tenant. The quota covers the
+/// keys and values of all functions of the tenant, and is checked before anything is written.
+pub struct LevelDbKv {
+    db: DB,
+    prefix: Vec<u8>,
+    quota: u64,
+    max_value_size: usize,
+}
+
+impl LevelDbKv {
+    /// Bytes of keys and values the tenant keeps in the store.
+    pub fn usage(&mut self) -> anyhow::Result<u64> {
+        match self.db.get(USAGE_KEY) {
+            Some(usage) => {
+                let usage: [u8; 8] = usage
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Corrupted usage of key-value store"))?;
+                Ok(u64::from_be_bytes(usage))
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn data_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut data_key = self.prefix.clone();
+        data_key.extend_from_slice(key);
+        data_key
+    }
+
+    // Size of the entry `key` of the function, if it exists.
+    fn entry_size(&mut self, key: &[u8]) -> Option<u64> {
+        let data_key = self.data_key(key);
+        self.db
+            .get(&data_key)
+            .map(|value| (key.len() + value.len()) as u64)
+    }
+
+    fn write(&mut self, mut batch: WriteBatch, usage: u64) -> anyhow::Result<()> {
+        batch.put(USAGE_KEY, &usage.to_be_bytes());
+        self.db.write(batch, true)?;
+        Ok(())
+    }
+}
+
+impl KvStore for LevelDbKv {
+    fn get(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let data_key = self.data_key(key);
+        Ok(self.db.get(&data_key))
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        if value.len() > self.max_value_size {
+            return Err(KvError::ValueTooLarge(value.len(), self.max_value_size).into());
+        }
+        let replaced = self.entry_size(key).unwrap_or(0);
+        let usage = self.usage()?.saturating_sub(replaced) + (key.len() + value.len()) as u64;
+        if usage > self.quota {
+            return Err(KvError::QuotaExceeded(self.quota).into());
+        }
+        let mut batch = WriteBatch::new();
+        batch.put(&self.data_key(key), value);
+        self.write(batch, usage)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> anyhow::Result<()> {
+        let size = match self.entry_size(key) {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        let usage = self.usage()?.saturating_sub(size);
+        let mut batch = WriteBatch::new();
+        batch.delete(&self.data_key(key));
+        self.write(batch, usage)
+    }
+}
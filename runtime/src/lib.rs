@@ -18,16 +18,21 @@
 extern crate sgx_types;
 
 mod default;
+mod kv;
 pub use default::DefaultRuntime;
+pub use kv::{KvScope, KvSpace, LevelDbKv};
 
 #[cfg(any(feature = "enclave_unit_test", test_mode))]
 mod raw_io;
 #[cfg(any(feature = "enclave_unit_test", test_mode))]
-pub use raw_io::RawIoRuntime;
+pub use raw_io::{OutputBuffers, RawIoRuntime};
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
+    use super::*;
+    use teaclave_test_utils::check_all_passed;
+
     pub fn run_tests() -> bool {
-        true
+        check_all_passed!(default::tests::run_tests(), raw_io::tests::run_tests())
     }
 }
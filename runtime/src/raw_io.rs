@@ -15,44 +15,815 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use std::collections::HashMap;
 use std::io;
+use std::sync::{Arc, Mutex};
 use std::untrusted::fs::File;
 
+use crate::KvScope;
+use teaclave_types::check_scratch_name;
+use teaclave_types::CancellationToken;
+use teaclave_types::RandomAccess;
 use teaclave_types::StagedFiles;
 use teaclave_types::TeaclaveRuntime;
+use teaclave_types::{
+    ExecutionEnvironment, ExecutionLimits, ExecutionLog, ExecutionMetrics, ExecutionPolicy,
+    ExecutionProgress, FunctionRng, IoManifest, IoManifestRecorder, KvError, KvStore, OutputMeter,
+    KV_CAPABILITY,
+};
+
+/// Contents of the in-memory outputs of a `RawIoRuntime`. The handle stays valid after the
+/// runtime has been handed to a function, so tests can inspect the outputs afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct OutputBuffers(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+impl OutputBuffers {
+    pub fn get(&self, identifier: &str) -> Option<Vec<u8>> {
+        self.0.lock().ok()?.get(identifier).cloned()
+    }
+
+    pub fn into_inner(self) -> HashMap<String, Vec<u8>> {
+        match self.0.lock() {
+            Ok(buffers) => buffers.clone(),
+            Err(_) => HashMap::new(),
+        }
+    }
+}
+
+struct MemoryWriter {
+    buffers: OutputBuffers,
+    identifier: String,
+}
+
+impl io::Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffers = self
+            .buffers
+            .0
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Poisoned output buffer"))?;
+        buffers
+            .entry(self.identifier.clone())
+            .or_default()
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 pub struct RawIoRuntime {
     input_files: StagedFiles,
     output_files: StagedFiles,
+    output_buffers: OutputBuffers,
+    scratch_buffers: OutputBuffers,
+    output_recipient_key: Option<Vec<u8>>,
+    cancellation: CancellationToken,
+    output_meter: OutputMeter,
+    execution_log: ExecutionLog,
+    progress: ExecutionProgress,
+    policy: ExecutionPolicy,
+    rng: FunctionRng,
+    environment: ExecutionEnvironment,
+    metrics: ExecutionMetrics,
+    io_recorder: IoManifestRecorder,
+    kv: Option<KvScope>,
 }
 
 impl RawIoRuntime {
     pub fn new(input_files: StagedFiles, output_files: StagedFiles) -> RawIoRuntime {
+        let cancellation = CancellationToken::default();
+        let metrics = ExecutionMetrics::new();
+        metrics.track_cancellation(cancellation.clone());
         RawIoRuntime {
             input_files,
             output_files,
+            output_buffers: OutputBuffers::default(),
+            scratch_buffers: OutputBuffers::default(),
+            output_recipient_key: None,
+            cancellation,
+            output_meter: OutputMeter::default(),
+            execution_log: ExecutionLog::default(),
+            progress: ExecutionProgress::default(),
+            policy: ExecutionPolicy::default(),
+            rng: FunctionRng::default(),
+            environment: ExecutionEnvironment::default(),
+            metrics,
+            io_recorder: IoManifestRecorder::default(),
+            kv: None,
         }
     }
+
+    /// Handle to the outputs staged with `StagedFiles::from_memory`.
+    pub fn output_buffers(&self) -> OutputBuffers {
+        self.output_buffers.clone()
+    }
+
+    /// Contents of the outputs staged with `StagedFiles::from_memory`, by identifier.
+    pub fn into_output_buffers(self) -> HashMap<String, Vec<u8>> {
+        self.output_buffers.into_inner()
+    }
+
+    /// Seal function results to the given P-256 public key instead of returning them in clear.
+    pub fn with_output_recipient_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.output_recipient_key = Some(key.into());
+        self
+    }
+
+    /// Let the given token cancel functions running in this runtime.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.metrics.track_cancellation(token.clone());
+        self.cancellation = token;
+        self
+    }
+
+    /// Meter the outputs of functions running in this runtime against the output limits in
+    /// `limits`. The wall time limit is enforced by the cancellation token.
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.output_meter = OutputMeter::new(limits);
+        self
+    }
+
+    /// Collect the messages functions log through `TeaclaveRuntime::log` in `log`.
+    pub fn with_execution_log(mut self, log: ExecutionLog) -> Self {
+        self.execution_log = log;
+        self
+    }
+
+    /// Store the progress functions report through `TeaclaveRuntime::report_progress` in
+    /// `progress`.
+    pub fn with_progress(mut self, progress: ExecutionProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Let functions running in this runtime use the gated builtins `policy` allows.
+    pub fn with_policy(mut self, policy: ExecutionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Hand out `rng` as the randomness of functions running in this runtime.
+    pub fn with_rng(mut self, rng: FunctionRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Let functions running in this runtime use up to `threads` threads.
+    pub fn with_max_parallelism(mut self, threads: usize) -> Self {
+        self.environment.max_parallelism = threads.max(1);
+        self
+    }
+
+    /// Describe the execution to functions running in this runtime with `environment`,
+    /// including the number of threads they may use.
+    pub fn with_environment(mut self, environment: ExecutionEnvironment) -> Self {
+        let threads = environment.max_parallelism;
+        self.environment = environment;
+        self.with_max_parallelism(threads)
+    }
+
+    /// Record the digests of what functions running in this runtime read and write in
+    /// `recorder`.
+    pub fn with_io_recorder(mut self, recorder: IoManifestRecorder) -> Self {
+        self.io_recorder = recorder;
+        self
+    }
+
+    /// Keep the counters of functions running in this runtime in `metrics`, where the caller
+    /// can still read them after a failed execution.
+    pub fn with_metrics(mut self, metrics: ExecutionMetrics) -> Self {
+        metrics.track_cancellation(self.cancellation.clone());
+        self.metrics = metrics;
+        self
+    }
+
+    /// Let functions running in this runtime use the key-value store `scope`, if the policy
+    /// grants them `KV_CAPABILITY`. Without a scope, functions have no key-value store.
+    pub fn with_kv(mut self, scope: Option<KvScope>) -> Self {
+        self.kv = scope;
+        self
+    }
 }
 
 impl TeaclaveRuntime for RawIoRuntime {
     fn open_input(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Read>> {
+        if let Some(content) = self.input_files.get_memory(identifier) {
+            let reader = Box::new(io::Cursor::new(content.to_vec()));
+            let recorded = self.io_recorder.record_input(identifier, reader);
+            return Ok(self.metrics.count_input(identifier, recorded));
+        }
         let file_info = self
             .input_files
             .get(identifier)
             .ok_or_else(|| anyhow::anyhow!("Invalid input file identifier."))?;
         log::debug!("open_input: {:?}", file_info.path);
         let f = File::open(&file_info.path)?;
-        Ok(Box::new(f))
+        let recorded = self.io_recorder.record_input(identifier, Box::new(f));
+        Ok(self.metrics.count_input(identifier, recorded))
+    }
+
+    fn open_input_random_access(
+        &self,
+        identifier: &str,
+    ) -> anyhow::Result<Option<Box<dyn RandomAccess>>> {
+        if let Some(content) = self.input_files.get_memory(identifier) {
+            let file = Box::new(io::Cursor::new(content.to_vec()));
+            let recorded = self
+                .io_recorder
+                .record_input_random_access(identifier, file);
+            return Ok(Some(
+                self.metrics.count_input_random_access(identifier, recorded),
+            ));
+        }
+        let file_info = self
+            .input_files
+            .get(identifier)
+            .ok_or_else(|| anyhow::anyhow!("Invalid input file identifier."))?;
+        log::debug!("open_input_random_access: {:?}", file_info.path);
+        let f = File::open(&file_info.path)?;
+        let recorded = self
+            .io_recorder
+            .record_input_random_access(identifier, Box::new(f));
+        Ok(Some(
+            self.metrics.count_input_random_access(identifier, recorded),
+        ))
     }
 
     fn create_output(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Write>> {
+        if self.output_files.get_memory(identifier).is_some() {
+            self.output_buffers
+                .0
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Poisoned output buffer"))?
+                .insert(identifier.to_string(), Vec::new());
+            let writer = MemoryWriter {
+                buffers: self.output_buffers.clone(),
+                identifier: identifier.to_string(),
+            };
+            let recorded = self.io_recorder.record_output(identifier, Box::new(writer));
+            let metered = self.output_meter.meter(identifier, recorded);
+            let counted = self.metrics.count_output(identifier, metered);
+            return self.output_files.seal_output(identifier, counted);
+        }
         let file_info = self
             .output_files
             .get(identifier)
             .ok_or_else(|| anyhow::anyhow!("Invalid output file identifier"))?;
         log::debug!("create_output: {:?}", file_info.path);
         let f = File::create(&file_info.path)?;
-        Ok(Box::new(f))
+        let recorded = self.io_recorder.record_output(identifier, Box::new(f));
+        let metered = self.output_meter.meter(identifier, recorded);
+        let counted = self.metrics.count_output(identifier, metered);
+        self.output_files.seal_output(identifier, counted)
+    }
+
+    fn create_scratch(&self, name: &str) -> anyhow::Result<Box<dyn io::Write>> {
+        check_scratch_name(name)?;
+        self.scratch_buffers
+            .0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Poisoned scratch buffer"))?
+            .insert(name.to_string(), Vec::new());
+        let writer = MemoryWriter {
+            buffers: self.scratch_buffers.clone(),
+            identifier: name.to_string(),
+        };
+        let metered = self
+            .output_meter
+            .meter(&format!("scratch:{}", name), Box::new(writer));
+        Ok(self.metrics.count_scratch(name, metered))
+    }
+
+    fn open_scratch(&self, name: &str) -> anyhow::Result<Box<dyn io::Read>> {
+        check_scratch_name(name)?;
+        let content = self
+            .scratch_buffers
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Invalid scratch file name: {:?}", name))?;
+        Ok(Box::new(io::Cursor::new(content)))
+    }
+
+    fn lists_staged_files(&self) -> bool {
+        true
+    }
+
+    fn input_keys(&self) -> Vec<String> {
+        self.input_files
+            .keys()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn output_keys(&self) -> Vec<String> {
+        self.output_files
+            .keys()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn verify_inputs(&self) -> anyhow::Result<()> {
+        self.input_files.verify_digests()
+    }
+
+    fn output_recipient_key(&self) -> Option<&[u8]> {
+        self.output_recipient_key.as_deref()
+    }
+
+    fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    fn log(&self, level: log::Level, message: &str) {
+        self.execution_log.log(level, message);
+    }
+
+    fn report_progress(&self, fraction: f32, note: &str) {
+        self.progress.report(fraction, note);
+    }
+
+    fn execution_policy(&self) -> ExecutionPolicy {
+        self.policy.clone()
+    }
+
+    fn kv(&self) -> anyhow::Result<Box<dyn KvStore>> {
+        if !self.policy.grants(KV_CAPABILITY) {
+            return Err(KvError::CapabilityDenied.into());
+        }
+        let scope = self
+            .kv
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No key-value store is configured for this runtime"))?;
+        Ok(Box::new(scope.open()?))
+    }
+
+    fn rng(&self) -> FunctionRng {
+        self.rng.clone()
+    }
+
+    fn max_parallelism(&self) -> usize {
+        self.environment.max_parallelism
+    }
+
+    fn environment(&self) -> ExecutionEnvironment {
+        self.environment.clone()
+    }
+
+    fn metrics(&self) -> ExecutionMetrics {
+        self.metrics.clone()
+    }
+
+    fn io_manifest(&self) -> Option<IoManifest> {
+        Some(self.io_recorder.manifest())
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::KvSpace;
+    use std::convert::TryInto;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::untrusted::fs;
+    use teaclave_test_utils::*;
+    use teaclave_types::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_memory_input_memory_output,
+            test_memory_input_file_output,
+            test_file_input_memory_output,
+            test_input_output_keys,
+            test_execution_log,
+            test_scratch,
+            test_scratch_counts_against_limits,
+            test_execution_metrics,
+            test_execution_metrics_of_failed_run,
+            test_sealed_output,
+            test_io_manifest_of_copy,
+            test_io_manifest_of_partial_read,
+            test_kv_persists_across_executions,
+            test_kv_sealed_master_key,
+            test_kv_quota,
+            test_kv_capability_denied,
+        )
+    }
+
+    fn memory_files(identifier: &str, content: &[u8]) -> StagedFiles {
+        StagedFiles::from_memory(hashmap!(identifier => content.to_vec()))
+    }
+
+    fn test_memory_input_memory_output() {
+        let runtime = RawIoRuntime::new(
+            memory_files("input", b"Hello Teaclave!"),
+            memory_files("output", b"ignored"),
+        );
+
+        let mut input = String::new();
+        runtime
+            .open_input("input")
+            .unwrap()
+            .read_to_string(&mut input)
+            .unwrap();
+        assert_eq!(input, "Hello Teaclave!");
+
+        let mut file = runtime.open_input_random_access("input").unwrap().unwrap();
+        assert_eq!(file.size_of().unwrap(), 15);
+
+        // Outputs start empty and are replaced when created again.
+        let buffers = runtime.output_buffers();
+        runtime
+            .create_output("output")
+            .unwrap()
+            .write_all(b"first")
+            .unwrap();
+        assert_eq!(buffers.get("output").unwrap(), b"first");
+        runtime
+            .create_output("output")
+            .unwrap()
+            .write_all(b"second")
+            .unwrap();
+        assert_eq!(runtime.into_output_buffers()["output"], b"second");
+    }
+
+    fn test_memory_input_file_output() {
+        let output = Path::new("fixtures/runtime/output.txt");
+        let output_files = StagedFiles::new(hashmap!(
+            "output" =>
+            StagedFileInfo::new(output, Default::default(), FileAuthTag::mock()),
+        ));
+        let runtime = RawIoRuntime::new(memory_files("input", b"Hello Teaclave!"), output_files);
+
+        runtime
+            .copy_input_to_output("input", "output", &mut |_| {})
+            .unwrap();
+        assert_eq!(fs::read(output).unwrap(), b"Hello Teaclave!");
+        assert!(runtime.into_output_buffers().is_empty());
+    }
+
+    fn test_file_input_memory_output() {
+        let input = Path::new("fixtures/runtime/input.txt");
+        let input_files = StagedFiles::new(hashmap!(
+            "input" =>
+            StagedFileInfo::new(input, Default::default(), FileAuthTag::mock()),
+        ));
+        let runtime = RawIoRuntime::new(input_files, memory_files("output", b""));
+
+        runtime
+            .copy_input_to_output("input", "output", &mut |_| {})
+            .unwrap();
+        assert_eq!(
+            runtime.into_output_buffers()["output"],
+            fs::read(input).unwrap()
+        );
+    }
+
+    fn test_input_output_keys() {
+        let input_files = StagedFiles::new(hashmap!(
+            "input_data2" =>
+            StagedFileInfo::new("fixtures/runtime/input.txt", Default::default(), FileAuthTag::mock()),
+            "input_data0" =>
+            StagedFileInfo::new("fixtures/runtime/input.txt", Default::default(), FileAuthTag::mock()),
+        ));
+        let runtime = RawIoRuntime::new(input_files, memory_files("output", b""));
+
+        assert_eq!(runtime.input_keys(), vec!["input_data0", "input_data2"]);
+        assert_eq!(runtime.output_keys(), vec!["output"]);
+    }
+
+    fn test_execution_log() {
+        let log = ExecutionLog::new(64);
+        let runtime = RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+            .with_execution_log(log.clone());
+        runtime.log(log::Level::Info, "first");
+        runtime.log(log::Level::Warn, "second");
+        assert_eq!(log.drain(), vec!["[INFO] first", "[WARN] second"]);
+    }
+
+    fn test_scratch() {
+        let runtime = RawIoRuntime::new(StagedFiles::default(), memory_files("output", b""));
+        let mut spill = runtime.create_scratch("spill").unwrap();
+        for row in 0..100 {
+            writeln!(spill, "row {}", row).unwrap();
+        }
+        drop(spill);
+
+        let mut rows = String::new();
+        runtime
+            .open_scratch("spill")
+            .unwrap()
+            .read_to_string(&mut rows)
+            .unwrap();
+        assert_eq!(rows.lines().count(), 100);
+        assert_eq!(rows.lines().last(), Some("row 99"));
+
+        assert!(runtime.open_scratch("missing").is_err());
+        assert!(runtime.create_scratch("../output").is_err());
+        // Scratch files are not outputs.
+        assert_eq!(runtime.output_keys(), vec!["output"]);
+        assert!(runtime.into_output_buffers()["output"].is_empty());
+    }
+
+    fn test_scratch_counts_against_limits() {
+        let limits = ExecutionLimits::unlimited().max_total_output_bytes(8);
+        let runtime = RawIoRuntime::new(StagedFiles::default(), memory_files("output", b""))
+            .with_limits(limits);
+
+        runtime
+            .create_scratch("spill")
+            .unwrap()
+            .write_all(b"123456")
+            .unwrap();
+        let result = runtime.create_output("output").unwrap().write_all(b"789");
+        let error = anyhow::Error::from(result.unwrap_err());
+        assert_eq!(
+            ResourceLimitExceeded::find(&error),
+            Some(&ResourceLimitExceeded::TotalOutputBytes(8))
+        );
+    }
+
+    // Stands in for a function copying one input through the chunked helpers and another one
+    // through plain reads and writes.
+    fn copy_function(runtime: FunctionRuntime) -> anyhow::Result<()> {
+        runtime.copy_input_to_output("input_a", "output_a", &mut |_| {})?;
+        runtime.cancellation().checkpoint()?;
+        let mut input = runtime.open_input("input_b")?;
+        let mut output = runtime.create_output("output_b")?;
+        io::copy(&mut input, &mut output)?;
+        Ok(())
+    }
+
+    fn copy_runtime() -> RawIoRuntime {
+        let inputs = StagedFiles::from_memory(hashmap!(
+            "input_a" => vec![1; DEFAULT_CHUNK_SIZE * 2 + 100],
+            "input_b" => vec![2; 1000],
+        ));
+        let outputs = StagedFiles::from_memory(hashmap!(
+            "output_a" => Vec::new(),
+            "output_b" => Vec::new(),
+        ));
+        RawIoRuntime::new(inputs, outputs)
+    }
+
+    fn test_execution_metrics() {
+        let runtime = copy_runtime();
+        let metrics = runtime.metrics();
+        copy_function(Box::new(runtime)).unwrap();
+
+        let size_a = (DEFAULT_CHUNK_SIZE * 2 + 100) as u64;
+        let input_bytes = metrics.input_bytes();
+        assert_eq!(input_bytes["input_a"], size_a);
+        assert_eq!(input_bytes["input_b"], 1000);
+        let output_bytes = metrics.output_bytes();
+        assert_eq!(output_bytes["output_a"], size_a);
+        assert_eq!(output_bytes["output_b"], 1000);
+        assert_eq!(metrics.checkpoints(), 1);
+        assert_eq!(metrics.peak_scratch_bytes(), 0);
+    }
+
+    fn test_execution_metrics_of_failed_run() {
+        let limits =
+            ExecutionLimits::unlimited().max_single_output_bytes(DEFAULT_CHUNK_SIZE as u64 + 10);
+        let runtime = copy_runtime().with_limits(limits);
+        let metrics = runtime.metrics();
+        assert!(copy_function(Box::new(runtime)).is_err());
+
+        // The second chunk was read, but writing it crossed the limit.
+        assert_eq!(
+            metrics.input_bytes()["input_a"],
+            2 * DEFAULT_CHUNK_SIZE as u64
+        );
+        assert_eq!(
+            metrics.output_bytes()["output_a"],
+            DEFAULT_CHUNK_SIZE as u64
+        );
+        assert!(!metrics.input_bytes().contains_key("input_b"));
+        assert_eq!(metrics.checkpoints(), 0);
+    }
+
+    fn test_sealed_output() {
+        use ring::agreement;
+        use teaclave_crypto::RecipientPrivateKey;
+
+        let input: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let seal = || {
+            let private_key = RecipientPrivateKey::generate(&agreement::X25519).unwrap();
+            let public_key = private_key.public_key().unwrap();
+            let output_files = memory_files("output", b"").with_recipient_key("output", public_key);
+            let runtime = RawIoRuntime::new(memory_files("input", &input), output_files);
+            assert_eq!(
+                runtime
+                    .output_files
+                    .metadata("output")
+                    .unwrap()
+                    .sealed_stream_version,
+                Some(SEALED_STREAM_VERSION)
+            );
+            let sealed_outputs = runtime.output_files.sealed_outputs();
+            runtime
+                .copy_input_to_output("input", "output", &mut |_| {})
+                .unwrap();
+            sealed_outputs.check().unwrap();
+            (private_key, runtime.into_output_buffers()["output"].clone())
+        };
+
+        let (private_key, sealed) = seal();
+        assert!(sealed.len() > input.len());
+        assert!(!sealed.windows(64).any(|window| window == &input[..64]));
+        let mut plaintext = Vec::new();
+        open_sealed_stream(&private_key, &mut io::Cursor::new(sealed), &mut plaintext).unwrap();
+        assert_eq!(plaintext, input);
+
+        let (private_key, mut sealed) = seal();
+        let middle = sealed.len() / 2;
+        sealed[middle] ^= 1;
+        let mut plaintext = Vec::new();
+        assert!(
+            open_sealed_stream(&private_key, &mut io::Cursor::new(sealed), &mut plaintext).is_err()
+        );
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        ring::digest::digest(&ring::digest::SHA256, data)
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn test_io_manifest_of_copy() {
+        let input: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let recorder = IoManifestRecorder::new();
+        let runtime = RawIoRuntime::new(memory_files("input", &input), memory_files("output", b""))
+            .with_io_recorder(recorder.clone());
+        let outputs = runtime.output_buffers();
+        runtime
+            .copy_input_to_output("input", "output", &mut |chunk| chunk.reverse())
+            .unwrap();
+        drop(runtime);
+
+        let output = outputs.get("output").unwrap();
+        let manifest = recorder.manifest();
+        assert_eq!(
+            manifest.inputs["input"],
+            InputRecord {
+                opened: true,
+                bytes: input.len() as u64,
+                sha256: Some(sha256(&input)),
+                partial: false,
+            }
+        );
+        assert_eq!(
+            manifest.outputs["output"],
+            OutputRecord {
+                opened: true,
+                bytes: output.len() as u64,
+                sha256: Some(sha256(&output)),
+            }
+        );
+        assert_ne!(output, input);
+    }
+
+    fn test_io_manifest_of_partial_read() {
+        let input = vec![7; 1000];
+        let recorder = IoManifestRecorder::new();
+        let runtime = RawIoRuntime::new(memory_files("input", &input), StagedFiles::default())
+            .with_io_recorder(recorder.clone());
+
+        let mut half = vec![0; 500];
+        runtime
+            .open_input("input")
+            .unwrap()
+            .read_exact(&mut half)
+            .unwrap();
+        let manifest = recorder.manifest();
+        assert_eq!(
+            manifest.inputs["input"],
+            InputRecord {
+                opened: true,
+                bytes: 500,
+                sha256: Some(sha256(&input[..500])),
+                partial: true,
+            }
+        );
+        assert!(manifest.outputs.is_empty());
+
+        // Reading the input again up to its end replaces the partial record.
+        let mut all = Vec::new();
+        runtime
+            .open_input("input")
+            .unwrap()
+            .read_to_end(&mut all)
+            .unwrap();
+        assert!(!recorder.manifest().inputs["input"].partial);
+    }
+
+    fn kv_space(name: &str) -> KvSpace {
+        let base_dir = Path::new("/tmp/teaclave_kv_test").join(name);
+        let _ = fs::remove_dir_all(&base_dir);
+        KvSpace::new(base_dir, [7; 16], b"test")
+    }
+
+    fn kv_runtime(scope: &KvScope) -> RawIoRuntime {
+        RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+            .with_policy(ExecutionPolicy::new().grant(KV_CAPABILITY))
+            .with_kv(Some(scope.clone()))
+    }
+
+    // Stands in for a function which counts its runs.
+    fn count_run(runtime: &dyn TeaclaveRuntime) -> anyhow::Result<u64> {
+        let mut kv = runtime.kv()?;
+        let count = match kv.get(b"count")? {
+            Some(count) => u64::from_be_bytes(count.as_slice().try_into()?) + 1,
+            None => 1,
+        };
+        kv.put(b"count", &count.to_be_bytes())?;
+        Ok(count)
+    }
+
+    fn test_kv_persists_across_executions() {
+        let space = kv_space("persist");
+        let scope = space.scope("tenant-a", "counter");
+        assert_eq!(count_run(&kv_runtime(&scope)).unwrap(), 1);
+        assert_eq!(count_run(&kv_runtime(&scope)).unwrap(), 2);
+
+        // Other functions and other tenants do not see the counter.
+        assert_eq!(
+            count_run(&kv_runtime(&space.scope("tenant-a", "other"))).unwrap(),
+            1
+        );
+        assert_eq!(
+            count_run(&kv_runtime(&space.scope("tenant-b", "counter"))).unwrap(),
+            1
+        );
+        assert_eq!(count_run(&kv_runtime(&scope)).unwrap(), 3);
+    }
+
+    fn test_kv_sealed_master_key() {
+        let base_dir = Path::new("/tmp/teaclave_kv_test").join("sealed");
+        let _ = fs::remove_dir_all(&base_dir);
+        let scope = |space: KvSpace| space.scope("tenant-a", "counter");
+
+        let space = KvSpace::open_sealed(&base_dir, b"test").unwrap();
+        assert_eq!(count_run(&kv_runtime(&scope(space))).unwrap(), 1);
+        // Opening the space again, e.g. after a restart, loads the same master key.
+        let space = KvSpace::open_sealed(&base_dir, b"test").unwrap();
+        assert_eq!(count_run(&kv_runtime(&scope(space))).unwrap(), 2);
+    }
+
+    fn test_kv_quota() {
+        let space = kv_space("quota").with_quota(100).with_max_value_size(40);
+        let runtime = kv_runtime(&space.scope("tenant-a", "counter"));
+        let mut kv = runtime.kv().unwrap();
+
+        let error = kv.put(b"big", &[0; 41]).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&KvError::ValueTooLarge(41, 40)));
+
+        // Each entry takes 2 + 40 bytes, so the third one exceeds the quota.
+        kv.put(b"k1", &[1; 40]).unwrap();
+        kv.put(b"k2", &[2; 40]).unwrap();
+        let error = kv.put(b"k3", &[3; 40]).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&KvError::QuotaExceeded(100)));
+        assert_eq!(kv.get(b"k3").unwrap(), None);
+
+        // The quota is shared by all functions of the tenant.
+        drop(kv);
+        let other = kv_runtime(&space.scope("tenant-a", "other"));
+        let error = other.kv().unwrap().put(b"k3", &[3; 40]).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&KvError::QuotaExceeded(100)));
+
+        // Overwriting and deleting entries frees their space.
+        let mut kv = runtime.kv().unwrap();
+        kv.put(b"k1", &[1; 10]).unwrap();
+        kv.delete(b"k2").unwrap();
+        kv.put(b"k3", &[3; 40]).unwrap();
+        kv.put(b"k4", &[4; 30]).unwrap();
+        assert_eq!(kv.get(b"k2").unwrap(), None);
+        assert_eq!(kv.get(b"k3").unwrap(), Some(vec![3; 40]));
+    }
+
+    fn test_kv_capability_denied() {
+        let space = kv_space("denied");
+        let runtime = RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+            .with_kv(Some(space.scope("tenant-a", "counter")));
+        let error = count_run(&runtime).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&KvError::CapabilityDenied));
+
+        // Allowing builtins tagged like the capability doesn't grant it.
+        let runtime = RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+            .with_policy(ExecutionPolicy::new().allow_tag(KV_CAPABILITY))
+            .with_kv(Some(space.scope("tenant-a", "counter")));
+        let error = count_run(&runtime).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&KvError::CapabilityDenied));
+
+        // A runtime without a store fails even if the capability is granted.
+        let runtime = RawIoRuntime::new(StagedFiles::default(), StagedFiles::default())
+            .with_policy(ExecutionPolicy::new().grant(KV_CAPABILITY));
+        assert!(runtime.kv().is_err());
     }
 }
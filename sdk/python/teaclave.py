@@ -32,7 +32,7 @@ import time
 import ssl
 import socket
 
-from typing import Tuple, Dict, List, Any
+from typing import Tuple, Dict, List, Any, Optional
 from enum import IntEnum
 
 import cryptography
@@ -662,13 +662,18 @@ class GetFunctionUsageStatsRequest(Request):
 
 class RegisterInputFileRequest(Request):
 
-    def __init__(self, metadata: Metadata, url: str, cmac: List[int],
-                 crypto_info: CryptoInfo):
+    def __init__(self,
+                 metadata: Metadata,
+                 url: str,
+                 cmac: List[int],
+                 crypto_info: CryptoInfo,
+                 digest: Optional[List[int]] = None):
         self.request = "register_input_file"
         self.metadata = metadata
         self.url = url
         self.cmac = cmac
         self.crypto_info = crypto_info
+        self.digest = digest or []
 
 
 class RegisterOutputFileRequest(Request):
@@ -898,12 +903,19 @@ class FrontendService(TeaclaveService):
         else:
             raise TeaclaveException("Failed to disable function")
 
-    def register_input_file(self, url: str, schema: str, key: List[int],
-                            iv: List[int], cmac: List[int]):
+    def register_input_file(self,
+                            url: str,
+                            schema: str,
+                            key: List[int],
+                            iv: List[int],
+                            cmac: List[int],
+                            digest: Optional[List[int]] = None):
+        """Register an input file. A non-empty digest is the SHA-256 of the
+        plaintext, which the execution service checks before running a task."""
         self.check_metadata()
         self.check_channel()
         request = RegisterInputFileRequest(self.metadata, url, cmac,
-                                           CryptoInfo(schema, key, iv))
+                                           CryptoInfo(schema, key, iv), digest)
         _write_message(self.channel, request)
         response = _read_message(self.channel)
         if response["result"] == "ok":
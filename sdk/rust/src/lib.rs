@@ -582,7 +582,7 @@ mod tests {
     use super::*;
     use std::convert::TryInto;
     use std::fs;
-    use teaclave_types::hashmap;
+    use teaclave_types::{hashmap, FunctionSummary};
 
     const ENCLAVE_INFO_PATH: &str = "../../release/services/enclave_info.toml";
     #[cfg(dcap)]
@@ -651,7 +651,8 @@ mod tests {
         let _ = client.invoke_task(&task_id).unwrap();
         let (result, log) = client.get_task_result(&task_id).unwrap();
         let usage_number = client.get_function_usage_stats(&function_id).unwrap();
-        assert_eq!(result, b"Hello, Teaclave!");
+        let summary: FunctionSummary = serde_json::from_slice(&result).unwrap();
+        assert_eq!(summary.message, "Hello, Teaclave!");
         assert!(log.is_empty());
         assert_eq!(1, usage_number);
 
@@ -667,7 +668,8 @@ mod tests {
         let _ = client.invoke_task(&task_id).unwrap();
         let (result, log) = client.get_task_result(&task_id).unwrap();
         let usage_number = client.get_function_usage_stats(&function_id).unwrap();
-        assert_eq!(result, b"Hello, Teaclave!");
+        let summary: FunctionSummary = serde_json::from_slice(&result).unwrap();
+        assert_eq!(summary.message, "Hello, Teaclave!");
         assert!(log.is_empty());
         assert_eq!(2, usage_number);
 
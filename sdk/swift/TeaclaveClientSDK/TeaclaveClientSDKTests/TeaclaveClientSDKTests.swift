@@ -29,6 +29,12 @@ class TeaclaveClientSDKTests: XCTestCase {
     // Setup AS CA certificate path e.g., /incubator-teaclave/keys/ias_root_ca_cert.pem
     let as_root_ca_cert_path = ""
 
+    // Builtin functions return a JSON-encoded function summary.
+    func summaryMessage(_ result: String) throws -> String? {
+        let summary = try JSONSerialization.jsonObject(with: Data(result.utf8)) as? [String: Any]
+        return summary?["message"] as? String
+    }
+
     func testBuiltinEcho() throws {
         let client = AuthenticationClient(
             address: authentication_service_address,
@@ -68,7 +74,7 @@ class TeaclaveClientSDKTests: XCTestCase {
 
         try frontend_client.invoke_task(task_id: task_id).get()
         let task_result = try frontend_client.get_task_result(task_id: task_id).get()
-        XCTAssert(task_result == "Hello, Teaclave!")
+        XCTAssert(try summaryMessage(task_result) == "Hello, Teaclave!")
     }
 
     func testBuiltinPasswordCheck() throws {
@@ -93,10 +99,12 @@ class TeaclaveClientSDKTests: XCTestCase {
             payload: [],
             arguments: [],
             inputs: [
-                ["name": "password", "description": "Client 0's data."],
-                ["name": "exposed_passwords", "description": "Client 1's data."]
+                ["name": "candidates", "description": "Client 0's data."],
+                ["name": "corpus", "description": "Client 1's data."]
             ],
-            outputs: []
+            outputs: [
+                ["name": "result", "description": "Per-candidate result."]
+            ]
         )
         let response = try user0_frontend_client.register_function(with: register_function_request).get()
         let function_id = response.function_id
@@ -106,10 +114,12 @@ class TeaclaveClientSDKTests: XCTestCase {
             function_arguments: "{}",
             executor: "builtin",
             inputs_ownership: [
-                OwnerList(data_name: "password", uids: ["user0"]),
-                OwnerList(data_name: "exposed_passwords", uids: ["user1"]),
+                OwnerList(data_name: "candidates", uids: ["user0"]),
+                OwnerList(data_name: "corpus", uids: ["user1"]),
             ],
-            outputs_ownership: []
+            outputs_ownership: [
+                OwnerList(data_name: "result", uids: ["user0"]),
+            ]
         )
         let task_id = try user0_frontend_client.create_task(with: create_task_request).get().task_id
 
@@ -121,12 +131,17 @@ class TeaclaveClientSDKTests: XCTestCase {
             crypto_info: CryptoInfo(schema: "aes-gcm-128", key: key, iv: iv)
         )
         let user0_data_id = try user0_frontend_client.register_input_file(with: register_input_file_request).get().data_id
-
+        let register_output_file_request = RegisterOutputFileRequest(
+            url: "http://teaclave-file-service:6789/fixtures/functions/password_check/result.enc",
+            cmac: [],
+            crypto_info: CryptoInfo(schema: "teaclave-file-128", key: key, iv: [])
+        )
+        let user0_output_id = try user0_frontend_client.register_output_file(with: register_output_file_request).get().data_id
 
         let user0_assign_data_request = AssignDataRequest(
             task_id: task_id,
-            inputs: [DataMap(data_name: "password", data_id: user0_data_id)],
-            outputs: []
+            inputs: [DataMap(data_name: "candidates", data_id: user0_data_id)],
+            outputs: [DataMap(data_name: "result", data_id: user0_output_id)]
         )
         try user0_frontend_client.assign_data(with: user0_assign_data_request).get()
 
@@ -145,14 +160,14 @@ class TeaclaveClientSDKTests: XCTestCase {
         try user1_frontend_client.set_credential(id: "user1", token: user1_token).get()
 
         let user1_register_input_file_request = RegisterInputFileRequest(
-            url: "http://teaclave-file-service:6789/fixtures/functions/password_check/exposed_passwords.txt.enc",
-            cmac: [0x42, 0xb1, 0x6c, 0x29, 0xed, 0xeb, 0x9e, 0xe0, 0xe4, 0xd2, 0x19, 0xf3, 0xb5, 0x39, 0x59, 0x46],
-            crypto_info: CryptoInfo(schema: "teaclave-file-128", key: key, iv: [])
+            url: "http://teaclave-file-service:6789/fixtures/functions/password_check/corpus_sha1.txt.aes_gcm_128",
+            cmac: [0x83, 0x8b, 0xf3, 0x37, 0x21, 0x7c, 0x51, 0xbd, 0xed, 0xf1, 0xbb, 0x68, 0x2c, 0xd0, 0x30, 0xbd],
+            crypto_info: CryptoInfo(schema: "aes-gcm-128", key: key, iv: iv)
         )
         let user1_data_id = try user1_frontend_client.register_input_file(with: user1_register_input_file_request).get().data_id
         let user1_assign_data_request = AssignDataRequest(
             task_id: task_id,
-            inputs: [DataMap(data_name: "exposed_passwords", data_id: user1_data_id)],
+            inputs: [DataMap(data_name: "corpus", data_id: user1_data_id)],
             outputs: []
         )
         try user1_frontend_client.assign_data(with: user1_assign_data_request).get()
@@ -165,7 +180,7 @@ class TeaclaveClientSDKTests: XCTestCase {
         try user0_frontend_client.invoke_task(task_id: task_id).get()
 
         let result = try user0_frontend_client.get_task_result(task_id: task_id).get()
-        XCTAssert(result == "true")
+        XCTAssert(try summaryMessage(result) == "1 of 1 candidates found in corpus")
 
     }
 }
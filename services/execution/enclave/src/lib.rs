@@ -61,7 +61,7 @@ fn start_service(config: &RuntimeConfig) -> Result<()> {
         &enclave_info,
         AS_ROOT_CA_CERT,
         verifier::universal_quote_verifier,
-        attested_tls_config,
+        attested_tls_config.clone(),
     )?;
 
     let fusion_base = config.mount.fusion_base_dir.clone();
@@ -78,8 +78,11 @@ fn start_service(config: &RuntimeConfig) -> Result<()> {
     );
 
     info!(" Starting Execution: start ...");
-    let mut service =
-        service::TeaclaveExecutionService::new(scheduler_service_endpoint, fusion_base)?;
+    let mut service = service::TeaclaveExecutionService::new(
+        scheduler_service_endpoint,
+        fusion_base,
+        attested_tls_config,
+    )?;
 
     service.start()
 }
@@ -124,6 +127,7 @@ pub mod tests {
         run_tests!(
             ocall::tests::test_handle_file_request,
             service::tests::test_invoke_echo,
+            service::tests::test_invoke_with_policy,
             service::tests::test_invoke_gbdt_train,
             task_file_manager::tests::test_input,
         )
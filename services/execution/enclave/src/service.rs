@@ -18,27 +18,49 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::Duration;
 
 use crate::task_file_manager::TaskFileManager;
+use teaclave_attestation::AttestedTlsConfig;
 use teaclave_proto::teaclave_common::{ExecutorCommand, ExecutorStatus};
 use teaclave_proto::teaclave_scheduler_service::*;
 use teaclave_rpc::endpoint::Endpoint;
 use teaclave_types::*;
-use teaclave_worker::Worker;
+use teaclave_worker::{InvocationOptions, KvSpace, Worker};
 
 use anyhow::Result;
 use uuid::Uuid;
 
 static WORKER_BASE_DIR: &str = "/tmp/teaclave_agent/";
+// Key-value stores of the tenants, which outlive the tasks.
+static KV_BASE_DIR: &str = "/tmp/teaclave_kv/";
+const KV_SALT: &[u8] = b"teaclave-execution-kv";
+// Threads a function may use. The enclave has 22 TCSs, most of which are kept for the service.
+const MAX_FUNCTION_THREADS: usize = 4;
+// Memory functions may use for their buffers. The enclave heap is 768M, the rest of which is
+// kept for the service.
+const FUNCTION_MEMORY_BUDGET: u64 = 512 * 1024 * 1024;
+// Functions running at a time. The service runs one task at a time, but a function abandoned at
+// its hard timeout keeps running, and keeps its memory budget, until it returns.
+const MAX_CONCURRENT_FUNCTIONS: usize = 1;
+// Largest function result stored with a task. Tasks can lower it with their execution policy.
+const MAX_SUMMARY_BYTES: usize = 64 * 1024;
+// Wall time of functions whose task sets no limit. They are abandoned 30 seconds later.
+const FUNCTION_SOFT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+// Time the worker gets to start a thread in the health probe before the executor reports
+// itself unhealthy.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Clone)]
 pub(crate) struct TeaclaveExecutionService {
-    #[allow(dead_code)]
+    // Kept across tasks, so that its health counts the threads leaked by all of them.
     worker: Arc<Worker>,
     scheduler_client: Arc<Mutex<TeaclaveSchedulerClient>>,
     fusion_base: PathBuf,
+    // Signs the I/O manifests of the tasks with the attested key of the enclave.
+    attested_tls_config: Arc<RwLock<AttestedTlsConfig>>,
     id: Uuid,
     status: ExecutorStatus,
 }
@@ -47,6 +69,7 @@ impl TeaclaveExecutionService {
     pub(crate) fn new(
         scheduler_service_endpoint: Endpoint,
         fusion_base: impl AsRef<Path>,
+        attested_tls_config: Arc<RwLock<AttestedTlsConfig>>,
     ) -> Result<Self> {
         let mut i = 0;
         let channel = loop {
@@ -61,11 +84,21 @@ impl TeaclaveExecutionService {
             std::thread::sleep(std::time::Duration::from_secs(3));
         };
         let scheduler_client = Arc::new(Mutex::new(TeaclaveSchedulerClient::new(channel)?));
+        let kv_space = KvSpace::open_sealed(KV_BASE_DIR, KV_SALT)?;
+        let worker = Worker::default()
+            .with_max_parallelism(MAX_FUNCTION_THREADS)
+            .with_memory_budget(FUNCTION_MEMORY_BUDGET)
+            .with_concurrency_limits(MAX_CONCURRENT_FUNCTIONS, FUNCTION_MEMORY_BUDGET)
+            .with_max_summary_bytes(MAX_SUMMARY_BYTES)
+            .with_default_timeouts(Some(FUNCTION_SOFT_TIMEOUT), None)
+            .with_kv_space(kv_space)
+            .with_enclave_debug_mode(teaclave_attestation::enclave_debug_mode()?);
 
         Ok(TeaclaveExecutionService {
-            worker: Arc::new(Worker::default()),
+            worker: Arc::new(worker),
             scheduler_client,
             fusion_base: fusion_base.as_ref().to_owned(),
+            attested_tls_config,
             id: Uuid::new_v4(),
             status: ExecutorStatus::Idle,
         })
@@ -75,12 +108,17 @@ impl TeaclaveExecutionService {
         let (tx, rx) = mpsc::channel();
         let mut current_task: Arc<Option<StagedTask>> = Arc::new(None);
         let mut task_handle: Option<thread::JoinHandle<()>> = None;
+        let mut cancellation = CancellationToken::new();
+        let mut progress = ExecutionProgress::new();
+        let mut reported_progress: Option<TaskProgress> = None;
 
         loop {
             std::thread::sleep(std::time::Duration::from_secs(3));
 
             match self.heartbeat() {
                 Ok(ExecutorCommand::Stop) => {
+                    // Give a running function the chance to stop at its next checkpoint.
+                    cancellation.cancel();
                     log::info!("Executor {} is stopped", self.id);
                     return Err(anyhow::anyhow!("EnclaveForceTermination"));
                 }
@@ -93,9 +131,22 @@ impl TeaclaveExecutionService {
                             let fusion_base = self.fusion_base.clone();
                             current_task = Arc::new(Some(task));
                             let task_copy = current_task.clone();
+                            cancellation = CancellationToken::new();
+                            let task_cancellation = cancellation.clone();
+                            progress = ExecutionProgress::new();
+                            reported_progress = None;
+                            let task_progress = progress.clone();
+                            let attested_tls_config = self.attested_tls_config.clone();
+                            let worker = self.worker.clone();
                             let handle = thread::spawn(move || {
-                                let result =
-                                    invoke_task(task_copy.as_ref().as_ref().unwrap(), &fusion_base);
+                                let result = invoke_task(
+                                    task_copy.as_ref().as_ref().unwrap(),
+                                    &fusion_base,
+                                    task_cancellation,
+                                    task_progress,
+                                    &attested_tls_config,
+                                    &worker,
+                                );
                                 tx_task.send(result).unwrap();
                             });
                             task_handle = Some(handle);
@@ -113,16 +164,27 @@ impl TeaclaveExecutionService {
                 _ => {}
             }
 
+            // Forward the latest progress of the function, if it changed since the last report.
+            if let (ExecutorStatus::Executing, Some(latest)) = (self.status, progress.latest()) {
+                if reported_progress.as_ref() != Some(&latest) {
+                    let task_id = current_task.as_ref().as_ref().unwrap().task_id;
+                    match self.update_task_progress(&task_id, latest.clone()) {
+                        Ok(_) => reported_progress = Some(latest),
+                        Err(e) => log::warn!("UpdateTaskProgress Error: {:?}", e),
+                    }
+                }
+            }
+
             match rx.try_recv() {
                 Ok(result) => {
                     let task_unwrapped = current_task.as_ref().as_ref().unwrap();
                     match result {
-                        Ok(_) => log::debug!(
+                        TaskResult::Ok(_) => log::debug!(
                             "InvokeTask: {:?}, {:?}, success",
                             task_unwrapped.task_id,
                             task_unwrapped.function_id
                         ),
-                        Err(_) => log::debug!(
+                        _ => log::debug!(
                             "InvokeTask: {:?}, {:?}, failure",
                             task_unwrapped.task_id,
                             task_unwrapped.function_id
@@ -172,10 +234,19 @@ impl TeaclaveExecutionService {
     }
 
     fn heartbeat(&mut self) -> Result<ExecutorCommand> {
-        let request = HeartbeatRequest {
-            executor_id: self.id,
-            status: self.status,
+        // Only an idle executor is offered tasks, so the busy one skips the probe.
+        let healthy = match self.status {
+            ExecutorStatus::Idle => match self.worker.probe(PROBE_TIMEOUT) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::warn!("Worker health probe failed: {:?}", e);
+                    false
+                }
+            },
+            _ => true,
         };
+        let request = HeartbeatRequest::new(self.id, self.status)
+            .health(healthy, self.worker.health().leaked_threads());
         let response = self
             .scheduler_client
             .clone()
@@ -187,12 +258,11 @@ impl TeaclaveExecutionService {
         Ok(response.command)
     }
 
-    fn update_task_result(
-        &mut self,
-        task_id: &Uuid,
-        task_result: Result<TaskOutputs>,
-    ) -> Result<()> {
-        let request = UpdateTaskResultRequest::new(*task_id, task_result);
+    fn update_task_result(&mut self, task_id: &Uuid, task_result: TaskResult) -> Result<()> {
+        let request = UpdateTaskResultRequest {
+            task_id: *task_id,
+            task_result,
+        };
 
         let _response = self
             .scheduler_client
@@ -204,6 +274,18 @@ impl TeaclaveExecutionService {
         Ok(())
     }
 
+    fn update_task_progress(&mut self, task_id: &Uuid, progress: TaskProgress) -> Result<()> {
+        let request = UpdateTaskStatusRequest::progress(task_id.to_owned(), progress);
+        let _response = self
+            .scheduler_client
+            .clone()
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Cannot lock scheduler client"))?
+            .update_task_status(request)?;
+
+        Ok(())
+    }
+
     fn update_task_status(&mut self, task_id: &Uuid, task_status: TaskStatus) -> Result<()> {
         let request = UpdateTaskStatusRequest::new(task_id.to_owned(), task_status);
         let _response = self
@@ -217,7 +299,18 @@ impl TeaclaveExecutionService {
     }
 }
 
-fn invoke_task(task: &StagedTask, fusion_base: &PathBuf) -> Result<TaskOutputs> {
+/// Runs `task` and signs its I/O manifest. The manifest and the log of the task are attached to
+/// its outputs, or to its failure, so that users and auditors see what a failed function logged,
+/// read and wrote, too. A failure also carries the counters of the execution, which otherwise
+/// come with the summary.
+fn invoke_task(
+    task: &StagedTask,
+    fusion_base: &PathBuf,
+    cancellation: CancellationToken,
+    progress: ExecutionProgress,
+    attested_tls_config: &RwLock<AttestedTlsConfig>,
+    worker: &Worker,
+) -> TaskResult {
     let save_log = task
         .function_arguments
         .get("save_log")
@@ -231,6 +324,85 @@ fn invoke_task(task: &StagedTask, fusion_base: &PathBuf) -> Result<TaskOutputs>
         log::info!(buffer = log_arc.expose_addr(); "");
     }
 
+    let io_recorder = IoManifestRecorder::new();
+    let execution_log = ExecutionLog::default();
+    let metrics = ExecutionMetrics::new();
+    let options = InvocationOptions {
+        cancellation,
+        log: execution_log.clone(),
+        progress,
+        io_recorder: io_recorder.clone(),
+        metrics: metrics.clone(),
+    };
+    let result = run_task(task, fusion_base, options, worker);
+
+    if save_log {
+        log::info!(buffer = 0; "");
+    }
+    let log = Arc::try_unwrap(log_arc)
+        .map_err(|_| anyhow::anyhow!("log buffer is referenced more than once"))
+        .and_then(|log| {
+            log.into_inner()
+                .map_err(|_| anyhow::anyhow!("log buffer is poisoned"))
+        });
+    let (result, mut log) = match log {
+        Ok(log) => (result, log),
+        Err(e) => (result.and(Err(e)), Vec::new()),
+    };
+    log.extend(execution_log.drain());
+
+    let status = match result {
+        Ok(_) => IoManifestStatus::Completed,
+        Err(_) => IoManifestStatus::Failed,
+    };
+    let io_manifest = sign_io_manifest(&task.task_id, &io_recorder, status, attested_tls_config);
+    match (result, io_manifest) {
+        (Ok((summary, outputs_tag)), Ok(io_manifest)) => TaskResult::Ok(
+            TaskOutputs::new(summary.as_bytes(), outputs_tag, log).with_io_manifest(io_manifest),
+        ),
+        (Ok(_), Err(e)) => TaskResult::Err(
+            TaskFailure::from_error(e)
+                .with_log(log)
+                .with_metrics(metrics.to_summary_metrics()),
+        ),
+        (Err(e), io_manifest) => {
+            let mut failure = TaskFailure::from_error(e)
+                .with_log(log)
+                .with_metrics(metrics.to_summary_metrics());
+            match io_manifest {
+                Ok(io_manifest) => failure = failure.with_io_manifest(io_manifest),
+                Err(e) => log::error!("Cannot sign the I/O manifest of a failed task: {:?}", e),
+            }
+            TaskResult::Err(failure)
+        }
+    }
+}
+
+fn sign_io_manifest(
+    task_id: &Uuid,
+    io_recorder: &IoManifestRecorder,
+    status: IoManifestStatus,
+    attested_tls_config: &RwLock<AttestedTlsConfig>,
+) -> Result<SignedIoManifest> {
+    let mut manifest = io_recorder.manifest();
+    manifest.task_id = *task_id;
+    manifest.status = status;
+    let attested_tls_config = attested_tls_config
+        .read()
+        .map_err(|_| anyhow::anyhow!("Cannot lock attested TLS config"))?;
+    SignedIoManifest::sign(&manifest, &attested_tls_config.cert, |manifest| {
+        attested_tls_config.sign(manifest)
+    })
+}
+
+/// Runs the function of `task` and uploads its outputs. Returns the summary of the function
+/// and the tags of the outputs.
+fn run_task(
+    task: &StagedTask,
+    fusion_base: &PathBuf,
+    options: InvocationOptions,
+    worker: &Worker,
+) -> Result<(String, HashMap<String, FileAuthTag>)> {
     let file_mgr = TaskFileManager::new(
         WORKER_BASE_DIR,
         fusion_base,
@@ -241,20 +413,10 @@ fn invoke_task(task: &StagedTask, fusion_base: &PathBuf) -> Result<TaskOutputs>
     let invocation = prepare_task(task, &file_mgr)?;
 
     log::debug!("Invoke function: {:?}", invocation);
-    let worker = Worker::default();
-    let summary = worker.invoke_function(invocation)?;
+    let summary = worker.invoke_function(invocation, options)?;
 
     let outputs_tag = finalize_task(&file_mgr)?;
-    if save_log {
-        log::info!(buffer = 0; "");
-    }
-
-    let log = Arc::try_unwrap(log_arc)
-        .map_err(|_| anyhow::anyhow!("log buffer is referenced more than once"))?
-        .into_inner()?;
-    let task_outputs = TaskOutputs::new(summary.as_bytes(), outputs_tag, log);
-
-    Ok(task_outputs)
+    Ok((summary, outputs_tag))
 }
 
 fn prepare_task(task: &StagedTask, file_mgr: &TaskFileManager) -> Result<StagedFunction> {
@@ -265,11 +427,15 @@ fn prepare_task(task: &StagedTask, file_mgr: &TaskFileManager) -> Result<StagedF
         .executor_type(task.executor_type)
         .executor(task.executor)
         .name(&task.function_name)
-        .arguments(task.function_arguments.clone())
+        .arguments(task.function_arguments.clone().without_platform_arguments())
         .payload(task.function_payload.clone())
         .input_files(input_files)
         .output_files(output_files)
         .runtime_name("default")
+        .tenant(&task.user_id)
+        .limits(task.limits)
+        .deterministic_seed(task.deterministic_seed)
+        .policy(task.policy.clone())
         .build();
     Ok(staged_function)
 }
@@ -309,11 +475,55 @@ pub mod tests {
         let invocation = prepare_task(&staged_task, &file_mgr).unwrap();
 
         let worker = Worker::default();
-        let result = worker.invoke_function(invocation);
+        let result = worker.invoke_function(invocation, InvocationOptions::default());
         if result.is_ok() {
             finalize_task(&file_mgr).unwrap();
         }
-        assert_eq!(result.unwrap(), "Hello, Teaclave!");
+        let summary: FunctionSummary = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(summary.message, "Hello, Teaclave!");
+    }
+
+    pub fn test_invoke_with_policy() {
+        let invoke = |function_name: &str, policy: ExecutionPolicy| {
+            let function_arguments =
+                FunctionArguments::from_json(json!({"message": "Hello, Teaclave!"})).unwrap();
+            let staged_task = StagedTaskBuilder::new()
+                .task_id(Uuid::new_v4())
+                .executor(Executor::Builtin)
+                .function_name(function_name)
+                .function_arguments(function_arguments)
+                .policy(policy)
+                .build();
+            let file_mgr = TaskFileManager::new(
+                WORKER_BASE_DIR,
+                "/tmp/fusion_base",
+                &staged_task.task_id,
+                &staged_task.input_data,
+                &staged_task.output_data,
+            )
+            .unwrap();
+            let invocation = prepare_task(&staged_task, &file_mgr).unwrap();
+            Worker::default().invoke_function(invocation, InvocationOptions::default())
+        };
+
+        // The policy of the staged task reaches the function.
+        let policy = ExecutionPolicy::new().redact_string("Teaclave");
+        let summary: FunctionSummary =
+            serde_json::from_str(&invoke("builtin-echo", policy).unwrap()).unwrap();
+        assert_eq!(summary.message, "Hello, [REDACTED]!");
+        assert!(summary.redacted);
+
+        // Gated builtins only run for tasks whose policy allows them.
+        let error = invoke("builtin-fuzzy-intersect", ExecutionPolicy::default()).unwrap_err();
+        assert_eq!(
+            TaskFailure::from_error(error).reason,
+            "Invalid arguments: Function disabled by the execution policy: builtin-fuzzy-intersect"
+        );
+        let policy = ExecutionPolicy::new().allow_function("builtin-fuzzy-intersect");
+        let error = invoke("builtin-fuzzy-intersect", policy).unwrap_err();
+        assert!(!TaskFailure::from_error(error)
+            .reason
+            .contains("disabled by the execution policy"));
     }
 
     pub fn test_invoke_gbdt_train() {
@@ -364,7 +574,7 @@ pub mod tests {
         let invocation = prepare_task(&staged_task, &file_mgr).unwrap();
 
         let worker = Worker::default();
-        let result = worker.invoke_function(invocation);
+        let result = worker.invoke_function(invocation, InvocationOptions::default());
         if result.is_ok() {
             finalize_task(&file_mgr).unwrap();
         }
@@ -157,6 +157,11 @@ impl InterInput {
                 StagedFileInfo::create_with_bytes(dst, &bytes)?
             }
         };
+        // The runtime checks the staged plaintext against it before the function runs.
+        let staged_file_info = match self.file.digest {
+            Some(digest) => staged_file_info.with_expected_digest(digest),
+            None => staged_file_info,
+        };
         Ok((self.funiq_key.clone(), staged_file_info))
     }
 }
@@ -323,7 +328,9 @@ pub mod tests {
             Url::parse("http://localhost:6789/fixtures/functions/gbdt_training/train.aes_gcm_128")
                 .unwrap();
         let tag = FileAuthTag::from_hex("592f1e607649d89ff2aa8a2841a57cad").unwrap();
-        let input_file = FunctionInputFile::new(input_url, tag, crypto);
+        // A digest the plaintext does not match, so that the check below fails.
+        let digest = Digest::compute(b"other training data");
+        let input_file = FunctionInputFile::new(input_url, tag, crypto).with_digest(digest);
 
         let output_url =
             Url::parse("http://localhost:6789/fixtures/functions/gbdt_training/result.aes_gcm_128")
@@ -349,6 +356,10 @@ pub mod tests {
         let sin_file = input_files.get("training_data").unwrap();
         // sout_file has random key2
         let sout_file = output_files.get("result").unwrap();
+        // The declared digest reaches the staged input.
+        assert_eq!(sin_file.expected_digest, Some(digest));
+        let error = sin_file.verify_digest("training_data").unwrap_err();
+        assert!(error.downcast_ref::<InputIntegrityError>().is_some());
         // convert sin_file to sout_file to simulate the executor's behavior
         sin_file
             .convert_to_teaclave_file(&sout_file.path, sout_file.crypto_info)
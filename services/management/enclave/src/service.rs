@@ -63,12 +63,13 @@ impl TeaclaveManagement for TeaclaveManagementService {
     ) -> TeaclaveServiceResponseResult<RegisterInputFileResponse> {
         let user_id = get_request_user_id(&request)?;
         let request = request.message;
-        let input_file = TeaclaveInputFile::new(
+        let mut input_file = TeaclaveInputFile::new(
             request.url,
             request.cmac,
             request.crypto_info,
             vec![user_id],
         );
+        input_file.digest = request.digest;
 
         self.write_to_db(&input_file)?;
 
@@ -95,12 +96,14 @@ impl TeaclaveManagement for TeaclaveManagementService {
             ManagementServiceError::PermissionDenied
         );
 
-        let input_file = TeaclaveInputFile::new(
+        // The file moved, its content and so its digest stay the same.
+        let mut input_file = TeaclaveInputFile::new(
             request.url,
             old_input_file.cmac,
             old_input_file.crypto_info,
             old_input_file.owner,
         );
+        input_file.digest = old_input_file.digest;
 
         self.write_to_db(&input_file)?;
 
@@ -335,6 +338,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
         let role = get_request_role(&request)?;
 
+        let argument_schema = argument_schema(&function);
         if function.public || role == UserRole::PlatformAdmin || function.owner == user_id {
             let response = GetFunctionResponse {
                 name: function.name,
@@ -347,6 +351,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
                 inputs: function.inputs,
                 outputs: function.outputs,
                 user_allowlist: function.user_allowlist,
+                argument_schema,
             };
 
             Ok(response)
@@ -362,6 +367,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
                 inputs: function.inputs,
                 outputs: function.outputs,
                 user_allowlist: vec![],
+                argument_schema,
             };
 
             Ok(response)
@@ -576,7 +582,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
             request.outputs_ownership,
             function,
         )
-        .map_err(|_| ManagementServiceError::InvalidTask)?;
+        .map_err(|_| ManagementServiceError::InvalidTask)?
+        .execution_policy(request.execution_policy);
 
         log::debug!("CreateTask: {:?}", task);
         let ts: TaskState = task.into();
@@ -618,6 +625,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
             assigned_outputs: ts.assigned_outputs.external_ids(),
             result: ts.result,
             status: ts.status,
+            progress: ts.progress,
         };
         Ok(response)
     }
@@ -812,9 +820,9 @@ impl TeaclaveManagement for TeaclaveManagementService {
 
                 log::debug!("Canceled Task: {:?}", task);
 
-                task.update_result(TaskResult::Err(TaskFailure {
-                    reason: "Task canceled".to_string(),
-                }))
+                task.update_result(TaskResult::Err(
+                    TaskFailure::new("Task canceled").with_category(TaskFailureCategory::Cancelled),
+                ))
                 .map_err(|_| {
                     ManagementServiceError::TaskCancelError("cannot update result".to_string())
                 })?;
@@ -1038,6 +1046,18 @@ fn get_request_role<T>(request: &Request<T>) -> Result<UserRole, ManagementServi
     Ok(UserRole::from_str(role))
 }
 
+/// JSON Schema of the arguments of a builtin function, empty if the function is not a builtin or
+/// is not known to the registry.
+fn argument_schema(function: &Function) -> String {
+    if function.executor_type != ExecutorType::Builtin {
+        return String::new();
+    }
+    teaclave_function::registry()
+        .describe(&function.name)
+        .map(|manifest| manifest.schema_json.to_string())
+        .unwrap_or_default()
+}
+
 fn create_fusion_data(owners: impl Into<OwnerList>) -> anyhow::Result<TeaclaveOutputFile> {
     let uuid = Uuid::new_v4();
     let url = format!("fusion:///TEACLAVE_FUSION_BASE/{}.fusion", uuid);
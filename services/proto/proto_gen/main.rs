@@ -92,6 +92,11 @@ pub fn get_default_config() -> prost_build::Config {
     let mut config = prost_build::Config::new();
     config.service_generator(Box::new(MesaTEEServiceGenerator));
     config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    // Fields added after the SDKs shipped, which serialized requests may leave out.
+    config.field_attribute(
+        ".teaclave_frontend_service_proto.RegisterInputFileRequest.digest",
+        "#[serde(default)]",
+    );
     config
 }
 
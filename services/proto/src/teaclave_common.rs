@@ -19,7 +19,10 @@ use crate::teaclave_common_proto as proto;
 use anyhow::{bail, Error, Result};
 use std::convert::TryInto;
 use teaclave_crypto::TeaclaveFile128Key;
-use teaclave_types::{FileCrypto, TaskFailure, TaskOutputs, TaskResult, TaskStatus};
+use teaclave_types::{
+    FileCrypto, SignedIoManifest, TaskFailure, TaskFailureCategory, TaskOutputs, TaskProgress,
+    TaskResult, TaskStatus,
+};
 
 #[derive(Debug)]
 pub struct UserCredential {
@@ -128,23 +131,92 @@ pub fn i32_from_task_status(status: TaskStatus) -> i32 {
     }
 }
 
+pub fn i32_to_task_failure_category(category: i32) -> Result<TaskFailureCategory> {
+    let ret = match proto::TaskFailureCategory::from_i32(category) {
+        Some(proto::TaskFailureCategory::Internal) => TaskFailureCategory::Internal,
+        Some(proto::TaskFailureCategory::InvalidArguments) => TaskFailureCategory::InvalidArguments,
+        Some(proto::TaskFailureCategory::InvalidInputData) => TaskFailureCategory::InvalidInputData,
+        Some(proto::TaskFailureCategory::ResourceLimit) => TaskFailureCategory::ResourceLimit,
+        Some(proto::TaskFailureCategory::Cancelled) => TaskFailureCategory::Cancelled,
+        Some(proto::TaskFailureCategory::Timeout) => TaskFailureCategory::Timeout,
+        None => bail!("invalid task failure category"),
+    };
+    Ok(ret)
+}
+
+pub fn i32_from_task_failure_category(category: TaskFailureCategory) -> i32 {
+    match category {
+        TaskFailureCategory::Internal => proto::TaskFailureCategory::Internal as i32,
+        TaskFailureCategory::InvalidArguments => {
+            proto::TaskFailureCategory::InvalidArguments as i32
+        }
+        TaskFailureCategory::InvalidInputData => {
+            proto::TaskFailureCategory::InvalidInputData as i32
+        }
+        TaskFailureCategory::ResourceLimit => proto::TaskFailureCategory::ResourceLimit as i32,
+        TaskFailureCategory::Cancelled => proto::TaskFailureCategory::Cancelled as i32,
+        TaskFailureCategory::Timeout => proto::TaskFailureCategory::Timeout as i32,
+    }
+}
+
+// Empty fields stand for no manifest.
+fn signed_io_manifest_from_proto(
+    manifest: Vec<u8>,
+    signature: Vec<u8>,
+    cert: Vec<u8>,
+) -> Option<SignedIoManifest> {
+    if manifest.is_empty() {
+        None
+    } else {
+        Some(SignedIoManifest {
+            manifest,
+            signature,
+            cert,
+        })
+    }
+}
+
 impl std::convert::TryFrom<proto::TaskOutputs> for TaskOutputs {
     type Error = Error;
     fn try_from(proto: proto::TaskOutputs) -> Result<Self> {
+        let io_manifest = signed_io_manifest_from_proto(
+            proto.io_manifest,
+            proto.io_manifest_signature,
+            proto.io_manifest_cert,
+        );
         let ret = TaskOutputs {
             return_value: proto.return_value,
             tags_map: proto.tags_map.try_into()?,
             log: proto.log,
+            io_manifest,
         };
         Ok(ret)
     }
 }
 impl std::convert::From<TaskOutputs> for proto::TaskOutputs {
     fn from(outputs: TaskOutputs) -> Self {
+        let io_manifest = outputs.io_manifest.unwrap_or_default();
         proto::TaskOutputs {
             return_value: outputs.return_value,
             tags_map: outputs.tags_map.into(),
             log: outputs.log,
+            io_manifest: io_manifest.manifest,
+            io_manifest_signature: io_manifest.signature,
+            io_manifest_cert: io_manifest.cert,
+        }
+    }
+}
+
+impl std::convert::From<proto::TaskProgress> for TaskProgress {
+    fn from(proto: proto::TaskProgress) -> Self {
+        TaskProgress::new(proto.fraction, proto.note)
+    }
+}
+impl std::convert::From<TaskProgress> for proto::TaskProgress {
+    fn from(progress: TaskProgress) -> Self {
+        proto::TaskProgress {
+            fraction: progress.fraction,
+            note: progress.note,
         }
     }
 }
@@ -154,14 +226,29 @@ impl std::convert::TryFrom<proto::TaskFailure> for TaskFailure {
     fn try_from(proto: proto::TaskFailure) -> Result<Self> {
         let ret = TaskFailure {
             reason: proto.reason,
+            category: i32_to_task_failure_category(proto.category)?,
+            io_manifest: signed_io_manifest_from_proto(
+                proto.io_manifest,
+                proto.io_manifest_signature,
+                proto.io_manifest_cert,
+            ),
+            log: proto.log,
+            metrics: proto.metrics.into_iter().collect(),
         };
         Ok(ret)
     }
 }
 impl std::convert::From<TaskFailure> for proto::TaskFailure {
     fn from(outputs: TaskFailure) -> Self {
+        let io_manifest = outputs.io_manifest.unwrap_or_default();
         proto::TaskFailure {
             reason: outputs.reason,
+            category: i32_from_task_failure_category(outputs.category),
+            io_manifest: io_manifest.manifest,
+            io_manifest_signature: io_manifest.signature,
+            io_manifest_cert: io_manifest.cert,
+            log: outputs.log,
+            metrics: outputs.metrics.into_iter().collect(),
         }
     }
 }
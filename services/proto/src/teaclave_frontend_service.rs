@@ -25,9 +25,9 @@ use core::convert::TryInto;
 use std::collections::HashMap;
 use teaclave_rpc::into_request;
 use teaclave_types::{
-    Executor, ExecutorType, ExternalID, FileAuthTag, FileCrypto, FunctionArgument,
-    FunctionArguments, FunctionBuilder, FunctionInput, FunctionOutput, OwnerList, TaskFileOwners,
-    TaskResult, TaskStatus, UserID, UserList,
+    Digest, ExecutionPolicy, Executor, ExecutorType, ExternalID, FileAuthTag, FileCrypto,
+    FunctionArgument, FunctionArguments, FunctionBuilder, FunctionInput, FunctionOutput, OwnerList,
+    TaskFileOwners, TaskProgress, TaskResult, TaskStatus, UserID, UserList,
 };
 use url::Url;
 
@@ -43,6 +43,9 @@ pub struct RegisterInputFileRequest {
     pub url: Url,
     pub cmac: FileAuthTag,
     pub crypto_info: FileCrypto,
+    /// SHA-256 of the plaintext, which the execution service checks before any function
+    /// reads the file.
+    pub digest: Option<Digest>,
 }
 
 impl RegisterInputFileRequest {
@@ -51,8 +54,14 @@ impl RegisterInputFileRequest {
             url,
             cmac,
             crypto_info: crypto.into(),
+            digest: None,
         }
     }
+
+    pub fn with_digest(mut self, digest: Digest) -> Self {
+        self.digest = Some(digest);
+        self
+    }
 }
 
 #[into_request(TeaclaveFrontendRequest::UpdateInputFile)]
@@ -540,6 +549,8 @@ pub struct GetFunctionResponse {
     pub inputs: Vec<FunctionInput>,
     pub outputs: Vec<FunctionOutput>,
     pub user_allowlist: Vec<String>,
+    /// JSON Schema of the arguments if the function is a builtin, otherwise empty.
+    pub argument_schema: String,
 }
 
 #[into_request(TeaclaveManagementRequest::GetFunctionUsageStats)]
@@ -605,6 +616,7 @@ pub struct CreateTaskRequest {
     pub executor: Executor,
     pub inputs_ownership: TaskFileOwners,
     pub outputs_ownership: TaskFileOwners,
+    pub execution_policy: ExecutionPolicy,
 }
 
 impl CreateTaskRequest {
@@ -643,6 +655,13 @@ impl CreateTaskRequest {
             ..self
         }
     }
+
+    pub fn execution_policy(self, execution_policy: ExecutionPolicy) -> Self {
+        Self {
+            execution_policy,
+            ..self
+        }
+    }
 }
 
 #[into_request(TeaclaveManagementResponse::CreateTask)]
@@ -686,6 +705,8 @@ pub struct GetTaskResponse {
     pub assigned_outputs: HashMap<String, ExternalID>,
     pub status: TaskStatus,
     pub result: TaskResult,
+    /// Latest progress reported by the function while the task runs.
+    pub progress: Option<TaskProgress>,
 }
 
 #[into_request(TeaclaveManagementRequest::AssignData)]
@@ -772,10 +793,16 @@ impl std::convert::TryFrom<proto::RegisterInputFileRequest> for RegisterInputFil
             .crypto_info
             .ok_or_else(|| anyhow!("missing crypto_info"))?
             .try_into()?;
+        let digest = if proto.digest.is_empty() {
+            None
+        } else {
+            Some(Digest::from_bytes(&proto.digest)?)
+        };
         Ok(RegisterInputFileRequest {
             url,
             cmac,
             crypto_info,
+            digest,
         })
     }
 }
@@ -786,6 +813,10 @@ impl From<RegisterInputFileRequest> for proto::RegisterInputFileRequest {
             url: request.url.as_str().to_string(),
             cmac: request.cmac.to_bytes(),
             crypto_info: Some(request.crypto_info.into()),
+            digest: request
+                .digest
+                .map(|digest| digest.as_bytes().to_vec())
+                .unwrap_or_default(),
         }
     }
 }
@@ -1514,6 +1545,7 @@ impl std::convert::TryFrom<proto::GetFunctionResponse> for GetFunctionResponse {
             inputs: inputs?,
             outputs: outputs?,
             user_allowlist: proto.user_allowlist,
+            argument_schema: proto.argument_schema,
         };
 
         Ok(ret)
@@ -1549,6 +1581,7 @@ impl From<GetFunctionResponse> for proto::GetFunctionResponse {
             inputs,
             outputs,
             user_allowlist: response.user_allowlist,
+            argument_schema: response.argument_schema,
         }
     }
 }
@@ -1603,6 +1636,11 @@ impl std::convert::TryFrom<proto::CreateTaskRequest> for CreateTaskRequest {
         let outputs_ownership = from_proto_ownership(proto.outputs_ownership);
         let function_id = proto.function_id.try_into()?;
         let executor = proto.executor.try_into()?;
+        let execution_policy = if proto.execution_policy.is_empty() {
+            ExecutionPolicy::default()
+        } else {
+            serde_json::from_str(&proto.execution_policy)?
+        };
 
         let ret = Self {
             function_id,
@@ -1610,6 +1648,7 @@ impl std::convert::TryFrom<proto::CreateTaskRequest> for CreateTaskRequest {
             executor,
             inputs_ownership,
             outputs_ownership,
+            execution_policy,
         };
         Ok(ret)
     }
@@ -1620,6 +1659,11 @@ impl From<CreateTaskRequest> for proto::CreateTaskRequest {
         let function_arguments = request.function_arguments.into_string();
         let inputs_ownership = to_proto_ownership(request.inputs_ownership);
         let outputs_ownership = to_proto_ownership(request.outputs_ownership);
+        let execution_policy = if request.execution_policy == ExecutionPolicy::default() {
+            String::new()
+        } else {
+            serde_json::to_string(&request.execution_policy).unwrap_or_default()
+        };
 
         Self {
             function_id: request.function_id.to_string(),
@@ -1627,6 +1671,7 @@ impl From<CreateTaskRequest> for proto::CreateTaskRequest {
             executor: request.executor.to_string(),
             inputs_ownership,
             outputs_ownership,
+            execution_policy,
         }
     }
 }
@@ -1718,6 +1763,7 @@ impl std::convert::TryFrom<proto::GetTaskResponse> for GetTaskResponse {
             assigned_outputs,
             status,
             result,
+            progress: proto.progress.map(TaskProgress::from),
         };
 
         Ok(ret)
@@ -1746,6 +1792,7 @@ impl From<GetTaskResponse> for proto::GetTaskResponse {
             assigned_outputs,
             status,
             result: Some(response.result.into()),
+            progress: response.progress.map(Into::into),
         }
     }
 }
@@ -31,7 +31,7 @@ pub use proto::TeaclaveSchedulerClient;
 pub use proto::TeaclaveSchedulerRequest;
 pub use proto::TeaclaveSchedulerResponse;
 use teaclave_rpc::into_request;
-use teaclave_types::{StagedTask, TaskFailure, TaskOutputs, TaskResult, TaskStatus};
+use teaclave_types::{StagedTask, TaskFailure, TaskOutputs, TaskProgress, TaskResult, TaskStatus};
 use uuid::Uuid;
 
 #[into_request(TeaclaveSchedulerRequest::Subscribe)]
@@ -57,6 +57,10 @@ pub struct PullTaskResponse {
 pub struct HeartbeatRequest {
     pub executor_id: Uuid,
     pub status: ExecutorStatus,
+    /// Set if the executor failed its health probe and must not get new tasks.
+    pub unhealthy: bool,
+    /// Function threads the executor abandoned at their hard timeout.
+    pub leaked_threads: u64,
 }
 
 impl HeartbeatRequest {
@@ -64,8 +68,16 @@ impl HeartbeatRequest {
         Self {
             executor_id,
             status,
+            unhealthy: false,
+            leaked_threads: 0,
         }
     }
+
+    pub fn health(mut self, healthy: bool, leaked_threads: u64) -> Self {
+        self.unhealthy = !healthy;
+        self.leaked_threads = leaked_threads;
+        self
+    }
 }
 
 #[into_request(TeaclaveSchedulerResponse::Heartbeat)]
@@ -96,9 +108,7 @@ impl UpdateTaskResultRequest {
     pub fn new(task_id: Uuid, task_result: Result<TaskOutputs>) -> Self {
         let result = match task_result {
             Ok(task_output) => TaskResult::Ok(task_output),
-            Err(e) => TaskResult::Err(TaskFailure {
-                reason: e.to_string(),
-            }),
+            Err(e) => TaskResult::Err(TaskFailure::from_error(e)),
         };
         Self {
             task_id,
@@ -114,6 +124,7 @@ pub struct UpdateTaskResultResponse {}
 pub struct UpdateTaskStatusRequest {
     pub task_id: Uuid,
     pub task_status: TaskStatus,
+    pub progress: Option<TaskProgress>,
 }
 
 impl UpdateTaskStatusRequest {
@@ -121,6 +132,16 @@ impl UpdateTaskStatusRequest {
         Self {
             task_id,
             task_status,
+            progress: None,
+        }
+    }
+
+    /// Reports the latest progress of the function of a running task.
+    pub fn progress(task_id: Uuid, progress: TaskProgress) -> Self {
+        Self {
+            task_id,
+            task_status: TaskStatus::Running,
+            progress: Some(progress),
         }
     }
 }
@@ -208,6 +229,8 @@ impl std::convert::TryFrom<proto::HeartbeatRequest> for HeartbeatRequest {
         let ret = Self {
             executor_id,
             status,
+            unhealthy: proto.unhealthy,
+            leaked_threads: proto.leaked_threads,
         };
         Ok(ret)
     }
@@ -219,6 +242,8 @@ impl std::convert::From<HeartbeatRequest> for proto::HeartbeatRequest {
         proto::HeartbeatRequest {
             executor_id,
             status: req.status.into(),
+            unhealthy: req.unhealthy,
+            leaked_threads: req.leaked_threads,
         }
     }
 }
@@ -281,6 +306,7 @@ impl std::convert::TryFrom<proto::UpdateTaskStatusRequest> for UpdateTaskStatusR
         let ret = Self {
             task_id: Uuid::parse_str(&proto.task_id)?,
             task_status,
+            progress: proto.progress.map(TaskProgress::from),
         };
         Ok(ret)
     }
@@ -292,6 +318,7 @@ impl std::convert::From<UpdateTaskStatusRequest> for proto::UpdateTaskStatusRequ
         proto::UpdateTaskStatusRequest {
             task_id: req.task_id.to_string(),
             task_status,
+            progress: req.progress.map(Into::into),
         }
     }
 }
@@ -206,7 +206,10 @@ impl TeaclaveSchedulerResources {
         let mut task: Task<Cancel> = ts.try_into()?;
 
         // Only TaskStatus::Running/Staged is allowed here.
-        let result_err = TaskResult::Err(TaskFailure::new("Task Canceled by the user"));
+        let result_err = TaskResult::Err(
+            TaskFailure::new("Task Canceled by the user")
+                .with_category(TaskFailureCategory::Cancelled),
+        );
 
         task.update_result(result_err)?;
 
@@ -317,7 +320,15 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
             }
         }
 
-        if !resources.task_queue.is_empty() {
+        // An executor which failed its health probe, e.g. because abandoned function threads
+        // hold all of its TCSs, would fail every task it pulls.
+        if request.message.unhealthy {
+            log::warn!(
+                "Executor {} is unhealthy with {} leaked threads, not assigning tasks",
+                executor_id,
+                request.message.leaked_threads
+            );
+        } else if !resources.task_queue.is_empty() {
             command = ExecutorCommand::NewTask;
         }
 
@@ -362,7 +373,22 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
             .map_err(|_| anyhow!("cannot lock scheduler resources"))?;
 
         let request = request.message;
-        let ts = resources.get_task_state(&request.task_id)?;
+        let mut ts = resources.get_task_state(&request.task_id)?;
+
+        // A running task only reports the progress of its function.
+        if ts.status == TaskStatus::Running {
+            if request.task_status != TaskStatus::Running {
+                return Err(anyhow!(
+                    "cannot update the status of a running task to {:?}",
+                    request.task_status
+                )
+                .into());
+            }
+            ts.progress = request.progress;
+            resources.put_into_db(&ts)?;
+            return Ok(UpdateTaskStatusResponse {});
+        }
+
         let task: Task<Run> = ts.try_into()?;
 
         log::debug!("UpdateTaskStatus: Task {:?}", task);
@@ -384,6 +410,20 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
 
         let request = request.message;
         let ts = resources.get_task_state(&request.task_id)?;
+
+        // A function stopped by its cancellation token ends up canceled rather than finished.
+        if let TaskResult::Err(failure) = &request.task_result {
+            if failure.is_cancelled() {
+                let mut task: Task<Cancel> = ts.try_into()?;
+                task.update_result(request.task_result)?;
+                log::debug!("UpdateTaskResult: Task {:?}", task);
+
+                let ts = TaskState::from(task);
+                resources.put_into_db(&ts)?;
+                return Ok(UpdateTaskResultResponse {});
+            }
+        }
+
         let mut task: Task<Finish> = ts.try_into()?;
 
         if let TaskResult::Ok(outputs) = &request.task_result {
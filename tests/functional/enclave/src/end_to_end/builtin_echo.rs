@@ -64,5 +64,6 @@ pub fn test_echo_task_success() {
 
     // Get Task
     let ret_val = get_task_until(&mut client, &task_id, TaskStatus::Finished);
-    assert_eq!(&ret_val, "Hello From Teaclave!");
+    let summary: FunctionSummary = serde_json::from_str(&ret_val).unwrap();
+    assert_eq!(summary.message, "Hello From Teaclave!");
 }
@@ -34,7 +34,8 @@ pub fn test_gbdt_training_task() {
     invoke_task(&mut client, &task_id).unwrap();
 
     let ret_val = get_task_until(&mut client, &task_id, TaskStatus::Finished);
-    assert_eq!(&ret_val, "Trained 120 lines of data.");
+    let summary: FunctionSummary = serde_json::from_str(&ret_val).unwrap();
+    assert_eq!(summary.message, "Trained 120 lines of data.");
 }
 
 // Authenticate user before talking to frontend service
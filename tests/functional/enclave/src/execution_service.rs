@@ -59,7 +59,8 @@ fn test_execute_function() {
     let get_response = storage_client.get(get_request).unwrap();
     let updated_task = TaskState::from_slice(get_response.value.as_slice()).unwrap();
     let result = updated_task.result.unwrap();
-    assert_eq!(result.return_value, b"Hello, Teaclave Tests!");
+    let summary: FunctionSummary = serde_json::from_slice(&result.return_value).unwrap();
+    assert_eq!(summary.message, "Hello, Teaclave Tests!");
     let info_log = result.log.iter().find(|l| l.contains("INFO")).unwrap();
 
     assert_eq!(
@@ -391,10 +391,7 @@ fn test_cancel_task() {
     std::thread::sleep(std::time::Duration::from_secs(5));
 
     let executor_id = Uuid::new_v4();
-    let request = HeartbeatRequest {
-        executor_id,
-        status: ExecutorStatus::Idle,
-    };
+    let request = HeartbeatRequest::new(executor_id, ExecutorStatus::Idle);
 
     let response = scheduler_client.heartbeat(request).unwrap();
     assert!(response.command == ExecutorCommand::NewTask);
@@ -456,10 +453,7 @@ fn test_fail_task() {
     std::thread::sleep(std::time::Duration::from_secs(5));
 
     let executor_id = Uuid::new_v4();
-    let request = HeartbeatRequest {
-        executor_id,
-        status: ExecutorStatus::Idle,
-    };
+    let request = HeartbeatRequest::new(executor_id, ExecutorStatus::Idle);
     let response = scheduler_client.heartbeat(request).unwrap();
     assert!(response.command == ExecutorCommand::NewTask);
 
@@ -467,10 +461,7 @@ fn test_fail_task() {
     let response = scheduler_client.pull_task(pull_task_request).unwrap();
     log::debug!("response: {:?}", response);
 
-    let request = HeartbeatRequest {
-        executor_id,
-        status: ExecutorStatus::Executing,
-    };
+    let request = HeartbeatRequest::new(executor_id, ExecutorStatus::Executing);
     let response = scheduler_client.heartbeat(request).unwrap();
     log::debug!("response: {:?}", response);
     assert!(response.command == ExecutorCommand::NoAction);
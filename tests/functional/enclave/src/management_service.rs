@@ -315,6 +315,22 @@ fn test_get_function() {
     let response = client.get_function(request);
     // mock_unauthorized_user is PlatformAdmin
     assert!(response.is_ok());
+    assert!(response.unwrap().argument_schema.is_empty());
+
+    // builtin functions come with the schema of their arguments
+    let request = RegisterFunctionRequestBuilder::new()
+        .name("builtin-echo")
+        .executor_type(ExecutorType::Builtin)
+        .public(true)
+        .arguments(vec![FunctionArgument::new("message", "", true)])
+        .build();
+    let mut client = authorized_client("mock_user");
+    let function_id = client.register_function(request).unwrap().function_id;
+    let request = GetFunctionRequest::new(function_id);
+    let response = client.get_function(request).unwrap();
+    let schema: serde_json::Value = serde_json::from_str(&response.argument_schema).unwrap();
+    assert!(schema["properties"]["message"].is_object());
+    assert!(schema["properties"]["save_log"].is_object());
 }
 
 fn create_valid_task_request() -> CreateTaskRequest {
@@ -16,12 +16,17 @@
 // under the License.
 
 use serde_json::json;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use teaclave_crypto::TeaclaveFile128Key;
 use teaclave_types::{
-    hashmap, read_all_bytes, Executor, ExecutorType, FileAuthTag, FunctionArguments,
-    StagedFileInfo, StagedFiles, StagedFunctionBuilder,
+    hashmap, read_all_bytes, Digest, ExecutionPolicy, ExecutionProgress, Executor, ExecutorType,
+    FileAuthTag, FunctionArguments, FunctionError, FunctionRuntime, FunctionSummary,
+    InputIntegrityError, StagedFileInfo, StagedFiles, StagedFunctionBuilder, TaskFailure,
+    TaskFailureCategory, TaskProgress, TeaclaveExecutor,
 };
-use teaclave_worker::Worker;
+use teaclave_worker::{InvocationOptions, Worker};
 
 fn test_start_worker() {
     let arguments = FunctionArguments::from_json(json!({
@@ -66,16 +71,316 @@ fn test_start_worker() {
 
     let worker = Worker::default();
 
-    let summary = worker.invoke_function(staged_function).unwrap();
-    assert_eq!(summary, "Trained 120 lines of data.");
+    let summary = worker
+        .invoke_function(staged_function, InvocationOptions::default())
+        .unwrap();
+    let summary: FunctionSummary = serde_json::from_str(&summary).unwrap();
+    assert_eq!(summary.message, "Trained 120 lines of data.");
 
     let result = output_info.get_plaintext().unwrap();
     let expected = read_all_bytes(expected_output).unwrap();
     assert_eq!(&result[..], &expected[..]);
 }
 
+#[derive(Default)]
+struct PanickingExecutor;
+
+impl TeaclaveExecutor for PanickingExecutor {
+    fn execute(
+        &self,
+        _name: String,
+        _arguments: FunctionArguments,
+        _payload: Vec<u8>,
+        _runtime: FunctionRuntime,
+    ) -> anyhow::Result<String> {
+        panic!("function must not run on tampered inputs");
+    }
+}
+
+fn test_tampered_input_fails_before_execution() {
+    let good = StagedFileInfo::create_with_bytes("/tmp/worker_good_input.enc", b"good")
+        .unwrap()
+        .with_expected_digest(Digest::compute(b"good"));
+    // The owner registered "original", but the staged file holds something else.
+    let tampered = StagedFileInfo::create_with_bytes("/tmp/worker_tampered_input.enc", b"tampered")
+        .unwrap()
+        .with_expected_digest(Digest::compute(b"original"));
+    let input_files = StagedFiles::new(hashmap!(
+        "good" => good,
+        "tampered" => tampered));
+
+    let staged_function = StagedFunctionBuilder::new()
+        .executor_type(ExecutorType::Builtin)
+        .executor(Executor::Builtin)
+        .name("builtin-echo")
+        .arguments(FunctionArguments::default())
+        .input_files(input_files)
+        .output_files(StagedFiles::default())
+        .runtime_name("default")
+        .build();
+
+    let mut worker = Worker::default();
+    worker.register_executor((ExecutorType::Builtin, Executor::Builtin), || {
+        Box::<PanickingExecutor>::default()
+    });
+
+    let error = worker
+        .invoke_function(staged_function, InvocationOptions::default())
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<InputIntegrityError>(),
+        Some(&InputIntegrityError {
+            key: "tampered".to_string(),
+            expected: Digest::compute(b"original"),
+            actual: Digest::compute(b"tampered"),
+        })
+    );
+}
+
+#[derive(Default)]
+struct ProgressExecutor;
+
+impl TeaclaveExecutor for ProgressExecutor {
+    fn execute(
+        &self,
+        _name: String,
+        _arguments: FunctionArguments,
+        _payload: Vec<u8>,
+        runtime: FunctionRuntime,
+    ) -> anyhow::Result<String> {
+        runtime.report_progress(-1.0, "starting");
+        thread::sleep(Duration::from_millis(300));
+        runtime.report_progress(0.5, "halfway");
+        // Right after the previous report, so it is dropped.
+        runtime.report_progress(0.6, "too soon");
+        thread::sleep(Duration::from_millis(300));
+        runtime.report_progress(1.5, "done");
+        Ok(String::new())
+    }
+}
+
+fn wait_for_progress(progress: &ExecutionProgress, fraction: f32) -> TaskProgress {
+    let started = Instant::now();
+    loop {
+        match progress.latest() {
+            Some(latest) if latest.fraction >= fraction => return latest,
+            _ => {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+fn test_function_progress() {
+    let staged_function = StagedFunctionBuilder::new()
+        .executor_type(ExecutorType::Builtin)
+        .executor(Executor::Builtin)
+        .name("progress")
+        .arguments(FunctionArguments::default())
+        .input_files(StagedFiles::default())
+        .output_files(StagedFiles::default())
+        .runtime_name("default")
+        .build();
+
+    let mut worker = Worker::default();
+    worker.register_executor((ExecutorType::Builtin, Executor::Builtin), || {
+        Box::<ProgressExecutor>::default()
+    });
+
+    let progress = ExecutionProgress::new();
+    let function_progress = progress.clone();
+    let handle = thread::spawn(move || {
+        let options = InvocationOptions {
+            progress: function_progress,
+            ..Default::default()
+        };
+        worker.invoke_function(staged_function, options)
+    });
+
+    // The executor sees each milestone while the function is still running.
+    assert_eq!(
+        wait_for_progress(&progress, 0.0),
+        TaskProgress::new(0.0, "starting")
+    );
+    assert_eq!(
+        wait_for_progress(&progress, 0.5),
+        TaskProgress::new(0.5, "halfway")
+    );
+    handle.join().unwrap().unwrap();
+    assert_eq!(progress.latest(), Some(TaskProgress::new(1.0, "done")));
+}
+
+/// Stands in for a Python function, which returns plain text rather than a summary.
+#[derive(Default)]
+struct ScriptExecutor;
+
+impl TeaclaveExecutor for ScriptExecutor {
+    fn execute(
+        &self,
+        _name: String,
+        arguments: FunctionArguments,
+        _payload: Vec<u8>,
+        _runtime: FunctionRuntime,
+    ) -> anyhow::Result<String> {
+        let message: String = arguments.get("message")?.as_str().unwrap().to_string();
+        if arguments.get("fail").is_ok() {
+            return Err(FunctionError::invalid_input_data("records", message).into());
+        }
+        Ok(message)
+    }
+}
+
+fn test_result_postprocessing() {
+    let input = StagedFileInfo::create_with_bytes("/tmp/worker_redacted_input.enc", b"records")
+        .unwrap()
+        .with_expected_digest(Digest::compute(b"records"));
+    let cmac = input.cmac.to_hex();
+    let invocation = |executor_type, executor, name: &str, arguments| {
+        StagedFunctionBuilder::new()
+            .executor_type(executor_type)
+            .executor(executor)
+            .name(name)
+            .arguments(FunctionArguments::from_json(arguments).unwrap())
+            .input_files(StagedFiles::new(hashmap!("records" => input.clone())))
+            .output_files(StagedFiles::default())
+            .policy(ExecutionPolicy::new().redact_string("patient-0042"))
+            .runtime_name("default")
+            .build()
+    };
+    let mut worker = Worker::default()
+        .with_max_summary_bytes(256)
+        .with_redactor(Arc::new(|text: &str| -> Option<String> {
+            text.find("token=")
+                .map(|i| text[..i].to_string() + "token=***")
+        }));
+    worker.register_executor((ExecutorType::Python, Executor::MesaPy), || {
+        Box::<ScriptExecutor>::default()
+    });
+
+    // Summaries of builtins are shortened to the limit of the worker and redacted.
+    let function = invocation(
+        ExecutorType::Builtin,
+        Executor::Builtin,
+        "builtin-echo",
+        json!({ "message": format!("patient-0042 {}", "x".repeat(4096)) }),
+    );
+    let json = worker
+        .invoke_function(function, InvocationOptions::default())
+        .unwrap();
+    let summary: FunctionSummary = serde_json::from_str(&json).unwrap();
+    assert!(json.len() <= 256);
+    assert!(summary.truncated && summary.redacted);
+    assert!(summary.message.starts_with("[REDACTED] xx"));
+
+    // So are the plain results of other executors, also for the digests of the inputs.
+    let message = format!("tag {} auth token=abc123", cmac);
+    let function = invocation(
+        ExecutorType::Python,
+        Executor::MesaPy,
+        "script",
+        json!({ "message": message }),
+    );
+    let result = worker
+        .invoke_function(function, InvocationOptions::default())
+        .unwrap();
+    assert_eq!(result, "tag [REDACTED] auth token=***");
+
+    // And the reasons of their failures.
+    let message = format!("bad record patient-0042 in {}", Digest::compute(b"records"));
+    let function = invocation(
+        ExecutorType::Python,
+        Executor::MesaPy,
+        "script",
+        json!({ "message": message, "fail": true }),
+    );
+    let error = worker
+        .invoke_function(function, InvocationOptions::default())
+        .unwrap_err();
+    assert_eq!(
+        TaskFailure::from_error(error).reason,
+        "Invalid data in input records: bad record [REDACTED] in [REDACTED]"
+    );
+}
+
+fn test_worker_probe() {
+    let worker = Worker::default();
+    worker.probe(Duration::from_secs(5)).unwrap();
+    assert_eq!(worker.health().leaked_threads(), 0);
+}
+
+/// Stands in for a function which never reaches a cancellation checkpoint.
+#[derive(Default)]
+struct SleepingExecutor;
+
+impl TeaclaveExecutor for SleepingExecutor {
+    fn execute(
+        &self,
+        _name: String,
+        _arguments: FunctionArguments,
+        _payload: Vec<u8>,
+        _runtime: FunctionRuntime,
+    ) -> anyhow::Result<String> {
+        thread::sleep(Duration::from_millis(1000));
+        Ok(String::new())
+    }
+}
+
+fn test_concurrency_limits() {
+    const MIB: u64 = 1024 * 1024;
+    let function = || {
+        StagedFunctionBuilder::new()
+            .executor_type(ExecutorType::Builtin)
+            .executor(Executor::Builtin)
+            .name("sleep")
+            .arguments(FunctionArguments::default())
+            .input_files(StagedFiles::default())
+            .output_files(StagedFiles::default())
+            .runtime_name("default")
+            .build()
+    };
+    let mut worker = Worker::default()
+        .with_memory_budget(MIB)
+        .with_concurrency_limits(1, MIB)
+        .with_default_timeouts(None, Some(Duration::from_millis(100)));
+    worker.register_executor((ExecutorType::Builtin, Executor::Builtin), || {
+        Box::<SleepingExecutor>::default()
+    });
+
+    let error = worker
+        .invoke_function(function(), InvocationOptions::default())
+        .unwrap_err();
+    assert_eq!(
+        TaskFailure::from_error(error).category,
+        TaskFailureCategory::Timeout
+    );
+
+    // The abandoned function keeps its memory budget, so no other function is admitted and the
+    // worker reports itself unhealthy until it returns.
+    assert!(worker.probe(Duration::from_secs(5)).is_err());
+    let error = worker
+        .invoke_function(function(), InvocationOptions::default())
+        .unwrap_err();
+    // The refusal is an internal failure, which a retry may not run into.
+    assert!(matches!(
+        FunctionError::from(error),
+        FunctionError::Internal(source)
+            if source.to_string().starts_with("Memory budget of 1048576 bytes exceeds the 0 bytes left")
+    ));
+
+    thread::sleep(Duration::from_millis(1500));
+    worker.probe(Duration::from_secs(5)).unwrap();
+}
+
 pub fn run_tests() -> bool {
     use teaclave_test_utils::*;
 
-    run_tests!(test_start_worker)
+    run_tests!(
+        test_start_worker,
+        test_tampered_input_fails_before_execution,
+        test_function_progress,
+        test_result_postprocessing,
+        test_worker_probe,
+        test_concurrency_limits,
+    )
 }
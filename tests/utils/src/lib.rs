@@ -96,6 +96,33 @@ macro_rules! run_tests {
     }
 }
 
+/// Runs the builtin function `$function` with a `RawIoRuntime` which stages `$inputs`, a map
+/// from identifiers to contents, in memory and has an empty buffer for each of `$outputs`, a
+/// slice or array of identifiers. Builder calls on the runtime, such as
+/// `with_environment(environment)`, may follow. Evaluates to the result of the function and the
+/// contents of the output buffers.
+#[macro_export]
+macro_rules! run_builtin {
+    (
+        $function:expr, $arguments:expr, $inputs:expr, $outputs:expr
+        $(, $builder:ident($($arg:expr),*))* $(,)?
+    ) => {{
+        let arguments = teaclave_types::FunctionArguments::from_json($arguments).unwrap();
+        let outputs: ::std::collections::HashMap<String, Vec<u8>> = $outputs
+            .iter()
+            .map(|identifier: &&str| (identifier.to_string(), Vec::new()))
+            .collect();
+        let runtime = teaclave_runtime::RawIoRuntime::new(
+            teaclave_types::StagedFiles::from_memory($inputs),
+            teaclave_types::StagedFiles::from_memory(outputs),
+        )
+        $(.$builder($($arg),*))*;
+        let buffers = runtime.output_buffers();
+        let result = $function.run(arguments, Box::new(runtime));
+        (result, buffers.into_inner())
+    }};
+}
+
 pub fn test_start() {
     println!("\nstart running tests");
 }
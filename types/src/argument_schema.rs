@@ -0,0 +1,245 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::PLATFORM_ARGUMENTS;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// JSON type of an argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgumentType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+/// What a builtin accepts for one argument.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArgumentSpec {
+    pub name: String,
+    /// JSON type of the value, or `None` for any value.
+    pub ty: Option<ArgumentType>,
+    pub required: bool,
+    #[serde(default)]
+    pub description: String,
+    /// The only values accepted, if not empty.
+    #[serde(default)]
+    pub allowed: Vec<Value>,
+    /// Inclusive bounds of numbers.
+    #[serde(default)]
+    pub minimum: Option<f64>,
+    #[serde(default)]
+    pub maximum: Option<f64>,
+}
+
+impl ArgumentSpec {
+    /// An optional argument of type `ty`.
+    pub fn new(name: impl Into<String>, ty: ArgumentType) -> Self {
+        Self {
+            ty: Some(ty),
+            ..Self::any(name)
+        }
+    }
+
+    /// An optional argument taking any value.
+    pub fn any(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ty: None,
+            required: false,
+            description: String::new(),
+            allowed: Vec::new(),
+            minimum: None,
+            maximum: None,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn one_of(mut self, allowed: &[&str]) -> Self {
+        self.allowed = allowed.iter().map(|value| json!(value)).collect();
+        self
+    }
+
+    pub fn minimum(mut self, minimum: f64) -> Self {
+        self.minimum = Some(minimum);
+        self
+    }
+
+    pub fn maximum(mut self, maximum: f64) -> Self {
+        self.maximum = Some(maximum);
+        self
+    }
+
+    fn to_json_schema(&self) -> Value {
+        let mut schema = Map::new();
+        if let Some(ty) = self.ty {
+            schema.insert("type".to_string(), json!(ty));
+        }
+        if !self.description.is_empty() {
+            schema.insert("description".to_string(), json!(self.description));
+        }
+        if !self.allowed.is_empty() {
+            schema.insert("enum".to_string(), json!(self.allowed));
+        }
+        if let Some(minimum) = self.minimum {
+            schema.insert("minimum".to_string(), json!(minimum));
+        }
+        if let Some(maximum) = self.maximum {
+            schema.insert("maximum".to_string(), json!(maximum));
+        }
+        Value::Object(schema)
+    }
+}
+
+/// Machine-readable description of the arguments of a builtin, for clients which build
+/// argument sets without reading the source of the builtin.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArgumentSchema {
+    pub arguments: Vec<ArgumentSpec>,
+}
+
+impl ArgumentSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A schema which only knows the names of the arguments, all optional and of any type.
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Self {
+        Self {
+            arguments: names
+                .iter()
+                .map(|name| ArgumentSpec::any(name.as_ref()))
+                .collect(),
+        }
+    }
+
+    pub fn argument(mut self, argument: ArgumentSpec) -> Self {
+        self.arguments.push(argument);
+        self
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.arguments
+            .iter()
+            .map(|argument| argument.name.as_str())
+            .collect()
+    }
+
+    /// The schema as a draft-07 JSON Schema of the arguments object of a task. Arguments not in
+    /// the schema are rejected, except the `PLATFORM_ARGUMENTS`, which any task may set.
+    pub fn to_json_schema(&self) -> Value {
+        let mut properties: Map<String, Value> = self
+            .arguments
+            .iter()
+            .map(|argument| (argument.name.clone(), argument.to_json_schema()))
+            .collect();
+        for name in PLATFORM_ARGUMENTS {
+            properties.entry(name.to_string()).or_insert_with(|| {
+                json!({
+                    "type": ["string", "boolean"],
+                    "description": "Reserved for the platform",
+                })
+            });
+        }
+        let required: Vec<&str> = self
+            .arguments
+            .iter()
+            .filter(|argument| argument.required)
+            .map(|argument| argument.name.as_str())
+            .collect();
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false,
+        })
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_to_json_schema, test_from_names)
+    }
+
+    fn test_to_json_schema() {
+        let schema = ArgumentSchema::new()
+            .argument(
+                ArgumentSpec::new("message", ArgumentType::String)
+                    .required()
+                    .description("Text to echo"),
+            )
+            .argument(ArgumentSpec::new("order", ArgumentType::String).one_of(&["first", "sorted"]))
+            .argument(
+                ArgumentSpec::new("ratio", ArgumentType::Number)
+                    .minimum(0.0)
+                    .maximum(1.0),
+            );
+        assert_eq!(
+            schema.to_json_schema(),
+            json!({
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "type": "object",
+                "properties": {
+                    "message": {"type": "string", "description": "Text to echo"},
+                    "order": {"type": "string", "enum": ["first", "sorted"]},
+                    "ratio": {"type": "number", "minimum": 0.0, "maximum": 1.0},
+                    "save_log": {
+                        "type": ["string", "boolean"],
+                        "description": "Reserved for the platform",
+                    },
+                },
+                "required": ["message"],
+                "additionalProperties": false,
+            })
+        );
+        assert_eq!(schema.names(), vec!["message", "order", "ratio"]);
+    }
+
+    fn test_from_names() {
+        let schema = ArgumentSchema::from_names(&["a", "b"]);
+        assert_eq!(
+            schema.to_json_schema()["properties"],
+            json!({
+                "a": {},
+                "b": {},
+                "save_log": {
+                    "type": ["string", "boolean"],
+                    "description": "Reserved for the platform",
+                },
+            })
+        );
+        assert_eq!(schema.to_json_schema()["required"], json!([]));
+    }
+}
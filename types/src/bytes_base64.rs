@@ -0,0 +1,99 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Serde helper for binary fields of function arguments, for use with
+//! `#[serde(with = "teaclave_types::bytes_base64")]`. Values are serialized as base64 strings
+//! and may be given either as a base64 string or as a JSON array of numbers.
+
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json::Value;
+
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode(bytes))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    decode_value(&value).map_err(serde::de::Error::custom)
+}
+
+/// Decodes a base64 string or an array of numbers in 0..=255. Errors tell where decoding
+/// failed.
+pub(crate) fn decode_value(value: &Value) -> Result<Vec<u8>, String> {
+    match value {
+        Value::String(s) => base64::decode(s).map_err(|e| match e {
+            base64::DecodeError::InvalidByte(position, byte) => {
+                format!(
+                    "invalid base64 byte 0x{:02x} at position {}",
+                    byte, position
+                )
+            }
+            base64::DecodeError::InvalidLastSymbol(position, byte) => {
+                format!(
+                    "invalid base64 last symbol 0x{:02x} at position {}",
+                    byte, position
+                )
+            }
+            base64::DecodeError::InvalidLength => {
+                format!("invalid base64 length at position {}", s.len())
+            }
+        }),
+        Value::Array(a) => a
+            .iter()
+            .enumerate()
+            .map(|(position, v)| match v.as_u64() {
+                Some(b) if b <= u8::MAX as u64 => Ok(b as u8),
+                _ => Err(format!("element at position {} is not a byte", position)),
+            })
+            .collect(),
+        _ => Err("expected a base64 string or an array of bytes".to_string()),
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_bytes_base64_fields)
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Arguments {
+        #[serde(with = "crate::bytes_base64")]
+        key: Vec<u8>,
+    }
+
+    fn test_bytes_base64_fields() {
+        let arguments: Arguments = serde_json::from_value(json!({"key": "AAECAw=="})).unwrap();
+        assert_eq!(arguments.key, vec![0, 1, 2, 3]);
+        assert_eq!(
+            serde_json::to_value(&arguments).unwrap(),
+            json!({"key": "AAECAw=="})
+        );
+
+        let arguments: Arguments = serde_json::from_value(json!({"key": [0, 1, 2, 3]})).unwrap();
+        assert_eq!(arguments.key, vec![0, 1, 2, 3]);
+
+        let error = serde_json::from_value::<Arguments>(json!({"key": [0, 256]}))
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "element at position 1 is not a byte");
+    }
+}
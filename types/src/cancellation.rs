@@ -0,0 +1,139 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#![allow(clippy::nonstandard_macro_braces)]
+
+use crate::ResourceLimitExceeded;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "mesalock_sgx")]
+use std::untrusted::time::InstantEx;
+use thiserror::Error;
+
+/// Number of rows or iterations a long-running function processes between two checks of its
+/// cancellation token.
+pub const CANCELLATION_CHECK_INTERVAL: usize = 1024;
+
+/// Error returned by functions which stopped because their task was cancelled.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Task cancelled")]
+pub struct Cancelled;
+
+/// Shared flag which lets the worker stop a running function. Functions poll it at convenient
+/// points of their loops, so cancellation is cooperative. The same checkpoints enforce the
+/// wall time limit of the execution, if any.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<(Instant, Duration)>,
+    checkpoints: Arc<AtomicU64>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets checkpoints fail with `ResourceLimitExceeded::WallTime` once `max_wall_time` has
+    /// passed from now.
+    pub fn with_deadline(mut self, max_wall_time: Duration) -> Self {
+        self.deadline = Some((Instant::now() + max_wall_time, max_wall_time));
+        self
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Number of calls to `checkpoint` on this token and its clones.
+    pub fn checkpoints(&self) -> u64 {
+        self.checkpoints.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `Cancelled` if the task was cancelled, or with `ResourceLimitExceeded` if it
+    /// ran past its deadline.
+    pub fn checkpoint(&self) -> anyhow::Result<()> {
+        self.checkpoints.fetch_add(1, Ordering::SeqCst);
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        if let Some((deadline, max_wall_time)) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(ResourceLimitExceeded::WallTime(max_wall_time).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_cancel_running_function, test_uncancelled_function)
+    }
+
+    // Stands in for a builtin looping over its input.
+    fn count_rows(token: CancellationToken, rows: usize) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for row in 0..rows {
+            if row % CANCELLATION_CHECK_INTERVAL == 0 {
+                token.checkpoint()?;
+                thread::sleep(Duration::from_millis(1));
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn test_cancel_running_function() {
+        let token = CancellationToken::new();
+        let function_token = token.clone();
+        let handle = thread::spawn(move || count_rows(function_token, usize::MAX));
+
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+        // The function would practically never finish on its own, so returning at all means it
+        // stopped at one of its next checkpoints.
+        let result = handle.join().unwrap();
+
+        assert!(token.is_cancelled());
+        let error = result.unwrap_err();
+        assert_eq!(error.downcast_ref::<Cancelled>(), Some(&Cancelled));
+    }
+
+    fn test_uncancelled_function() {
+        let token = CancellationToken::new();
+        let function_token = token.clone();
+        let handle = thread::spawn(move || count_rows(function_token, 10_000));
+
+        assert_eq!(handle.join().unwrap().unwrap(), 10_000);
+        assert!(!token.is_cancelled());
+        assert!(token.checkpoint().is_ok());
+        // One checkpoint every CANCELLATION_CHECK_INTERVAL rows, plus the one above.
+        assert_eq!(token.checkpoints(), 11);
+    }
+}
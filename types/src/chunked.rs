@@ -0,0 +1,311 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::io;
+
+/// Chunk size used by `TeaclaveRuntime::copy_input_to_output`.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Total buffer size of the readers returned by `TeaclaveRuntime::open_inputs`.
+pub const DEFAULT_INPUT_BUFFER_BUDGET: usize = 256 * 1024;
+
+/// Iterator over an input file in chunks of `chunk_size` bytes. Only the last chunk may be
+/// shorter, so functions never hold more than one chunk of the input in memory.
+pub struct ChunkedReader {
+    inner: Box<dyn io::Read>,
+    chunk_size: usize,
+    peak_buffer_size: usize,
+    done: bool,
+}
+
+impl ChunkedReader {
+    pub fn new(inner: Box<dyn io::Read>, chunk_size: usize) -> Self {
+        Self {
+            inner,
+            chunk_size: chunk_size.max(1),
+            peak_buffer_size: 0,
+            done: false,
+        }
+    }
+
+    /// The largest buffer allocated for a chunk so far.
+    pub fn peak_buffer_size(&self) -> usize {
+        self.peak_buffer_size
+    }
+
+    fn read_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut chunk = vec![0; self.chunk_size];
+        self.peak_buffer_size = self.peak_buffer_size.max(chunk.capacity());
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match self.inner.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        chunk.truncate(filled);
+        Ok(chunk)
+    }
+}
+
+impl Iterator for ChunkedReader {
+    type Item = anyhow::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_chunk() {
+            Ok(chunk) if chunk.is_empty() => {
+                self.done = true;
+                None
+            }
+            Ok(chunk) => {
+                self.done = chunk.len() < self.chunk_size;
+                Some(Ok(chunk))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+/// Writer that collects data into chunks of `chunk_size` bytes and hands each full chunk to the
+/// output file at once. The remainder is written by `flush`, which is also attempted on drop.
+pub struct ChunkedWriter {
+    inner: Box<dyn io::Write>,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    peak_buffer_size: usize,
+}
+
+impl ChunkedWriter {
+    pub fn new(inner: Box<dyn io::Write>, chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        Self {
+            inner,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            peak_buffer_size: 0,
+        }
+    }
+
+    /// The largest amount of data buffered at once so far.
+    pub fn peak_buffer_size(&self) -> usize {
+        self.peak_buffer_size
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.inner.write_all(&self.buffer)?;
+            self.buffer.clear();
+        }
+        self.inner.flush()
+    }
+}
+
+impl io::Write for ChunkedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        self.peak_buffer_size = self.peak_buffer_size.max(self.buffer.len());
+        if self.buffer.len() == self.chunk_size {
+            self.write_buffer()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()
+    }
+}
+
+impl Drop for ChunkedWriter {
+    fn drop(&mut self) {
+        let _ = self.write_buffer();
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::TeaclaveRuntime;
+    use std::collections::HashMap;
+    use std::io::{BufRead, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_chunked_reader,
+            test_chunked_writer,
+            test_copy_input_to_output,
+            test_open_inputs,
+        )
+    }
+
+    const CHUNK_SIZE: usize = 1024;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Records the largest read on the underlying input, i.e. the buffer size of the reader
+    // in front of it.
+    struct RecordingReader {
+        inner: io::Cursor<Vec<u8>>,
+        largest_read: Arc<AtomicUsize>,
+    }
+
+    impl io::Read for RecordingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.largest_read.fetch_max(buf.len(), Ordering::SeqCst);
+            self.inner.read(buf)
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryRuntime {
+        inputs: HashMap<String, Vec<u8>>,
+        outputs: HashMap<String, SharedBuffer>,
+        largest_read: Arc<AtomicUsize>,
+    }
+
+    impl TeaclaveRuntime for MemoryRuntime {
+        fn open_input(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Read>> {
+            let data = self
+                .inputs
+                .get(identifier)
+                .ok_or_else(|| anyhow::anyhow!("Invalid input file identifier."))?;
+            Ok(Box::new(RecordingReader {
+                inner: io::Cursor::new(data.clone()),
+                largest_read: self.largest_read.clone(),
+            }))
+        }
+
+        fn create_output(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Write>> {
+            let buffer = self
+                .outputs
+                .get(identifier)
+                .ok_or_else(|| anyhow::anyhow!("Invalid output file identifier"))?;
+            Ok(Box::new(buffer.clone()))
+        }
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn test_chunked_reader() {
+        let data = test_data(CHUNK_SIZE * 7 + 123);
+        let mut reader = ChunkedReader::new(Box::new(io::Cursor::new(data.clone())), CHUNK_SIZE);
+
+        let mut read = Vec::new();
+        let mut chunks = 0;
+        for chunk in &mut reader {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= CHUNK_SIZE);
+            read.extend_from_slice(&chunk);
+            chunks += 1;
+        }
+        assert_eq!(read, data);
+        assert_eq!(chunks, 8);
+        assert_eq!(reader.peak_buffer_size(), CHUNK_SIZE);
+    }
+
+    fn test_chunked_writer() {
+        let data = test_data(CHUNK_SIZE * 7 + 123);
+        let output = SharedBuffer::default();
+        let mut writer = ChunkedWriter::new(Box::new(output.clone()), CHUNK_SIZE);
+
+        let mut total = 0;
+        for piece in data.chunks(700) {
+            writer.write_all(piece).unwrap();
+            total += piece.len();
+            // Full chunks are handed to the output as soon as they are complete.
+            let written = output.0.lock().unwrap().len();
+            assert_eq!(written, total / CHUNK_SIZE * CHUNK_SIZE);
+        }
+        assert_eq!(output.0.lock().unwrap().len(), CHUNK_SIZE * 7);
+        writer.flush().unwrap();
+        assert_eq!(*output.0.lock().unwrap(), data);
+        assert_eq!(writer.peak_buffer_size(), CHUNK_SIZE);
+    }
+
+    fn test_copy_input_to_output() {
+        let data = test_data(DEFAULT_CHUNK_SIZE * 3 + 123);
+        let output = SharedBuffer::default();
+        let mut runtime = MemoryRuntime::default();
+        runtime.inputs.insert("input".to_string(), data.clone());
+        runtime.outputs.insert("output".to_string(), output.clone());
+
+        let mut largest_chunk = 0;
+        let written = runtime
+            .copy_input_to_output("input", "output", &mut |chunk| {
+                largest_chunk = largest_chunk.max(chunk.len());
+                chunk.iter_mut().for_each(|b| *b ^= 0x5a);
+            })
+            .unwrap();
+
+        let expected: Vec<u8> = data.iter().map(|b| b ^ 0x5a).collect();
+        assert_eq!(written, data.len() as u64);
+        assert_eq!(*output.0.lock().unwrap(), expected);
+        assert!(largest_chunk <= DEFAULT_CHUNK_SIZE);
+    }
+
+    fn test_open_inputs() {
+        let mut runtime = MemoryRuntime::default();
+        let lines: Vec<String> = (0..10_000).map(|i| format!("line {}", i)).collect();
+        for name in &["a", "b", "c"] {
+            runtime
+                .inputs
+                .insert(name.to_string(), lines.join("\n").into_bytes());
+        }
+
+        let readers = runtime
+            .open_inputs_with_budget(&["a", "b", "c"], 3 * CHUNK_SIZE)
+            .unwrap();
+        assert_eq!(readers.len(), 3);
+        // The readers are independent: each reads its own input from the start, no matter how
+        // far the others got.
+        let mut readers: Vec<_> = readers.into_iter().map(|r| r.lines()).collect();
+        for line in &lines {
+            for reader in &mut readers {
+                assert_eq!(&reader.next().unwrap().unwrap(), line);
+            }
+        }
+        assert!(readers.iter_mut().all(|reader| reader.next().is_none()));
+        assert_eq!(runtime.largest_read.load(Ordering::SeqCst), CHUNK_SIZE);
+
+        assert!(runtime.open_inputs(&["a", "missing"]).is_err());
+    }
+}
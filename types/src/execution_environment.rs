@@ -0,0 +1,78 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use serde::{Deserialize, Serialize};
+
+/// What a function runs on, as configured by the worker for one execution. Runtimes fix the
+/// environment when they are built, so every call of `TeaclaveRuntime::environment` during an
+/// execution returns the same values.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExecutionEnvironment {
+    /// Largest number of threads the function may use.
+    pub max_parallelism: usize,
+    /// Bytes of memory the function may use for its own buffers, if the worker sets a budget.
+    pub memory_budget_bytes: Option<u64>,
+    /// Whether the randomness of the function is seeded, so that its results are reproducible.
+    pub deterministic: bool,
+    /// Version of the platform running the function.
+    pub platform_version: String,
+    /// Whether the enclave runs in debug mode, in which its memory is not confidential.
+    pub enclave_debug_mode: bool,
+}
+
+impl Default for ExecutionEnvironment {
+    fn default() -> Self {
+        Self {
+            max_parallelism: 1,
+            memory_budget_bytes: None,
+            deterministic: false,
+            platform_version: String::new(),
+            enclave_debug_mode: false,
+        }
+    }
+}
+
+impl ExecutionEnvironment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_parallelism(mut self, threads: usize) -> Self {
+        self.max_parallelism = threads.max(1);
+        self
+    }
+
+    pub fn memory_budget_bytes(mut self, bytes: Option<u64>) -> Self {
+        self.memory_budget_bytes = bytes;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn platform_version(mut self, version: impl ToString) -> Self {
+        self.platform_version = version.to_string();
+        self
+    }
+
+    pub fn enclave_debug_mode(mut self, debug: bool) -> Self {
+        self.enclave_debug_mode = debug;
+        self
+    }
+}
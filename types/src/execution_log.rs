@@ -0,0 +1,314 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::format;
+use std::sync::{Arc, Mutex};
+
+/// Log bytes kept per execution unless configured otherwise.
+pub const DEFAULT_MAX_LOG_BYTES: usize = 64 * 1024;
+
+/// Targets of the `log` records of functions which `capture_log_record` captures by default:
+/// those of the builtin functions. Records of the platform, e.g. of the runtime, stay out of
+/// the execution log.
+pub const DEFAULT_FUNCTION_LOG_TARGETS: &[&str] = &["teaclave_function"];
+
+type Redactor = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+thread_local! {
+    static CURRENT_LOG: RefCell<Option<ExecutionLog>> = RefCell::new(None);
+}
+
+/// Log of a single function execution, returned with its result. Holds at most `max_bytes` of
+/// log lines; older lines are dropped first and the number of dropped lines is reported when
+/// the log is drained.
+#[derive(Clone)]
+pub struct ExecutionLog {
+    buffer: Arc<Mutex<LogBuffer>>,
+    redact: Option<Redactor>,
+    targets: Arc<Vec<String>>,
+}
+
+struct LogBuffer {
+    lines: VecDeque<String>,
+    bytes: usize,
+    max_bytes: usize,
+    dropped: usize,
+}
+
+impl Default for ExecutionLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_LOG_BYTES)
+    }
+}
+
+impl fmt::Debug for ExecutionLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let buffer = self.buffer.lock().unwrap();
+        f.debug_struct("ExecutionLog")
+            .field("lines", &buffer.lines.len())
+            .field("bytes", &buffer.bytes)
+            .field("max_bytes", &buffer.max_bytes)
+            .field("dropped", &buffer.dropped)
+            .finish()
+    }
+}
+
+impl ExecutionLog {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            buffer: Arc::new(Mutex::new(LogBuffer {
+                lines: VecDeque::new(),
+                bytes: 0,
+                max_bytes,
+                dropped: 0,
+            })),
+            redact: None,
+            targets: Arc::new(
+                DEFAULT_FUNCTION_LOG_TARGETS
+                    .iter()
+                    .map(|target| target.to_string())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Captures the `log` records of the targets `targets` and their submodules instead of
+    /// those of `DEFAULT_FUNCTION_LOG_TARGETS`.
+    pub fn with_targets(mut self, targets: &[&str]) -> Self {
+        self.targets = Arc::new(targets.iter().map(|target| target.to_string()).collect());
+        self
+    }
+
+    fn captures(&self, target: &str) -> bool {
+        self.targets.iter().any(|prefix| {
+            target
+                .strip_prefix(prefix.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+        })
+    }
+
+    /// Passes every message through `redact` before it is stored, e.g. to mask data that must
+    /// not leave the enclave with the result.
+    pub fn with_redact(mut self, redact: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.redact = Some(Arc::new(redact));
+        self
+    }
+
+    pub fn log(&self, level: log::Level, message: &str) {
+        self.push(format!("[{}] {}", level, message));
+    }
+
+    fn push(&self, line: String) {
+        let mut line = match &self.redact {
+            Some(redact) => redact(&line),
+            None => line,
+        };
+        let mut buffer = self.buffer.lock().unwrap();
+        if line.len() > buffer.max_bytes {
+            let mut end = buffer.max_bytes;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            line.truncate(end);
+        }
+        buffer.bytes += line.len();
+        buffer.lines.push_back(line);
+        while buffer.bytes > buffer.max_bytes {
+            let dropped = buffer.lines.pop_front().unwrap();
+            buffer.bytes -= dropped.len();
+            buffer.dropped += 1;
+        }
+    }
+
+    /// Number of lines dropped to stay within the size limit since the last `drain`.
+    pub fn dropped(&self) -> usize {
+        self.buffer.lock().unwrap().dropped
+    }
+
+    /// Takes the logged lines, preceded by a marker line if older lines were dropped.
+    pub fn drain(&self) -> Vec<String> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let mut lines = Vec::with_capacity(buffer.lines.len() + 1);
+        if buffer.dropped > 0 {
+            lines.push(format!(
+                "[WARN] {} earlier log lines were dropped",
+                buffer.dropped
+            ));
+        }
+        lines.extend(buffer.lines.drain(..));
+        buffer.bytes = 0;
+        buffer.dropped = 0;
+        lines
+    }
+
+    /// Runs `f` with this log as the target of `capture_log_record` on the current thread, so
+    /// that `log` macros used by the function end up in its execution log. Threads spawned by
+    /// `f` are not captured.
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<ExecutionLog>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                CURRENT_LOG.with(|current| *current.borrow_mut() = previous);
+            }
+        }
+
+        let previous = CURRENT_LOG.with(|current| current.replace(Some(self.clone())));
+        let _restore = Restore(previous);
+        f()
+    }
+
+    /// The log of the execution running on the current thread, if any.
+    pub fn current() -> Option<ExecutionLog> {
+        CURRENT_LOG.with(|current| current.borrow().clone())
+    }
+}
+
+/// Adapter for `log::Log` implementations: stores `record` in the log of the execution running
+/// on the current thread and returns true, or returns false if there is none or the record is
+/// not one of the function's. Loggers must keep the records of the platform made while a
+/// function runs out of logs which reach users; see `ExecutionLog::current`.
+pub fn capture_log_record(record: &log::Record) -> bool {
+    let log = match ExecutionLog::current() {
+        Some(log) => log,
+        None => return false,
+    };
+    if !log.captures(record.target()) {
+        return false;
+    }
+    let line = match record.module_path() {
+        Some(module_path) => format!("[{} {}] {}", record.level(), module_path, record.args()),
+        None => format!("[{}] {}", record.level(), record.args()),
+    };
+    log.push(line);
+    true
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::thread;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_execution_log_truncation,
+            test_execution_log_redact,
+            test_execution_log_scope,
+            test_execution_log_targets,
+        )
+    }
+
+    fn record(message: &str, f: impl FnOnce(&log::Record)) {
+        record_of("teaclave_function::echo", message, f)
+    }
+
+    fn record_of(target: &str, message: &str, f: impl FnOnce(&log::Record)) {
+        f(&log::Record::builder()
+            .args(format_args!("{}", message))
+            .level(log::Level::Info)
+            .target(target)
+            .module_path(Some("builtin"))
+            .build())
+    }
+
+    fn test_execution_log_truncation() {
+        // Each line is "[INFO] line N", 13 bytes.
+        let log = ExecutionLog::new(40);
+        for i in 0..5 {
+            log.log(log::Level::Info, &format!("line {}", i));
+        }
+        assert_eq!(log.dropped(), 2);
+        assert_eq!(
+            log.drain(),
+            vec![
+                "[WARN] 2 earlier log lines were dropped",
+                "[INFO] line 2",
+                "[INFO] line 3",
+                "[INFO] line 4",
+            ]
+        );
+        assert!(log.drain().is_empty());
+
+        // A single line longer than the limit is cut.
+        log.log(log::Level::Warn, &"x".repeat(100));
+        assert_eq!(log.drain(), vec![format!("[WARN] {}", "x".repeat(33))]);
+    }
+
+    fn test_execution_log_redact() {
+        let log = ExecutionLog::default().with_redact(|line| line.replace("secret", "***"));
+        log.log(log::Level::Info, "password is secret");
+        assert_eq!(log.drain(), vec!["[INFO] password is ***"]);
+    }
+
+    fn test_execution_log_scope() {
+        let first = ExecutionLog::default();
+        let second = ExecutionLog::default();
+
+        record("outside", |r| assert!(!capture_log_record(r)));
+        first.scope(|| {
+            record("first", |r| assert!(capture_log_record(r)));
+            second.scope(|| record("second", |r| assert!(capture_log_record(r))));
+            record("first again", |r| assert!(capture_log_record(r)));
+        });
+        record("outside", |r| assert!(!capture_log_record(r)));
+
+        // An execution on another thread logs into its own log.
+        let third = ExecutionLog::default();
+        let handle = {
+            let third = third.clone();
+            thread::spawn(move || {
+                third.scope(|| record("third", |r| assert!(capture_log_record(r))))
+            })
+        };
+        first.scope(|| handle.join().unwrap());
+
+        assert_eq!(
+            first.drain(),
+            vec!["[INFO builtin] first", "[INFO builtin] first again"]
+        );
+        assert_eq!(second.drain(), vec!["[INFO builtin] second"]);
+        assert_eq!(third.drain(), vec!["[INFO builtin] third"]);
+    }
+
+    fn test_execution_log_targets() {
+        let log = ExecutionLog::default();
+        log.scope(|| {
+            record_of("teaclave_function", "function", |r| {
+                assert!(capture_log_record(r))
+            });
+            // Records of the platform, e.g. of the runtime opening an output, are not captured.
+            for target in &["teaclave_runtime::default", "teaclave_functions", ""] {
+                record_of(target, "platform", |r| assert!(!capture_log_record(r)));
+            }
+        });
+        assert_eq!(log.drain(), vec!["[INFO builtin] function"]);
+
+        let log = ExecutionLog::default().with_targets(&["mesapy"]);
+        log.scope(|| {
+            record_of("mesapy::script", "script", |r| {
+                assert!(capture_log_record(r))
+            });
+            record("builtin", |r| assert!(!capture_log_record(r)));
+        });
+        assert_eq!(log.drain(), vec!["[INFO builtin] script"]);
+    }
+}
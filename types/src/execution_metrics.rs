@@ -0,0 +1,346 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::{CancellationToken, RandomAccess};
+use std::collections::BTreeMap;
+use std::format;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "mesalock_sgx")]
+use std::untrusted::time::InstantEx;
+
+/// Counters of one function execution for capacity planning. The runtime wraps the inputs,
+/// outputs and scratch files it hands out, so the counters are kept without any help of the
+/// function. Clones share the counters, so the executor can read them after the runtime has
+/// been handed to the function.
+#[derive(Clone, Debug)]
+pub struct ExecutionMetrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    started: Instant,
+    input_bytes: BTreeMap<String, u64>,
+    output_bytes: BTreeMap<String, u64>,
+    scratch_bytes: BTreeMap<String, u64>,
+    peak_scratch_bytes: u64,
+    timings: BTreeMap<String, u64>,
+    cancellation: CancellationToken,
+}
+
+#[derive(Clone, Copy)]
+enum Counter {
+    Input,
+    Output,
+    Scratch,
+}
+
+impl Default for ExecutionMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExecutionMetrics {
+    /// Starts the wall clock of the execution.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MetricsState {
+                started: Instant::now(),
+                input_bytes: BTreeMap::new(),
+                output_bytes: BTreeMap::new(),
+                scratch_bytes: BTreeMap::new(),
+                peak_scratch_bytes: 0,
+                timings: BTreeMap::new(),
+                cancellation: CancellationToken::default(),
+            })),
+        }
+    }
+
+    /// Counts the checkpoints passed on `token` as the checkpoints of the execution.
+    pub fn track_cancellation(&self, token: CancellationToken) {
+        self.state.lock().unwrap().cancellation = token;
+    }
+
+    pub fn wall_time(&self) -> Duration {
+        self.state.lock().unwrap().started.elapsed()
+    }
+
+    /// Bytes read from each opened input, by identifier.
+    pub fn input_bytes(&self) -> BTreeMap<String, u64> {
+        self.state.lock().unwrap().input_bytes.clone()
+    }
+
+    /// Bytes written to each created output, by identifier.
+    pub fn output_bytes(&self) -> BTreeMap<String, u64> {
+        self.state.lock().unwrap().output_bytes.clone()
+    }
+
+    /// Largest total size of the scratch files at any one time.
+    pub fn peak_scratch_bytes(&self) -> u64 {
+        self.state.lock().unwrap().peak_scratch_bytes
+    }
+
+    /// Adds `elapsed` to the time spent in the block `label` of the function.
+    pub fn record_time(&self, label: &str, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let mut state = self.state.lock().unwrap();
+        let total = state.timings.entry(label.to_string()).or_insert(0);
+        *total = total.saturating_add(micros);
+    }
+
+    /// Microseconds spent in each block timed with `record_time`, by label.
+    pub fn timings(&self) -> BTreeMap<String, u64> {
+        self.state.lock().unwrap().timings.clone()
+    }
+
+    pub fn checkpoints(&self) -> u64 {
+        self.state.lock().unwrap().cancellation.checkpoints()
+    }
+
+    /// The counters as `FunctionSummary` metrics. Their names start with "runtime." so that
+    /// they do not clash with the metrics of the function.
+    pub fn to_summary_metrics(&self) -> BTreeMap<String, f64> {
+        let mut metrics = BTreeMap::new();
+        metrics.insert(
+            "runtime.wall_time_ms".to_string(),
+            self.wall_time().as_secs_f64() * 1000.0,
+        );
+        for (key, bytes) in self.input_bytes() {
+            metrics.insert(format!("runtime.input_bytes.{}", key), bytes as f64);
+        }
+        for (key, bytes) in self.output_bytes() {
+            metrics.insert(format!("runtime.output_bytes.{}", key), bytes as f64);
+        }
+        metrics.insert(
+            "runtime.peak_scratch_bytes".to_string(),
+            self.peak_scratch_bytes() as f64,
+        );
+        metrics.insert("runtime.checkpoints".to_string(), self.checkpoints() as f64);
+        for (label, micros) in self.timings() {
+            metrics.insert(format!("runtime.time_us.{}", label), micros as f64);
+        }
+        metrics
+    }
+
+    /// Wraps the reader of the input `identifier` so that the bytes read from it are counted.
+    pub fn count_input(&self, identifier: &str, inner: Box<dyn io::Read>) -> Box<dyn io::Read> {
+        self.open(Counter::Input, identifier);
+        Box::new(CountedFile {
+            inner,
+            identifier: identifier.to_string(),
+            counter: Counter::Input,
+            metrics: self.clone(),
+        })
+    }
+
+    /// Like `count_input`, for inputs opened for random access.
+    pub fn count_input_random_access(
+        &self,
+        identifier: &str,
+        inner: Box<dyn RandomAccess>,
+    ) -> Box<dyn RandomAccess> {
+        self.open(Counter::Input, identifier);
+        Box::new(CountedFile {
+            inner,
+            identifier: identifier.to_string(),
+            counter: Counter::Input,
+            metrics: self.clone(),
+        })
+    }
+
+    /// Wraps the writer of the output `identifier` so that the bytes written to it are counted.
+    pub fn count_output(&self, identifier: &str, inner: Box<dyn io::Write>) -> Box<dyn io::Write> {
+        self.open(Counter::Output, identifier);
+        self.counted_writer(Counter::Output, identifier, inner)
+    }
+
+    /// Wraps the writer of the scratch file `name`. Creating a scratch file again truncates
+    /// it, so its earlier content no longer counts towards the scratch usage.
+    pub fn count_scratch(&self, name: &str, inner: Box<dyn io::Write>) -> Box<dyn io::Write> {
+        self.state
+            .lock()
+            .unwrap()
+            .scratch_bytes
+            .insert(name.to_string(), 0);
+        self.counted_writer(Counter::Scratch, name, inner)
+    }
+
+    fn counted_writer(
+        &self,
+        counter: Counter,
+        identifier: &str,
+        inner: Box<dyn io::Write>,
+    ) -> Box<dyn io::Write> {
+        Box::new(CountedFile {
+            inner,
+            identifier: identifier.to_string(),
+            counter,
+            metrics: self.clone(),
+        })
+    }
+
+    /// Makes opened files show up in the counters even if nothing is read or written.
+    fn open(&self, counter: Counter, identifier: &str) {
+        let mut state = self.state.lock().unwrap();
+        let counts = match counter {
+            Counter::Input => &mut state.input_bytes,
+            Counter::Output => &mut state.output_bytes,
+            Counter::Scratch => &mut state.scratch_bytes,
+        };
+        counts.entry(identifier.to_string()).or_insert(0);
+    }
+
+    fn add(&self, counter: Counter, identifier: &str, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        let counts = match counter {
+            Counter::Input => &mut state.input_bytes,
+            Counter::Output => &mut state.output_bytes,
+            Counter::Scratch => &mut state.scratch_bytes,
+        };
+        *counts.entry(identifier.to_string()).or_insert(0) += bytes as u64;
+        if let Counter::Scratch = counter {
+            let total = state.scratch_bytes.values().sum();
+            state.peak_scratch_bytes = state.peak_scratch_bytes.max(total);
+        }
+    }
+}
+
+struct CountedFile<F: ?Sized> {
+    inner: Box<F>,
+    identifier: String,
+    counter: Counter,
+    metrics: ExecutionMetrics,
+}
+
+impl io::Read for CountedFile<dyn io::Read> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.metrics.add(self.counter, &self.identifier, n);
+        Ok(n)
+    }
+}
+
+impl RandomAccess for CountedFile<dyn RandomAccess> {
+    fn size_of(&mut self) -> io::Result<u64> {
+        self.inner.size_of()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read_at(offset, buf)?;
+        self.metrics.add(self.counter, &self.identifier, n);
+        Ok(n)
+    }
+}
+
+impl io::Write for CountedFile<dyn io::Write> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.metrics.add(self.counter, &self.identifier, n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_io_counters,
+            test_peak_scratch_bytes,
+            test_summary_metrics
+        )
+    }
+
+    fn test_io_counters() {
+        let metrics = ExecutionMetrics::new();
+        let mut input = metrics.count_input("input", Box::new(io::Cursor::new(vec![7; 100])));
+        let mut buf = [0; 30];
+        input.read_exact(&mut buf).unwrap();
+        let mut file =
+            metrics.count_input_random_access("table", Box::new(io::Cursor::new(vec![7; 100])));
+        file.read_exact_at(90, &mut buf[..10]).unwrap();
+        let mut output = metrics.count_output("output", Box::new(Vec::new()));
+        output.write_all(b"12345").unwrap();
+        metrics.count_output("unused", Box::new(Vec::new()));
+
+        assert_eq!(metrics.input_bytes()["input"], 30);
+        assert_eq!(metrics.input_bytes()["table"], 10);
+        assert_eq!(metrics.output_bytes()["output"], 5);
+        assert_eq!(metrics.output_bytes()["unused"], 0);
+        // Clones share the counters.
+        input.read_exact(&mut buf).unwrap();
+        assert_eq!(metrics.clone().input_bytes()["input"], 60);
+    }
+
+    fn test_peak_scratch_bytes() {
+        let metrics = ExecutionMetrics::new();
+        metrics
+            .count_scratch("a", Box::new(Vec::new()))
+            .write_all(&[0; 40])
+            .unwrap();
+        metrics
+            .count_scratch("b", Box::new(Vec::new()))
+            .write_all(&[0; 20])
+            .unwrap();
+        assert_eq!(metrics.peak_scratch_bytes(), 60);
+
+        // Recreating "a" truncates it; the peak stays until the usage grows past it.
+        let mut a = metrics.count_scratch("a", Box::new(Vec::new()));
+        a.write_all(&[0; 30]).unwrap();
+        assert_eq!(metrics.peak_scratch_bytes(), 60);
+        a.write_all(&[0; 30]).unwrap();
+        assert_eq!(metrics.peak_scratch_bytes(), 80);
+        // Scratch files are not outputs.
+        assert!(metrics.output_bytes().is_empty());
+    }
+
+    fn test_summary_metrics() {
+        let metrics = ExecutionMetrics::new();
+        let token = CancellationToken::new();
+        metrics.track_cancellation(token.clone());
+        for _ in 0..3 {
+            token.checkpoint().unwrap();
+        }
+        metrics
+            .count_output("output", Box::new(Vec::new()))
+            .write_all(b"abc")
+            .unwrap();
+
+        metrics.record_time("parse", Duration::from_micros(250));
+        metrics.record_time("parse", Duration::from_micros(50));
+
+        let summary = metrics.to_summary_metrics();
+        assert_eq!(summary["runtime.checkpoints"], 3.0);
+        assert_eq!(summary["runtime.time_us.parse"], 300.0);
+        assert_eq!(summary["runtime.output_bytes.output"], 3.0);
+        assert_eq!(summary["runtime.peak_scratch_bytes"], 0.0);
+        assert!(summary["runtime.wall_time_ms"] >= 0.0);
+        assert!(!summary
+            .keys()
+            .any(|key| key.starts_with("runtime.input_bytes")));
+    }
+}
@@ -0,0 +1,85 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Which gated builtins a task may run, e.g. experimental builtins for tenants who opted in.
+/// Builtins declare capability tags next to their name; the registry enables a tagged builtin
+/// only if the policy allows its name or one of its tags. Untagged builtins are not gated.
+/// Runtime capabilities, e.g. the key-value store, are granted separately from the tags, so
+/// that allowing a tag never grants a capability of the same name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExecutionPolicy {
+    #[serde(default)]
+    pub allowed_functions: BTreeSet<String>,
+    #[serde(default)]
+    pub allowed_tags: BTreeSet<String>,
+    /// Runtime capabilities granted to the function, e.g. `KV_CAPABILITY`.
+    #[serde(default)]
+    pub capabilities: BTreeSet<String>,
+    /// Largest size in bytes of the JSON summary a function may return. The worker shortens
+    /// larger summaries.
+    #[serde(default)]
+    pub max_summary_bytes: Option<usize>,
+    /// Strings which the worker redacts from the summary of a function, e.g. identifiers of
+    /// the records of the task.
+    #[serde(default)]
+    pub redacted_strings: BTreeSet<String>,
+}
+
+impl ExecutionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_function(mut self, name: impl ToString) -> Self {
+        self.allowed_functions.insert(name.to_string());
+        self
+    }
+
+    pub fn allow_tag(mut self, tag: impl ToString) -> Self {
+        self.allowed_tags.insert(tag.to_string());
+        self
+    }
+
+    pub fn grant(mut self, capability: impl ToString) -> Self {
+        self.capabilities.insert(capability.to_string());
+        self
+    }
+
+    pub fn max_summary_bytes(mut self, max_summary_bytes: usize) -> Self {
+        self.max_summary_bytes = Some(max_summary_bytes);
+        self
+    }
+
+    pub fn redact_string(mut self, string: impl ToString) -> Self {
+        self.redacted_strings.insert(string.to_string());
+        self
+    }
+
+    /// Whether the function called `name` with capability `tags` is allowed by name or by one
+    /// of its tags.
+    pub fn allows(&self, name: &str, tags: &[String]) -> bool {
+        self.allowed_functions.contains(name) || tags.iter().any(|t| self.allowed_tags.contains(t))
+    }
+
+    /// Whether the policy grants the runtime capability `capability`, e.g. `KV_CAPABILITY`.
+    pub fn grants(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
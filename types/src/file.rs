@@ -16,7 +16,7 @@
 // under the License.
 
 use crate::storage::Storable;
-use crate::{FileAuthTag, FileCrypto, OwnerList};
+use crate::{Digest, FileAuthTag, FileCrypto, OwnerList};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use url::Url;
@@ -36,6 +36,9 @@ pub struct TeaclaveInputFile {
     pub crypto_info: FileCrypto,
     pub owner: OwnerList,
     pub uuid: Uuid,
+    /// SHA-256 of the plaintext, if the owner declared one when registering the file.
+    #[serde(default)]
+    pub digest: Option<Digest>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -60,6 +63,7 @@ impl TeaclaveInputFile {
             crypto_info,
             owner: owner.into(),
             uuid: create_uuid(),
+            digest: None,
         }
     }
 
@@ -72,6 +76,7 @@ impl TeaclaveInputFile {
             crypto_info: output.crypto_info,
             owner: output.owner,
             uuid: output.uuid,
+            digest: None,
         };
         Ok(input)
     }
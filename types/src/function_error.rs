@@ -0,0 +1,244 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#![allow(clippy::nonstandard_macro_braces)]
+
+use crate::{ArgumentError, Cancelled, InputIntegrityError, ResourceLimitExceeded, TaskFailure};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Who is to blame for a failed task, and so whether retrying it can help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TaskFailureCategory {
+    InvalidArguments,
+    InvalidInputData,
+    ResourceLimit,
+    Cancelled,
+    /// The function neither finished nor stopped at a checkpoint before its hard timeout.
+    Timeout,
+    Internal,
+}
+
+impl Default for TaskFailureCategory {
+    fn default() -> Self {
+        TaskFailureCategory::Internal
+    }
+}
+
+impl TaskFailureCategory {
+    /// Failures caused by what the user submitted. Running the same task again fails the same
+    /// way.
+    pub fn is_user_error(&self) -> bool {
+        matches!(
+            self,
+            TaskFailureCategory::InvalidArguments | TaskFailureCategory::InvalidInputData
+        )
+    }
+
+    /// Failures of the platform, which a retry may not run into.
+    pub fn is_retryable(&self) -> bool {
+        *self == TaskFailureCategory::Internal
+    }
+}
+
+/// Error of a function, telling user errors apart from platform errors. Other errors convert
+/// into `Internal` unless they are one of the typed errors of the runtime, such as `Cancelled`.
+#[derive(Error, Debug)]
+pub enum FunctionError {
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
+    #[error("Invalid data in input {key}: {reason}")]
+    InvalidInputData { key: String, reason: String },
+    #[error(transparent)]
+    ResourceLimit(ResourceLimitExceeded),
+    #[error("{}", Cancelled)]
+    Cancelled,
+    /// The function was abandoned while still running, see `ExecutionLimits::hard_wall_time`.
+    #[error("Execution did not stop within the hard timeout of {0:?}")]
+    Timeout(Duration),
+    /// The message of the underlying error may reveal details of the platform, so it is only
+    /// logged and never shown to users.
+    #[error("Internal error")]
+    Internal(#[source] anyhow::Error),
+}
+
+impl FunctionError {
+    pub fn invalid_arguments(reason: impl ToString) -> Self {
+        FunctionError::InvalidArguments(reason.to_string())
+    }
+
+    pub fn invalid_input_data(key: impl ToString, reason: impl ToString) -> Self {
+        FunctionError::InvalidInputData {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn category(&self) -> TaskFailureCategory {
+        match self {
+            FunctionError::InvalidArguments(_) => TaskFailureCategory::InvalidArguments,
+            FunctionError::InvalidInputData { .. } => TaskFailureCategory::InvalidInputData,
+            FunctionError::ResourceLimit(_) => TaskFailureCategory::ResourceLimit,
+            FunctionError::Cancelled => TaskFailureCategory::Cancelled,
+            FunctionError::Timeout(_) => TaskFailureCategory::Timeout,
+            FunctionError::Internal(_) => TaskFailureCategory::Internal,
+        }
+    }
+}
+
+impl From<anyhow::Error> for FunctionError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<FunctionError>() {
+            Ok(function_error) => return function_error,
+            Err(error) => error,
+        };
+        if error.downcast_ref::<Cancelled>().is_some() {
+            return FunctionError::Cancelled;
+        }
+        if let Some(arguments) = error.downcast_ref::<ArgumentError>() {
+            return FunctionError::invalid_arguments(arguments);
+        }
+        if let Some(limit) = ResourceLimitExceeded::find(&error) {
+            return FunctionError::ResourceLimit(limit.clone());
+        }
+        if let Some(integrity) = error.downcast_ref::<InputIntegrityError>() {
+            return FunctionError::invalid_input_data(&integrity.key, "failed integrity check");
+        }
+        FunctionError::Internal(error)
+    }
+}
+
+/// Output limits surface as I/O errors from `write`, so I/O errors are classified like any other
+/// error.
+impl From<io::Error> for FunctionError {
+    fn from(error: io::Error) -> Self {
+        anyhow::Error::from(error).into()
+    }
+}
+
+impl From<ResourceLimitExceeded> for FunctionError {
+    fn from(limit: ResourceLimitExceeded) -> Self {
+        FunctionError::ResourceLimit(limit)
+    }
+}
+
+impl From<Cancelled> for FunctionError {
+    fn from(_: Cancelled) -> Self {
+        FunctionError::Cancelled
+    }
+}
+
+impl From<FunctionError> for TaskFailure {
+    fn from(error: FunctionError) -> Self {
+        if let FunctionError::Internal(e) = &error {
+            log::error!("Task failed with an internal error: {:?}", e);
+        }
+        TaskFailure {
+            reason: error.to_string(),
+            category: error.category(),
+            io_manifest: None,
+            log: Vec::new(),
+            metrics: Default::default(),
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::Digest;
+    use std::time::Duration;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_function_error_categories, test_internal_error_hidden)
+    }
+
+    fn failure(error: anyhow::Error) -> TaskFailure {
+        TaskFailure::from_error(error)
+    }
+
+    fn test_function_error_categories() {
+        let f = failure(FunctionError::invalid_arguments("missing field `message`").into());
+        assert_eq!(f.category, TaskFailureCategory::InvalidArguments);
+        assert_eq!(f.reason, "Invalid arguments: missing field `message`");
+        assert!(f.category.is_user_error());
+        assert!(!f.category.is_retryable());
+
+        let f = failure(FunctionError::invalid_input_data("input", "bad record 2").into());
+        assert_eq!(f.category, TaskFailureCategory::InvalidInputData);
+        assert_eq!(f.reason, "Invalid data in input input: bad record 2");
+
+        let integrity = InputIntegrityError {
+            key: "training_data".to_string(),
+            expected: Digest::compute(b"expected"),
+            actual: Digest::compute(b"actual"),
+        };
+        let f = failure(integrity.into());
+        assert_eq!(f.category, TaskFailureCategory::InvalidInputData);
+        assert_eq!(
+            f.reason,
+            "Invalid data in input training_data: failed integrity check"
+        );
+
+        // Output limits surface as I/O errors of the writer.
+        let limit = ResourceLimitExceeded::TotalOutputBytes(8);
+        let f = failure(io::Error::from(limit.clone()).into());
+        assert_eq!(f.category, TaskFailureCategory::ResourceLimit);
+        assert_eq!(f.reason, limit.to_string());
+        let f = TaskFailure::from(FunctionError::from(io::Error::from(limit)));
+        assert_eq!(f.category, TaskFailureCategory::ResourceLimit);
+        let f = failure(ResourceLimitExceeded::WallTime(Duration::from_secs(1)).into());
+        assert_eq!(f.category, TaskFailureCategory::ResourceLimit);
+
+        let f = failure(FunctionError::Timeout(Duration::from_secs(2)).into());
+        assert_eq!(f.category, TaskFailureCategory::Timeout);
+        assert_eq!(
+            f.reason,
+            "Execution did not stop within the hard timeout of 2s"
+        );
+        assert!(!f.category.is_user_error());
+        assert!(!f.category.is_retryable());
+
+        let f = failure(anyhow::Error::from(Cancelled).context("while reading input"));
+        assert_eq!(f.category, TaskFailureCategory::Cancelled);
+        assert!(f.is_cancelled());
+        assert!(!f.category.is_retryable());
+    }
+
+    fn test_internal_error_hidden() {
+        let error = anyhow::anyhow!("sgx_fopen_auto_key failed: /tmp/fusion_base/xyz");
+        let function_error = FunctionError::from(error);
+        assert_eq!(function_error.category(), TaskFailureCategory::Internal);
+        assert!(format!("{:?}", function_error).contains("sgx_fopen_auto_key"));
+
+        let f = TaskFailure::from(function_error);
+        assert_eq!(f.category, TaskFailureCategory::Internal);
+        assert!(f.category.is_retryable());
+        assert_eq!(f.reason, "Internal error");
+        assert!(!f.to_string().contains("sgx_fopen_auto_key"));
+
+        // Errors which went through anyhow keep their variant.
+        let wrapped = anyhow::Error::from(FunctionError::invalid_arguments("bad"));
+        assert_eq!(
+            FunctionError::from(wrapped).category(),
+            TaskFailureCategory::InvalidArguments
+        );
+    }
+}
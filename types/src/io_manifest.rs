@@ -0,0 +1,478 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::RandomAccess;
+use anyhow::Result;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Record of the files a function read and wrote, for auditors. The runtime builds it while the
+/// function runs and the executor signs it with the attestation key of the enclave, whether the
+/// task succeeded or not.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoManifest {
+    pub task_id: Uuid,
+    pub status: IoManifestStatus,
+    /// Hex encoded SHA-256 digest of the DER encoded attested cert whose key signed the
+    /// manifest. `SignedIoManifest` carries the cert itself.
+    pub attestation_cert_sha256: String,
+    /// Every staged input and output, including those the function never opened.
+    pub inputs: BTreeMap<String, InputRecord>,
+    pub outputs: BTreeMap<String, OutputRecord>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoManifestStatus {
+    Completed,
+    /// The task failed. The records show what the function read and wrote until then.
+    Failed,
+}
+
+impl Default for IoManifestStatus {
+    fn default() -> Self {
+        IoManifestStatus::Completed
+    }
+}
+
+/// What a function read from one input, the last time it opened it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputRecord {
+    pub opened: bool,
+    pub bytes: u64,
+    /// Hex encoded SHA-256 digest of the bytes read, in the order they were read. Inputs opened
+    /// for random access have none.
+    pub sha256: Option<String>,
+    /// Set unless the function read the input up to its end.
+    pub partial: bool,
+}
+
+/// What a function wrote to one output, as handed to the output file, i.e. after sealing to
+/// the recipient key of the output, if any.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputRecord {
+    pub opened: bool,
+    pub bytes: u64,
+    /// Hex encoded SHA-256 digest of the bytes written. Outputs never opened have none.
+    pub sha256: Option<String>,
+}
+
+impl IoManifest {
+    /// The serialized manifest, which is what gets signed. The encoding is canonical: compact
+    /// JSON with the fields in declaration order and the records sorted by identifier.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Serialized `IoManifest` with the signature of the enclave over exactly these bytes, and the
+/// attested cert of the enclave, whose key made the signature.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedIoManifest {
+    pub manifest: Vec<u8>,
+    /// DER encoded ECDSA signature with the key of `cert`.
+    pub signature: Vec<u8>,
+    /// DER encoded attested cert of the enclave.
+    pub cert: Vec<u8>,
+}
+
+impl SignedIoManifest {
+    /// Binds `manifest` to `cert`, serializes it and signs it with `sign`, which must use the
+    /// key of `cert`.
+    pub fn sign(
+        manifest: &IoManifest,
+        cert: &[u8],
+        sign: impl FnOnce(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<Self> {
+        let mut manifest = manifest.clone();
+        manifest.attestation_cert_sha256 = hex::encode(digest::digest(&digest::SHA256, cert));
+        let manifest = manifest.to_bytes()?;
+        let signature = sign(&manifest)?;
+        Ok(Self {
+            manifest,
+            signature,
+            cert: cert.to_vec(),
+        })
+    }
+
+    /// The manifest, after checking that it names `cert`. Only trust it after verifying the
+    /// signature with the key of `cert` and the attestation report in `cert`.
+    pub fn manifest(&self) -> Result<IoManifest> {
+        let manifest = IoManifest::from_bytes(&self.manifest)?;
+        anyhow::ensure!(
+            manifest.attestation_cert_sha256
+                == hex::encode(digest::digest(&digest::SHA256, &self.cert)),
+            "I/O manifest names another attestation cert"
+        );
+        Ok(manifest)
+    }
+}
+
+/// Builds the `IoManifest` of one execution. The runtime wraps the inputs and outputs it hands
+/// out, and the digests are updated with every read and write, so nothing needs to be read
+/// twice. Clones share the records, so the executor can take the manifest after the runtime
+/// has been handed to the function.
+#[derive(Clone, Default)]
+pub struct IoManifestRecorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    inputs: BTreeMap<String, FileRecord>,
+    outputs: BTreeMap<String, FileRecord>,
+}
+
+struct FileRecord {
+    opened: bool,
+    bytes: u64,
+    // None for inputs opened for random access.
+    digest: Option<digest::Context>,
+    complete: bool,
+}
+
+impl FileRecord {
+    fn new(digest: bool) -> Self {
+        Self {
+            opened: true,
+            bytes: 0,
+            digest: if digest {
+                Some(digest::Context::new(&digest::SHA256))
+            } else {
+                None
+            },
+            complete: false,
+        }
+    }
+
+    fn unopened() -> Self {
+        Self {
+            opened: false,
+            bytes: 0,
+            digest: None,
+            complete: false,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.bytes += data.len() as u64;
+        if let Some(context) = &mut self.digest {
+            context.update(data);
+        }
+    }
+
+    fn sha256(&self) -> Option<String> {
+        self.digest
+            .as_ref()
+            .map(|context| hex::encode(context.clone().finish()))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Input,
+    Output,
+}
+
+impl IoManifestRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the staged inputs and outputs, so that the manifest lists those the function
+    /// never opens, too.
+    pub fn declare<'a>(
+        &self,
+        inputs: impl IntoIterator<Item = &'a str>,
+        outputs: impl IntoIterator<Item = &'a str>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        for identifier in inputs {
+            state
+                .inputs
+                .entry(identifier.to_string())
+                .or_insert_with(FileRecord::unopened);
+        }
+        for identifier in outputs {
+            state
+                .outputs
+                .entry(identifier.to_string())
+                .or_insert_with(FileRecord::unopened);
+        }
+    }
+
+    /// The manifest of everything read and written so far. The caller fills in the task, the
+    /// status and the cert.
+    pub fn manifest(&self) -> IoManifest {
+        let state = self.state.lock().unwrap();
+        let inputs = state
+            .inputs
+            .iter()
+            .map(|(identifier, record)| {
+                let input = InputRecord {
+                    opened: record.opened,
+                    bytes: record.bytes,
+                    sha256: record.sha256(),
+                    partial: !record.complete,
+                };
+                (identifier.clone(), input)
+            })
+            .collect();
+        let outputs = state
+            .outputs
+            .iter()
+            .map(|(identifier, record)| {
+                let output = OutputRecord {
+                    opened: record.opened,
+                    bytes: record.bytes,
+                    sha256: record.sha256(),
+                };
+                (identifier.clone(), output)
+            })
+            .collect();
+        IoManifest {
+            inputs,
+            outputs,
+            ..Default::default()
+        }
+    }
+
+    /// Wraps the reader of the input `identifier`. Opening an input again starts its record
+    /// over.
+    pub fn record_input(&self, identifier: &str, inner: Box<dyn io::Read>) -> Box<dyn io::Read> {
+        self.open(Direction::Input, identifier, true);
+        Box::new(RecordedFile {
+            inner,
+            identifier: identifier.to_string(),
+            recorder: self.clone(),
+        })
+    }
+
+    /// Like `record_input`, for inputs opened for random access. Only the number of bytes read
+    /// is recorded, as reads in arbitrary order have no meaningful digest.
+    pub fn record_input_random_access(
+        &self,
+        identifier: &str,
+        inner: Box<dyn RandomAccess>,
+    ) -> Box<dyn RandomAccess> {
+        self.open(Direction::Input, identifier, false);
+        Box::new(RecordedFile {
+            inner,
+            identifier: identifier.to_string(),
+            recorder: self.clone(),
+        })
+    }
+
+    /// Wraps the writer of the output `identifier`.
+    pub fn record_output(&self, identifier: &str, inner: Box<dyn io::Write>) -> Box<dyn io::Write> {
+        self.open(Direction::Output, identifier, true);
+        Box::new(RecordedFile {
+            inner,
+            identifier: identifier.to_string(),
+            recorder: self.clone(),
+        })
+    }
+
+    fn open(&self, direction: Direction, identifier: &str, digest: bool) {
+        let mut state = self.state.lock().unwrap();
+        let records = match direction {
+            Direction::Input => &mut state.inputs,
+            Direction::Output => &mut state.outputs,
+        };
+        records.insert(identifier.to_string(), FileRecord::new(digest));
+    }
+
+    fn with_record(&self, direction: Direction, identifier: &str, f: impl FnOnce(&mut FileRecord)) {
+        let mut state = self.state.lock().unwrap();
+        let records = match direction {
+            Direction::Input => &mut state.inputs,
+            Direction::Output => &mut state.outputs,
+        };
+        if let Some(record) = records.get_mut(identifier) {
+            f(record);
+        }
+    }
+}
+
+struct RecordedFile<F: ?Sized> {
+    inner: Box<F>,
+    identifier: String,
+    recorder: IoManifestRecorder,
+}
+
+impl io::Read for RecordedFile<dyn io::Read> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.recorder
+            .with_record(Direction::Input, &self.identifier, |record| {
+                if n == 0 && !buf.is_empty() {
+                    record.complete = true;
+                } else {
+                    record.update(&buf[..n]);
+                }
+            });
+        Ok(n)
+    }
+}
+
+impl RandomAccess for RecordedFile<dyn RandomAccess> {
+    fn size_of(&mut self) -> io::Result<u64> {
+        self.inner.size_of()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read_at(offset, buf)?;
+        self.recorder
+            .with_record(Direction::Input, &self.identifier, |record| {
+                record.update(&buf[..n])
+            });
+        Ok(n)
+    }
+}
+
+impl io::Write for RecordedFile<dyn io::Write> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.recorder
+            .with_record(Direction::Output, &self.identifier, |record| {
+                record.update(&buf[..n])
+            });
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_io_manifest_recorder, test_signed_io_manifest)
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(digest::digest(&digest::SHA256, data))
+    }
+
+    fn test_io_manifest_recorder() {
+        let recorder = IoManifestRecorder::new();
+        recorder.declare(vec!["input", "skipped"], vec!["output", "missing"]);
+        let data: Vec<u8> = (0..100).collect();
+
+        let mut input = recorder.record_input("input", Box::new(io::Cursor::new(data.clone())));
+        let mut buf = [0; 40];
+        input.read_exact(&mut buf).unwrap();
+        let manifest = recorder.manifest();
+        assert_eq!(manifest.inputs["input"].bytes, 40);
+        assert_eq!(manifest.inputs["input"].sha256, Some(sha256(&data[..40])));
+        assert!(manifest.inputs["input"].partial);
+        // Reading the rest is not enough: the end counts once a read hits it.
+        let mut rest = [0; 60];
+        input.read_exact(&mut rest).unwrap();
+        assert!(recorder.manifest().inputs["input"].partial);
+        assert_eq!(input.read(&mut buf).unwrap(), 0);
+        let manifest = recorder.manifest();
+        assert_eq!(manifest.inputs["input"].sha256, Some(sha256(&data)));
+        assert!(!manifest.inputs["input"].partial);
+
+        let mut table =
+            recorder.record_input_random_access("table", Box::new(io::Cursor::new(data.clone())));
+        table.read_exact_at(90, &mut buf[..10]).unwrap();
+        let mut output = recorder.record_output("output", Box::new(Vec::new()));
+        output.write_all(b"abc").unwrap();
+        recorder.record_output("unused", Box::new(Vec::new()));
+
+        // Clones share the records.
+        let manifest = recorder.clone().manifest();
+        assert_eq!(
+            manifest.inputs["table"],
+            InputRecord {
+                opened: true,
+                bytes: 10,
+                sha256: None,
+                partial: true
+            }
+        );
+        assert_eq!(manifest.outputs["output"].bytes, 3);
+        assert_eq!(manifest.outputs["output"].sha256, Some(sha256(b"abc")));
+        assert_eq!(manifest.outputs["unused"].sha256, Some(sha256(b"")));
+
+        // Declared files which the function never opened are listed, too.
+        assert_eq!(
+            manifest.inputs["skipped"],
+            InputRecord {
+                opened: false,
+                bytes: 0,
+                sha256: None,
+                partial: true
+            }
+        );
+        assert!(manifest.inputs["input"].opened);
+        assert_eq!(
+            manifest.outputs["missing"],
+            OutputRecord {
+                opened: false,
+                bytes: 0,
+                sha256: None
+            }
+        );
+    }
+
+    fn test_signed_io_manifest() {
+        let recorder = IoManifestRecorder::new();
+        recorder
+            .record_output("output", Box::new(Vec::new()))
+            .write_all(b"abc")
+            .unwrap();
+        let mut manifest = recorder.manifest();
+        manifest.task_id = Uuid::new_v4();
+        manifest.status = IoManifestStatus::Failed;
+        let cert = b"attested cert";
+
+        let signed =
+            SignedIoManifest::sign(&manifest, cert, |bytes| Ok(sha256(bytes).into_bytes()))
+                .unwrap();
+        assert_eq!(signed.signature, sha256(&signed.manifest).into_bytes());
+        assert_eq!(signed.cert, cert);
+        let signed_manifest = signed.manifest().unwrap();
+        assert_eq!(signed_manifest.attestation_cert_sha256, sha256(cert));
+        assert_eq!(signed_manifest.task_id, manifest.task_id);
+        assert_eq!(signed_manifest.status, IoManifestStatus::Failed);
+        assert_eq!(signed_manifest.outputs, manifest.outputs);
+        // Signing the same manifest again gives the same bytes.
+        let again = SignedIoManifest::sign(&manifest, cert, |_| Ok(Vec::new())).unwrap();
+        assert_eq!(again.manifest, signed.manifest);
+
+        let mut swapped = signed.clone();
+        swapped.cert = b"another cert".to_vec();
+        assert!(swapped.manifest().is_err());
+        assert!(SignedIoManifest::sign(&manifest, cert, |_| anyhow::bail!("no key")).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use thiserror::Error;
+
+/// Capability a task's `ExecutionPolicy` must grant before its function may use the key-value
+/// store of its tenant.
+pub const KV_CAPABILITY: &str = "kv";
+
+/// Largest value a function may store under a single key.
+pub const DEFAULT_KV_MAX_VALUE_SIZE: usize = 64 * 1024;
+
+/// Bytes of keys and values a tenant may keep in its key-value store, over all functions.
+pub const DEFAULT_KV_QUOTA: u64 = 16 * 1024 * 1024;
+
+/// Small persistent key-value store which outlives the execution, e.g. for a counter or a
+/// cursor a function carries from one task to the next. Keys are private to the tenant and the
+/// function, and the store of a tenant is encrypted with a key derived for that tenant.
+pub trait KvStore {
+    fn get(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
+    fn put(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()>;
+    fn delete(&mut self, key: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Error returned by `TeaclaveRuntime::kv` and `KvStore` operations which the store refused.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+    #[error("The task does not grant the {} capability", KV_CAPABILITY)]
+    CapabilityDenied,
+    #[error("Value of {0} bytes exceeds the limit of {1} bytes")]
+    ValueTooLarge(usize, usize),
+    #[error("Key-value store of the tenant exceeds its quota of {0} bytes")]
+    QuotaExceeded(u64),
+}
@@ -17,43 +17,100 @@
 
 extern crate sgx_types;
 
+mod argument_schema;
 mod attestation;
+pub mod bytes_base64;
+mod cancellation;
+mod chunked;
 mod crypto;
 mod error;
+mod execution_environment;
+mod execution_log;
+mod execution_metrics;
+mod execution_policy;
 mod file;
 mod file_agent;
 mod function;
+mod function_error;
+mod io_manifest;
+mod kv;
+mod limits;
 mod macros;
+mod progress;
+mod random_access;
+mod rng;
+mod scratch;
+mod sealed_stream;
 mod staged_file;
 mod staged_function;
 mod staged_task;
+mod staging_declaration;
 mod storage;
 mod task;
 mod task_state;
+mod typed_arguments;
 mod user;
 mod worker;
 
+pub use argument_schema::*;
 pub use attestation::*;
+pub use cancellation::*;
+pub use chunked::*;
 pub use crypto::*;
 pub use error::*;
+pub use execution_environment::*;
+pub use execution_log::*;
+pub use execution_metrics::*;
+pub use execution_policy::*;
 pub use file::*;
 pub use file_agent::*;
 pub use function::*;
+pub use function_error::*;
+pub use io_manifest::*;
+pub use kv::*;
+pub use limits::*;
 pub use macros::*;
+pub use progress::*;
+pub use random_access::*;
+pub use rng::*;
+pub use scratch::*;
+pub use sealed_stream::*;
 pub use staged_file::*;
 pub use staged_function::*;
 pub use staged_task::*;
+pub use staging_declaration::*;
 pub use storage::*;
 pub use task::*;
 pub use task_state::*;
+pub use typed_arguments::*;
 pub use user::*;
 pub use worker::*;
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use teaclave_test_utils::check_all_passed;
 
     pub fn run_tests() -> bool {
-        worker::tests::run_tests()
+        check_all_passed!(
+            argument_schema::tests::run_tests(),
+            bytes_base64::tests::run_tests(),
+            cancellation::tests::run_tests(),
+            chunked::tests::run_tests(),
+            execution_log::tests::run_tests(),
+            execution_metrics::tests::run_tests(),
+            function_error::tests::run_tests(),
+            io_manifest::tests::run_tests(),
+            limits::tests::run_tests(),
+            progress::tests::run_tests(),
+            rng::tests::run_tests(),
+            scratch::tests::run_tests(),
+            sealed_stream::tests::run_tests(),
+            staged_file::tests::run_tests(),
+            staged_function::tests::run_tests(),
+            staging_declaration::tests::run_tests(),
+            typed_arguments::tests::run_tests(),
+            worker::tests::run_tests()
+        )
     }
 }
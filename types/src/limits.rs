@@ -0,0 +1,275 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#![allow(clippy::nonstandard_macro_braces)]
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Resource limits of a single function execution. `None` leaves the resource unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExecutionLimits {
+    /// Bytes the function may write to all of its outputs together.
+    pub max_total_output_bytes: Option<u64>,
+    /// Bytes the function may write to any one output.
+    pub max_single_output_bytes: Option<u64>,
+    /// Time after which the function fails at its next cancellation checkpoint.
+    pub max_wall_time: Option<Duration>,
+    /// Time after which a function which has not stopped is abandoned and fails with a timeout.
+    /// Its thread cannot be killed and keeps its TCS until the enclave restarts.
+    pub hard_wall_time: Option<Duration>,
+}
+
+impl ExecutionLimits {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn max_total_output_bytes(mut self, bytes: u64) -> Self {
+        self.max_total_output_bytes = Some(bytes);
+        self
+    }
+
+    pub fn max_single_output_bytes(mut self, bytes: u64) -> Self {
+        self.max_single_output_bytes = Some(bytes);
+        self
+    }
+
+    pub fn max_wall_time(mut self, time: Duration) -> Self {
+        self.max_wall_time = Some(time);
+        self
+    }
+
+    pub fn hard_wall_time(mut self, time: Duration) -> Self {
+        self.hard_wall_time = Some(time);
+        self
+    }
+}
+
+/// Error returned by functions which ran out of one of their `ExecutionLimits`, or of the
+/// memory budget of their `ExecutionEnvironment`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ResourceLimitExceeded {
+    #[error("Output {0} exceeds the limit of {1} bytes")]
+    SingleOutputBytes(String, u64),
+    #[error("Outputs exceed the total limit of {0} bytes")]
+    TotalOutputBytes(u64),
+    #[error("Execution exceeds the wall time limit of {0:?}")]
+    WallTime(Duration),
+    #[error("Execution exceeds the memory budget of {0} bytes")]
+    MemoryBudget(u64),
+}
+
+impl ResourceLimitExceeded {
+    /// Finds the limit violation behind a function error. Output limits surface as I/O errors
+    /// from `write`, so the error may be wrapped in an `io::Error`.
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref::<Self>().or_else(|| {
+            error
+                .downcast_ref::<io::Error>()
+                .and_then(|e| e.get_ref())
+                .and_then(|e| e.downcast_ref::<Self>())
+        })
+    }
+}
+
+impl From<ResourceLimitExceeded> for io::Error {
+    fn from(error: ResourceLimitExceeded) -> Self {
+        io::Error::new(io::ErrorKind::Other, error)
+    }
+}
+
+/// Counts the bytes written to the outputs of one execution and rejects writes beyond the
+/// output limits. A rejected write is not passed on, so an output never holds more than its
+/// budget.
+#[derive(Clone, Debug, Default)]
+pub struct OutputMeter {
+    limits: ExecutionLimits,
+    total: Arc<AtomicU64>,
+}
+
+impl OutputMeter {
+    pub fn new(limits: ExecutionLimits) -> Self {
+        Self {
+            limits,
+            total: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Bytes written to all outputs so far.
+    pub fn total_bytes(&self) -> u64 {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    /// Wraps the writer of the output `identifier` so that its writes count against the limits.
+    pub fn meter(&self, identifier: &str, inner: Box<dyn io::Write>) -> Box<dyn io::Write> {
+        if self.limits.max_total_output_bytes.is_none()
+            && self.limits.max_single_output_bytes.is_none()
+        {
+            return inner;
+        }
+        Box::new(MeteredWriter {
+            inner,
+            identifier: identifier.to_string(),
+            written: 0,
+            meter: self.clone(),
+        })
+    }
+
+    fn reserve(&self, bytes: u64) -> Result<(), ResourceLimitExceeded> {
+        let limit = match self.limits.max_total_output_bytes {
+            Some(limit) => limit,
+            None => {
+                self.total.fetch_add(bytes, Ordering::SeqCst);
+                return Ok(());
+            }
+        };
+        self.total
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                total.checked_add(bytes).filter(|&t| t <= limit)
+            })
+            .map(|_| ())
+            .map_err(|_| ResourceLimitExceeded::TotalOutputBytes(limit))
+    }
+}
+
+struct MeteredWriter {
+    inner: Box<dyn io::Write>,
+    identifier: String,
+    written: u64,
+    meter: OutputMeter,
+}
+
+impl io::Write for MeteredWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len() as u64;
+        if let Some(limit) = self.meter.limits.max_single_output_bytes {
+            if self.written + len > limit {
+                let identifier = self.identifier.clone();
+                return Err(ResourceLimitExceeded::SingleOutputBytes(identifier, limit).into());
+            }
+        }
+        self.meter.reserve(len)?;
+        self.inner.write_all(buf)?;
+        self.written += len;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::CancellationToken;
+    use std::io::Write;
+    use std::sync::Mutex;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_single_output_limit,
+            test_total_output_limit,
+            test_output_under_limit,
+            test_wall_time_limit,
+        )
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn limit_error(result: io::Result<()>) -> ResourceLimitExceeded {
+        let error = anyhow::Error::from(result.unwrap_err());
+        ResourceLimitExceeded::find(&error).unwrap().clone()
+    }
+
+    fn test_single_output_limit() {
+        let meter = OutputMeter::new(ExecutionLimits::unlimited().max_single_output_bytes(8));
+        let buffer = SharedBuffer::default();
+        let mut output = meter.meter("output", Box::new(buffer.clone()));
+
+        output.write_all(b"12345").unwrap();
+        assert_eq!(
+            limit_error(output.write_all(b"6789")),
+            ResourceLimitExceeded::SingleOutputBytes("output".to_string(), 8)
+        );
+        // The write crossing the limit is dropped as a whole.
+        assert_eq!(*buffer.0.lock().unwrap(), b"12345");
+    }
+
+    fn test_total_output_limit() {
+        let meter = OutputMeter::new(ExecutionLimits::unlimited().max_total_output_bytes(8));
+        let first = SharedBuffer::default();
+        let second = SharedBuffer::default();
+        let mut output1 = meter.meter("output1", Box::new(first.clone()));
+        let mut output2 = meter.meter("output2", Box::new(second.clone()));
+
+        output1.write_all(b"12345").unwrap();
+        assert_eq!(
+            limit_error(output2.write_all(b"6789")),
+            ResourceLimitExceeded::TotalOutputBytes(8)
+        );
+        output2.write_all(b"678").unwrap();
+        assert_eq!(meter.total_bytes(), 8);
+        assert_eq!(*second.0.lock().unwrap(), b"678");
+    }
+
+    fn test_output_under_limit() {
+        let limits = ExecutionLimits::unlimited()
+            .max_total_output_bytes(1024)
+            .max_single_output_bytes(1024);
+        let meter = OutputMeter::new(limits);
+        let buffer = SharedBuffer::default();
+        let mut output = meter.meter("output", Box::new(buffer.clone()));
+
+        for _ in 0..4 {
+            output.write_all(&[0x5a; 256]).unwrap();
+        }
+        output.flush().unwrap();
+        assert_eq!(*buffer.0.lock().unwrap(), vec![0x5a; 1024]);
+        assert_eq!(meter.total_bytes(), 1024);
+    }
+
+    fn test_wall_time_limit() {
+        let token = CancellationToken::new().with_deadline(Duration::from_secs(0));
+        let error = token.checkpoint().unwrap_err();
+        assert_eq!(
+            ResourceLimitExceeded::find(&error),
+            Some(&ResourceLimitExceeded::WallTime(Duration::from_secs(0)))
+        );
+
+        let token = CancellationToken::new().with_deadline(Duration::from_secs(3600));
+        assert!(token.checkpoint().is_ok());
+    }
+}
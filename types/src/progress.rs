@@ -0,0 +1,220 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "mesalock_sgx")]
+use std::untrusted::time::InstantEx;
+
+/// Shortest time between two progress updates a function gets to store. Of the updates reported
+/// faster, e.g. once per row, only the last is kept, and stored once the interval is over or the
+/// function returns. The update which completes the function is stored right away.
+pub const PROGRESS_REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Progress of a running task as last reported by its function.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TaskProgress {
+    /// Completed fraction of the work, between 0 and 1.
+    pub fraction: f32,
+    pub note: String,
+}
+
+impl TaskProgress {
+    pub fn new(fraction: f32, note: impl Into<String>) -> Self {
+        Self {
+            fraction,
+            note: note.into(),
+        }
+    }
+}
+
+/// Progress of a single function execution. Clones share the latest update, so the executor
+/// can sample it while the function is still running.
+#[derive(Clone, Debug)]
+pub struct ExecutionProgress {
+    state: Arc<Mutex<ProgressState>>,
+    min_interval: Duration,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    latest: Option<TaskProgress>,
+    /// Last update reported within the minimum interval after `latest`.
+    pending: Option<TaskProgress>,
+    reported_at: Option<Instant>,
+}
+
+impl ProgressState {
+    fn within(&self, min_interval: Duration, now: Instant) -> bool {
+        self.reported_at.map_or(false, |reported_at| {
+            now.duration_since(reported_at) < min_interval
+        })
+    }
+
+    fn store(&mut self, update: TaskProgress, now: Instant) {
+        self.latest = Some(update);
+        self.pending = None;
+        self.reported_at = Some(now);
+    }
+}
+
+impl Default for ExecutionProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExecutionProgress {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ProgressState::default())),
+            min_interval: PROGRESS_REPORT_INTERVAL,
+        }
+    }
+
+    /// Replaces `PROGRESS_REPORT_INTERVAL` as the shortest time between two stored updates.
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Stores `fraction` and `note` as the latest progress. If the previous update is less than
+    /// the minimum interval old, the update is kept aside instead, replacing the one kept before,
+    /// until `latest` is sampled after the interval or `flush` is called. Fractions outside of
+    /// [0, 1] are clamped rather than rejected, so a rounding error never fails a function.
+    pub fn report(&self, fraction: f32, note: &str) {
+        let fraction = if fraction.is_nan() {
+            log::debug!("Progress fraction NaN treated as 0");
+            0.0
+        } else if !(0.0..=1.0).contains(&fraction) {
+            let clamped = fraction.clamp(0.0, 1.0);
+            log::debug!("Progress fraction {} clamped to {}", fraction, clamped);
+            clamped
+        } else {
+            fraction
+        };
+
+        let update = TaskProgress::new(fraction, note.to_string());
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if fraction < 1.0 && state.within(self.min_interval, now) {
+            state.pending = Some(update);
+            return;
+        }
+        state.store(update, now);
+    }
+
+    /// The latest stored update, if the function reported any. An update kept aside by the rate
+    /// limit is stored first if the interval is over by now.
+    pub fn latest(&self) -> Option<TaskProgress> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if !state.within(self.min_interval, now) {
+            if let Some(pending) = state.pending.take() {
+                state.store(pending, now);
+            }
+        }
+        state.latest.clone()
+    }
+
+    /// Stores the update kept aside by the rate limit, if any, regardless of the interval. The
+    /// worker calls it when the function returns, so its last report is never lost.
+    pub fn flush(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.pending.take() {
+            state.store(pending, Instant::now());
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::thread;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_latest_progress,
+            test_progress_clamping,
+            test_progress_rate_limit,
+            test_progress_next_tick,
+        )
+    }
+
+    fn test_latest_progress() {
+        let progress = ExecutionProgress::new().with_min_interval(Duration::ZERO);
+        assert_eq!(progress.latest(), None);
+
+        let function_progress = progress.clone();
+        thread::spawn(move || {
+            function_progress.report(0.25, "loaded");
+            function_progress.report(0.5, "sorted");
+            function_progress.report(0.75, "merged");
+        })
+        .join()
+        .unwrap();
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.75, "merged")));
+    }
+
+    fn test_progress_clamping() {
+        let progress = ExecutionProgress::new().with_min_interval(Duration::ZERO);
+        progress.report(-0.5, "before start");
+        assert_eq!(
+            progress.latest(),
+            Some(TaskProgress::new(0.0, "before start"))
+        );
+        progress.report(1.5, "past the end");
+        assert_eq!(
+            progress.latest(),
+            Some(TaskProgress::new(1.0, "past the end"))
+        );
+        progress.report(f32::NAN, "unknown");
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.0, "unknown")));
+    }
+
+    fn test_progress_rate_limit() {
+        let progress = ExecutionProgress::new().with_min_interval(Duration::from_secs(3600));
+        for row in 0..1000 {
+            progress.report(row as f32 / 1000.0, "row");
+        }
+        // Only the first update of the interval is stored ...
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.0, "row")));
+        // ... and the last one is kept until the function returns ...
+        progress.flush();
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.999, "row")));
+        // ... but completion is stored right away.
+        progress.report(1.0, "done");
+        assert_eq!(progress.latest(), Some(TaskProgress::new(1.0, "done")));
+        progress.flush();
+        assert_eq!(progress.latest(), Some(TaskProgress::new(1.0, "done")));
+    }
+
+    fn test_progress_next_tick() {
+        let progress = ExecutionProgress::new().with_min_interval(Duration::from_millis(50));
+        progress.report(0.1, "first");
+        progress.report(0.2, "second");
+        progress.report(0.3, "third");
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.1, "first")));
+
+        // The executor samples the progress after the interval and gets the last update.
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.3, "third")));
+        assert_eq!(progress.latest(), Some(TaskProgress::new(0.3, "third")));
+    }
+}
@@ -0,0 +1,139 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+#[cfg(not(feature = "mesalock_sgx"))]
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "mesalock_sgx")]
+use std::untrusted::fs;
+
+use anyhow::Context;
+use sgx_tprotected_fs::SgxFile;
+use teaclave_crypto::TeaclaveFile128Key;
+use uuid::Uuid;
+
+/// Directory under which runtimes create the scratch directories of their executions.
+pub const DEFAULT_SCRATCH_BASE_DIR: &str = "/tmp/teaclave_scratch";
+
+/// Checks that `name` can be used as the name of a scratch file. Names are plain file names,
+/// so a function cannot reach outside the scratch space of its execution.
+pub fn check_scratch_name(name: &str) -> anyhow::Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    anyhow::ensure!(valid, "Invalid scratch file name: {:?}", name);
+    Ok(())
+}
+
+/// Temporary files of one execution. The files live in a directory of their own, are encrypted
+/// with a key which never leaves the enclave, and are deleted with the directory when the
+/// `ScratchDir` is dropped at the end of the execution.
+pub struct ScratchDir {
+    path: PathBuf,
+    key: TeaclaveFile128Key,
+}
+
+impl ScratchDir {
+    /// Reserves a fresh directory under `base_dir`. The directory is only created with the
+    /// first scratch file.
+    pub fn new(base_dir: impl AsRef<Path>) -> Self {
+        let path = base_dir
+            .as_ref()
+            .join(format!("scratch-{}", Uuid::new_v4()));
+        Self {
+            path,
+            key: TeaclaveFile128Key::random(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates the scratch file `name`, replacing any earlier file of the same name.
+    pub fn create(&self, name: &str) -> anyhow::Result<Box<dyn io::Write>> {
+        check_scratch_name(name)?;
+        fs::create_dir_all(&self.path).context("Failed to create scratch directory")?;
+        let f = SgxFile::create_with_key(self.path.join(name), self.key.key)?;
+        Ok(Box::new(f))
+    }
+
+    pub fn open(&self, name: &str) -> anyhow::Result<Box<dyn io::Read>> {
+        check_scratch_name(name)?;
+        let f = SgxFile::open_with_key(self.path.join(name), self.key.key)
+            .with_context(|| format!("Invalid scratch file name: {:?}", name))?;
+        Ok(Box::new(f))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("Failed to remove scratch directory {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_scratch_dir, test_scratch_name)
+    }
+
+    fn test_scratch_dir() {
+        let scratch = ScratchDir::new(DEFAULT_SCRATCH_BASE_DIR);
+        let other = ScratchDir::new(DEFAULT_SCRATCH_BASE_DIR);
+        assert_ne!(scratch.path(), other.path());
+        let path = scratch.path().to_path_buf();
+
+        let mut writer = scratch.create("spill").unwrap();
+        writer.write_all(b"spilled rows").unwrap();
+        drop(writer);
+        let mut content = Vec::new();
+        scratch
+            .open("spill")
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"spilled rows");
+        // Another execution cannot see the file.
+        assert!(other.open("spill").is_err());
+
+        // The file is encrypted on disk.
+        let raw = fs::read(path.join("spill")).unwrap();
+        assert!(!raw.windows(7).any(|w| w == b"spilled"));
+
+        drop(scratch);
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    fn test_scratch_name() {
+        assert!(check_scratch_name("spill-1.tmp").is_ok());
+        for name in &["", ".", "..", "../x", "a/b", "/tmp/x", ".hidden"] {
+            assert!(check_scratch_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
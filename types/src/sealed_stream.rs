@@ -0,0 +1,515 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::agreement;
+use ring::hkdf;
+use ring::rand::SystemRandom;
+use teaclave_crypto::RecipientPrivateKey;
+
+/// Version of the sealed stream format, recorded in the header of every stream and in the
+/// metadata of outputs staged with a recipient key.
+pub const SEALED_STREAM_VERSION: u8 = 1;
+
+/// Plaintext size of every chunk of a sealed stream but the last.
+pub const SEALED_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+const MAGIC: &[u8; 4] = b"TCSS";
+const HKDF_INFO: &[u8] = b"teaclave-sealed-stream-v1";
+const TAG_LENGTH: usize = 16;
+const LAST_CHUNK_FLAG: u32 = 1 << 31;
+const CURVE_P256: u8 = 1;
+const CURVE_X25519: u8 = 2;
+
+fn algorithm(curve: u8) -> anyhow::Result<&'static agreement::Algorithm> {
+    match curve {
+        CURVE_P256 => Ok(&agreement::ECDH_P256),
+        CURVE_X25519 => Ok(&agreement::X25519),
+        _ => anyhow::bail!("Unsupported sealed stream curve: {}", curve),
+    }
+}
+
+/// Recipient keys are told apart by length: 65 bytes for an uncompressed SEC1 P-256 point,
+/// 32 bytes for an X25519 key.
+fn curve_of(public_key: &[u8]) -> anyhow::Result<u8> {
+    match public_key.len() {
+        65 => Ok(CURVE_P256),
+        32 => Ok(CURVE_X25519),
+        n => anyhow::bail!("Unsupported recipient public key length: {} bytes", n),
+    }
+}
+
+fn derive_key(
+    private_key: agreement::EphemeralPrivateKey,
+    curve: u8,
+    peer_public_key: &[u8],
+) -> anyhow::Result<LessSafeKey> {
+    let peer_public_key = agreement::UnparsedPublicKey::new(algorithm(curve)?, peer_public_key);
+    agreement::agree_ephemeral(
+        private_key,
+        &peer_public_key,
+        anyhow::anyhow!("Ecdh key agreement error"),
+        |shared_secret| {
+            let mut key = [0u8; 32];
+            hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+                .extract(shared_secret)
+                .expand(&[HKDF_INFO], &aead::AES_256_GCM)
+                .and_then(|okm| okm.fill(&mut key))
+                .map_err(|_| anyhow::anyhow!("Hkdf expand error"))?;
+            let key = UnboundKey::new(&aead::AES_256_GCM, &key)
+                .map_err(|_| anyhow::anyhow!("Aead unbound key init error"))?;
+            Ok(LessSafeKey::new(key))
+        },
+    )
+}
+
+/// Every stream has a fresh key, so the nonce only needs to tell its chunks apart. The last byte
+/// marks the final chunk, so cutting a stream at a chunk boundary or flipping the final chunk
+/// flag of a length prefix does not go unnoticed.
+fn chunk_nonce(counter: u64, last: bool) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Writer which encrypts everything written to it to a recipient's public key, so that only the
+/// holder of the matching private key can read the output.
+///
+/// The stream starts with a header of magic, version, curve and a fresh ephemeral public key.
+/// The AES-256-GCM key is derived with HKDF-SHA256 from an ECDH agreement between the
+/// ephemeral key and the recipient's key. The body is a sequence of chunks of at most
+/// `SEALED_STREAM_CHUNK_SIZE` plaintext bytes, each prefixed with its big-endian u32 ciphertext
+/// length, whose top bit marks the final chunk, and authenticated together with the header.
+/// The final chunk, which may be empty, is written by `finish`, which must be called before the
+/// writer is dropped so that its errors are not lost; `flush` only flushes the complete chunks.
+pub struct SealedStreamWriter {
+    inner: Box<dyn Write>,
+    key: LessSafeKey,
+    header: Vec<u8>,
+    header_written: bool,
+    buffer: Vec<u8>,
+    counter: u64,
+    finished: bool,
+}
+
+impl SealedStreamWriter {
+    /// Seals to `recipient_public_key`, an uncompressed SEC1 encoded P-256 point or an X25519
+    /// public key.
+    pub fn new(inner: Box<dyn Write>, recipient_public_key: &[u8]) -> anyhow::Result<Self> {
+        let curve = curve_of(recipient_public_key)?;
+        let ephemeral_key =
+            agreement::EphemeralPrivateKey::generate(algorithm(curve)?, &SystemRandom::new())
+                .map_err(|_| anyhow::anyhow!("Ecdh key generation error"))?;
+        let ephemeral_public_key = ephemeral_key
+            .compute_public_key()
+            .map_err(|_| anyhow::anyhow!("Ecdh public key error"))?;
+
+        let mut header = MAGIC.to_vec();
+        header.push(SEALED_STREAM_VERSION);
+        header.push(curve);
+        header.extend_from_slice(ephemeral_public_key.as_ref());
+        let key = derive_key(ephemeral_key, curve, recipient_public_key)
+            .context("Invalid recipient public key")?;
+
+        Ok(Self {
+            inner,
+            key,
+            header,
+            header_written: false,
+            buffer: Vec::with_capacity(SEALED_STREAM_CHUNK_SIZE),
+            counter: 0,
+            finished: false,
+        })
+    }
+
+    /// Writes the final chunk. Writing after `finish` fails.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        // A stream whose final chunk failed is broken for good; there is nothing to retry.
+        self.finished = true;
+        self.write_chunk(true)?;
+        self.inner.flush()
+    }
+
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        if !self.header_written {
+            self.inner.write_all(&self.header)?;
+            self.header_written = true;
+        }
+        let mut chunk = std::mem::take(&mut self.buffer);
+        self.key
+            .seal_in_place_append_tag(
+                chunk_nonce(self.counter, last),
+                Aad::from(&self.header),
+                &mut chunk,
+            )
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Aead seal_in_place error"))?;
+        let mut length = chunk.len() as u32;
+        if last {
+            length |= LAST_CHUNK_FLAG;
+        }
+        self.inner.write_all(&length.to_be_bytes())?;
+        self.inner.write_all(&chunk)?;
+        self.counter += 1;
+        chunk.clear();
+        self.buffer = chunk;
+        Ok(())
+    }
+}
+
+impl Write for SealedStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Sealed stream already finished",
+            ));
+        }
+        if self.buffer.len() == SEALED_STREAM_CHUNK_SIZE {
+            self.write_chunk(false)?;
+        }
+        let n = buf.len().min(SEALED_STREAM_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for SealedStreamWriter {
+    fn drop(&mut self) {
+        // Without its final chunk the stream can't be told apart from a truncated one.
+        debug_assert!(
+            self.finished || std::thread::panicking(),
+            "SealedStreamWriter dropped without finish"
+        );
+    }
+}
+
+#[derive(Debug)]
+enum SealedOutputState {
+    Open,
+    Finished,
+    Failed(String),
+}
+
+/// Sealed outputs of an execution. Functions get their outputs as plain writers, which can't be
+/// finished explicitly, so the writers of sealed outputs finish their stream when the function
+/// drops them and record the outcome here; the worker then fails the task with `check` if an
+/// output was not completed.
+#[derive(Clone, Debug, Default)]
+pub struct SealedOutputs(Arc<Mutex<HashMap<String, SealedOutputState>>>);
+
+impl SealedOutputs {
+    /// Writer of the output `identifier` which writes through `writer` and finishes it when
+    /// dropped.
+    pub fn wrap(&self, identifier: &str, writer: SealedStreamWriter) -> Box<dyn Write> {
+        self.set(identifier, SealedOutputState::Open);
+        Box::new(SealedOutputWriter {
+            identifier: identifier.to_string(),
+            writer,
+            outputs: self.clone(),
+        })
+    }
+
+    /// Fails if a sealed output could not be finished, or is still open.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let states = self
+            .0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Poisoned sealed outputs"))?;
+        let mut identifiers: Vec<&String> = states.keys().collect();
+        identifiers.sort();
+        for identifier in identifiers {
+            match &states[identifier] {
+                SealedOutputState::Finished => (),
+                SealedOutputState::Open => {
+                    anyhow::bail!("Sealed output {} was not finished", identifier)
+                }
+                SealedOutputState::Failed(reason) => {
+                    anyhow::bail!("Failed to finish sealed output {}: {}", identifier, reason)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&self, identifier: &str, state: SealedOutputState) {
+        if let Ok(mut states) = self.0.lock() {
+            states.insert(identifier.to_string(), state);
+        }
+    }
+}
+
+struct SealedOutputWriter {
+    identifier: String,
+    writer: SealedStreamWriter,
+    outputs: SealedOutputs,
+}
+
+impl Write for SealedOutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for SealedOutputWriter {
+    fn drop(&mut self) {
+        let state = match self.writer.finish() {
+            Ok(()) => SealedOutputState::Finished,
+            Err(e) => SealedOutputState::Failed(e.to_string()),
+        };
+        self.outputs.set(&self.identifier, state);
+    }
+}
+
+/// Decrypts a stream written by `SealedStreamWriter` with the recipient's private key, writing
+/// the plaintext to `plaintext` and returning its length. Meant for clients, outside the
+/// enclave.
+///
+/// Every chunk is authenticated before its plaintext is written, but a truncated stream is only
+/// detected at its end, so `plaintext` must be discarded if this fails.
+pub fn open_sealed_stream(
+    recipient_private_key: &RecipientPrivateKey,
+    sealed: &mut dyn Read,
+    plaintext: &mut dyn Write,
+) -> anyhow::Result<u64> {
+    let mut header = vec![0u8; MAGIC.len() + 2];
+    sealed
+        .read_exact(&mut header)
+        .context("Truncated sealed stream header")?;
+    anyhow::ensure!(&header[..MAGIC.len()] == MAGIC, "Not a sealed stream");
+    let version = header[MAGIC.len()];
+    anyhow::ensure!(
+        version == SEALED_STREAM_VERSION,
+        "Unsupported sealed stream version: {}",
+        version
+    );
+    let curve = header[MAGIC.len() + 1];
+    let algorithm = algorithm(curve)?;
+    anyhow::ensure!(
+        algorithm == recipient_private_key.algorithm(),
+        "Sealed stream curve does not match the private key"
+    );
+    let mut ephemeral_public_key = vec![0u8; if curve == CURVE_P256 { 65 } else { 32 }];
+    sealed
+        .read_exact(&mut ephemeral_public_key)
+        .context("Truncated sealed stream header")?;
+    header.extend_from_slice(&ephemeral_public_key);
+    let key = derive_key(
+        recipient_private_key.agreement_key()?,
+        curve,
+        &ephemeral_public_key,
+    )?;
+
+    let mut counter = 0u64;
+    let mut written = 0u64;
+    loop {
+        let mut length = [0u8; 4];
+        sealed
+            .read_exact(&mut length)
+            .context("Sealed stream is truncated")?;
+        let length = u32::from_be_bytes(length);
+        let last = length & LAST_CHUNK_FLAG != 0;
+        let length = (length & !LAST_CHUNK_FLAG) as usize;
+        anyhow::ensure!(
+            (TAG_LENGTH..=SEALED_STREAM_CHUNK_SIZE + TAG_LENGTH).contains(&length),
+            "Invalid chunk length in sealed stream: {}",
+            length
+        );
+        let mut chunk = vec![0u8; length];
+        sealed
+            .read_exact(&mut chunk)
+            .context("Sealed stream is truncated")?;
+        let chunk = key
+            .open_in_place(chunk_nonce(counter, last), Aad::from(&header), &mut chunk)
+            .map_err(|_| anyhow::anyhow!("Sealed stream chunk {} is corrupted", counter))?;
+        plaintext.write_all(chunk)?;
+        written += chunk.len() as u64;
+        counter += 1;
+        if last {
+            break;
+        }
+    }
+    let mut trailing = [0u8; 1];
+    anyhow::ensure!(
+        sealed.read(&mut trailing)? == 0,
+        "Trailing data after the final chunk of the sealed stream"
+    );
+    plaintext.flush()?;
+    Ok(written)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_sealed_stream_round_trip,
+            test_sealed_stream_tampering,
+            test_sealed_stream_invalid_recipient,
+            test_sealed_outputs,
+        )
+    }
+
+    fn recipient(algorithm: &'static agreement::Algorithm) -> (RecipientPrivateKey, Vec<u8>) {
+        let private_key = RecipientPrivateKey::generate(algorithm).unwrap();
+        let public_key = private_key.public_key().unwrap();
+        (private_key, public_key)
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn seal(public_key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let sealed = SharedBuffer::default();
+        let mut writer = SealedStreamWriter::new(Box::new(sealed.clone()), public_key).unwrap();
+        for piece in plaintext.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+        // Flushing must not end the stream early.
+        writer.flush().unwrap();
+        writer.finish().unwrap();
+        let sealed = sealed.0.lock().unwrap().clone();
+        sealed
+    }
+
+    fn open(private_key: &RecipientPrivateKey, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        open_sealed_stream(private_key, &mut io::Cursor::new(sealed), &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn test_sealed_stream_round_trip() {
+        let plaintext: Vec<u8> = (0..SEALED_STREAM_CHUNK_SIZE * 2 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        for algorithm in &[&agreement::ECDH_P256, &agreement::X25519] {
+            for len in &[0, 10, SEALED_STREAM_CHUNK_SIZE, plaintext.len()] {
+                let (private_key, public_key) = recipient(*algorithm);
+                let sealed = seal(&public_key, &plaintext[..*len]);
+                assert_eq!(&sealed[..4], MAGIC);
+                assert_eq!(sealed[4], SEALED_STREAM_VERSION);
+                assert_eq!(open(&private_key, &sealed).unwrap(), &plaintext[..*len]);
+            }
+        }
+    }
+
+    fn test_sealed_stream_tampering() {
+        const FIRST_CHUNK: usize = MAGIC.len() + 2 + 65;
+        const FIRST_CHUNK_END: usize = FIRST_CHUNK + 4 + SEALED_STREAM_CHUNK_SIZE + TAG_LENGTH;
+        let tamperings: Vec<fn(&mut Vec<u8>)> = vec![
+            |sealed| *sealed.last_mut().unwrap() ^= 1,
+            |sealed| sealed[FIRST_CHUNK - 1] ^= 1,
+            |sealed| sealed.push(0),
+            |sealed| sealed.truncate(sealed.len() - 1),
+            |sealed| sealed.truncate(FIRST_CHUNK_END),
+            // The first chunk on its own, claiming to be the final one.
+            |sealed| {
+                sealed.truncate(FIRST_CHUNK_END);
+                sealed[FIRST_CHUNK] |= 0x80;
+            },
+        ];
+
+        let plaintext = vec![7u8; SEALED_STREAM_CHUNK_SIZE + 100];
+        for tamper in tamperings {
+            let (private_key, public_key) = recipient(&agreement::ECDH_P256);
+            let mut sealed = seal(&public_key, &plaintext);
+            tamper(&mut sealed);
+            assert!(open(&private_key, &sealed).is_err());
+        }
+
+        let (_, public_key) = recipient(&agreement::ECDH_P256);
+        let sealed = seal(&public_key, &plaintext);
+        let (other_key, _) = recipient(&agreement::ECDH_P256);
+        assert!(open(&other_key, &sealed).is_err());
+        let (x25519_key, _) = recipient(&agreement::X25519);
+        assert!(open(&x25519_key, &sealed).is_err());
+    }
+
+    fn test_sealed_stream_invalid_recipient() {
+        assert!(SealedStreamWriter::new(Box::new(io::sink()), &[4u8; 65]).is_err());
+        assert!(SealedStreamWriter::new(Box::new(io::sink()), &[4u8; 33]).is_err());
+    }
+
+    struct FailingSink;
+
+    impl Write for FailingSink {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn test_sealed_outputs() {
+        let (private_key, public_key) = recipient(&agreement::X25519);
+        let outputs = SealedOutputs::default();
+
+        // Dropping the writer of an output finishes its stream.
+        let sealed = SharedBuffer::default();
+        let writer = SealedStreamWriter::new(Box::new(sealed.clone()), &public_key).unwrap();
+        let mut output = outputs.wrap("output", writer);
+        output.write_all(b"result").unwrap();
+        output.flush().unwrap();
+        assert!(outputs.check().is_err());
+        drop(output);
+        outputs.check().unwrap();
+        let sealed = sealed.0.lock().unwrap().clone();
+        assert_eq!(open(&private_key, &sealed).unwrap(), b"result");
+
+        // A final chunk which can't be written fails the check.
+        let writer = SealedStreamWriter::new(Box::new(FailingSink), &public_key).unwrap();
+        let mut output = outputs.wrap("log", writer);
+        output.write_all(b"x").unwrap();
+        drop(output);
+        let error = outputs.check().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to finish sealed output log: disk full"
+        );
+    }
+}
@@ -27,14 +27,120 @@ use std::untrusted::fs::File;
 
 use crate::FileAuthTag;
 use crate::FileCrypto;
+use crate::RandomAccess;
+use crate::{SealedOutputs, SealedStreamWriter, SEALED_STREAM_VERSION};
 use anyhow::Context;
+use ring::digest;
+use serde::{Deserialize, Serialize};
 use sgx_tprotected_fs::SgxFile;
+use thiserror::Error;
+
+/// Size of the buffer inputs are streamed through while their digest is computed.
+const DIGEST_BUFFER_SIZE: usize = 64 * 1024;
+
+/// SHA-256 digest of the plaintext of a staged file.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Digest(bytes)
+    }
+
+    pub fn from_hex(hex: impl AsRef<str>) -> anyhow::Result<Self> {
+        let bytes = hex::decode(hex.as_ref()).context("Invalid digest")?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            bytes.len() == 32,
+            "Invalid digest length: {} bytes",
+            bytes.len()
+        );
+        let mut digest = [0; 32];
+        digest.copy_from_slice(bytes);
+        Ok(Digest(digest))
+    }
+
+    /// Digest of `bytes`.
+    pub fn compute(bytes: &[u8]) -> Self {
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(bytes);
+        Self::finish(context)
+    }
+
+    /// Digest of everything `reader` yields, read `DIGEST_BUFFER_SIZE` bytes at a time.
+    pub fn compute_reader(reader: &mut dyn Read) -> io::Result<Self> {
+        let mut context = digest::Context::new(&digest::SHA256);
+        let mut buffer = vec![0; DIGEST_BUFFER_SIZE];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => context.update(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self::finish(context))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn finish(context: digest::Context) -> Self {
+        let mut digest = [0; 32];
+        digest.copy_from_slice(context.finish().as_ref());
+        Digest(digest)
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl std::fmt::Debug for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+/// Error returned when a staged input does not match the digest its owner declared.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Input {key} failed integrity check: expected digest {expected}, got {actual}")]
+pub struct InputIntegrityError {
+    pub key: String,
+    pub expected: Digest,
+    pub actual: Digest,
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct StagedFileInfo {
     pub path: PathBuf,
     pub crypto_info: TeaclaveFile128Key,
     pub cmac: FileAuthTag,
+    /// Plaintext size, if it was declared when the file was staged.
+    pub size: Option<u64>,
+    /// SHA-256 digest of the plaintext, if the owner of the file declared one.
+    pub expected_digest: Option<Digest>,
+}
+
+/// What a function may learn about a staged file. Unlike `StagedFileInfo` it does not carry
+/// the file key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StagedFileMetadata {
+    /// Location of the file, or `None` for files staged in memory.
+    pub path: Option<PathBuf>,
+    /// Crypto schema the staged file is stored with.
+    pub crypto_schema: String,
+    /// Plaintext size, if known.
+    pub size: Option<u64>,
+    /// Version of the sealed stream format the file is encrypted with for its recipient, for
+    /// outputs staged with a recipient public key.
+    pub sealed_stream_version: Option<u8>,
 }
 
 impl StagedFileInfo {
@@ -47,16 +153,56 @@ impl StagedFileInfo {
             path: path.as_ref().into(),
             crypto_info,
             cmac: cmac.into(),
+            size: None,
+            expected_digest: None,
         }
     }
 
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_expected_digest(mut self, digest: Digest) -> Self {
+        self.expected_digest = Some(digest);
+        self
+    }
+
+    /// Checks the plaintext against the expected digest, reading the file once with a bounded
+    /// buffer. Files without an expected digest pass unread.
+    pub fn verify_digest(&self, key: &str) -> anyhow::Result<()> {
+        let expected = match self.expected_digest {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let mut file = self.open_verified()?;
+        let actual = Digest::compute_reader(&mut file)?;
+        if actual != expected {
+            return Err(InputIntegrityError {
+                key: key.to_string(),
+                expected,
+                actual,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
     pub fn create_readable_io(&self) -> anyhow::Result<Box<dyn io::Read>> {
+        Ok(Box::new(self.open_verified()?))
+    }
+
+    pub fn create_random_access_io(&self) -> anyhow::Result<Box<dyn RandomAccess>> {
+        Ok(Box::new(self.open_verified()?))
+    }
+
+    fn open_verified(&self) -> anyhow::Result<SgxFile> {
         let f = SgxFile::open_with_key(&self.path, self.crypto_info.key)?;
         let tag = f
             .get_mac()
             .context("Failed to get gmac from protected file")?;
         anyhow::ensure!(self.cmac == tag, "Corrupted input file: {:?}", self.path);
-        Ok(Box::new(f))
+        Ok(f)
     }
 
     pub fn create_writable_io(&self) -> anyhow::Result<Box<dyn io::Write>> {
@@ -156,7 +302,7 @@ impl StagedFileInfo {
         f.write_all(bytes)?;
         f.flush()?;
         let tag = f.get_mac()?;
-        Ok(Self::new(path.as_ref(), crypto, tag))
+        Ok(Self::new(path.as_ref(), crypto, tag).with_size(bytes.len() as u64))
     }
 }
 
@@ -170,19 +316,132 @@ pub fn read_all_bytes(path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
 #[derive(Debug, Default, Clone)]
 pub struct StagedFiles {
     entries: HashMap<String, StagedFileInfo>,
+    memory: HashMap<String, Vec<u8>>,
+    recipient_keys: HashMap<String, Vec<u8>>,
+    sealed_outputs: SealedOutputs,
 }
 
 impl StagedFiles {
     pub fn new(entries: HashMap<String, StagedFileInfo>) -> Self {
-        StagedFiles { entries }
+        StagedFiles {
+            entries,
+            memory: HashMap::new(),
+            recipient_keys: HashMap::new(),
+            sealed_outputs: SealedOutputs::default(),
+        }
+    }
+
+    /// Stages files in memory instead of on disk. Only runtimes for tests such as `RawIoRuntime`
+    /// serve them. For outputs only the identifiers matter; the runtime starts them empty.
+    pub fn from_memory(memory: HashMap<String, Vec<u8>>) -> Self {
+        StagedFiles {
+            entries: HashMap::new(),
+            memory,
+            recipient_keys: HashMap::new(),
+            sealed_outputs: SealedOutputs::default(),
+        }
+    }
+
+    /// Encrypts the output `key` to `public_key`, an uncompressed SEC1 encoded P-256 point or an
+    /// X25519 public key. Runtimes wrap the writer of such outputs in a `SealedStreamWriter`, so
+    /// neither the function nor the storage sees the recipient's plaintext.
+    pub fn with_recipient_key(mut self, key: impl Into<String>, public_key: Vec<u8>) -> Self {
+        self.recipient_keys.insert(key.into(), public_key);
+        self
+    }
+
+    pub fn recipient_key(&self, key: &str) -> Option<&[u8]> {
+        self.recipient_keys
+            .get(key)
+            .map(|public_key| public_key.as_slice())
+    }
+
+    /// Wraps `writer`, the writer of the output `key`, in a `SealedStreamWriter` if the output
+    /// has a recipient key. The stream is finished when the returned writer is dropped; see
+    /// `sealed_outputs` for its outcome.
+    pub fn seal_output(&self, key: &str, writer: Box<dyn Write>) -> anyhow::Result<Box<dyn Write>> {
+        match self.recipient_keys.get(key) {
+            Some(public_key) => {
+                let writer = SealedStreamWriter::new(writer, public_key)
+                    .with_context(|| format!("Failed to seal output {}", key))?;
+                Ok(self.sealed_outputs.wrap(key, writer))
+            }
+            None => Ok(writer),
+        }
+    }
+
+    /// Outcome of the outputs wrapped by `seal_output`, shared with all clones.
+    pub fn sealed_outputs(&self) -> SealedOutputs {
+        self.sealed_outputs.clone()
     }
 
     pub fn get(&self, key: &str) -> Option<&StagedFileInfo> {
         self.entries.get(key)
     }
 
+    pub fn get_memory(&self, key: &str) -> Option<&[u8]> {
+        self.memory.get(key).map(|content| content.as_slice())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key) || self.memory.contains_key(key)
+    }
+
+    /// Identifiers of all staged files, file-backed and in memory, in sorted order.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .entries
+            .keys()
+            .chain(self.memory.keys())
+            .map(|key| key.as_str())
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// Identifiers and metadata of all staged files in the order of `keys`.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, StagedFileMetadata)> + '_ {
+        self.keys()
+            .into_iter()
+            .filter_map(move |key| Some((key, self.metadata(key)?)))
+    }
+
+    pub fn metadata(&self, key: &str) -> Option<StagedFileMetadata> {
+        let sealed_stream_version = self.recipient_keys.get(key).map(|_| SEALED_STREAM_VERSION);
+        if let Some(info) = self.entries.get(key) {
+            return Some(StagedFileMetadata {
+                path: Some(info.path.clone()),
+                crypto_schema: TeaclaveFile128Key::SCHEMA.to_string(),
+                size: info.size,
+                sealed_stream_version,
+            });
+        }
+        let content = self.memory.get(key)?;
+        Some(StagedFileMetadata {
+            path: None,
+            crypto_schema: FileCrypto::Raw.schema().to_string(),
+            size: Some(content.len() as u64),
+            sealed_stream_version,
+        })
+    }
+
+    /// Checks every file-backed entry against its expected digest, in the order of `keys`.
+    pub fn verify_digests(&self) -> anyhow::Result<()> {
+        for key in self.keys() {
+            if let Some(info) = self.entries.get(key) {
+                info.verify_digest(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn memory_identifiers(&self) -> impl Iterator<Item = &String> {
+        self.memory.keys()
+    }
+
     pub fn len(&self) -> usize {
-        self.entries.len()
+        self.entries.len() + self.memory.len()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -194,6 +453,107 @@ impl std::iter::FromIterator<(String, StagedFileInfo)> for StagedFiles {
     fn from_iter<T: IntoIterator<Item = (String, StagedFileInfo)>>(iter: T) -> Self {
         StagedFiles {
             entries: HashMap::from_iter(iter),
+            memory: HashMap::new(),
+            recipient_keys: HashMap::new(),
+            sealed_outputs: SealedOutputs::default(),
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_staged_files_enumeration,
+            test_staged_files_metadata,
+            test_digest,
+        )
+    }
+
+    fn mixed_files() -> StagedFiles {
+        let mut memory = HashMap::new();
+        memory.insert("input_b".to_string(), b"memory".to_vec());
+        let mut files = StagedFiles::from_memory(memory);
+        files.entries.insert(
+            "input_c".to_string(),
+            StagedFileInfo::new(
+                "/tmp/c",
+                TeaclaveFile128Key::random(),
+                FileAuthTag::default(),
+            ),
+        );
+        files.entries.insert(
+            "input_a".to_string(),
+            StagedFileInfo::new(
+                "/tmp/a",
+                TeaclaveFile128Key::random(),
+                FileAuthTag::default(),
+            )
+            .with_size(42),
+        );
+        files
+    }
+
+    fn test_staged_files_enumeration() {
+        let files = mixed_files();
+        assert_eq!(files.len(), 3);
+        // Sorted regardless of backing and insertion order.
+        for _ in 0..3 {
+            assert_eq!(files.keys(), vec!["input_a", "input_b", "input_c"]);
         }
+        let keys: Vec<&str> = files.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, files.keys());
+        assert!(files.contains("input_b"));
+        assert!(files.contains("input_c"));
+        assert!(!files.contains("output"));
+        assert!(StagedFiles::default().keys().is_empty());
+    }
+
+    fn test_staged_files_metadata() {
+        let files = mixed_files();
+        assert_eq!(
+            files.metadata("input_a"),
+            Some(StagedFileMetadata {
+                path: Some(PathBuf::from("/tmp/a")),
+                crypto_schema: "teaclave-file-128".to_string(),
+                size: Some(42),
+                sealed_stream_version: None,
+            })
+        );
+        assert_eq!(files.metadata("input_c").unwrap().size, None);
+        assert_eq!(
+            files.metadata("input_b"),
+            Some(StagedFileMetadata {
+                path: None,
+                crypto_schema: "raw".to_string(),
+                size: Some(6),
+                sealed_stream_version: None,
+            })
+        );
+        assert_eq!(files.metadata("output"), None);
+
+        let files = files.with_recipient_key("input_c", vec![4; 65]);
+        assert_eq!(files.recipient_key("input_c"), Some(&[4; 65][..]));
+        assert_eq!(
+            files.metadata("input_c").unwrap().sealed_stream_version,
+            Some(SEALED_STREAM_VERSION)
+        );
+    }
+
+    fn test_digest() {
+        let digest = Digest::compute(b"abc");
+        assert_eq!(
+            digest.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(Digest::from_hex(digest.to_string()).unwrap(), digest);
+        assert!(Digest::from_hex("ba7816bf").is_err());
+
+        let data: Vec<u8> = (0..DIGEST_BUFFER_SIZE * 3 + 17).map(|i| i as u8).collect();
+        let streamed = Digest::compute_reader(&mut io::Cursor::new(data.clone())).unwrap();
+        assert_eq!(streamed, Digest::compute(&data));
     }
 }
@@ -15,16 +15,31 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use crate::{Executor, ExecutorType, StagedFiles, TeaclaveRuntime};
+use crate::{
+    ArgumentError, ExecutionLimits, ExecutionPolicy, Executor, ExecutorType, StagedFiles,
+    TeaclaveRuntime,
+};
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::Read;
 
 use anyhow::{Context, Result};
 
 pub type FunctionRuntime = Box<dyn TeaclaveRuntime + Send + Sync>;
 type ArgumentValue = serde_json::Value;
 
+/// Key of the object which stands in for an argument stored in a staged input, i.e.
+/// `{"$file": "<input_key>"}`.
+pub const FILE_ARGUMENT_KEY: &str = "$file";
+/// Largest input accepted as an argument by `FunctionArguments::resolve_files`.
+pub const MAX_FILE_ARGUMENT_SIZE: u64 = 16 * 1024 * 1024;
+/// Arguments the platform reads from the arguments of any task, whatever its function:
+/// `save_log` returns the log of the execution with the result. They are taken out before the
+/// function gets its arguments, and every argument schema accepts them.
+pub const PLATFORM_ARGUMENTS: &[&str] = &["save_log"];
+
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct FunctionArguments {
     inner: serde_json::Map<String, ArgumentValue>,
@@ -83,6 +98,22 @@ impl FunctionArguments {
             .with_context(|| format!("key not found: {}", key))
     }
 
+    /// Binary argument given as a base64 string or an array of numbers, of at most `max_len`
+    /// bytes if set.
+    pub fn get_bytes(&self, key: &str, max_len: Option<usize>) -> anyhow::Result<Vec<u8>> {
+        let bytes = crate::bytes_base64::decode_value(self.get(key)?)
+            .map_err(|e| anyhow::anyhow!("Invalid bytes in argument {}: {}", key, e))?;
+        if let Some(max_len) = max_len {
+            anyhow::ensure!(
+                bytes.len() <= max_len,
+                "Argument {} is longer than {} bytes",
+                key,
+                max_len
+            );
+        }
+        Ok(bytes)
+    }
+
     pub fn into_vec(self) -> Vec<String> {
         let mut vector = Vec::new();
 
@@ -97,6 +128,14 @@ impl FunctionArguments {
         vector
     }
 
+    /// Deserializes the arguments into the argument struct of a function. Errors name the
+    /// argument which failed, e.g. `arguments.message: invalid type: integer `1`, expected a
+    /// string`. Unknown arguments are ignored, unless `T` opts in to rejecting them with
+    /// `#[serde(deny_unknown_fields)]`.
+    pub fn into_typed<T: DeserializeOwned>(self) -> Result<T, ArgumentError> {
+        crate::typed_arguments::from_value(&ArgumentValue::Object(self.inner))
+    }
+
     pub fn into_string(self) -> String {
         ArgumentValue::Object(self.inner).to_string()
     }
@@ -104,6 +143,80 @@ impl FunctionArguments {
     pub fn insert(&mut self, k: String, v: ArgumentValue) -> Option<ArgumentValue> {
         self.inner.insert(k, v)
     }
+
+    /// The arguments without the `PLATFORM_ARGUMENTS`, as the function gets them.
+    pub fn without_platform_arguments(mut self) -> Self {
+        for name in PLATFORM_ARGUMENTS {
+            self.inner.remove(*name);
+        }
+        self
+    }
+
+    /// Replaces every `{"$file": "<input_key>"}` value, at any depth, with the JSON content of
+    /// that staged input. Lets callers pass arguments too large to travel inline. Functions
+    /// accepting such arguments call this before deserializing them.
+    pub fn resolve_files(self, runtime: &dyn TeaclaveRuntime) -> Result<Self> {
+        self.resolve_files_with_limit(runtime, MAX_FILE_ARGUMENT_SIZE)
+    }
+
+    /// Like `resolve_files`, but rejects inputs larger than `max_size` bytes.
+    pub fn resolve_files_with_limit(
+        mut self,
+        runtime: &dyn TeaclaveRuntime,
+        max_size: u64,
+    ) -> Result<Self> {
+        for value in self.inner.values_mut() {
+            resolve_file_value(value, runtime, max_size)?;
+        }
+        Ok(self)
+    }
+}
+
+fn file_argument_key(value: &ArgumentValue) -> Option<&str> {
+    match value {
+        ArgumentValue::Object(o) if o.len() == 1 => o.get(FILE_ARGUMENT_KEY)?.as_str(),
+        _ => None,
+    }
+}
+
+fn resolve_file_value(
+    value: &mut ArgumentValue,
+    runtime: &dyn TeaclaveRuntime,
+    max_size: u64,
+) -> Result<()> {
+    if let Some(key) = file_argument_key(value) {
+        *value = read_file_argument(key, runtime, max_size)?;
+        return Ok(());
+    }
+    match value {
+        ArgumentValue::Object(o) => o
+            .values_mut()
+            .try_for_each(|v| resolve_file_value(v, runtime, max_size)),
+        ArgumentValue::Array(a) => a
+            .iter_mut()
+            .try_for_each(|v| resolve_file_value(v, runtime, max_size)),
+        _ => Ok(()),
+    }
+}
+
+fn read_file_argument(
+    key: &str,
+    runtime: &dyn TeaclaveRuntime,
+    max_size: u64,
+) -> Result<ArgumentValue> {
+    let file = runtime
+        .open_input(key)
+        .with_context(|| format!("Argument file {} is not a staged input", key))?;
+    let mut content = Vec::new();
+    file.take(max_size + 1).read_to_end(&mut content)?;
+    anyhow::ensure!(
+        content.len() as u64 <= max_size,
+        "Argument file {} exceeds {} bytes",
+        key,
+        max_size
+    );
+    serde_json::from_slice(&content)
+        .with_context(|| format!("Argument file {} is not valid JSON", key))
 }
 
 #[derive(Debug, Default)]
@@ -116,6 +229,12 @@ pub struct StagedFunction {
     pub executor_type: ExecutorType,
     pub executor: Executor,
     pub runtime_name: String,
+    pub limits: ExecutionLimits,
+    pub policy: ExecutionPolicy,
+    pub deterministic_seed: Option<u64>,
+    /// User the task runs for. Runtimes scope state which outlives the execution, like the
+    /// key-value store, to the tenant.
+    pub tenant: Option<String>,
 }
 
 #[derive(Default)]
@@ -160,6 +279,26 @@ impl StagedFunctionBuilder {
         self
     }
 
+    pub fn limits(mut self, limits: ExecutionLimits) -> Self {
+        self.function.limits = limits;
+        self
+    }
+
+    pub fn policy(mut self, policy: ExecutionPolicy) -> Self {
+        self.function.policy = policy;
+        self
+    }
+
+    pub fn deterministic_seed(mut self, seed: Option<u64>) -> Self {
+        self.function.deterministic_seed = seed;
+        self
+    }
+
+    pub fn tenant(mut self, tenant: impl ToString) -> Self {
+        self.function.tenant = Some(tenant.to_string());
+        self
+    }
+
     pub fn runtime_name(mut self, runtime_name: impl ToString) -> Self {
         self.function.runtime_name = runtime_name.to_string();
         self
@@ -174,3 +313,175 @@ impl StagedFunctionBuilder {
         self.function
     }
 }
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_resolve_file_arguments,
+            test_resolve_nested_file_arguments,
+            test_resolve_missing_file_argument,
+            test_resolve_invalid_file_argument,
+            test_resolve_file_argument_size_limit,
+            test_get_bytes_base64,
+            test_get_bytes_invalid_padding,
+            test_get_bytes_array,
+            test_get_bytes_max_len,
+            test_without_platform_arguments,
+        )
+    }
+
+    #[derive(Default)]
+    struct MemoryRuntime {
+        inputs: HashMap<String, Vec<u8>>,
+    }
+
+    impl MemoryRuntime {
+        fn with_input(mut self, identifier: &str, content: &str) -> Self {
+            self.inputs
+                .insert(identifier.to_string(), content.as_bytes().to_vec());
+            self
+        }
+    }
+
+    impl TeaclaveRuntime for MemoryRuntime {
+        fn open_input(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Read>> {
+            let data = self
+                .inputs
+                .get(identifier)
+                .ok_or_else(|| anyhow::anyhow!("Invalid input file identifier."))?;
+            Ok(Box::new(io::Cursor::new(data.clone())))
+        }
+
+        fn create_output(&self, _identifier: &str) -> anyhow::Result<Box<dyn io::Write>> {
+            anyhow::bail!("Invalid output file identifier")
+        }
+    }
+
+    fn test_resolve_file_arguments() {
+        let runtime = MemoryRuntime::default().with_input("features", r#"["age", "income"]"#);
+        let arguments = FunctionArguments::from_json(json!({
+            "features": {"$file": "features"},
+            "depth": 3,
+        }))
+        .unwrap();
+
+        let resolved = arguments.resolve_files(&runtime).unwrap();
+        assert_eq!(
+            ArgumentValue::Object(resolved.inner),
+            json!({"features": ["age", "income"], "depth": 3})
+        );
+    }
+
+    fn test_resolve_nested_file_arguments() {
+        let runtime = MemoryRuntime::default()
+            .with_input("schema", r#"{"columns": ["id", "name"]}"#)
+            .with_input("labels", r#""label""#);
+        let arguments = FunctionArguments::from_json(json!({
+            "table": {"schema": {"$file": "schema"}, "key": "id"},
+            "targets": [{"$file": "labels"}, "other"],
+            // Objects with more keys are left alone.
+            "literal": {"$file": "schema", "note": "kept"},
+        }))
+        .unwrap();
+
+        let resolved = arguments.resolve_files(&runtime).unwrap();
+        assert_eq!(
+            ArgumentValue::Object(resolved.inner),
+            json!({
+                "table": {"schema": {"columns": ["id", "name"]}, "key": "id"},
+                "targets": ["label", "other"],
+                "literal": {"$file": "schema", "note": "kept"},
+            })
+        );
+    }
+
+    fn test_resolve_missing_file_argument() {
+        let runtime = MemoryRuntime::default();
+        let arguments =
+            FunctionArguments::from_json(json!({"features": {"$file": "missing"}})).unwrap();
+
+        let error = arguments.resolve_files(&runtime).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Argument file missing is not a staged input"
+        );
+    }
+
+    fn test_resolve_invalid_file_argument() {
+        let runtime = MemoryRuntime::default().with_input("features", "age,income");
+        let arguments =
+            FunctionArguments::from_json(json!({"features": {"$file": "features"}})).unwrap();
+
+        let error = arguments.resolve_files(&runtime).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Argument file features is not valid JSON"
+        );
+    }
+
+    fn test_resolve_file_argument_size_limit() {
+        let runtime = MemoryRuntime::default().with_input("features", r#"["age", "income"]"#);
+        let arguments =
+            FunctionArguments::from_json(json!({"features": {"$file": "features"}})).unwrap();
+
+        let error = arguments
+            .clone()
+            .resolve_files_with_limit(&runtime, 8)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Argument file features exceeds 8 bytes");
+        assert!(arguments.resolve_files_with_limit(&runtime, 17).is_ok());
+    }
+
+    fn test_get_bytes_base64() {
+        let arguments = FunctionArguments::from_json(json!({"iv": "AAECAwQFBgcICQoL"})).unwrap();
+        assert_eq!(
+            arguments.get_bytes("iv", None).unwrap(),
+            (0..12).collect::<Vec<u8>>()
+        );
+        let error = arguments.get_bytes("salt", None).unwrap_err();
+        assert_eq!(error.to_string(), "key not found: salt");
+    }
+
+    fn test_get_bytes_invalid_padding() {
+        let arguments = FunctionArguments::from_json(json!({"iv": "a==="})).unwrap();
+        let error = arguments.get_bytes("iv", None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid bytes in argument iv: invalid base64 byte 0x3d at position 1"
+        );
+    }
+
+    fn test_get_bytes_array() {
+        let arguments =
+            FunctionArguments::from_json(json!({"salt": [0, 127, 255], "bad": [1, -1]})).unwrap();
+        assert_eq!(
+            arguments.get_bytes("salt", None).unwrap(),
+            vec![0, 127, 255]
+        );
+        let error = arguments.get_bytes("bad", None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid bytes in argument bad: element at position 1 is not a byte"
+        );
+    }
+
+    fn test_get_bytes_max_len() {
+        let arguments = FunctionArguments::from_json(json!({"key": "AAECAw=="})).unwrap();
+        assert_eq!(arguments.get_bytes("key", Some(4)).unwrap().len(), 4);
+        let error = arguments.get_bytes("key", Some(3)).unwrap_err();
+        assert_eq!(error.to_string(), "Argument key is longer than 3 bytes");
+    }
+
+    fn test_without_platform_arguments() {
+        let arguments =
+            FunctionArguments::from_json(json!({"message": "Hello", "save_log": "true"})).unwrap();
+        let arguments = arguments.without_platform_arguments();
+        assert_eq!(arguments.into_string(), r#"{"message":"Hello"}"#);
+    }
+}
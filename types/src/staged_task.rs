@@ -23,8 +23,8 @@ use url::Url;
 use uuid::Uuid;
 
 use crate::{
-    Executor, ExecutorType, FileAuthTag, FileCrypto, FunctionArguments, Storable,
-    TeaclaveInputFile, TeaclaveOutputFile,
+    Digest, ExecutionLimits, ExecutionPolicy, Executor, ExecutorType, FileAuthTag, FileCrypto,
+    FunctionArguments, Storable, TeaclaveInputFile, TeaclaveOutputFile,
 };
 
 const STAGED_TASK_PREFIX: &str = "staged-"; // staged-task-uuid
@@ -128,6 +128,9 @@ pub struct FunctionInputFile {
     pub url: Url,
     pub cmac: FileAuthTag,
     pub crypto_info: FileCrypto,
+    /// SHA-256 of the plaintext, checked against the staged file before the function runs.
+    #[serde(default)]
+    pub digest: Option<Digest>,
 }
 
 impl FunctionInputFile {
@@ -136,8 +139,14 @@ impl FunctionInputFile {
             url,
             cmac,
             crypto_info: crypto.into(),
+            digest: None,
         }
     }
+
+    pub fn with_digest(mut self, digest: Digest) -> Self {
+        self.digest = Some(digest);
+        self
+    }
 }
 
 impl From<TeaclaveInputFile> for FunctionInputFile {
@@ -146,6 +155,7 @@ impl From<TeaclaveInputFile> for FunctionInputFile {
             url: file.url,
             cmac: file.cmac,
             crypto_info: file.crypto_info,
+            digest: file.digest,
         }
     }
 }
@@ -186,6 +196,15 @@ pub struct StagedTask {
     pub function_payload: Vec<u8>,
     pub input_data: FunctionInputFiles,
     pub output_data: FunctionOutputFiles,
+    /// Limits of the execution, set per task so they can differ between tenants.
+    #[serde(default)]
+    pub limits: ExecutionLimits,
+    /// Seed of the function's RNG, for reproducible executions. Random if unset.
+    #[serde(default)]
+    pub deterministic_seed: Option<u64>,
+    /// Gated builtins and capabilities the task may use, and how its summary is post-processed.
+    #[serde(default)]
+    pub policy: ExecutionPolicy,
 }
 
 impl Storable for StagedTask {
@@ -266,6 +285,21 @@ impl StagedTaskBuilder {
         self
     }
 
+    pub fn limits(mut self, limits: ExecutionLimits) -> Self {
+        self.task.limits = limits;
+        self
+    }
+
+    pub fn deterministic_seed(mut self, seed: u64) -> Self {
+        self.task.deterministic_seed = Some(seed);
+        self
+    }
+
+    pub fn policy(mut self, policy: ExecutionPolicy) -> Self {
+        self.task.policy = policy;
+        self
+    }
+
     pub fn build(self) -> StagedTask {
         self.task
     }
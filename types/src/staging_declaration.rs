@@ -0,0 +1,195 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::FunctionArguments;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How a function finds the identifier of one of its files.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileKey {
+    Fixed(String),
+    /// Any identifier with this prefix, for functions taking any number of files.
+    Prefix(String),
+    /// The identifier is the value of the argument `name`, or `default` if it is not given.
+    Argument {
+        name: String,
+        default: String,
+    },
+}
+
+impl fmt::Display for FileKey {
+    /// The notation of `FunctionDescriptor::inputs`: prefixes end with `*`, and arguments show
+    /// their default.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileKey::Fixed(identifier) => write!(f, "{}", identifier),
+            FileKey::Prefix(prefix) => write!(f, "{}*", prefix),
+            FileKey::Argument { default, .. } => write!(f, "{}", default),
+        }
+    }
+}
+
+/// A file a function expects to be staged.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSpec {
+    pub key: FileKey,
+    /// Required files fail the task before the function runs if they are not staged. A
+    /// required prefix needs at least one file with the prefix.
+    pub required: bool,
+}
+
+impl FileSpec {
+    pub fn fixed(identifier: impl Into<String>) -> Self {
+        Self::with_key(FileKey::Fixed(identifier.into()))
+    }
+
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self::with_key(FileKey::Prefix(prefix.into()))
+    }
+
+    pub fn argument(name: impl Into<String>, default: impl Into<String>) -> Self {
+        Self::with_key(FileKey::Argument {
+            name: name.into(),
+            default: default.into(),
+        })
+    }
+
+    fn with_key(key: FileKey) -> Self {
+        Self {
+            key,
+            required: false,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Whether the staged file `identifier` is this file. Resolve the spec first, arguments
+    /// only match their default.
+    pub fn matches(&self, identifier: &str) -> bool {
+        match &self.key {
+            FileKey::Fixed(fixed) => fixed == identifier,
+            FileKey::Prefix(prefix) => identifier.starts_with(prefix.as_str()),
+            FileKey::Argument { default, .. } => default == identifier,
+        }
+    }
+
+    /// The spec with the identifier given by `arguments`, if it depends on them. Arguments
+    /// which are not strings are left to the function to reject.
+    pub fn resolve(&self, arguments: &FunctionArguments) -> Self {
+        let key = match &self.key {
+            FileKey::Argument { name, default } => {
+                let identifier = arguments
+                    .get(name)
+                    .ok()
+                    .and_then(|value| value.as_str())
+                    .unwrap_or(default);
+                FileKey::Fixed(identifier.to_string())
+            }
+            key => key.clone(),
+        };
+        Self {
+            key,
+            required: self.required,
+        }
+    }
+}
+
+/// The files a builtin reads and writes, declared next to its argument schema so that the
+/// executor can check the staged files of a task before running it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagingDeclaration {
+    pub inputs: Vec<FileSpec>,
+    pub outputs: Vec<FileSpec>,
+}
+
+impl StagingDeclaration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn input(mut self, input: FileSpec) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn output(mut self, output: FileSpec) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// The declaration for one execution, with the identifiers given by `arguments`.
+    pub fn resolve(&self, arguments: &FunctionArguments) -> Self {
+        let resolve = |specs: &[FileSpec]| specs.iter().map(|s| s.resolve(arguments)).collect();
+        Self {
+            inputs: resolve(&self.inputs),
+            outputs: resolve(&self.outputs),
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_file_spec_matches, test_resolve)
+    }
+
+    fn test_file_spec_matches() {
+        let fixed = FileSpec::fixed("model_file").required();
+        assert!(fixed.required);
+        assert!(fixed.matches("model_file"));
+        assert!(!fixed.matches("model_file2"));
+
+        let prefix = FileSpec::prefix("input_data");
+        assert!(!prefix.required);
+        assert!(prefix.matches("input_data"));
+        assert!(prefix.matches("input_data12"));
+        assert!(!prefix.matches("output_data1"));
+
+        assert_eq!(fixed.key.to_string(), "model_file");
+        assert_eq!(prefix.key.to_string(), "input_data*");
+        assert_eq!(FileSpec::argument("input", "data").key.to_string(), "data");
+    }
+
+    fn test_resolve() {
+        let declaration = StagingDeclaration::new()
+            .input(FileSpec::argument("input", "input").required())
+            .input(FileSpec::prefix("extra"))
+            .output(FileSpec::argument("output", "output"));
+
+        let arguments = FunctionArguments::from_json(json!({"input": "table"})).unwrap();
+        let resolved = declaration.resolve(&arguments);
+        assert_eq!(
+            resolved,
+            StagingDeclaration::new()
+                .input(FileSpec::fixed("table").required())
+                .input(FileSpec::prefix("extra"))
+                .output(FileSpec::fixed("output"))
+        );
+
+        // Mistyped arguments are reported by the function, the preflight takes the default.
+        let arguments = FunctionArguments::from_json(json!({"input": 3})).unwrap();
+        assert!(declaration.resolve(&arguments).inputs[0].matches("input"));
+    }
+}
@@ -19,7 +19,7 @@ use crate::*;
 use anyhow::{anyhow, bail, ensure, Error, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Iter;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use uuid::Uuid;
 
@@ -214,6 +214,9 @@ pub struct TaskOutputs {
     pub return_value: Vec<u8>,
     pub tags_map: OutputsTags,
     pub log: Vec<String>,
+    /// Signed record of the files the function read and wrote.
+    #[serde(default)]
+    pub io_manifest: Option<SignedIoManifest>,
 }
 
 impl TaskOutputs {
@@ -226,21 +229,78 @@ impl TaskOutputs {
             return_value: value.into(),
             tags_map: OutputsTags::new(tags_map),
             log,
+            io_manifest: None,
         }
     }
+
+    pub fn with_io_manifest(mut self, io_manifest: SignedIoManifest) -> Self {
+        self.io_manifest = Some(io_manifest);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TaskFailure {
     pub reason: String,
+    #[serde(default)]
+    pub category: TaskFailureCategory,
+    /// Signed record of the files the function read and wrote before it failed.
+    #[serde(default)]
+    pub io_manifest: Option<SignedIoManifest>,
+    /// What the function logged before it failed.
+    #[serde(default)]
+    pub log: Vec<String>,
+    /// Counters of the execution up to its failure, named like the "runtime." metrics of a
+    /// `FunctionSummary`.
+    #[serde(default)]
+    pub metrics: BTreeMap<String, f64>,
 }
 
 impl TaskFailure {
     pub fn new(reason: impl ToString) -> Self {
         TaskFailure {
             reason: reason.to_string(),
+            category: TaskFailureCategory::Internal,
+            io_manifest: None,
+            log: Vec::new(),
+            metrics: BTreeMap::new(),
         }
     }
+
+    pub fn with_category(mut self, category: TaskFailureCategory) -> Self {
+        self.category = category;
+        self
+    }
+
+    pub fn with_io_manifest(mut self, io_manifest: SignedIoManifest) -> Self {
+        self.io_manifest = Some(io_manifest);
+        self
+    }
+
+    pub fn with_log(mut self, log: Vec<String>) -> Self {
+        self.log = log;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: BTreeMap<String, f64>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Failure of a task whose function stopped because it was cancelled.
+    pub fn cancelled() -> Self {
+        Self::new(Cancelled).with_category(TaskFailureCategory::Cancelled)
+    }
+
+    /// Failure of a task which ended with `error`. The error is classified as a `FunctionError`,
+    /// so users see why their task failed without learning about platform internals.
+    pub fn from_error(error: anyhow::Error) -> Self {
+        FunctionError::from(error).into()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason == Cancelled.to_string()
+    }
 }
 
 impl std::fmt::Display for TaskFailure {
@@ -40,6 +40,13 @@ pub struct TaskState {
     pub assigned_outputs: TaskFiles<TeaclaveOutputFile>,
     pub result: TaskResult,
     pub status: TaskStatus,
+    /// Latest progress reported by the function, while the task is running.
+    #[serde(default)]
+    pub progress: Option<TaskProgress>,
+    /// Execution policy the creator requested for the task, which every participant approves
+    /// along with the rest of the task.
+    #[serde(default)]
+    pub execution_policy: ExecutionPolicy,
 }
 
 impl Storable for TaskState {
@@ -193,6 +200,13 @@ impl Task<Create> {
             extra: Create,
         })
     }
+
+    /// Runs the task under `policy` instead of the default policy, which enables no gated
+    /// builtins.
+    pub fn execution_policy(mut self, policy: ExecutionPolicy) -> Self {
+        self.state.execution_policy = policy;
+        self
+    }
 }
 
 impl Task<Assign> {
@@ -291,6 +305,9 @@ impl Task<Stage> {
             function_arguments,
             input_data: self.state.assigned_inputs.clone().into(),
             output_data: self.state.assigned_outputs.clone().into(),
+            limits: ExecutionLimits::default(),
+            deterministic_seed: None,
+            policy: self.state.execution_policy.clone(),
         };
         Ok(staged_task)
     }
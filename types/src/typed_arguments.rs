@@ -0,0 +1,491 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::FunctionError;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    Unexpected, VariantAccess, Visitor,
+};
+use serde_json::Value;
+use std::fmt;
+
+/// Error of `FunctionArguments::into_typed`, naming the argument which failed, e.g.
+/// `arguments.message: invalid type: integer `1`, expected a string`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgumentError {
+    path: String,
+    message: String,
+}
+
+impl ArgumentError {
+    /// Path of the failed value, starting at `arguments`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // Errors are raised at the innermost value and passed up unchanged.
+    fn at(mut self, path: &Path<'_>) -> Self {
+        if self.path.is_empty() {
+            self.path = path.to_string();
+        }
+        self
+    }
+}
+
+impl fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for ArgumentError {}
+
+impl de::Error for ArgumentError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        ArgumentError {
+            path: String::new(),
+            message: message.to_string(),
+        }
+    }
+
+    // Arguments are JSON, so unit values are nulls.
+    fn invalid_type(unexpected: Unexpected<'_>, expected: &dyn de::Expected) -> Self {
+        match unexpected {
+            Unexpected::Unit => {
+                de::Error::custom(format_args!("invalid type: null, expected {}", expected))
+            }
+            unexpected => de::Error::custom(format_args!(
+                "invalid type: {}, expected {}",
+                unexpected, expected
+            )),
+        }
+    }
+}
+
+impl From<ArgumentError> for FunctionError {
+    fn from(error: ArgumentError) -> Self {
+        FunctionError::invalid_arguments(error)
+    }
+}
+
+enum Path<'a> {
+    Root,
+    Key(&'a Path<'a>, &'a str),
+    Index(&'a Path<'a>, usize),
+}
+
+impl fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Path::Root => write!(f, "arguments"),
+            Path::Key(parent, key) => write!(f, "{}.{}", parent, key),
+            Path::Index(parent, index) => write!(f, "{}[{}]", parent, index),
+        }
+    }
+}
+
+pub(crate) fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, ArgumentError> {
+    T::deserialize(Tracked {
+        value,
+        path: &Path::Root,
+    })
+}
+
+fn unexpected(value: &Value) -> Unexpected<'_> {
+    match value {
+        Value::Null => Unexpected::Unit,
+        Value::Bool(b) => Unexpected::Bool(*b),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => Unexpected::Unsigned(u),
+            (_, Some(i)) => Unexpected::Signed(i),
+            _ => Unexpected::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => Unexpected::Str(s),
+        Value::Array(_) => Unexpected::Seq,
+        Value::Object(_) => Unexpected::Map,
+    }
+}
+
+/// Deserializer over a JSON value which remembers where in the arguments the value is.
+struct Tracked<'a> {
+    value: &'a Value,
+    path: &'a Path<'a>,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Tracked<'a> {
+    type Error = ArgumentError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ArgumentError> {
+        let path = self.path;
+        let result = match self.value {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(u), _) => visitor.visit_u64(u),
+                (_, Some(i)) => visitor.visit_i64(i),
+                _ => visitor.visit_f64(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(s) => visitor.visit_str(s),
+            Value::Array(a) => visitor.visit_seq(Seq {
+                iter: a.iter().enumerate(),
+                path,
+            }),
+            Value::Object(o) => visitor.visit_map(Map {
+                iter: o.iter(),
+                value: None,
+                path,
+            }),
+        };
+        result.map_err(|e| e.at(path))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ArgumentError> {
+        let path = self.path;
+        let result = match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        };
+        result.map_err(|e| e.at(path))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ArgumentError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ArgumentError> {
+        let path = self.path;
+        let result = match self.value {
+            Value::String(s) => {
+                let variant: de::value::StrDeserializer<ArgumentError> =
+                    s.as_str().into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            Value::Object(o) if o.len() == 1 => {
+                let (variant, value) = o.iter().next().unwrap();
+                visitor.visit_enum(Enum {
+                    variant,
+                    value,
+                    path,
+                })
+            }
+            other => Err(de::Error::invalid_type(
+                unexpected(other),
+                &"a variant name or an object with a single key",
+            )),
+        };
+        result.map_err(|e| e.at(path))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Seq<'a> {
+    iter: std::iter::Enumerate<std::slice::Iter<'a, Value>>,
+    path: &'a Path<'a>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for Seq<'a> {
+    type Error = ArgumentError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ArgumentError> {
+        match self.iter.next() {
+            Some((index, value)) => {
+                let path = Path::Index(self.path, index);
+                seed.deserialize(Tracked { value, path: &path }).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct Map<'a> {
+    iter: serde_json::map::Iter<'a>,
+    value: Option<(&'a str, &'a Value)>,
+    path: &'a Path<'a>,
+}
+
+impl<'de, 'a> MapAccess<'de> for Map<'a> {
+    type Error = ArgumentError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ArgumentError> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some((key, value));
+                // Unknown fields are reported at their own path.
+                let path = Path::Key(self.path, key);
+                let key: de::value::StrDeserializer<ArgumentError> =
+                    key.as_str().into_deserializer();
+                seed.deserialize(key).map(Some).map_err(|e| e.at(&path))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ArgumentError> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        let path = Path::Key(self.path, key);
+        seed.deserialize(Tracked { value, path: &path })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct Enum<'a> {
+    variant: &'a str,
+    value: &'a Value,
+    path: &'a Path<'a>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for Enum<'a> {
+    type Error = ArgumentError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), ArgumentError> {
+        let variant: de::value::StrDeserializer<ArgumentError> = self.variant.into_deserializer();
+        let variant = seed.deserialize(variant)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for Enum<'a> {
+    type Error = ArgumentError;
+
+    fn unit_variant(self) -> Result<(), ArgumentError> {
+        match self.value {
+            Value::Null => Ok(()),
+            other => Err(de::Error::invalid_type(unexpected(other), &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, ArgumentError> {
+        let path = Path::Key(self.path, self.variant);
+        seed.deserialize(Tracked {
+            value: self.value,
+            path: &path,
+        })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ArgumentError> {
+        let path = Path::Key(self.path, self.variant);
+        de::Deserializer::deserialize_seq(
+            Tracked {
+                value: self.value,
+                path: &path,
+            },
+            visitor,
+        )
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ArgumentError> {
+        let path = Path::Key(self.path, self.variant);
+        de::Deserializer::deserialize_map(
+            Tracked {
+                value: self.value,
+                path: &path,
+            },
+            visitor,
+        )
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::FunctionArguments;
+    use serde::Deserialize;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_typed_arguments,
+            test_top_level_errors,
+            test_nested_errors,
+            test_unknown_arguments,
+        )
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Window {
+        size: u32,
+        #[serde(default)]
+        step: Option<u32>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Mode {
+        Fast,
+        Sampled { fraction: f64 },
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Arguments {
+        message: String,
+        #[serde(default)]
+        windows: Vec<Window>,
+        #[serde(default)]
+        mode: Option<Mode>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct StrictArguments {
+        message: String,
+    }
+
+    fn arguments(json: Value) -> FunctionArguments {
+        FunctionArguments::from_json(json).unwrap()
+    }
+
+    fn error<T: DeserializeOwned + fmt::Debug>(json: Value) -> String {
+        arguments(json).into_typed::<T>().unwrap_err().to_string()
+    }
+
+    fn test_typed_arguments() {
+        let typed: Arguments = arguments(json!({
+            "message": "hello",
+            "windows": [{"size": 4}, {"size": 8, "step": 2}],
+            "mode": {"sampled": {"fraction": 0.5}},
+            "unused": true,
+        }))
+        .into_typed()
+        .unwrap();
+        assert_eq!(
+            typed,
+            Arguments {
+                message: "hello".to_string(),
+                windows: vec![
+                    Window {
+                        size: 4,
+                        step: None
+                    },
+                    Window {
+                        size: 8,
+                        step: Some(2)
+                    },
+                ],
+                mode: Some(Mode::Sampled { fraction: 0.5 }),
+            }
+        );
+
+        let typed: Arguments = arguments(json!({"message": "hello", "mode": "fast"}))
+            .into_typed()
+            .unwrap();
+        assert_eq!(typed.mode, Some(Mode::Fast));
+    }
+
+    fn test_top_level_errors() {
+        assert_eq!(
+            error::<Arguments>(json!({"message": 1})),
+            "arguments.message: invalid type: integer `1`, expected a string"
+        );
+        assert_eq!(
+            error::<Arguments>(json!({})),
+            "arguments: missing field `message`"
+        );
+
+        let e = arguments(json!({"message": null}))
+            .into_typed::<Arguments>()
+            .unwrap_err();
+        assert_eq!(e.path(), "arguments.message");
+        assert_eq!(e.message(), "invalid type: null, expected a string");
+        let e = FunctionError::from(e);
+        assert_eq!(
+            e.to_string(),
+            "Invalid arguments: arguments.message: invalid type: null, expected a string"
+        );
+    }
+
+    fn test_nested_errors() {
+        assert_eq!(
+            error::<Arguments>(json!({"message": "", "windows": [{"size": 4}, {"size": -1}]})),
+            "arguments.windows[1].size: invalid value: integer `-1`, expected u32"
+        );
+        assert_eq!(
+            error::<Arguments>(json!({"message": "", "windows": [{"step": 1}]})),
+            "arguments.windows[0]: missing field `size`"
+        );
+        assert_eq!(
+            error::<Arguments>(json!({"message": "", "mode": {"sampled": {"fraction": "1"}}})),
+            "arguments.mode.sampled.fraction: invalid type: string \"1\", expected f64"
+        );
+        assert_eq!(
+            error::<Arguments>(json!({"message": "", "mode": "slow"})),
+            "arguments.mode: unknown variant `slow`, expected `fast` or `sampled`"
+        );
+    }
+
+    fn test_unknown_arguments() {
+        // Unknown arguments are only rejected by structs which opt in.
+        assert!(arguments(json!({"message": "", "extra": 1}))
+            .into_typed::<Arguments>()
+            .is_ok());
+        assert_eq!(
+            error::<StrictArguments>(json!({"message": "", "extra": 1})),
+            "arguments.extra: unknown field `extra`, expected `message`"
+        );
+    }
+}
@@ -15,15 +15,209 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use crate::{FunctionArguments, FunctionRuntime, OutputsTags};
+use crate::{
+    CancellationToken, ChunkedReader, ChunkedWriter, ExecutionEnvironment, ExecutionMetrics,
+    ExecutionPolicy, FunctionArguments, FunctionRng, FunctionRuntime, IoManifest, KvStore,
+    OutputsTags, RandomAccess, DEFAULT_CHUNK_SIZE, DEFAULT_INPUT_BUFFER_BUDGET,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::convert::TryInto;
-use std::io;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+#[cfg(feature = "mesalock_sgx")]
+use std::untrusted::time::InstantEx;
 
 pub trait TeaclaveRuntime {
     fn open_input(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Read>>;
     fn create_output(&self, identifier: &str) -> anyhow::Result<Box<dyn io::Write>>;
+
+    /// P-256 public key of the caller that the function result should be sealed to, if the
+    /// caller asked for an encrypted result.
+    fn output_recipient_key(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Whether `input_keys` and `output_keys` list every staged file. Runtimes which cannot
+    /// enumerate their files keep the default, and the executor skips its staging preflight.
+    fn lists_staged_files(&self) -> bool {
+        false
+    }
+
+    /// Identifiers of the staged input files in sorted order, so that functions working on all
+    /// of their inputs do not need to guess names.
+    fn input_keys(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Identifiers of the staged output files in sorted order.
+    fn output_keys(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Checks the staged inputs against the digests their owners declared before any function
+    /// code runs. Runtimes without file-backed inputs have nothing to check.
+    fn verify_inputs(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Token which is tripped when the task should stop. Long-running functions call
+    /// `checkpoint()` on it inside their loops.
+    fn cancellation(&self) -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Logs a message of the function into the log of its execution, which is returned with
+    /// the task result.
+    fn log(&self, level: log::Level, message: &str) {
+        log::log!(level, "{}", message);
+    }
+
+    /// Randomness of the execution. Functions must not use any other source, so that the
+    /// executor can make them reproducible by fixing the seed.
+    fn rng(&self) -> FunctionRng {
+        FunctionRng::from_entropy()
+    }
+
+    /// Counters of the execution, kept by the runtime while the function runs. Runtimes which
+    /// do not keep any return empty metrics.
+    fn metrics(&self) -> ExecutionMetrics {
+        ExecutionMetrics::default()
+    }
+
+    /// What the execution read and wrote so far, as recorded for its `IoManifest`. Runtimes
+    /// which do not record it return `None`.
+    fn io_manifest(&self) -> Option<IoManifest> {
+        None
+    }
+
+    /// Reports how far the function got, e.g. after each pass over its input. The executor
+    /// samples the latest report for the status of the task. Fractions outside of [0, 1] are
+    /// clamped, and runtimes drop reports which come in faster than they are worth storing.
+    fn report_progress(&self, _fraction: f32, _note: &str) {}
+
+    /// Gated builtins the task may run. Runtimes without a policy only run ungated builtins.
+    fn execution_policy(&self) -> ExecutionPolicy {
+        ExecutionPolicy::default()
+    }
+
+    /// Largest number of threads a function may use, granted by the executor. Functions which
+    /// take a thread count validate it against this ceiling.
+    fn max_parallelism(&self) -> usize {
+        1
+    }
+
+    /// The environment of the execution, e.g. for functions to pick defaults for arguments the
+    /// task leaves out. The values stay the same for the whole execution.
+    fn environment(&self) -> ExecutionEnvironment {
+        ExecutionEnvironment::new().max_parallelism(self.max_parallelism())
+    }
+
+    /// Creates a temporary file private to the execution, e.g. to spill data which does not fit
+    /// in memory. Scratch files count against the output limits, are never returned to the
+    /// caller, and are deleted when the execution finishes.
+    fn create_scratch(&self, _name: &str) -> anyhow::Result<Box<dyn io::Write>> {
+        anyhow::bail!("Scratch files are not supported by this runtime")
+    }
+
+    /// Reads back a scratch file written with `create_scratch`.
+    fn open_scratch(&self, _name: &str) -> anyhow::Result<Box<dyn io::Read>> {
+        anyhow::bail!("Scratch files are not supported by this runtime")
+    }
+
+    /// Opens the key-value store of the tenant running the task, scoped to the function. Fails
+    /// with `KvError::CapabilityDenied` unless the execution policy grants `KV_CAPABILITY`.
+    fn kv(&self) -> anyhow::Result<Box<dyn KvStore>> {
+        anyhow::bail!("Key-value stores are not supported by this runtime")
+    }
+
+    /// Opens an input file for reads at arbitrary offsets. Runtimes which can only read their
+    /// inputs sequentially return `None`, and functions fall back to `open_input`.
+    fn open_input_random_access(
+        &self,
+        _identifier: &str,
+    ) -> anyhow::Result<Option<Box<dyn RandomAccess>>> {
+        Ok(None)
+    }
+
+    /// Opens several inputs to be read side by side, e.g. by a join which steps through two
+    /// sorted inputs in lockstep. The buffers of the readers share
+    /// `DEFAULT_INPUT_BUFFER_BUDGET`.
+    fn open_inputs(&self, identifiers: &[&str]) -> anyhow::Result<Vec<Box<dyn io::BufRead>>> {
+        self.open_inputs_with_budget(identifiers, DEFAULT_INPUT_BUFFER_BUDGET)
+    }
+
+    /// Like `open_inputs`, with an equal share of `buffer_budget` bytes as the buffer of each
+    /// reader.
+    fn open_inputs_with_budget(
+        &self,
+        identifiers: &[&str],
+        buffer_budget: usize,
+    ) -> anyhow::Result<Vec<Box<dyn io::BufRead>>> {
+        let capacity = (buffer_budget / identifiers.len().max(1)).max(1);
+        identifiers
+            .iter()
+            .map(|identifier| {
+                let reader = io::BufReader::with_capacity(capacity, self.open_input(identifier)?);
+                Ok(Box::new(reader) as Box<dyn io::BufRead>)
+            })
+            .collect()
+    }
+
+    /// Reads an input file in chunks of `chunk_size` bytes instead of all at once.
+    fn open_input_chunked(
+        &self,
+        identifier: &str,
+        chunk_size: usize,
+    ) -> anyhow::Result<ChunkedReader> {
+        Ok(ChunkedReader::new(self.open_input(identifier)?, chunk_size))
+    }
+
+    /// Creates an output file which is written in chunks of `chunk_size` bytes.
+    fn create_output_chunked(
+        &self,
+        identifier: &str,
+        chunk_size: usize,
+    ) -> anyhow::Result<ChunkedWriter> {
+        Ok(ChunkedWriter::new(
+            self.create_output(identifier)?,
+            chunk_size,
+        ))
+    }
+
+    /// Streams an input file to an output file, letting `transform` rewrite every chunk in
+    /// between. Returns the number of bytes written.
+    fn copy_input_to_output(
+        &self,
+        input: &str,
+        output: &str,
+        transform: &mut dyn FnMut(&mut Vec<u8>),
+    ) -> anyhow::Result<u64> {
+        let mut writer = self.create_output_chunked(output, DEFAULT_CHUNK_SIZE)?;
+        let mut written = 0;
+        for chunk in self.open_input_chunked(input, DEFAULT_CHUNK_SIZE)? {
+            let mut chunk = chunk?;
+            transform(&mut chunk);
+            writer.write_all(&chunk)?;
+            written += chunk.len() as u64;
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+}
+
+impl dyn TeaclaveRuntime + Send + Sync {
+    /// Runs `f` and records its elapsed time in the metrics of the execution under `label`,
+    /// e.g. to see which phase of a function takes the time. Blocks timed under the same label
+    /// add up.
+    pub fn time_block<T>(&self, label: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.metrics().record_time(label, start.elapsed());
+        result
+    }
 }
 
 pub trait TeaclaveExecutor {
@@ -36,6 +230,101 @@ pub trait TeaclaveExecutor {
     ) -> anyhow::Result<String>;
 }
 
+/// Health counters of a worker, kept across the executions it runs.
+#[derive(Clone, Debug, Default)]
+pub struct WorkerHealth {
+    leaked_threads: Arc<AtomicU64>,
+}
+
+impl WorkerHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a function thread abandoned at its hard timeout.
+    pub fn record_leaked_thread(&self) {
+        self.leaked_threads.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Number of abandoned function threads. Each of them holds a TCS of the enclave until it
+    /// restarts, so a growing count means the worker runs out of threads.
+    pub fn leaked_threads(&self) -> u64 {
+        self.leaked_threads.load(Ordering::SeqCst)
+    }
+}
+
+/// Information about an output file written by a function.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OutputInfo {
+    pub size: u64,
+}
+
+impl OutputInfo {
+    pub fn new(size: u64) -> Self {
+        Self { size }
+    }
+}
+
+/// Structured result of a builtin function. It is serialized to JSON and
+/// returned to the client in place of a plain summary string.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FunctionSummary {
+    pub message: String,
+    pub metrics: BTreeMap<String, f64>,
+    pub outputs: BTreeMap<String, OutputInfo>,
+    pub warnings: Vec<String>,
+    /// Whether the executor shortened the summary to the size limit of the execution policy.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub truncated: bool,
+    /// Whether the executor redacted parts of the summary.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub redacted: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl FunctionSummary {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn metric(mut self, name: impl Into<String>, value: f64) -> Self {
+        self.metrics.insert(name.into(), value);
+        self
+    }
+
+    pub fn output(mut self, identifier: impl Into<String>, info: OutputInfo) -> Self {
+        self.outputs.insert(identifier.into(), info);
+        self
+    }
+
+    pub fn warning(mut self, warning: impl Into<String>) -> Self {
+        self.warnings.push(warning.into());
+        self
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+impl From<String> for FunctionSummary {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<&str> for FunctionSummary {
+    fn from(message: &str) -> Self {
+        Self::new(message)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum ExecutorType {
     Builtin,
@@ -146,7 +435,87 @@ pub struct ExecutionResult {
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
+    use super::*;
+    use serde_json::json;
+    use teaclave_test_utils::*;
+
     pub fn run_tests() -> bool {
-        true
+        run_tests!(
+            test_function_summary_from_string,
+            test_function_summary_json,
+            test_time_block,
+        )
+    }
+
+    #[derive(Default)]
+    struct MetricsRuntime {
+        metrics: ExecutionMetrics,
+    }
+
+    impl TeaclaveRuntime for MetricsRuntime {
+        fn open_input(&self, _identifier: &str) -> anyhow::Result<Box<dyn io::Read>> {
+            anyhow::bail!("no inputs")
+        }
+
+        fn create_output(&self, _identifier: &str) -> anyhow::Result<Box<dyn io::Write>> {
+            anyhow::bail!("no outputs")
+        }
+
+        fn metrics(&self) -> ExecutionMetrics {
+            self.metrics.clone()
+        }
+    }
+
+    fn test_time_block() {
+        let runtime: FunctionRuntime = Box::new(MetricsRuntime::default());
+        let rows = runtime.time_block("parse", || {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            42
+        });
+        assert_eq!(rows, 42);
+
+        let metrics = runtime.metrics().to_summary_metrics();
+        assert!(metrics["runtime.time_us.parse"] >= 2000.0);
+        assert!(!metrics.contains_key("runtime.time_us.aggregate"));
+    }
+
+    fn test_function_summary_from_string() {
+        let summary = FunctionSummary::from(format!("Trained {} lines of data.", 120));
+        assert_eq!(summary.message, "Trained 120 lines of data.");
+        assert!(summary.metrics.is_empty());
+        assert!(summary.outputs.is_empty());
+        assert!(summary.warnings.is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&summary.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "message": "Trained 120 lines of data.",
+                "metrics": {},
+                "outputs": {},
+                "warnings": [],
+            })
+        );
+    }
+
+    fn test_function_summary_json() {
+        let summary = FunctionSummary::new("done")
+            .metric("bytes_read", 42.0)
+            .output("output", OutputInfo::new(7))
+            .warning("input was empty");
+
+        let json: serde_json::Value = serde_json::from_str(&summary.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "message": "done",
+                "metrics": {"bytes_read": 42.0},
+                "outputs": {"output": {"size": 7}},
+                "warnings": ["input was empty"],
+            })
+        );
+
+        let parsed: FunctionSummary = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, summary);
     }
 }
@@ -18,7 +18,8 @@
 extern crate sgx_types;
 
 mod worker;
-pub use worker::Worker;
+pub use teaclave_runtime::KvSpace;
+pub use worker::{InvocationOptions, RuntimeSettings, Worker};
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
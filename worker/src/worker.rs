@@ -18,35 +18,108 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::collections::HashMap;
 use std::format;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
-use teaclave_types::{Executor, ExecutorType, StagedFiles, StagedFunction};
+use teaclave_types::{
+    CancellationToken, ExecutionEnvironment, ExecutionLimits, ExecutionLog, ExecutionMetrics,
+    ExecutionPolicy, ExecutionProgress, Executor, ExecutorType, FunctionRng, IoManifestRecorder,
+    StagedFiles, StagedFunction, WorkerHealth,
+};
 
 use teaclave_executor::*;
-use teaclave_runtime::DefaultRuntime;
+use teaclave_runtime::{DefaultRuntime, KvScope, KvSpace};
 use teaclave_types::{TeaclaveExecutor, TeaclaveRuntime};
 
 type BoxedTeaclaveExecutor = Box<dyn TeaclaveExecutor + Send + Sync>;
 type BoxedTeaclaveRuntime = Box<dyn TeaclaveRuntime + Send + Sync>;
 type ExecutorBuilder = fn() -> BoxedTeaclaveExecutor;
-type RuntimeBuilder = fn(StagedFiles, StagedFiles) -> BoxedTeaclaveRuntime;
+type RuntimeBuilder =
+    fn(StagedFiles, StagedFiles, RuntimeSettings, InvocationOptions) -> BoxedTeaclaveRuntime;
+
+/// What the caller of `Worker::invoke_function` shares with the function it invokes. Each
+/// defaults to one the caller does not keep.
+#[derive(Clone, Default)]
+pub struct InvocationOptions {
+    /// Stops the function with a `Cancelled` error once tripped.
+    pub cancellation: CancellationToken,
+    /// Collects what the function logs, through its runtime or the `log` macros.
+    pub log: ExecutionLog,
+    /// Stores the progress the function reports, which the caller can sample while it runs.
+    pub progress: ExecutionProgress,
+    /// Records what the function reads and writes, from which the caller takes the
+    /// `IoManifest` of the execution.
+    pub io_recorder: IoManifestRecorder,
+    /// Keeps the counters of the execution, which the caller can read also after a failure.
+    pub metrics: ExecutionMetrics,
+}
+
+/// What the worker derives for the runtime of a function from the staged function and its own
+/// configuration.
+pub struct RuntimeSettings {
+    pub limits: ExecutionLimits,
+    pub policy: ExecutionPolicy,
+    pub rng: FunctionRng,
+    pub environment: ExecutionEnvironment,
+    pub kv: Option<KvScope>,
+}
 
 pub struct Worker {
     runtimes: HashMap<String, RuntimeBuilder>,
     executors: HashMap<(ExecutorType, Executor), ExecutorBuilder>,
+    max_parallelism: usize,
+    memory_budget_bytes: Option<u64>,
+    concurrency: Option<ConcurrencyLimits>,
+    enclave_debug_mode: bool,
+    soft_timeout: Option<Duration>,
+    hard_timeout: Option<Duration>,
+    health: WorkerHealth,
+    kv_space: Option<KvSpace>,
+    max_summary_bytes: Option<usize>,
+    redactors: Vec<Arc<dyn SummaryRedactor>>,
 }
 
+/// Time a function gets after its soft timeout to stop before it is abandoned, if only the soft
+/// timeout is known.
+const HARD_TIMEOUT_GRACE: Duration = Duration::from_secs(30);
+
 impl Default for Worker {
     fn default() -> Self {
         let mut worker = Worker::new();
 
         // Register supported runtimes
-        worker.register_runtime("default", |input, output| {
-            Box::new(DefaultRuntime::new(input, output))
+        worker.register_runtime("default", |input, output, settings, options| {
+            Box::new(
+                DefaultRuntime::new(input, output)
+                    .with_cancellation(options.cancellation)
+                    .with_limits(settings.limits)
+                    .with_policy(settings.policy)
+                    .with_execution_log(options.log)
+                    .with_progress(options.progress)
+                    .with_rng(settings.rng)
+                    .with_environment(settings.environment)
+                    .with_io_recorder(options.io_recorder)
+                    .with_metrics(options.metrics)
+                    .with_kv(settings.kv),
+            )
         });
 
         #[cfg(test_mode)]
-        worker.register_runtime("raw-io", |input, output| {
-            Box::new(teaclave_runtime::RawIoRuntime::new(input, output))
+        worker.register_runtime("raw-io", |input, output, settings, options| {
+            Box::new(
+                teaclave_runtime::RawIoRuntime::new(input, output)
+                    .with_cancellation(options.cancellation)
+                    .with_limits(settings.limits)
+                    .with_policy(settings.policy)
+                    .with_execution_log(options.log)
+                    .with_progress(options.progress)
+                    .with_rng(settings.rng)
+                    .with_environment(settings.environment)
+                    .with_io_recorder(options.io_recorder)
+                    .with_metrics(options.metrics)
+                    .with_kv(settings.kv),
+            )
         });
 
         // Register supported executors
@@ -73,7 +146,106 @@ impl Worker {
         Self {
             runtimes: HashMap::new(),
             executors: HashMap::new(),
+            max_parallelism: 1,
+            memory_budget_bytes: None,
+            concurrency: None,
+            enclave_debug_mode: false,
+            soft_timeout: None,
+            hard_timeout: None,
+            health: WorkerHealth::default(),
+            kv_space: None,
+            max_summary_bytes: None,
+            redactors: Vec::new(),
+        }
+    }
+
+    /// Lets functions use up to `threads` threads. Each thread of a function occupies a TCS of
+    /// the enclave, so the ceiling must leave enough of them to the service. Defaults to 1.
+    pub fn with_max_parallelism(mut self, threads: usize) -> Self {
+        self.max_parallelism = threads.max(1);
+        self
+    }
+
+    /// Bytes of memory functions may use for their buffers, which functions holding data in
+    /// memory size themselves by. No budget by default.
+    pub fn with_memory_budget(mut self, bytes: u64) -> Self {
+        self.memory_budget_bytes = Some(bytes);
+        self
+    }
+
+    /// Runs at most `max_concurrent` functions at a time, and admits a function only if its
+    /// memory budget fits under `memory_ceiling` next to those of the running ones. Functions
+    /// abandoned at their hard timeout keep their slot and budget until they return. No limits
+    /// by default.
+    pub fn with_concurrency_limits(mut self, max_concurrent: usize, memory_ceiling: u64) -> Self {
+        self.concurrency = Some(ConcurrencyLimits::new(max_concurrent, memory_ceiling));
+        self
+    }
+
+    /// Tells functions whether the enclave they run in is a debug enclave, as read from the
+    /// attributes of its report.
+    pub fn with_enclave_debug_mode(mut self, debug: bool) -> Self {
+        self.enclave_debug_mode = debug;
+        self
+    }
+
+    /// Timeouts of functions whose task does not set `max_wall_time` or `hard_wall_time`. With a
+    /// hard timeout, functions run on a dedicated thread which is abandoned once it passes.
+    pub fn with_default_timeouts(mut self, soft: Option<Duration>, hard: Option<Duration>) -> Self {
+        self.soft_timeout = soft;
+        self.hard_timeout = hard;
+        self
+    }
+
+    /// Key-value stores of the tenants whose functions the worker runs. Functions of tasks
+    /// without a tenant get no store.
+    pub fn with_kv_space(mut self, space: KvSpace) -> Self {
+        self.kv_space = Some(space);
+        self
+    }
+
+    /// Largest size in bytes of the results of functions, which the execution policy of a task
+    /// can only lower. No limit by default.
+    pub fn with_max_summary_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_summary_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Adds `redactor` to the redaction of the results of all functions, next to the redacted
+    /// strings of the execution policy of each task and the digests of its inputs.
+    pub fn with_redactor(mut self, redactor: Arc<dyn SummaryRedactor>) -> Self {
+        self.redactors.push(redactor);
+        self
+    }
+
+    /// Health counters of the worker, shared with the executions it runs.
+    pub fn health(&self) -> WorkerHealth {
+        self.health.clone()
+    }
+
+    /// Checks that the worker can still start a function thread within `timeout`, and admit a
+    /// function under its concurrency limits. Abandoned function threads keep their TCS and
+    /// their memory budget, so once they hold all the TCSs of the enclave, or too much of the
+    /// memory ceiling, no function can run any more.
+    pub fn probe(&self, timeout: Duration) -> anyhow::Result<()> {
+        if let Some(limits) = &self.concurrency {
+            let memory_budget = self.memory_budget_bytes.unwrap_or(0);
+            anyhow::ensure!(
+                limits.available_memory() >= memory_budget,
+                "Only {} bytes of memory are left for a function with a budget of {}",
+                limits.available_memory(),
+                memory_budget
+            );
         }
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .spawn(move || {
+                let _ = sender.send(());
+            })
+            .map_err(|e| anyhow::anyhow!("Cannot start a function thread: {}", e))?;
+        receiver
+            .recv_timeout(timeout)
+            .map_err(|_| anyhow::anyhow!("A function thread did not start within {:?}", timeout))
     }
 
     pub fn register_runtime(&mut self, name: impl ToString, builder: RuntimeBuilder) {
@@ -84,29 +256,102 @@ impl Worker {
         self.executors.insert(key, builder);
     }
 
-    pub fn invoke_function(&self, function: StagedFunction) -> anyhow::Result<String> {
+    /// Invokes a function which stops with a `Cancelled` error once the cancellation of
+    /// `options` is tripped, or with a `ResourceLimitExceeded` error once it runs out of its
+    /// `limits`. What the function logs, reports as progress, reads and writes goes to the
+    /// other sinks of `options`.
+    pub fn invoke_function(
+        &self,
+        function: StagedFunction,
+        mut options: InvocationOptions,
+    ) -> anyhow::Result<String> {
         let executor = self.get_executor(function.executor_type, function.executor)?;
-        let runtime = self.get_runtime(
-            &function.runtime_name,
+        let soft_timeout = function.limits.max_wall_time.or(self.soft_timeout);
+        let hard_timeout = function
+            .limits
+            .hard_wall_time
+            .or(self.hard_timeout)
+            .or_else(|| soft_timeout.map(|soft| soft + HARD_TIMEOUT_GRACE));
+        if let Some(max_wall_time) = soft_timeout {
+            options.cancellation = options.cancellation.with_deadline(max_wall_time);
+        }
+        // Inside the timeout, so that an abandoned function holds its slot and its memory budget
+        // until it returns.
+        let executor: BoxedTeaclaveExecutor = match &self.concurrency {
+            Some(limits) => Box::new(ConcurrentExecutor::with_limits(executor, limits.clone())),
+            None => executor,
+        };
+        let executor: BoxedTeaclaveExecutor = match hard_timeout {
+            Some(hard_timeout) => {
+                let executor = TimeoutExecutor::new(executor, hard_timeout, self.health.clone());
+                match soft_timeout {
+                    Some(soft_timeout) => Box::new(executor.soft_timeout(soft_timeout)),
+                    None => Box::new(executor),
+                }
+            }
+            None => executor,
+        };
+        let kv = match (&self.kv_space, &function.tenant) {
+            (Some(space), Some(tenant)) => Some(space.scope(tenant, &function.name)),
+            _ => None,
+        };
+        let environment = ExecutionEnvironment::new()
+            .max_parallelism(self.max_parallelism)
+            .memory_budget_bytes(self.memory_budget_bytes)
+            .deterministic(function.deterministic_seed.is_some())
+            .platform_version(env!("CARGO_PKG_VERSION"))
+            .enclave_debug_mode(self.enclave_debug_mode);
+        // Whatever executor runs the function, its result and the reason of its failure reach
+        // the user only after this post-processing.
+        let mut postprocessor = SummaryPostProcessor::from_policy(&function.policy)
+            .redact_input_digests(&function.input_files);
+        if let Some(max_bytes) = self.max_summary_bytes {
+            postprocessor = postprocessor.max_bytes_at_most(max_bytes);
+        }
+        for redactor in &self.redactors {
+            postprocessor = postprocessor.redactor(redactor.clone());
+        }
+        options
+            .io_recorder
+            .declare(function.input_files.keys(), function.output_files.keys());
+        let sealed_outputs = function.output_files.sealed_outputs();
+        let build_runtime = self.get_runtime_builder(&function.runtime_name)?;
+        let log = options.log.clone();
+        let progress = options.progress.clone();
+        let settings = RuntimeSettings {
+            limits: function.limits,
+            policy: function.policy,
+            rng: FunctionRng::new(function.deterministic_seed),
+            environment,
+            kv,
+        };
+        let runtime = build_runtime(
             function.input_files,
             function.output_files,
-        )?;
-        executor.execute(function.name, function.arguments, function.payload, runtime)
+            settings,
+            options,
+        );
+        // Tampered inputs must fail the task before the function reads any of them.
+        runtime.verify_inputs()?;
+        let result = log.scope(|| {
+            executor.execute(function.name, function.arguments, function.payload, runtime)
+        });
+        progress.flush();
+        result
+            .and_then(|output| {
+                // A sealed output without its final chunk reads as truncated to its recipient.
+                sealed_outputs.check()?;
+                Ok(output)
+            })
+            .map(|output| postprocessor.process_output(output))
+            .map_err(|error| postprocessor.process_error(error))
     }
 
-    fn get_runtime(
-        &self,
-        name: &str,
-        input_files: StagedFiles,
-        output_files: StagedFiles,
-    ) -> anyhow::Result<BoxedTeaclaveRuntime> {
-        let build_runtime = self
-            .runtimes
+    fn get_runtime_builder(&self, name: &str) -> anyhow::Result<RuntimeBuilder> {
+        self.runtimes
             .get(name)
-            .ok_or_else(|| anyhow::anyhow!(format!("Runtime {} not available.", name)))?;
-
-        let runtime = build_runtime(input_files, output_files);
-        Ok(runtime)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!(format!("Runtime {} not available.", name)))
     }
 
     fn get_executor(